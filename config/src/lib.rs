@@ -2,18 +2,30 @@
 use crypto::threshold::{
     PublicKey as ThresholdPublicKey, PublicKeySet, SecretKeySet, SecretKeyShare, SerdeSecret,
 };
-use crypto::{generate_production_keypair, PublicKey, SecretKey};
-use log::info;
+use crypto::{
+    generate_production_keypair, Digest, HashKind, PublicKey, SchemeKind, SecretKey, Signature,
+};
+use ed25519_dalek::Digest as _;
+use ed25519_dalek::Sha512;
+use log::{info, warn, LevelFilter};
 use rand::SeedableRng;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
 use std::fs::{self, OpenOptions};
 use std::io::BufWriter;
 use std::io::Write as _;
-use std::net::SocketAddr;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::Path;
 use thiserror::Error;
 
+pub mod threshold;
+
+#[cfg(test)]
+#[path = "tests/config_tests.rs"]
+pub mod config_tests;
+
 #[derive(Error, Debug)]
 pub enum ConfigError {
     #[error("Node {0} is not in the committee")]
@@ -27,27 +39,142 @@ pub enum ConfigError {
 
     #[error("Failed to write config file '{file}': {message}")]
     ExportError { file: String, message: String },
+
+    #[error("Failed to parse config: {0}")]
+    ParseError(String),
+
+    #[error("Invalid committee: {0}")]
+    InvalidCommittee(String),
+
+    #[error("The signature of config file '{file}' is missing or invalid")]
+    InvalidSignature { file: String },
+
+    #[error("The threshold keypair does not match the threshold public key of the committee")]
+    ThresholdKeyMismatch,
+
+    #[error("A threshold of {threshold} requires more than {threshold} key shares but the committee only has {size} authorities")]
+    IncompatibleThreshold { threshold: usize, size: usize },
+
+    #[error("The committee sets the threshold of worker {worker} to {expected}, not {found}")]
+    ThresholdMismatch {
+        worker: WorkerId,
+        expected: usize,
+        found: usize,
+    },
+
+    #[error(
+        "The committee uses {committee} signatures but this node was built for {node} signatures"
+    )]
+    SchemeMismatch {
+        committee: SchemeKind,
+        node: SchemeKind,
+    },
+
+    #[error("The committee hashes with {committee} but this node was built for {node}")]
+    HashMismatch { committee: HashKind, node: HashKind },
+
+    #[error(
+        "The workers of the committee use durable_acks = {committee} but this node uses {node}"
+    )]
+    DurableAcksMismatch { committee: bool, node: bool },
+
+    #[error("The secret key share does not match share {0} of the threshold public key set")]
+    ShareMismatch(usize),
+
+    #[error(
+        "The threshold keypair and the threshold public key were generated from different seeds"
+    )]
+    SeedMismatch,
+
+    #[error("The threshold keypair was generated with {keypair} but the threshold public key with {public_key}")]
+    KeygenMismatch { keypair: String, public_key: String },
+
+    #[error("Invalid log filter '{0}' (expected <module>=<level>)")]
+    InvalidLogFilter(String),
+
+    #[error("A garbage collection depth of {gc_depth} rounds is too small: the DAG may advance by {minimum} rounds while a lagging node waits for a sync retry")]
+    GcDepthTooSmall { gc_depth: u64, minimum: u64 },
+
+    #[error(
+        "Invalid commit latency buckets {0:?}: the upper bounds must be finite and increasing"
+    )]
+    InvalidLatencyBuckets(Vec<f64>),
+
+    #[error("Cannot bind the listeners to address {address}: {message}")]
+    UnbindableAddress { address: IpAddr, message: String },
 }
 
+/// Parses comma-separated `<module>=<level>` log directives (eg. `network=warn,consensus=info`), the
+/// levels being those of `RUST_LOG`.
+pub fn parse_log_filters(filters: &str) -> Result<Vec<(String, LevelFilter)>, ConfigError> {
+    filters
+        .split(',')
+        .map(str::trim)
+        .filter(|x| !x.is_empty())
+        .map(|directive| {
+            let mut parts = directive.splitn(2, '=');
+            let module = parts.next().unwrap_or_default().trim();
+            match parts.next().map(|x| x.trim().parse::<LevelFilter>()) {
+                Some(Ok(level)) if !module.is_empty() => Ok((module.to_string(), level)),
+                _ => Err(ConfigError::InvalidLogFilter(directive.to_string())),
+            }
+        })
+        .collect()
+}
+
+/// Whether the config file `path` is written in TOML (rather than JSON).
+fn is_toml(path: &str) -> bool {
+    Path::new(path).extension().map_or(false, |x| x == "toml")
+}
+
+/// Config files are parsed as TOML if their path ends in `.toml`, and as JSON otherwise.
 pub trait Import: DeserializeOwned {
     fn import(path: &str) -> Result<Self, ConfigError> {
-        let reader = || -> Result<Self, std::io::Error> {
-            let data = fs::read(path)?;
-            Ok(serde_json::from_slice(data.as_slice())?)
+        let reader = || -> Result<Self, Box<dyn std::error::Error>> {
+            let data = fs::read_to_string(path)?;
+            if is_toml(path) {
+                Ok(toml::from_str(&data)?)
+            } else {
+                Ok(serde_json::from_str(&data)?)
+            }
         };
-        reader().map_err(|e| ConfigError::ImportError {
+        let config = reader().map_err(|e| ConfigError::ImportError {
             file: path.to_string(),
             message: e.to_string(),
-        })
+        })?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Parses the same JSON content as `import`, but from memory rather than from a file.
+    fn import_from_str(data: &str) -> Result<Self, ConfigError> {
+        let config: Self =
+            serde_json::from_str(data).map_err(|e| ConfigError::ParseError(e.to_string()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks the consistency of a freshly imported config (nothing by default).
+    fn validate(&self) -> Result<(), ConfigError> {
+        Ok(())
     }
 }
 
+/// Config files are written in TOML if their path ends in `.toml`, and in JSON otherwise.
 pub trait Export: Serialize {
     fn export(&self, path: &str) -> Result<(), ConfigError> {
-        let writer = || -> Result<(), std::io::Error> {
-            let file = OpenOptions::new().create(true).write(true).open(path)?;
+        let writer = || -> Result<(), Box<dyn std::error::Error>> {
+            let data = if is_toml(path) {
+                toml::to_string_pretty(self)?
+            } else {
+                serde_json::to_string_pretty(self)?
+            };
+            let file = OpenOptions::new()
+                .create(true)
+                .write(true)
+                .truncate(true)
+                .open(path)?;
             let mut writer = BufWriter::new(file);
-            let data = serde_json::to_string_pretty(self).unwrap();
             writer.write_all(data.as_ref())?;
             writer.write_all(b"\n")?;
             Ok(())
@@ -61,28 +188,343 @@ pub trait Export: Serialize {
 
 pub type Stake = u32;
 pub type WorkerId = u32;
+/// The epoch of a threshold key set. The key set changes (and the epoch increases) on reconfiguration.
+pub type Epoch = u64;
 
+/// The smallest garbage collection depth (the commit rule reaches 3 rounds below the last round).
+const MIN_GC_DEPTH: u64 = 3;
+
+/// The node's parameters. The fields `max_header_delay`, `suspect_after_rounds`, `sync_retry_delay`,
+/// `batch_size`, `transaction_ttl`, `dedup_window`, and `signing_epoch` can be changed at runtime (see
+/// `Parameters::reload`); the others are only read when the node boots.
 #[derive(Deserialize, Clone)]
 pub struct Parameters {
     /// The preferred header size. The primary creates a new header when it has enough parents and
     /// enough batches' digests to reach `header_size`. Denominated in bytes.
     pub header_size: usize,
     /// The maximum delay that the primary waits between generating two headers, even if the header
-    /// did not reach `max_header_size`. Denominated in ms.
+    /// did not reach `max_header_size`. Denominated in ms. Live-reloadable.
+    ///
+    /// The primary never creates a header before it has a quorum of parents from the previous round. Once
+    /// it has them, it creates a header as soon as its workers provided enough batches' digests (see
+    /// `header_size`), or it has `max_header_parents` parents, or this delay elapsed (since the previous
+    /// header). When the workers are idle, headers are thus only created every `max_header_delay`; on
+    /// high-latency networks, a larger delay lets the headers carry more batches and more parents.
+    #[serde(alias = "max_header_delay_ms")]
     pub max_header_delay: u64,
-    /// The depth of the garbage collection (Denominated in number of rounds).
+    /// The number of parents after which the primary creates a header without waiting for batches or
+    /// for `max_header_delay`. The certificates of the previous round arriving once the primary has a
+    /// quorum are added to the next header up to this number (the header always holds at least a quorum).
+    #[serde(default = "default_max_header_parents")]
+    pub max_header_parents: usize,
+    /// The number of batches' digests the primary waits for before creating a header because it reached
+    /// `header_size` or `max_header_parents`: only `max_header_delay` elapsing creates headers with fewer
+    /// batches. Live-reloadable.
+    #[serde(default)]
+    pub min_header_batches: usize,
+    /// The depth of the garbage collection (Denominated in number of rounds). A lagging node gets the
+    /// certificates it misses from its peers, which only keep them for `gc_depth` rounds: while it waits
+    /// `sync_retry_delay` to retry its sync requests, the DAG advances by up to (roughly)
+    /// `sync_retry_delay / max_header_delay` rounds, so `gc_depth` should be at least that (see
+    /// `Parameters::min_gc_depth`), or lagging nodes may never catch up.
     pub gc_depth: u64,
+    /// The number of rounds without any of its certificates after which the primary suspects an authority
+    /// to be faulty (until one of its certificates shows up again). Live-reloadable.
+    #[serde(default = "default_suspect_after_rounds")]
+    pub suspect_after_rounds: u64,
     /// The delay after which the synchronizer retries to send sync requests. Denominated in ms.
+    /// Live-reloadable.
     pub sync_retry_delay: u64,
     /// Determine with how many nodes to sync when re-trying to send sync-request. These nodes
     /// are picked at random from the committee.
     pub sync_retry_nodes: usize,
+    /// The maximum number of missing certificates (or batches) requested less than `sync_retry_delay` ago.
+    /// When more are missing, the others wait for the next retry, the ones waiting the longest first.
+    #[serde(default = "default_max_sync_requests")]
+    pub max_sync_requests: usize,
+    /// The number of headers and certificates whose signatures the primary verifies in parallel (on the
+    /// blocking threads of the runtime).
+    #[serde(default = "default_verification_threads")]
+    pub verification_threads: usize,
+    /// The number of certificates the primary keeps in memory to check the parents of the headers. The
+    /// least recently used ones are evicted (and read again from the store when needed).
+    #[serde(default = "default_max_cached_certificates")]
+    pub max_cached_certificates: usize,
+    /// How far in the future (according to our clock) the timestamps of the headers of the other primaries
+    /// may be. The later headers are rejected; older timestamps are accepted (the author may be lagging).
+    /// Denominated in ms.
+    #[serde(default = "default_max_header_drift")]
+    pub max_header_drift: u64,
     /// The preferred batch size. The workers seal a batch of transactions when it reaches this size.
-    /// Denominated in bytes.
+    /// Denominated in bytes. Live-reloadable.
     pub batch_size: usize,
     /// The delay after which the workers seal a batch of transactions, even if `max_batch_size`
     /// is not reached. Denominated in ms.
     pub max_batch_delay: u64,
+    /// The delay during which the workers coalesce the digests of their batches before notifying their
+    /// primary of all of them in a single message: a longer delay means fewer messages, but the batches
+    /// reach the headers later. The digests are sent immediately if zero. Denominated in ms.
+    #[serde(default)]
+    pub digest_flush_interval: u64,
+    /// The address on which the primary streams the ordered certificates to its subscribers. The
+    /// certificates are not streamed if this address is not specified.
+    #[serde(default)]
+    pub output_address: Option<SocketAddr>,
+    /// The address on which the primary streams the committed sub-dags to the subscribers, from the index
+    /// each of them requests (replaying the sub-dags still within the garbage collection depth). The
+    /// sub-dags are not streamed if this address is not specified.
+    #[serde(default)]
+    pub sub_dag_address: Option<SocketAddr>,
+    /// The delay after which the node gives up decrypting the batches of a committed certificate and
+    /// outputs those left as failed, so that a single certificate never withholds the rest of the
+    /// committed sequence. Denominated in ms.
+    #[serde(default = "default_decryption_timeout")]
+    pub decryption_timeout_ms: u64,
+    /// If set, the store is pruned: the headers, certificates and batches committed more than this many
+    /// rounds below the garbage collection round are deleted. Denominated in number of rounds.
+    #[serde(default)]
+    pub pruning_margin: Option<u64>,
+    /// If set, the connections with the other nodes of the committee are TLS connections. Clients still
+    /// submit their transactions in plaintext.
+    #[serde(default)]
+    pub tls: Option<TlsParameters>,
+    /// The delay after which the primary and workers first try to reconnect to a peer. Denominated in ms.
+    #[serde(default = "default_min_reconnect_delay")]
+    pub min_reconnect_delay_ms: u64,
+    /// The maximum delay between two reconnection attempts to a peer. Denominated in ms.
+    #[serde(default = "default_max_reconnect_delay")]
+    pub max_reconnect_delay_ms: u64,
+    /// The factor by which the reconnection delay grows after every failed attempt (the delay is reset
+    /// once a message goes through).
+    #[serde(default = "default_reconnect_backoff_multiplier")]
+    pub reconnect_backoff_multiplier: f64,
+    /// The maximum rate at which the primary and workers read the messages of a connection from another
+    /// authority; faster peers are throttled. Denominated in messages per second.
+    #[serde(default = "default_max_peer_messages_per_second")]
+    pub max_peer_messages_per_second: u64,
+    /// The maximum rate at which the primary and workers read the bytes of a connection from another
+    /// authority. Denominated in bytes per second.
+    #[serde(default = "default_max_peer_bytes_per_second")]
+    pub max_peer_bytes_per_second: u64,
+    /// The rule the consensus uses to order the DAG.
+    #[serde(default)]
+    pub consensus: ConsensusProtocol,
+    /// The size of the client transactions not yet sealed into a batch above which a worker rejects new
+    /// transactions (replying that its mempool is full). Denominated in bytes.
+    #[serde(default = "default_mempool_high_water_mark")]
+    pub mempool_high_water_mark: usize,
+    /// The size below which a worker rejecting transactions accepts them again. Denominated in bytes.
+    #[serde(default = "default_mempool_low_water_mark")]
+    pub mempool_low_water_mark: usize,
+    /// The number of client connections each worker keeps open at once; the transaction listener closes
+    /// the connections it accepts beyond it (after telling the client why).
+    #[serde(default = "default_max_client_connections")]
+    pub max_client_connections: usize,
+    /// The age after which the workers drop the client transactions not yet sealed into a batch (0
+    /// disables the expiry). Denominated in ms. Live-reloadable.
+    #[serde(default)]
+    pub transaction_ttl: u64,
+    /// The zstd level at which the workers compress their batches before storing and sending them. The
+    /// batches are not compressed if this level is not specified.
+    #[serde(default)]
+    pub batch_compression_level: Option<i32>,
+    /// Whether the workers only acknowledge the batches of the other workers once they are synced to
+    /// disk, and only count such acknowledgements towards the quorum of their own batches. Otherwise a
+    /// batch counts as delivered as soon as a quorum received it.
+    #[serde(default = "default_durable_acks")]
+    pub durable_acks: bool,
+    /// The number of recent client transactions each worker remembers to drop the duplicates before they
+    /// enter a batch (0 disables the deduplication). Live-reloadable.
+    #[serde(default)]
+    pub dedup_window: usize,
+    /// Whether the workers order the transactions of their batches by the priority prefixed to them (the
+    /// highest first, ties broken by digest) rather than by arrival, and reject the batches of the other
+    /// workers that are not ordered this way. All the workers of a committee must agree on it.
+    #[serde(default)]
+    pub priority_ordering: bool,
+    /// The fraction of their batches whose digest, number of transactions and first bytes of each
+    /// transaction the workers log (at trace level), between 0 (none) and 1 (all). Live-reloadable.
+    #[serde(default)]
+    pub batch_sample_rate: f64,
+    /// The capacity of the channels between the tasks of the node. A task whose output channel is full
+    /// stops processing its input, which is how the node slows down under load: too small a capacity
+    /// blocks a whole pipeline behind a single slow message, while too large a capacity buffers so much
+    /// that the node looks healthy long after it is overloaded.
+    #[serde(default = "default_channel_capacity")]
+    pub channel_capacity: usize,
+    /// The upper bounds of the buckets of the histogram of the commit latency (the time from the creation
+    /// of a header to the commit of its certificate). Denominated in seconds, in increasing order.
+    #[serde(default = "default_commit_latency_buckets")]
+    pub commit_latency_buckets: Vec<f64>,
+    /// The address on which the workers also accept client transactions over HTTP (`POST /transactions`),
+    /// worker `id` listening on the port of this address plus `id`. The transactions are only received
+    /// over TCP if this address is not specified.
+    #[serde(default)]
+    pub http_transactions_address: Option<SocketAddr>,
+    /// The delay after which the workers stop waiting for the batches of the transactions submitted with
+    /// `POST /transactions?receipts=true` to be acknowledged by a quorum. Denominated in ms.
+    #[serde(default = "default_receipt_timeout")]
+    pub receipt_timeout_ms: u64,
+    /// The size that the batches of the workers never exceed (the workers reject larger transactions).
+    /// Denominated in bytes.
+    #[serde(default = "default_max_batch_size_bytes")]
+    pub max_batch_size_bytes: usize,
+    /// The number of transactions at which the workers seal a batch, even if `batch_size` is not reached.
+    #[serde(default = "default_max_batch_size_txs")]
+    pub max_batch_size_txs: usize,
+    /// The epoch of the signing keys. It selects the key the primary signs with (see `KeyPair::next`)
+    /// and the keys it accepts from the other authorities (see `Authority::key_rotation`).
+    #[serde(default)]
+    pub signing_epoch: Epoch,
+    /// The local address on which the primary and workers bind their listeners, on the ports of their
+    /// addresses in the committee. The committee addresses are those the peers connect to: behind a NAT
+    /// (or on a multi-homed host) they may not be local to the node. Binds all interfaces by default.
+    #[serde(default = "default_bind_address")]
+    pub bind_address: IpAddr,
+    /// The log levels of some modules, as comma-separated `<module>=<level>` directives (see
+    /// `parse_log_filters`). They quiet the chatty modules: a module never logs more than the level set with
+    /// `-v`, and `RUST_LOG` overrides these directives.
+    #[serde(default = "default_log_filters")]
+    pub log_filters: String,
+    /// The seed of the random choices of the node (the peers the primary and workers ask for missing
+    /// data), which are otherwise seeded from entropy. It is set with `--seed`, not read from the
+    /// parameters file, and kept upon reload.
+    #[serde(skip)]
+    pub seed: Option<u64>,
+    /// The round of the committed leader after which the consensus stops, letting the node shut down
+    /// (it runs forever if not set). It is set with `--max-committed-rounds`, not read from the parameters
+    /// file.
+    #[serde(skip)]
+    pub max_committed_rounds: Option<u64>,
+}
+
+/// The consensus protocols ordering the DAG.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConsensusProtocol {
+    /// Asynchronous: the leaders are elected after the fact (by a common coin) and committed with the
+    /// support of the next round.
+    Tusk,
+    /// Partially synchronous: the leaders are known in advance and committed as soon as the next round
+    /// votes for them.
+    Bullshark,
+}
+
+impl Default for ConsensusProtocol {
+    fn default() -> Self {
+        Self::Tusk
+    }
+}
+
+impl std::fmt::Display for ConsensusProtocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Tusk => write!(f, "tusk"),
+            Self::Bullshark => write!(f, "bullshark"),
+        }
+    }
+}
+
+/// The TLS certificate and private key of a node (PEM files). The public key of the certificate must be
+/// the (ed25519) public key of the node: this is how the other nodes authenticate it.
+#[derive(Deserialize, Clone, PartialEq)]
+pub struct TlsParameters {
+    pub certificate: String,
+    pub private_key: String,
+}
+
+// By default, the headers include every parent received before they are created.
+fn default_max_header_parents() -> usize {
+    usize::MAX
+}
+
+fn default_mempool_high_water_mark() -> usize {
+    5_000_000
+}
+
+fn default_mempool_low_water_mark() -> usize {
+    2_500_000
+}
+
+fn default_max_client_connections() -> usize {
+    1_000
+}
+
+fn default_channel_capacity() -> usize {
+    1_000
+}
+
+fn default_max_batch_size_bytes() -> usize {
+    usize::MAX
+}
+
+fn default_max_batch_size_txs() -> usize {
+    usize::MAX
+}
+
+fn default_suspect_after_rounds() -> u64 {
+    20
+}
+
+fn default_max_sync_requests() -> usize {
+    1_000
+}
+
+fn default_verification_threads() -> usize {
+    4
+}
+
+fn default_max_cached_certificates() -> usize {
+    10_000
+}
+
+fn default_max_header_drift() -> u64 {
+    10_000
+}
+
+fn default_durable_acks() -> bool {
+    false
+}
+
+fn default_bind_address() -> IpAddr {
+    IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+}
+
+fn default_log_filters() -> String {
+    "network=warn,consensus=info".to_string()
+}
+
+fn default_receipt_timeout() -> u64 {
+    10_000
+}
+
+fn default_commit_latency_buckets() -> Vec<f64> {
+    vec![0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0]
+}
+
+fn default_decryption_timeout() -> u64 {
+    10_000
+}
+
+fn default_min_reconnect_delay() -> u64 {
+    200
+}
+
+fn default_max_reconnect_delay() -> u64 {
+    60_000
+}
+
+fn default_reconnect_backoff_multiplier() -> f64 {
+    2.0
+}
+
+// The rate limits are far above the rates of a healthy committee, they only stop floods.
+fn default_max_peer_messages_per_second() -> u64 {
+    50_000
+}
+
+fn default_max_peer_bytes_per_second() -> u64 {
+    1_000_000_000
 }
 
 impl Default for Parameters {
@@ -90,30 +532,353 @@ impl Default for Parameters {
         Self {
             header_size: 1_000,
             max_header_delay: 100,
+            max_header_parents: default_max_header_parents(),
+            min_header_batches: 0,
             gc_depth: 50,
+            suspect_after_rounds: default_suspect_after_rounds(),
             sync_retry_delay: 5_000,
             sync_retry_nodes: 3,
+            max_sync_requests: default_max_sync_requests(),
+            verification_threads: default_verification_threads(),
+            max_cached_certificates: default_max_cached_certificates(),
+            max_header_drift: default_max_header_drift(),
             batch_size: 500_000,
             max_batch_delay: 100,
+            digest_flush_interval: 0,
+            output_address: None,
+            sub_dag_address: None,
+            decryption_timeout_ms: default_decryption_timeout(),
+            pruning_margin: None,
+            tls: None,
+            min_reconnect_delay_ms: default_min_reconnect_delay(),
+            max_reconnect_delay_ms: default_max_reconnect_delay(),
+            reconnect_backoff_multiplier: default_reconnect_backoff_multiplier(),
+            max_peer_messages_per_second: default_max_peer_messages_per_second(),
+            max_peer_bytes_per_second: default_max_peer_bytes_per_second(),
+            consensus: ConsensusProtocol::default(),
+            mempool_high_water_mark: default_mempool_high_water_mark(),
+            mempool_low_water_mark: default_mempool_low_water_mark(),
+            max_client_connections: default_max_client_connections(),
+            transaction_ttl: 0,
+            batch_compression_level: None,
+            durable_acks: default_durable_acks(),
+            dedup_window: 0,
+            priority_ordering: false,
+            batch_sample_rate: 0.0,
+            channel_capacity: default_channel_capacity(),
+            commit_latency_buckets: default_commit_latency_buckets(),
+            http_transactions_address: None,
+            receipt_timeout_ms: default_receipt_timeout(),
+            max_batch_size_bytes: default_max_batch_size_bytes(),
+            max_batch_size_txs: default_max_batch_size_txs(),
+            signing_epoch: 0,
+            bind_address: default_bind_address(),
+            log_filters: default_log_filters(),
+            seed: None,
+            max_committed_rounds: None,
         }
     }
 }
 
-impl Import for Parameters {}
+impl Import for Parameters {
+    /// Rejects the parameters from which the metrics cannot be built.
+    fn validate(&self) -> Result<(), ConfigError> {
+        self.check_commit_latency_buckets()
+    }
+}
 
 impl Parameters {
+    /// The smallest garbage collection depth with which a lagging node can still sync: the number of rounds
+    /// the DAG may advance during a sync retry (assuming a round lasts at least `max_header_delay`), and
+    /// no less than the rounds spanned by a commit.
+    pub fn min_gc_depth(&self) -> u64 {
+        let delay = self.max_header_delay.max(1);
+        let rounds = self.sync_retry_delay / delay + (self.sync_retry_delay % delay != 0) as u64;
+        rounds.max(MIN_GC_DEPTH)
+    }
+
+    /// Checks that `gc_depth` is not dangerously small (see `min_gc_depth`).
+    pub fn check_gc_depth(&self) -> Result<(), ConfigError> {
+        let minimum = self.min_gc_depth();
+        if self.gc_depth < minimum {
+            return Err(ConfigError::GcDepthTooSmall {
+                gc_depth: self.gc_depth,
+                minimum,
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks that we can listen on `bind_address` (the listeners are only bound once the tasks are spawned).
+    pub fn check_bind_address(&self) -> Result<(), ConfigError> {
+        std::net::TcpListener::bind(SocketAddr::new(self.bind_address, 0))
+            .map(|_| ())
+            .map_err(|e| ConfigError::UnbindableAddress {
+                address: self.bind_address,
+                message: e.to_string(),
+            })
+    }
+
+    /// Checks that the upper bounds of `commit_latency_buckets` are finite and strictly increasing, as the
+    /// histogram requires.
+    pub fn check_commit_latency_buckets(&self) -> Result<(), ConfigError> {
+        let buckets = &self.commit_latency_buckets;
+        if buckets.iter().any(|x| !x.is_finite()) || buckets.windows(2).any(|x| x[0] >= x[1]) {
+            return Err(ConfigError::InvalidLatencyBuckets(buckets.clone()));
+        }
+        Ok(())
+    }
+
     pub fn log(&self) {
         info!("Header size set to {} B", self.header_size);
         info!("Max header delay set to {} ms", self.max_header_delay);
+        if self.max_header_parents != usize::MAX {
+            info!("Max header parents set to {}", self.max_header_parents);
+        }
+        if self.min_header_batches > 0 {
+            info!("Min header batches set to {}", self.min_header_batches);
+        }
         info!("Garbage collection depth set to {} rounds", self.gc_depth);
+        info!(
+            "Suspect authorities after {} rounds",
+            self.suspect_after_rounds
+        );
         info!("Sync retry delay set to {} ms", self.sync_retry_delay);
         info!("Sync retry nodes set to {} nodes", self.sync_retry_nodes);
+        info!("Max sync requests set to {}", self.max_sync_requests);
+        info!("Verification threads set to {}", self.verification_threads);
+        info!(
+            "Max cached certificates set to {}",
+            self.max_cached_certificates
+        );
+        info!("Max header drift set to {} ms", self.max_header_drift);
         info!("Batch size set to {} B", self.batch_size);
         info!("Max batch delay set to {} ms", self.max_batch_delay);
+        if self.digest_flush_interval > 0 {
+            info!(
+                "Digest flush interval set to {} ms",
+                self.digest_flush_interval
+            );
+        }
+        if self.max_batch_size_bytes != usize::MAX {
+            info!("Max batch size set to {} B", self.max_batch_size_bytes);
+        }
+        if self.max_batch_size_txs != usize::MAX {
+            info!(
+                "Max batch size set to {} transactions",
+                self.max_batch_size_txs
+            );
+        }
+        if let Some(address) = self.output_address {
+            info!("Certificate stream address set to {}", address);
+        }
+        if let Some(address) = self.sub_dag_address {
+            info!("Sub-dag stream address set to {}", address);
+        }
+        info!(
+            "Decryption timeout set to {} ms",
+            self.decryption_timeout_ms
+        );
+        if let Some(margin) = self.pruning_margin {
+            info!("Store pruning margin set to {} rounds", margin);
+        }
+        if let Some(tls) = &self.tls {
+            info!("TLS certificate set to {}", tls.certificate);
+        }
+        info!(
+            "Reconnect delay set to {} ms up to {} ms (x{} per attempt)",
+            self.min_reconnect_delay_ms,
+            self.max_reconnect_delay_ms,
+            self.reconnect_backoff_multiplier
+        );
+        info!(
+            "Peer rate limit set to {} messages/s and {} B/s",
+            self.max_peer_messages_per_second, self.max_peer_bytes_per_second
+        );
+        info!("Consensus protocol set to {}", self.consensus);
+        info!(
+            "Mempool water marks set to {} B (high) and {} B (low)",
+            self.mempool_high_water_mark, self.mempool_low_water_mark
+        );
+        info!(
+            "Max client connections set to {}",
+            self.max_client_connections
+        );
+        if self.transaction_ttl > 0 {
+            info!("Transaction TTL set to {} ms", self.transaction_ttl);
+        }
+        if let Some(level) = self.batch_compression_level {
+            info!("Batch compression level set to {}", level);
+        }
+        info!("Durable acknowledgements set to {}", self.durable_acks);
+        if self.dedup_window > 0 {
+            info!(
+                "Deduplication window set to {} transactions",
+                self.dedup_window
+            );
+        }
+        if self.priority_ordering {
+            info!("Batches ordered by transaction priority");
+        }
+        if self.batch_sample_rate > 0.0 {
+            info!("Batch sample rate set to {}", self.batch_sample_rate);
+        }
+        info!("Channel capacity set to {}", self.channel_capacity);
+        info!(
+            "Commit latency buckets set to {:?} s",
+            self.commit_latency_buckets
+        );
+        if let Some(address) = self.http_transactions_address {
+            info!("HTTP transactions address set to {}", address);
+            info!("Receipt timeout set to {} ms", self.receipt_timeout_ms);
+        }
+        if self.signing_epoch > 0 {
+            info!("Signing epoch set to {}", self.signing_epoch);
+        }
+        if !self.bind_address.is_unspecified() {
+            info!("Bind address set to {}", self.bind_address);
+        }
+        info!("Log filters set to '{}'", self.log_filters);
+        if let Some(seed) = self.seed {
+            info!("Seed set to {}", seed);
+        }
+        if let Some(rounds) = self.max_committed_rounds {
+            info!("Max committed rounds set to {}", rounds);
+        }
+    }
+
+    /// The local address on which to listen for the connections made to `advertised`, one of our addresses
+    /// in the committee.
+    pub fn listen_address(&self, advertised: SocketAddr) -> SocketAddr {
+        SocketAddr::new(self.bind_address, advertised.port())
+    }
+
+    /// Copies the live-reloadable fields of `new` into `self`. Returns the names of the other fields whose
+    /// value differs in `new`: these changes are ignored until the node restarts.
+    pub fn reload(&mut self, new: &Parameters) -> Vec<&'static str> {
+        self.max_header_delay = new.max_header_delay;
+        self.min_header_batches = new.min_header_batches;
+        self.suspect_after_rounds = new.suspect_after_rounds;
+        self.sync_retry_delay = new.sync_retry_delay;
+        self.batch_size = new.batch_size;
+        self.transaction_ttl = new.transaction_ttl;
+        self.dedup_window = new.dedup_window;
+        self.batch_sample_rate = new.batch_sample_rate;
+        self.signing_epoch = new.signing_epoch;
+
+        let mut ignored = Vec::new();
+        if self.header_size != new.header_size {
+            ignored.push("header_size");
+        }
+        if self.max_header_parents != new.max_header_parents {
+            ignored.push("max_header_parents");
+        }
+        if self.gc_depth != new.gc_depth {
+            ignored.push("gc_depth");
+        }
+        if self.sync_retry_nodes != new.sync_retry_nodes {
+            ignored.push("sync_retry_nodes");
+        }
+        if self.max_sync_requests != new.max_sync_requests {
+            ignored.push("max_sync_requests");
+        }
+        if self.verification_threads != new.verification_threads {
+            ignored.push("verification_threads");
+        }
+        if self.max_cached_certificates != new.max_cached_certificates {
+            ignored.push("max_cached_certificates");
+        }
+        if self.max_header_drift != new.max_header_drift {
+            ignored.push("max_header_drift");
+        }
+        if self.max_batch_delay != new.max_batch_delay {
+            ignored.push("max_batch_delay");
+        }
+        if self.digest_flush_interval != new.digest_flush_interval {
+            ignored.push("digest_flush_interval");
+        }
+        if self.output_address != new.output_address {
+            ignored.push("output_address");
+        }
+        if self.sub_dag_address != new.sub_dag_address {
+            ignored.push("sub_dag_address");
+        }
+        if self.decryption_timeout_ms != new.decryption_timeout_ms {
+            ignored.push("decryption_timeout_ms");
+        }
+        if self.pruning_margin != new.pruning_margin {
+            ignored.push("pruning_margin");
+        }
+        if self.tls != new.tls {
+            ignored.push("tls");
+        }
+        if self.min_reconnect_delay_ms != new.min_reconnect_delay_ms {
+            ignored.push("min_reconnect_delay_ms");
+        }
+        if self.max_reconnect_delay_ms != new.max_reconnect_delay_ms {
+            ignored.push("max_reconnect_delay_ms");
+        }
+        if (self.reconnect_backoff_multiplier - new.reconnect_backoff_multiplier).abs()
+            > f64::EPSILON
+        {
+            ignored.push("reconnect_backoff_multiplier");
+        }
+        if self.max_peer_messages_per_second != new.max_peer_messages_per_second {
+            ignored.push("max_peer_messages_per_second");
+        }
+        if self.max_peer_bytes_per_second != new.max_peer_bytes_per_second {
+            ignored.push("max_peer_bytes_per_second");
+        }
+        if self.consensus != new.consensus {
+            ignored.push("consensus");
+        }
+        if self.mempool_high_water_mark != new.mempool_high_water_mark {
+            ignored.push("mempool_high_water_mark");
+        }
+        if self.mempool_low_water_mark != new.mempool_low_water_mark {
+            ignored.push("mempool_low_water_mark");
+        }
+        if self.max_client_connections != new.max_client_connections {
+            ignored.push("max_client_connections");
+        }
+        if self.batch_compression_level != new.batch_compression_level {
+            ignored.push("batch_compression_level");
+        }
+        if self.durable_acks != new.durable_acks {
+            ignored.push("durable_acks");
+        }
+        if self.priority_ordering != new.priority_ordering {
+            ignored.push("priority_ordering");
+        }
+        if self.channel_capacity != new.channel_capacity {
+            ignored.push("channel_capacity");
+        }
+        if self.commit_latency_buckets != new.commit_latency_buckets {
+            ignored.push("commit_latency_buckets");
+        }
+        if self.http_transactions_address != new.http_transactions_address {
+            ignored.push("http_transactions_address");
+        }
+        if self.receipt_timeout_ms != new.receipt_timeout_ms {
+            ignored.push("receipt_timeout_ms");
+        }
+        if self.max_batch_size_bytes != new.max_batch_size_bytes {
+            ignored.push("max_batch_size_bytes");
+        }
+        if self.max_batch_size_txs != new.max_batch_size_txs {
+            ignored.push("max_batch_size_txs");
+        }
+        if self.bind_address != new.bind_address {
+            ignored.push("bind_address");
+        }
+        if self.log_filters != new.log_filters {
+            ignored.push("log_filters");
+        }
+        ignored
     }
 }
 
-#[derive(Clone, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct PrimaryAddresses {
     /// Address to receive messages from other primaries (WAN).
     pub primary_to_primary: SocketAddr,
@@ -121,7 +886,7 @@ pub struct PrimaryAddresses {
     pub worker_to_primary: SocketAddr,
 }
 
-#[derive(Clone, Deserialize, Eq, Hash, PartialEq)]
+#[derive(Clone, Serialize, Deserialize, Eq, Hash, PartialEq)]
 pub struct WorkerAddresses {
     /// Address to receive client transactions (WAN).
     pub transactions: SocketAddr,
@@ -131,24 +896,415 @@ pub struct WorkerAddresses {
     pub primary_to_worker: SocketAddr,
 }
 
-#[derive(Clone, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Authority {
     /// The voting power of this authority.
     pub stake: Stake,
     /// The network addresses of the primary.
     pub primary: PrimaryAddresses,
     /// Map of workers' id and their network addresses.
+    #[serde(with = "worker_ids")]
     pub workers: HashMap<WorkerId, WorkerAddresses>,
+    /// The rotation of the signing key of the authority, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_rotation: Option<KeyRotation>,
+}
+
+/// The rotation of the signing key of an authority. The authority keeps its name (the key under which it
+/// appears in the committee) but signs with `key` from epoch `from_epoch`. The others accept both keys
+/// during the overlap, from `from_epoch` until `overlap_until` (excluded), and only `key` afterwards.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct KeyRotation {
+    pub key: PublicKey,
+    pub from_epoch: Epoch,
+    pub overlap_until: Epoch,
+}
+
+/// (De)serializes maps keyed by worker ids with string keys, since TOML tables only have string keys. This
+/// matches the way JSON already writes them, so JSON files are unaffected.
+mod worker_ids {
+    use super::WorkerId;
+    use serde::de::Error as _;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::collections::HashMap;
+
+    pub fn serialize<S, V>(workers: &HashMap<WorkerId, V>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+        V: Serialize,
+    {
+        serializer.collect_map(workers.iter().map(|(id, value)| (id.to_string(), value)))
+    }
+
+    pub fn deserialize<'de, D, V>(deserializer: D) -> Result<HashMap<WorkerId, V>, D::Error>
+    where
+        D: Deserializer<'de>,
+        V: Deserialize<'de>,
+    {
+        HashMap::<String, V>::deserialize(deserializer)?
+            .into_iter()
+            .map(|(id, value)| {
+                id.parse::<WorkerId>()
+                    .map(|id| (id, value))
+                    .map_err(|e| D::Error::custom(format!("Invalid worker id '{}': {}", id, e)))
+            })
+            .collect()
+    }
 }
 
-#[derive(Clone, Deserialize)]
+/// The threshold encryption settings of a worker shard, overriding those of the committee.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ShardThreshold {
+    /// The degree of the shard's key set: decrypting its batches requires `threshold + 1` shares.
+    pub threshold: usize,
+    /// The threshold public key of the shard (defaults to the one of the committee).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_key: Option<ThresholdPublicKey>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Committee {
+    /// The signature scheme of the keys of the authorities (committee files without it use ed25519).
+    #[serde(default)]
+    pub signature_scheme: SchemeKind,
+    /// The hash function of the digests of the batches, headers and certificates (committee files without it
+    /// use SHA-512).
+    #[serde(default)]
+    pub hash_function: HashKind,
+    /// Whether the workers of the committee acknowledge the batches of their peers only once they are on
+    /// disk (the `durable_acks` parameter, which all of them must share). Committee files without it do not
+    /// check the setting.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub durable_acks: Option<bool>,
+    /// The threshold public key matching the key shares of the authorities. Committee files written before
+    /// this field existed do not have it, in which case the threshold keypairs cannot be checked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub threshold_public_key: Option<ThresholdPublicKey>,
+    /// The threshold settings of the worker shards (identified by worker id) that use their own key set.
+    #[serde(
+        default,
+        skip_serializing_if = "HashMap::is_empty",
+        with = "worker_ids"
+    )]
+    pub shard_thresholds: HashMap<WorkerId, ShardThreshold>,
     pub authorities: BTreeMap<PublicKey, Authority>,
 }
 
-impl Import for Committee {}
+impl Import for Committee {
+    /// Rejects the committees whose stakes cannot be added up or whose workers reuse a port, and warns
+    /// about those in which a single authority holds a quorum on its own.
+    fn validate(&self) -> Result<(), ConfigError> {
+        self.check_stake()?;
+        self.check_worker_ports()?;
+        if let Some(name) = self.dominant_authority() {
+            warn!(
+                "Authority {} holds a quorum of the stake on its own: the committee is centralized",
+                name
+            );
+        }
+        Ok(())
+    }
+}
+impl Export for Committee {}
+
+/// The file holding the detached signature of the config file `path`.
+fn signature_path(path: &str) -> String {
+    format!("{}.sig", path)
+}
+
+/// The digest signed by the detached signature of a config file (the first 32 bytes of the SHA-512 of
+/// its content).
+fn file_digest(data: &[u8]) -> Digest {
+    Digest(<[u8; 32]>::try_from(&Sha512::digest(data)[..32]).unwrap())
+}
 
 impl Committee {
+    /// Creates a committee where each authority runs its primary and `workers` workers on a single host.
+    /// Ports are assigned sequentially starting from `base_port`: two for each primary, then three for
+    /// each of its workers (the same layout used by the benchmark scripts).
+    pub fn new(
+        authorities: Vec<(PublicKey, Stake, IpAddr)>,
+        base_port: u16,
+        workers: usize,
+    ) -> Result<Self, ConfigError> {
+        let mut port = base_port as u32;
+        let mut next_address = |host: IpAddr| -> Result<SocketAddr, ConfigError> {
+            let address = u16::try_from(port)
+                .map(|x| SocketAddr::new(host, x))
+                .map_err(|_| {
+                    ConfigError::InvalidCommittee(format!(
+                        "Not enough ports above {} for all authorities",
+                        base_port
+                    ))
+                })?;
+            port += 1;
+            Ok(address)
+        };
+
+        let mut committee = BTreeMap::new();
+        for (name, stake, host) in authorities {
+            let primary = PrimaryAddresses {
+                primary_to_primary: next_address(host)?,
+                worker_to_primary: next_address(host)?,
+            };
+            let mut worker_addresses = HashMap::new();
+            for id in 0..workers {
+                worker_addresses.insert(
+                    id as WorkerId,
+                    WorkerAddresses {
+                        primary_to_worker: next_address(host)?,
+                        transactions: next_address(host)?,
+                        worker_to_worker: next_address(host)?,
+                    },
+                );
+            }
+            let authority = Authority {
+                stake,
+                primary,
+                workers: worker_addresses,
+                key_rotation: None,
+            };
+            if committee.insert(name, authority).is_some() {
+                return Err(ConfigError::InvalidCommittee(format!(
+                    "Authority {} appears more than once",
+                    name
+                )));
+            }
+        }
+        Ok(Self {
+            threshold_public_key: None,
+            signature_scheme: SchemeKind::selected(),
+            hash_function: HashKind::selected(),
+            durable_acks: None,
+            shard_thresholds: HashMap::new(),
+            authorities: committee,
+        })
+    }
+
+    /// Checks that a threshold keypair was derived from the same key set as the threshold public key of the
+    /// committee. The check passes if the committee does not specify a threshold public key.
+    pub fn check_threshold_key(&self, pk_set: &PublicKeySet) -> Result<(), ConfigError> {
+        match &self.threshold_public_key {
+            Some(expected) if expected != &pk_set.public_key() => {
+                Err(ConfigError::ThresholdKeyMismatch)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks that the committee has enough authorities to hold `threshold + 1` key shares.
+    pub fn check_threshold(&self, threshold: usize) -> Result<(), ConfigError> {
+        if threshold >= self.size() {
+            return Err(ConfigError::IncompatibleThreshold {
+                threshold,
+                size: self.size(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Returns the threshold the committee sets for worker `id`, if its shard uses its own key set.
+    pub fn shard_threshold(&self, id: &WorkerId) -> Option<usize> {
+        self.shard_thresholds.get(id).map(|x| x.threshold)
+    }
+
+    /// Checks that the threshold keypair of worker `id` matches the settings of its shard (or those of the
+    /// committee if the shard does not use its own key set), and that the committee can hold its shares.
+    pub fn check_worker_threshold_key(
+        &self,
+        id: &WorkerId,
+        pk_set: &PublicKeySet,
+    ) -> Result<(), ConfigError> {
+        let threshold = pk_set.threshold();
+        self.check_threshold(threshold)?;
+        match self.shard_thresholds.get(id) {
+            Some(shard) => {
+                if shard.threshold != threshold {
+                    return Err(ConfigError::ThresholdMismatch {
+                        worker: *id,
+                        expected: shard.threshold,
+                        found: threshold,
+                    });
+                }
+                match shard
+                    .public_key
+                    .as_ref()
+                    .or_else(|| self.threshold_public_key.as_ref())
+                {
+                    Some(expected) if expected != &pk_set.public_key() => {
+                        Err(ConfigError::ThresholdKeyMismatch)
+                    }
+                    _ => Ok(()),
+                }
+            }
+            None => self.check_threshold_key(pk_set),
+        }
+    }
+
+    /// Checks that the committee has a positive total stake, that no two authorities share an address, and
+    /// that the threshold of each worker shard is compatible with the committee size.
+    pub fn sanity_check(&self) -> Result<(), ConfigError> {
+        self.check_signature_scheme()?;
+        self.check_hash_function()?;
+
+        self.check_stake()?;
+        self.check_worker_ports()?;
+        if self.total_stake() == 0 {
+            return Err(ConfigError::InvalidCommittee(
+                "The total stake must be positive".to_string(),
+            ));
+        }
+
+        let mut owners = HashMap::new();
+        for (name, address) in self.addresses() {
+            if let Some(other) = owners.insert(address, name).filter(|x| x != &name) {
+                return Err(ConfigError::InvalidCommittee(format!(
+                    "Address {} is shared by authorities {} and {}",
+                    address, other, name
+                )));
+            }
+        }
+
+        for (name, authority) in &self.authorities {
+            if let Some(rotation) = &authority.key_rotation {
+                if rotation.from_epoch > rotation.overlap_until {
+                    return Err(ConfigError::InvalidCommittee(format!(
+                        "The key rotation of {} ends before it starts",
+                        name
+                    )));
+                }
+                if self.authorities.contains_key(&rotation.key) {
+                    return Err(ConfigError::InvalidCommittee(format!(
+                        "Authority {} rotates to the key of authority {}",
+                        name, rotation.key
+                    )));
+                }
+            }
+        }
+
+        for (id, shard) in &self.shard_thresholds {
+            if !self
+                .authorities
+                .values()
+                .any(|x| x.workers.contains_key(id))
+            {
+                return Err(ConfigError::InvalidCommittee(format!(
+                    "The threshold of unknown worker {} is set",
+                    id
+                )));
+            }
+            self.check_threshold(shard.threshold)?;
+        }
+        Ok(())
+    }
+
+    /// Exports the committee, and signs the resulting file with the governance key `secret`. The
+    /// (base64) signature is written next to it, in `<path>.sig`.
+    pub fn export_signed(&self, path: &str, secret: &SecretKey) -> Result<(), ConfigError> {
+        self.export(path)?;
+        let signature_file = signature_path(path);
+        let writer = || -> Result<(), Box<dyn std::error::Error>> {
+            let data = fs::read(path)?;
+            let signature = Signature::new(&file_digest(&data), secret);
+            fs::write(&signature_file, format!("{}\n", signature.encode_base64()))?;
+            Ok(())
+        };
+        writer().map_err(|e| ConfigError::ExportError {
+            file: signature_file.clone(),
+            message: e.to_string(),
+        })
+    }
+
+    /// Imports a committee file after checking its detached signature (in `<path>.sig`) against the
+    /// public key of the governance key, so that a tampered file is never loaded.
+    pub fn import_signed(path: &str, trust_anchor: &PublicKey) -> Result<Self, ConfigError> {
+        let read = |file: &str| {
+            fs::read(file).map_err(|e| ConfigError::ImportError {
+                file: file.to_string(),
+                message: e.to_string(),
+            })
+        };
+        let data = read(path)?;
+        let encoded = read(&signature_path(path))?;
+        let invalid = || ConfigError::InvalidSignature {
+            file: path.to_string(),
+        };
+        let signature = std::str::from_utf8(&encoded)
+            .ok()
+            .and_then(|x| Signature::decode_base64(x.trim()).ok())
+            .ok_or_else(invalid)?;
+        signature
+            .verify(&file_digest(&data), trust_anchor)
+            .map_err(|_| invalid())?;
+
+        // Parse the very bytes we verified rather than reading the file again.
+        let parser = || -> Result<Self, Box<dyn std::error::Error>> {
+            let data = std::str::from_utf8(&data)?;
+            if is_toml(path) {
+                Ok(toml::from_str(data)?)
+            } else {
+                Ok(serde_json::from_str(data)?)
+            }
+        };
+        let committee = parser().map_err(|e| ConfigError::ImportError {
+            file: path.to_string(),
+            message: e.to_string(),
+        })?;
+        committee.validate()?;
+        Ok(committee)
+    }
+
+    /// Checks that the keys of the committee are in the signature scheme this node was built for.
+    pub fn check_signature_scheme(&self) -> Result<(), ConfigError> {
+        if self.signature_scheme != SchemeKind::selected() {
+            return Err(ConfigError::SchemeMismatch {
+                committee: self.signature_scheme,
+                node: SchemeKind::selected(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks that the committee computes its digests with the hash function this node was built for: the
+    /// digests of the other nodes would never match ours.
+    pub fn check_hash_function(&self) -> Result<(), ConfigError> {
+        if self.hash_function != HashKind::selected() {
+            return Err(ConfigError::HashMismatch {
+                committee: self.hash_function,
+                node: HashKind::selected(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks that this node acknowledges batches as the other workers of the committee do: a worker requiring
+    /// durable acknowledgements never gathers a quorum of them from workers that do not store batches first.
+    pub fn check_durable_acks(&self, durable_acks: bool) -> Result<(), ConfigError> {
+        match self.durable_acks {
+            Some(committee) if committee != durable_acks => Err(ConfigError::DurableAcksMismatch {
+                committee,
+                node: durable_acks,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns all the network addresses of the committee (of primaries and workers), along with the
+    /// authority they belong to.
+    pub fn addresses(&self) -> Vec<(PublicKey, SocketAddr)> {
+        let mut addresses = Vec::new();
+        for (name, authority) in &self.authorities {
+            addresses.push((*name, authority.primary.primary_to_primary));
+            addresses.push((*name, authority.primary.worker_to_primary));
+            for worker in authority.workers.values() {
+                addresses.push((*name, worker.transactions));
+                addresses.push((*name, worker.worker_to_worker));
+                addresses.push((*name, worker.primary_to_worker));
+            }
+        }
+        addresses
+    }
+
     /// Returns the number of authorities.
     pub fn size(&self) -> usize {
         self.authorities.len()
@@ -159,6 +1315,25 @@ impl Committee {
         self.authorities.get(&name).map_or_else(|| 0, |x| x.stake)
     }
 
+    /// Returns the keys that the authority `name` may sign with in `epoch`: its name, the key it rotates
+    /// to, or both during the overlap of the rotation. There is none if `name` is not in the committee.
+    pub fn signing_keys(&self, name: &PublicKey, epoch: Epoch) -> Vec<PublicKey> {
+        match self.authorities.get(name).map(|x| &x.key_rotation) {
+            None => Vec::new(),
+            Some(None) => vec![*name],
+            Some(Some(rotation)) => {
+                let mut keys = Vec::new();
+                if epoch < rotation.overlap_until {
+                    keys.push(*name);
+                }
+                if epoch >= rotation.from_epoch {
+                    keys.push(rotation.key);
+                }
+                keys
+            }
+        }
+    }
+
     /// Returns the stake of all authorities except `myself`.
     pub fn others_stake(&self, myself: &PublicKey) -> Vec<(PublicKey, Stake)> {
         self.authorities
@@ -172,16 +1347,94 @@ impl Committee {
     pub fn quorum_threshold(&self) -> Stake {
         // If N = 3f + 1 + k (0 <= k < 3)
         // then (2 N + 3) / 3 = 2f + 1 + (2k + 2)/3 = 2f + 1 + k = N - f
-        let total_votes: Stake = self.authorities.values().map(|x| x.stake).sum();
-        2 * total_votes / 3 + 1
+        2 * self.total_stake() / 3 + 1
     }
 
     /// Returns the stake required to reach availability (f+1).
     pub fn validity_threshold(&self) -> Stake {
         // If N = 3f + 1 + k (0 <= k < 3)
         // then (N + 2) / 3 = f + 1 + k/3 = f + 1
-        let total_votes: Stake = self.authorities.values().map(|x| x.stake).sum();
-        (total_votes + 2) / 3
+        (self.total_stake() + 2) / 3
+    }
+
+    /// Returns the stake of all the authorities.
+    pub fn total_stake(&self) -> Stake {
+        self.authorities.values().map(|x| x.stake).sum()
+    }
+
+    /// Checks that the stakes add up to a total stake the thresholds can be computed from (their
+    /// computation doubles it).
+    pub fn check_stake(&self) -> Result<(), ConfigError> {
+        self.authorities
+            .values()
+            .try_fold(0 as Stake, |total, x| total.checked_add(x.stake))
+            .and_then(|total| total.checked_mul(2))
+            .map(|_| ())
+            .ok_or_else(|| ConfigError::InvalidCommittee("The total stake overflows".to_string()))
+    }
+
+    /// Checks that the transactions, worker-to-worker, and primary-to-worker addresses of each worker use
+    /// distinct ports, so that the client-facing port can be firewalled separately from the others.
+    pub fn check_worker_ports(&self) -> Result<(), ConfigError> {
+        for (name, authority) in &self.authorities {
+            for (id, x) in &authority.workers {
+                let ports = [
+                    x.transactions.port(),
+                    x.worker_to_worker.port(),
+                    x.primary_to_worker.port(),
+                ];
+                if ports[0] == ports[1] || ports[0] == ports[2] || ports[1] == ports[2] {
+                    return Err(ConfigError::InvalidCommittee(format!(
+                        "Worker {} of authority {} uses the same port for two of its addresses",
+                        id, name
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets the stake of some authorities, leaving their keys and addresses (and the other authorities)
+    /// untouched. Refuses to let an authority hold a quorum on its own unless `force` is set. The committee
+    /// is left unchanged on error.
+    pub fn reweight(
+        &mut self,
+        stakes: &[(PublicKey, Stake)],
+        force: bool,
+    ) -> Result<(), ConfigError> {
+        let mut reweighted = self.clone();
+        for (name, stake) in stakes {
+            reweighted
+                .authorities
+                .get_mut(name)
+                .ok_or(ConfigError::NotInCommittee(*name))?
+                .stake = *stake;
+        }
+        reweighted.check_stake()?;
+        if reweighted.total_stake() == 0 {
+            return Err(ConfigError::InvalidCommittee(
+                "The total stake must be positive".to_string(),
+            ));
+        }
+        if let Some(name) = reweighted.dominant_authority() {
+            if !force {
+                return Err(ConfigError::InvalidCommittee(format!(
+                    "Authority {} would hold a quorum of the stake on its own",
+                    name
+                )));
+            }
+        }
+        *self = reweighted;
+        Ok(())
+    }
+
+    /// Returns an authority holding a quorum of the stake on its own (if any).
+    pub fn dominant_authority(&self) -> Option<PublicKey> {
+        let quorum = self.quorum_threshold();
+        self.authorities
+            .iter()
+            .find(|(_, x)| x.stake >= quorum)
+            .map(|(name, _)| *name)
     }
 
     /// Returns the primary addresses of the target primary.
@@ -229,6 +1482,20 @@ impl Committee {
             .collect()
     }
 
+    /// Returns the ids of all our workers (sorted).
+    pub fn our_worker_ids(&self, myself: &PublicKey) -> Result<Vec<WorkerId>, ConfigError> {
+        let mut ids: Vec<_> = self
+            .authorities
+            .get(myself)
+            .ok_or_else(|| ConfigError::NotInCommittee(*myself))?
+            .workers
+            .keys()
+            .cloned()
+            .collect();
+        ids.sort_unstable();
+        Ok(ids)
+    }
+
     /// Returns the addresses of all workers with a specific id except the ones of the authority
     /// specified by `myself`.
     pub fn others_workers(
@@ -256,6 +1523,17 @@ pub struct KeyPair {
     pub name: PublicKey,
     /// The node's secret key.
     pub secret: SecretKey,
+    /// The key the node rotates to, if any (see `KeyPair::rotate`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next: Option<NextKey>,
+}
+
+/// The key a node signs with from epoch `from_epoch` (while keeping `KeyPair::name` as identifier).
+#[derive(Serialize, Deserialize)]
+pub struct NextKey {
+    pub key: PublicKey,
+    pub secret: SecretKey,
+    pub from_epoch: Epoch,
 }
 
 impl Import for KeyPair {}
@@ -264,7 +1542,27 @@ impl Export for KeyPair {}
 impl KeyPair {
     pub fn new() -> Self {
         let (name, secret) = generate_production_keypair();
-        Self { name, secret }
+        Self {
+            name,
+            secret,
+            next: None,
+        }
+    }
+
+    /// Generates the key the node signs with from `from_epoch`, and returns the rotation to add to the
+    /// node's entry in the committee (the old key is accepted until `overlap_until`).
+    pub fn rotate(&mut self, from_epoch: Epoch, overlap_until: Epoch) -> KeyRotation {
+        let (key, secret) = generate_production_keypair();
+        self.next = Some(NextKey {
+            key,
+            secret,
+            from_epoch,
+        });
+        KeyRotation {
+            key,
+            from_epoch,
+            overlap_until,
+        }
     }
 }
 
@@ -282,9 +1580,16 @@ pub struct ThresholdKeyPair {
     pub sk_share: SerdeSecret<SecretKeyShare>,
     /// The threshold public key set (used for decrypting)
     pub pk_set: PublicKeySet,
+    /// The epoch during which this key share is used (files without an epoch belong to epoch 0).
+    #[serde(default)]
+    pub epoch: Epoch,
+    /// How the key set was derived, if it was derived from a seed (rather than by a DKG).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keygen: Option<SeededKeygen>,
 }
 
 impl ThresholdKeyPair {
+    /// Derives the key share of epoch 0 (see `with_epoch` to use it in another epoch).
     pub fn new(threshold: usize, node_index: usize, seed: u64) -> Self {
         let mut rng = rand::prelude::StdRng::seed_from_u64(seed);
         let sk_set = SecretKeySet::random(threshold, &mut rng);
@@ -294,11 +1599,132 @@ impl ThresholdKeyPair {
             node_index,
             sk_share,
             pk_set,
+            epoch: 0,
+            keygen: Some(SeededKeygen::new(seed, threshold)),
+        }
+    }
+
+    pub fn with_epoch(self, epoch: Epoch) -> Self {
+        Self { epoch, ..self }
+    }
+
+    /// Records the number of key shares generated from the seed (when known).
+    pub fn with_num_shares(mut self, num_shares: usize) -> Self {
+        if let Some(keygen) = &mut self.keygen {
+            keygen.num_shares = Some(num_shares);
         }
+        self
+    }
+
+    /// Checks that this keypair was generated along with the threshold public key of `file`: from the
+    /// same seed and with the same parameters (as far as both files record them), and with the same key.
+    pub fn verify_file(&self, file: &ThresholdPublicKeyFile) -> Result<(), ConfigError> {
+        if let (Some(ours), Some(theirs)) = (&self.keygen, &file.keygen) {
+            ours.check(theirs)?;
+        }
+        self.verify(&file.public_key)
+    }
+
+    /// Checks that the key set of this keypair has the threshold public key `public_key`, and that the
+    /// secret key share is the one committed in the key set at the index of the node.
+    pub fn verify(&self, public_key: &ThresholdPublicKey) -> Result<(), ConfigError> {
+        if &self.pk_set.public_key() != public_key {
+            return Err(ConfigError::ThresholdKeyMismatch);
+        }
+        if self.pk_set.public_key_share(self.node_index) != self.sk_share.public_key_share() {
+            return Err(ConfigError::ShareMismatch(self.node_index));
+        }
+        Ok(())
     }
 }
 impl Import for ThresholdKeyPair {}
 impl Export for ThresholdKeyPair {}
 
+/// The parameters a threshold key set was derived from a seed with. The seed itself is not recorded: its
+/// fingerprint tells which files were generated together, and whether a seed is reused across committees
+/// (it tells no more about the seed than the threshold public key already does).
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SeededKeygen {
+    /// The hash of the seed.
+    pub seed_fingerprint: Digest,
+    /// The threshold of the key set.
+    pub threshold: usize,
+    /// The number of key shares generated from the seed (if known).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub num_shares: Option<usize>,
+}
+
+impl SeededKeygen {
+    pub fn new(seed: u64, threshold: usize) -> Self {
+        Self {
+            seed_fingerprint: Self::fingerprint(seed),
+            threshold,
+            num_shares: None,
+        }
+    }
+
+    /// Hashes a seed (under its own prefix, so that it never doubles as the digest of some other data).
+    pub fn fingerprint(seed: u64) -> Digest {
+        let data = [&b"threshold-keygen-seed"[..], &seed.to_le_bytes()].concat();
+        Digest(<[u8; 32]>::try_from(&Sha512::digest(&data)[..32]).unwrap())
+    }
+
+    /// Checks that `other` describes the same key generation.
+    pub fn check(&self, other: &Self) -> Result<(), ConfigError> {
+        if self.seed_fingerprint != other.seed_fingerprint {
+            return Err(ConfigError::SeedMismatch);
+        }
+        if self.threshold != other.threshold {
+            return Err(ConfigError::KeygenMismatch {
+                keypair: format!("a threshold of {}", self.threshold),
+                public_key: format!("a threshold of {}", other.threshold),
+            });
+        }
+        match (self.num_shares, other.num_shares) {
+            (Some(ours), Some(theirs)) if ours != theirs => Err(ConfigError::KeygenMismatch {
+                keypair: format!("{} key shares", ours),
+                public_key: format!("{} key shares", theirs),
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The content of a threshold public key file: the key, and how it was generated (for the key sets
+/// derived from a seed). Files holding only the key are still read.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(from = "PublicKeyFileContent")]
+pub struct ThresholdPublicKeyFile {
+    pub public_key: ThresholdPublicKey,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keygen: Option<SeededKeygen>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PublicKeyFileContent {
+    WithKeygen {
+        public_key: ThresholdPublicKey,
+        #[serde(default)]
+        keygen: Option<SeededKeygen>,
+    },
+    KeyOnly(ThresholdPublicKey),
+}
+
+impl From<PublicKeyFileContent> for ThresholdPublicKeyFile {
+    fn from(content: PublicKeyFileContent) -> Self {
+        match content {
+            PublicKeyFileContent::WithKeygen { public_key, keygen } => Self { public_key, keygen },
+            PublicKeyFileContent::KeyOnly(public_key) => Self {
+                public_key,
+                keygen: None,
+            },
+        }
+    }
+}
+
+impl Import for ThresholdPublicKeyFile {}
+impl Export for ThresholdPublicKeyFile {}
+
 impl Import for ThresholdPublicKey {}
 impl Export for ThresholdPublicKey {}
@@ -0,0 +1,593 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use std::net::Ipv4Addr;
+
+fn committee() -> Committee {
+    let host = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    let authorities = (0..4).map(|_| (KeyPair::new().name, 1, host)).collect();
+    Committee::new(authorities, 13_000, 1).unwrap()
+}
+
+fn path(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!("{}_{}.json", name, std::process::id()));
+    let _ = fs::remove_file(&path);
+    path.to_str().unwrap().to_string()
+}
+
+#[test]
+fn signed_committee_round_trip() {
+    let governance = KeyPair::new();
+    let committee = committee();
+    let file = path("signed_committee");
+    committee.export_signed(&file, &governance.secret).unwrap();
+
+    let imported = Committee::import_signed(&file, &governance.name).unwrap();
+    assert_eq!(imported.authorities.len(), committee.authorities.len());
+}
+
+#[test]
+fn tampered_committee_is_rejected() {
+    let governance = KeyPair::new();
+    let file = path("tampered_committee");
+    committee()
+        .export_signed(&file, &governance.secret)
+        .unwrap();
+
+    // Redirect an authority to another port.
+    let data = fs::read_to_string(&file).unwrap().replace("13000", "13999");
+    fs::write(&file, data).unwrap();
+    match Committee::import_signed(&file, &governance.name) {
+        Err(ConfigError::InvalidSignature { .. }) => (),
+        x => panic!("Unexpected result: {:?}", x.map(|_| ())),
+    }
+}
+
+#[test]
+fn committee_signed_by_another_key_is_rejected() {
+    let file = path("unknown_signer_committee");
+    committee()
+        .export_signed(&file, &KeyPair::new().secret)
+        .unwrap();
+    match Committee::import_signed(&file, &KeyPair::new().name) {
+        Err(ConfigError::InvalidSignature { .. }) => (),
+        x => panic!("Unexpected result: {:?}", x.map(|_| ())),
+    }
+}
+
+#[test]
+fn channel_capacity_defaults_to_1000() {
+    let data = r#"{
+        "header_size": 1000,
+        "max_header_delay": 100,
+        "gc_depth": 50,
+        "sync_retry_delay": 5000,
+        "sync_retry_nodes": 3,
+        "batch_size": 500000,
+        "max_batch_delay": 100
+    }"#;
+    let parameters = Parameters::import_from_str(data).unwrap();
+    assert_eq!(parameters.channel_capacity, 1_000);
+    assert_eq!(Parameters::default().channel_capacity, 1_000);
+
+    let data = data.replace("\"gc_depth\"", "\"channel_capacity\": 10, \"gc_depth\"");
+    let parameters = Parameters::import_from_str(&data).unwrap();
+    assert_eq!(parameters.channel_capacity, 10);
+}
+
+fn committee_with_stakes(stakes: &[Stake]) -> Committee {
+    let host = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    let authorities = stakes
+        .iter()
+        .map(|stake| (KeyPair::new().name, *stake, host))
+        .collect();
+    Committee::new(authorities, 13_000, 1).unwrap()
+}
+
+#[test]
+fn thresholds() {
+    // (stakes, quorum threshold, validity threshold)
+    let cases: Vec<(Vec<Stake>, Stake, Stake)> = vec![
+        (vec![1], 1, 1),
+        (vec![1, 1, 1], 3, 1),
+        (vec![1, 1, 1, 1], 3, 2),
+        (vec![1, 1, 1, 1, 1], 4, 2),
+        (vec![1; 7], 5, 3),
+        (vec![1, 2, 3, 4], 7, 4),
+        (vec![10, 10, 10, 1], 21, 11),
+    ];
+    for (stakes, quorum, validity) in cases {
+        let committee = committee_with_stakes(&stakes);
+        assert_eq!(committee.quorum_threshold(), quorum, "stakes {:?}", stakes);
+        assert_eq!(
+            committee.validity_threshold(),
+            validity,
+            "stakes {:?}",
+            stakes
+        );
+
+        // Any f+1 stake intersects any quorum in at least one honest authority, and two quorums
+        // intersect in more than f.
+        let total = committee.total_stake();
+        assert!(2 * quorum > total + (total - quorum));
+        assert!(validity + quorum > total);
+    }
+}
+
+#[test]
+fn dominant_authority() {
+    assert!(committee_with_stakes(&[1, 1, 1, 1])
+        .dominant_authority()
+        .is_none());
+    assert!(committee_with_stakes(&[2, 1, 1, 1])
+        .dominant_authority()
+        .is_none());
+
+    let committee = committee_with_stakes(&[10, 1, 1, 1]);
+    let (name, _) = committee
+        .authorities
+        .iter()
+        .find(|(_, x)| x.stake == 10)
+        .unwrap();
+    assert_eq!(committee.dominant_authority(), Some(*name));
+
+    // A centralized committee is still imported (with a warning).
+    let data = serde_json::to_string(&committee).unwrap();
+    assert!(Committee::import_from_str(&data).is_ok());
+}
+
+#[test]
+fn overflowing_stake_is_rejected() {
+    let committee = committee_with_stakes(&[Stake::MAX / 2, 1, 1, 1]);
+    let data = serde_json::to_string(&committee).unwrap();
+    match Committee::import_from_str(&data) {
+        Err(ConfigError::InvalidCommittee(_)) => (),
+        x => panic!("Unexpected result: {:?}", x.map(|_| ())),
+    }
+    assert!(committee_with_stakes(&[Stake::MAX / 4, 1, 1, 1])
+        .check_stake()
+        .is_ok());
+}
+
+#[test]
+fn signing_keys_during_rotation() {
+    let mut committee = committee_with_stakes(&[1, 1, 1, 1]);
+    let name = *committee.authorities.keys().next().unwrap();
+    assert_eq!(committee.signing_keys(&name, 0), vec![name]);
+    assert!(committee.signing_keys(&KeyPair::new().name, 0).is_empty());
+
+    // The old key is accepted until the end of the overlap, the new one from its start.
+    let mut keypair = KeyPair::new();
+    let rotation = keypair.rotate(/* from_epoch */ 2, /* overlap_until */ 4);
+    assert_eq!(keypair.next.as_ref().unwrap().key, rotation.key);
+    committee.authorities.get_mut(&name).unwrap().key_rotation = Some(rotation.clone());
+    assert!(committee.sanity_check().is_ok());
+    assert_eq!(committee.signing_keys(&name, 1), vec![name]);
+    assert_eq!(committee.signing_keys(&name, 3), vec![name, rotation.key]);
+    assert_eq!(committee.signing_keys(&name, 4), vec![rotation.key]);
+
+    // A rotation cannot end before it starts.
+    committee.authorities.get_mut(&name).unwrap().key_rotation = Some(KeyRotation {
+        overlap_until: 1,
+        ..rotation
+    });
+    assert!(committee.sanity_check().is_err());
+}
+
+#[test]
+fn worker_ports_must_be_distinct() {
+    let mut committee = committee();
+    assert!(committee.check_worker_ports().is_ok());
+
+    // The transactions of a worker cannot be received on its worker-to-worker port (even on another host).
+    let authority = committee.authorities.values_mut().next().unwrap();
+    let worker = authority.workers.values_mut().next().unwrap();
+    worker.transactions = SocketAddr::new(
+        IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        worker.worker_to_worker.port(),
+    );
+    let data = serde_json::to_string(&committee).unwrap();
+    match Committee::import_from_str(&data) {
+        Err(ConfigError::InvalidCommittee(_)) => (),
+        x => panic!("Unexpected result: {:?}", x.map(|_| ())),
+    }
+    assert!(committee.sanity_check().is_err());
+}
+
+#[test]
+fn reweight_committee() {
+    let mut committee = committee_with_stakes(&[1, 1, 1, 1]);
+    let names: Vec<_> = committee.authorities.keys().cloned().collect();
+    committee.reweight(&[(names[0], 3)], false).unwrap();
+    assert_eq!(committee.stake(&names[0]), 3);
+    assert_eq!(committee.stake(&names[1]), 1);
+    assert_eq!(committee.total_stake(), 6);
+
+    // An authority cannot hold a quorum on its own, unless forced.
+    assert!(committee.reweight(&[(names[1], 10)], false).is_err());
+    assert_eq!(committee.stake(&names[1]), 1);
+    committee.reweight(&[(names[1], 10)], true).unwrap();
+    assert_eq!(committee.dominant_authority(), Some(names[1]));
+
+    // The authorities must be in the committee.
+    assert!(matches!(
+        committee.reweight(&[(KeyPair::new().name, 1)], false),
+        Err(ConfigError::NotInCommittee(_))
+    ));
+}
+
+#[test]
+fn gc_depth_boundary() {
+    // The defaults leave just enough rounds for a sync retry.
+    let mut parameters = Parameters::default();
+    assert_eq!(parameters.min_gc_depth(), 50);
+    assert!(parameters.check_gc_depth().is_ok());
+
+    parameters.gc_depth = 49;
+    assert!(matches!(
+        parameters.check_gc_depth(),
+        Err(ConfigError::GcDepthTooSmall {
+            gc_depth: 49,
+            minimum: 50
+        })
+    ));
+
+    // Slower rounds (or faster retries) allow a smaller depth, down to the rounds spanned by a commit.
+    parameters.max_header_delay = 1_000;
+    assert_eq!(parameters.min_gc_depth(), 5);
+    parameters.gc_depth = 5;
+    assert!(parameters.check_gc_depth().is_ok());
+    parameters.sync_retry_delay = 1;
+    assert_eq!(parameters.min_gc_depth(), 3);
+
+    // Huge retry delays do not overflow.
+    parameters.max_header_delay = 0;
+    parameters.sync_retry_delay = u64::MAX;
+    assert_eq!(parameters.min_gc_depth(), u64::MAX);
+    parameters.max_header_delay = 2;
+    assert_eq!(parameters.min_gc_depth(), u64::MAX / 2 + 1);
+
+    // Reloading slower retries makes the depth too small again.
+    let new = Parameters {
+        sync_retry_delay: 5_000,
+        max_header_delay: 1_000,
+        ..parameters.clone()
+    };
+    parameters.reload(&new);
+    assert!(parameters.check_gc_depth().is_ok());
+    let new = Parameters {
+        sync_retry_delay: 100_000,
+        ..new
+    };
+    parameters.reload(&new);
+    assert!(matches!(
+        parameters.check_gc_depth(),
+        Err(ConfigError::GcDepthTooSmall {
+            gc_depth: 5,
+            minimum: 100
+        })
+    ));
+}
+
+#[test]
+fn committee_of_another_hash_function_is_rejected() {
+    let mut committee = committee();
+    assert_eq!(committee.hash_function, HashKind::selected());
+    assert!(committee.check_hash_function().is_ok());
+
+    // A committee written by nodes built with the other hash function.
+    let other = match HashKind::selected() {
+        HashKind::Sha512 => HashKind::Blake3,
+        HashKind::Blake3 => HashKind::Sha512,
+    };
+    committee.hash_function = other;
+    let file = path("other_hash_committee");
+    committee.export(&file).unwrap();
+    let imported = Committee::import(&file).unwrap();
+    assert_eq!(imported.hash_function, other);
+    assert!(matches!(
+        imported.sanity_check(),
+        Err(ConfigError::HashMismatch { committee, node }) if committee == other && node == HashKind::selected()
+    ));
+}
+
+#[test]
+fn committee_of_other_durable_acks_is_rejected() {
+    let mut committee = committee();
+    assert!(committee.check_durable_acks(true).is_ok());
+    assert!(committee.check_durable_acks(false).is_ok());
+
+    committee.durable_acks = Some(true);
+    let file = path("durable_acks_committee");
+    committee.export(&file).unwrap();
+    let imported = Committee::import(&file).unwrap();
+    assert_eq!(imported.durable_acks, Some(true));
+    assert!(imported.check_durable_acks(true).is_ok());
+    assert!(matches!(
+        imported.check_durable_acks(false),
+        Err(ConfigError::DurableAcksMismatch {
+            committee: true,
+            node: false
+        })
+    ));
+}
+
+#[test]
+fn parse_module_log_filters() {
+    let filters = parse_log_filters(&Parameters::default().log_filters).unwrap();
+    assert_eq!(
+        filters,
+        vec![
+            ("network".to_string(), LevelFilter::Warn),
+            ("consensus".to_string(), LevelFilter::Info)
+        ]
+    );
+
+    let filters = parse_log_filters(" primary::core = DEBUG ,, worker=off").unwrap();
+    assert_eq!(
+        filters,
+        vec![
+            ("primary::core".to_string(), LevelFilter::Debug),
+            ("worker".to_string(), LevelFilter::Off)
+        ]
+    );
+    assert!(parse_log_filters("").unwrap().is_empty());
+
+    // A directive needs both a module and a valid level.
+    for filters in &["network", "=warn", "network=loud"] {
+        assert!(matches!(
+            parse_log_filters(filters),
+            Err(ConfigError::InvalidLogFilter(_))
+        ));
+    }
+}
+
+#[test]
+fn import_keypairs_from_str() {
+    // The keypairs parsed from memory are the ones parsed from their file.
+    let keypair = KeyPair::new();
+    let file = path("import_keypairs_from_str");
+    keypair.export(&file).unwrap();
+    let data = fs::read_to_string(&file).unwrap();
+    assert_eq!(KeyPair::import_from_str(&data).unwrap().name, keypair.name);
+    let _ = fs::remove_file(&file);
+
+    let threshold_keypair = ThresholdKeyPair::new(1, 2, 0);
+    let data = serde_json::to_string(&threshold_keypair).unwrap();
+    let imported = ThresholdKeyPair::import_from_str(&data).unwrap();
+    assert_eq!(imported.node_index, 2);
+    assert_eq!(imported.pk_set, threshold_keypair.pk_set);
+
+    // Invalid content is reported as such.
+    assert!(matches!(
+        KeyPair::import_from_str("{"),
+        Err(ConfigError::ParseError(_))
+    ));
+}
+
+#[test]
+fn toml_committee_round_trip() {
+    let committee = committee();
+    let file = path("toml_committee_round_trip").replace(".json", ".toml");
+    committee.export(&file).unwrap();
+
+    // The file is indeed TOML, and reads back to the same committee.
+    let data = fs::read_to_string(&file).unwrap();
+    assert!(serde_json::from_str::<serde_json::Value>(&data).is_err());
+    assert!(toml::from_str::<toml::Value>(&data).is_ok());
+    let imported = Committee::import(&file).unwrap();
+    assert_eq!(
+        serde_json::to_value(&imported).unwrap(),
+        serde_json::to_value(&committee).unwrap()
+    );
+    let _ = fs::remove_file(&file);
+}
+
+#[test]
+fn import_toml_parameters() {
+    let file = path("import_toml_parameters").replace(".json", ".toml");
+    let data = r#"
+        header_size = 1000
+        max_header_delay = 100
+        gc_depth = 50
+        sync_retry_delay = 5000
+        sync_retry_nodes = 3
+        batch_size = 500000
+        max_batch_delay = 100
+        channel_capacity = 10
+    "#;
+    fs::write(&file, data).unwrap();
+    let parameters = Parameters::import(&file).unwrap();
+    assert_eq!(parameters.gc_depth, 50);
+    assert_eq!(parameters.channel_capacity, 10);
+
+    // The same content is not valid JSON.
+    let json = file.replace(".toml", ".json");
+    fs::write(&json, data).unwrap();
+    assert!(Parameters::import(&json).is_err());
+    let _ = fs::remove_file(&file);
+    let _ = fs::remove_file(&json);
+}
+
+#[test]
+fn commit_latency_buckets_must_increase() {
+    let parameters = |buckets: &str| {
+        let data = format!(
+            r#"{{
+                "header_size": 1000,
+                "max_header_delay": 100,
+                "gc_depth": 50,
+                "sync_retry_delay": 5000,
+                "sync_retry_nodes": 3,
+                "batch_size": 500000,
+                "max_batch_delay": 100,
+                "commit_latency_buckets": {}
+            }}"#,
+            buckets
+        );
+        Parameters::import_from_str(&data)
+    };
+    assert!(parameters("[0.5, 1.0, 2.5]").is_ok());
+    for buckets in &["[1.0, 0.5]", "[0.5, 0.5]"] {
+        assert!(matches!(
+            parameters(buckets),
+            Err(ConfigError::InvalidLatencyBuckets(_))
+        ));
+    }
+}
+
+#[test]
+fn bind_address() {
+    // The listeners bind all interfaces by default, on the ports of the committee.
+    let mut parameters = Parameters::default();
+    let advertised = "203.0.113.7:4000".parse().unwrap();
+    assert_eq!(
+        parameters.listen_address(advertised),
+        "0.0.0.0:4000".parse::<SocketAddr>().unwrap()
+    );
+    assert!(parameters.check_bind_address().is_ok());
+
+    // A local address is bindable, while an address of another host is not.
+    let local = Parameters {
+        bind_address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+        ..Parameters::default()
+    };
+    assert_eq!(
+        local.listen_address(advertised),
+        "127.0.0.1:4000".parse::<SocketAddr>().unwrap()
+    );
+    assert!(local.check_bind_address().is_ok());
+    let remote = Parameters {
+        bind_address: "192.0.2.1".parse().unwrap(),
+        ..Parameters::default()
+    };
+    assert!(matches!(
+        remote.check_bind_address(),
+        Err(ConfigError::UnbindableAddress { .. })
+    ));
+
+    // The bind address is not live-reloadable.
+    assert_eq!(parameters.reload(&local), vec!["bind_address"]);
+    assert!(parameters.bind_address.is_unspecified());
+}
+
+#[test]
+fn check_threshold_key() {
+    let keypair = ThresholdKeyPair::new(1, 0, 0);
+    let mut committee = committee();
+
+    // Committee files without a threshold public key accept any keypair.
+    assert!(committee.check_threshold_key(&keypair.pk_set).is_ok());
+
+    committee.threshold_public_key = Some(keypair.pk_set.public_key());
+    assert!(committee.check_threshold_key(&keypair.pk_set).is_ok());
+
+    // The share of another key set (here from another seed) is rejected.
+    let other = ThresholdKeyPair::new(1, 0, 1);
+    assert!(matches!(
+        committee.check_threshold_key(&other.pk_set),
+        Err(ConfigError::ThresholdKeyMismatch)
+    ));
+}
+
+#[test]
+fn import_committee_without_threshold_key() {
+    let file = path("committee_without_threshold_key");
+    committee().export(&file).unwrap();
+    let data = fs::read_to_string(&file).unwrap();
+    assert!(!data.contains("threshold_public_key"));
+
+    let imported = Committee::import(&file).unwrap();
+    assert!(imported.threshold_public_key.is_none());
+    let _ = fs::remove_file(&file);
+}
+
+#[test]
+fn shard_thresholds() {
+    let mut committee = committee();
+    assert!(committee.check_threshold(3).is_ok());
+    assert!(matches!(
+        committee.check_threshold(4),
+        Err(ConfigError::IncompatibleThreshold {
+            threshold: 4,
+            size: 4
+        })
+    ));
+
+    // The threshold of a shard must leave enough authorities to hold its key shares.
+    let shard = ShardThreshold {
+        threshold: 2,
+        public_key: None,
+    };
+    committee.shard_thresholds.insert(0, shard.clone());
+    assert!(committee.sanity_check().is_ok());
+    assert_eq!(committee.shard_threshold(&0), Some(2));
+    committee.shard_thresholds.get_mut(&0).unwrap().threshold = 4;
+    assert!(matches!(
+        committee.sanity_check(),
+        Err(ConfigError::IncompatibleThreshold { .. })
+    ));
+
+    // Only the workers of the committee can have their own threshold.
+    committee.shard_thresholds.clear();
+    committee.shard_thresholds.insert(7, shard);
+    assert!(matches!(
+        committee.sanity_check(),
+        Err(ConfigError::InvalidCommittee(_))
+    ));
+}
+
+#[test]
+fn check_worker_threshold_key() {
+    let mut committee = committee();
+    let committee_keypair = ThresholdKeyPair::new(1, 0, 0);
+    let shard_keypair = ThresholdKeyPair::new(2, 0, 1);
+    committee.threshold_public_key = Some(committee_keypair.pk_set.public_key());
+    committee.shard_thresholds.insert(
+        0,
+        ShardThreshold {
+            threshold: 2,
+            public_key: Some(shard_keypair.pk_set.public_key()),
+        },
+    );
+
+    // Worker 0 uses the key set of its shard, the others the one of the committee.
+    assert!(committee
+        .check_worker_threshold_key(&0, &shard_keypair.pk_set)
+        .is_ok());
+    assert!(committee
+        .check_worker_threshold_key(&1, &committee_keypair.pk_set)
+        .is_ok());
+    assert!(matches!(
+        committee.check_worker_threshold_key(&1, &shard_keypair.pk_set),
+        Err(ConfigError::ThresholdKeyMismatch)
+    ));
+
+    // A key set of another threshold is rejected, even if the shard does not pin its public key.
+    assert!(matches!(
+        committee.check_worker_threshold_key(&0, &committee_keypair.pk_set),
+        Err(ConfigError::ThresholdMismatch {
+            worker: 0,
+            expected: 2,
+            found: 1
+        })
+    ));
+    committee.shard_thresholds.get_mut(&0).unwrap().public_key = None;
+    assert!(matches!(
+        committee.check_worker_threshold_key(&0, &committee_keypair.pk_set),
+        Err(ConfigError::ThresholdMismatch { .. })
+    ));
+
+    // Without its own public key, the shard falls back to the one of the committee.
+    let other = ThresholdKeyPair::new(2, 0, 2);
+    assert!(matches!(
+        committee.check_worker_threshold_key(&0, &other.pk_set),
+        Err(ConfigError::ThresholdKeyMismatch)
+    ));
+
+    // The committee cannot hold the shares of a key set of too high a threshold.
+    let oversized = ThresholdKeyPair::new(4, 0, 3);
+    assert!(matches!(
+        committee.check_worker_threshold_key(&1, &oversized.pk_set),
+        Err(ConfigError::IncompatibleThreshold { .. })
+    ));
+}
@@ -0,0 +1,130 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::{ConfigError, ThresholdPublicKeyFile};
+
+#[test]
+fn round_trip() {
+    // A mock committee of 4 nodes, any 2 of which can decrypt.
+    let keypairs: Vec<_> = (0..4).map(|i| ThresholdKeyPair::new(1, i, 0)).collect();
+    let pk_set = keypairs[0].pk_set.clone();
+
+    let plaintext = b"Hello, mempool!".to_vec();
+    let ciphertext = encrypt(&pk_set.public_key(), &plaintext);
+
+    let mut shares = BTreeMap::new();
+    for keypair in &keypairs {
+        let share = decryption_share(keypair, &ciphertext).unwrap();
+        shares.insert(keypair.node_index, share);
+    }
+
+    // Any two shares suffice.
+    let two: BTreeMap<_, _> = shares
+        .iter()
+        .filter(|(index, _)| **index == 1 || **index == 3)
+        .map(|(index, share)| (*index, share.clone()))
+        .collect();
+    assert_eq!(decrypt_shares(&pk_set, &ciphertext, &two), Ok(plaintext));
+
+    // But a single one does not.
+    let one: BTreeMap<_, _> = two.into_iter().take(1).collect();
+    assert_eq!(
+        decrypt_shares(&pk_set, &ciphertext, &one),
+        Err(ThresholdError::NotEnoughShares {
+            valid: 1,
+            required: 2
+        })
+    );
+}
+
+#[test]
+fn invalid_shares_are_ignored() {
+    let keypairs: Vec<_> = (0..4).map(|i| ThresholdKeyPair::new(1, i, 0)).collect();
+    let pk_set = keypairs[0].pk_set.clone();
+    let ciphertext = encrypt(&pk_set.public_key(), b"secret");
+
+    // Node 1 hands in the share it computed for another transaction.
+    let other = encrypt(&pk_set.public_key(), b"other");
+    let mut shares = BTreeMap::new();
+    shares.insert(0, decryption_share(&keypairs[0], &ciphertext).unwrap());
+    shares.insert(1, decryption_share(&keypairs[1], &other).unwrap());
+    assert_eq!(
+        decrypt_shares(&pk_set, &ciphertext, &shares),
+        Err(ThresholdError::NotEnoughShares {
+            valid: 1,
+            required: 2
+        })
+    );
+}
+
+#[test]
+fn malformed_ciphertext() {
+    let keypair = ThresholdKeyPair::new(1, 0, 0);
+    assert_eq!(
+        decryption_share(&keypair, &[0; 10]),
+        Err(ThresholdError::InvalidCiphertext)
+    );
+}
+
+#[test]
+fn verify_threshold_keypair() {
+    let keypair = ThresholdKeyPair::new(1, 2, 0);
+    let public_key = keypair.pk_set.public_key();
+    assert!(keypair.verify(&public_key).is_ok());
+
+    // The key set of another seed.
+    let other = ThresholdKeyPair::new(1, 2, 1);
+    assert!(matches!(
+        other.verify(&public_key),
+        Err(ConfigError::ThresholdKeyMismatch)
+    ));
+
+    // A share relabelled with the index of another node.
+    let relabelled = ThresholdKeyPair {
+        node_index: 3,
+        ..keypair
+    };
+    assert!(matches!(
+        relabelled.verify(&public_key),
+        Err(ConfigError::ShareMismatch(3))
+    ));
+}
+
+#[test]
+fn verify_seed_fingerprint() {
+    let public_key_file = |keypair: &ThresholdKeyPair| ThresholdPublicKeyFile {
+        public_key: keypair.pk_set.public_key(),
+        keygen: keypair.keygen.clone(),
+    };
+    let keypair = ThresholdKeyPair::new(1, 2, 0).with_num_shares(4);
+    assert!(keypair.verify_file(&public_key_file(&keypair)).is_ok());
+
+    // The public key of another seed.
+    let other = ThresholdKeyPair::new(1, 0, 1).with_num_shares(4);
+    assert!(matches!(
+        keypair.verify_file(&public_key_file(&other)),
+        Err(ConfigError::SeedMismatch)
+    ));
+
+    // The same seed with other parameters.
+    let other = ThresholdKeyPair::new(2, 0, 0).with_num_shares(4);
+    assert!(matches!(
+        keypair.verify_file(&public_key_file(&other)),
+        Err(ConfigError::KeygenMismatch { .. })
+    ));
+    let other = ThresholdKeyPair::new(1, 0, 0).with_num_shares(7);
+    assert!(matches!(
+        keypair.verify_file(&public_key_file(&other)),
+        Err(ConfigError::KeygenMismatch { .. })
+    ));
+
+    // The files holding only the public key are still read (and only the key is checked).
+    let data = serde_json::to_string(&keypair.pk_set.public_key()).unwrap();
+    let legacy: ThresholdPublicKeyFile = serde_json::from_str(&data).unwrap();
+    assert!(legacy.keygen.is_none());
+    assert!(keypair.verify_file(&legacy).is_ok());
+
+    // The fingerprint survives the round trip through the file.
+    let data = serde_json::to_string(&public_key_file(&keypair)).unwrap();
+    let file: ThresholdPublicKeyFile = serde_json::from_str(&data).unwrap();
+    assert_eq!(file.keygen, keypair.keygen);
+}
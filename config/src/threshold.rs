@@ -0,0 +1,76 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::ThresholdKeyPair;
+use crypto::threshold::{Ciphertext, DecryptionShare, PublicKey, PublicKeySet};
+use std::collections::BTreeMap;
+use thiserror::Error;
+
+#[cfg(test)]
+#[path = "tests/threshold_tests.rs"]
+pub mod threshold_tests;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum ThresholdError {
+    #[error("The transaction is not a valid ciphertext")]
+    InvalidCiphertext,
+
+    #[error("Got {valid} valid decryption shares ({required} required)")]
+    NotEnoughShares { valid: usize, required: usize },
+
+    #[error("Failed to combine the decryption shares")]
+    CombineFailed,
+}
+
+/// Encrypts a transaction with the threshold public key of the committee (`pk_set.public_key()`). The returned
+/// bytes are what clients submit to the workers: they stay hidden until the batch holding them is committed.
+pub fn encrypt(public_key: &PublicKey, plaintext: &[u8]) -> Vec<u8> {
+    bincode::serialize(&public_key.encrypt(plaintext)).expect("Failed to serialize ciphertext")
+}
+
+/// Computes the decryption share of the holder of `keypair` for a transaction produced by `encrypt`.
+pub fn decryption_share(
+    keypair: &ThresholdKeyPair,
+    ciphertext: &[u8],
+) -> Result<DecryptionShare, ThresholdError> {
+    let ciphertext = deserialize(ciphertext)?;
+    keypair
+        .sk_share
+        .decrypt_share(&ciphertext)
+        .ok_or(ThresholdError::InvalidCiphertext)
+}
+
+/// Recovers the plaintext of a transaction produced by `encrypt` from the decryption shares of the nodes,
+/// indexed by node index. Invalid shares are ignored; decryption requires `threshold + 1` valid ones.
+pub fn decrypt_shares(
+    pk_set: &PublicKeySet,
+    ciphertext: &[u8],
+    shares: &BTreeMap<usize, DecryptionShare>,
+) -> Result<Vec<u8>, ThresholdError> {
+    let ciphertext = deserialize(ciphertext)?;
+    let valid: BTreeMap<usize, &DecryptionShare> = shares
+        .iter()
+        .map(|(index, share)| (*index, share))
+        .filter(|(index, share)| {
+            pk_set
+                .public_key_share(*index)
+                .verify_decryption_share(share, &ciphertext)
+        })
+        .collect();
+
+    let required = pk_set.threshold() + 1;
+    if valid.len() < required {
+        return Err(ThresholdError::NotEnoughShares {
+            valid: valid.len(),
+            required,
+        });
+    }
+    pk_set
+        .decrypt(valid, &ciphertext)
+        .map_err(|_| ThresholdError::CombineFailed)
+}
+
+fn deserialize(ciphertext: &[u8]) -> Result<Ciphertext, ThresholdError> {
+    bincode::deserialize(ciphertext)
+        .ok()
+        .filter(|ciphertext: &Ciphertext| ciphertext.verify())
+        .ok_or(ThresholdError::InvalidCiphertext)
+}
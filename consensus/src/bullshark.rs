@@ -0,0 +1,55 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::state::{round_robin, State};
+use crate::{CommitRule, CommittedSubDag};
+use config::Committee;
+use log::debug;
+use primary::Certificate;
+
+/// The Bullshark commit rule (the partially synchronous version): the leader of every even round is known
+/// in advance, and it is committed as soon as f+1 certificates of the next round reference it. No coin is
+/// needed, so each leader commits one round after it is proposed (two rounds earlier than with Tusk).
+pub struct Bullshark {
+    /// The committee information.
+    committee: Committee,
+}
+
+impl Bullshark {
+    pub fn new(committee: Committee) -> Self {
+        Self { committee }
+    }
+}
+
+impl CommitRule for Bullshark {
+    fn try_commit(&mut self, certificate: &Certificate, state: &mut State) -> Vec<CommittedSubDag> {
+        // The certificates of round r vote for the leader of round r-1.
+        let round = certificate.round();
+        let leader_round = round - 1;
+
+        // We only elect leaders for even round numbers.
+        if leader_round % 2 != 0 || leader_round < 2 {
+            return Vec::new();
+        }
+
+        // If we already ordered this leader, there is nothing to do.
+        if leader_round <= state.last_committed_round {
+            return Vec::new();
+        }
+        let (leader_digest, leader) =
+            match state.certificate(&round_robin(&self.committee, leader_round), leader_round) {
+                Some(x) => x,
+                None => return Vec::new(),
+            };
+
+        // Commit the leader (and the preceding leaders linked to it) once it has f+1 votes. Every new
+        // certificate of round r may bring the vote that completes the quorum.
+        let stake = state.support(leader_digest, round, &self.committee);
+        if stake < self.committee.validity_threshold() {
+            debug!("Leader {:?} does not have enough support yet", leader);
+            return Vec::new();
+        }
+        debug!("Leader {:?} has enough support", leader);
+        let leader = leader.clone();
+        let committee = &self.committee;
+        state.commit(&leader, |round| round_robin(committee, round))
+    }
+}
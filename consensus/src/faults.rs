@@ -0,0 +1,347 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::simulation::{NodeId, SimRng, Tick};
+use bytes::Bytes;
+use config::Committee;
+use crypto::{Hash as _, SecretKey, Signature};
+use network::memory::{Link, Verdict};
+use primary::{PrimaryMessage, Round};
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::ops::Range;
+use std::time::Duration;
+use worker::{decompress, WorkerMessage};
+
+#[cfg(test)]
+#[path = "tests/faults_tests.rs"]
+pub mod faults_tests;
+
+/// The kinds of messages exchanged by the nodes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MessageKind {
+    /// A primary broadcasting one of its headers.
+    Header,
+    /// A primary voting for a header.
+    Vote,
+    /// A primary broadcasting one of its certificates.
+    Certificate,
+    /// A primary asking for certificates it misses (or follows, to catch up).
+    SyncRequest,
+    /// The certificates sent back to a primary catching up.
+    SyncReply,
+    /// A worker broadcasting one of its batches.
+    Batch,
+    /// The other messages of the workers, and the messages between a primary and its workers.
+    Other,
+}
+
+/// What happens to the messages matching a `Fault`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FaultKind {
+    /// The message is lost along with its connection: the reliable senders re-transmit it on a new one,
+    /// while the others lose it for good.
+    Drop,
+    /// The message is delivered twice (the copy on another connection).
+    Duplicate,
+    /// The message is delivered this many ticks later, and so are the messages sent after it on the same
+    /// connection.
+    Delay(Tick),
+    /// The message is tampered with in transit (its last byte is flipped), so that its receiver rejects it.
+    Corrupt,
+}
+
+/// Alters the messages matching a filter. By default, a fault alters all the messages between any two
+/// (distinct) nodes.
+#[derive(Clone, Debug)]
+pub struct Fault {
+    pub kind: FaultKind,
+    /// Only alters the messages sent by this node.
+    pub from: Option<NodeId>,
+    /// Only alters the messages sent to this node.
+    pub to: Option<NodeId>,
+    /// Only alters the messages of this kind.
+    pub message: Option<MessageKind>,
+    /// Only alters the messages carrying a header, vote or certificate of these rounds (the others carry
+    /// none, so they are only altered if the range holds all rounds).
+    pub rounds: Range<Round>,
+    /// The percentage of the matching messages that are altered (picked by the seeded generator).
+    pub percent: u64,
+}
+
+impl Fault {
+    pub fn new(kind: FaultKind) -> Self {
+        Self {
+            kind,
+            from: None,
+            to: None,
+            message: None,
+            rounds: 0..Round::MAX,
+            percent: 100,
+        }
+    }
+
+    pub fn from(mut self, node: NodeId) -> Self {
+        self.from = Some(node);
+        self
+    }
+
+    pub fn to(mut self, node: NodeId) -> Self {
+        self.to = Some(node);
+        self
+    }
+
+    pub fn messages(mut self, kind: MessageKind) -> Self {
+        self.message = Some(kind);
+        self
+    }
+
+    pub fn rounds(mut self, rounds: Range<Round>) -> Self {
+        self.rounds = rounds;
+        self
+    }
+
+    pub fn percent(mut self, percent: u64) -> Self {
+        self.percent = percent;
+        self
+    }
+
+    /// Whether the fault applies to a message of `kind` (carrying a header, vote or certificate of `round`,
+    /// if any) sent by `from` to `to`.
+    pub fn matches(
+        &self,
+        from: NodeId,
+        to: NodeId,
+        kind: MessageKind,
+        round: Option<Round>,
+    ) -> bool {
+        let round = match round {
+            Some(round) => self.rounds.contains(&round),
+            None => self.rounds == (0..Round::MAX),
+        };
+        self.from.map_or(true, |x| x == from)
+            && self.to.map_or(true, |x| x == to)
+            && self.message.map_or(true, |x| x == kind)
+            && round
+    }
+}
+
+/// The events of a `Scenario`.
+#[derive(Clone, Debug)]
+pub enum Event {
+    /// Starts altering messages.
+    Inject(Fault),
+    /// Stops altering messages (the messages already altered stay altered).
+    ClearFaults,
+    /// Cuts a group of nodes from the others for some ticks.
+    Partition(Vec<NodeId>, Tick),
+    /// Crashes a node, as far as the others can tell: it is cut from them for good.
+    Crash(NodeId),
+    /// Makes a node Byzantine: for every round, it sends a different header to the odd nodes than to the
+    /// even ones.
+    Equivocate(NodeId),
+}
+
+/// A script of events, each applied to the simulation at a given time.
+#[derive(Clone, Debug, Default)]
+pub struct Scenario {
+    events: Vec<(Tick, Event)>,
+}
+
+impl Scenario {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn at(mut self, time: Tick, event: Event) -> Self {
+        self.events.push((time, event));
+        self
+    }
+
+    /// The events, in the order in which they apply.
+    pub fn into_events(mut self) -> Vec<(Tick, Event)> {
+        // The sort is stable: events of the same time apply in the order of the script.
+        self.events.sort_by_key(|(time, _)| *time);
+        self.events
+    }
+}
+
+/// Alters the messages of the in-memory network as the faults and the misbehaving nodes dictate. The
+/// messages are decoded (to filter them by kind and round) from the address they are sent to; the replies
+/// travel unaltered.
+pub struct Faults {
+    /// The addresses on which the primaries receive the messages of the other primaries.
+    primaries: HashSet<SocketAddr>,
+    /// The addresses on which the workers receive the messages of the other workers.
+    workers: HashSet<SocketAddr>,
+    /// The secret keys of the nodes, indexed by `NodeId` (the Byzantine nodes sign their equivocations).
+    secrets: Vec<SecretKey>,
+    /// The faults altering the messages.
+    faults: Vec<Fault>,
+    /// The nodes cut from the others for good.
+    crashed: HashSet<NodeId>,
+    /// The nodes equivocating.
+    byzantine: HashSet<NodeId>,
+    /// The number of messages altered so far.
+    altered: usize,
+}
+
+impl Faults {
+    pub fn new(committee: &Committee, secrets: Vec<SecretKey>) -> Self {
+        let primaries = committee
+            .authorities
+            .values()
+            .map(|x| x.primary.primary_to_primary)
+            .collect();
+        let workers = committee
+            .authorities
+            .values()
+            .flat_map(|x| x.workers.values().map(|x| x.worker_to_worker))
+            .collect();
+        Self {
+            primaries,
+            workers,
+            secrets,
+            faults: Vec::new(),
+            crashed: HashSet::new(),
+            byzantine: HashSet::new(),
+            altered: 0,
+        }
+    }
+
+    /// Alters the messages matching `fault` from now on.
+    pub fn inject(&mut self, fault: Fault) {
+        self.faults.push(fault);
+    }
+
+    /// Stops altering messages.
+    pub fn clear(&mut self) {
+        self.faults.clear();
+    }
+
+    /// Cuts a node from the others for good.
+    pub fn crash(&mut self, node: NodeId) {
+        self.crashed.insert(node);
+    }
+
+    /// Makes a node equivocate from now on.
+    pub fn equivocate(&mut self, node: NodeId) {
+        self.byzantine.insert(node);
+    }
+
+    /// Whether a node crashed or equivocates.
+    pub fn faulty(&self, node: NodeId) -> bool {
+        self.crashed.contains(&node) || self.byzantine.contains(&node)
+    }
+
+    /// The number of messages altered so far.
+    pub fn altered(&self) -> usize {
+        self.altered
+    }
+
+    /// Whether a crash cuts the link.
+    pub fn cut(&self, link: &Link) -> bool {
+        link.from
+            .iter()
+            .chain(link.to.iter())
+            .any(|x| self.crashed.contains(x))
+    }
+
+    /// The kind of a message sent to `address`, and the round of the header, vote or certificate it
+    /// carries (if any).
+    pub fn classify(&self, address: SocketAddr, message: &[u8]) -> (MessageKind, Option<Round>) {
+        if self.primaries.contains(&address) {
+            return match bincode::deserialize::<PrimaryMessage>(message) {
+                Ok(PrimaryMessage::Header(x)) => (MessageKind::Header, Some(x.round)),
+                Ok(PrimaryMessage::Vote(x)) => (MessageKind::Vote, Some(x.round)),
+                Ok(PrimaryMessage::Certificate(x)) => (MessageKind::Certificate, Some(x.round())),
+                Ok(PrimaryMessage::StateSyncReply(..)) => (MessageKind::SyncReply, None),
+                Ok(_) => (MessageKind::SyncRequest, None),
+                Err(_) => (MessageKind::Other, None),
+            };
+        }
+        if self.workers.contains(&address) {
+            // Batches may be compressed.
+            let message = decompress(message.to_vec())
+                .ok()
+                .and_then(|x| bincode::deserialize::<WorkerMessage>(&x).ok());
+            if matches!(
+                message,
+                Some(WorkerMessage::Batch(..)) | Some(WorkerMessage::DecryptableBatch(..))
+            ) {
+                return (MessageKind::Batch, None);
+            }
+        }
+        (MessageKind::Other, None)
+    }
+
+    /// Alters a message sent over `link`, which usually takes `delay` to go through: the matching faults
+    /// apply (each with its probability), after the equivocations of the Byzantine sender (if any).
+    pub fn alter(
+        &mut self,
+        link: &Link,
+        mut message: Bytes,
+        mut delay: Duration,
+        rng: &mut SimRng,
+    ) -> Verdict {
+        let (from, to) = match (link.from, link.to) {
+            (Some(from), Some(to)) if from != to => (from, to),
+            _ => return Verdict::Deliver(delay, message),
+        };
+        let (kind, round) = self.classify(link.address, &message);
+        if kind == MessageKind::Header && self.byzantine.contains(&from) && to % 2 == 1 {
+            if let Some(equivocation) = equivocate(&message, &self.secrets[from]) {
+                message = equivocation;
+                self.altered += 1;
+            }
+        }
+
+        let mut duplicate = false;
+        let faults: Vec<_> = self
+            .faults
+            .iter()
+            .filter(|x| x.matches(from, to, kind, round))
+            .map(|x| (x.kind, x.percent))
+            .collect();
+        for (fault, percent) in faults {
+            if rng.range(1, 100) > percent {
+                continue;
+            }
+            self.altered += 1;
+            match fault {
+                FaultKind::Drop => return Verdict::Reset,
+                FaultKind::Duplicate => duplicate = true,
+                FaultKind::Delay(extra) => delay += Duration::from_millis(extra),
+                FaultKind::Corrupt => message = corrupt(&message),
+            }
+        }
+        if duplicate {
+            Verdict::Duplicate(delay, message)
+        } else {
+            Verdict::Deliver(delay, message)
+        }
+    }
+}
+
+/// Rewrites a header into a conflicting one (same author and round, but another timestamp hence another
+/// digest), signed by `secret` as the author would. Returns `None` if the message is not a header.
+fn equivocate(message: &[u8], secret: &SecretKey) -> Option<Bytes> {
+    match bincode::deserialize::<PrimaryMessage>(message) {
+        Ok(PrimaryMessage::Header(mut header)) => {
+            header.timestamp += 1;
+            header.id = header.digest();
+            header.signature = Signature::new(&header.id, secret);
+            let bytes = bincode::serialize(&PrimaryMessage::Header(header))
+                .expect("Failed to serialize our own header");
+            Some(Bytes::from(bytes))
+        }
+        _ => None,
+    }
+}
+
+/// Flips the last byte of a message.
+fn corrupt(message: &[u8]) -> Bytes {
+    let mut message = message.to_vec();
+    if let Some(last) = message.last_mut() {
+        *last ^= 0xff;
+    }
+    Bytes::from(message)
+}
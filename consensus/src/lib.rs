@@ -1,70 +1,246 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
-use config::{Committee, Stake};
-use crypto::Hash as _;
-use crypto::{Digest, PublicKey};
-use log::{debug, info, log_enabled, warn};
-use primary::{Certificate, Round};
-use std::cmp::max;
-use std::collections::{HashMap, HashSet};
+use config::{Committee, ConsensusProtocol};
+use crypto::{Digest, Hash as _};
+use log::{debug, info, warn};
+use primary::{Certificate, Round, RoundIndex};
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto as _;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use store::{KeyValueStore, StoreResult};
+use tokio::sync::mpsc::error::TrySendError;
 use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::task::JoinHandle;
+use tracing::info_span;
+
+mod bullshark;
+#[cfg(any(test, feature = "simulation"))]
+pub mod faults;
+mod metrics;
+mod reputation;
+#[cfg(any(test, feature = "simulation"))]
+pub mod simulation;
+mod state;
+mod tusk;
+
+pub use crate::bullshark::Bullshark;
+pub use crate::metrics::ConsensusMetrics;
+pub use crate::reputation::{read_leader_scores, LeaderScore, LeaderScores, LEADER_SCORES_KEY};
+pub use crate::state::{round_robin, Dag, State, Watermark};
+pub use crate::tusk::Tusk;
 
 #[cfg(test)]
 #[path = "tests/consensus_tests.rs"]
 pub mod consensus_tests;
 
-/// The representation of the DAG in memory.
-type Dag = HashMap<Round, HashMap<PublicKey, (Digest, Certificate)>>;
-
-/// The state that needs to be persisted for crash-recovery.
-struct State {
-    /// The last committed round.
-    last_committed_round: Round,
-    // Keeps the last committed round for each authority. This map is used to clean up the dag and
-    // ensure we don't commit twice the same certificate.
-    last_committed: HashMap<PublicKey, Round>,
-    /// Keeps the latest committed certificate (and its parents) for every authority. Anything older
-    /// must be regularly cleaned up through the function `update`.
-    dag: Dag,
+/// The certificates committed by a leader: the leader and the part of its causal history that no previous
+/// leader committed. The sub-dags have consecutive indices starting from 0, and the flat certificate stream
+/// of the consensus is the concatenation of their certificates in that order.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CommittedSubDag {
+    /// The leader committing the sub-dag.
+    pub leader: Certificate,
+    /// The committed certificates, in commit order (the leader is the last one).
+    pub certificates: Vec<Certificate>,
+    /// The position of the sub-dag in the total order of committed sub-dags.
+    pub sub_dag_index: u64,
 }
 
-impl State {
-    fn new(genesis: Vec<Certificate>) -> Self {
-        let genesis = genesis
-            .into_iter()
-            .map(|x| (x.origin(), (x.digest(), x)))
-            .collect::<HashMap<_, _>>();
+impl CommittedSubDag {
+    /// The round of the leader.
+    pub fn round(&self) -> Round {
+        self.leader.round()
+    }
+
+    /// The number of committed certificates.
+    pub fn len(&self) -> usize {
+        self.certificates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.certificates.is_empty()
+    }
+}
+
+/// Decides which certificates of the DAG to commit. The `Consensus` adds every new certificate to the DAG
+/// (and garbage collects it) before calling the rule, so new rules only need to elect and commit leaders,
+/// which `State::commit` helps with.
+pub trait CommitRule: Send + 'static {
+    /// Returns the sub-dags to output (in order) now that `certificate` is in the DAG of `state`. Their
+    /// indices must come from `State::sub_dag` (which `State::commit` calls).
+    fn try_commit(&mut self, certificate: &Certificate, state: &mut State) -> Vec<CommittedSubDag>;
+}
+
+/// Returns the commit rule of a consensus protocol (Tusk by default).
+pub fn commit_rule(protocol: ConsensusProtocol, committee: &Committee) -> Box<dyn CommitRule> {
+    match protocol {
+        ConsensusProtocol::Tusk => Box::new(Tusk::new(committee.clone())),
+        ConsensusProtocol::Bullshark => Box::new(Bullshark::new(committee.clone())),
+    }
+}
 
-        Self {
-            last_committed_round: 0,
-            last_committed: genesis.iter().map(|(x, (_, y))| (*x, y.round())).collect(),
-            dag: [(0, genesis)].iter().cloned().collect(),
+/// Orders a recorded stream of certificates offline, without store nor network. The certificates are fed to
+/// the commit rule as the primary feeds the `Consensus` (each one after its parents), and the sub-dags it
+/// commits are returned in order; this lets different rules be compared on the same DAG.
+pub fn replay<I>(
+    committee: &Committee,
+    gc_depth: Round,
+    mut commit_rule: Box<dyn CommitRule>,
+    certificates: I,
+) -> Vec<CommittedSubDag>
+where
+    I: IntoIterator<Item = Certificate>,
+{
+    let mut state = State::new(Certificate::genesis(committee), gc_depth);
+    let mut sequence = Vec::new();
+    for certificate in certificates {
+        if state.is_obsolete(&certificate) {
+            continue;
         }
+        state.add(certificate.clone());
+        sequence.extend(commit_rule.try_commit(&certificate, &mut state));
     }
+    sequence
+}
 
-    /// Update and clean up internal state base on committed certificates.
-    fn update(&mut self, certificate: &Certificate, gc_depth: Round) {
-        self.last_committed
-            .entry(certificate.origin())
-            .and_modify(|r| *r = max(*r, certificate.round()))
-            .or_insert_with(|| certificate.round());
-
-        let last_committed_round = *self.last_committed.values().max().unwrap();
-        self.last_committed_round = last_committed_round;
-
-        for (name, round) in &self.last_committed {
-            self.dag.retain(|r, authorities| {
-                authorities.retain(|n, _| n != name || r >= round);
-                !authorities.is_empty() && r + gc_depth >= last_committed_round
-            });
+/// The minimum delay between two warnings about the application not keeping up with the output.
+const STALL_WARNING_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The key under which the consensus persists its `Watermark`.
+pub const WATERMARK_KEY: &[u8] = b"consensus_watermark";
+
+/// The key under which the consensus persists its `CommittedWatermark`, for the consumers of its output.
+/// The value is 16 bytes: the index of the last committed sub-dag, then the round of its leader, both as
+/// big-endian unsigned 64-bit integers.
+pub const COMMITTED_KEY: &[u8] = b"consensus_committed";
+
+/// The last sub-dag the consensus committed. It is persisted (durably) once the certificates of the sub-dag
+/// are output, so after a restart the output resumes with sub-dag `sub_dag_index + 1`. A crash between the
+/// output and the persistence outputs the sub-dags committed meanwhile again, under the same indices: the
+/// consumers keeping their own progress skip them by index.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CommittedWatermark {
+    pub sub_dag_index: u64,
+    /// The round of the leader of the sub-dag.
+    pub round: Round,
+}
+
+impl CommittedWatermark {
+    /// Encodes the watermark as stored under `COMMITTED_KEY`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [self.sub_dag_index.to_be_bytes(), self.round.to_be_bytes()].concat()
+    }
+
+    /// Decodes a watermark stored under `COMMITTED_KEY` (if well formed).
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != 16 {
+            return None;
         }
+        Some(Self {
+            sub_dag_index: u64::from_be_bytes(bytes[..8].try_into().unwrap()),
+            round: u64::from_be_bytes(bytes[8..].try_into().unwrap()),
+        })
     }
 }
 
-pub struct Consensus {
+/// The prefix of the keys under which the consensus records its committed sub-dags, followed by their index
+/// (as a big-endian unsigned 64-bit integer).
+pub const SUB_DAG_PREFIX: &[u8] = b"consensus_sub_dag/";
+
+/// The key of the record of a committed sub-dag.
+pub fn sub_dag_key(sub_dag_index: u64) -> Vec<u8> {
+    [SUB_DAG_PREFIX, &sub_dag_index.to_be_bytes()].concat()
+}
+
+/// The record of a committed sub-dag. It only lists the digests of the certificates, which the primary
+/// stores under their digest.
+#[derive(Serialize, Deserialize)]
+struct SubDagRecord {
+    /// The round of the leader.
+    round: Round,
+    /// The digests of the committed certificates, in commit order (the leader is the last one).
+    certificates: Vec<Digest>,
+}
+
+/// Records a committed sub-dag in `store`, so that its consumers can read it again later.
+pub async fn write_sub_dag<S: KeyValueStore>(store: &mut S, sub_dag: &CommittedSubDag) {
+    let record = SubDagRecord {
+        round: sub_dag.round(),
+        certificates: sub_dag.certificates.iter().map(|x| x.digest()).collect(),
+    };
+    let bytes = bincode::serialize(&record).expect("Failed to serialize sub-dag record");
+    store.write(sub_dag_key(sub_dag.sub_dag_index), bytes).await;
+}
+
+/// Returns the round of the leader of a recorded sub-dag, without reading its certificates.
+pub async fn read_sub_dag_round<S: KeyValueStore>(
+    store: &mut S,
+    sub_dag_index: u64,
+) -> StoreResult<Option<Round>> {
+    Ok(read_sub_dag_record(store, sub_dag_index)
+        .await?
+        .map(|x| x.round))
+}
+
+/// Reads a committed sub-dag recorded in `store`. Returns `None` if the sub-dag is not recorded, or if the
+/// store no longer holds all its certificates (they were pruned).
+pub async fn read_sub_dag<S: KeyValueStore>(
+    store: &mut S,
+    sub_dag_index: u64,
+) -> StoreResult<Option<CommittedSubDag>> {
+    let record = match read_sub_dag_record(store, sub_dag_index).await? {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    let keys = record.certificates.iter().map(|x| x.to_vec()).collect();
+    let mut certificates = Vec::new();
+    for bytes in store.read_many(keys).await? {
+        match bytes.and_then(|x| bincode::deserialize::<Certificate>(&x).ok()) {
+            Some(certificate) => certificates.push(certificate),
+            None => return Ok(None),
+        }
+    }
+    let leader = match certificates.last() {
+        Some(x) => x.clone(),
+        None => return Ok(None),
+    };
+    Ok(Some(CommittedSubDag {
+        leader,
+        certificates,
+        sub_dag_index,
+    }))
+}
+
+async fn read_sub_dag_record<S: KeyValueStore>(
+    store: &mut S,
+    sub_dag_index: u64,
+) -> StoreResult<Option<SubDagRecord>> {
+    let record = store
+        .read(sub_dag_key(sub_dag_index))
+        .await?
+        .and_then(|x| bincode::deserialize(&x).ok());
+    Ok(record)
+}
+
+/// Reads the last sub-dag committed by the consensus running on `store` (if it committed any).
+pub async fn read_committed<S: KeyValueStore>(
+    store: &mut S,
+) -> StoreResult<Option<CommittedWatermark>> {
+    let watermark = store
+        .read(COMMITTED_KEY.to_vec())
+        .await?
+        .and_then(|x| CommittedWatermark::from_bytes(&x));
+    Ok(watermark)
+}
+
+pub struct Consensus<S> {
     /// The committee information.
     committee: Committee,
+    /// The persistent storage (shared with the primary, which stores the certificates).
+    store: S,
     /// The depth of the garbage collector.
     gc_depth: Round,
+    /// The rule ordering the DAG.
+    commit_rule: Box<dyn CommitRule>,
 
     /// Receives new certificates from the primary. The primary should send us new certificates only
     /// if it already sent us its whole history.
@@ -73,230 +249,311 @@ pub struct Consensus {
     tx_primary: Sender<Certificate>,
     /// Outputs the sequence of ordered certificates to the application layer.
     tx_output: Sender<Certificate>,
+    /// Optionally outputs the committed sub-dags, after all their certificates went to `tx_output`.
+    tx_sub_dags: Option<Sender<CommittedSubDag>>,
+    /// The metrics exported by the consensus.
+    metrics: ConsensusMetrics,
+    /// If set, we stop committing (and close our outputs) once we committed a leader of this round.
+    max_committed_round: Option<Round>,
 
     /// The genesis certificates.
     genesis: Vec<Certificate>,
+    /// The scores of the leaders of the sub-dags committed so far.
+    leader_scores: LeaderScores,
+    /// The last sub-dag persisted as committed (if any). The sub-dags up to it are never output again.
+    committed: Option<CommittedWatermark>,
+    /// When we last warned that the output channel is full, and how many stalls we did not report since.
+    last_stall_warning: Option<Instant>,
+    unreported_stalls: u64,
 }
 
-impl Consensus {
+impl<S: KeyValueStore> Consensus<S> {
+    #[allow(clippy::too_many_arguments)]
     pub fn spawn(
         committee: Committee,
+        store: S,
         gc_depth: Round,
+        commit_rule: Box<dyn CommitRule>,
         rx_primary: Receiver<Certificate>,
         tx_primary: Sender<Certificate>,
         tx_output: Sender<Certificate>,
-    ) {
+        tx_sub_dags: Option<Sender<CommittedSubDag>>,
+        metrics: ConsensusMetrics,
+        max_committed_round: Option<Round>,
+    ) -> JoinHandle<()> {
         tokio::spawn(async move {
-            Self {
-                committee: committee.clone(),
+            let mut consensus = Self {
+                genesis: Certificate::genesis(&committee),
+                committee,
+                store,
                 gc_depth,
+                commit_rule,
                 rx_primary,
                 tx_primary,
                 tx_output,
-                genesis: Certificate::genesis(&committee),
+                tx_sub_dags,
+                metrics,
+                max_committed_round,
+                leader_scores: LeaderScores::default(),
+                committed: None,
+                last_stall_warning: None,
+                unreported_stalls: 0,
+            };
+            consensus.run().await;
+            consensus.drain().await;
+        })
+    }
+
+    /// Resumes from the watermark persisted before a crash (if any): rebuilds the uncommitted part of the
+    /// dag from the certificates of the store. Returns the sub-dags committed while replaying them (those
+    /// the consensus had not processed before crashing).
+    async fn recover(&mut self, state: &mut State) -> StoreResult<Vec<CommittedSubDag>> {
+        self.committed = read_committed(&mut self.store).await?;
+        let watermark = match self.store.read(WATERMARK_KEY.to_vec()).await? {
+            Some(bytes) => match bincode::deserialize::<Watermark>(&bytes) {
+                Ok(x) => x,
+                Err(e) => {
+                    warn!("Ignoring the malformed consensus watermark: {}", e);
+                    return Ok(Vec::new());
+                }
+            },
+            None => return Ok(Vec::new()),
+        };
+        state.restore(watermark);
+        if let Some(scores) = read_leader_scores(&mut self.store).await? {
+            for (name, score) in &scores.scores {
+                self.metrics.observe_leader(name, score);
             }
-            .run()
-            .await;
-        });
+            self.leader_scores = scores;
+        }
+
+        // Replay the certificates that may still be committed, in round order. The committed ones are
+        // skipped, except the last of each authority (the dag keeps it).
+        let mut round_index = RoundIndex::new(self.store.clone(), &self.committee);
+        let first = state
+            .last_committed_round
+            .saturating_sub(self.gc_depth)
+            .max(1);
+        let last = round_index.last_round().await?;
+        let mut sequence = Vec::new();
+        for round in first..=last {
+            for certificate in round_index.certificates(round).await? {
+                if state.is_obsolete(&certificate) {
+                    continue;
+                }
+                state.add(certificate.clone());
+                sequence.extend(self.commit_rule.try_commit(&certificate, state));
+            }
+        }
+        info!(
+            "Consensus resumed after round {} ({} sub-dags committed before the restart)",
+            state.last_committed_round, state.sub_dag_index
+        );
+        Ok(sequence)
     }
 
     async fn run(&mut self) {
         // The consensus state (everything else is immutable).
-        let mut state = State::new(self.genesis.clone());
-
-        // Listen to incoming certificates.
-        while let Some(certificate) = self.rx_primary.recv().await {
-            debug!("Processing {:?}", certificate);
-            let round = certificate.round();
-
-            // Add the new certificate to the local storage.
-            state
-                .dag
-                .entry(round)
-                .or_insert_with(HashMap::new)
-                .insert(certificate.origin(), (certificate.digest(), certificate));
-
-            // Try to order the dag to commit. Start from the highest round for which we have at least
-            // 2f+1 certificates. This is because we need them to reveal the common coin.
-            let r = round - 1;
-
-            // We only elect leaders for even round numbers.
-            if r % 2 != 0 || r < 4 {
-                continue;
-            }
+        let mut state = State::new(self.genesis.clone(), self.gc_depth);
+        match self.recover(&mut state).await {
+            Ok(sequence) => self.commit(&state, sequence).await,
+            Err(e) => warn!("Failed to recover the consensus state: {}", e),
+        }
 
-            // Get the certificate's digest of the leader of round r-2. If we already ordered this leader,
-            // there is nothing to do.
-            let leader_round = r - 2;
-            if leader_round <= state.last_committed_round {
-                continue;
-            }
-            let (leader_digest, leader) = match self.leader(leader_round, &state.dag) {
+        // Listen to incoming certificates (until we committed enough rounds, if asked to).
+        while !self.reached_max_committed_round() {
+            let certificate = match self.rx_primary.recv().await {
                 Some(x) => x,
-                None => continue,
+                None => return,
             };
+            debug!("Processing {:?}", certificate);
 
-            // Check if the leader has f+1 support from its children (ie. round r-1).
-            let stake: Stake = state
-                .dag
-                .get(&(r - 1))
-                .expect("We should have the whole history by now")
-                .values()
-                .filter(|(_, x)| x.header.parents.contains(&leader_digest))
-                .map(|(_, x)| self.committee.stake(&x.origin()))
-                .sum();
-
-            // If it is the case, we can commit the leader. But first, we need to recursively go back to
-            // the last committed leader, and commit all preceding leaders in the right order. Committing
-            // a leader block means committing all its dependencies.
-            if stake < self.committee.validity_threshold() {
-                debug!("Leader {:?} does not have enough support", leader);
+            // After a restart, the primary may send us again certificates we replayed from the store.
+            if state.is_obsolete(&certificate) {
                 continue;
             }
 
-            // Get an ordered list of past leaders that are linked to the current leader.
-            debug!("Leader {:?} has enough support", leader);
-            let mut sequence = Vec::new();
-            for leader in self.order_leaders(leader, &state).iter().rev() {
-                // Starting from the oldest leader, flatten the sub-dag referenced by the leader.
-                for x in self.order_dag(leader, &state) {
-                    // Update and clean up internal state.
-                    state.update(&x, self.gc_depth);
-
-                    // Add the certificate to the sequence.
-                    sequence.push(x);
-                }
-            }
+            // Add the new certificate to the local storage, and try to order the dag to commit.
+            state.add(certificate.clone());
+            let sequence = self.commit_rule.try_commit(&certificate, &mut state);
+            self.commit(&state, sequence).await;
+        }
+        info!(
+            "Committed the leader of round {}: stopping the consensus",
+            self.leader_scores.last_leader_round
+        );
+    }
 
-            // Log the latest committed round of every authority (for debug).
-            if log_enabled!(log::Level::Debug) {
-                for (name, round) in &state.last_committed {
-                    debug!("Latest commit of {}: Round {}", name, round);
-                }
-            }
+    /// Whether we committed a leader of round `max_committed_round` or above.
+    fn reached_max_committed_round(&self) -> bool {
+        self.max_committed_round
+            .map_or(false, |x| self.leader_scores.last_leader_round >= x)
+    }
 
-            // Output the sequence in the right order.
-            for certificate in sequence {
-                #[cfg(not(feature = "benchmark"))]
-                info!("Committed {}", certificate.header);
+    /// Closes our outputs once we stopped committing, so that their consumers complete, and sinks the
+    /// certificates the primary keeps sending us until it stops too.
+    async fn drain(self) {
+        let Self {
+            mut rx_primary,
+            tx_output,
+            tx_sub_dags,
+            ..
+        } = self;
+        drop(tx_output);
+        drop(tx_sub_dags);
+        while rx_primary.recv().await.is_some() {}
+    }
 
-                #[cfg(feature = "benchmark")]
-                for digest in certificate.header.payload.keys() {
-                    // NOTE: This log entry is used to compute performance.
-                    info!("Committed {} -> {:?}", certificate.header, digest);
-                }
+    /// Outputs newly committed sub-dags in order, and then persists their watermark (along with the scores of
+    /// their leaders and the `CommittedWatermark` of the consumers) in a single synced write: the watermarks
+    /// move atomically, and only past sub-dags that were output. A crash before the write replays the
+    /// sub-dags from the previous watermark on restart, under the same indices; the sub-dags up to the
+    /// persisted index are skipped, so the output resumes exactly once per index at the consensus. The
+    /// records of the sub-dags precede the watermarks (every sub-dag up to them can be read again).
+    async fn commit(&mut self, state: &State, sequence: Vec<CommittedSubDag>) {
+        let persisted = self.committed.map(|x| x.sub_dag_index);
+        let sequence: Vec<_> = sequence
+            .into_iter()
+            .filter(|x| persisted.map_or(true, |index| x.sub_dag_index > index))
+            .collect();
+        let last = match sequence.last() {
+            Some(x) => CommittedWatermark {
+                sub_dag_index: x.sub_dag_index,
+                round: x.round(),
+            },
+            None => return,
+        };
+        for sub_dag in &sequence {
+            write_sub_dag(&mut self.store, sub_dag).await;
+            let committee = &self.committee;
+            self.leader_scores
+                .update(sub_dag, |round| round_robin(committee, round));
+        }
+        for (name, score) in &self.leader_scores.scores {
+            self.metrics.observe_leader(name, score);
+        }
 
-                self.tx_primary
-                    .send(certificate.clone())
-                    .await
-                    .expect("Failed to send certificate to primary");
+        // Output the sequence in the right order.
+        for sub_dag in sequence {
+            self.output(sub_dag).await;
+        }
 
-                if let Err(e) = self.tx_output.send(certificate).await {
-                    warn!("Failed to output certificate: {}", e);
-                }
-            }
+        // Syncing the write also makes the records of the sub-dags written before it durable.
+        let scores =
+            bincode::serialize(&self.leader_scores).expect("Failed to serialize leader scores");
+        let watermark =
+            bincode::serialize(&state.watermark()).expect("Failed to serialize watermark");
+        let entries = vec![
+            (LEADER_SCORES_KEY.to_vec(), scores),
+            (WATERMARK_KEY.to_vec(), watermark),
+            (COMMITTED_KEY.to_vec(), last.to_bytes()),
+        ];
+        match self.store.write_synced(entries).await {
+            Ok(()) => self.committed = Some(last),
+            Err(e) => warn!("Failed to persist the consensus watermark: {}", e),
         }
     }
 
-    /// Returns the certificate (and the certificate's digest) originated by the leader of the
-    /// specified round (if any).
-    fn leader<'a>(&self, round: Round, dag: &'a Dag) -> Option<&'a (Digest, Certificate)> {
-        // TODO: We should elect the leader of round r-2 using the common coin revealed at round r.
-        // At this stage, we are guaranteed to have 2f+1 certificates from round r (which is enough to
-        // compute the coin). We currently just use round-robin.
-        #[cfg(test)]
-        let coin = 0;
-        #[cfg(not(test))]
-        let coin = round;
-
-        // Elect the leader.
-        let mut keys: Vec<_> = self.committee.authorities.keys().cloned().collect();
-        keys.sort();
-        let leader = keys[coin as usize % self.committee.size()];
-
-        // Return its certificate and the certificate's digest.
-        dag.get(&round).map(|x| x.get(&leader)).flatten()
-    }
+    async fn output(&mut self, sub_dag: CommittedSubDag) {
+        debug!(
+            "Committed sub-dag {} of leader {} ({} certificates)",
+            sub_dag.sub_dag_index,
+            sub_dag.leader.header,
+            sub_dag.len()
+        );
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_millis() as u64)
+            .unwrap_or_default();
+        for certificate in &sub_dag.certificates {
+            // The last span of the trace of the header (the other spans are the primary's).
+            info_span!(
+                "commit",
+                header = %certificate.header.id,
+                round = certificate.round(),
+                sub_dag = sub_dag.sub_dag_index
+            )
+            .in_scope(|| self.observe_latency(certificate, now));
+
+            #[cfg(not(feature = "benchmark"))]
+            info!("Committed {}", certificate.header);
+
+            #[cfg(feature = "benchmark")]
+            for digest in certificate.header.payload.keys() {
+                // NOTE: This log entry is used to compute performance.
+                info!("Committed {} -> {:?}", certificate.header, digest);
+            }
 
-    /// Order the past leaders that we didn't already commit.
-    fn order_leaders(&self, leader: &Certificate, state: &State) -> Vec<Certificate> {
-        let mut to_commit = vec![leader.clone()];
-        let mut leader = leader;
-        for r in (state.last_committed_round + 2..leader.round())
-            .rev()
-            .step_by(2)
-        {
-            // Get the certificate proposed by the previous leader.
-            let (_, prev_leader) = match self.leader(r, &state.dag) {
-                Some(x) => x,
-                None => continue,
-            };
+            self.tx_primary
+                .send(certificate.clone())
+                .await
+                .expect("Failed to send certificate to primary");
 
-            // Check whether there is a path between the last two leaders.
-            if self.linked(leader, prev_leader, &state.dag) {
-                to_commit.push(prev_leader.clone());
-                leader = prev_leader;
-            }
+            self.deliver(certificate.clone(), sub_dag.sub_dag_index)
+                .await;
         }
-        to_commit
-    }
 
-    /// Checks if there is a path between two leaders.
-    fn linked(&self, leader: &Certificate, prev_leader: &Certificate, dag: &Dag) -> bool {
-        let mut parents = vec![leader];
-        for r in (prev_leader.round()..leader.round()).rev() {
-            parents = dag
-                .get(&(r))
-                .expect("We should have the whole history by now")
-                .values()
-                .filter(|(digest, _)| parents.iter().any(|x| x.header.parents.contains(digest)))
-                .map(|(_, certificate)| certificate)
-                .collect();
+        if let Some(tx_sub_dags) = &self.tx_sub_dags {
+            if let Err(e) = tx_sub_dags.send(sub_dag).await {
+                warn!("Failed to output sub-dag: {}", e);
+            }
         }
-        parents.contains(&prev_leader)
     }
 
-    /// Flatten the dag referenced by the input certificate. This is a classic depth-first search (pre-order):
-    /// https://en.wikipedia.org/wiki/Tree_traversal#Pre-order
-    fn order_dag(&self, leader: &Certificate, state: &State) -> Vec<Certificate> {
-        debug!("Processing sub-dag of {:?}", leader);
-        let mut ordered = Vec::new();
-        let mut already_ordered = HashSet::new();
-
-        let mut buffer = vec![leader];
-        while let Some(x) = buffer.pop() {
-            debug!("Sequencing {:?}", x);
-            ordered.push(x.clone());
-            for parent in &x.header.parents {
-                let (digest, certificate) = match state
-                    .dag
-                    .get(&(x.round() - 1))
-                    .map(|x| x.values().find(|(x, _)| x == parent))
-                    .flatten()
-                {
-                    Some(x) => x,
-                    None => continue, // We already ordered or GC up to here.
-                };
-
-                // We skip the certificate if we (1) already processed it or (2) we reached a round that we already
-                // committed for this authority.
-                let mut skip = already_ordered.contains(&digest);
-                skip |= state
-                    .last_committed
-                    .get(&certificate.origin())
-                    .map_or_else(|| false, |r| r == &certificate.round());
-                if !skip {
-                    buffer.push(certificate);
-                    already_ordered.insert(digest);
-                }
+    /// Outputs a certificate to the application layer. When the channel is full (the application does not
+    /// keep up), the consensus blocks until the application catches up: the stall is reported in the logs
+    /// (at most once per `STALL_WARNING_INTERVAL`) and in the metrics.
+    async fn deliver(&mut self, certificate: Certificate, sub_dag_index: u64) {
+        let certificate = match self.tx_output.try_send(certificate) {
+            Ok(()) => return,
+            Err(TrySendError::Full(certificate)) => certificate,
+            Err(TrySendError::Closed(_)) => {
+                warn!("Failed to output certificate: the application stopped");
+                return;
             }
+        };
+
+        self.metrics.output_stalls.inc();
+        self.unreported_stalls += 1;
+        let now = Instant::now();
+        if self
+            .last_stall_warning
+            .map_or(true, |x| now.duration_since(x) >= STALL_WARNING_INTERVAL)
+        {
+            warn!(
+                "Output channel full: consensus blocked on certificate {} (round {}, sub-dag {}) until the application catches up ({} stalls since the last warning)",
+                certificate.digest(),
+                certificate.round(),
+                sub_dag_index,
+                self.unreported_stalls
+            );
+            self.last_stall_warning = Some(now);
+            self.unreported_stalls = 0;
         }
 
-        // Ensure we do not commit garbage collected certificates.
-        ordered.retain(|x| x.round() + self.gc_depth >= state.last_committed_round);
+        self.metrics.output_blocked.set(1);
+        let result = self.tx_output.send(certificate).await;
+        self.metrics.output_blocked.set(0);
+        self.metrics
+            .output_stall_seconds
+            .inc_by(now.elapsed().as_secs_f64());
+        if let Err(e) = result {
+            warn!("Failed to output certificate: {}", e);
+        }
+    }
 
-        // Ordering the output by round is not really necessary but it makes the commit sequence prettier.
-        ordered.sort_by_key(|x| x.round());
-        ordered
+    /// Records the commit latency of a certificate committed at time `now` (in ms since the Unix epoch).
+    /// The latency of each certificate is measured from the timestamp of its own header, so that an author
+    /// lying about the time only skews the samples of its own certificates; timestamps in the future count
+    /// as no latency at all, and headers without timestamp (such as genesis) are not measured.
+    fn observe_latency(&self, certificate: &Certificate, now: u64) {
+        let timestamp = certificate.header.timestamp;
+        if timestamp == 0 {
+            return;
+        }
+        let latency = now.saturating_sub(timestamp);
+        self.metrics
+            .commit_latency
+            .observe(latency as f64 / 1_000.0);
     }
 }
@@ -0,0 +1,110 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::reputation::LeaderScore;
+use crypto::PublicKey;
+use prometheus::{
+    Counter, Histogram, HistogramOpts, IntCounter, IntGauge, IntGaugeVec, Opts, Registry,
+    DEFAULT_BUCKETS,
+};
+
+/// The metrics exported by the consensus.
+#[derive(Clone)]
+pub struct ConsensusMetrics {
+    /// The time from the creation of a header to the commit of its certificate (in seconds).
+    pub commit_latency: Histogram,
+    /// Whether the consensus is blocked on a full output channel (1) or not (0).
+    pub output_blocked: IntGauge,
+    /// The number of committed certificates the application was not ready to receive.
+    pub output_stalls: IntCounter,
+    /// The time the consensus spent blocked on a full output channel (in seconds).
+    pub output_stall_seconds: Counter,
+    /// The number of committed leader certificates of each authority (see `LeaderScores`).
+    pub leader_commits: IntGaugeVec,
+    /// The number of skipped leader rounds of each authority.
+    pub leader_skips: IntGaugeVec,
+}
+
+impl ConsensusMetrics {
+    /// Creates the metrics of the consensus and registers them with `registry`. The commit latency is
+    /// bucketed by the (increasing) upper bounds `buckets`.
+    pub fn new(registry: &Registry, buckets: &[f64]) -> Self {
+        let opts = HistogramOpts::new(
+            "consensus_commit_latency_seconds",
+            "Time from the creation of a header to the commit of its certificate",
+        )
+        .buckets(buckets.to_vec());
+        let metrics = Self {
+            commit_latency: Histogram::with_opts(opts).expect(
+                "The commit latency buckets must be increasing (see `Parameters::validate`)",
+            ),
+            output_blocked: IntGauge::new(
+                "consensus_output_blocked",
+                "Whether the consensus waits for the application to consume its output",
+            )
+            .unwrap(),
+            output_stalls: IntCounter::new(
+                "consensus_output_stalls_total",
+                "Committed certificates output while the output channel was full",
+            )
+            .unwrap(),
+            output_stall_seconds: Counter::new(
+                "consensus_output_stall_seconds_total",
+                "Time the consensus spent waiting for the application to consume its output",
+            )
+            .unwrap(),
+            leader_commits: IntGaugeVec::new(
+                Opts::new(
+                    "consensus_leader_commits",
+                    "Committed leader certificates of each authority",
+                ),
+                &["authority"],
+            )
+            .unwrap(),
+            leader_skips: IntGaugeVec::new(
+                Opts::new(
+                    "consensus_leader_skips",
+                    "Leader rounds skipped while each authority was the leader",
+                ),
+                &["authority"],
+            )
+            .unwrap(),
+        };
+        registry
+            .register(Box::new(metrics.commit_latency.clone()))
+            .expect("Failed to register the consensus metrics");
+        registry
+            .register(Box::new(metrics.output_blocked.clone()))
+            .expect("Failed to register the consensus metrics");
+        registry
+            .register(Box::new(metrics.output_stalls.clone()))
+            .expect("Failed to register the consensus metrics");
+        registry
+            .register(Box::new(metrics.output_stall_seconds.clone()))
+            .expect("Failed to register the consensus metrics");
+        registry
+            .register(Box::new(metrics.leader_commits.clone()))
+            .expect("Failed to register the consensus metrics");
+        registry
+            .register(Box::new(metrics.leader_skips.clone()))
+            .expect("Failed to register the consensus metrics");
+        metrics
+    }
+
+    /// Exports the score of a leader. The scores are gauges since they are persisted: they resume from
+    /// their last value after a restart.
+    pub fn observe_leader(&self, name: &PublicKey, score: &LeaderScore) {
+        let label = name.to_string();
+        self.leader_commits
+            .with_label_values(&[&label])
+            .set(score.commits as i64);
+        self.leader_skips
+            .with_label_values(&[&label])
+            .set(score.skips as i64);
+    }
+}
+
+impl Default for ConsensusMetrics {
+    /// Creates metrics that are not exported (useful for tests).
+    fn default() -> Self {
+        Self::new(&Registry::new(), DEFAULT_BUCKETS)
+    }
+}
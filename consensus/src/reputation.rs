@@ -0,0 +1,84 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::CommittedSubDag;
+use config::Committee;
+use crypto::PublicKey;
+use primary::Round;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use store::{KeyValueStore, StoreResult};
+
+#[cfg(test)]
+#[path = "tests/reputation_tests.rs"]
+pub mod reputation_tests;
+
+/// The key under which the consensus persists its `LeaderScores`.
+pub const LEADER_SCORES_KEY: &[u8] = b"consensus_leader_scores";
+
+/// How an authority fared in the rounds it was elected leader.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LeaderScore {
+    /// The number of its leader certificates that were committed.
+    pub commits: u64,
+    /// The number of leader rounds that were skipped while it was the leader (its certificate was missing
+    /// or not linked to the next committed leader).
+    pub skips: u64,
+}
+
+/// The scores of the leaders, derived from the sequence of committed sub-dags only: every honest node
+/// commits the same sequence, so they all compute the same scores. The leader rounds between two
+/// committed leaders are those that were skipped.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct LeaderScores {
+    /// The round of the last committed leader.
+    pub last_leader_round: Round,
+    /// The score of each authority that was elected at least once.
+    pub scores: BTreeMap<PublicKey, LeaderScore>,
+}
+
+impl LeaderScores {
+    /// Accounts for a committed sub-dag, the leader of each round being `elect(round)` (leaders are elected
+    /// every other round). Sub-dags whose leader is not above the last committed one are already accounted
+    /// for, so replaying them after a restart does not count them twice.
+    pub fn update<F>(&mut self, sub_dag: &CommittedSubDag, elect: F)
+    where
+        F: Fn(Round) -> PublicKey,
+    {
+        let round = sub_dag.round();
+        if round <= self.last_leader_round {
+            return;
+        }
+        for skipped in (self.last_leader_round + 2..round).step_by(2) {
+            self.scores.entry(elect(skipped)).or_default().skips += 1;
+        }
+        self.scores
+            .entry(sub_dag.leader.origin())
+            .or_default()
+            .commits += 1;
+        self.last_leader_round = round;
+    }
+
+    /// The score of an authority (zero if it was never elected).
+    pub fn score(&self, name: &PublicKey) -> LeaderScore {
+        self.scores.get(name).cloned().unwrap_or_default()
+    }
+
+    /// The scores of all the authorities of the committee, in the order of their keys.
+    pub fn committee_scores(&self, committee: &Committee) -> Vec<(PublicKey, LeaderScore)> {
+        committee
+            .authorities
+            .keys()
+            .map(|x| (*x, self.score(x)))
+            .collect()
+    }
+}
+
+/// Reads the leader scores persisted by the consensus running on `store` (if it committed any leader).
+pub async fn read_leader_scores<S: KeyValueStore>(
+    store: &mut S,
+) -> StoreResult<Option<LeaderScores>> {
+    let scores = store
+        .read(LEADER_SCORES_KEY.to_vec())
+        .await?
+        .and_then(|x| bincode::deserialize(&x).ok());
+    Ok(scores)
+}
@@ -0,0 +1,523 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::faults::{Event, Fault, Faults, Scenario};
+use crate::{commit_rule, Consensus, ConsensusMetrics};
+use bytes::Bytes;
+use config::{Committee, ConsensusProtocol, KeyPair, Parameters, ThresholdKeyPair};
+use crypto::threshold::PublicKey as ThresholdPublicKey;
+use crypto::{PublicKey, SecretKey};
+use network::memory::{Interceptor, Link, MemoryNetwork, Verdict};
+use network::SimpleSender;
+use primary::{Certificate, PeersHealth, Primary};
+use prometheus::Registry;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+use store::MemoryStore;
+use tokio::runtime::Builder;
+use tokio::sync::mpsc::{channel, unbounded_channel, Receiver, Sender, UnboundedSender};
+use tokio::sync::{oneshot, watch};
+use worker::{ShareRequest, Shutdown, ThresholdKeyRing, Worker};
+
+pub use network::memory::NodeId;
+
+#[cfg(test)]
+#[path = "tests/simulation_tests.rs"]
+pub mod simulation_tests;
+
+/// The time elapsed since the simulation started, in milliseconds.
+pub type Tick = u64;
+
+/// The port of the first primary of the committee (the other addresses follow).
+const BASE_PORT: u16 = 10_000;
+
+/// A seeded pseudo-random number generator (SplitMix64). It is simple enough that the sequences produced
+/// by a seed never change with the versions of our dependencies.
+pub struct SimRng(u64);
+
+impl SimRng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a number between `low` and `high` (inclusive).
+    pub fn range(&mut self, low: u64, high: u64) -> u64 {
+        if high <= low {
+            return low;
+        }
+        low + self.next_u64() % (high - low + 1)
+    }
+}
+
+/// The conditions of the links between the nodes.
+struct Conditions {
+    /// Draws the delays of the messages, and which of them the faults alter.
+    rng: SimRng,
+    /// The minimum and maximum delay of a message.
+    delays: (Tick, Tick),
+    /// The extra delay of some links.
+    slow_links: HashMap<(NodeId, NodeId), Tick>,
+    /// The groups of nodes cut from the others, and the time at which each partition heals.
+    partitions: Vec<(HashSet<NodeId>, Tick)>,
+    /// The faults altering the messages, and the misbehaving nodes.
+    faults: Faults,
+    /// The events of the scenario not yet applied, in order.
+    events: VecDeque<(Tick, Event)>,
+}
+
+impl Conditions {
+    /// Applies the events of the scenario due by `now`.
+    fn apply_events(&mut self, now: Tick) {
+        while self.events.front().map_or(false, |(x, _)| *x <= now) {
+            let (at, event) = self.events.pop_front().unwrap();
+            match event {
+                Event::Inject(fault) => self.faults.inject(fault),
+                Event::ClearFaults => self.faults.clear(),
+                Event::Partition(group, duration) => self
+                    .partitions
+                    .push((group.into_iter().collect(), at + duration)),
+                Event::Crash(node) => self.faults.crash(node),
+                Event::Equivocate(node) => self.faults.equivocate(node),
+            }
+        }
+    }
+
+    /// Whether a partition or a crash cuts the link (the clients are never cut from the node they talk to).
+    fn cut(&self, link: &Link, now: Tick) -> bool {
+        let (from, to) = match (link.from, link.to) {
+            (Some(from), Some(to)) => (from, to),
+            _ => return false,
+        };
+        self.faults.cut(link)
+            || self
+                .partitions
+                .iter()
+                .any(|(group, heal)| *heal > now && group.contains(&from) != group.contains(&to))
+    }
+}
+
+/// Applies the conditions of the links to the connections and messages of the in-memory network.
+struct Links {
+    /// When the simulation started.
+    start: Instant,
+    conditions: Mutex<Conditions>,
+}
+
+impl Links {
+    fn now(&self) -> Tick {
+        self.start.elapsed().as_millis() as Tick
+    }
+
+    /// The conditions of the links, once the events due by now apply.
+    fn conditions(&self) -> MutexGuard<'_, Conditions> {
+        let mut conditions = self.conditions.lock().unwrap();
+        conditions.apply_events(self.now());
+        conditions
+    }
+}
+
+impl Interceptor for Links {
+    fn connect(&self, link: &Link) -> bool {
+        !self.conditions().cut(link, self.now())
+    }
+
+    fn send(&self, link: &Link, message: Bytes) -> Verdict {
+        let mut conditions = self.conditions();
+
+        // The connections crossing a partition break: the reliable senders re-transmit their messages once
+        // it heals, and the nodes sync the ones that were lost.
+        if conditions.cut(link, self.now()) {
+            return Verdict::Reset;
+        }
+        let (from, to) = match (link.from, link.to) {
+            (Some(from), Some(to)) if from != to => (from, to),
+            _ => return Verdict::Deliver(Duration::default(), message),
+        };
+        let (min, max) = conditions.delays;
+        let delay = conditions.rng.range(min, max)
+            + conditions
+                .slow_links
+                .get(&(from, to))
+                .cloned()
+                .unwrap_or_default();
+
+        let conditions = &mut *conditions;
+        conditions.faults.alter(
+            link,
+            message,
+            Duration::from_millis(delay),
+            &mut conditions.rng,
+        )
+    }
+}
+
+/// A running node: its primary, worker and consensus, on their own thread and runtime.
+struct Node {
+    /// Stops the node when fired (or dropped).
+    tx_stop: oneshot::Sender<()>,
+    thread: thread::JoinHandle<()>,
+    /// Submits (encrypted) transactions to the worker of the node.
+    tx_transactions: UnboundedSender<Vec<u8>>,
+}
+
+impl Node {
+    /// Stops the tasks of the node, which closes its connections.
+    fn stop(self) {
+        let _ = self.tx_stop.send(());
+        let _ = self.thread.join();
+    }
+}
+
+/// Runs a committee of real primaries, workers and consensus cores in a single process, over an in-memory
+/// network whose links can be slowed down, partitioned, or altered by faults. Every node runs on its own
+/// thread: the delays of the messages (and the faults altering them) are drawn from a seeded generator, but
+/// the interleavings also depend on the scheduling of the threads, so that a run cannot be replayed. The
+/// time flows as usual.
+pub struct Simulation {
+    /// The committee information.
+    committee: Committee,
+    /// The keys of the nodes, indexed by `NodeId` (the nodes are sorted by public key).
+    keys: Vec<KeyPair>,
+    parameters: Parameters,
+    /// The seed of the threshold keys of the workers.
+    seed: u64,
+    /// The threshold public key with which the clients encrypt their transactions.
+    threshold_pk: ThresholdPublicKey,
+    network: Arc<MemoryNetwork>,
+    links: Arc<Links>,
+    /// The running nodes (`None` once crashed).
+    nodes: Vec<Option<Node>>,
+    /// The certificates committed by each node, in commit order.
+    committed: Vec<Arc<Mutex<Vec<Certificate>>>>,
+}
+
+impl Simulation {
+    /// Starts a committee of `size` nodes (of equal stake).
+    pub fn new(size: usize, parameters: Parameters, seed: u64) -> Self {
+        let mut keys: Vec<_> = (0..size).map(|_| KeyPair::new()).collect();
+        keys.sort_by_key(|x| x.name);
+        let host = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let committee = Committee::new(
+            keys.iter().map(|x| (x.name, 1, host)).collect(),
+            BASE_PORT,
+            /* workers */ 1,
+        )
+        .expect("Failed to make the committee");
+
+        let secrets = keys.iter().map(|x| duplicate(&x.secret)).collect();
+        let links = Arc::new(Links {
+            start: Instant::now(),
+            conditions: Mutex::new(Conditions {
+                rng: SimRng::new(seed),
+                delays: (1, 10),
+                slow_links: HashMap::new(),
+                partitions: Vec::new(),
+                faults: Faults::new(&committee, secrets),
+                events: VecDeque::new(),
+            }),
+        });
+        let mut simulation = Self {
+            committee,
+            keys,
+            parameters,
+            seed,
+            threshold_pk: Self::threshold_keypair(size, 0, seed).pk_set.public_key(),
+            network: MemoryNetwork::new(links.clone()),
+            links,
+            nodes: Vec::new(),
+            committed: (0..size).map(|_| Arc::default()).collect(),
+        };
+        simulation.nodes = (0..size).map(|x| Some(simulation.spawn(x))).collect();
+        simulation
+    }
+
+    /// Parameters making the nodes propose (and commit) every few tens of milliseconds.
+    pub fn parameters(protocol: ConsensusProtocol) -> Parameters {
+        Parameters {
+            header_size: 1_000,
+            max_header_delay: 50,
+            max_batch_delay: 50,
+            gc_depth: 50,
+            sync_retry_delay: 200,
+            min_reconnect_delay_ms: 10,
+            max_reconnect_delay_ms: 100,
+            consensus: protocol,
+            ..Parameters::default()
+        }
+    }
+
+    /// The threshold key share of a node (any `f + 1` of them decrypt the transactions).
+    fn threshold_keypair(size: usize, node: NodeId, seed: u64) -> ThresholdKeyPair {
+        ThresholdKeyPair::new((size - 1) / 3, node, seed)
+    }
+
+    /// Sets the range of the (random) delay of all messages between distinct nodes.
+    pub fn with_delays(self, min: Tick, max: Tick) -> Self {
+        self.links.conditions().delays = (min, max);
+        self
+    }
+
+    /// Plays a scenario: each of its events applies once the simulation reaches its time.
+    pub fn with_scenario(self, scenario: Scenario) -> Self {
+        self.links.conditions().events = scenario.into_events().into_iter().collect();
+        self
+    }
+
+    /// The number of nodes.
+    pub fn size(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// The public key of a node.
+    pub fn name(&self, node: NodeId) -> PublicKey {
+        self.keys[node].name
+    }
+
+    /// The current time.
+    pub fn now(&self) -> Tick {
+        self.links.now()
+    }
+
+    /// Delays by `extra` ticks all the messages that `from` sends to `to` from now on.
+    pub fn slow_link(&mut self, from: NodeId, to: NodeId, extra: Tick) {
+        self.links.conditions().slow_links.insert((from, to), extra);
+    }
+
+    /// Cuts `group` from the rest of the committee for `duration` ticks: the connections crossing the cut
+    /// are refused or reset until the partition heals.
+    pub fn partition(&mut self, group: &[NodeId], duration: Tick) {
+        let heal = self.now() + duration;
+        self.links
+            .conditions()
+            .partitions
+            .push((group.iter().cloned().collect(), heal));
+    }
+
+    /// Crashes a node: its tasks stop and its connections close.
+    pub fn crash(&mut self, node: NodeId) {
+        if let Some(node) = self.nodes[node].take() {
+            node.stop();
+        }
+    }
+
+    /// Alters the messages matching `fault` from now on.
+    pub fn inject(&mut self, fault: Fault) {
+        self.links.conditions().faults.inject(fault);
+    }
+
+    /// Makes a node equivocate from now on.
+    pub fn equivocate(&mut self, node: NodeId) {
+        self.links.conditions().faults.equivocate(node);
+    }
+
+    /// The nodes that neither crashed nor equivocate.
+    pub fn honest(&self) -> Vec<NodeId> {
+        let conditions = self.links.conditions();
+        (0..self.size())
+            .filter(|x| self.nodes[*x].is_some() && !conditions.faults.faulty(*x))
+            .collect()
+    }
+
+    /// The number of messages altered by the faults so far.
+    pub fn altered(&self) -> usize {
+        self.links.conditions().faults.altered()
+    }
+
+    /// The certificates committed by a node so far, in commit order.
+    pub fn committed(&self, node: NodeId) -> Vec<Certificate> {
+        self.committed[node].lock().unwrap().clone()
+    }
+
+    /// Submits a transaction to the worker of a node, encrypted as the clients do.
+    pub fn submit(&self, node: NodeId, plaintext: &[u8]) {
+        if let Some(node) = &self.nodes[node] {
+            let transaction = config::threshold::encrypt(&self.threshold_pk, plaintext);
+            let _ = node.tx_transactions.send(transaction);
+        }
+    }
+
+    /// Waits until `condition` holds, or until `timeout`. Returns whether the condition holds.
+    pub fn run_until<F: Fn(&Self) -> bool>(&self, condition: F, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        while !condition(self) {
+            if Instant::now() > deadline {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        true
+    }
+
+    /// Waits until every honest node committed `count` certificates, or until `timeout`. Returns whether the
+    /// honest nodes committed them.
+    pub fn run_until_committed(&self, count: usize, timeout: Duration) -> bool {
+        self.run_until(
+            |simulation| {
+                simulation
+                    .honest()
+                    .iter()
+                    .all(|x| simulation.committed[*x].lock().unwrap().len() >= count)
+            },
+            timeout,
+        )
+    }
+
+    /// Checks that the sequences committed by the honest nodes are prefixes of one another. Returns the
+    /// length of the prefix committed by all of them.
+    pub fn check_agreement(&self) -> Result<usize, String> {
+        let committed: Vec<_> = self
+            .honest()
+            .into_iter()
+            .map(|x| (x, self.committed(x)))
+            .collect();
+        let (longest, reference) = match committed.iter().max_by_key(|(_, x)| x.len()) {
+            Some(x) => x,
+            None => return Ok(0),
+        };
+        for (node, certificates) in &committed {
+            if let Some(i) = (0..certificates.len()).find(|i| certificates[*i] != reference[*i]) {
+                return Err(format!(
+                    "Nodes {} and {} committed different certificates at position {}: {:?} and {:?}",
+                    node, longest, i, certificates[i], reference[i]
+                ));
+            }
+        }
+        Ok(committed
+            .iter()
+            .map(|(_, x)| x.len())
+            .min()
+            .unwrap_or_default())
+    }
+
+    /// Starts a node on its own thread and runtime, which routes its connections through the network.
+    fn spawn(&self, node: NodeId) -> Node {
+        let keypair = KeyPair {
+            name: self.keys[node].name,
+            secret: duplicate(&self.keys[node].secret),
+            next: None,
+        };
+        let threshold_keypair = Self::threshold_keypair(self.size(), node, self.seed);
+        let committee = self.committee.clone();
+        let parameters = self.parameters.clone();
+        let network = self.network.clone();
+        let committed = self.committed[node].clone();
+        let (tx_stop, mut rx_stop) = oneshot::channel();
+        let (tx_transactions, mut rx_transactions) = unbounded_channel::<Vec<u8>>();
+        let thread = thread::spawn(move || {
+            let runtime = Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("Failed to build the runtime of a node");
+
+            // Dropping the runtime (after the loop) stops all the tasks of the node.
+            runtime.block_on(async move {
+                network.enter(Some(node));
+                let transactions = committee
+                    .worker(&keypair.name, &0)
+                    .expect("Our public key is not in the committee")
+                    .transactions;
+                let (mut rx_output, _channels) =
+                    spawn_components(keypair, committee, parameters, threshold_keypair);
+                let mut client = SimpleSender::new();
+                loop {
+                    tokio::select! {
+                        _ = &mut rx_stop => break,
+                        Some(certificate) = rx_output.recv() => {
+                            committed.lock().unwrap().push(certificate);
+                        },
+                        Some(transaction) = rx_transactions.recv() => {
+                            client.send(transactions, Bytes::from(transaction)).await;
+                        }
+                    }
+                }
+            });
+        });
+        Node {
+            tx_stop,
+            thread,
+            tx_transactions,
+        }
+    }
+}
+
+impl Drop for Simulation {
+    fn drop(&mut self) {
+        for node in self.nodes.drain(..).flatten() {
+            node.stop();
+        }
+    }
+}
+
+/// Copies a secret key (they are not `Clone`, to avoid leaving copies of them around).
+fn duplicate(secret: &SecretKey) -> SecretKey {
+    SecretKey::decode_base64(&secret.encode_base64()).expect("Failed to copy secret key")
+}
+
+/// Spawns the primary, worker and consensus of a node, wired as a node runs them. Returns the certificates
+/// committed by the consensus, and the channels the components need to stay open.
+fn spawn_components(
+    keypair: KeyPair,
+    committee: Committee,
+    parameters: Parameters,
+    threshold_keypair: ThresholdKeyPair,
+) -> (
+    Receiver<Certificate>,
+    (watch::Sender<Parameters>, Sender<ShareRequest>),
+) {
+    let name = keypair.name;
+    let (tx_parameters, rx_parameters) = watch::channel(parameters.clone());
+    let (tx_new_certificates, rx_new_certificates) = channel(parameters.channel_capacity);
+    let (tx_feedback, rx_feedback) = channel(parameters.channel_capacity);
+    let (tx_output, rx_output) = channel(parameters.channel_capacity);
+    let store = MemoryStore::new();
+    Primary::spawn(
+        keypair,
+        committee.clone(),
+        parameters.clone(),
+        store.clone(),
+        /* tx_consensus */ tx_new_certificates,
+        /* rx_consensus */ rx_feedback,
+        PeersHealth::default(),
+        &Registry::new(),
+        rx_parameters.clone(),
+        /* tx_equivocations */ None,
+    )
+    .expect("Failed to spawn the primary");
+    Consensus::spawn(
+        committee.clone(),
+        store,
+        parameters.gc_depth,
+        commit_rule(parameters.consensus, &committee),
+        /* rx_primary */ rx_new_certificates,
+        /* tx_primary */ tx_feedback,
+        tx_output,
+        /* tx_sub_dags */ None,
+        ConsensusMetrics::new(&Registry::new(), &parameters.commit_latency_buckets),
+        parameters.max_committed_rounds,
+    );
+
+    let (tx_share_requests, rx_share_requests) = channel(parameters.channel_capacity);
+    Worker::spawn(
+        name,
+        /* id */ 0,
+        ThresholdKeyRing::new(threshold_keypair),
+        committee,
+        parameters,
+        MemoryStore::new(),
+        &Registry::new(),
+        rx_parameters,
+        rx_share_requests,
+        Shutdown::default(),
+    )
+    .expect("Failed to spawn the worker");
+    (rx_output, (tx_parameters, tx_share_requests))
+}
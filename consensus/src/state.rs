@@ -0,0 +1,262 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::CommittedSubDag;
+use config::{Committee, Stake};
+use crypto::Hash as _;
+use crypto::{Digest, PublicKey};
+use log::debug;
+use primary::{Certificate, Round};
+use serde::{Deserialize, Serialize};
+use std::cmp::max;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// The representation of the DAG in memory.
+pub type Dag = HashMap<Round, HashMap<PublicKey, (Digest, Certificate)>>;
+
+/// The part of the `State` persisted after every commit. Upon restart, the DAG is rebuilt from the store
+/// and this watermark tells which of its certificates were already committed.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Watermark {
+    pub last_committed_round: Round,
+    pub last_committed: BTreeMap<PublicKey, Round>,
+    pub sub_dag_index: u64,
+}
+
+/// The state that needs to be persisted for crash-recovery.
+pub struct State {
+    /// The last committed round.
+    pub last_committed_round: Round,
+    // Keeps the last committed round for each authority. This map is used to clean up the dag and
+    // ensure we don't commit twice the same certificate.
+    pub last_committed: HashMap<PublicKey, Round>,
+    /// Keeps the latest committed certificate (and its parents) for every authority. Anything older
+    /// must be regularly cleaned up through the function `update`.
+    pub dag: Dag,
+    /// The index of the next committed sub-dag.
+    pub sub_dag_index: u64,
+    /// The depth of the garbage collector.
+    gc_depth: Round,
+}
+
+impl State {
+    pub fn new(genesis: Vec<Certificate>, gc_depth: Round) -> Self {
+        let genesis = genesis
+            .into_iter()
+            .map(|x| (x.origin(), (x.digest(), x)))
+            .collect::<HashMap<_, _>>();
+
+        Self {
+            last_committed_round: 0,
+            last_committed: genesis.iter().map(|(x, (_, y))| (*x, y.round())).collect(),
+            dag: [(0, genesis)].iter().cloned().collect(),
+            sub_dag_index: 0,
+            gc_depth,
+        }
+    }
+
+    /// The watermark of the commits so far.
+    pub fn watermark(&self) -> Watermark {
+        Watermark {
+            last_committed_round: self.last_committed_round,
+            last_committed: self.last_committed.iter().map(|(x, y)| (*x, *y)).collect(),
+            sub_dag_index: self.sub_dag_index,
+        }
+    }
+
+    /// Resumes from a persisted watermark (before adding the uncommitted certificates to the dag).
+    pub fn restore(&mut self, watermark: Watermark) {
+        self.last_committed_round = watermark.last_committed_round;
+        self.last_committed.extend(watermark.last_committed);
+        self.sub_dag_index = watermark.sub_dag_index;
+    }
+
+    /// Whether a certificate is older than the last committed certificate of its author (the dag no
+    /// longer needs it, and adding it back could commit it twice).
+    pub fn is_obsolete(&self, certificate: &Certificate) -> bool {
+        self.last_committed
+            .get(&certificate.origin())
+            .map_or(false, |r| certificate.round() < *r)
+    }
+
+    /// Adds a new certificate to the dag.
+    pub fn add(&mut self, certificate: Certificate) {
+        self.dag
+            .entry(certificate.round())
+            .or_insert_with(HashMap::new)
+            .insert(certificate.origin(), (certificate.digest(), certificate));
+    }
+
+    /// Update and clean up internal state base on committed certificates.
+    fn update(&mut self, certificate: &Certificate) {
+        self.last_committed
+            .entry(certificate.origin())
+            .and_modify(|r| *r = max(*r, certificate.round()))
+            .or_insert_with(|| certificate.round());
+
+        let last_committed_round = *self.last_committed.values().max().unwrap();
+        self.last_committed_round = last_committed_round;
+
+        let gc_depth = self.gc_depth;
+        for (name, round) in &self.last_committed {
+            self.dag.retain(|r, authorities| {
+                authorities.retain(|n, _| n != name || r >= round);
+                !authorities.is_empty() && r + gc_depth >= last_committed_round
+            });
+        }
+    }
+
+    /// Returns the certificate (and the certificate's digest) of `name` at the specified round (if any).
+    pub fn certificate(&self, name: &PublicKey, round: Round) -> Option<&(Digest, Certificate)> {
+        self.dag.get(&round).map(|x| x.get(name)).flatten()
+    }
+
+    /// Returns the stake of the certificates of round `round` referencing `digest`.
+    pub fn support(&self, digest: &Digest, round: Round, committee: &Committee) -> Stake {
+        self.dag
+            .get(&round)
+            .expect("We should have the whole history by now")
+            .values()
+            .filter(|(_, x)| x.header.parents.contains(digest))
+            .map(|(_, x)| committee.stake(&x.origin()))
+            .sum()
+    }
+
+    /// Commits a leader: we recursively go back to the last committed leader (the leader of each round being
+    /// `elect(round)`), and commit all preceding leaders in the right order. Committing a leader block means
+    /// committing all its dependencies. Returns the sub-dag committed by each leader, oldest first.
+    pub fn commit<F>(&mut self, leader: &Certificate, elect: F) -> Vec<CommittedSubDag>
+    where
+        F: Fn(Round) -> PublicKey,
+    {
+        let mut sequence = Vec::new();
+        for leader in self.order_leaders(leader, elect).into_iter().rev() {
+            // Starting from the oldest leader, flatten the sub-dag referenced by the leader.
+            let certificates = self.order_dag(&leader);
+
+            // Update and clean up internal state.
+            for x in &certificates {
+                self.update(x);
+            }
+            sequence.push(self.sub_dag(leader, certificates));
+        }
+
+        // Log the latest committed round of every authority (for debug).
+        for (name, round) in &self.last_committed {
+            debug!("Latest commit of {}: Round {}", name, round);
+        }
+        sequence
+    }
+
+    /// Assigns the next index to the sub-dag made of `certificates` (ending with its `leader`).
+    pub fn sub_dag(
+        &mut self,
+        leader: Certificate,
+        certificates: Vec<Certificate>,
+    ) -> CommittedSubDag {
+        let sub_dag_index = self.sub_dag_index;
+        self.sub_dag_index += 1;
+        CommittedSubDag {
+            leader,
+            certificates,
+            sub_dag_index,
+        }
+    }
+
+    /// Order the past leaders that we didn't already commit.
+    fn order_leaders<F>(&self, leader: &Certificate, elect: F) -> Vec<Certificate>
+    where
+        F: Fn(Round) -> PublicKey,
+    {
+        let mut to_commit = vec![leader.clone()];
+        let mut leader = leader;
+        for r in (self.last_committed_round + 2..leader.round())
+            .rev()
+            .step_by(2)
+        {
+            // Get the certificate proposed by the previous leader.
+            let (_, prev_leader) = match self.certificate(&elect(r), r) {
+                Some(x) => x,
+                None => continue,
+            };
+
+            // Check whether there is a path between the last two leaders.
+            if self.linked(leader, prev_leader) {
+                to_commit.push(prev_leader.clone());
+                leader = prev_leader;
+            }
+        }
+        to_commit
+    }
+
+    /// Checks if there is a path between two leaders.
+    fn linked(&self, leader: &Certificate, prev_leader: &Certificate) -> bool {
+        let mut parents = vec![leader];
+        for r in (prev_leader.round()..leader.round()).rev() {
+            parents = self
+                .dag
+                .get(&(r))
+                .expect("We should have the whole history by now")
+                .values()
+                .filter(|(digest, _)| parents.iter().any(|x| x.header.parents.contains(digest)))
+                .map(|(_, certificate)| certificate)
+                .collect();
+        }
+        parents.contains(&prev_leader)
+    }
+
+    /// Flatten the dag referenced by the input certificate. This is a classic depth-first search (pre-order):
+    /// https://en.wikipedia.org/wiki/Tree_traversal#Pre-order
+    fn order_dag(&self, leader: &Certificate) -> Vec<Certificate> {
+        debug!("Processing sub-dag of {:?}", leader);
+        let mut ordered = Vec::new();
+        let mut already_ordered = HashSet::new();
+
+        let mut buffer = vec![leader];
+        while let Some(x) = buffer.pop() {
+            debug!("Sequencing {:?}", x);
+            ordered.push(x.clone());
+            for parent in &x.header.parents {
+                let (digest, certificate) = match self
+                    .dag
+                    .get(&(x.round() - 1))
+                    .map(|x| x.values().find(|(x, _)| x == parent))
+                    .flatten()
+                {
+                    Some(x) => x,
+                    None => continue, // We already ordered or GC up to here.
+                };
+
+                // We skip the certificate if we (1) already processed it or (2) we reached a round that we already
+                // committed for this authority.
+                let mut skip = already_ordered.contains(&digest);
+                skip |= self
+                    .last_committed
+                    .get(&certificate.origin())
+                    .map_or_else(|| false, |r| r == &certificate.round());
+                if !skip {
+                    buffer.push(certificate);
+                    already_ordered.insert(digest);
+                }
+            }
+        }
+
+        // Ensure we do not commit garbage collected certificates.
+        ordered.retain(|x| x.round() + self.gc_depth >= self.last_committed_round);
+
+        // Ordering the output by round is not really necessary but it makes the commit sequence prettier.
+        ordered.sort_by_key(|x| x.round());
+        ordered
+    }
+}
+
+/// Elects the leader of a round in a round-robin fashion.
+pub fn round_robin(committee: &Committee, round: Round) -> PublicKey {
+    // Tests always elect the same leader.
+    #[cfg(test)]
+    let coin = 0;
+    #[cfg(not(test))]
+    let coin = round;
+
+    let mut keys: Vec<_> = committee.authorities.keys().cloned().collect();
+    keys.sort();
+    keys[coin as usize % committee.size()]
+}
@@ -1,16 +1,20 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
 use config::{Authority, PrimaryAddresses};
+use crypto::Hash as _;
 use crypto::{
     generate_keypair,
     threshold::{SecretKeySet, SecretKeyShare},
-    SecretKey,
+    Digest, HashKind, PublicKey, SchemeKind, SecretKey,
 };
-use primary::Header;
+use primary::{Header, RoundIndex};
 use rand::rngs::StdRng;
 use rand::SeedableRng as _;
-use std::collections::{BTreeSet, VecDeque};
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::time::{SystemTime, UNIX_EPOCH};
+use store::MemoryStore;
 use tokio::sync::mpsc::channel;
+use tokio::time::sleep;
 
 // Fixture
 fn keys() -> Vec<(PublicKey, SecretKey)> {
@@ -23,6 +27,11 @@ pub fn mock_committee() -> Committee {
     let mut rng = rand::thread_rng();
     let sk_set = SecretKeySet::random(1, &mut rng);
     Committee {
+        threshold_public_key: None,
+        signature_scheme: SchemeKind::selected(),
+        hash_function: HashKind::selected(),
+        durable_acks: None,
+        shard_thresholds: HashMap::new(),
         authorities: keys()
             .iter()
             .enumerate()
@@ -36,6 +45,7 @@ pub fn mock_committee() -> Committee {
                             worker_to_primary: "0.0.0.0:0".parse().unwrap(),
                         },
                         workers: HashMap::default(),
+                        key_rotation: None,
                     },
                 )
             })
@@ -108,10 +118,15 @@ async fn commit_one() {
     let (tx_output, mut rx_output) = channel(1);
     Consensus::spawn(
         mock_committee(),
+        MemoryStore::new(),
         /* gc_depth */ 50,
+        commit_rule(ConsensusProtocol::Tusk, &mock_committee()),
         rx_waiter,
         tx_primary,
         tx_output,
+        /* tx_sub_dags */ None,
+        ConsensusMetrics::default(),
+        /* max_committed_round */ None,
     );
     tokio::spawn(async move { while rx_primary.recv().await.is_some() {} });
 
@@ -153,10 +168,15 @@ async fn dead_node() {
     let (tx_output, mut rx_output) = channel(1);
     Consensus::spawn(
         mock_committee(),
+        MemoryStore::new(),
         /* gc_depth */ 50,
+        commit_rule(ConsensusProtocol::Tusk, &mock_committee()),
         rx_waiter,
         tx_primary,
         tx_output,
+        /* tx_sub_dags */ None,
+        ConsensusMetrics::default(),
+        /* max_committed_round */ None,
     );
     tokio::spawn(async move { while rx_primary.recv().await.is_some() {} });
 
@@ -177,6 +197,52 @@ async fn dead_node() {
     assert_eq!(certificate.round(), 6);
 }
 
+// Run for 9 dag rounds in ideal conditions, stopping once the leader of round 4 is committed: the output
+// then closes, even though the certificates of the next rounds would commit the leader of round 6.
+#[tokio::test]
+async fn stop_at_max_committed_round() {
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let genesis = Certificate::genesis(&mock_committee())
+        .iter()
+        .map(|x| x.digest())
+        .collect::<BTreeSet<_>>();
+    let (mut certificates, _) = make_certificates(1, 9, &genesis, &keys);
+
+    // Spawn the consensus engine and sink the primary channel.
+    let (tx_waiter, rx_waiter) = channel(1);
+    let (tx_primary, mut rx_primary) = channel(1);
+    let (tx_output, mut rx_output) = channel(1);
+    Consensus::spawn(
+        mock_committee(),
+        MemoryStore::new(),
+        /* gc_depth */ 50,
+        commit_rule(ConsensusProtocol::Tusk, &mock_committee()),
+        rx_waiter,
+        tx_primary,
+        tx_output,
+        /* tx_sub_dags */ None,
+        ConsensusMetrics::default(),
+        /* max_committed_round */ Some(4),
+    );
+    tokio::spawn(async move { while rx_primary.recv().await.is_some() {} });
+
+    // Feed all certificates to the consensus: it keeps receiving them after it stopped committing.
+    let feeder = tokio::spawn(async move {
+        while let Some(certificate) = certificates.pop_front() {
+            tx_waiter.send(certificate).await.unwrap();
+        }
+    });
+
+    // We commit the leaders of rounds 2 and 4 (and all their ancestors), then the output closes.
+    let mut committed = Vec::new();
+    while let Some(certificate) = rx_output.recv().await {
+        committed.push(certificate.round());
+    }
+    assert_eq!(committed.len(), 4 * keys.len() - 3);
+    assert_eq!(committed.last(), Some(&4));
+    assert!(feeder.await.is_ok());
+}
+
 // Run for 6 dag rounds. The leaders of round 2 does not have enough support, but the leader of
 // round 4 does. The leader of rounds 2 and 4 should thus be committed upon entering round 6.
 #[tokio::test]
@@ -241,10 +307,15 @@ async fn not_enough_support() {
     let (tx_output, mut rx_output) = channel(1);
     Consensus::spawn(
         mock_committee(),
+        MemoryStore::new(),
         /* gc_depth */ 50,
+        commit_rule(ConsensusProtocol::Tusk, &mock_committee()),
         rx_waiter,
         tx_primary,
         tx_output,
+        /* tx_sub_dags */ None,
+        ConsensusMetrics::default(),
+        /* max_committed_round */ None,
     );
     tokio::spawn(async move { while rx_primary.recv().await.is_some() {} });
 
@@ -304,10 +375,15 @@ async fn missing_leader() {
     let (tx_output, mut rx_output) = channel(1);
     Consensus::spawn(
         mock_committee(),
+        MemoryStore::new(),
         /* gc_depth */ 50,
+        commit_rule(ConsensusProtocol::Tusk, &mock_committee()),
         rx_waiter,
         tx_primary,
         tx_output,
+        /* tx_sub_dags */ None,
+        ConsensusMetrics::default(),
+        /* max_committed_round */ None,
     );
     tokio::spawn(async move { while rx_primary.recv().await.is_some() {} });
 
@@ -333,3 +409,540 @@ async fn missing_leader() {
     let certificate = rx_output.recv().await.unwrap();
     assert_eq!(certificate.round(), 4);
 }
+
+// Runs the consensus with the specified protocol on the certificates and returns the first `count` committed
+// certificates.
+async fn commit_sequence(
+    protocol: ConsensusProtocol,
+    mut certificates: VecDeque<Certificate>,
+    count: usize,
+) -> Vec<Certificate> {
+    let (tx_waiter, rx_waiter) = channel(1);
+    let (tx_primary, mut rx_primary) = channel(1);
+    let (tx_output, mut rx_output) = channel(1);
+    Consensus::spawn(
+        mock_committee(),
+        MemoryStore::new(),
+        /* gc_depth */ 50,
+        commit_rule(protocol, &mock_committee()),
+        rx_waiter,
+        tx_primary,
+        tx_output,
+        /* tx_sub_dags */ None,
+        ConsensusMetrics::default(),
+        /* max_committed_round */ None,
+    );
+    tokio::spawn(async move { while rx_primary.recv().await.is_some() {} });
+    tokio::spawn(async move {
+        while let Some(certificate) = certificates.pop_front() {
+            tx_waiter.send(certificate).await.unwrap();
+        }
+    });
+
+    let mut sequence = Vec::new();
+    for _ in 0..count {
+        sequence.push(rx_output.recv().await.unwrap());
+    }
+    sequence
+}
+
+// Run Tusk and Bullshark on the same 9 dag rounds in ideal conditions. Tusk commits the leaders of rounds
+// 2, 4, and 6 while Bullshark also commits the leader of round 8 (it needs no coin). Both commit the same
+// sequence up to the leader of round 6.
+#[tokio::test]
+async fn tusk_and_bullshark_agree() {
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let genesis = Certificate::genesis(&mock_committee())
+        .iter()
+        .map(|x| x.digest())
+        .collect::<BTreeSet<_>>();
+    let (certificates, _) = make_certificates(1, 9, &genesis, &keys);
+
+    // The leader of round 2 commits 4 certificates of round 1 and itself; every following leader commits
+    // the 3 remaining certificates of the previous round, the 4 certificates of its parent round and itself.
+    let tusk = commit_sequence(ConsensusProtocol::Tusk, certificates.clone(), 21).await;
+    let bullshark = commit_sequence(ConsensusProtocol::Bullshark, certificates, 29).await;
+
+    assert_eq!(tusk.last().unwrap().round(), 6);
+    assert_eq!(bullshark[20].round(), 6);
+    assert_eq!(bullshark.last().unwrap().round(), 8);
+    let digests = |x: &[Certificate]| x.iter().map(|x| x.digest()).collect::<Vec<_>>();
+    assert_eq!(digests(&tusk), digests(&bullshark[..21]));
+}
+
+// A commit rule committing every certificate as soon as the consensus receives it.
+struct CommitAll;
+
+impl CommitRule for CommitAll {
+    fn try_commit(&mut self, certificate: &Certificate, state: &mut State) -> Vec<CommittedSubDag> {
+        vec![state.sub_dag(certificate.clone(), vec![certificate.clone()])]
+    }
+}
+
+// The consensus outputs whatever a custom commit rule decides to commit.
+#[tokio::test]
+async fn custom_commit_rule() {
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let genesis = Certificate::genesis(&mock_committee())
+        .iter()
+        .map(|x| x.digest())
+        .collect::<BTreeSet<_>>();
+    let (certificates, _) = make_certificates(1, 2, &genesis, &keys);
+
+    let (tx_waiter, rx_waiter) = channel(1);
+    let (tx_primary, mut rx_primary) = channel(1);
+    let (tx_output, mut rx_output) = channel(1);
+    Consensus::spawn(
+        mock_committee(),
+        MemoryStore::new(),
+        /* gc_depth */ 50,
+        Box::new(CommitAll),
+        rx_waiter,
+        tx_primary,
+        tx_output,
+        /* tx_sub_dags */ None,
+        ConsensusMetrics::default(),
+        /* max_committed_round */ None,
+    );
+    tokio::spawn(async move { while rx_primary.recv().await.is_some() {} });
+
+    let expected: Vec<_> = certificates.iter().map(|x| x.digest()).collect();
+    tokio::spawn(async move {
+        for certificate in certificates {
+            tx_waiter.send(certificate).await.unwrap();
+        }
+    });
+    for digest in expected {
+        assert_eq!(rx_output.recv().await.unwrap().digest(), digest);
+    }
+}
+
+// Run for 9 dag rounds in ideal conditions. The consensus outputs one sub-dag per committed leader (rounds 2,
+// 4, and 6), and the flat certificate stream is the concatenation of the sub-dags.
+#[tokio::test]
+async fn committed_sub_dags() {
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let genesis = Certificate::genesis(&mock_committee())
+        .iter()
+        .map(|x| x.digest())
+        .collect::<BTreeSet<_>>();
+    let (mut certificates, _) = make_certificates(1, 9, &genesis, &keys);
+
+    let (tx_waiter, rx_waiter) = channel(1);
+    let (tx_primary, mut rx_primary) = channel(1);
+    let (tx_output, mut rx_output) = channel(1);
+    let (tx_sub_dags, mut rx_sub_dags) = channel(1);
+    Consensus::spawn(
+        mock_committee(),
+        MemoryStore::new(),
+        /* gc_depth */ 50,
+        commit_rule(ConsensusProtocol::Tusk, &mock_committee()),
+        rx_waiter,
+        tx_primary,
+        tx_output,
+        Some(tx_sub_dags),
+        ConsensusMetrics::default(),
+        /* max_committed_round */ None,
+    );
+    tokio::spawn(async move { while rx_primary.recv().await.is_some() {} });
+    tokio::spawn(async move {
+        while let Some(certificate) = certificates.pop_front() {
+            tx_waiter.send(certificate).await.unwrap();
+        }
+    });
+
+    for (index, (round, size)) in vec![(2, 5), (4, 8), (6, 8)].into_iter().enumerate() {
+        let mut flat = Vec::new();
+        for _ in 0..size {
+            flat.push(rx_output.recv().await.unwrap().digest());
+        }
+        let sub_dag = rx_sub_dags.recv().await.unwrap();
+        assert_eq!(sub_dag.sub_dag_index, index as u64);
+        assert_eq!(sub_dag.round(), round);
+        assert_eq!(sub_dag.certificates.last(), Some(&sub_dag.leader));
+        let digests: Vec<_> = sub_dag.certificates.iter().map(|x| x.digest()).collect();
+        assert_eq!(digests, flat);
+    }
+}
+
+// Stores the certificates as the primary does before sending them to the consensus.
+async fn store_certificates(store: &MemoryStore, certificates: &VecDeque<Certificate>) {
+    let mut store = store.clone();
+    let mut round_index = RoundIndex::new(store.clone(), &mock_committee());
+    for certificate in certificates {
+        let bytes = bincode::serialize(certificate).unwrap();
+        store.write(certificate.digest().to_vec(), bytes).await;
+        round_index.insert(certificate).await.unwrap();
+    }
+}
+
+// Kill the consensus after the leader of round 2 is committed and restart it on the same store: its output
+// continues with the leaders of rounds 4 and 6, without duplicating nor skipping any certificate.
+#[tokio::test]
+async fn restart_resumes_output() {
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let genesis = Certificate::genesis(&mock_committee())
+        .iter()
+        .map(|x| x.digest())
+        .collect::<BTreeSet<_>>();
+    let (certificates, _) = make_certificates(1, 9, &genesis, &keys);
+    let expected: Vec<_> = commit_sequence(ConsensusProtocol::Tusk, certificates.clone(), 21)
+        .await
+        .iter()
+        .map(|x| x.digest())
+        .collect();
+
+    // Feed the certificates of rounds 1 to 5 and kill the consensus once it output the first sub-dag.
+    let store = MemoryStore::new();
+    let mut first: VecDeque<_> = certificates.iter().take(20).cloned().collect();
+    let second: VecDeque<_> = certificates.iter().skip(20).cloned().collect();
+    store_certificates(&store, &first).await;
+
+    let (tx_waiter, rx_waiter) = channel(100);
+    let (tx_primary, mut rx_primary) = channel(100);
+    let (tx_output, mut rx_output) = channel(100);
+    let handle = Consensus::spawn(
+        mock_committee(),
+        store.clone(),
+        /* gc_depth */ 50,
+        commit_rule(ConsensusProtocol::Tusk, &mock_committee()),
+        rx_waiter,
+        tx_primary,
+        tx_output,
+        /* tx_sub_dags */ None,
+        ConsensusMetrics::default(),
+        /* max_committed_round */ None,
+    );
+    tokio::spawn(async move { while rx_primary.recv().await.is_some() {} });
+    while let Some(certificate) = first.pop_front() {
+        tx_waiter.send(certificate).await.unwrap();
+    }
+    let mut output = Vec::new();
+    for _ in 0..5 {
+        output.push(rx_output.recv().await.unwrap().digest());
+    }
+    handle.abort();
+    assert!(handle.await.is_err());
+    let committed = read_committed(&mut store.clone()).await.unwrap();
+    assert_eq!(
+        committed,
+        Some(CommittedWatermark {
+            sub_dag_index: 0,
+            round: 2
+        })
+    );
+
+    // The primary stored the certificates of the next rounds before the crash but the consensus never
+    // received them: the restarted consensus replays them from the store, and then receives them again.
+    store_certificates(&store, &second).await;
+    let (tx_waiter, rx_waiter) = channel(100);
+    let (tx_primary, mut rx_primary) = channel(100);
+    let (tx_output, mut rx_output) = channel(100);
+    Consensus::spawn(
+        mock_committee(),
+        store.clone(),
+        /* gc_depth */ 50,
+        commit_rule(ConsensusProtocol::Tusk, &mock_committee()),
+        rx_waiter,
+        tx_primary,
+        tx_output,
+        /* tx_sub_dags */ None,
+        ConsensusMetrics::default(),
+        /* max_committed_round */ None,
+    );
+    tokio::spawn(async move { while rx_primary.recv().await.is_some() {} });
+    for certificate in second {
+        tx_waiter.send(certificate).await.unwrap();
+    }
+    for _ in 0..16 {
+        output.push(rx_output.recv().await.unwrap().digest());
+    }
+    assert_eq!(output, expected);
+    let committed = read_committed(&mut store.clone()).await.unwrap();
+    assert_eq!(
+        committed,
+        Some(CommittedWatermark {
+            sub_dag_index: 2,
+            round: 6
+        })
+    );
+
+    // Nothing else is output.
+    assert!(
+        tokio::time::timeout(tokio::time::Duration::from_millis(100), rx_output.recv())
+            .await
+            .is_err()
+    );
+}
+
+// Kill the consensus after it output the first sub-dag but before it persisted its watermarks: the restarted
+// consensus outputs the sub-dag again under the same index, so that a consumer skipping the indices it
+// processed sees every sub-dag exactly once.
+#[tokio::test]
+async fn crash_before_persisting_outputs_again() {
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let genesis = Certificate::genesis(&mock_committee())
+        .iter()
+        .map(|x| x.digest())
+        .collect::<BTreeSet<_>>();
+    let (certificates, _) = make_certificates(1, 9, &genesis, &keys);
+    let store = MemoryStore::new();
+    store_certificates(&store, &certificates).await;
+
+    let spawn = |store: MemoryStore| {
+        let (tx_waiter, rx_waiter) = channel(100);
+        let (tx_primary, mut rx_primary) = channel(100);
+        let (tx_output, mut rx_output) = channel(100);
+        let (tx_sub_dags, rx_sub_dags) = channel(100);
+        let handle = Consensus::spawn(
+            mock_committee(),
+            store,
+            /* gc_depth */ 50,
+            commit_rule(ConsensusProtocol::Tusk, &mock_committee()),
+            rx_waiter,
+            tx_primary,
+            tx_output,
+            Some(tx_sub_dags),
+            ConsensusMetrics::default(),
+            /* max_committed_round */ None,
+        );
+        tokio::spawn(async move { while rx_primary.recv().await.is_some() {} });
+        tokio::spawn(async move { while rx_output.recv().await.is_some() {} });
+        (handle, tx_waiter, rx_sub_dags)
+    };
+
+    // Commit the first sub-dag, then roll the watermarks back as if the consensus crashed right after the
+    // output (the record of the sub-dag was written before it).
+    let (handle, tx_waiter, mut rx_sub_dags) = spawn(store.clone());
+    for certificate in certificates.iter().take(20) {
+        tx_waiter.send(certificate.clone()).await.unwrap();
+    }
+    let first = rx_sub_dags.recv().await.unwrap();
+    assert_eq!(first.sub_dag_index, 0);
+    handle.abort();
+    assert!(handle.await.is_err());
+    let mut rollback = store.clone();
+    let watermarks = vec![
+        LEADER_SCORES_KEY.to_vec(),
+        WATERMARK_KEY.to_vec(),
+        COMMITTED_KEY.to_vec(),
+    ];
+    rollback.delete(watermarks).await;
+
+    // The primary sends its history again to the restarted consensus.
+    let (_handle, tx_waiter, mut rx_sub_dags) = spawn(store.clone());
+    for certificate in certificates {
+        tx_waiter.send(certificate).await.unwrap();
+    }
+    let again = rx_sub_dags.recv().await.unwrap();
+    assert_eq!(again.sub_dag_index, 0);
+    assert_eq!(again.certificates, first.certificates);
+    for index in 1..3 {
+        assert_eq!(rx_sub_dags.recv().await.unwrap().sub_dag_index, index);
+    }
+
+    // The watermarks are persisted once the sub-dags are output.
+    tokio::task::yield_now().await;
+    let committed = read_committed(&mut store.clone()).await.unwrap();
+    assert_eq!(
+        committed,
+        Some(CommittedWatermark {
+            sub_dag_index: 2,
+            round: 6
+        })
+    );
+}
+
+// The latency of every committed certificate is measured from the timestamp of its own header.
+#[tokio::test]
+async fn commit_latency() {
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let genesis = Certificate::genesis(&mock_committee())
+        .iter()
+        .map(|x| x.digest())
+        .collect::<BTreeSet<_>>();
+    let (mut certificates, next_parents) = make_certificates(1, 4, &genesis, &keys);
+    let (_, certificate) = mock_certificate(keys[0], 5, next_parents);
+    certificates.push_back(certificate);
+
+    // The certificates of round 1 were created a second ago, while the leader of round 2 claims to be
+    // created in the future (the digests of the mock certificates do not depend on the timestamps).
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+    for certificate in certificates.iter_mut() {
+        certificate.header.timestamp = match certificate.round() {
+            1 => now - 1_000,
+            _ => now + 3_600_000,
+        };
+    }
+
+    let metrics = ConsensusMetrics::default();
+    let (tx_waiter, rx_waiter) = channel(1);
+    let (tx_primary, mut rx_primary) = channel(1);
+    let (tx_output, mut rx_output) = channel(1);
+    Consensus::spawn(
+        mock_committee(),
+        MemoryStore::new(),
+        /* gc_depth */ 50,
+        commit_rule(ConsensusProtocol::Tusk, &mock_committee()),
+        rx_waiter,
+        tx_primary,
+        tx_output,
+        /* tx_sub_dags */ None,
+        metrics.clone(),
+        /* max_committed_round */ None,
+    );
+    tokio::spawn(async move { while rx_primary.recv().await.is_some() {} });
+    while let Some(certificate) = certificates.pop_front() {
+        tx_waiter.send(certificate).await.unwrap();
+    }
+    for _ in 0..5 {
+        rx_output.recv().await.unwrap();
+    }
+
+    // Four samples of about a second, and a sample of no latency for the leader.
+    let histogram = &metrics.commit_latency;
+    assert_eq!(histogram.get_sample_count(), 5);
+    assert!(histogram.get_sample_sum() >= 4.0);
+    assert!(histogram.get_sample_sum() < 8.0);
+}
+
+// The consensus blocks on a full output channel until the application catches up, and reports it.
+#[tokio::test]
+async fn report_output_stalls() {
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let genesis = Certificate::genesis(&mock_committee())
+        .iter()
+        .map(|x| x.digest())
+        .collect::<BTreeSet<_>>();
+    let (mut certificates, next_parents) = make_certificates(1, 4, &genesis, &keys);
+    let (_, certificate) = mock_certificate(keys[0], 5, next_parents);
+    certificates.push_back(certificate);
+
+    let metrics = ConsensusMetrics::default();
+    let (tx_waiter, rx_waiter) = channel(1);
+    let (tx_primary, mut rx_primary) = channel(1);
+    let (tx_output, mut rx_output) = channel(1);
+    Consensus::spawn(
+        mock_committee(),
+        MemoryStore::new(),
+        /* gc_depth */ 50,
+        commit_rule(ConsensusProtocol::Tusk, &mock_committee()),
+        rx_waiter,
+        tx_primary,
+        tx_output,
+        /* tx_sub_dags */ None,
+        metrics.clone(),
+        /* max_committed_round */ None,
+    );
+    tokio::spawn(async move { while rx_primary.recv().await.is_some() {} });
+    while let Some(certificate) = certificates.pop_front() {
+        tx_waiter.send(certificate).await.unwrap();
+    }
+
+    // The channel holds a single certificate of the committed sub-dag: the consensus waits for us.
+    sleep(Duration::from_millis(200)).await;
+    assert_eq!(metrics.output_blocked.get(), 1);
+    assert_eq!(metrics.output_stalls.get(), 1);
+
+    // Nothing is lost nor reordered once we consume the output.
+    let mut rounds = Vec::new();
+    for _ in 0..5 {
+        rounds.push(rx_output.recv().await.unwrap().round());
+    }
+    assert_eq!(rounds, vec![1, 1, 1, 1, 2]);
+    sleep(Duration::from_millis(50)).await;
+    assert_eq!(metrics.output_blocked.get(), 0);
+    assert!(metrics.output_stalls.get() <= 4);
+    assert!(metrics.output_stall_seconds.get() >= 0.1);
+}
+
+// Replaying a DAG offline commits the same sequence as the consensus.
+#[test]
+fn replay_orders_recorded_certificates() {
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let genesis = Certificate::genesis(&mock_committee())
+        .iter()
+        .map(|x| x.digest())
+        .collect::<BTreeSet<_>>();
+    let (mut certificates, next_parents) = make_certificates(1, 4, &genesis, &keys);
+    let (_, certificate) = mock_certificate(keys[0], 5, next_parents);
+    certificates.push_back(certificate);
+
+    let committee = mock_committee();
+    let rule = commit_rule(ConsensusProtocol::Tusk, &committee);
+    let sequence = replay(&committee, /* gc_depth */ 50, rule, certificates);
+    assert_eq!(sequence.len(), 1);
+    assert_eq!(sequence[0].round(), 2);
+    assert_eq!(sequence[0].sub_dag_index, 0);
+    let rounds: Vec<_> = sequence[0].certificates.iter().map(|x| x.round()).collect();
+    assert_eq!(rounds, vec![1, 1, 1, 1, 2]);
+}
+
+#[test]
+fn committed_watermark_encoding() {
+    let watermark = CommittedWatermark {
+        sub_dag_index: 7,
+        round: 258,
+    };
+    let bytes = watermark.to_bytes();
+    assert_eq!(bytes, vec![0, 0, 0, 0, 0, 0, 0, 7, 0, 0, 0, 0, 0, 0, 1, 2]);
+    assert_eq!(CommittedWatermark::from_bytes(&bytes), Some(watermark));
+    assert_eq!(CommittedWatermark::from_bytes(&bytes[1..]), None);
+}
+
+// The consensus records its sub-dags in the store, from which they can be read again (along with their
+// certificates, stored by the primary).
+#[tokio::test]
+async fn read_recorded_sub_dags() {
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let genesis = Certificate::genesis(&mock_committee())
+        .iter()
+        .map(|x| x.digest())
+        .collect::<BTreeSet<_>>();
+    let (mut certificates, _) = make_certificates(1, 9, &genesis, &keys);
+    let store = MemoryStore::new();
+    store_certificates(&store, &certificates).await;
+
+    let (tx_waiter, rx_waiter) = channel(1);
+    let (tx_primary, mut rx_primary) = channel(1);
+    let (tx_output, mut rx_output) = channel(1);
+    let (tx_sub_dags, mut rx_sub_dags) = channel(1);
+    Consensus::spawn(
+        mock_committee(),
+        store.clone(),
+        /* gc_depth */ 50,
+        commit_rule(ConsensusProtocol::Tusk, &mock_committee()),
+        rx_waiter,
+        tx_primary,
+        tx_output,
+        Some(tx_sub_dags),
+        ConsensusMetrics::default(),
+        /* max_committed_round */ None,
+    );
+    tokio::spawn(async move { while rx_primary.recv().await.is_some() {} });
+    tokio::spawn(async move { while rx_output.recv().await.is_some() {} });
+    tokio::spawn(async move {
+        while let Some(certificate) = certificates.pop_front() {
+            tx_waiter.send(certificate).await.unwrap();
+        }
+    });
+
+    let mut store = store.clone();
+    for index in 0..3 {
+        let sub_dag = rx_sub_dags.recv().await.unwrap();
+        let recorded = read_sub_dag(&mut store, index).await.unwrap().unwrap();
+        assert_eq!(recorded.sub_dag_index, index);
+        assert_eq!(recorded.leader.digest(), sub_dag.leader.digest());
+        let digests: Vec<_> = recorded.certificates.iter().map(|x| x.digest()).collect();
+        let expected: Vec<_> = sub_dag.certificates.iter().map(|x| x.digest()).collect();
+        assert_eq!(digests, expected);
+        assert_eq!(
+            read_sub_dag_round(&mut store, index).await.unwrap(),
+            Some(sub_dag.round())
+        );
+    }
+    assert!(read_sub_dag(&mut store, 3).await.unwrap().is_none());
+}
@@ -0,0 +1,149 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::simulation::Simulation;
+use config::{ConsensusProtocol, KeyPair};
+use crypto::Hash as _;
+use primary::Header;
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr};
+
+fn simulation(seed: u64, scenario: Scenario) -> Simulation {
+    Simulation::new(4, Simulation::parameters(ConsensusProtocol::Tusk), seed)
+        .with_delays(1, 20)
+        .with_scenario(scenario)
+}
+
+/// Fails if an honest node committed two certificates of the same author and round.
+fn check_no_conflicts(simulation: &Simulation) {
+    for node in simulation.honest() {
+        let mut seen = HashSet::new();
+        for certificate in simulation.committed(node) {
+            assert!(seen.insert((certificate.origin(), certificate.round())));
+        }
+    }
+}
+
+#[test]
+fn matches_filters() {
+    let fault = Fault::new(FaultKind::Drop)
+        .from(1)
+        .messages(MessageKind::Certificate)
+        .rounds(5..10);
+    assert!(fault.matches(1, 2, MessageKind::Certificate, Some(5)));
+    assert!(!fault.matches(2, 1, MessageKind::Certificate, Some(5)));
+    assert!(!fault.matches(1, 2, MessageKind::SyncReply, Some(5)));
+    assert!(!fault.matches(1, 2, MessageKind::Certificate, Some(10)));
+    assert!(!fault.matches(1, 2, MessageKind::Certificate, None));
+    assert!(Fault::new(FaultKind::Drop).matches(1, 2, MessageKind::SyncRequest, None));
+}
+
+#[test]
+fn classify_and_equivocate() {
+    let keypair = KeyPair::new();
+    let host = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    let committee = Committee::new(vec![(keypair.name, 1, host)], 10_000, 1).unwrap();
+    let authority = &committee.authorities[&keypair.name];
+    let primary = authority.primary.primary_to_primary;
+    let worker = authority.workers.values().next().unwrap().worker_to_worker;
+    let faults = Faults::new(&committee, Vec::new());
+
+    let mut header = Header {
+        author: keypair.name,
+        round: 3,
+        ..Header::default()
+    };
+    header.id = header.digest();
+    header.signature = Signature::new(&header.id, &keypair.secret);
+    let message = bincode::serialize(&PrimaryMessage::Header(header.clone())).unwrap();
+
+    // Messages are decoded from the address they are sent to.
+    assert_eq!(
+        faults.classify(primary, &message),
+        (MessageKind::Header, Some(3))
+    );
+    assert_eq!(
+        faults.classify(worker, &message),
+        (MessageKind::Other, None)
+    );
+
+    // The equivocation conflicts with the header, and is signed by its author.
+    let equivocation = equivocate(&message, &keypair.secret).unwrap();
+    let equivocation = match bincode::deserialize(&equivocation).unwrap() {
+        PrimaryMessage::Header(x) => x,
+        _ => panic!("Unexpected message"),
+    };
+    assert_eq!(
+        (equivocation.author, equivocation.round),
+        (header.author, header.round)
+    );
+    assert_ne!(equivocation.id, header.id);
+    assert_eq!(equivocation.id, equivocation.digest());
+    assert!(equivocation
+        .signature
+        .verify(&equivocation.id, &keypair.name)
+        .is_ok());
+
+    // Corrupting a message flips its last byte (the signature of a header).
+    let corrupted = corrupt(&message);
+    assert_eq!(corrupted.len(), message.len());
+    assert_ne!(&corrupted[..], &message[..]);
+}
+
+#[test]
+fn safety_despite_equivocation() {
+    let scenario = Scenario::new().at(0, Event::Equivocate(3));
+    let simulation = simulation(11, scenario);
+    assert!(simulation.run_until_committed(20, Duration::from_secs(60)));
+    assert_eq!(simulation.honest(), vec![0, 1, 2]);
+    assert!(simulation.altered() > 0);
+    check_no_conflicts(&simulation);
+    assert!(simulation.check_agreement().unwrap() >= 20);
+}
+
+#[test]
+fn liveness_resumes_after_faults_clear() {
+    // Tests always elect node 0: nothing commits while its messages are lost.
+    let scenario = Scenario::new()
+        .at(0, Event::Inject(Fault::new(FaultKind::Drop).from(0)))
+        .at(1_500, Event::ClearFaults);
+    let simulation = simulation(12, scenario);
+    assert!(simulation.run_until(|x| x.now() >= 1_200, Duration::from_secs(5)));
+    assert!(simulation.committed(1).is_empty());
+
+    // The others fetch the missing certificates of node 0 once its messages go through again.
+    assert!(simulation.run_until_committed(10, Duration::from_secs(60)));
+    simulation.check_agreement().unwrap();
+}
+
+#[test]
+fn corrupted_messages_are_rejected() {
+    let corrupt = Fault::new(FaultKind::Corrupt)
+        .from(1)
+        .to(2)
+        .messages(MessageKind::Certificate)
+        .percent(50);
+    let scenario = Scenario::new().at(0, Event::Inject(corrupt));
+    let simulation = simulation(13, scenario);
+    assert!(simulation.run_until_committed(20, Duration::from_secs(60)));
+    assert!(simulation.altered() > 0);
+    check_no_conflicts(&simulation);
+    assert!(simulation.check_agreement().unwrap() >= 20);
+}
+
+#[test]
+fn duplicated_and_delayed_messages_are_harmless() {
+    let scenario = Scenario::new()
+        .at(0, Event::Inject(Fault::new(FaultKind::Duplicate)))
+        .at(
+            0,
+            Event::Inject(Fault::new(FaultKind::Delay(15)).percent(30)),
+        )
+        .at(500, Event::Partition(vec![0, 1], 1_000))
+        .at(2_000, Event::Crash(3));
+    let simulation = simulation(14, scenario);
+    assert!(simulation.run_until(|x| x.now() >= 2_000, Duration::from_secs(5)));
+    assert!(simulation.run_until_committed(30, Duration::from_secs(60)));
+    assert_eq!(simulation.honest(), vec![0, 1, 2]);
+    check_no_conflicts(&simulation);
+    assert!(simulation.check_agreement().unwrap() >= 30);
+}
@@ -0,0 +1,104 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::consensus_tests::mock_committee;
+use crate::{commit_rule, replay, round_robin};
+use config::ConsensusProtocol;
+use crypto::Hash as _;
+use primary::{Certificate, Header};
+use std::collections::BTreeSet;
+
+fn sub_dag(author: PublicKey, round: Round, sub_dag_index: u64) -> CommittedSubDag {
+    let leader = Certificate {
+        header: Header {
+            author,
+            round,
+            ..Header::default()
+        },
+        ..Certificate::default()
+    };
+    CommittedSubDag {
+        leader: leader.clone(),
+        certificates: vec![leader],
+        sub_dag_index,
+    }
+}
+
+#[test]
+fn score_committed_sequence() {
+    let committee = mock_committee();
+    let keys: Vec<_> = committee.authorities.keys().cloned().collect();
+    let elect = |round: Round| keys[(round / 2) as usize % keys.len()];
+
+    // The leaders of rounds 2, 8 and 10 are committed: those of rounds 4 and 6 were skipped. The second
+    // authority leads both rounds 2 and 10.
+    let mut scores = LeaderScores::default();
+    for (index, round) in [2, 8, 10].iter().enumerate() {
+        scores.update(&sub_dag(elect(*round), *round, index as u64), elect);
+    }
+    assert_eq!(scores.last_leader_round, 10);
+    let expected = [(1, 0), (2, 0), (0, 1), (0, 1)];
+    for (i, (commits, skips)) in expected.iter().enumerate() {
+        let score = LeaderScore {
+            commits: *commits,
+            skips: *skips,
+        };
+        assert_eq!(scores.score(&keys[i]), score, "authority {}", i);
+    }
+
+    // Replaying a sub-dag that was already accounted for changes nothing.
+    let before = scores.clone();
+    scores.update(&sub_dag(elect(8), 8, 1), elect);
+    assert_eq!(scores, before);
+
+    let listed = scores.committee_scores(&committee);
+    assert_eq!(listed.len(), committee.size());
+    assert!(listed
+        .iter()
+        .all(|(name, score)| *score == scores.score(name)));
+}
+
+#[test]
+fn score_replayed_dag() {
+    // Tests elect the first authority at every round: remove its certificates of rounds 1 and 2 so that
+    // the leader of round 2 is missing, and commit the leader of round 4 instead.
+    let committee = mock_committee();
+    let keys: Vec<_> = committee.authorities.keys().cloned().collect();
+    let mut parents = Certificate::genesis(&committee)
+        .iter()
+        .map(|x| x.digest())
+        .collect::<BTreeSet<_>>();
+    let mut certificates = Vec::new();
+    for round in 1..=7 {
+        let authors = if round <= 2 { &keys[1..] } else { &keys[..] };
+        let next: Vec<_> = authors
+            .iter()
+            .map(|author| Certificate {
+                header: Header {
+                    author: *author,
+                    round,
+                    parents: parents.clone(),
+                    ..Header::default()
+                },
+                ..Certificate::default()
+            })
+            .collect();
+        parents = next.iter().map(|x| x.digest()).collect();
+        certificates.extend(next);
+    }
+
+    let rule = commit_rule(ConsensusProtocol::Tusk, &committee);
+    let sequence = replay(&committee, /* gc_depth */ 50, rule, certificates);
+    let mut scores = LeaderScores::default();
+    for sub_dag in &sequence {
+        scores.update(sub_dag, |round| round_robin(&committee, round));
+    }
+    assert_eq!(scores.last_leader_round, 4);
+    assert_eq!(
+        scores.score(&keys[0]),
+        LeaderScore {
+            commits: 1,
+            skips: 1
+        }
+    );
+    assert_eq!(scores.score(&keys[1]), LeaderScore::default());
+}
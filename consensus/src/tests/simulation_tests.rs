@@ -0,0 +1,78 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+
+fn simulation(protocol: ConsensusProtocol, seed: u64) -> Simulation {
+    Simulation::new(4, Simulation::parameters(protocol), seed).with_delays(1, 20)
+}
+
+#[test]
+fn honest_nodes_agree() {
+    for protocol in &[ConsensusProtocol::Tusk, ConsensusProtocol::Bullshark] {
+        let simulation = simulation(*protocol, 1);
+        assert!(simulation.run_until_committed(20, Duration::from_secs(30)));
+        assert!(simulation.check_agreement().unwrap() >= 20);
+    }
+}
+
+#[test]
+fn commit_submitted_transactions() {
+    let simulation = simulation(ConsensusProtocol::Tusk, 2);
+    assert!(simulation.run_until_committed(1, Duration::from_secs(30)));
+    for i in 0..10u8 {
+        simulation.submit(0, &[i; 100]);
+    }
+
+    // The batch of worker 0 ends up in the payload of a certificate committed by everyone.
+    let batches = |simulation: &Simulation, node: NodeId| -> usize {
+        simulation
+            .committed(node)
+            .iter()
+            .map(|x| x.header.payload.len())
+            .sum()
+    };
+    let committed = |simulation: &Simulation| {
+        simulation
+            .honest()
+            .iter()
+            .all(|x| batches(simulation, *x) > 0)
+    };
+    assert!(simulation.run_until(committed, Duration::from_secs(30)));
+    simulation.check_agreement().unwrap();
+}
+
+#[test]
+fn agree_after_partition_heals() {
+    let mut simulation = simulation(ConsensusProtocol::Tusk, 7);
+    simulation.partition(&[3], 3_000);
+
+    // The other nodes still hold a quorum: they commit while node 3 is cut.
+    assert!(simulation.run_until(|x| x.committed(0).len() >= 10, Duration::from_millis(2_500)));
+    assert!(simulation.committed(3).is_empty());
+    simulation.check_agreement().unwrap();
+
+    // Node 3 catches up once the partition heals, and commits the same sequence.
+    let target = simulation.committed(0).len() + 10;
+    assert!(simulation.run_until_committed(target, Duration::from_secs(60)));
+    simulation.check_agreement().unwrap();
+}
+
+#[test]
+fn agree_despite_crash() {
+    let mut simulation = simulation(ConsensusProtocol::Tusk, 3);
+    simulation.crash(3);
+    simulation.slow_link(1, 2, 30);
+    assert!(simulation.run_until_committed(20, Duration::from_secs(30)));
+    assert_eq!(simulation.honest(), vec![0, 1, 2]);
+    assert!(simulation.check_agreement().unwrap() >= 20);
+}
+
+#[test]
+fn deterministic_rng() {
+    let mut first = SimRng::new(5);
+    let mut second = SimRng::new(5);
+    for _ in 0..100 {
+        let x = first.range(3, 9);
+        assert!((3..=9).contains(&x));
+        assert_eq!(x, second.range(3, 9));
+    }
+}
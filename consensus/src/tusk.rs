@@ -0,0 +1,60 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::state::{round_robin, State};
+use crate::{CommitRule, CommittedSubDag};
+use config::Committee;
+use log::debug;
+use primary::Certificate;
+
+/// The Tusk commit rule: once we have 2f+1 certificates of an odd round r (revealing the coin), elect the
+/// leader of round r-2 and commit it if it has f+1 support from round r-1.
+pub struct Tusk {
+    /// The committee information.
+    committee: Committee,
+}
+
+impl Tusk {
+    pub fn new(committee: Committee) -> Self {
+        Self { committee }
+    }
+}
+
+impl CommitRule for Tusk {
+    fn try_commit(&mut self, certificate: &Certificate, state: &mut State) -> Vec<CommittedSubDag> {
+        // Try to order the dag to commit. Start from the highest round for which we have at least
+        // 2f+1 certificates. This is because we need them to reveal the common coin.
+        let r = certificate.round() - 1;
+
+        // We only elect leaders for even round numbers.
+        if r % 2 != 0 || r < 4 {
+            return Vec::new();
+        }
+
+        // Get the certificate's digest of the leader of round r-2. If we already ordered this leader,
+        // there is nothing to do.
+        let leader_round = r - 2;
+        if leader_round <= state.last_committed_round {
+            return Vec::new();
+        }
+        // TODO: We should elect the leader of round r-2 using the common coin revealed at round r.
+        // At this stage, we are guaranteed to have 2f+1 certificates from round r (which is enough to
+        // compute the coin). We currently just use round-robin.
+        let (leader_digest, leader) =
+            match state.certificate(&round_robin(&self.committee, leader_round), leader_round) {
+                Some(x) => x,
+                None => return Vec::new(),
+            };
+
+        // Check if the leader has f+1 support from its children (ie. round r-1).
+        let stake = state.support(leader_digest, r - 1, &self.committee);
+
+        // If it is the case, we can commit the leader.
+        if stake < self.committee.validity_threshold() {
+            debug!("Leader {:?} does not have enough support", leader);
+            return Vec::new();
+        }
+        debug!("Leader {:?} has enough support", leader);
+        let leader = leader.clone();
+        let committee = &self.committee;
+        state.commit(&leader, |round| round_robin(committee, round))
+    }
+}
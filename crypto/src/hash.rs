@@ -0,0 +1,100 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::Digest;
+use ed25519_dalek::Digest as _;
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto as _;
+use std::fmt;
+
+/// The hash function selected at compile time (through the `blake3` feature).
+#[cfg(not(feature = "blake3"))]
+pub type Hasher = Sha512;
+#[cfg(feature = "blake3")]
+pub type Hasher = Blake3;
+
+/// Identifies a hash function in the configuration files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashKind {
+    Sha512,
+    Blake3,
+}
+
+impl HashKind {
+    /// The hash function this binary computes the digests with.
+    pub fn selected() -> Self {
+        <Hasher as HashFunction>::KIND
+    }
+}
+
+impl Default for HashKind {
+    // Configuration files written before the hash function was selectable use SHA-512.
+    fn default() -> Self {
+        Self::Sha512
+    }
+}
+
+impl fmt::Display for HashKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Sha512 => write!(f, "sha512"),
+            Self::Blake3 => write!(f, "blake3"),
+        }
+    }
+}
+
+/// An incremental hash function producing the 32-byte digests of the batches, headers, votes and
+/// certificates.
+pub trait HashFunction: Default {
+    const KIND: HashKind;
+
+    fn update<T: AsRef<[u8]>>(&mut self, data: T);
+
+    /// Returns the digest of the data fed so far.
+    fn finalize(self) -> Digest;
+
+    /// Hashes `data` at once.
+    fn digest<T: AsRef<[u8]>>(data: T) -> Digest {
+        let mut hasher = Self::default();
+        hasher.update(data);
+        hasher.finalize()
+    }
+}
+
+/// SHA-512, truncated to its first 32 bytes.
+#[derive(Default)]
+pub struct Sha512(ed25519_dalek::Sha512);
+
+impl HashFunction for Sha512 {
+    const KIND: HashKind = HashKind::Sha512;
+
+    fn update<T: AsRef<[u8]>>(&mut self, data: T) {
+        self.0.update(data);
+    }
+
+    fn finalize(self) -> Digest {
+        Digest(self.0.finalize().as_slice()[..32].try_into().unwrap())
+    }
+}
+
+/// BLAKE3, with its default 32-byte output.
+#[cfg(feature = "blake3")]
+#[derive(Default)]
+pub struct Blake3(blake3::Hasher);
+
+#[cfg(feature = "blake3")]
+impl HashFunction for Blake3 {
+    const KIND: HashKind = HashKind::Blake3;
+
+    fn update<T: AsRef<[u8]>>(&mut self, data: T) {
+        self.0.update(data.as_ref());
+    }
+
+    fn finalize(self) -> Digest {
+        Digest(*self.0.finalize().as_bytes())
+    }
+}
+
+/// Hashes `data` with the selected hash function.
+pub fn hash<T: AsRef<[u8]>>(data: T) -> Digest {
+    Hasher::digest(data)
+}
@@ -1,7 +1,5 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
-use ed25519_dalek as dalek;
 use ed25519_dalek::ed25519;
-use ed25519_dalek::Signer as _;
 use rand::rngs::OsRng;
 use rand::{CryptoRng, RngCore};
 use rayon::prelude::IntoParallelRefIterator;
@@ -14,16 +12,31 @@ use threshold_crypto::serde_impl::SerdeSecret;
 use threshold_crypto::Ciphertext;
 use threshold_crypto::DecryptionShare;
 use threshold_crypto::SecretKeyShare;
-use tokio::sync::mpsc::{channel, Sender};
+use tokio::sync::mpsc::{channel, Receiver, Sender};
 use tokio::sync::oneshot;
 
 #[cfg(test)]
 #[path = "tests/crypto_tests.rs"]
 pub mod crypto_tests;
+mod hash;
+mod scheme;
 pub mod threshold;
 
+#[cfg(feature = "blake3")]
+pub use hash::Blake3;
+pub use hash::{hash, HashFunction, HashKind, Hasher, Sha512};
+pub use scheme::{Bls, Ed25519, Scheme, SchemeKind, SignatureScheme};
+
 pub type CryptoError = ed25519::Error;
 
+/// The length (in bytes) of the keys and signatures of the selected signature scheme.
+pub const PUBLIC_KEY_LENGTH: usize = <Scheme as SignatureScheme>::PUBLIC_KEY_LENGTH;
+pub const SECRET_KEY_LENGTH: usize = <Scheme as SignatureScheme>::SECRET_KEY_LENGTH;
+pub const SIGNATURE_LENGTH: usize = <Scheme as SignatureScheme>::SIGNATURE_LENGTH;
+
+/// Signatures are serialized as 32-byte chunks (serde only supports arrays of up to 32 elements).
+const SIGNATURE_PARTS: usize = SIGNATURE_LENGTH / 32;
+
 /// Represents a hash digest (32 bytes).
 #[derive(Hash, PartialEq, Default, Eq, Clone, Deserialize, Serialize, Ord, PartialOrd)]
 pub struct Digest(pub [u8; 32]);
@@ -69,8 +82,8 @@ pub trait Hash {
 }
 
 /// Represents a public key (in bytes).
-#[derive(Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd, Default)]
-pub struct PublicKey(pub [u8; 32]);
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct PublicKey(pub [u8; PUBLIC_KEY_LENGTH]);
 
 impl PublicKey {
     pub fn encode_base64(&self) -> String {
@@ -79,13 +92,20 @@ impl PublicKey {
 
     pub fn decode_base64(s: &str) -> Result<Self, base64::DecodeError> {
         let bytes = base64::decode(s)?;
-        let array = bytes[..32]
-            .try_into()
-            .map_err(|_| base64::DecodeError::InvalidLength)?;
+        let array = bytes
+            .get(..PUBLIC_KEY_LENGTH)
+            .and_then(|x| x.try_into().ok())
+            .ok_or(base64::DecodeError::InvalidLength)?;
         Ok(Self(array))
     }
 }
 
+impl Default for PublicKey {
+    fn default() -> Self {
+        Self([0; PUBLIC_KEY_LENGTH])
+    }
+}
+
 impl fmt::Debug for PublicKey {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         write!(f, "{}", self.encode_base64())
@@ -125,18 +145,28 @@ impl AsRef<[u8]> for PublicKey {
 }
 
 /// Represents a secret key (in bytes).
-pub struct SecretKey([u8; 64]);
+pub struct SecretKey([u8; SECRET_KEY_LENGTH]);
 
 impl SecretKey {
     pub fn encode_base64(&self) -> String {
         base64::encode(&self.0[..])
     }
 
+    /// The seed of an ed25519 secret key (from which the key pair of the TLS certificate of the node is
+    /// derived), or `None` if the node signs with another scheme.
+    pub fn ed25519_seed(&self) -> Option<&[u8]> {
+        match SchemeKind::selected() {
+            SchemeKind::Ed25519 => Some(&self.0[..32]),
+            SchemeKind::Bls => None,
+        }
+    }
+
     pub fn decode_base64(s: &str) -> Result<Self, base64::DecodeError> {
         let bytes = base64::decode(s)?;
-        let array = bytes[..64]
-            .try_into()
-            .map_err(|_| base64::DecodeError::InvalidLength)?;
+        let array = bytes
+            .get(..SECRET_KEY_LENGTH)
+            .and_then(|x| x.try_into().ok())
+            .ok_or(base64::DecodeError::InvalidLength)?;
         Ok(Self(array))
     }
 }
@@ -175,54 +205,65 @@ pub fn generate_keypair<R>(csprng: &mut R) -> (PublicKey, SecretKey)
 where
     R: CryptoRng + RngCore,
 {
-    let keypair = dalek::Keypair::generate(csprng);
-    let public = PublicKey(keypair.public.to_bytes());
-    let secret = SecretKey(keypair.to_bytes());
+    let (public, secret) = Scheme::generate(csprng);
+    let public = PublicKey(public[..].try_into().expect("Unexpected public key length"));
+    let secret = SecretKey(secret[..].try_into().expect("Unexpected secret key length"));
     (public, secret)
 }
 
-/// Represents an ed25519 signature.
+/// Represents a signature of the selected signature scheme.
 #[derive(Serialize, Deserialize, Clone, Default, Debug)]
 pub struct Signature {
-    part1: [u8; 32],
-    part2: [u8; 32],
+    parts: [[u8; 32]; SIGNATURE_PARTS],
 }
 
 impl Signature {
     pub fn new(digest: &Digest, secret: &SecretKey) -> Self {
-        let keypair = dalek::Keypair::from_bytes(&secret.0).expect("Unable to load secret key");
-        let sig = keypair.sign(&digest.0).to_bytes();
-        let part1 = sig[..32].try_into().expect("Unexpected signature length");
-        let part2 = sig[32..64].try_into().expect("Unexpected signature length");
-        Signature { part1, part2 }
+        let sig = Scheme::sign(&secret.0, &digest.0);
+        let mut parts = [[0; 32]; SIGNATURE_PARTS];
+        for (part, chunk) in parts.iter_mut().zip(sig.chunks(32)) {
+            *part = chunk.try_into().expect("Unexpected signature length");
+        }
+        Signature { parts }
     }
 
-    fn flatten(&self) -> [u8; 64] {
-        [self.part1, self.part2]
-            .concat()
-            .try_into()
-            .expect("Unexpected signature length")
+    fn flatten(&self) -> Vec<u8> {
+        self.parts.concat()
+    }
+
+    pub fn encode_base64(&self) -> String {
+        base64::encode(&self.flatten())
+    }
+
+    pub fn decode_base64(s: &str) -> Result<Self, base64::DecodeError> {
+        let bytes = base64::decode(s)?;
+        if bytes.len() != SIGNATURE_LENGTH {
+            return Err(base64::DecodeError::InvalidLength);
+        }
+        let mut parts = [[0; 32]; SIGNATURE_PARTS];
+        for (part, chunk) in parts.iter_mut().zip(bytes.chunks(32)) {
+            *part = chunk.try_into().expect("Unexpected signature length");
+        }
+        Ok(Signature { parts })
     }
 
     pub fn verify(&self, digest: &Digest, public_key: &PublicKey) -> Result<(), CryptoError> {
-        let signature = ed25519::signature::Signature::from_bytes(&self.flatten())?;
-        let key = dalek::PublicKey::from_bytes(&public_key.0)?;
-        key.verify_strict(&digest.0, &signature)
+        Scheme::verify(&public_key.0, &digest.0, &self.flatten())
     }
 
     pub fn verify_batch<'a, I>(digest: &Digest, votes: I) -> Result<(), CryptoError>
     where
         I: IntoIterator<Item = &'a (PublicKey, Signature)>,
     {
-        let mut messages: Vec<&[u8]> = Vec::new();
-        let mut signatures: Vec<dalek::Signature> = Vec::new();
-        let mut keys: Vec<dalek::PublicKey> = Vec::new();
-        for (key, sig) in votes.into_iter() {
-            messages.push(&digest.0[..]);
-            signatures.push(ed25519::signature::Signature::from_bytes(&sig.flatten())?);
-            keys.push(dalek::PublicKey::from_bytes(&key.0)?);
-        }
-        dalek::verify_batch(&messages[..], &signatures[..], &keys[..])
+        let flattened: Vec<_> = votes
+            .into_iter()
+            .map(|(key, sig)| (&key.0[..], sig.flatten()))
+            .collect();
+        let votes: Vec<_> = flattened
+            .iter()
+            .map(|(key, sig)| (*key, &sig[..]))
+            .collect();
+        Scheme::verify_batch(&digest.0, &votes)
     }
 }
 
@@ -248,6 +289,28 @@ impl SignatureService {
         Self { channel: tx }
     }
 
+    /// Spawns a service signing with `secret` until it receives a new secret key on `rx_secret` (when the
+    /// node rotates its key), and with the new key afterwards.
+    pub fn spawn_with_rotation(secret: SecretKey, mut rx_secret: Receiver<SecretKey>) -> Self {
+        let (tx, mut rx): (Sender<(_, oneshot::Sender<_>)>, _) = channel(100);
+        tokio::spawn(async move {
+            let mut secret = secret;
+            loop {
+                tokio::select! {
+                    Some(next) = rx_secret.recv() => secret = next,
+                    request = rx.recv() => match request {
+                        Some((digest, sender)) => {
+                            let signature = Signature::new(&digest, &secret);
+                            let _ = sender.send(signature);
+                        }
+                        None => break,
+                    },
+                }
+            }
+        });
+        Self { channel: tx }
+    }
+
     pub async fn request_signature(&mut self, digest: Digest) -> Signature {
         let (sender, receiver): (oneshot::Sender<_>, oneshot::Receiver<_>) = oneshot::channel();
         if let Err(e) = self.channel.send((digest, sender)).await {
@@ -0,0 +1,161 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::CryptoError;
+use ed25519_dalek as dalek;
+use ed25519_dalek::ed25519;
+use ed25519_dalek::Signer as _;
+use rand::rngs::StdRng;
+use rand::{CryptoRng, RngCore, SeedableRng as _};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto as _;
+use std::fmt;
+
+/// The signature scheme selected at compile time (through the `bls` feature).
+#[cfg(not(feature = "bls"))]
+pub type Scheme = Ed25519;
+#[cfg(feature = "bls")]
+pub type Scheme = Bls;
+
+/// Identifies a signature scheme in the configuration files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SchemeKind {
+    Ed25519,
+    Bls,
+}
+
+impl SchemeKind {
+    /// The scheme this binary signs and verifies with.
+    pub fn selected() -> Self {
+        <Scheme as SignatureScheme>::KIND
+    }
+}
+
+impl Default for SchemeKind {
+    // Configuration files written before the scheme was selectable use ed25519.
+    fn default() -> Self {
+        Self::Ed25519
+    }
+}
+
+impl fmt::Display for SchemeKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Ed25519 => write!(f, "ed25519"),
+            Self::Bls => write!(f, "bls"),
+        }
+    }
+}
+
+/// A signature scheme over raw bytes. `PublicKey`, `SecretKey` and `Signature` hold the encoding of the
+/// keys and signatures of the selected `Scheme`.
+pub trait SignatureScheme {
+    const KIND: SchemeKind;
+    const PUBLIC_KEY_LENGTH: usize;
+    const SECRET_KEY_LENGTH: usize;
+    const SIGNATURE_LENGTH: usize;
+
+    /// Returns the encoding of a fresh (public, secret) keypair.
+    fn generate<R: CryptoRng + RngCore>(csprng: &mut R) -> (Vec<u8>, Vec<u8>);
+
+    fn sign(secret: &[u8], message: &[u8]) -> Vec<u8>;
+
+    fn verify(public: &[u8], message: &[u8], signature: &[u8]) -> Result<(), CryptoError>;
+
+    /// Verifies many (public key, signature) pairs over the same message.
+    fn verify_batch(message: &[u8], votes: &[(&[u8], &[u8])]) -> Result<(), CryptoError>;
+}
+
+pub struct Ed25519;
+
+impl SignatureScheme for Ed25519 {
+    const KIND: SchemeKind = SchemeKind::Ed25519;
+    const PUBLIC_KEY_LENGTH: usize = dalek::PUBLIC_KEY_LENGTH;
+    const SECRET_KEY_LENGTH: usize = dalek::KEYPAIR_LENGTH;
+    const SIGNATURE_LENGTH: usize = dalek::SIGNATURE_LENGTH;
+
+    fn generate<R: CryptoRng + RngCore>(csprng: &mut R) -> (Vec<u8>, Vec<u8>) {
+        let keypair = dalek::Keypair::generate(csprng);
+        (
+            keypair.public.to_bytes().to_vec(),
+            keypair.to_bytes().to_vec(),
+        )
+    }
+
+    fn sign(secret: &[u8], message: &[u8]) -> Vec<u8> {
+        let keypair = dalek::Keypair::from_bytes(secret).expect("Unable to load secret key");
+        keypair.sign(message).to_bytes().to_vec()
+    }
+
+    fn verify(public: &[u8], message: &[u8], signature: &[u8]) -> Result<(), CryptoError> {
+        let signature = ed25519::signature::Signature::from_bytes(signature)?;
+        let key = dalek::PublicKey::from_bytes(public)?;
+        key.verify_strict(message, &signature)
+    }
+
+    fn verify_batch(message: &[u8], votes: &[(&[u8], &[u8])]) -> Result<(), CryptoError> {
+        let mut messages: Vec<&[u8]> = Vec::new();
+        let mut signatures: Vec<dalek::Signature> = Vec::new();
+        let mut keys: Vec<dalek::PublicKey> = Vec::new();
+        for (key, sig) in votes {
+            messages.push(message);
+            signatures.push(ed25519::signature::Signature::from_bytes(sig)?);
+            keys.push(dalek::PublicKey::from_bytes(key)?);
+        }
+        dalek::verify_batch(&messages[..], &signatures[..], &keys[..])
+    }
+}
+
+/// BLS signatures over BLS12-381 (through threshold_crypto). The secret key is stored as the seed from
+/// which the scalar is derived, so that it has a fixed-size encoding.
+pub struct Bls;
+
+impl Bls {
+    fn secret_key(seed: &[u8]) -> threshold_crypto::SecretKey {
+        let seed: [u8; 32] = seed.try_into().expect("Unable to load secret key");
+        let mut rng = StdRng::from_seed(seed);
+        let mut scalar = threshold_crypto::poly::Poly::random(0, &mut rng).evaluate(0usize);
+        threshold_crypto::SecretKey::from_mut(&mut scalar)
+    }
+}
+
+impl SignatureScheme for Bls {
+    const KIND: SchemeKind = SchemeKind::Bls;
+    const PUBLIC_KEY_LENGTH: usize = threshold_crypto::PK_SIZE;
+    const SECRET_KEY_LENGTH: usize = 32;
+    const SIGNATURE_LENGTH: usize = threshold_crypto::SIG_SIZE;
+
+    fn generate<R: CryptoRng + RngCore>(csprng: &mut R) -> (Vec<u8>, Vec<u8>) {
+        let mut seed = vec![0u8; Self::SECRET_KEY_LENGTH];
+        csprng.fill_bytes(&mut seed);
+        let public = Self::secret_key(&seed).public_key().to_bytes().to_vec();
+        (public, seed)
+    }
+
+    fn sign(secret: &[u8], message: &[u8]) -> Vec<u8> {
+        Self::secret_key(secret).sign(message).to_bytes().to_vec()
+    }
+
+    fn verify(public: &[u8], message: &[u8], signature: &[u8]) -> Result<(), CryptoError> {
+        let public: [u8; threshold_crypto::PK_SIZE] =
+            public.try_into().map_err(|_| CryptoError::new())?;
+        let signature: [u8; threshold_crypto::SIG_SIZE] =
+            signature.try_into().map_err(|_| CryptoError::new())?;
+        let key =
+            threshold_crypto::PublicKey::from_bytes(public).map_err(|_| CryptoError::new())?;
+        let signature =
+            threshold_crypto::Signature::from_bytes(signature).map_err(|_| CryptoError::new())?;
+        if !key.verify(&signature, message) {
+            return Err(CryptoError::new());
+        }
+        Ok(())
+    }
+
+    // The votes sign the same message but threshold_crypto does not expose signature aggregation, so we
+    // verify them independently (in parallel).
+    fn verify_batch(message: &[u8], votes: &[(&[u8], &[u8])]) -> Result<(), CryptoError> {
+        votes
+            .par_iter()
+            .try_for_each(|(key, sig)| Self::verify(key, message, sig))
+    }
+}
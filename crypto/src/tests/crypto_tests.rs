@@ -1,13 +1,11 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
-use ed25519_dalek::Digest as _;
-use ed25519_dalek::Sha512;
 use rand::rngs::StdRng;
 use rand::SeedableRng as _;
 
 impl Hash for &[u8] {
     fn digest(&self) -> Digest {
-        Digest(Sha512::digest(self).as_slice()[..32].try_into().unwrap())
+        hash(self)
     }
 }
 
@@ -130,3 +128,47 @@ async fn signature_service() {
     // Verify the signature we received.
     assert!(signature.verify(&digest, &public_key).is_ok());
 }
+
+#[tokio::test]
+async fn signature_service_rotation() {
+    let (old_public_key, old_secret_key) = keys().pop().unwrap();
+    let (new_public_key, new_secret_key) = keys().remove(0);
+    let (tx_secret, rx_secret) = channel(1);
+    let mut service = SignatureService::spawn_with_rotation(old_secret_key, rx_secret);
+
+    // The service signs with the old key until it receives the new one.
+    let message: &[u8] = b"Hello, world!";
+    let digest = message.digest();
+    let signature = service.request_signature(digest.clone()).await;
+    assert!(signature.verify(&digest, &old_public_key).is_ok());
+
+    tx_secret.send(new_secret_key).await.unwrap();
+    tokio::task::yield_now().await;
+    let signature = service.request_signature(digest.clone()).await;
+    assert!(signature.verify(&digest, &new_public_key).is_ok());
+}
+
+#[test]
+fn bls_signatures() {
+    // Test the BLS scheme directly, whichever scheme is selected.
+    let mut rng = StdRng::from_seed([0; 32]);
+    let keys: Vec<_> = (0..3).map(|_| Bls::generate(&mut rng)).collect();
+    let message = b"Hello, world!";
+    let signatures: Vec<_> = keys
+        .iter()
+        .map(|(_, secret)| Bls::sign(secret, message))
+        .collect();
+    assert_eq!(keys[0].0.len(), Bls::PUBLIC_KEY_LENGTH);
+    assert_eq!(signatures[0].len(), Bls::SIGNATURE_LENGTH);
+    assert!(Bls::verify(&keys[0].0, message, &signatures[0]).is_ok());
+    assert!(Bls::verify(&keys[0].0, b"Bad message!", &signatures[0]).is_err());
+
+    let mut votes: Vec<_> = keys
+        .iter()
+        .zip(signatures.iter())
+        .map(|((public, _), sig)| (&public[..], &sig[..]))
+        .collect();
+    assert!(Bls::verify_batch(message, &votes).is_ok());
+    votes[0].1 = &signatures[1][..];
+    assert!(Bls::verify_batch(message, &votes).is_err());
+}
@@ -4,8 +4,10 @@ use std::collections::BTreeMap;
 
 // TODO: might want to abstract these so that we don't expose the underlying crypto_threshold library types
 pub use threshold_crypto::{
-    serde_impl::SerdeSecret, Ciphertext, DecryptionShare, PublicKey, PublicKeySet, PublicKeyShare,
-    SecretKeySet, SecretKeyShare,
+    poly::{Commitment, Poly},
+    serde_impl::SerdeSecret,
+    Ciphertext, DecryptionShare, PublicKey, PublicKeySet, PublicKeyShare, SecretKey, SecretKeySet,
+    SecretKeyShare,
 };
 
 // In this example scenario, the `SecretSociety` is the "trusted key dealer". The trusted dealer is
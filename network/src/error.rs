@@ -11,6 +11,9 @@ pub enum NetworkError {
     #[error("Failed to accept connection: {0}")]
     FailedToListen(std::io::Error),
 
+    #[error("Failed to authenticate {0}: {1}")]
+    FailedToAuthenticate(SocketAddr, std::io::Error),
+
     #[error("Failed to send message to {0}: {1}")]
     FailedToSendMessage(SocketAddr, std::io::Error),
 
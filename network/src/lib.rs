@@ -1,13 +1,19 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 mod error;
+#[cfg(any(test, feature = "simulation"))]
+pub mod memory;
+mod rate_limiter;
 mod receiver;
 mod reliable_sender;
 mod simple_sender;
+pub mod tls;
 
 #[cfg(test)]
 #[path = "tests/common.rs"]
 pub mod common;
 
-pub use crate::receiver::{MessageHandler, Receiver, Writer};
-pub use crate::reliable_sender::{CancelHandler, ReliableSender};
+pub use crate::rate_limiter::RateLimit;
+pub use crate::receiver::{MessageHandler, Receiver, Writer, TOO_MANY_CONNECTIONS};
+pub use crate::reliable_sender::{Backoff, CancelHandler, ReliableSender};
 pub use crate::simple_sender::SimpleSender;
+pub use crate::tls::{TlsConfig, TlsError};
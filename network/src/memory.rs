@@ -0,0 +1,223 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::tls::Stream;
+use bytes::Bytes;
+use futures::sink::SinkExt as _;
+use futures::stream::{SplitSink, StreamExt as _};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use tokio::io::{duplex, DuplexStream};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::time::{sleep, sleep_until, Duration, Instant};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+#[cfg(test)]
+#[path = "tests/memory_tests.rs"]
+pub mod memory_tests;
+
+/// The size (in bytes) of the buffers of the in-memory connections.
+const BUFFER_SIZE: usize = 1 << 20;
+
+/// The index of a node of the in-memory network.
+pub type NodeId = usize;
+
+/// A connection of the in-memory network, from the node `from` (`None` for the clients) to the node `to`
+/// listening on `address`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Link {
+    pub from: Option<NodeId>,
+    pub to: Option<NodeId>,
+    pub address: SocketAddr,
+}
+
+/// What happens to a message sent over a link.
+#[derive(Debug)]
+pub enum Verdict {
+    /// Delivers the message (possibly altered) after a delay. The messages of a connection are delivered
+    /// in the order they are sent, whatever their delays.
+    Deliver(Duration, Bytes),
+    /// Delivers the message after a delay, and a copy of it on a separate connection.
+    Duplicate(Duration, Bytes),
+    /// Loses the message and closes the connection once the previous messages are delivered, as a failing
+    /// link would: the reliable senders re-transmit the messages that were not acknowledged.
+    Reset,
+}
+
+/// Decides the fate of the connections and messages of the in-memory network.
+pub trait Interceptor: Send + Sync + 'static {
+    /// Whether a connection can be opened over `link`.
+    fn connect(&self, _link: &Link) -> bool {
+        true
+    }
+
+    /// What happens to a message sent over `link`.
+    fn send(&self, _link: &Link, message: Bytes) -> Verdict {
+        Verdict::Deliver(Duration::default(), message)
+    }
+}
+
+/// A perfect network.
+impl Interceptor for () {}
+
+/// Routes the connections of the senders and receivers of this crate through memory rather than TCP, so
+/// that whole committees can run in a single process under the faults chosen by an `Interceptor`. Each
+/// thread must `enter` the network to use it: it is meant to run every node on its own thread (and
+/// runtime), which then tells the network on whose behalf the connections are opened. TLS is ignored.
+pub struct MemoryNetwork {
+    /// The listeners of the nodes, by address.
+    listeners: Mutex<HashMap<SocketAddr, (Option<NodeId>, UnboundedSender<Stream>)>>,
+    /// Decides the fate of the connections and messages.
+    interceptor: Arc<dyn Interceptor>,
+}
+
+thread_local! {
+    /// The network entered by the current thread, and the node it runs.
+    static CURRENT: RefCell<Option<(Arc<MemoryNetwork>, Option<NodeId>)>> = RefCell::new(None);
+}
+
+/// The network entered by the current thread (if any), and the node it runs.
+pub(crate) fn current() -> Option<(Arc<MemoryNetwork>, Option<NodeId>)> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+type Transport = Framed<DuplexStream, LengthDelimitedCodec>;
+
+impl MemoryNetwork {
+    pub fn new(interceptor: Arc<dyn Interceptor>) -> Arc<Self> {
+        Arc::new(Self {
+            listeners: Mutex::new(HashMap::new()),
+            interceptor,
+        })
+    }
+
+    /// Routes the connections opened and accepted on the current thread through this network, on behalf
+    /// of `node` (`None` for the clients, which are not nodes).
+    pub fn enter(self: &Arc<Self>, node: Option<NodeId>) {
+        CURRENT.with(|current| *current.borrow_mut() = Some((self.clone(), node)));
+    }
+
+    /// Routes the connections of the current thread through TCP again.
+    pub fn leave() {
+        CURRENT.with(|current| *current.borrow_mut() = None);
+    }
+
+    /// Listens on `address` on behalf of `node`, replacing any previous listener (e.g. the one of a node that
+    /// crashed and restarted). Returns the accepted connections.
+    pub(crate) fn bind(
+        &self,
+        address: SocketAddr,
+        node: Option<NodeId>,
+    ) -> UnboundedReceiver<Stream> {
+        let (tx, rx) = unbounded_channel();
+        self.listeners.lock().unwrap().insert(address, (node, tx));
+        rx
+    }
+
+    /// The listener of `address`. The nodes usually listen on all interfaces while the others connect to
+    /// the address of the committee: we then match the port.
+    fn listener(&self, address: SocketAddr) -> Option<(Option<NodeId>, UnboundedSender<Stream>)> {
+        let listeners = self.listeners.lock().unwrap();
+        let any = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), address.port());
+        listeners
+            .get(&address)
+            .or_else(|| listeners.get(&any))
+            .cloned()
+    }
+
+    /// Opens a connection to `address` on behalf of `from`. Must be called within a tokio runtime.
+    pub fn connect(
+        self: &Arc<Self>,
+        address: SocketAddr,
+        from: Option<NodeId>,
+    ) -> io::Result<Stream> {
+        let refused = || io::Error::from(io::ErrorKind::ConnectionRefused);
+        let (to, listener) = self.listener(address).ok_or_else(refused)?;
+        let link = Link { from, to, address };
+        if !self.interceptor.connect(&link) {
+            return Err(refused());
+        }
+
+        let (client, client_end) = duplex(BUFFER_SIZE);
+        let (server, server_end) = duplex(BUFFER_SIZE);
+        listener.send(Box::new(server)).map_err(|_| refused())?;
+        tokio::spawn(self.clone().relay(link, client_end, server_end));
+        Ok(Box::new(client))
+    }
+
+    /// Carries the messages of a connection to the listener (through the interceptor), and its replies back.
+    async fn relay(self: Arc<Self>, link: Link, client: DuplexStream, server: DuplexStream) {
+        let (mut client_writer, mut client_reader) =
+            Framed::new(client, LengthDelimitedCodec::new()).split();
+        let (server_writer, mut server_reader) =
+            Framed::new(server, LengthDelimitedCodec::new()).split();
+
+        // The messages are delivered by a separate task, so that their delays run concurrently. `None`
+        // closes the connection.
+        let (tx_delivery, rx_delivery) = unbounded_channel();
+        let delivery = tokio::spawn(Self::deliver(server_writer, rx_delivery));
+        loop {
+            tokio::select! {
+                message = client_reader.next() => match message {
+                    Some(Ok(message)) => match self.interceptor.send(&link, message.freeze()) {
+                        Verdict::Deliver(delay, message) => {
+                            let _ = tx_delivery.send(Some((Instant::now() + delay, message)));
+                        }
+                        Verdict::Duplicate(delay, message) => {
+                            self.duplicate(link.address, delay, message.clone());
+                            let _ = tx_delivery.send(Some((Instant::now() + delay, message)));
+                        }
+                        Verdict::Reset => {
+                            let _ = tx_delivery.send(None);
+                            return;
+                        }
+                    },
+                    // The sender closed the connection: deliver its last messages before closing ours.
+                    _ => {
+                        drop(tx_delivery);
+                        let _ = delivery.await;
+                        return;
+                    }
+                },
+                reply = server_reader.next() => match reply {
+                    Some(Ok(reply)) => {
+                        if client_writer.send(reply.freeze()).await.is_err() {
+                            return;
+                        }
+                    }
+                    _ => return,
+                },
+            }
+        }
+    }
+
+    /// Delivers the messages of a connection once their delays elapse.
+    async fn deliver(
+        mut writer: SplitSink<Transport, Bytes>,
+        mut rx_delivery: UnboundedReceiver<Option<(Instant, Bytes)>>,
+    ) {
+        while let Some(Some((deadline, message))) = rx_delivery.recv().await {
+            sleep_until(deadline).await;
+            if writer.send(message).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Delivers a copy of a message on a separate connection (whose replies are lost).
+    fn duplicate(&self, address: SocketAddr, delay: Duration, message: Bytes) {
+        let listener = match self.listener(address) {
+            Some((_, listener)) => listener,
+            None => return,
+        };
+        let (client, server) = duplex(BUFFER_SIZE);
+        if listener.send(Box::new(server)).is_ok() {
+            tokio::spawn(async move {
+                sleep(delay).await;
+                let mut transport = Framed::new(client, LengthDelimitedCodec::new());
+                let _ = transport.send(message).await;
+            });
+        }
+    }
+}
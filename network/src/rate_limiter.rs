@@ -0,0 +1,54 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use std::time::{Duration, Instant};
+
+/// The maximum rate at which a receiver reads the messages of a single connection. Each connection may
+/// briefly exceed it by up to one second's worth of messages and bytes.
+#[derive(Clone, Copy, Debug)]
+pub struct RateLimit {
+    pub messages_per_second: u64,
+    pub bytes_per_second: u64,
+}
+
+/// A pair of token buckets (one counting messages, the other bytes) enforcing a `RateLimit`.
+pub(crate) struct RateLimiter {
+    limit: RateLimit,
+    /// The number of messages we may still read without waiting.
+    messages: f64,
+    /// The number of bytes we may still read without waiting.
+    bytes: f64,
+    /// The last time the buckets were refilled.
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            messages: limit.messages_per_second as f64,
+            bytes: limit.bytes_per_second as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Accounts for a message of `size` bytes and returns how long to wait before processing it in order
+    /// to stay within the limit (zero if we are within the limit).
+    pub fn consume(&mut self, size: usize) -> Duration {
+        let messages_rate = self.limit.messages_per_second.max(1) as f64;
+        let bytes_rate = self.limit.bytes_per_second.max(1) as f64;
+
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.last_refill = now;
+        self.messages = (self.messages + elapsed * messages_rate).min(messages_rate);
+        self.bytes = (self.bytes + elapsed * bytes_rate).min(bytes_rate);
+
+        self.messages -= 1.0;
+        self.bytes -= size as f64;
+        let wait = (-self.messages / messages_rate).max(-self.bytes / bytes_rate);
+        if wait > 0.0 {
+            Duration::from_secs_f64(wait)
+        } else {
+            Duration::default()
+        }
+    }
+}
@@ -1,13 +1,20 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use crate::error::NetworkError;
+use crate::rate_limiter::{RateLimit, RateLimiter};
+use crate::tls::{self, AsyncStream, Stream, TlsConfig};
 use async_trait::async_trait;
 use bytes::Bytes;
+use futures::sink::SinkExt as _;
 use futures::stream::SplitSink;
 use futures::stream::StreamExt as _;
 use log::{debug, info, warn};
 use std::error::Error;
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
 use tokio_util::codec::{Framed, LengthDelimitedCodec};
 
 #[cfg(test)]
@@ -15,7 +22,11 @@ use tokio_util::codec::{Framed, LengthDelimitedCodec};
 pub mod receiver_tests;
 
 /// Convenient alias for the writer end of the TCP channel.
-pub type Writer = SplitSink<Framed<TcpStream, LengthDelimitedCodec>, Bytes>;
+pub type Writer = SplitSink<Framed<Stream, LengthDelimitedCodec>, Bytes>;
+
+/// The reply sent on the connections accepted beyond the maximum number of connections, right before
+/// closing them.
+pub const TOO_MANY_CONNECTIONS: &[u8] = b"TooManyConnections";
 
 #[async_trait]
 pub trait MessageHandler: Clone + Send + Sync + 'static {
@@ -33,18 +44,123 @@ pub struct Receiver<Handler: MessageHandler> {
     address: SocketAddr,
     /// Struct responsible to define how to handle received messages.
     handler: Handler,
+    /// If set, the incoming connections must be TLS connections from committee members.
+    tls: Option<Arc<TlsConfig>>,
+    /// If set, the maximum rate at which we read the messages of each connection.
+    limit: Option<RateLimit>,
+    /// If set, the maximum number of connections we keep open at once.
+    max_connections: Option<usize>,
+    /// The number of connections currently open.
+    connections: Arc<AtomicUsize>,
+}
+
+/// Counts a connection as open until dropped.
+struct ConnectionGuard(Arc<AtomicUsize>);
+
+impl ConnectionGuard {
+    fn new(connections: Arc<AtomicUsize>) -> Self {
+        connections.fetch_add(1, Ordering::SeqCst);
+        Self(connections)
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
 }
 
 impl<Handler: MessageHandler> Receiver<Handler> {
-    /// Spawn a new network receiver handling connections from any incoming peer.
-    pub fn spawn(address: SocketAddr, handler: Handler) {
+    /// Spawn a new network receiver handling connections from any incoming peer, over TLS if a TLS
+    /// configuration is installed.
+    pub fn spawn(address: SocketAddr, handler: Handler) -> JoinHandle<()> {
+        Self::spawn_with_tls(address, handler, tls::installed())
+    }
+
+    /// Spawn a new network receiver handling connections from clients (which are not committee members
+    /// and thus never use TLS).
+    pub fn spawn_public(address: SocketAddr, handler: Handler) -> JoinHandle<()> {
+        Self::spawn_with_tls(address, handler, None)
+    }
+
+    /// Spawn a new network receiver handling connections from clients, keeping at most `max_connections`
+    /// of them open at once: the connections accepted beyond are closed right away (after replying
+    /// `TOO_MANY_CONNECTIONS`), while the open ones are unaffected.
+    pub fn spawn_public_with_max_connections(
+        address: SocketAddr,
+        handler: Handler,
+        max_connections: usize,
+    ) -> JoinHandle<()> {
+        Self::spawn_inner(address, handler, None, None, Some(max_connections))
+    }
+
+    /// Spawn a new network receiver only accepting TLS connections from the peers of `tls` (if set).
+    pub fn spawn_with_tls(
+        address: SocketAddr,
+        handler: Handler,
+        tls: Option<Arc<TlsConfig>>,
+    ) -> JoinHandle<()> {
+        Self::spawn_inner(address, handler, tls, None, None)
+    }
+
+    /// Spawn a new network receiver (over TLS if a TLS configuration is installed) throttling the
+    /// connections exceeding `limit`.
+    pub fn spawn_with_limit(
+        address: SocketAddr,
+        handler: Handler,
+        limit: RateLimit,
+    ) -> JoinHandle<()> {
+        Self::spawn_inner(address, handler, tls::installed(), Some(limit), None)
+    }
+
+    fn spawn_inner(
+        address: SocketAddr,
+        handler: Handler,
+        tls: Option<Arc<TlsConfig>>,
+        limit: Option<RateLimit>,
+        max_connections: Option<usize>,
+    ) -> JoinHandle<()> {
         tokio::spawn(async move {
-            Self { address, handler }.run().await;
-        });
+            Self {
+                address,
+                handler,
+                tls,
+                limit,
+                max_connections,
+                connections: Arc::new(AtomicUsize::new(0)),
+            }
+            .run()
+            .await;
+        })
     }
 
     /// Main loop responsible to accept incoming connections and spawn a new runner to handle it.
     async fn run(&self) {
+        #[cfg(any(test, feature = "simulation"))]
+        {
+            if let Some((network, node)) = crate::memory::current() {
+                let mut listener = network.bind(self.address, node);
+                debug!("Listening on {} (in memory)", self.address);
+                while let Some(stream) = listener.recv().await {
+                    if let Some(max) = self.max_connections {
+                        if self.connections.load(Ordering::SeqCst) >= max {
+                            warn!("Refusing connection: {} connections are already open", max);
+                            Self::refuse(stream);
+                            continue;
+                        }
+                    }
+                    let guard = ConnectionGuard::new(self.connections.clone());
+                    let (handler, limit) = (self.handler.clone(), self.limit);
+                    let peer = self.address;
+                    tokio::spawn(async move {
+                        let _guard = guard;
+                        Self::serve(stream, peer, handler, limit).await;
+                    });
+                }
+                return;
+            }
+        }
+
         let listener = TcpListener::bind(&self.address)
             .await
             .expect("Failed to bind TCP port");
@@ -58,32 +174,101 @@ impl<Handler: MessageHandler> Receiver<Handler> {
                     continue;
                 }
             };
+            if let Some(max) = self.max_connections {
+                if self.connections.load(Ordering::SeqCst) >= max {
+                    warn!(
+                        "Refusing connection from {}: {} connections are already open",
+                        peer, max
+                    );
+                    Self::refuse(socket);
+                    continue;
+                }
+            }
             info!("Incoming connection established with {}", peer);
-            Self::spawn_runner(socket, peer, self.handler.clone()).await;
+            let guard = ConnectionGuard::new(self.connections.clone());
+            Self::spawn_runner(
+                socket,
+                peer,
+                self.handler.clone(),
+                self.tls.clone(),
+                self.limit,
+                guard,
+            )
+            .await;
         }
     }
 
+    /// Tells the peer why we close its connection, without holding back the accept loop.
+    fn refuse(socket: impl AsyncStream + 'static) {
+        tokio::spawn(async move {
+            let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+            let _ = transport
+                .send(Bytes::from_static(TOO_MANY_CONNECTIONS))
+                .await;
+        });
+    }
+
     /// Spawn a new runner to handle a specific TCP connection. It receives messages and process them
     /// using the provided handler.
-    async fn spawn_runner(socket: TcpStream, peer: SocketAddr, handler: Handler) {
+    async fn spawn_runner(
+        socket: TcpStream,
+        peer: SocketAddr,
+        handler: Handler,
+        tls: Option<Arc<TlsConfig>>,
+        limit: Option<RateLimit>,
+        guard: ConnectionGuard,
+    ) {
         tokio::spawn(async move {
-            let transport = Framed::new(socket, LengthDelimitedCodec::new());
-            let (mut writer, mut reader) = transport.split();
-            while let Some(frame) = reader.next().await {
-                match frame.map_err(|e| NetworkError::FailedToReceiveMessage(peer, e)) {
-                    Ok(message) => {
-                        if let Err(e) = handler.dispatch(&mut writer, message.freeze()).await {
-                            warn!("{}", e);
-                            return;
+            // The connection counts as open until the runner returns.
+            let _guard = guard;
+            // Run the TLS handshake in the runner, so that slow peers do not hold back the others.
+            let stream: Stream = match tls {
+                Some(config) => match config.accept(socket).await {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        warn!("{}", NetworkError::FailedToAuthenticate(peer, e));
+                        return;
+                    }
+                },
+                None => Box::new(socket),
+            };
+            Self::serve(stream, peer, handler, limit).await;
+        });
+    }
+
+    /// Receives the messages of a connection and processes them using the provided handler.
+    async fn serve(stream: Stream, peer: SocketAddr, handler: Handler, limit: Option<RateLimit>) {
+        let transport = Framed::new(stream, LengthDelimitedCodec::new());
+        let (mut writer, mut reader) = transport.split();
+        let mut limiter = limit.map(RateLimiter::new);
+        let mut throttled = false;
+        while let Some(frame) = reader.next().await {
+            match frame.map_err(|e| NetworkError::FailedToReceiveMessage(peer, e)) {
+                Ok(message) => {
+                    // Slow down the peers sending too fast: we stop reading their connection, so their
+                    // messages pile up in their own buffers rather than in our channels.
+                    if let Some(limiter) = &mut limiter {
+                        let delay = limiter.consume(message.len());
+                        if delay.as_nanos() > 0 {
+                            if !throttled {
+                                warn!("Throttling peer {}: it exceeds its rate limit", peer);
+                            }
+                            sleep(delay).await;
                         }
+                        throttled = delay.as_nanos() > 0;
                     }
-                    Err(e) => {
+
+                    if let Err(e) = handler.dispatch(&mut writer, message.freeze()).await {
                         warn!("{}", e);
                         return;
                     }
                 }
+                Err(e) => {
+                    warn!("{}", e);
+                    return;
+                }
             }
-            warn!("Connection closed by peer {}", peer);
-        });
+        }
+        warn!("Connection closed by peer {}", peer);
     }
 }
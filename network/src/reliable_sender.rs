@@ -1,5 +1,6 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use crate::error::NetworkError;
+use crate::tls::{self, Stream, TlsConfig};
 use bytes::Bytes;
 use futures::sink::SinkExt as _;
 use futures::stream::StreamExt as _;
@@ -11,7 +12,7 @@ use std::cmp::min;
 use std::collections::{HashMap, VecDeque};
 use std::fmt::Debug;
 use std::net::SocketAddr;
-use tokio::net::TcpStream;
+use std::sync::Arc;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
 use tokio::sync::oneshot;
 use tokio::time::{sleep, Duration};
@@ -24,6 +25,34 @@ pub mod reliable_sender_tests;
 /// Convenient alias for cancel handlers returned to the caller task.
 pub type CancelHandler = oneshot::Receiver<Bytes>;
 
+/// How long the connections of the `ReliableSender` wait before re-attempting to connect to their peer. The
+/// delay starts at `min_delay`, is multiplied by `multiplier` after every failed attempt (up to `max_delay`),
+/// and goes back to `min_delay` once a message is successfully sent. Delays are denominated in ms.
+#[derive(Clone, Copy, Debug)]
+pub struct Backoff {
+    pub min_delay: u64,
+    pub max_delay: u64,
+    pub multiplier: f64,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            min_delay: 200,
+            max_delay: 60_000,
+            multiplier: 2.0,
+        }
+    }
+}
+
+impl Backoff {
+    /// The delay to wait after a failed attempt, if we waited `delay` before it.
+    pub fn next(&self, delay: u64) -> u64 {
+        let next = (delay as f64 * self.multiplier) as u64;
+        min(next, self.max_delay).max(self.min_delay)
+    }
+}
+
 /// We keep alive one TCP connection per peer, each connection is handled by a separate task (called `Connection`).
 /// We communicate with our 'connections' through a dedicated channel kept by the HashMap called `connections`.
 /// This sender is 'reliable' in the sense that it keeps trying to re-transmit messages for which it didn't
@@ -33,6 +62,10 @@ pub struct ReliableSender {
     connections: HashMap<SocketAddr, Sender<InnerMessage>>,
     /// Small RNG just used to shuffle nodes and randomize connections (not crypto related).
     rng: SmallRng,
+    /// If set, the connections are TLS connections.
+    tls: Option<Arc<TlsConfig>>,
+    /// The reconnection backoff of the connections.
+    backoff: Backoff,
 }
 
 impl std::default::Default for ReliableSender {
@@ -42,26 +75,44 @@ impl std::default::Default for ReliableSender {
 }
 
 impl ReliableSender {
+    /// Creates a sender using TLS if a TLS configuration is installed.
     pub fn new() -> Self {
+        Self::with_tls(tls::installed())
+    }
+
+    pub fn with_tls(tls: Option<Arc<TlsConfig>>) -> Self {
         Self {
             connections: HashMap::new(),
             rng: SmallRng::from_entropy(),
+            tls,
+            backoff: Backoff::default(),
         }
     }
 
+    /// Sets the reconnection backoff of the connections opened from now on.
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
     /// Helper function to spawn a new connection.
-    fn spawn_connection(address: SocketAddr) -> Sender<InnerMessage> {
+    fn spawn_connection(
+        address: SocketAddr,
+        tls: Option<Arc<TlsConfig>>,
+        backoff: Backoff,
+    ) -> Sender<InnerMessage> {
         let (tx, rx) = channel(1_000);
-        Connection::spawn(address, rx);
+        Connection::spawn(address, rx, tls, backoff);
         tx
     }
 
     /// Reliably send a message to a specific address.
     pub async fn send(&mut self, address: SocketAddr, data: Bytes) -> CancelHandler {
         let (sender, receiver) = oneshot::channel();
+        let (tls, backoff) = (&self.tls, self.backoff);
         self.connections
             .entry(address)
-            .or_insert_with(|| Self::spawn_connection(address))
+            .or_insert_with(|| Self::spawn_connection(address, tls.clone(), backoff))
             .send(InnerMessage {
                 data,
                 cancel_handler: sender,
@@ -116,20 +167,31 @@ struct Connection {
     address: SocketAddr,
     /// Channel from which the connection receives its commands.
     receiver: Receiver<InnerMessage>,
-    /// The initial delay to wait before re-attempting a connection (in ms).
-    retry_delay: u64,
+    /// How long to wait before re-attempting a connection.
+    backoff: Backoff,
+    /// The delay to wait before the next connection attempt (in ms).
+    delay: u64,
     /// Buffer keeping all messages that need to be re-transmitted.
     buffer: VecDeque<(Bytes, oneshot::Sender<Bytes>)>,
+    /// If set, the connection is a TLS connection.
+    tls: Option<Arc<TlsConfig>>,
 }
 
 impl Connection {
-    fn spawn(address: SocketAddr, receiver: Receiver<InnerMessage>) {
+    fn spawn(
+        address: SocketAddr,
+        receiver: Receiver<InnerMessage>,
+        tls: Option<Arc<TlsConfig>>,
+        backoff: Backoff,
+    ) {
         tokio::spawn(async move {
             Self {
                 address,
                 receiver,
-                retry_delay: 200,
+                backoff,
+                delay: backoff.min_delay,
                 buffer: VecDeque::new(),
+                tls,
             }
             .run()
             .await;
@@ -138,15 +200,11 @@ impl Connection {
 
     /// Main loop trying to connect to the peer and transmit messages.
     async fn run(&mut self) {
-        let mut delay = self.retry_delay;
         let mut retry = 0;
         loop {
-            match TcpStream::connect(self.address).await {
+            match tls::connect(self.address, self.tls.as_deref()).await {
                 Ok(stream) => {
                     info!("Outgoing connection established with {}", self.address);
-
-                    // Reset the delay.
-                    delay = self.retry_delay;
                     retry = 0;
 
                     // Try to transmit all messages in the buffer and keep transmitting incoming messages.
@@ -156,14 +214,14 @@ impl Connection {
                 }
                 Err(e) => {
                     warn!("{}", NetworkError::FailedToConnect(self.address, retry, e));
-                    let timer = sleep(Duration::from_millis(delay));
+                    let timer = sleep(Duration::from_millis(self.delay));
                     tokio::pin!(timer);
 
                     'waiter: loop {
                         tokio::select! {
                             // Wait an increasing delay before attempting to reconnect.
                             () = &mut timer => {
-                                delay = min(2*delay, 60_000);
+                                self.delay = self.backoff.next(self.delay);
                                 retry +=1;
                                 break 'waiter;
                             },
@@ -182,7 +240,7 @@ impl Connection {
     }
 
     /// Transmit messages once we have established a connection.
-    async fn keep_alive(&mut self, stream: TcpStream) -> NetworkError {
+    async fn keep_alive(&mut self, stream: Stream) -> NetworkError {
         // This buffer keeps all messages and handlers that we have successfully transmitted but for
         // which we are still waiting to receive an ACK.
         let mut pending_replies = VecDeque::new();
@@ -200,7 +258,8 @@ impl Connection {
                 match writer.send(data.clone()).await {
                     Ok(()) => {
                         // The message has been sent, we remove it from the buffer and add it to
-                        // `pending_replies` while we wait for an ACK.
+                        // `pending_replies` while we wait for an ACK. The peer is up: reset the backoff.
+                        self.delay = self.backoff.min_delay;
                         pending_replies.push_back((data, handler));
                     }
                     Err(e) => {
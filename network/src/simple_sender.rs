@@ -1,5 +1,6 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use crate::error::NetworkError;
+use crate::tls::{self, TlsConfig};
 use bytes::Bytes;
 use futures::sink::SinkExt as _;
 use futures::stream::StreamExt as _;
@@ -9,7 +10,7 @@ use rand::rngs::SmallRng;
 use rand::SeedableRng as _;
 use std::collections::HashMap;
 use std::net::SocketAddr;
-use tokio::net::TcpStream;
+use std::sync::Arc;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
 use tokio_util::codec::{Framed, LengthDelimitedCodec};
 
@@ -24,6 +25,8 @@ pub struct SimpleSender {
     connections: HashMap<SocketAddr, Sender<Bytes>>,
     /// Small RNG just used to shuffle nodes and randomize connections (not crypto related).
     rng: SmallRng,
+    /// If set, the connections are TLS connections.
+    tls: Option<Arc<TlsConfig>>,
 }
 
 impl std::default::Default for SimpleSender {
@@ -33,17 +36,32 @@ impl std::default::Default for SimpleSender {
 }
 
 impl SimpleSender {
+    /// Creates a sender using TLS if a TLS configuration is installed.
     pub fn new() -> Self {
+        Self::with_tls(tls::installed())
+    }
+
+    pub fn with_tls(tls: Option<Arc<TlsConfig>>) -> Self {
         Self {
             connections: HashMap::new(),
             rng: SmallRng::from_entropy(),
+            tls,
+        }
+    }
+
+    /// Seeds the RNG picking the peers of `lucky_broadcast`, so that a sender fed the same calls picks the
+    /// same peers. The RNG stays seeded from entropy if `seed` is not set.
+    pub fn with_seed(mut self, seed: Option<u64>) -> Self {
+        if let Some(seed) = seed {
+            self.rng = SmallRng::seed_from_u64(seed);
         }
+        self
     }
 
     /// Helper function to spawn a new connection.
-    fn spawn_connection(address: SocketAddr) -> Sender<Bytes> {
+    fn spawn_connection(&self, address: SocketAddr) -> Sender<Bytes> {
         let (tx, rx) = channel(1_000);
-        Connection::spawn(address, rx);
+        Connection::spawn(address, rx, self.tls.clone());
         tx
     }
 
@@ -58,7 +76,7 @@ impl SimpleSender {
         }
 
         // Otherwise make a new connection.
-        let tx = Self::spawn_connection(address);
+        let tx = self.spawn_connection(address);
         // TOUNDERSTAND: what happens if the send fails?
         if tx.send(data).await.is_ok() {
             self.connections.insert(address, tx);
@@ -74,15 +92,16 @@ impl SimpleSender {
 
     /// Pick a few addresses at random (specified by `nodes`) and try (best-effort) to send the
     /// message only to them. This is useful to pick nodes with whom to sync.
-    pub async fn lucky_broadcast(
-        &mut self,
-        mut addresses: Vec<SocketAddr>,
-        data: Bytes,
-        nodes: usize,
-    ) {
+    pub async fn lucky_broadcast(&mut self, addresses: Vec<SocketAddr>, data: Bytes, nodes: usize) {
+        let addresses = self.pick(addresses, nodes);
+        self.broadcast(addresses, data).await
+    }
+
+    /// Picks `nodes` of the `addresses` at random.
+    fn pick(&mut self, mut addresses: Vec<SocketAddr>, nodes: usize) -> Vec<SocketAddr> {
         addresses.shuffle(&mut self.rng);
         addresses.truncate(nodes);
-        self.broadcast(addresses, data).await
+        addresses
     }
 }
 
@@ -92,19 +111,27 @@ struct Connection {
     address: SocketAddr,
     /// Channel from which the connection receives its commands.
     receiver: Receiver<Bytes>,
+    /// If set, the connection is a TLS connection.
+    tls: Option<Arc<TlsConfig>>,
 }
 
 impl Connection {
-    fn spawn(address: SocketAddr, receiver: Receiver<Bytes>) {
+    fn spawn(address: SocketAddr, receiver: Receiver<Bytes>, tls: Option<Arc<TlsConfig>>) {
         tokio::spawn(async move {
-            Self { address, receiver }.run().await;
+            Self {
+                address,
+                receiver,
+                tls,
+            }
+            .run()
+            .await;
         });
     }
 
     /// Main loop trying to connect to the peer and transmit messages.
     async fn run(&mut self) {
         // Try to connect to the peer.
-        let (mut writer, mut reader) = match TcpStream::connect(self.address).await {
+        let (mut writer, mut reader) = match tls::connect(self.address, self.tls.as_deref()).await {
             Ok(stream) => Framed::new(stream, LengthDelimitedCodec::new()).split(),
             Err(e) => {
                 warn!(
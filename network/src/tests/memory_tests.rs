@@ -0,0 +1,155 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::{MessageHandler, Receiver, ReliableSender, Writer};
+use async_trait::async_trait;
+use std::error::Error;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::sync::mpsc::{channel, Sender};
+
+#[derive(Clone)]
+struct TestHandler {
+    deliver: Sender<Bytes>,
+}
+
+#[async_trait]
+impl MessageHandler for TestHandler {
+    async fn dispatch(&self, writer: &mut Writer, message: Bytes) -> Result<(), Box<dyn Error>> {
+        let _ = writer.send(Bytes::from("Ack")).await;
+        self.deliver.send(message).await.unwrap();
+        Ok(())
+    }
+}
+
+/// Spawns a receiver delivering its messages to the returned channel.
+async fn receiver(address: SocketAddr) -> tokio::sync::mpsc::Receiver<Bytes> {
+    let (tx, rx) = channel(100);
+    Receiver::spawn(address, TestHandler { deliver: tx });
+    sleep(Duration::from_millis(50)).await;
+    rx
+}
+
+/// Applies `verdict` to the messages numbered by `sent`, and refuses the connections to `refused`.
+struct TestInterceptor<F: Fn(usize, Bytes) -> Verdict + Send + Sync + 'static> {
+    sent: AtomicUsize,
+    verdict: F,
+    refused: Option<SocketAddr>,
+}
+
+impl<F: Fn(usize, Bytes) -> Verdict + Send + Sync + 'static> Interceptor for TestInterceptor<F> {
+    fn connect(&self, link: &Link) -> bool {
+        self.refused != Some(link.address)
+    }
+
+    fn send(&self, _link: &Link, message: Bytes) -> Verdict {
+        (self.verdict)(self.sent.fetch_add(1, Ordering::SeqCst), message)
+    }
+}
+
+fn network<F: Fn(usize, Bytes) -> Verdict + Send + Sync + 'static>(
+    verdict: F,
+    refused: Option<SocketAddr>,
+) -> Arc<MemoryNetwork> {
+    MemoryNetwork::new(Arc::new(TestInterceptor {
+        sent: AtomicUsize::new(0),
+        verdict,
+        refused,
+    }))
+}
+
+#[tokio::test]
+async fn send_in_memory() {
+    let network = MemoryNetwork::new(Arc::new(()));
+    network.enter(Some(0));
+
+    // Nodes listen on all interfaces, and are reached on the address of the committee.
+    let mut rx = receiver("0.0.0.0:6000".parse().unwrap()).await;
+    let address = "127.0.0.1:6000".parse::<SocketAddr>().unwrap();
+    let mut sender = ReliableSender::new();
+    let cancel_handler = sender.send(address, Bytes::from("Hello")).await;
+    assert_eq!(cancel_handler.await.unwrap(), Bytes::from("Ack"));
+    assert_eq!(rx.recv().await.unwrap(), Bytes::from("Hello"));
+
+    // Nothing listens on this port over TCP.
+    MemoryNetwork::leave();
+    assert!(tokio::net::TcpStream::connect(address).await.is_err());
+}
+
+#[tokio::test]
+async fn retransmit_after_reset() {
+    // The first message is lost along with its connection.
+    let network = network(
+        |sent, message| match sent {
+            0 => Verdict::Reset,
+            _ => Verdict::Deliver(Duration::default(), message),
+        },
+        None,
+    );
+    network.enter(Some(0));
+
+    let address = "127.0.0.1:6100".parse::<SocketAddr>().unwrap();
+    let mut rx = receiver(address).await;
+    let mut sender = ReliableSender::new();
+    let cancel_handler = sender.send(address, Bytes::from("Hello")).await;
+    assert!(cancel_handler.await.is_ok());
+    assert_eq!(rx.recv().await.unwrap(), Bytes::from("Hello"));
+}
+
+#[tokio::test]
+async fn delay_in_order() {
+    // The first message is delayed more than the second, which still comes after it.
+    let network = network(
+        |sent, message| match sent {
+            0 => Verdict::Deliver(Duration::from_millis(200), message),
+            _ => Verdict::Deliver(Duration::default(), message),
+        },
+        None,
+    );
+    network.enter(Some(0));
+
+    let address = "127.0.0.1:6200".parse::<SocketAddr>().unwrap();
+    let mut rx = receiver(address).await;
+    let mut sender = ReliableSender::new();
+    let now = Instant::now();
+    let first = sender.send(address, Bytes::from("first")).await;
+    let second = sender.send(address, Bytes::from("second")).await;
+    assert_eq!(rx.recv().await.unwrap(), Bytes::from("first"));
+    assert!(now.elapsed() >= Duration::from_millis(200));
+    assert_eq!(rx.recv().await.unwrap(), Bytes::from("second"));
+    assert!(first.await.is_ok());
+    assert!(second.await.is_ok());
+}
+
+#[tokio::test]
+async fn duplicate() {
+    let network = network(
+        |_, message| Verdict::Duplicate(Duration::default(), message),
+        None,
+    );
+    network.enter(Some(0));
+
+    let address = "127.0.0.1:6300".parse::<SocketAddr>().unwrap();
+    let mut rx = receiver(address).await;
+    let mut sender = ReliableSender::new();
+    let cancel_handler = sender.send(address, Bytes::from("Hello")).await;
+    assert!(cancel_handler.await.is_ok());
+    assert_eq!(rx.recv().await.unwrap(), Bytes::from("Hello"));
+    assert_eq!(rx.recv().await.unwrap(), Bytes::from("Hello"));
+}
+
+#[tokio::test]
+async fn refuse_connections() {
+    let address = "127.0.0.1:6400".parse::<SocketAddr>().unwrap();
+    let network = network(
+        |_, message| Verdict::Deliver(Duration::default(), message),
+        Some(address),
+    );
+    network.enter(Some(0));
+
+    let _rx = receiver(address).await;
+    let error = network.connect(address, Some(1)).err().unwrap();
+    assert_eq!(error.kind(), io::ErrorKind::ConnectionRefused);
+
+    // Nothing listens on the other ports.
+    let other = "127.0.0.1:6401".parse::<SocketAddr>().unwrap();
+    assert!(network.connect(other, Some(1)).is_err());
+}
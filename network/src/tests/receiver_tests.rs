@@ -46,3 +46,97 @@ async fn receive() {
     let received = message.unwrap();
     assert_eq!(received, sent);
 }
+
+#[tokio::test]
+async fn throttle_flooding_peer() {
+    // Make a network receiver reading at most 10 messages per second from each connection.
+    let address = "127.0.0.1:4100".parse::<SocketAddr>().unwrap();
+    let (tx, mut rx) = channel(100);
+    let limit = RateLimit {
+        messages_per_second: 10,
+        bytes_per_second: 1_000_000,
+    };
+    Receiver::spawn_with_limit(address, TestHandler { deliver: tx }, limit);
+    sleep(Duration::from_millis(50)).await;
+
+    // A peer floods the receiver, then another one sends a single message.
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut flooder = Framed::new(stream, LengthDelimitedCodec::new());
+    let flood = Bytes::from(bincode::serialize("Flood").unwrap());
+    for _ in 0..50 {
+        flooder.send(flood.clone()).await.unwrap();
+    }
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+    let sent = "Hello, world!";
+    transport
+        .send(Bytes::from(bincode::serialize(sent).unwrap()))
+        .await
+        .unwrap();
+
+    // Ensure the second peer's message goes through while the flood is throttled (the flooder can only
+    // exceed the limit by one second's worth of messages).
+    let mut flooded = 0;
+    let mut received = false;
+    let timer = sleep(Duration::from_millis(500));
+    tokio::pin!(timer);
+    loop {
+        tokio::select! {
+            Some(message) = rx.recv() => {
+                if message == sent {
+                    received = true;
+                } else {
+                    flooded += 1;
+                }
+            },
+            () = &mut timer => break,
+        }
+    }
+    assert!(received);
+    assert!(
+        flooded <= 20,
+        "{} messages of the flood went through",
+        flooded
+    );
+}
+
+#[tokio::test]
+async fn refuse_connections_beyond_max() {
+    // Make a network receiver keeping at most 2 connections open.
+    let address = "127.0.0.1:4200".parse::<SocketAddr>().unwrap();
+    let (tx, mut rx) = channel(10);
+    Receiver::spawn_public_with_max_connections(address, TestHandler { deliver: tx }, 2);
+    sleep(Duration::from_millis(50)).await;
+
+    // Open 2 connections, making sure the receiver handles each of them.
+    let message = Bytes::from(bincode::serialize("Hello, world!").unwrap());
+    let mut open = Vec::new();
+    for _ in 0..2 {
+        let stream = TcpStream::connect(address).await.unwrap();
+        let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+        transport.send(message.clone()).await.unwrap();
+        assert!(rx.recv().await.is_some());
+        open.push(transport);
+    }
+
+    // The third connection is told why it is closed.
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut refused = Framed::new(stream, LengthDelimitedCodec::new());
+    let reply = refused.next().await.unwrap().unwrap();
+    assert_eq!(&reply[..], TOO_MANY_CONNECTIONS);
+    assert!(refused.next().await.is_none());
+
+    // The first connections stay open.
+    for transport in &mut open {
+        transport.send(message.clone()).await.unwrap();
+        assert!(rx.recv().await.is_some());
+    }
+
+    // Closing one of them makes room for a new connection.
+    drop(open.pop());
+    sleep(Duration::from_millis(50)).await;
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+    transport.send(message).await.unwrap();
+    assert!(rx.recv().await.is_some());
+}
@@ -2,6 +2,7 @@
 use super::*;
 use crate::common::listener;
 use futures::future::try_join_all;
+use tokio::time::Instant;
 
 #[tokio::test]
 async fn send() {
@@ -65,3 +66,44 @@ async fn retry() {
     // Ensure the server received the message (ie. it did not panic).
     assert!(handle.await.is_ok());
 }
+
+#[test]
+fn backoff_schedule() {
+    let backoff = Backoff {
+        min_delay: 100,
+        max_delay: 1_000,
+        multiplier: 2.0,
+    };
+    let mut delay = backoff.min_delay;
+    let mut schedule = vec![delay];
+    for _ in 0..5 {
+        delay = backoff.next(delay);
+        schedule.push(delay);
+    }
+    assert_eq!(schedule, vec![100, 200, 400, 800, 1_000, 1_000]);
+}
+
+#[tokio::test]
+async fn reconnect_with_backoff() {
+    // Send a message to a peer that is down.
+    let address = "127.0.0.1:5400".parse::<SocketAddr>().unwrap();
+    let message = "Hello, world!";
+    let backoff = Backoff {
+        min_delay: 100,
+        max_delay: 1_000,
+        multiplier: 2.0,
+    };
+    let mut sender = ReliableSender::new().with_backoff(backoff);
+    let start = Instant::now();
+    let cancel_handler = sender.send(address, Bytes::from(message)).await;
+
+    // The peer comes back up after 400ms. The connection attempts happen after waiting 100ms, 200ms and
+    // 400ms, so the attempt that reaches the peer is the fourth one (about 700ms after the first).
+    sleep(Duration::from_millis(400)).await;
+    let handle = listener(address, message.to_string());
+    assert!(cancel_handler.await.is_ok());
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(650), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_millis(1_400), "{:?}", elapsed);
+    assert!(handle.await.is_ok());
+}
@@ -40,3 +40,26 @@ async fn broadcast() {
     // Ensure all servers received the broadcast.
     assert!(try_join_all(handles).await.is_ok());
 }
+
+#[test]
+fn seeded_peer_selection() {
+    let addresses: Vec<SocketAddr> = (0..10)
+        .map(|x| format!("127.0.0.1:{}", 6_300 + x).parse().unwrap())
+        .collect();
+
+    // Senders seeded alike pick the same peers, call after call.
+    let mut first = SimpleSender::new().with_seed(Some(42));
+    let mut second = SimpleSender::new().with_seed(Some(42));
+    for _ in 0..5 {
+        let picked = first.pick(addresses.clone(), 3);
+        assert_eq!(picked.len(), 3);
+        assert_eq!(picked, second.pick(addresses.clone(), 3));
+    }
+
+    // Another seed picks other peers (at least once).
+    let mut other = SimpleSender::new().with_seed(Some(43));
+    let mut reference = SimpleSender::new().with_seed(Some(42));
+    assert!(
+        (0..5).any(|_| other.pick(addresses.clone(), 3) != reference.pick(addresses.clone(), 3))
+    );
+}
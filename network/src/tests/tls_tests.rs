@@ -0,0 +1,87 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::{MessageHandler, Receiver, ReliableSender, Writer};
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::sink::SinkExt as _;
+use std::error::Error;
+use tokio::sync::mpsc::{channel, Sender};
+use tokio::time::{sleep, timeout, Duration};
+
+#[derive(Clone)]
+struct TestHandler {
+    deliver: Sender<Bytes>,
+}
+
+#[async_trait]
+impl MessageHandler for TestHandler {
+    async fn dispatch(&self, writer: &mut Writer, message: Bytes) -> Result<(), Box<dyn Error>> {
+        let _ = writer.send(Bytes::from("Ack")).await;
+        self.deliver.send(message).await.unwrap();
+        Ok(())
+    }
+}
+
+fn config(seed: u8, peers: &HashMap<SocketAddr, Vec<u8>>) -> Arc<TlsConfig> {
+    let (certificate, private_key) = generate_certificate(&[seed; 32]).unwrap();
+    Arc::new(TlsConfig::from_pem(&certificate, &private_key, peers.clone()).unwrap())
+}
+
+fn identity(seed: u8) -> Vec<u8> {
+    config(seed, &HashMap::new()).identity().to_vec()
+}
+
+/// Spawns a TLS receiver and returns the channel delivering its messages.
+async fn receiver(address: SocketAddr, tls: Arc<TlsConfig>) -> tokio::sync::mpsc::Receiver<Bytes> {
+    let (tx, rx) = channel(1);
+    Receiver::spawn_with_tls(address, TestHandler { deliver: tx }, Some(tls));
+    sleep(Duration::from_millis(50)).await;
+    rx
+}
+
+#[tokio::test]
+async fn committee_members_connect() {
+    let address = "127.0.0.1:6500".parse::<SocketAddr>().unwrap();
+    let other = "127.0.0.1:6501".parse::<SocketAddr>().unwrap();
+    let peers: HashMap<_, _> = vec![(address, identity(1)), (other, identity(2))]
+        .into_iter()
+        .collect();
+    let mut rx = receiver(address, config(1, &peers)).await;
+
+    // Send a message over TLS and ensure it is delivered and acknowledged.
+    let mut sender = ReliableSender::with_tls(Some(config(2, &peers)));
+    let cancel_handler = sender.send(address, Bytes::from("Hello")).await;
+    assert_eq!(rx.recv().await.unwrap(), Bytes::from("Hello"));
+    assert!(cancel_handler.await.is_ok());
+}
+
+#[tokio::test]
+async fn unexpected_server_is_rejected() {
+    // The receiver holds a key that differs from the one pinned for its address.
+    let address = "127.0.0.1:6510".parse::<SocketAddr>().unwrap();
+    let other = "127.0.0.1:6511".parse::<SocketAddr>().unwrap();
+    let peers: HashMap<_, _> = vec![(address, identity(1)), (other, identity(2))]
+        .into_iter()
+        .collect();
+    let mut rx = receiver(address, config(3, &peers)).await;
+
+    let mut sender = ReliableSender::with_tls(Some(config(2, &peers)));
+    let _cancel_handler = sender.send(address, Bytes::from("Hello")).await;
+    assert!(timeout(Duration::from_millis(500), rx.recv())
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn unknown_client_is_rejected() {
+    // The sender's key is not a committee key.
+    let address = "127.0.0.1:6520".parse::<SocketAddr>().unwrap();
+    let peers: HashMap<_, _> = vec![(address, identity(1))].into_iter().collect();
+    let mut rx = receiver(address, config(1, &peers)).await;
+
+    let mut sender = ReliableSender::with_tls(Some(config(2, &peers)));
+    let _cancel_handler = sender.send(address, Bytes::from("Hello")).await;
+    assert!(timeout(Duration::from_millis(500), rx.recv())
+        .await
+        .is_err());
+}
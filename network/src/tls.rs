@@ -0,0 +1,263 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use once_cell::sync::OnceCell;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::internal::pemfile;
+use tokio_rustls::rustls::{
+    Certificate, ClientCertVerified, ClientCertVerifier, ClientConfig, DistinguishedNames,
+    RootCertStore, ServerCertVerified, ServerCertVerifier, ServerConfig, TLSError,
+};
+use tokio_rustls::webpki::{DNSName, DNSNameRef};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+#[cfg(test)]
+#[path = "tests/tls_tests.rs"]
+pub mod tls_tests;
+
+/// The object identifier of ed25519 public keys.
+const ED25519_OID: &str = "1.3.101.112";
+
+/// The name given to the certificates of the nodes. Peers are identified by the public key of their
+/// certificate, not by their name.
+const SERVER_NAME: &str = "narwhal";
+
+/// The PKCS#8 (v1) encoding of an ed25519 secret key, without the 32 bytes of the seed.
+const PKCS8_ED25519_PREFIX: [u8; 16] = [
+    0x30, 0x2e, 0x02, 0x01, 0x00, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x04, 0x22, 0x04, 0x20,
+];
+
+/// The configuration installed by the node, used by the senders and receivers created without an
+/// explicit configuration.
+static INSTALLED: OnceCell<Arc<TlsConfig>> = OnceCell::new();
+
+#[derive(Debug, Error)]
+pub enum TlsError {
+    #[error("Failed to read '{file}': {source}")]
+    Io { file: String, source: io::Error },
+
+    #[error("No PEM-encoded certificate found")]
+    MissingCertificate,
+
+    #[error("No PEM-encoded PKCS#8 private key found")]
+    MissingPrivateKey,
+
+    #[error("The certificate is not an ed25519 certificate")]
+    NotEd25519,
+
+    #[error(transparent)]
+    Rustls(#[from] TLSError),
+
+    #[error(transparent)]
+    Generate(#[from] rcgen::RcgenError),
+}
+
+/// A connection, which may or may not be encrypted.
+pub trait AsyncStream: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> AsyncStream for T {}
+
+pub type Stream = Box<dyn AsyncStream>;
+
+/// Wraps the connections with the peers in TLS. Every node presents a certificate whose public key is its
+/// (ed25519) public key in the committee, so that a TLS handshake authenticates the peer: we only accept
+/// connections from the public keys of the committee, and only connect to an address if the peer holds the
+/// public key of the authority that owns the address.
+pub struct TlsConfig {
+    /// Our own public key (the public key of our certificate).
+    identity: Vec<u8>,
+    /// Accepts connections from any peer of the committee.
+    acceptor: TlsAcceptor,
+    /// Connects to the peers, pinning the expected public key of each address.
+    connectors: HashMap<SocketAddr, TlsConnector>,
+}
+
+impl TlsConfig {
+    /// Loads our certificate and private key (PEM files) and pins the public key of the peers listening on
+    /// all the addresses of `peers`.
+    pub fn new(
+        certificate_path: &str,
+        private_key_path: &str,
+        peers: HashMap<SocketAddr, Vec<u8>>,
+    ) -> Result<Self, TlsError> {
+        let read = |path: &str| {
+            fs::read_to_string(path).map_err(|source| TlsError::Io {
+                file: path.to_string(),
+                source,
+            })
+        };
+        Self::from_pem(&read(certificate_path)?, &read(private_key_path)?, peers)
+    }
+
+    /// Builds the configuration from the PEM encoding of our certificate and private key.
+    pub fn from_pem(
+        certificate: &str,
+        private_key: &str,
+        peers: HashMap<SocketAddr, Vec<u8>>,
+    ) -> Result<Self, TlsError> {
+        let certificates = pemfile::certs(&mut certificate.as_bytes())
+            .ok()
+            .filter(|x| !x.is_empty())
+            .ok_or(TlsError::MissingCertificate)?;
+        let private_key = pemfile::pkcs8_private_keys(&mut private_key.as_bytes())
+            .ok()
+            .and_then(|mut x| x.pop())
+            .ok_or(TlsError::MissingPrivateKey)?;
+        let identity = identity(&certificates).map_err(|_| TlsError::NotEd25519)?;
+
+        let verifier = Arc::new(CommitteeVerifier(peers.values().cloned().collect()));
+        let mut server = ServerConfig::new(verifier);
+        server.set_single_cert(certificates.clone(), private_key.clone())?;
+        let acceptor = TlsAcceptor::from(Arc::new(server));
+
+        let mut connectors = HashMap::new();
+        for (address, key) in peers {
+            let mut client = ClientConfig::new();
+            client.set_single_client_cert(certificates.clone(), private_key.clone())?;
+            client
+                .dangerous()
+                .set_certificate_verifier(Arc::new(PinnedVerifier(key)));
+            connectors.insert(address, TlsConnector::from(Arc::new(client)));
+        }
+
+        Ok(Self {
+            identity,
+            acceptor,
+            connectors,
+        })
+    }
+
+    /// The public key of our certificate.
+    pub fn identity(&self) -> &[u8] {
+        &self.identity
+    }
+
+    /// Runs the client side of the TLS handshake on a connection to `address`.
+    pub(crate) async fn connect(
+        &self,
+        address: SocketAddr,
+        stream: TcpStream,
+    ) -> io::Result<Stream> {
+        let connector = self.connectors.get(&address).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("{} is not the address of a committee member", address),
+            )
+        })?;
+        let name = DNSNameRef::try_from_ascii_str(SERVER_NAME).expect("Invalid server name");
+        let stream = connector.connect(name, stream).await?;
+        Ok(Box::new(stream))
+    }
+
+    /// Runs the server side of the TLS handshake on an incoming connection.
+    pub(crate) async fn accept(&self, stream: TcpStream) -> io::Result<Stream> {
+        let stream = self.acceptor.accept(stream).await?;
+        Ok(Box::new(stream))
+    }
+}
+
+/// Installs the configuration used by all the senders and receivers created from now on (except those
+/// receiving connections from clients). Returns `false` if a configuration is already installed.
+pub fn install(config: TlsConfig) -> bool {
+    INSTALLED.set(Arc::new(config)).is_ok()
+}
+
+/// The installed configuration, if any.
+pub fn installed() -> Option<Arc<TlsConfig>> {
+    INSTALLED.get().cloned()
+}
+
+/// Opens a connection to `address`, encrypted if `tls` is set.
+pub(crate) async fn connect(address: SocketAddr, tls: Option<&TlsConfig>) -> io::Result<Stream> {
+    #[cfg(any(test, feature = "simulation"))]
+    {
+        if let Some((network, node)) = crate::memory::current() {
+            return network.connect(address, node);
+        }
+    }
+    let stream = TcpStream::connect(address).await?;
+    match tls {
+        Some(config) => config.connect(address, stream).await,
+        None => Ok(Box::new(stream)),
+    }
+}
+
+/// Generates a self-signed certificate whose public key is the ed25519 public key of `seed` (the 32 bytes
+/// of an ed25519 secret key). Returns the certificate and the private key, PEM-encoded.
+pub fn generate_certificate(seed: &[u8]) -> Result<(String, String), TlsError> {
+    let pkcs8 = [&PKCS8_ED25519_PREFIX[..], seed].concat();
+    let mut params = rcgen::CertificateParams::new(vec![SERVER_NAME.to_string()]);
+    params.alg = &rcgen::PKCS_ED25519;
+    params.key_pair = Some(rcgen::KeyPair::from_der(&pkcs8)?);
+    let certificate = rcgen::Certificate::from_params(params)?;
+    Ok((
+        certificate.serialize_pem()?,
+        certificate.serialize_private_key_pem(),
+    ))
+}
+
+/// Returns the ed25519 public key of the end-entity certificate of a chain.
+fn identity(certificates: &[Certificate]) -> Result<Vec<u8>, TLSError> {
+    let certificate = certificates
+        .first()
+        .ok_or(TLSError::NoCertificatesPresented)?;
+    let (_, certificate) = x509_parser::parse_x509_certificate(&certificate.0)
+        .map_err(|_| TLSError::General("Invalid peer certificate".to_string()))?;
+    let key = &certificate.tbs_certificate.subject_pki;
+    if key.algorithm.algorithm.to_id_string() != ED25519_OID {
+        return Err(TLSError::General(
+            "The peer certificate is not an ed25519 certificate".to_string(),
+        ));
+    }
+    Ok(key.subject_public_key.data.to_vec())
+}
+
+/// Accepts the clients presenting the public key of a committee member. The handshake itself proves that
+/// the client holds the matching private key.
+struct CommitteeVerifier(HashSet<Vec<u8>>);
+
+impl ClientCertVerifier for CommitteeVerifier {
+    fn client_auth_root_subjects(&self, _sni: Option<&DNSName>) -> Option<DistinguishedNames> {
+        Some(DistinguishedNames::new())
+    }
+
+    fn verify_client_cert(
+        &self,
+        presented_certs: &[Certificate],
+        _sni: Option<&DNSName>,
+    ) -> Result<ClientCertVerified, TLSError> {
+        if self.0.contains(&identity(presented_certs)?) {
+            Ok(ClientCertVerified::assertion())
+        } else {
+            Err(TLSError::General(
+                "The peer is not a committee member".to_string(),
+            ))
+        }
+    }
+}
+
+/// Accepts the server only if it presents the expected public key.
+struct PinnedVerifier(Vec<u8>);
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        _roots: &RootCertStore,
+        presented_certs: &[Certificate],
+        _dns_name: DNSNameRef<'_>,
+        _ocsp_response: &[u8],
+    ) -> Result<ServerCertVerified, TLSError> {
+        if identity(presented_certs)? == self.0 {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(TLSError::General(
+                "The peer presented an unexpected public key".to_string(),
+            ))
+        }
+    }
+}
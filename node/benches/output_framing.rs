@@ -0,0 +1,65 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+//! Compares the size of the certificates streamed to the subscribers, and how fast they are encoded and
+//! decoded, with the binary and the JSON framings. Run with `cargo bench --bench output_framing`.
+use crypto::{Digest, PublicKey, Signature};
+use node::Framing;
+use primary::{Certificate, Header};
+use std::time::Instant;
+
+/// The number of certificates encoded and decoded with each framing.
+const CERTIFICATES: usize = 10_000;
+
+/// The number of batches referenced by each header.
+const PAYLOAD: usize = 10;
+
+/// The size of the committee (the number of parents and votes of each certificate).
+const COMMITTEE: usize = 4;
+
+fn certificate(round: u64) -> Certificate {
+    let digest = |i: usize| {
+        let mut bytes = [0u8; 32];
+        bytes[..8].copy_from_slice(&(round * 1_000 + i as u64).to_be_bytes());
+        Digest(bytes)
+    };
+    let header = Header {
+        round,
+        payload: (0..PAYLOAD).map(|i| (digest(i), 0)).collect(),
+        parents: (PAYLOAD..PAYLOAD + COMMITTEE).map(digest).collect(),
+        timestamp: round,
+        ..Header::default()
+    };
+    Certificate {
+        header,
+        votes: (0..COMMITTEE)
+            .map(|_| (PublicKey::default(), Signature::default()))
+            .collect(),
+    }
+}
+
+fn main() {
+    let certificates: Vec<_> = (0..CERTIFICATES as u64).map(certificate).collect();
+    for framing in &[Framing::Binary, Framing::Json] {
+        let start = Instant::now();
+        let encoded: Vec<_> = certificates.iter().map(|x| framing.encode(x)).collect();
+        let encoding = start.elapsed();
+
+        let start = Instant::now();
+        for bytes in &encoded {
+            let decoded: Certificate = match framing {
+                Framing::Binary => bincode::deserialize(bytes).unwrap(),
+                Framing::Json => serde_json::from_slice(bytes).unwrap(),
+            };
+            assert_eq!(decoded.header.payload.len(), PAYLOAD);
+        }
+        let decoding = start.elapsed();
+
+        let size: usize = encoded.iter().map(|x| x.len()).sum();
+        println!(
+            "{:<6?}: {} B per certificate, encode {:.0} certificates/s, decode {:.0} certificates/s",
+            framing,
+            size / CERTIFICATES,
+            CERTIFICATES as f64 / encoding.as_secs_f64(),
+            CERTIFICATES as f64 / decoding.as_secs_f64()
+        );
+    }
+}
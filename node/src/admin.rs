@@ -0,0 +1,349 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::decryption::Decryptor;
+use crate::http::{reply, spawn_http_server, HttpHandler};
+use crate::throughput::Throughput;
+use async_trait::async_trait;
+use config::{Committee, WorkerId};
+use consensus::{read_leader_scores, Watermark, WATERMARK_KEY};
+use crypto::{Digest, Hash as _, PublicKey};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use primary::{Certificate, PeersHealth, Round, RoundIndex};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::time::Duration;
+use store::{KeyValueStore, PrefixedStore, Store, StoreError};
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+use worker::{ThresholdKeyRing, WorkerMessage};
+
+#[cfg(test)]
+#[path = "tests/admin_tests.rs"]
+pub mod admin_tests;
+
+/// The primary is considered ready if it processed certificates from a quorum within this window.
+pub const READINESS_WINDOW: Duration = Duration::from_secs(30);
+
+/// The store is considered writable if a probe value can be written and read back within this delay.
+const STORE_PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The (reserved) key under which the readiness probe writes to the store.
+const STORE_PROBE_KEY: &[u8] = b"\0admin-readiness-probe";
+
+/// The prefix of the path of the batch lookups.
+const BATCH_PATH: &str = "/batch/";
+
+/// The number of rounds of the DAG rendered by `/dag` when the request does not specify any.
+const DEFAULT_DAG_ROUNDS: Round = 10;
+
+/// The maximum number of rounds of the DAG rendered by `/dag`, so that the replies stay cheap to build
+/// on a loaded node (a round holds at most one certificate per authority).
+pub const MAX_DAG_ROUNDS: Round = 100;
+
+/// The store and threshold keys of each of the workers we run.
+pub type WorkerStores = HashMap<WorkerId, (PrefixedStore<Store>, ThresholdKeyRing)>;
+
+/// A small HTTP server exposing the liveness (`/healthz`) and readiness (`/readyz`) of the node, the
+/// authorities its primary suspects faulty (`/suspects`), the last rounds of its DAG (`/dag?rounds=N`),
+/// the scores of the leaders committed by its consensus (`/leaders`), its committed transaction throughput
+/// (`/throughput`), and the batches held by its workers (`/batch/{digest}`).
+pub struct AdminServer {
+    /// The committee information.
+    committee: Committee,
+    /// The health of our peers, as reported by the primary (if we run one).
+    peers_health: Option<PeersHealth>,
+    /// The data store of the node, which must be writable for the node to be ready.
+    store: Store,
+    /// The store holding the certificates of the primary and the watermark of the consensus (if we run
+    /// a primary).
+    dag_store: Option<Store>,
+    /// The workers in whose stores to look up batches.
+    workers: WorkerStores,
+    /// The throughput of the committed transactions (if we decrypt the committed batches).
+    throughput: Option<Throughput>,
+}
+
+impl AdminServer {
+    pub fn spawn(
+        address: SocketAddr,
+        committee: Committee,
+        peers_health: Option<PeersHealth>,
+        store: Store,
+        dag_store: Option<Store>,
+        workers: WorkerStores,
+        throughput: Option<Throughput>,
+    ) -> JoinHandle<()> {
+        spawn_http_server(
+            "admin",
+            address,
+            Self {
+                committee,
+                peers_health,
+                store,
+                dag_store,
+                workers,
+                throughput,
+            },
+        )
+    }
+
+    /// Reports whether the store is writable and the primary recently processed certificates from at
+    /// least 2f+1 authorities. A node without primary is ready as soon as its store is writable.
+    async fn readiness(&self) -> Response<Body> {
+        match &self.peers_health {
+            Some(health) if !health.is_ready(&self.committee, READINESS_WINDOW) => {
+                return reply(StatusCode::SERVICE_UNAVAILABLE, "not ready")
+            }
+            _ => (),
+        }
+        match timeout(STORE_PROBE_TIMEOUT, probe_store(&mut self.store.clone())).await {
+            Ok(true) => reply(StatusCode::OK, "ready"),
+            _ => reply(StatusCode::SERVICE_UNAVAILABLE, "store not writable"),
+        }
+    }
+
+    /// Lists the authorities missing from the DAG for too many rounds (none if we run no primary), along
+    /// with the last round at which the primary saw them.
+    fn suspects(&self) -> Response<Body> {
+        let suspects: Vec<_> = self
+            .peers_health
+            .as_ref()
+            .map(|x| x.suspects())
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(name, round)| {
+                serde_json::json!({
+                    "authority": name.to_string(),
+                    "last_seen_round": round,
+                })
+            })
+            .collect();
+        reply(
+            StatusCode::OK,
+            serde_json::json!({ "suspects": suspects }).to_string(),
+        )
+    }
+
+    /// Renders the certificates of the last `rounds=N` rounds of the DAG (at most `MAX_DAG_ROUNDS`) in the
+    /// GraphViz DOT format. The certificates are read from the store through the round index, so this
+    /// only shows those the primary processed; the ones the consensus committed are highlighted.
+    async fn dag(&self, query: Option<&str>) -> Response<Body> {
+        let store = match &self.dag_store {
+            Some(x) => x.clone(),
+            None => return reply(StatusCode::NOT_FOUND, "this node runs no primary"),
+        };
+        let rounds = match query
+            .unwrap_or_default()
+            .split('&')
+            .find_map(|x| x.strip_prefix("rounds="))
+        {
+            Some(x) => match x.parse::<Round>() {
+                Ok(x) if x > 0 => x.min(MAX_DAG_ROUNDS),
+                _ => return reply(StatusCode::BAD_REQUEST, "invalid number of rounds"),
+            },
+            None => DEFAULT_DAG_ROUNDS,
+        };
+
+        let mut round_index = RoundIndex::new(store.clone(), &self.committee);
+        let result = async {
+            let last = round_index.last_round().await?;
+            let mut certificates = Vec::new();
+            for round in last.saturating_sub(rounds - 1).max(1)..=last {
+                certificates.extend(round_index.certificates(round).await?);
+            }
+            let watermark = store.clone().read(WATERMARK_KEY.to_vec()).await?;
+            Ok::<_, StoreError>((certificates, watermark))
+        }
+        .await;
+        let (certificates, watermark) = match result {
+            Ok(x) => x,
+            Err(e) => {
+                return reply(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("failed to read the DAG: {}", e),
+                )
+            }
+        };
+        let last_committed = watermark
+            .and_then(|x| bincode::deserialize::<Watermark>(&x).ok())
+            .map(|x| x.last_committed)
+            .unwrap_or_default();
+        reply(StatusCode::OK, dot(&certificates, &last_committed))
+    }
+
+    /// Lists how many leader certificates of each authority the consensus committed, and how many leader
+    /// rounds it skipped while the authority was the leader.
+    async fn leaders(&self) -> Response<Body> {
+        let mut store = match &self.dag_store {
+            Some(x) => x.clone(),
+            None => return reply(StatusCode::NOT_FOUND, "this node runs no primary"),
+        };
+        let scores = match read_leader_scores(&mut store).await {
+            Ok(x) => x.unwrap_or_default(),
+            Err(e) => {
+                return reply(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("failed to read the leader scores: {}", e),
+                )
+            }
+        };
+        let leaders: Vec<_> = scores
+            .committee_scores(&self.committee)
+            .into_iter()
+            .map(|(name, score)| {
+                serde_json::json!({
+                    "authority": name.to_string(),
+                    "commits": score.commits,
+                    "skips": score.skips,
+                })
+            })
+            .collect();
+        reply(
+            StatusCode::OK,
+            serde_json::json!({
+                "last_leader_round": scores.last_leader_round,
+                "leaders": leaders,
+            })
+            .to_string(),
+        )
+    }
+
+    /// Reports the number of committed transactions and their rate (and the same for the unique ones, if
+    /// the node deduplicates the transactions). Only nodes decrypting the committed batches count them.
+    fn throughput(&self) -> Response<Body> {
+        match &self.throughput {
+            Some(throughput) => reply(
+                StatusCode::OK,
+                serde_json::to_string(&throughput.report()).unwrap(),
+            ),
+            None => reply(
+                StatusCode::NOT_FOUND,
+                "this node does not decrypt the committed batches",
+            ),
+        }
+    }
+
+    /// Looks up a batch by digest (in base64, with either the standard or the URL-safe alphabet) in the
+    /// stores of our workers. Returns its transactions in base64, decrypted if the batch carries enough
+    /// decryption shares and raw otherwise.
+    async fn batch(&self, encoded: &str) -> Response<Body> {
+        let digest = match base64::decode(encoded)
+            .or_else(|_| base64::decode_config(encoded, base64::URL_SAFE))
+            .ok()
+            .and_then(|x| Digest::try_from(x.as_slice()).ok())
+        {
+            Some(x) => x,
+            None => return reply(StatusCode::BAD_REQUEST, "invalid digest"),
+        };
+
+        for (id, (store, key_ring)) in &self.workers {
+            let serialized = match store.clone().read(digest.to_vec()).await {
+                Ok(Some(x)) => x,
+                Ok(None) => continue,
+                Err(e) => {
+                    return reply(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        format!("failed to read the store of worker {}: {}", id, e),
+                    )
+                }
+            };
+            // The stores of the workers only hold batches under their digest.
+            let (epoch, batch, shares) = match worker::decompress(serialized)
+                .ok()
+                .and_then(|x| bincode::deserialize(&x).ok())
+            {
+                Some(WorkerMessage::Batch(epoch, batch)) => (epoch, batch, None),
+                Some(WorkerMessage::DecryptableBatch(epoch, batch, shares)) => {
+                    (epoch, batch, Some(shares))
+                }
+                _ => continue,
+            };
+            let decrypted = shares.and_then(|shares| {
+                let pk_set = key_ring.public_key_set(epoch).ok()?;
+                Decryptor::decrypt_transactions(&pk_set, &digest, &batch, &shares).ok()
+            });
+            let body = serde_json::json!({
+                "digest": base64::encode(&digest),
+                "worker_id": id,
+                "epoch": epoch,
+                "decrypted": decrypted.is_some(),
+                "transactions": decrypted
+                    .unwrap_or(batch)
+                    .iter()
+                    .map(base64::encode)
+                    .collect::<Vec<_>>(),
+            });
+            return reply(StatusCode::OK, body.to_string());
+        }
+        reply(StatusCode::NOT_FOUND, "batch not found")
+    }
+}
+
+#[async_trait]
+impl HttpHandler for AdminServer {
+    async fn handle(&self, request: Request<Body>) -> Response<Body> {
+        match (request.method(), request.uri().path()) {
+            (&Method::GET, "/healthz") => reply(StatusCode::OK, "ok"),
+            (&Method::GET, "/readyz") => self.readiness().await,
+            (&Method::GET, "/suspects") => self.suspects(),
+            (&Method::GET, "/dag") => self.dag(request.uri().query()).await,
+            (&Method::GET, "/leaders") => self.leaders().await,
+            (&Method::GET, "/throughput") => self.throughput(),
+            (&Method::GET, path) if path.starts_with(BATCH_PATH) => {
+                self.batch(&path[BATCH_PATH.len()..]).await
+            }
+            _ => reply(StatusCode::NOT_FOUND, "not found"),
+        }
+    }
+}
+
+/// Writes a fresh value under a reserved key of the store, and checks that it reads it back.
+async fn probe_store<S: KeyValueStore>(store: &mut S) -> bool {
+    let value = rand::random::<u64>().to_be_bytes().to_vec();
+    store.write(STORE_PROBE_KEY.to_vec(), value.clone()).await;
+    matches!(store.read(STORE_PROBE_KEY.to_vec()).await, Ok(Some(x)) if x == value)
+}
+
+/// Renders certificates as a GraphViz digraph: one node per certificate (labeled by author and round),
+/// and one edge per parent among the rendered certificates. A certificate counts as committed if its
+/// round is not above the last committed round of its author.
+fn dot(certificates: &[Certificate], last_committed: &BTreeMap<PublicKey, Round>) -> String {
+    let digests: HashSet<_> = certificates.iter().map(|x| x.digest()).collect();
+    let mut graph = String::from("digraph dag {\n    rankdir=BT;\n    node [shape=box];\n");
+    for certificate in certificates {
+        let committed = last_committed
+            .get(&certificate.origin())
+            .map_or(false, |r| certificate.round() <= *r);
+        let style = if committed {
+            ", style=filled, fillcolor=lightgreen"
+        } else {
+            ""
+        };
+        let _ = writeln!(
+            graph,
+            "    \"{:?}\" [label=\"{}\\nround {}\"{}];",
+            certificate.digest(),
+            certificate.origin(),
+            certificate.round(),
+            style
+        );
+    }
+    for certificate in certificates {
+        for parent in certificate
+            .header
+            .parents
+            .iter()
+            .filter(|x| digests.contains(x))
+        {
+            let _ = writeln!(
+                graph,
+                "    \"{:?}\" -> \"{:?}\";",
+                certificate.digest(),
+                parent
+            );
+        }
+    }
+    graph.push_str("}\n");
+    graph
+}
@@ -4,7 +4,7 @@ use bytes::BufMut as _;
 use bytes::Bytes;
 use bytes::BytesMut;
 use clap::{crate_name, crate_version, App, AppSettings};
-use config::Import;
+use config::{Import, ThresholdPublicKeyFile};
 use crypto::threshold::PublicKey as ThresholdPublicKey;
 use env_logger::Env;
 use futures::future::join_all;
@@ -41,8 +41,9 @@ async fn main() -> Result<()> {
         .parse::<SocketAddr>()
         .context("Invalid socket address format")?;
     let threshold_pk_file = matches.value_of("threshold_pk").unwrap();
-    let threshold_pk =
-        ThresholdPublicKey::import(threshold_pk_file).expect("Failed to load threshold public key");
+    let threshold_pk = ThresholdPublicKeyFile::import(threshold_pk_file)
+        .expect("Failed to load threshold public key")
+        .public_key;
     let size = matches
         .value_of("size")
         .unwrap()
@@ -177,9 +178,8 @@ impl Client {
                 msg = r; // Ensures all clients send different txs.
             };
 
-            let ciphertext = self.threshold_pk.encrypt(msg.to_le_bytes());
             let serialized_ciphertext: SerializedCiphertext =
-                bincode::serialize(&ciphertext).unwrap();
+                config::threshold::encrypt(&self.threshold_pk, &msg.to_le_bytes());
             // The messages we send consist of a serialized ciphertext followed by a suffix_byte
             // (0 for sample (benchmark) txs and 1 for standard txs) and the msg (8 bytes)
             // info!("benchmark_client: sending {:?} to target validator", tx);
@@ -0,0 +1,254 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use bytes::Bytes;
+use futures::sink::SinkExt as _;
+use futures::stream::StreamExt as _;
+use log::{info, warn};
+use primary::Certificate;
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::task::JoinHandle;
+use tokio::time::{timeout, Duration};
+use tokio_util::codec::{FramedRead, FramedWrite, LengthDelimitedCodec};
+
+#[cfg(test)]
+#[path = "tests/certificate_stream_tests.rs"]
+pub mod certificate_stream_tests;
+
+/// The number of certificates buffered for each subscriber. Subscribers falling further behind are dropped.
+pub const SUBSCRIBER_BUFFER: usize = 1_000;
+
+/// The delay within which a new subscriber sends the byte choosing its framing. The subscribers that send
+/// nothing (those written before the framing could be chosen) get the binary framing.
+pub const NEGOTIATION_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// How the certificates are written to a subscriber, as chosen by the first byte it sends.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Framing {
+    /// Bincode-serialized certificates, each prefixed by its length (as a 4-byte big-endian integer).
+    Binary,
+    /// JSON-serialized certificates, one per line. Convenient, but much slower to encode and decode.
+    Json,
+}
+
+impl Framing {
+    /// The byte a subscriber sends to choose the binary framing.
+    pub const BINARY: u8 = b'B';
+    /// The byte a subscriber sends to choose the JSON framing.
+    pub const JSON: u8 = b'J';
+
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            Self::BINARY => Some(Self::Binary),
+            Self::JSON => Some(Self::Json),
+            _ => None,
+        }
+    }
+
+    /// The byte choosing this framing.
+    pub fn header(&self) -> u8 {
+        match self {
+            Self::Binary => Self::BINARY,
+            Self::Json => Self::JSON,
+        }
+    }
+
+    /// Serializes a certificate (the binary frames get their length prefix when written).
+    pub fn encode(&self, certificate: &Certificate) -> Bytes {
+        match self {
+            Self::Binary => bincode::serialize(certificate)
+                .expect("Failed to serialize certificate")
+                .into(),
+            Self::Json => {
+                let mut line =
+                    serde_json::to_vec(certificate).expect("Failed to serialize certificate");
+                line.push(b'\n');
+                line.into()
+            }
+        }
+    }
+}
+
+/// Fans out the ordered certificates to the application and to every subscriber connected over TCP.
+/// Each subscriber chooses its `Framing` by sending a single byte upon connection.
+pub struct CertificateStream {
+    /// The address on which to accept subscribers.
+    address: SocketAddr,
+    /// Receives the ordered certificates from consensus.
+    rx_output: Receiver<Certificate>,
+    /// Forwards the ordered certificates to the application.
+    tx_application: Sender<Certificate>,
+    /// Receives the subscribers once they chose their framing.
+    rx_subscribers: Receiver<(SocketAddr, Framing, Sender<Bytes>)>,
+    /// Hands the subscribers that chose their framing to the stream.
+    tx_subscribers: Sender<(SocketAddr, Framing, Sender<Bytes>)>,
+    /// The channels feeding the connected subscribers.
+    subscribers: Vec<(SocketAddr, Framing, Sender<Bytes>)>,
+}
+
+impl CertificateStream {
+    pub fn spawn(
+        address: SocketAddr,
+        rx_output: Receiver<Certificate>,
+        tx_application: Sender<Certificate>,
+    ) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let (tx_subscribers, rx_subscribers) = channel(SUBSCRIBER_BUFFER);
+            Self {
+                address,
+                rx_output,
+                tx_application,
+                rx_subscribers,
+                tx_subscribers,
+                subscribers: Vec::new(),
+            }
+            .run()
+            .await;
+        })
+    }
+
+    /// Send a certificate to all subscribers (serializing it once per framing), dropping the ones that
+    /// cannot keep up.
+    fn broadcast(&mut self, certificate: &Certificate) {
+        let mut encoded: HashMap<Framing, Bytes> = HashMap::new();
+        self.subscribers.retain(|(peer, framing, tx)| {
+            let bytes = encoded
+                .entry(*framing)
+                .or_insert_with(|| framing.encode(certificate))
+                .clone();
+            match tx.try_send(bytes) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    warn!("Dropping slow certificate subscriber {}", peer);
+                    false
+                }
+                Err(TrySendError::Closed(_)) => false,
+            }
+        });
+    }
+
+    /// Spawn a task reading the framing chosen by a newly connected subscriber, and then writing the
+    /// certificates to it.
+    fn subscribe(&self, mut socket: TcpStream, peer: SocketAddr) {
+        let tx_subscribers = self.tx_subscribers.clone();
+        tokio::spawn(async move {
+            let mut header = [0u8; 1];
+            let framing = match timeout(NEGOTIATION_TIMEOUT, socket.read_exact(&mut header)).await {
+                Ok(Ok(_)) => match Framing::from_byte(header[0]) {
+                    Some(x) => x,
+                    None => {
+                        warn!(
+                            "Certificate subscriber {} asked for an unknown framing",
+                            peer
+                        );
+                        return;
+                    }
+                },
+                Ok(Err(e)) => {
+                    warn!(
+                        "Failed to read the framing of certificate subscriber {}: {}",
+                        peer, e
+                    );
+                    return;
+                }
+                Err(_) => Framing::Binary,
+            };
+            info!(
+                "Certificate subscriber {} uses the {:?} framing",
+                peer, framing
+            );
+
+            let (tx, mut rx) = channel(SUBSCRIBER_BUFFER);
+            if tx_subscribers.send((peer, framing, tx)).await.is_err() {
+                return;
+            }
+            let result = match framing {
+                Framing::Binary => {
+                    let mut writer = FramedWrite::new(socket, LengthDelimitedCodec::new());
+                    loop {
+                        match rx.recv().await {
+                            Some(serialized) => match writer.send(serialized).await {
+                                Ok(()) => (),
+                                Err(e) => break Err(e),
+                            },
+                            None => break Ok(()),
+                        }
+                    }
+                }
+                Framing::Json => loop {
+                    match rx.recv().await {
+                        Some(line) => match socket.write_all(&line).await {
+                            Ok(()) => (),
+                            Err(e) => break Err(e),
+                        },
+                        None => break Ok(()),
+                    }
+                },
+            };
+            if let Err(e) = result {
+                warn!("Failed to stream certificate to {}: {}", peer, e);
+            }
+        });
+    }
+
+    async fn run(&mut self) {
+        let listener = TcpListener::bind(&self.address)
+            .await
+            .expect("Failed to bind the certificate stream TCP port");
+        info!("Streaming committed certificates on {}", self.address);
+
+        loop {
+            tokio::select! {
+                certificate = self.rx_output.recv() => match certificate {
+                    Some(certificate) => {
+                        self.broadcast(&certificate);
+                        self.tx_application
+                            .send(certificate)
+                            .await
+                            .expect("Failed to deliver certificate to the application");
+                    },
+                    // The consensus stopped: so does the application, once it processed the output.
+                    None => return,
+                },
+                Some(subscriber) = self.rx_subscribers.recv() => self.subscribers.push(subscriber),
+                result = listener.accept() => match result {
+                    Ok((socket, peer)) => {
+                        info!("Certificate subscriber {} connected", peer);
+                        self.subscribe(socket, peer);
+                    },
+                    Err(e) => warn!("Failed to accept certificate subscriber: {}", e),
+                },
+            }
+        }
+    }
+}
+
+/// Subscribes to the certificate stream of a node with the binary framing, and decodes the certificates.
+pub struct CertificateSubscriber {
+    reader: FramedRead<TcpStream, LengthDelimitedCodec>,
+}
+
+impl CertificateSubscriber {
+    pub async fn connect(address: SocketAddr) -> io::Result<Self> {
+        let mut socket = TcpStream::connect(address).await?;
+        socket.write_all(&[Framing::Binary.header()]).await?;
+        Ok(Self {
+            reader: FramedRead::new(socket, LengthDelimitedCodec::new()),
+        })
+    }
+
+    /// Returns the next committed certificate, or `None` once the node closed the stream.
+    pub async fn recv(&mut self) -> Option<io::Result<Certificate>> {
+        let frame = match self.reader.next().await? {
+            Ok(x) => x,
+            Err(e) => return Some(Err(e)),
+        };
+        Some(
+            bincode::deserialize(&frame).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        )
+    }
+}
@@ -0,0 +1,266 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use config::threshold::{self, ThresholdError};
+use config::WorkerId;
+use crypto::threshold::{DecryptionShare, PublicKeySet};
+use crypto::{BatchDecryptionShares, Digest};
+use futures::future::join_all;
+use primary::Certificate;
+use rayon::prelude::*;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use store::{Store, StoreError};
+use thiserror::Error;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::oneshot;
+use tokio::time::{timeout_at, Duration, Instant};
+use worker::{strip_priority, KeyRingError, ShareRequest, ThresholdKeyRing, WorkerMessage};
+
+#[cfg(test)]
+#[path = "tests/decryption_tests.rs"]
+pub mod decryption_tests;
+
+pub type DecryptionResult<T> = Result<T, DecryptionError>;
+
+#[derive(Debug, Error)]
+pub enum DecryptionError {
+    #[error("No threshold key for worker {0}")]
+    UnknownWorker(WorkerId),
+
+    #[error("Cannot decrypt batch {0}: {1}")]
+    KeyRing(Digest, KeyRingError),
+
+    #[error("Storage failure: {0}")]
+    StoreError(#[from] StoreError),
+
+    #[error("Batch {0} is not in the store")]
+    MissingBatch(Digest),
+
+    #[error("Failed to deserialize batch {0}: {1}")]
+    MalformedBatch(Digest, String),
+
+    #[error("Batch {0} does not carry decryption shares")]
+    NoDecryptionShares(Digest),
+
+    #[error("Transaction {1} of batch {0} is not a valid ciphertext")]
+    InvalidCiphertext(Digest, usize),
+
+    #[error(
+        "Transaction {index} of batch {digest} has {valid} valid and {invalid} invalid decryption shares ({required} required)"
+    )]
+    NotEnoughShares {
+        digest: Digest,
+        index: usize,
+        valid: usize,
+        invalid: usize,
+        required: usize,
+    },
+
+    #[error("The workers failed to gather enough decryption shares for batch {0}")]
+    SharesUnavailable(Digest),
+
+    #[error("Failed to combine the decryption shares of transaction {1} of batch {0}")]
+    CombineFailed(Digest, usize),
+
+    #[error("Gave up decrypting batch {0} after {1} ms")]
+    Timeout(Digest, u64),
+}
+
+/// Why a committed batch could not be decrypted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DecryptionFailure {
+    /// One of the transactions of the batch is not a valid ciphertext.
+    BadCiphertext,
+    /// Not enough nodes provided their decryption share (in time).
+    MissingShares,
+    /// Enough nodes provided their decryption share, but some of them are invalid.
+    BadShare,
+    /// The batch or the keys to decrypt it are not available to this node.
+    Unavailable,
+}
+
+impl DecryptionError {
+    pub fn failure(&self) -> DecryptionFailure {
+        match self {
+            Self::InvalidCiphertext(..) => DecryptionFailure::BadCiphertext,
+            Self::NotEnoughShares {
+                valid,
+                invalid,
+                required,
+                ..
+            } if *invalid > 0 && valid + invalid >= *required => DecryptionFailure::BadShare,
+            Self::CombineFailed(..) => DecryptionFailure::BadShare,
+            Self::NotEnoughShares { .. }
+            | Self::NoDecryptionShares(..)
+            | Self::SharesUnavailable(..)
+            | Self::Timeout(..) => DecryptionFailure::MissingShares,
+            Self::UnknownWorker(..)
+            | Self::KeyRing(..)
+            | Self::StoreError(..)
+            | Self::MissingBatch(..)
+            | Self::MalformedBatch(..) => DecryptionFailure::Unavailable,
+        }
+    }
+}
+
+/// The plaintext transactions of a committed batch.
+#[derive(Debug)]
+pub struct DecryptedBatch {
+    /// The digest of the batch.
+    pub digest: Digest,
+    /// The id of the worker that holds the batch.
+    pub worker_id: WorkerId,
+    /// The decrypted transactions, in the order they appear in the batch.
+    pub transactions: Vec<Vec<u8>>,
+}
+
+/// Decrypts the batches of committed certificates by combining the decryption shares stored alongside them.
+/// When these are not enough, the worker gathers the missing shares from the other authorities, for at most
+/// `timeout` per certificate. It must only be fed committed certificates: the transactions stay hidden until their
+/// order is fixed.
+pub struct Decryptor {
+    /// The threshold keys, the store, and the share requests channel of each of our workers.
+    workers: HashMap<WorkerId, (ThresholdKeyRing, Store, Sender<ShareRequest>)>,
+    /// The delay after which we give up decrypting the batches of a certificate.
+    timeout: Duration,
+}
+
+impl Decryptor {
+    pub fn new(
+        workers: HashMap<WorkerId, (ThresholdKeyRing, Store, Sender<ShareRequest>)>,
+        timeout: Duration,
+    ) -> Self {
+        Self { workers, timeout }
+    }
+
+    /// Decrypts all the batches referenced by a committed certificate. The batches are decrypted concurrently
+    /// under a single deadline, so a certificate never holds the output back for more than the timeout. The
+    /// batches that cannot be decrypted before the deadline are reported as failed.
+    pub async fn decrypt_certificate(
+        &self,
+        certificate: &Certificate,
+    ) -> Vec<DecryptionResult<DecryptedBatch>> {
+        let deadline = Instant::now() + self.timeout;
+        let batches = certificate
+            .header
+            .payload
+            .iter()
+            .map(|(digest, worker_id)| async move {
+                timeout_at(deadline, self.decrypt_batch(digest, *worker_id))
+                    .await
+                    .unwrap_or_else(|_| {
+                        Err(DecryptionError::Timeout(
+                            digest.clone(),
+                            self.timeout.as_millis() as u64,
+                        ))
+                    })
+            });
+        join_all(batches).await
+    }
+
+    /// Decrypts a single batch. The batch must already be in the store of the worker: its certificate could
+    /// not have been committed otherwise.
+    pub async fn decrypt_batch(
+        &self,
+        digest: &Digest,
+        worker_id: WorkerId,
+    ) -> DecryptionResult<DecryptedBatch> {
+        let (key_ring, store, tx_share_request) = self
+            .workers
+            .get(&worker_id)
+            .ok_or(DecryptionError::UnknownWorker(worker_id))?;
+
+        let serialized = store
+            .clone()
+            .read(digest.to_vec())
+            .await?
+            .ok_or_else(|| DecryptionError::MissingBatch(digest.clone()))?;
+        let serialized = worker::decompress(serialized)
+            .map_err(|e| DecryptionError::MalformedBatch(digest.clone(), e.to_string()))?;
+        let (epoch, batch, shares) = match bincode::deserialize(&serialized) {
+            Ok(WorkerMessage::DecryptableBatch(epoch, batch, shares)) => (epoch, batch, shares),
+            Ok(_) => return Err(DecryptionError::NoDecryptionShares(digest.clone())),
+            Err(e) => {
+                return Err(DecryptionError::MalformedBatch(
+                    digest.clone(),
+                    e.to_string(),
+                ))
+            }
+        };
+
+        // Select the key set the batch was encrypted with.
+        let pk_set = key_ring
+            .public_key_set(epoch)
+            .map_err(|e| DecryptionError::KeyRing(digest.clone(), e))?;
+
+        let transactions = match Self::decrypt_transactions(&pk_set, digest, &batch, &shares) {
+            Err(DecryptionError::NotEnoughShares { .. }) => {
+                let (sender, receiver) = oneshot::channel();
+                tx_share_request
+                    .send((digest.clone(), sender))
+                    .await
+                    .map_err(|_| DecryptionError::SharesUnavailable(digest.clone()))?;
+                let shares = receiver
+                    .await
+                    .map_err(|_| DecryptionError::SharesUnavailable(digest.clone()))?;
+                Self::decrypt_transactions(&pk_set, digest, &batch, &shares)?
+            }
+            result => result?,
+        };
+
+        Ok(DecryptedBatch {
+            digest: digest.clone(),
+            worker_id,
+            transactions,
+        })
+    }
+
+    /// Decrypts all the transactions of a batch.
+    pub(crate) fn decrypt_transactions(
+        pk_set: &PublicKeySet,
+        digest: &Digest,
+        batch: &[Vec<u8>],
+        shares: &BatchDecryptionShares,
+    ) -> DecryptionResult<Vec<Vec<u8>>> {
+        batch
+            .par_iter()
+            .enumerate()
+            .map(|(index, tx)| Self::decrypt_transaction(pk_set, digest, index, tx, shares))
+            .collect()
+    }
+
+    /// Decrypts the `index`-th transaction of a batch using the valid shares of the other nodes.
+    fn decrypt_transaction(
+        pk_set: &PublicKeySet,
+        digest: &Digest,
+        index: usize,
+        tx: &[u8],
+        shares: &BatchDecryptionShares,
+    ) -> DecryptionResult<Vec<u8>> {
+        let received: BTreeMap<usize, DecryptionShare> = shares
+            .iter()
+            .filter_map(|(node_index, node_shares)| {
+                node_shares
+                    .get(index)
+                    .map(|share| (*node_index, share.clone()))
+            })
+            .collect();
+
+        threshold::decrypt_shares(pk_set, strip_priority(tx), &received).map_err(|e| match e {
+            ThresholdError::InvalidCiphertext => {
+                DecryptionError::InvalidCiphertext(digest.clone(), index)
+            }
+            // The shares that are not valid were incorrectly computed by their node.
+            ThresholdError::NotEnoughShares { valid, required } => {
+                DecryptionError::NotEnoughShares {
+                    digest: digest.clone(),
+                    index,
+                    valid,
+                    invalid: received.len() - valid,
+                    required,
+                }
+            }
+            ThresholdError::CombineFailed => DecryptionError::CombineFailed(digest.clone(), index),
+        })
+    }
+}
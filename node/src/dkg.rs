@@ -0,0 +1,445 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use async_trait::async_trait;
+use bytes::Bytes;
+use config::{Committee, KeyPair, ThresholdKeyPair};
+use crypto::threshold::{
+    Ciphertext, Commitment, Poly, PublicKey as EncryptionKey, PublicKeySet,
+    SecretKey as DecryptionKey, SecretKeyShare, SerdeSecret,
+};
+use crypto::{hash, Digest, PublicKey, SecretKey, Signature};
+use futures::future::join_all;
+use futures::sink::SinkExt as _;
+use log::{debug, info, warn};
+use network::{CancelHandler, MessageHandler, ReliableSender, Writer};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use thiserror::Error;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+
+#[cfg(test)]
+#[path = "tests/dkg_tests.rs"]
+pub mod dkg_tests;
+
+/// The capacity of the channel delivering the messages of the other authorities.
+const CHANNEL_CAPACITY: usize = 1_000;
+
+pub type DkgResult<T> = Result<T, DkgError>;
+
+#[derive(Debug, Error)]
+pub enum DkgError {
+    #[error("Node {0} is not in the committee")]
+    NotInCommittee(PublicKey),
+
+    #[error("The threshold ({threshold}) must be smaller than the committee size ({size})")]
+    InvalidThreshold { threshold: usize, size: usize },
+
+    #[error("Only {qualified} authorities dealt valid shares ({required} required)")]
+    NotEnoughDealers { qualified: usize, required: usize },
+
+    #[error("The network receiver stopped before the key generation completed")]
+    NetworkClosed,
+}
+
+/// The content of the messages exchanged during the key generation.
+#[derive(Serialize, Deserialize)]
+enum DkgPayload {
+    /// The (ephemeral) key with which the other authorities encrypt the shares they deal to the author.
+    EncryptionKey(EncryptionKey),
+    /// The commitment to the polynomial of the author, and the share of every authority (in committee
+    /// order) encrypted with its encryption key.
+    Deal(Commitment, Vec<Ciphertext>),
+    /// The dealers whose share to the author does not match their commitment (possibly none).
+    Complaints(Vec<PublicKey>),
+    /// The (plaintext) shares the author dealt to the authorities that complained about it.
+    Justification(Vec<(PublicKey, Poly)>),
+}
+
+/// A deal received from an authority.
+struct Deal {
+    /// The commitment to the polynomial of the dealer.
+    commitment: Commitment,
+    /// The share dealt to us, if it matches the commitment.
+    share: Option<Poly>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct DkgMessage {
+    author: PublicKey,
+    payload: DkgPayload,
+    signature: Signature,
+}
+
+impl DkgMessage {
+    fn new(author: PublicKey, payload: DkgPayload, secret: &SecretKey) -> Self {
+        let signature = Signature::new(&Self::digest(&author, &payload), secret);
+        Self {
+            author,
+            payload,
+            signature,
+        }
+    }
+
+    fn digest(author: &PublicKey, payload: &DkgPayload) -> Digest {
+        let bytes =
+            bincode::serialize(&(author, payload)).expect("Failed to serialize DKG message");
+        hash(&bytes)
+    }
+
+    fn verify(&self, committee: &Committee) -> bool {
+        committee.stake(&self.author) > 0
+            && self
+                .signature
+                .verify(&Self::digest(&self.author, &self.payload), &self.author)
+                .is_ok()
+    }
+}
+
+/// Joint-Feldman (Pedersen) distributed key generation. Every authority deals a random polynomial of degree
+/// `threshold`, sends to each other authority the evaluation of this polynomial at its index (encrypted with
+/// an ephemeral key), and publishes a commitment to it. The secret share of each authority is the sum of the
+/// evaluations it receives; the threshold public key is the sum of the commitments. No party ever learns
+/// the full secret key.
+///
+/// An authority whose share does not match the commitment of its dealer complains about it. The dealer must
+/// then reveal the plaintext share to everyone: dealers that fail to reveal a valid share are disqualified,
+/// and only the deals of the qualified dealers are summed.
+///
+/// The protocol is synchronous: all the authorities of the committee must run it at the same time, and each
+/// round waits for the messages of all the authorities.
+pub struct Dkg {
+    /// The keypair of this authority, used to authenticate our messages.
+    keypair: KeyPair,
+    /// The committee information.
+    committee: Committee,
+    /// The degree of the polynomials: decryption requires `threshold + 1` shares.
+    threshold: usize,
+    /// The authorities of the committee, in order: their position is their share index.
+    authorities: Vec<PublicKey>,
+    /// Our share index.
+    index: usize,
+    /// Our ephemeral key to decrypt the shares dealt to us.
+    decryption_key: DecryptionKey,
+    /// The ephemeral encryption keys of the authorities.
+    encryption_keys: BTreeMap<PublicKey, EncryptionKey>,
+    /// The polynomial we dealt, kept to answer complaints.
+    polynomial: Option<Poly>,
+    /// The deals of the authorities.
+    deals: BTreeMap<PublicKey, Deal>,
+    /// The dealers whose deal is malformed (for everyone), or who failed to justify their deal.
+    disqualified: HashSet<PublicKey>,
+    /// The complaints of each authority.
+    complaints: BTreeMap<PublicKey, Vec<PublicKey>>,
+    /// The justification of each dealer.
+    justifications: BTreeMap<PublicKey, Vec<(PublicKey, Poly)>>,
+    /// Receives the messages of the other authorities.
+    rx_message: Receiver<DkgMessage>,
+    /// A network sender to broadcast our messages.
+    network: ReliableSender,
+    /// The handlers of our broadcasts, awaited before returning so that every authority gets our messages.
+    cancel_handlers: Vec<CancelHandler>,
+}
+
+impl Dkg {
+    /// Runs the key generation with the other authorities of the committee, using our primary-to-primary
+    /// address to receive their messages.
+    pub async fn run(
+        keypair: KeyPair,
+        committee: Committee,
+        threshold: usize,
+    ) -> DkgResult<ThresholdKeyPair> {
+        let authorities: Vec<_> = committee.authorities.keys().cloned().collect();
+        let index = authorities
+            .iter()
+            .position(|x| x == &keypair.name)
+            .ok_or(DkgError::NotInCommittee(keypair.name))?;
+        if threshold >= authorities.len() {
+            return Err(DkgError::InvalidThreshold {
+                threshold,
+                size: authorities.len(),
+            });
+        }
+
+        let (tx_message, rx_message) = channel(CHANNEL_CAPACITY);
+        let mut address = committee
+            .primary(&keypair.name)
+            .map_err(|_| DkgError::NotInCommittee(keypair.name))?
+            .primary_to_primary;
+        address.set_ip("0.0.0.0".parse().unwrap());
+        let receiver = network::Receiver::spawn(address, DkgReceiverHandler { tx_message });
+        info!("Running the distributed key generation on {}", address);
+
+        let result = Self {
+            keypair,
+            committee,
+            threshold,
+            authorities,
+            index,
+            decryption_key: DecryptionKey::random(),
+            encryption_keys: BTreeMap::new(),
+            polynomial: None,
+            deals: BTreeMap::new(),
+            disqualified: HashSet::new(),
+            complaints: BTreeMap::new(),
+            justifications: BTreeMap::new(),
+            rx_message,
+            network: ReliableSender::new(),
+            cancel_handlers: Vec::new(),
+        }
+        .generate()
+        .await;
+        receiver.abort();
+        result
+    }
+
+    async fn generate(mut self) -> DkgResult<ThresholdKeyPair> {
+        // Publish our encryption key.
+        let encryption_key = self.decryption_key.public_key();
+        self.encryption_keys
+            .insert(self.keypair.name, encryption_key.clone());
+        self.broadcast(DkgPayload::EncryptionKey(encryption_key))
+            .await;
+        self.advance().await;
+
+        while self.justifications.len() < self.authorities.len() {
+            let message = self
+                .rx_message
+                .recv()
+                .await
+                .ok_or(DkgError::NetworkClosed)?;
+            if !message.verify(&self.committee) {
+                warn!(
+                    "Ignoring DKG message with invalid signature from {}",
+                    message.author
+                );
+                continue;
+            }
+            let author = message.author;
+            match message.payload {
+                DkgPayload::EncryptionKey(key) => {
+                    debug!("Received the encryption key of {}", author);
+                    self.encryption_keys.entry(author).or_insert(key);
+                }
+                DkgPayload::Deal(commitment, shares) => {
+                    self.process_deal(author, commitment, &shares)
+                }
+                DkgPayload::Complaints(accused) => {
+                    debug!("Received the complaints of {}", author);
+                    self.complaints.entry(author).or_insert(accused);
+                }
+                DkgPayload::Justification(revealed) => {
+                    debug!("Received the justification of {}", author);
+                    self.justifications.entry(author).or_insert(revealed);
+                }
+            }
+            self.advance().await;
+        }
+
+        // Ensure every authority received our messages before leaving.
+        join_all(self.cancel_handlers.drain(..)).await;
+        self.finish()
+    }
+
+    /// Moves to the next round once we received the messages of the current round from all authorities:
+    /// we deal once we know all encryption keys, complain once we received all deals, and justify our deal
+    /// once we received all complaints.
+    async fn advance(&mut self) {
+        let size = self.authorities.len();
+        if self.encryption_keys.len() == size && self.polynomial.is_none() {
+            self.deal().await;
+        }
+        if self.deals.len() == size && !self.complaints.contains_key(&self.keypair.name) {
+            let accused: Vec<_> = self
+                .deals
+                .iter()
+                .filter(|(dealer, deal)| {
+                    deal.share.is_none() && !self.disqualified.contains(dealer)
+                })
+                .map(|(dealer, _)| *dealer)
+                .collect();
+            for dealer in &accused {
+                warn!("Complaining about the deal of {}", dealer);
+            }
+            self.complaints.insert(self.keypair.name, accused.clone());
+            self.broadcast(DkgPayload::Complaints(accused)).await;
+        }
+        if self.complaints.len() == size && !self.justifications.contains_key(&self.keypair.name) {
+            let polynomial = self
+                .polynomial
+                .as_ref()
+                .expect("Complaints received before dealing");
+            let revealed: Vec<_> = self
+                .complaints
+                .iter()
+                .filter(|(_, accused)| accused.contains(&self.keypair.name))
+                .map(|(complainer, _)| {
+                    let index = self.share_index(complainer);
+                    (*complainer, Poly::constant(polynomial.evaluate(index)))
+                })
+                .collect();
+            self.justifications
+                .insert(self.keypair.name, revealed.clone());
+            self.broadcast(DkgPayload::Justification(revealed)).await;
+        }
+    }
+
+    /// Deals a random polynomial to all authorities (including ourselves).
+    async fn deal(&mut self) {
+        let polynomial = Poly::random(self.threshold, &mut rand::thread_rng());
+        let commitment = polynomial.commitment();
+        let shares: Vec<_> = self
+            .authorities
+            .iter()
+            .map(|name| {
+                let share = Poly::constant(polynomial.evaluate(self.share_index(name)));
+                let bytes = bincode::serialize(&share).expect("Failed to serialize share");
+                self.encryption_keys[name].encrypt(bytes)
+            })
+            .collect();
+        self.polynomial = Some(polynomial);
+
+        self.process_deal(self.keypair.name, commitment.clone(), &shares);
+        self.broadcast(DkgPayload::Deal(commitment, shares)).await;
+    }
+
+    /// Checks the share dealt to us by `dealer` against its commitment. Malformed deals disqualify their
+    /// dealer; we complain about the shares that do not match their commitment.
+    fn process_deal(&mut self, dealer: PublicKey, commitment: Commitment, shares: &[Ciphertext]) {
+        if self.deals.contains_key(&dealer) {
+            return;
+        }
+
+        if commitment.degree() != self.threshold || shares.len() != self.authorities.len() {
+            warn!("Disqualifying {}: its deal is malformed", dealer);
+            self.disqualified.insert(dealer);
+        }
+        let index = self.share_index(&self.keypair.name);
+        let share = shares
+            .get(self.index)
+            .and_then(|ciphertext| self.decryption_key.decrypt(ciphertext))
+            .and_then(|bytes| bincode::deserialize::<Poly>(&bytes).ok())
+            .filter(|share| commitment.evaluate(index) == share.commitment().evaluate(0usize));
+
+        debug!("Received the deal of {}", dealer);
+        self.deals.insert(dealer, Deal { commitment, share });
+    }
+
+    /// Sums the deals of the qualified dealers into our threshold keypair. A dealer is disqualified if its
+    /// deal is malformed, or if it fails to reveal a share matching its commitment to any authority that
+    /// complained about it.
+    fn finish(mut self) -> DkgResult<ThresholdKeyPair> {
+        for (complainer, accused) in &self.complaints {
+            for dealer in accused {
+                let commitment = match self.deals.get(dealer) {
+                    Some(deal) => &deal.commitment,
+                    None => continue,
+                };
+                let revealed = self.justifications[dealer]
+                    .iter()
+                    .find(|(name, _)| name == complainer)
+                    .map(|(_, share)| share.clone());
+                let index = self.share_index(complainer);
+                match revealed {
+                    Some(share)
+                        if commitment.evaluate(index) == share.commitment().evaluate(0usize) =>
+                    {
+                        if complainer == &self.keypair.name {
+                            self.deals.get_mut(dealer).unwrap().share = Some(share);
+                        }
+                    }
+                    _ => {
+                        warn!("Disqualifying {}: it failed to justify its deal", dealer);
+                        self.disqualified.insert(*dealer);
+                    }
+                }
+            }
+        }
+
+        let mut share = Poly::zero();
+        let mut commitment = Poly::zero().commitment();
+        let mut qualified = 0;
+        for (dealer, deal) in &self.deals {
+            if self.disqualified.contains(dealer) {
+                continue;
+            }
+            // Our own complaints were answered above: a qualified dealer always has a share for us.
+            share += deal
+                .share
+                .as_ref()
+                .expect("Qualified dealer without a valid share");
+            commitment += &deal.commitment;
+            qualified += 1;
+        }
+
+        // At least one of the qualified dealers must be honest for the key to remain secret.
+        if qualified <= self.threshold {
+            return Err(DkgError::NotEnoughDealers {
+                qualified,
+                required: self.threshold + 1,
+            });
+        }
+
+        let mut secret = share.evaluate(0usize);
+        let keypair = ThresholdKeyPair {
+            node_index: self.index,
+            sk_share: SerdeSecret(SecretKeyShare::from_mut(&mut secret)),
+            pk_set: PublicKeySet::from(commitment),
+            epoch: 0,
+            keygen: None,
+        };
+        info!(
+            "Distributed key generation completed with {} qualified dealers",
+            qualified
+        );
+        Ok(keypair)
+    }
+
+    /// The point at which the polynomials are evaluated to derive the share of `name`.
+    fn share_index(&self, name: &PublicKey) -> usize {
+        self.authorities
+            .iter()
+            .position(|x| x == name)
+            .expect("Unknown authority")
+            + 1
+    }
+
+    /// Reliably broadcasts a message to all other authorities.
+    async fn broadcast(&mut self, payload: DkgPayload) {
+        let message = DkgMessage::new(self.keypair.name, payload, &self.keypair.secret);
+        let bytes = bincode::serialize(&message).expect("Failed to serialize DKG message");
+        let addresses = self
+            .committee
+            .others_primaries(&self.keypair.name)
+            .into_iter()
+            .map(|(_, x)| x.primary_to_primary)
+            .collect();
+        let handlers = self.network.broadcast(addresses, Bytes::from(bytes)).await;
+        self.cancel_handlers.extend(handlers);
+    }
+}
+
+/// Defines how the network receiver handles incoming DKG messages.
+#[derive(Clone)]
+struct DkgReceiverHandler {
+    tx_message: Sender<DkgMessage>,
+}
+
+#[async_trait]
+impl MessageHandler for DkgReceiverHandler {
+    async fn dispatch(&self, writer: &mut Writer, serialized: Bytes) -> Result<(), Box<dyn Error>> {
+        // Reply with an ACK.
+        let _ = writer.send(Bytes::from("Ack")).await;
+
+        // Deserialize the message and send it to the key generation.
+        match bincode::deserialize(&serialized) {
+            Ok(message) => {
+                // The key generation stops listening once it completed: late messages are not needed.
+                if self.tx_message.send(message).await.is_err() {
+                    debug!("Dropping DKG message received after the key generation completed");
+                }
+            }
+            Err(e) => warn!("Serialization error: {}", e),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,54 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use async_trait::async_trait;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use log::{info, warn};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::task::JoinHandle;
+
+/// Handles the requests received by one of the HTTP servers of the node.
+#[async_trait]
+pub trait HttpHandler: Send + Sync + 'static {
+    async fn handle(&self, request: Request<Body>) -> Response<Body>;
+}
+
+/// Spawns an HTTP server serving all requests received on `address` with `handler`.
+pub fn spawn_http_server<H: HttpHandler>(
+    name: &'static str,
+    address: SocketAddr,
+    handler: H,
+) -> JoinHandle<()> {
+    let handler = Arc::new(handler);
+    tokio::spawn(async move {
+        let make_service = make_service_fn(move |_| {
+            let handler = handler.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    let handler = handler.clone();
+                    async move { Ok::<_, Infallible>(handler.handle(request).await) }
+                }))
+            }
+        });
+        let server = match Server::try_bind(&address) {
+            Ok(builder) => builder.serve(make_service),
+            Err(e) => {
+                warn!("Failed to bind the {} server to {}: {}", name, address, e);
+                return;
+            }
+        };
+        info!("The {} server is listening on {}", name, address);
+        if let Err(e) = server.await {
+            warn!("The {} server failed: {}", name, e);
+        }
+    })
+}
+
+/// Builds a response with the specified status code and body.
+pub fn reply<B: Into<Body>>(status: StatusCode, body: B) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(body.into())
+        .expect("Failed to build HTTP response")
+}
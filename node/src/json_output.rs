@@ -0,0 +1,224 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::{Application, DecryptedBatch, DecryptionFailure, DecryptionResult};
+use async_trait::async_trait;
+use config::WorkerId;
+use crypto::Hash as _;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::warn;
+use primary::{Certificate, Round};
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+
+#[cfg(test)]
+#[path = "tests/json_output_tests.rs"]
+pub mod json_output_tests;
+
+/// The version of the schema of the JSON records. Bump it whenever the fields of `CommittedRecord` change.
+pub const SCHEMA_VERSION: u32 = 3;
+
+/// The maximum number of records buffered before flushing the file (it is otherwise flushed every
+/// `FLUSH_INTERVAL`).
+const FLUSH_RECORDS: usize = 100;
+
+/// A JSON record describing a committed certificate.
+#[derive(Serialize)]
+struct CommittedRecord {
+    schema_version: u32,
+    /// The base64 encoding of the public key of the header's author.
+    author: String,
+    round: Round,
+    /// The base64 encoding of the header digest.
+    header_digest: String,
+    /// The base64 encoding of the certificate digest.
+    certificate_digest: String,
+    /// When the author created the header, according to its own clock (in ms since the Unix epoch).
+    timestamp: u64,
+    /// The number of batches' digests referenced by the header.
+    payload_size: usize,
+    /// The outcome of the decryption of each batch (only when the node decrypts the committed batches).
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    batches: Vec<BatchRecord>,
+}
+
+/// A JSON record describing the decryption of a committed batch.
+#[derive(Serialize)]
+struct BatchRecord {
+    /// The base64 encoding of the batch digest.
+    digest: String,
+    worker_id: WorkerId,
+    status: BatchStatus,
+}
+
+#[derive(Serialize)]
+enum BatchStatus {
+    Decrypted {
+        transactions: usize,
+    },
+    DecryptionFailed {
+        reason: DecryptionFailure,
+        message: String,
+    },
+}
+
+impl CommittedRecord {
+    fn new(certificate: &Certificate, batches: &[DecryptionResult<DecryptedBatch>]) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            author: certificate.origin().encode_base64(),
+            round: certificate.round(),
+            header_digest: format!("{:?}", certificate.header.id),
+            certificate_digest: format!("{:?}", certificate.digest()),
+            timestamp: certificate.header.timestamp,
+            payload_size: certificate.header.payload.len(),
+            batches: certificate
+                .header
+                .payload
+                .iter()
+                .zip(batches)
+                .map(|((digest, worker_id), result)| BatchRecord {
+                    digest: format!("{:?}", digest),
+                    worker_id: *worker_id,
+                    status: match result {
+                        Ok(batch) => BatchStatus::Decrypted {
+                            transactions: batch.transactions.len(),
+                        },
+                        Err(e) => BatchStatus::DecryptionFailed {
+                            reason: e.failure(),
+                            message: e.to_string(),
+                        },
+                    },
+                })
+                .collect(),
+        }
+    }
+}
+
+/// The file receiving the records, compressed according to its extension. Every process appends its own
+/// gzip member (or zstd frame), and concatenated members (or frames) decompress to the concatenated records.
+enum OutputFile {
+    Plain(BufWriter<File>),
+    Gzip(GzEncoder<BufWriter<File>>),
+    Zstd(zstd::Encoder<'static, BufWriter<File>>),
+}
+
+impl OutputFile {
+    fn open(path: &str) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let writer = BufWriter::new(file);
+        Ok(if path.ends_with(".gz") {
+            Self::Gzip(GzEncoder::new(writer, Compression::default()))
+        } else if path.ends_with(".zst") {
+            Self::Zstd(zstd::Encoder::new(writer, zstd::DEFAULT_COMPRESSION_LEVEL)?)
+        } else {
+            Self::Plain(writer)
+        })
+    }
+
+    /// Terminates the compressed stream (so that the file can be decompressed entirely) and flushes it.
+    fn finish(self) -> io::Result<()> {
+        let mut writer = match self {
+            Self::Plain(writer) => writer,
+            Self::Gzip(encoder) => encoder.finish()?,
+            Self::Zstd(encoder) => encoder.finish()?,
+        };
+        writer.flush()
+    }
+}
+
+impl Write for OutputFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Plain(x) => x.write(buf),
+            Self::Gzip(x) => x.write(buf),
+            Self::Zstd(x) => x.write(buf),
+        }
+    }
+
+    /// Flushing a compressed file also flushes the compressor, so that a reader sees all the records
+    /// written so far (even before the stream is finished).
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Plain(x) => x.flush(),
+            Self::Gzip(x) => x.flush(),
+            Self::Zstd(x) => x.flush(),
+        }
+    }
+}
+
+/// An application appending one JSON object per committed certificate to a file (newline-delimited JSON).
+/// Files ending in `.gz` or `.zst` are compressed with gzip or zstd.
+pub struct JsonOutput {
+    /// The file where to append the records (only taken upon drop).
+    writer: Option<OutputFile>,
+    /// The number of records written since the last flush.
+    pending: usize,
+}
+
+impl JsonOutput {
+    pub fn new(path: &str) -> io::Result<Self> {
+        Ok(Self {
+            writer: Some(OutputFile::open(path)?),
+            pending: 0,
+        })
+    }
+
+    fn write(&mut self, record: &CommittedRecord) -> io::Result<()> {
+        let writer = self.writer.as_mut().expect("The JSON output is closed");
+        serde_json::to_writer(&mut *writer, record)?;
+        writer.write_all(b"\n")?;
+        self.pending += 1;
+        if self.pending >= FLUSH_RECORDS {
+            self.flush_pending()?;
+        }
+        Ok(())
+    }
+
+    /// Flushes the records written since the last flush (if any).
+    fn flush_pending(&mut self) -> io::Result<()> {
+        if self.pending == 0 {
+            return Ok(());
+        }
+        let writer = self.writer.as_mut().expect("The JSON output is closed");
+        writer.flush()?;
+        self.pending = 0;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Application for JsonOutput {
+    async fn process_certificate(&mut self, certificate: Certificate) {
+        self.process_committed(certificate, Vec::new()).await;
+    }
+
+    async fn process_committed(
+        &mut self,
+        certificate: Certificate,
+        batches: Vec<DecryptionResult<DecryptedBatch>>,
+    ) {
+        if let Err(e) = self.write(&CommittedRecord::new(&certificate, &batches)) {
+            warn!(
+                "Failed to write committed certificate to JSON output: {}",
+                e
+            );
+        }
+    }
+
+    async fn flush(&mut self) {
+        if let Err(e) = self.flush_pending() {
+            warn!("Failed to flush the JSON output: {}", e);
+        }
+    }
+}
+
+impl Drop for JsonOutput {
+    fn drop(&mut self) {
+        if let Some(writer) = self.writer.take() {
+            if let Err(e) = writer.finish() {
+                warn!("Failed to close the JSON output: {}", e);
+            }
+        }
+    }
+}
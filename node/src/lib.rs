@@ -0,0 +1,171 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+mod admin;
+mod certificate_stream;
+mod decryption;
+#[cfg(feature = "dkg")]
+mod dkg;
+mod http;
+mod json_output;
+#[cfg(feature = "benchmark")]
+mod load_generator;
+mod metrics;
+mod sub_dag_stream;
+mod throughput;
+
+#[cfg(test)]
+#[path = "tests/node_tests.rs"]
+pub mod node_tests;
+
+pub use crate::admin::{AdminServer, WorkerStores};
+pub use crate::certificate_stream::{CertificateStream, CertificateSubscriber, Framing};
+pub use crate::decryption::{
+    DecryptedBatch, DecryptionError, DecryptionFailure, DecryptionResult, Decryptor,
+};
+#[cfg(feature = "dkg")]
+pub use crate::dkg::{Dkg, DkgError, DkgResult};
+pub use crate::json_output::{JsonOutput, SCHEMA_VERSION};
+#[cfg(feature = "benchmark")]
+pub use crate::load_generator::{FileReplayer, LoadGenerator, LoadReport};
+pub use crate::metrics::MetricsServer;
+pub use crate::sub_dag_stream::{SubDagReply, SubDagStream, SubDagSubscriber, SubscribeRequest};
+pub use crate::throughput::{Throughput, ThroughputReport, THROUGHPUT_WINDOW};
+
+use async_trait::async_trait;
+use consensus::CommittedWatermark;
+use crypto::Hash as _;
+use log::{info, warn};
+use primary::Certificate;
+use tokio::sync::mpsc::Receiver;
+use tokio::time::{interval, Duration};
+
+/// The delay between two calls to `Application::flush`.
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// An application running on top of the consensus. It receives every committed certificate, in order.
+#[async_trait]
+pub trait Application: Send {
+    /// Called before the first certificate when the node restarts on a store in which the consensus already
+    /// committed sub-dags: the certificates of the sub-dags up to `committed` were output before the restart,
+    /// and are not output again. Those of the sub-dags committed after it may have been output before a crash
+    /// too (the watermark is persisted after the output), and are output again: an application keeping its
+    /// own progress can skip the output it processed.
+    async fn resume(&mut self, _committed: CommittedWatermark) {}
+
+    /// Apply the application-specific logic to the next committed certificate.
+    async fn process_certificate(&mut self, certificate: Certificate);
+
+    /// Apply the application-specific logic to the plaintext transactions of the batches of the last
+    /// committed certificate, or to the reason why one of them could not be decrypted. Batches are only
+    /// decrypted when the node runs an entire authority.
+    async fn process_decrypted_batch(&mut self, _batch: DecryptionResult<DecryptedBatch>) {}
+
+    /// Apply the application-specific logic to the next committed certificate along with the outcome of the
+    /// decryption of its batches (one per batch, in the order of the header's payload; empty if the node does
+    /// not decrypt). By default, the certificate and then each batch are handed to the methods above.
+    async fn process_committed(
+        &mut self,
+        certificate: Certificate,
+        batches: Vec<DecryptionResult<DecryptedBatch>>,
+    ) {
+        self.process_certificate(certificate).await;
+        for batch in batches {
+            self.process_decrypted_batch(batch).await;
+        }
+    }
+
+    /// Called every `FLUSH_INTERVAL`, so that the application can write out the output it buffered even
+    /// when nothing is committed for a while.
+    async fn flush(&mut self) {}
+}
+
+/// An application ignoring all the certificates it receives.
+pub struct NoOpApplication;
+
+#[async_trait]
+impl Application for NoOpApplication {
+    async fn process_certificate(&mut self, _certificate: Certificate) {}
+}
+
+/// An application logging the digest and round of every committed certificate.
+pub struct LoggingApplication;
+
+#[async_trait]
+impl Application for LoggingApplication {
+    async fn resume(&mut self, committed: CommittedWatermark) {
+        info!(
+            "Resuming the output after sub-dag {} (round {})",
+            committed.sub_dag_index, committed.round
+        );
+    }
+
+    async fn process_certificate(&mut self, certificate: Certificate) {
+        info!(
+            "Received committed certificate {} of round {}",
+            certificate.digest(),
+            certificate.round()
+        );
+    }
+
+    async fn process_decrypted_batch(&mut self, batch: DecryptionResult<DecryptedBatch>) {
+        if let Ok(batch) = batch {
+            info!(
+                "Decrypted {} transaction(s) of committed batch {}",
+                batch.transactions.len(),
+                batch.digest
+            );
+        }
+    }
+}
+
+/// Receives an ordered list of certificates and feeds them to the application. If a `decryptor` is provided,
+/// the batches of each certificate are decrypted and fed to the application as well. A batch that cannot be
+/// decrypted is reported as failed: it never withholds the certificates committed after it. The `committed`
+/// watermark (read from the store upon boot, if any) is handed to the application first. The transactions
+/// of the decrypted batches are counted by `throughput` (if any). Returns the number of certificates handed
+/// to the application once the output closes (when the consensus stops).
+pub async fn analyze<A: Application>(
+    mut rx_output: Receiver<Certificate>,
+    mut application: A,
+    decryptor: Option<Decryptor>,
+    committed: Option<CommittedWatermark>,
+    throughput: Option<Throughput>,
+) -> u64 {
+    if let Some(committed) = committed {
+        application.resume(committed).await;
+    }
+    let mut certificates = 0;
+    let mut flush = interval(FLUSH_INTERVAL);
+    loop {
+        let certificate = tokio::select! {
+            certificate = rx_output.recv() => match certificate {
+                Some(certificate) => certificate,
+                None => break,
+            },
+            _ = flush.tick() => {
+                application.flush().await;
+                continue;
+            }
+        };
+        certificates += 1;
+        let batches = match decryptor.as_ref() {
+            Some(decryptor) => decryptor.decrypt_certificate(&certificate).await,
+            None => Vec::new(),
+        };
+        for batch in &batches {
+            match batch {
+                Ok(batch) => {
+                    if let Some(throughput) = &throughput {
+                        throughput.record(&batch.transactions);
+                    }
+                }
+                Err(e) => warn!(
+                    "Failed to decrypt committed batch ({:?}): {}",
+                    e.failure(),
+                    e
+                ),
+            }
+        }
+        application.process_committed(certificate, batches).await;
+    }
+    certificates
+}
@@ -0,0 +1,242 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use bytes::Bytes;
+use crypto::threshold::PublicKey as ThresholdPublicKey;
+use futures::sink::SinkExt as _;
+use futures::stream::StreamExt as _;
+use log::{info, warn};
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::fs::File;
+use tokio::net::TcpStream;
+use tokio::time::{interval, Duration, Instant};
+use tokio_util::codec::{Framed, FramedRead, LengthDelimitedCodec};
+
+#[cfg(test)]
+#[path = "tests/load_generator_tests.rs"]
+pub mod load_generator_tests;
+
+/// The number of bursts of transactions per second.
+const PRECISION: u64 = 20;
+
+/// The size of the header of every transaction: a counter and a timestamp (both u64).
+pub const TRANSACTION_HEADER_SIZE: usize = 16;
+
+/// Submits encrypted transactions of a fixed size to the transactions endpoint of a worker, at a fixed rate.
+/// The plaintext of each transaction starts with a counter (incremented with every transaction) and the
+/// time at which it was built (in milliseconds since the Unix epoch), both big-endian, followed by zeros
+/// up to the requested size.
+pub struct LoadGenerator {
+    /// The transactions address of the worker.
+    pub target: SocketAddr,
+    /// The key with which to encrypt the transactions.
+    pub threshold_pk: ThresholdPublicKey,
+    /// The number of transactions to submit per second.
+    pub rate: u64,
+    /// The size of the plaintext of each transaction (in bytes).
+    pub size: usize,
+    /// For how long to submit transactions.
+    pub duration: Duration,
+}
+
+impl LoadGenerator {
+    pub async fn run(&self) -> io::Result<LoadReport> {
+        if self.size < TRANSACTION_HEADER_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Transactions must be at least {} bytes",
+                    TRANSACTION_HEADER_SIZE
+                ),
+            ));
+        }
+        if self.rate == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The rate must be positive",
+            ));
+        }
+
+        let stream = TcpStream::connect(self.target).await?;
+        let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+        info!(
+            "Submitting {} B transactions to {} at {} tx/s for {} s",
+            self.size,
+            self.target,
+            self.rate,
+            self.duration.as_secs()
+        );
+
+        let burst = (self.rate / PRECISION).max(1);
+        let period = Duration::from_secs_f64(burst as f64 / self.rate as f64);
+        let mut timer = interval(period);
+        let mut latencies = Vec::new();
+        let mut counter = 0;
+        let start = Instant::now();
+        let deadline = start + self.duration;
+
+        'main: while Instant::now() < deadline {
+            // Encrypt the burst before waiting for its slot: encryption is slow and should not eat into the
+            // submission rate.
+            let transactions: Vec<_> = (counter..counter + burst)
+                .map(|x| self.transaction(x))
+                .collect();
+            timer.tick().await;
+            let now = Instant::now();
+            for transaction in transactions {
+                let sent = Instant::now();
+                if let Err(e) = transport.send(transaction).await {
+                    warn!("Failed to send transaction: {}", e);
+                    break 'main;
+                }
+                latencies.push(sent.elapsed());
+                counter += 1;
+            }
+            if now.elapsed() > period {
+                warn!("Transaction rate too high for this client");
+            }
+        }
+
+        Ok(LoadReport::new(start.elapsed(), latencies))
+    }
+
+    fn transaction(&self, counter: u64) -> Bytes {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Failed to measure time")
+            .as_millis() as u64;
+        let mut plaintext = Vec::with_capacity(self.size);
+        plaintext.extend_from_slice(&counter.to_be_bytes());
+        plaintext.extend_from_slice(&timestamp.to_be_bytes());
+        plaintext.resize(self.size, 0);
+        Bytes::from(config::threshold::encrypt(&self.threshold_pk, &plaintext))
+    }
+}
+
+/// Replays the transactions of a file to the transactions endpoint of a worker, at a fixed rate. The file
+/// uses the framing of the endpoint (each transaction is prefixed by its length, as a 4-byte big-endian
+/// integer), so that the traffic captured from clients can be replayed as is. The transactions are sent
+/// untouched: they should already be encrypted.
+pub struct FileReplayer {
+    /// The transactions address of the worker.
+    pub target: SocketAddr,
+    /// The file holding the transactions.
+    pub file: String,
+    /// The number of transactions to submit per second.
+    pub rate: u64,
+}
+
+impl FileReplayer {
+    /// Submits all the transactions of the file, in order.
+    pub async fn run(&self) -> io::Result<LoadReport> {
+        if self.rate == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The rate must be positive",
+            ));
+        }
+
+        let mut reader =
+            FramedRead::new(File::open(&self.file).await?, LengthDelimitedCodec::new());
+        let stream = TcpStream::connect(self.target).await?;
+        let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+        info!(
+            "Replaying the transactions of '{}' to {} at {} tx/s",
+            self.file, self.target, self.rate
+        );
+
+        let burst = (self.rate / PRECISION).max(1);
+        let period = Duration::from_secs_f64(burst as f64 / self.rate as f64);
+        let mut timer = interval(period);
+        let mut latencies = Vec::new();
+        let start = Instant::now();
+
+        'main: loop {
+            // Read the burst before waiting for its slot.
+            let mut transactions = Vec::new();
+            while (transactions.len() as u64) < burst {
+                match reader.next().await {
+                    Some(transaction) => transactions.push(transaction?.freeze()),
+                    None => break,
+                }
+            }
+            if transactions.is_empty() {
+                break;
+            }
+            let last = (transactions.len() as u64) < burst;
+            timer.tick().await;
+            let now = Instant::now();
+            for transaction in transactions {
+                let sent = Instant::now();
+                if let Err(e) = transport.send(transaction).await {
+                    warn!("Failed to send transaction: {}", e);
+                    break 'main;
+                }
+                latencies.push(sent.elapsed());
+            }
+            if now.elapsed() > period {
+                warn!("Transaction rate too high for this client");
+            }
+            if last {
+                break;
+            }
+        }
+
+        Ok(LoadReport::new(start.elapsed(), latencies))
+    }
+}
+
+/// The throughput and submission latencies achieved by a `LoadGenerator` (or a `FileReplayer`).
+pub struct LoadReport {
+    /// The time spent submitting transactions.
+    pub elapsed: Duration,
+    /// The time taken to submit each transaction, sorted.
+    pub latencies: Vec<Duration>,
+}
+
+impl LoadReport {
+    fn new(elapsed: Duration, mut latencies: Vec<Duration>) -> Self {
+        latencies.sort();
+        Self { elapsed, latencies }
+    }
+
+    /// The number of transactions submitted.
+    pub fn sent(&self) -> usize {
+        self.latencies.len()
+    }
+
+    /// The achieved throughput (in transactions per second).
+    pub fn throughput(&self) -> f64 {
+        self.sent() as f64 / self.elapsed.as_secs_f64()
+    }
+
+    /// The submission latency below which `percentile` percent of the transactions were submitted.
+    pub fn latency(&self, percentile: usize) -> Option<Duration> {
+        if self.latencies.is_empty() {
+            return None;
+        }
+        let index = (self.latencies.len() * percentile / 100).min(self.latencies.len() - 1);
+        Some(self.latencies[index])
+    }
+}
+
+impl fmt::Display for LoadReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Submitted {} transactions in {:.1} s ({:.0} tx/s)",
+            self.sent(),
+            self.elapsed.as_secs_f64(),
+            self.throughput()
+        )?;
+        write!(f, "Submission latency:")?;
+        for percentile in &[50, 90, 99, 100] {
+            match self.latency(*percentile) {
+                Some(latency) => write!(f, " p{} {} us", percentile, latency.as_micros())?,
+                None => write!(f, " p{} -", percentile)?,
+            }
+        }
+        Ok(())
+    }
+}
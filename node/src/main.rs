@@ -1,38 +1,304 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
-use anyhow::{Context, Result};
-use clap::{crate_name, crate_version, App, AppSettings, ArgMatches, SubCommand};
+use anyhow::{bail, ensure, Context, Result};
+use clap::{crate_name, crate_version, App, AppSettings, Arg, ArgGroup, ArgMatches, SubCommand};
 use config::Export;
-use config::Import as _;
-use config::ThresholdKeyPair;
-use config::{Committee, KeyPair, Parameters, WorkerId};
-use consensus::Consensus;
+use config::Import;
+use config::{parse_log_filters, Committee, Epoch, KeyPair, Parameters, Stake, WorkerId};
+use config::{SeededKeygen, ThresholdKeyPair, ThresholdPublicKeyFile};
+use consensus::{CommittedSubDag, Consensus, ConsensusMetrics};
+use crypto::PublicKey;
 use env_logger::Env;
-use primary::{Certificate, Primary};
-use store::Store;
-use tokio::sync::mpsc::{channel, Receiver};
-use worker::Worker;
+use log::{info, warn, LevelFilter};
+use network::TlsConfig;
+use node::{
+    analyze, AdminServer, Application, CertificateStream, Decryptor, JsonOutput, MetricsServer,
+    NoOpApplication, SubDagStream, Throughput, WorkerStores,
+};
+use primary::{Certificate, Observer, PeersHealth, Primary};
+use prometheus::Registry;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::io::Write as _;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use store::{PrefixedStore, Store, StoreKey};
+use tokio::sync::mpsc::{channel, Sender};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+use worker::{Shutdown, ThresholdKeyRing, Worker};
 
-/// The default channel capacity.
-pub const CHANNEL_CAPACITY: usize = 1_000;
+#[cfg(test)]
+#[path = "tests/main_tests.rs"]
+mod main_tests;
+
+/// The time the workers have upon shutdown to get the batches in flight acknowledged and stored.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let matches = App::new(crate_name!())
+    let matches = app().get_matches();
+
+    let log_level = match matches.occurrences_of("v") {
+        0 => LevelFilter::Error,
+        1 => LevelFilter::Warn,
+        2 => LevelFilter::Info,
+        3 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    };
+    // The module filters are applied before `RUST_LOG`, so that the environment still has the last word.
+    let mut logger = env_logger::Builder::new();
+    logger.filter_level(log_level);
+    for (module, level) in parse_log_filters(&log_filters(&matches))? {
+        logger.filter_module(&module, level.min(log_level));
+    }
+    logger.parse_env(Env::default());
+    let log_format = match matches.value_of("log-format") {
+        Some(format) => format.to_string(),
+        None => env::var("LOG_FORMAT").unwrap_or_else(|_| "text".to_string()),
+    };
+    match log_format.as_str() {
+        "text" => {
+            #[cfg(feature = "benchmark")]
+            logger.format_timestamp_millis();
+        }
+        "json" => {
+            logger.format(format_json_log);
+        }
+        other => bail!("Unknown log format '{}' (expected 'text' or 'json')", other),
+    }
+    logger.init();
+
+    match matches.subcommand() {
+        ("generate_keypair", Some(sub_matches)) => KeyPair::new()
+            .export(sub_matches.value_of("filename").unwrap())
+            .context("Failed to generate key pair")?,
+        ("rotate_keypair", Some(sub_matches)) => rotate_keypair(sub_matches)?,
+        ("generate_store_key", Some(sub_matches)) => StoreKey::generate()
+            .export(sub_matches.value_of("filename").unwrap())
+            .context("Failed to generate store key")?,
+        ("generate_tls_certificate", Some(sub_matches)) => {
+            let keypair = KeyPair::import(sub_matches.value_of("keypair").unwrap())
+                .context("Failed to load the node's keypair")?;
+            let seed = keypair
+                .secret
+                .ed25519_seed()
+                .context("TLS certificates require ed25519 keys")?;
+            let (certificate, private_key) = network::tls::generate_certificate(seed)
+                .context("Failed to generate the TLS certificate")?;
+            fs::write(sub_matches.value_of("certificate").unwrap(), certificate)
+                .context("Failed to write the TLS certificate")?;
+            fs::write(sub_matches.value_of("private_key").unwrap(), private_key)
+                .context("Failed to write the TLS private key")?;
+        }
+        ("generate_threshold_keypair", Some(sub_matches)) => {
+            let threshold = sub_matches
+                .value_of("threshold")
+                .map(|x| x.parse::<usize>().context("threshold must be an integer"))
+                .transpose()?;
+            let node_index = sub_matches
+                .value_of("node_index")
+                .unwrap()
+                .parse::<usize>()
+                .context("node_index must be an integer")?;
+            let (threshold, num_shares) = match sub_matches.value_of("committee") {
+                Some(file) => {
+                    let committee = Committee::import(file)
+                        .context("Failed to load the committee information")?;
+                    let worker = sub_matches
+                        .value_of("worker")
+                        .map(|x| x.parse::<WorkerId>().context("worker must be an integer"))
+                        .transpose()?;
+                    let threshold = shard_threshold(&committee, worker, threshold, node_index)?;
+                    (threshold, Some(committee.size()))
+                }
+                None => (
+                    threshold.context("Specify the threshold (or a committee setting it)")?,
+                    None,
+                ),
+            };
+            let seed = sub_matches
+                .value_of("seed")
+                .unwrap()
+                .parse::<u64>()
+                .context("seed must be an integer")?;
+            let epoch = sub_matches
+                .value_of("epoch")
+                .unwrap_or("0")
+                .parse::<u64>()
+                .context("epoch must be an integer")?;
+            let mut keypair = ThresholdKeyPair::new(threshold, node_index, seed).with_epoch(epoch);
+            if let Some(num_shares) = num_shares {
+                keypair = keypair.with_num_shares(num_shares);
+            }
+            keypair
+                .export(sub_matches.value_of("filename").unwrap())
+                .context("Failed to generate threshold keypair")?;
+            info!(
+                "Generated key share {} from the seed of fingerprint {}",
+                node_index,
+                SeededKeygen::fingerprint(seed)
+            );
+        }
+        ("generate_threshold_publickey", Some(sub_matches)) => {
+            let threshold = sub_matches
+                .value_of("threshold")
+                .unwrap()
+                .parse::<usize>()
+                .context("threshold must be an integer")?;
+            let seed = sub_matches
+                .value_of("seed")
+                .unwrap()
+                .parse::<u64>()
+                .context("seed must be an integer")?;
+            let num_shares = sub_matches
+                .value_of("num_shares")
+                .map(|x| x.parse::<usize>().context("num_shares must be an integer"))
+                .transpose()?;
+            // reusing this structure just to generate the public key is an ugly hack but this is a hackathon..
+            let mut keypair = ThresholdKeyPair::new(threshold, 0, seed);
+            if let Some(num_shares) = num_shares {
+                keypair = keypair.with_num_shares(num_shares);
+            }
+            let file = ThresholdPublicKeyFile {
+                public_key: keypair.pk_set.public_key(),
+                keygen: keypair.keygen,
+            };
+            file.export(sub_matches.value_of("filename").unwrap())
+                .context("Failed to generate threshold public key")?;
+            info!(
+                "Generated the threshold public key from the seed of fingerprint {}",
+                SeededKeygen::fingerprint(seed)
+            );
+        }
+        ("verify_threshold_keypair", Some(sub_matches)) => {
+            let file = sub_matches.value_of("threshold_keypair").unwrap();
+            let keypair = ThresholdKeyPair::import(file)
+                .with_context(|| format!("Failed to load the threshold keypair '{}'", file))?;
+            let file = sub_matches.value_of("public-key").unwrap();
+            let public_key = ThresholdPublicKeyFile::import(file)
+                .with_context(|| format!("Failed to load the threshold public key '{}'", file))?;
+            keypair
+                .verify_file(&public_key)
+                .context("Invalid threshold keypair")?;
+            println!(
+                "Valid key share {} of a key set of threshold {} (epoch {})",
+                keypair.node_index,
+                keypair.pk_set.threshold(),
+                keypair.epoch
+            );
+            if let Some(keygen) = &keypair.keygen {
+                println!(
+                    "Generated from the seed of fingerprint {}",
+                    keygen.seed_fingerprint
+                );
+            }
+        }
+        ("generate_committee", Some(sub_matches)) => generate_committee(sub_matches)?,
+        ("keygen_batch", Some(sub_matches)) => keygen_batch(sub_matches)?,
+        ("reweight_committee", Some(sub_matches)) => reweight_committee(sub_matches)?,
+        ("inspect_committee", Some(sub_matches)) => {
+            let committee = Committee::import(sub_matches.value_of("committee").unwrap())
+                .context("Failed to load the committee information")?;
+            inspect_committee(&committee);
+        }
+        ("validate_config", Some(sub_matches)) => validate_config(sub_matches)?,
+        ("replay", Some(sub_matches)) => replay(sub_matches)?,
+        ("snapshot_store", Some(sub_matches)) => {
+            let out = sub_matches.value_of("out").unwrap();
+            let count = store::snapshot(sub_matches.value_of("store").unwrap(), out)
+                .context("Failed to snapshot the store")?;
+            println!("Wrote {} entries to {}", count, out);
+        }
+        ("restore_store", Some(sub_matches)) => {
+            let path = sub_matches.value_of("store").unwrap();
+            let count = store::restore(
+                sub_matches.value_of("in").unwrap(),
+                path,
+                sub_matches.is_present("force"),
+            )
+            .context("Failed to restore the store")?;
+            println!("Restored {} entries into {}", count, path);
+        }
+        #[cfg(feature = "dkg")]
+        ("dkg", Some(sub_matches)) => {
+            let keypair = KeyPair::import(sub_matches.value_of("keypair").unwrap())
+                .context("Failed to load the node's keypair")?;
+            let committee = Committee::import(sub_matches.value_of("committee").unwrap())
+                .context("Failed to load the committee information")?;
+            let threshold = sub_matches
+                .value_of("threshold")
+                .unwrap()
+                .parse::<usize>()
+                .context("threshold must be an integer")?;
+            node::Dkg::run(keypair, committee, threshold)
+                .await
+                .context("Failed to run the distributed key generation")?
+                .export(sub_matches.value_of("filename").unwrap())
+                .context("Failed to export the threshold keypair")?;
+        }
+        #[cfg(feature = "benchmark")]
+        ("benchmark", Some(sub_matches)) => benchmark(sub_matches).await?,
+        #[cfg(feature = "benchmark")]
+        ("submit_file", Some(sub_matches)) => submit_file(sub_matches).await?,
+        ("run", Some(sub_matches)) => match sub_matches.value_of("output-json") {
+            Some(filename) => {
+                let output =
+                    JsonOutput::new(filename).context("Failed to open the JSON output file")?;
+                run(sub_matches, output).await?
+            }
+            None => run(sub_matches, NoOpApplication).await?,
+        },
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+/// The command line interface of the node.
+#[allow(clippy::let_and_return)] // The subcommands of the optional features are added below.
+fn app() -> App<'static, 'static> {
+    let app = App::new(crate_name!())
         .version(crate_version!())
         .about("A research implementation of Narwhal and Tusk.")
         .args_from_usage("-v... 'Sets the level of verbosity'")
+        .args_from_usage("--log-format=[FORMAT] 'The format of the logs, either text (default) or json (overrides the LOG_FORMAT environment variable)'")
+        .args_from_usage("--log-filters=[FILTERS] 'The log levels of some modules, as comma-separated <module>=<level> directives (overrides the log_filters parameter; RUST_LOG overrides both)'")
         .subcommand(
             SubCommand::with_name("generate_keypair")
                 .about("Print a fresh key pair to file")
                 .args_from_usage("--filename=<FILE> 'The file where to print the new key pair'"),
         )
+        .subcommand(
+            SubCommand::with_name("rotate_keypair")
+                .about("Add to a key pair file the key the node signs with from an epoch, and print the rotation to add to the committee")
+                .args_from_usage("--filename=<FILE> 'The key pair file to rotate (it keeps the node's name)'")
+                .args_from_usage("--epoch=<INT> 'The signing epoch from which the node signs with the new key'")
+                .args_from_usage("--overlap=[INT] 'The number of epochs during which the other nodes accept both keys (defaults to 1)'"),
+        )
+        .subcommand(
+            SubCommand::with_name("generate_store_key")
+                .about("Print a fresh key to encrypt the values of a data store to file")
+                .args_from_usage("--filename=<FILE> 'The file where to print the new key (in base64)'"),
+        )
+        .subcommand(
+            SubCommand::with_name("generate_tls_certificate")
+                .about("Print a self-signed TLS certificate holding the node's public key, and its private key")
+                .args_from_usage("--keypair=<FILE> 'The file containing the node keypair'")
+                .args_from_usage("--certificate=<FILE> 'The file where to print the certificate (PEM)'")
+                .args_from_usage("--private_key=<FILE> 'The file where to print the private key of the certificate (PEM)'"),
+        )
         .subcommand(
             SubCommand::with_name("generate_threshold_keypair")
                 .about("Print fresh threshold keypair to file")
                 .args_from_usage("--filename=<FILE> 'The file where to print the new threshold key shares'")
-                .args_from_usage("--threshold=<INT> 'The threshold number st (threshold+1)/num_shares needed to decrypt'")
+                .args_from_usage("--threshold=[INT] 'The threshold number st (threshold+1)/num_shares needed to decrypt (defaults to the threshold of the worker shard in the committee)'")
+                .args_from_usage("--committee=[FILE] 'The committee the key shares are generated for (num_shares is its size)'")
+                .args_from_usage("--worker=[INT] 'The id of the worker shard the key shares are generated for'")
                 .args_from_usage("--node_index=<INT> 'The index of the share to generate'")
                 .args_from_usage("--seed=<INT> 'The seed number to generate the threshold keys (use same seed as generate_threshold_publickey)'")
+                .args_from_usage("--epoch=[INT] 'The epoch in which the threshold keys are used (defaults to 0)'")
         )
         .subcommand(
             SubCommand::with_name("generate_threshold_publickey")
@@ -40,134 +306,934 @@ async fn main() -> Result<()> {
                 .args_from_usage("--filename=<FILE> 'The file where to print the new threshold key shares'")
                 .args_from_usage("--threshold=<INT> 'The threshold number st (threshold+1)/num_shares needed to decrypt'")
                 .args_from_usage("--seed=<INT> 'The seed number to generate the threshold keys (use same seed as generate_threshold_keypair)'")
+                .args_from_usage("--num_shares=[INT] 'The number of key shares generated from the seed (recorded in the file to check the key shares against)'")
+        )
+        .subcommand(
+            SubCommand::with_name("verify_threshold_keypair")
+                .about("Check that a threshold keypair holds a valid share of a threshold public key")
+                .args_from_usage("--threshold_keypair=<FILE> 'The file containing the threshold keypair'")
+                .args_from_usage("--public-key=<FILE> 'The file containing the threshold public key'")
+        )
+        .subcommand(
+            SubCommand::with_name("generate_committee")
+                .about("Print a committee file assembled from the authorities' keys and hosts")
+                .args_from_usage("--filename=<FILE> 'The file where to print the committee'")
+                .args_from_usage("--keys=<FILE>... 'The key pair files of the authorities'")
+                .args_from_usage("--hosts=<IP>... 'The host of each authority (or a single host shared by all authorities)'")
+                .args_from_usage("--stakes=[INT]... 'The stake of each authority (defaults to 1)'")
+                .args_from_usage("--base_port=<PORT> 'The first port to assign; the others are assigned sequentially'")
+                .args_from_usage("--workers=<INT> 'The number of workers of each authority'")
+                .args_from_usage("--threshold_publickey=[FILE] 'The threshold public key matching the key shares of the authorities'")
+                .args_from_usage("--governance_keypair=[FILE] 'The governance key pair with which to sign the committee file (the signature is printed to <FILE>.sig)'")
+        )
+        .subcommand(
+            SubCommand::with_name("keygen_batch")
+                .about("Print to a directory the key pairs and threshold key shares of a local committee, its threshold public key and its committee file")
+                .args_from_usage("--nodes=<INT> 'The number of authorities'")
+                .args_from_usage("--threshold=<INT> 'The threshold number st (threshold+1)/nodes needed to decrypt'")
+                .args_from_usage("--outdir=<DIR> 'The directory where to print the files (created if needed)'")
+                .args_from_usage("--seed=[INT] 'The seed number to generate the threshold keys (defaults to a random seed)'")
+                .args_from_usage("--base_port=[PORT] 'The first port of the committee on localhost; the others are assigned sequentially (defaults to 3000)'")
+                .args_from_usage("--workers=[INT] 'The number of workers of each authority (defaults to 1)'")
+        )
+        .subcommand(
+            SubCommand::with_name("reweight_committee")
+                .about("Change the stake of some authorities of a committee file (keeping their keys and addresses)")
+                .args_from_usage("--committee=<FILE> 'The file containing committee information'")
+                .args_from_usage("--stakes=<STAKES> 'The new stakes, as a comma-separated list of <PUBKEY>:<STAKE>'")
+                .args_from_usage("--filename=[FILE] 'The file where to print the new committee (defaults to the input file)'")
+                .args_from_usage("--governance_keypair=[FILE] 'The governance key pair with which to sign the new committee file (the signature is printed to <FILE>.sig)'")
+                .args_from_usage("--force 'Allow an authority to hold a quorum of the stake on its own'")
+        )
+        .subcommand(
+            SubCommand::with_name("inspect_committee")
+                .about("Print a summary of the committee")
+                .args_from_usage("--committee=<FILE> 'The file containing committee information'")
+        )
+        .subcommand(
+            SubCommand::with_name("validate_config")
+                .about("Check the consistency of the configuration files of a node")
+                .args_from_usage("--committee=<FILE> 'The file containing committee information'")
+                .args_from_usage("--parameters=[FILE] 'The file containing the node parameters'")
+                .args_from_usage("--keypair=<FILE> 'The file containing the node keypair'")
+                .args_from_usage("--threshold_keypair=[FILE] 'The file containing the node threshold keypair'")
+        )
+        .subcommand(
+            SubCommand::with_name("snapshot_store")
+                .about("Copy the data store of a stopped node (primary or worker) into a single file")
+                .args_from_usage("--store=<PATH> 'The path of the data store'")
+                .args_from_usage("--out=<FILE> 'The file where to write the snapshot'")
+        )
+        .subcommand(
+            SubCommand::with_name("restore_store")
+                .about("Restore a data store from a snapshot")
+                .args_from_usage("--in=<FILE> 'The file containing the snapshot'")
+                .args_from_usage("--store=<PATH> 'The path of the data store to restore'")
+                .args_from_usage("--force 'Replace the content of the store if it is not empty'")
+        )
+        .subcommand(
+            SubCommand::with_name("replay")
+                .about("Order a recorded stream of certificates offline and print the committed sequence (one JSON record per certificate)")
+                .args_from_usage("--input=<FILE> 'The file containing the certificates (newline-delimited JSON)'")
+                .args_from_usage("--committee=<FILE> 'The file containing committee information'")
+                .args_from_usage("--parameters=[FILE] 'The file setting the consensus protocol and garbage collection depth (defaults: Tusk, 50 rounds)'")
         )
         .subcommand(
             SubCommand::with_name("run")
                 .about("Run a node")
-                .args_from_usage("--keypair=<FILE> 'The file containing the node keypair'")
+                .args_from_usage("--keypair=[FILE] 'The file containing the node keypair'")
+                .arg(Arg::from_usage("--keypair-env=[VAR] 'The environment variable containing the node keypair'").conflicts_with("keypair"))
+                .group(ArgGroup::with_name("node_keypair").args(&["keypair", "keypair-env"]))
                 .args_from_usage("--committee=<FILE> 'The file containing committee information'")
+                .args_from_usage("--governance-key=[KEY] 'The (base64) public key of the governance key: the committee file must then carry a valid signature by this key (in <FILE>.sig)'")
                 .args_from_usage("--parameters=[FILE] 'The file containing the node parameters'")
                 .args_from_usage("--store=<PATH> 'The path where to create the data store'")
+                .args_from_usage("--store-key=[FILE] 'The file containing the (base64) key with which to encrypt the values of the data store'")
+                .arg(Arg::from_usage("--store-key-env=[VAR] 'The environment variable containing the (base64) key with which to encrypt the values of the data store'").conflicts_with("store-key"))
+                .args_from_usage("--output-json=[FILE] 'The file where to append the committed certificates (newline-delimited JSON, compressed if it ends in .gz or .zst)'")
+                .args_from_usage("--http-admin=[ADDR] 'The address of the HTTP server exposing the /healthz, /readyz, /suspects, /dag?rounds=N, /leaders, /throughput, and /batch/{digest} endpoints'")
+                .args_from_usage("--metrics-addr=[ADDR] 'The address of the HTTP server exposing the Prometheus metrics of the primary and workers (on /metrics)'")
+                .args_from_usage("--force 'Start even if the garbage collection depth is too small for lagging nodes to catch up'")
+                .args_from_usage("--max-committed-rounds=[INT] 'Shut down once the consensus committed a leader of this round (or a later one), after printing a summary of the run'")
+                .args_from_usage("--seed=[INT] 'The seed of the random peer selection of the primary and workers (defaults to entropy); give each node its own seed'")
                 .subcommand(SubCommand::with_name("primary").about("Run a single primary"))
+                .subcommand(SubCommand::with_name("observer").about("Follow the DAG of the committee and order it locally, without taking part in it (no keypair needed)"))
                 .subcommand(
                     SubCommand::with_name("worker")
                         .about("Run a single worker")
                         .args_from_usage("--id=<INT> 'The worker id'")
-                        .args_from_usage("--threshold_keypair=<FILE> 'The file containing the node threshold keypair'")
+                        .args_from_usage("--threshold_keypair=[FILE] 'The file containing the node threshold keypair'")
+                        .arg(Arg::from_usage("--threshold_keypair-env=[VAR] 'The environment variable containing the node threshold keypair'").conflicts_with("threshold_keypair"))
+                        .group(ArgGroup::with_name("node_threshold_keypair").args(&["threshold_keypair", "threshold_keypair-env"]).required(true))
+                )
+                .subcommand(
+                    SubCommand::with_name("workers")
+                        .about("Run several workers in a single process (sharing its data store)")
+                        .arg(Arg::from_usage("--ids=<INT>... 'The ids of the workers (comma-separated)'").use_delimiter(true))
+                        .args_from_usage("--threshold-keypair-dir=<DIR> 'The directory containing the threshold keypair of each worker (one file <id>.json per worker id)'")
+                )
+                .subcommand(
+                    SubCommand::with_name("authority")
+                        .about("Run a primary and all its workers")
+                        .args_from_usage("--threshold_keypairs=<DIR> 'The directory containing the threshold keypair of each worker (one file <id>.json per worker id)'")
                 )
                 .setting(AppSettings::SubcommandRequiredElseHelp),
         )
-        .setting(AppSettings::SubcommandRequiredElseHelp)
-        .get_matches();
+        .setting(AppSettings::SubcommandRequiredElseHelp);
+    #[cfg(feature = "dkg")]
+    let app = app.subcommand(
+        SubCommand::with_name("dkg")
+            .about("Run a distributed key generation with the other authorities and print this node's threshold keypair to file")
+            .args_from_usage("--keypair=<FILE> 'The file containing the node keypair'")
+            .args_from_usage("--committee=<FILE> 'The file containing committee information (the primary-to-primary addresses are used)'")
+            .args_from_usage("--threshold=<INT> 'The threshold number st (threshold+1)/num_shares needed to decrypt'")
+            .args_from_usage("--filename=<FILE> 'The file where to print the new threshold keypair'"),
+    );
+    #[cfg(feature = "benchmark")]
+    let app = app.subcommand(
+        SubCommand::with_name("benchmark")
+            .about("Submit a synthetic load of encrypted transactions to a worker and report the achieved throughput")
+            .args_from_usage("--target=<ADDR> 'The transactions address of the worker'")
+            .args_from_usage("--threshold_pk=<FILE> 'The file containing the threshold public key with which to encrypt the transactions'")
+            .args_from_usage("--rate=<INT> 'The rate (txs/s) at which to submit the transactions'")
+            .args_from_usage("--size=<INT> 'The size of each (plaintext) transaction in bytes'")
+            .args_from_usage("--duration=<INT> 'For how long (in seconds) to submit transactions'"),
+    );
+    #[cfg(feature = "benchmark")]
+    let app = app.subcommand(
+        SubCommand::with_name("submit_file")
+            .about("Replay the (length-prefixed) transactions of a file to a worker and report the achieved throughput")
+            .args_from_usage("--target=<ADDR> 'The transactions address of the worker'")
+            .args_from_usage("--file=<FILE> 'The file containing the transactions, each prefixed by its length (4 bytes, big-endian)'")
+            .args_from_usage("--rate=<INT> 'The rate (txs/s) at which to submit the transactions'"),
+    );
+    app
+}
 
-    let log_level = match matches.occurrences_of("v") {
-        0 => "error",
-        1 => "warn",
-        2 => "info",
-        3 => "debug",
-        _ => "trace",
+/// The module log filters: those of the command line, or else those of the parameters file of the node we
+/// run. The parameters are only peeked at here (the logger is not set up yet): `run` reports their errors.
+fn log_filters(matches: &ArgMatches<'_>) -> String {
+    if let Some(filters) = matches.value_of("log-filters") {
+        return filters.to_string();
+    }
+    matches
+        .subcommand_matches("run")
+        .and_then(|x| x.value_of("parameters"))
+        .and_then(|file| Parameters::import(file).ok())
+        .unwrap_or_default()
+        .log_filters
+}
+
+/// Formats a log record as a single-line JSON object. The timestamp is in milliseconds since the Unix epoch.
+fn format_json_log(
+    buf: &mut env_logger::fmt::Formatter,
+    record: &log::Record<'_>,
+) -> std::io::Result<()> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_millis() as u64)
+        .unwrap_or_default();
+    writeln!(buf, "{}", json_log_line(record, timestamp))
+}
+
+/// The JSON object describing a log record emitted at `timestamp` (in ms since the Unix epoch).
+fn json_log_line(record: &log::Record<'_>, timestamp: u64) -> serde_json::Value {
+    serde_json::json!({
+        "timestamp": timestamp,
+        "level": record.level().to_string(),
+        "target": record.target(),
+        "message": record.args().to_string(),
+    })
+}
+
+// Assembles a committee from the authorities' keys and hosts, and prints it to file.
+fn generate_committee(matches: &ArgMatches<'_>) -> Result<()> {
+    let names = matches
+        .values_of("keys")
+        .unwrap()
+        .map(|file| {
+            KeyPair::import(file)
+                .map(|keypair| keypair.name)
+                .with_context(|| format!("Failed to load the key pair '{}'", file))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let hosts = matches
+        .values_of("hosts")
+        .unwrap()
+        .map(|host| {
+            host.parse::<IpAddr>()
+                .with_context(|| format!("Invalid host '{}'", host))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let stakes = match matches.values_of("stakes") {
+        Some(values) => values
+            .map(|stake| {
+                stake
+                    .parse::<Stake>()
+                    .with_context(|| format!("Invalid stake '{}'", stake))
+            })
+            .collect::<Result<Vec<_>>>()?,
+        None => vec![1; names.len()],
     };
-    let mut logger = env_logger::Builder::from_env(Env::default().default_filter_or(log_level));
-    #[cfg(feature = "benchmark")]
-    logger.format_timestamp_millis();
-    logger.init();
+    let base_port = matches
+        .value_of("base_port")
+        .unwrap()
+        .parse::<u16>()
+        .context("The base port must be a valid port number")?;
+    let workers = matches
+        .value_of("workers")
+        .unwrap()
+        .parse::<usize>()
+        .context("The number of workers must be a positive integer")?;
 
-    match matches.subcommand() {
-        ("generate_keypair", Some(sub_matches)) => KeyPair::new()
-            .export(sub_matches.value_of("filename").unwrap())
-            .context("Failed to generate key pair")?,
-        ("generate_threshold_keypair", Some(sub_matches)) => {
-            let threshold = sub_matches
-                .value_of("threshold")
-                .unwrap()
-                .parse::<usize>()
-                .context("threshold must be an integer")?;
-            let node_index = sub_matches
-                .value_of("node_index")
-                .unwrap()
-                .parse::<usize>()
-                .context("node_index must be an integer")?;
-            let seed = sub_matches
-                .value_of("seed")
-                .unwrap()
-                .parse::<u64>()
-                .context("seed must be an integer")?;
-            ThresholdKeyPair::new(threshold, node_index, seed)
-                .export(sub_matches.value_of("filename").unwrap())
-                .context("Failed to generate threshold keypair")?;
+    ensure!(
+        hosts.len() == 1 || hosts.len() == names.len(),
+        "Expected one host per authority or a single host, got {} hosts for {} authorities",
+        hosts.len(),
+        names.len()
+    );
+    ensure!(
+        stakes.len() == names.len(),
+        "Expected one stake per authority, got {} stakes for {} authorities",
+        stakes.len(),
+        names.len()
+    );
+
+    let authorities = names
+        .into_iter()
+        .zip(stakes)
+        .enumerate()
+        .map(|(i, (name, stake))| (name, stake, hosts[i % hosts.len()]))
+        .collect();
+    let mut committee = Committee::new(authorities, base_port, workers)?;
+    if let Some(file) = matches.value_of("threshold_publickey") {
+        let key = ThresholdPublicKeyFile::import(file)
+            .with_context(|| format!("Failed to load the threshold public key '{}'", file))?;
+        committee.threshold_public_key = Some(key.public_key);
+    }
+    committee.sanity_check()?;
+    let filename = matches.value_of("filename").unwrap();
+    match matches.value_of("governance_keypair") {
+        Some(file) => {
+            let governance = KeyPair::import(file)
+                .with_context(|| format!("Failed to load the governance key pair '{}'", file))?;
+            committee
+                .export_signed(filename, &governance.secret)
+                .context("Failed to generate committee")
         }
-        ("generate_threshold_publickey", Some(sub_matches)) => {
-            let threshold = sub_matches
-                .value_of("threshold")
-                .unwrap()
-                .parse::<usize>()
-                .context("threshold must be an integer")?;
-            let seed = sub_matches
-                .value_of("seed")
+        None => committee
+            .export(filename)
+            .context("Failed to generate committee"),
+    }
+}
+
+// Generates the key material of a committee of `nodes` authorities running on localhost: for each authority
+// `node-<i>.json` (its key pair) and `threshold-<i>.json` (its threshold key share of index i), along with
+// `threshold_public_key.json` and `committee.json`.
+fn keygen_batch(matches: &ArgMatches<'_>) -> Result<()> {
+    let nodes = matches
+        .value_of("nodes")
+        .unwrap()
+        .parse::<usize>()
+        .context("The number of nodes must be a positive integer")?;
+    let threshold = matches
+        .value_of("threshold")
+        .unwrap()
+        .parse::<usize>()
+        .context("threshold must be an integer")?;
+    let seed = match matches.value_of("seed") {
+        Some(x) => x.parse::<u64>().context("seed must be an integer")?,
+        None => rand::random(),
+    };
+    let base_port = matches
+        .value_of("base_port")
+        .unwrap_or("3000")
+        .parse::<u16>()
+        .context("The base port must be a valid port number")?;
+    let workers = matches
+        .value_of("workers")
+        .unwrap_or("1")
+        .parse::<usize>()
+        .context("The number of workers must be a positive integer")?;
+    ensure!(nodes > 0, "The committee needs at least one authority");
+    ensure!(
+        threshold < nodes,
+        "A threshold of {} needs more than {} authorities to decrypt",
+        threshold,
+        nodes
+    );
+
+    let outdir = Path::new(matches.value_of("outdir").unwrap());
+    fs::create_dir_all(outdir)
+        .with_context(|| format!("Failed to create the directory {}", outdir.display()))?;
+    let path = |name: String| outdir.join(name).to_string_lossy().into_owned();
+
+    let mut authorities = Vec::new();
+    let mut public_key = None;
+    for i in 0..nodes {
+        let keypair = KeyPair::new();
+        keypair
+            .export(&path(format!("node-{}.json", i)))
+            .context("Failed to generate key pair")?;
+        authorities.push((keypair.name, 1, IpAddr::from([127, 0, 0, 1])));
+
+        let share = ThresholdKeyPair::new(threshold, i, seed).with_num_shares(nodes);
+        share
+            .export(&path(format!("threshold-{}.json", i)))
+            .context("Failed to generate threshold keypair")?;
+        if public_key.is_none() {
+            public_key = Some(ThresholdPublicKeyFile {
+                public_key: share.pk_set.public_key(),
+                keygen: share.keygen,
+            });
+        }
+    }
+    let public_key = public_key.unwrap();
+    public_key
+        .export(&path("threshold_public_key.json".to_string()))
+        .context("Failed to generate threshold public key")?;
+
+    let mut committee = Committee::new(authorities, base_port, workers)?;
+    committee.threshold_public_key = Some(public_key.public_key);
+    committee.sanity_check()?;
+    committee
+        .export(&path("committee.json".to_string()))
+        .context("Failed to generate committee")?;
+    info!(
+        "Generated the keys of {} authorities in {} from the seed of fingerprint {}",
+        nodes,
+        outdir.display(),
+        SeededKeygen::fingerprint(seed)
+    );
+    Ok(())
+}
+
+// Changes the stake of some authorities and exports the resulting committee.
+fn reweight_committee(matches: &ArgMatches<'_>) -> Result<()> {
+    let input = matches.value_of("committee").unwrap();
+    let mut committee =
+        Committee::import(input).context("Failed to load the committee information")?;
+    let stakes = matches
+        .value_of("stakes")
+        .unwrap()
+        .split(',')
+        .map(|entry| {
+            let mut parts = entry.trim().splitn(2, ':');
+            let name = parts.next().unwrap_or_default();
+            let stake = parts
+                .next()
+                .with_context(|| format!("Expected <PUBKEY>:<STAKE>, got '{}'", entry))?;
+            let name = PublicKey::decode_base64(name)
+                .with_context(|| format!("Invalid public key '{}'", name))?;
+            let stake = stake
+                .parse::<Stake>()
+                .with_context(|| format!("Invalid stake '{}'", stake))?;
+            Ok((name, stake))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    committee.reweight(&stakes, matches.is_present("force"))?;
+    committee.sanity_check()?;
+    println!(
+        "Quorum threshold (2f+1): {} of {} stake",
+        committee.quorum_threshold(),
+        committee.total_stake()
+    );
+    let filename = matches.value_of("filename").unwrap_or(input);
+    match matches.value_of("governance_keypair") {
+        Some(file) => {
+            let governance = KeyPair::import(file)
+                .with_context(|| format!("Failed to load the governance key pair '{}'", file))?;
+            committee
+                .export_signed(filename, &governance.secret)
+                .context("Failed to export the committee")
+        }
+        None => committee
+            .export(filename)
+            .context("Failed to export the committee"),
+    }
+}
+
+// Feeds a recorded stream of certificates to the commit rule and prints the certificates it commits, in order.
+fn replay(matches: &ArgMatches<'_>) -> Result<()> {
+    let committee = Committee::import(matches.value_of("committee").unwrap())
+        .context("Failed to load the committee information")?;
+    let parameters = match matches.value_of("parameters") {
+        Some(filename) => {
+            Parameters::import(filename).context("Failed to load the node's parameters")?
+        }
+        None => Parameters::default(),
+    };
+    let input = matches.value_of("input").unwrap();
+    let data = fs::read_to_string(input).with_context(|| format!("Failed to read '{}'", input))?;
+    let mut certificates = data
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str::<Certificate>(line)
+                .with_context(|| format!("Invalid certificate on line {} of '{}'", i + 1, input))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    // The commit rule expects the parents of a certificate before the certificate itself.
+    certificates.sort_by_key(|x| x.round());
+
+    let rule = consensus::commit_rule(parameters.consensus, &committee);
+    for sub_dag in consensus::replay(&committee, parameters.gc_depth, rule, certificates) {
+        for certificate in &sub_dag.certificates {
+            let record = serde_json::json!({
+                "sub_dag_index": sub_dag.sub_dag_index,
+                "leader_round": sub_dag.round(),
+                "author": certificate.origin().encode_base64(),
+                "round": certificate.round(),
+                "header_digest": format!("{:?}", certificate.header.id),
+                "timestamp": certificate.header.timestamp,
+            });
+            println!("{}", record);
+        }
+    }
+    Ok(())
+}
+
+// Prints the composition of the committee as a table, one row per worker.
+fn inspect_committee(committee: &Committee) {
+    print!("{}", committee_table(committee));
+}
+
+// Renders the composition of the committee as a table (see `inspect_committee`).
+fn committee_table(committee: &Committee) -> String {
+    let header = [
+        "AUTHORITY",
+        "STAKE",
+        "PRIMARY",
+        "WORKER",
+        "TRANSACTIONS",
+        "WORKER_TO_WORKER",
+        "PRIMARY_TO_WORKER",
+    ];
+    let mut rows = Vec::new();
+    for (name, authority) in &committee.authorities {
+        let mut row = vec![
+            name.to_string(),
+            authority.stake.to_string(),
+            authority.primary.primary_to_primary.to_string(),
+        ];
+        let mut ids: Vec<_> = authority.workers.keys().collect();
+        ids.sort();
+        if ids.is_empty() {
+            row.extend(vec![String::new(); 4]);
+            rows.push(row);
+        }
+        for (i, id) in ids.into_iter().enumerate() {
+            if i > 0 {
+                row = vec![String::new(); 3];
+            }
+            let addresses = &authority.workers[id];
+            row.push(id.to_string());
+            row.push(addresses.transactions.to_string());
+            row.push(addresses.worker_to_worker.to_string());
+            row.push(addresses.primary_to_worker.to_string());
+            rows.push(row.clone());
+        }
+    }
+
+    let widths: Vec<_> = header
+        .iter()
+        .enumerate()
+        .map(|(i, title)| {
+            rows.iter()
+                .map(|row| row[i].len())
+                .chain(std::iter::once(title.len()))
+                .max()
                 .unwrap()
-                .parse::<u64>()
-                .context("seed must be an integer")?;
-            // reusing this structure just to generate the public key is an ugly hack but this is a hackathon..
-            ThresholdKeyPair::new(threshold, 0, seed)
-                .pk_set
-                .public_key()
-                .export(sub_matches.value_of("filename").unwrap())
-                .context("Failed to generate threshold public key")?;
+        })
+        .collect();
+    let mut table = String::new();
+    let mut print_row = |row: Vec<&str>| {
+        let cells: Vec<_> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = *width))
+            .collect();
+        table.push_str(cells.join("  ").trim_end());
+        table.push('\n');
+    };
+    print_row(header.to_vec());
+    for row in &rows {
+        print_row(row.iter().map(|x| x.as_str()).collect());
+    }
+
+    table.push('\n');
+    table.push_str(&format!("Authorities: {}\n", committee.size()));
+    table.push_str(&format!(
+        "Quorum threshold (2f+1): {} of {} stake\n",
+        committee.quorum_threshold(),
+        committee
+            .authorities
+            .values()
+            .map(|x| x.stake)
+            .sum::<Stake>()
+    ));
+    table.push_str(&format!(
+        "Validity threshold (f+1): {}\n",
+        committee.validity_threshold()
+    ));
+    table
+}
+
+// Statically checks the configuration files of a node, prints all the problems found, and fails if there
+// are any.
+fn validate_config(matches: &ArgMatches<'_>) -> Result<()> {
+    let problems = config_problems(matches);
+    if problems.is_empty() {
+        println!("The configuration is valid");
+        return Ok(());
+    }
+    for problem in &problems {
+        println!("- {}", problem);
+    }
+    bail!("Found {} configuration problem(s)", problems.len())
+}
+
+// Lists the problems found in the configuration files of a node (see `validate_config`).
+fn config_problems(matches: &ArgMatches<'_>) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    let committee = match Committee::import(matches.value_of("committee").unwrap()) {
+        Ok(committee) => Some(committee),
+        Err(e) => {
+            problems.push(e.to_string());
+            None
+        }
+    };
+    if let Some(file) = matches.value_of("parameters") {
+        match Parameters::import(file) {
+            Ok(parameters) => {
+                if let Err(e) = parameters.check_gc_depth() {
+                    problems.push(e.to_string());
+                }
+                if let Err(e) = parse_log_filters(&parameters.log_filters) {
+                    problems.push(e.to_string());
+                }
+                if let Err(e) = parameters.check_bind_address() {
+                    problems.push(e.to_string());
+                }
+                if let Some(Err(e)) = committee
+                    .as_ref()
+                    .map(|x| x.check_durable_acks(parameters.durable_acks))
+                {
+                    problems.push(e.to_string());
+                }
+            }
+            Err(e) => problems.push(e.to_string()),
+        }
+    }
+    let keypair = match KeyPair::import(matches.value_of("keypair").unwrap()) {
+        Ok(keypair) => Some(keypair),
+        Err(e) => {
+            problems.push(e.to_string());
+            None
+        }
+    };
+    let threshold_keypair = match matches.value_of("threshold_keypair") {
+        Some(file) => match ThresholdKeyPair::import(file) {
+            Ok(threshold_keypair) => Some(threshold_keypair),
+            Err(e) => {
+                problems.push(e.to_string());
+                None
+            }
+        },
+        None => None,
+    };
+
+    if let Some(committee) = &committee {
+        if let Err(e) = committee.sanity_check() {
+            problems.push(e.to_string());
+        }
+
+        // Ensure no address is used twice (even by the same authority).
+        let mut seen = HashSet::new();
+        for (name, address) in committee.addresses() {
+            if !seen.insert(address) {
+                problems.push(format!(
+                    "Address {} (of authority {}) is used more than once",
+                    address, name
+                ));
+            }
+        }
+
+        if let Some(keypair) = &keypair {
+            if committee.stake(&keypair.name) == 0 {
+                problems.push(format!(
+                    "The public key {} of the keypair is not in the committee",
+                    keypair.name
+                ));
+            }
+        }
+
+        if let Some(threshold_keypair) = &threshold_keypair {
+            // Decryption requires `threshold + 1` shares, one from each authority.
+            let threshold = threshold_keypair.pk_set.threshold();
+            if threshold >= committee.size() {
+                problems.push(format!(
+                    "The threshold key requires {} decryption shares but the committee only has {} authorities",
+                    threshold + 1,
+                    committee.size()
+                ));
+            }
+            if let Err(e) = committee.check_threshold_key(&threshold_keypair.pk_set) {
+                problems.push(e.to_string());
+            }
+            if threshold_keypair.node_index >= committee.size() {
+                problems.push(format!(
+                    "The threshold key share index {} is out of range for a committee of {} authorities",
+                    threshold_keypair.node_index,
+                    committee.size()
+                ));
+            }
         }
-        ("run", Some(sub_matches)) => run(sub_matches).await?,
-        _ => unreachable!(),
     }
+    problems
+}
+
+// Submits a synthetic load of transactions to a worker and prints the achieved throughput.
+#[cfg(feature = "benchmark")]
+async fn benchmark(matches: &ArgMatches<'_>) -> Result<()> {
+    let target = matches
+        .value_of("target")
+        .unwrap()
+        .parse::<SocketAddr>()
+        .context("Invalid target address")?;
+    let threshold_pk = ThresholdPublicKeyFile::import(matches.value_of("threshold_pk").unwrap())
+        .context("Failed to load the threshold public key")?
+        .public_key;
+    let rate = matches
+        .value_of("rate")
+        .unwrap()
+        .parse::<u64>()
+        .context("rate must be an integer")?;
+    let size = matches
+        .value_of("size")
+        .unwrap()
+        .parse::<usize>()
+        .context("size must be an integer")?;
+    let duration = matches
+        .value_of("duration")
+        .unwrap()
+        .parse::<u64>()
+        .context("duration must be an integer")?;
+
+    let generator = node::LoadGenerator {
+        target,
+        threshold_pk,
+        rate,
+        size,
+        duration: Duration::from_secs(duration),
+    };
+    let report = generator
+        .run()
+        .await
+        .context(format!("Failed to submit transactions to {}", target))?;
+    println!("{}", report);
+    Ok(())
+}
+
+// Replays the transactions of a file to a worker.
+#[cfg(feature = "benchmark")]
+async fn submit_file(matches: &ArgMatches<'_>) -> Result<()> {
+    let target = matches
+        .value_of("target")
+        .unwrap()
+        .parse::<SocketAddr>()
+        .context("Invalid target address")?;
+    let rate = matches
+        .value_of("rate")
+        .unwrap()
+        .parse::<u64>()
+        .context("rate must be an integer")?;
+    let file = matches.value_of("file").unwrap().to_string();
+
+    let replayer = node::FileReplayer { target, file, rate };
+    let report = replayer
+        .run()
+        .await
+        .with_context(|| format!("Failed to replay '{}' to {}", replayer.file, target))?;
+    println!("{}", report);
     Ok(())
 }
 
-// Runs either a worker or a primary.
-async fn run(matches: &ArgMatches<'_>) -> Result<()> {
-    let key_file = matches.value_of("keypair").unwrap();
+// Runs either a worker, a primary, or an entire authority. The committed certificates are fed to `application`.
+async fn run<A: Application>(matches: &ArgMatches<'_>, application: A) -> Result<()> {
+    #[cfg(feature = "otlp")]
+    install_otlp_exporter()?;
+
     let committee_file = matches.value_of("committee").unwrap();
     let parameters_file = matches.value_of("parameters");
     let store_path = matches.value_of("store").unwrap();
+    let store_key = match (
+        matches.value_of("store-key"),
+        matches.value_of("store-key-env"),
+    ) {
+        (Some(file), _) => Some(
+            StoreKey::from_file(file)
+                .with_context(|| format!("Failed to load the store key '{}'", file))?,
+        ),
+        (None, Some(var)) => {
+            let data = env::var(var)
+                .with_context(|| format!("Failed to read the environment variable '{}'", var))?;
+            Some(StoreKey::decode_base64(&data).context("Invalid store key")?)
+        }
+        (None, None) => None,
+    };
+
+    // Read the committee and node's keypair from file (or from the environment). Observers have none.
+    let observer = matches.subcommand_name() == Some("observer");
+    let keypair: Option<KeyPair> = if observer {
+        None
+    } else {
+        ensure!(
+            matches.is_present("node_keypair"),
+            "Specify the node's keypair with --keypair or --keypair-env"
+        );
+        Some(
+            import_from_file_or_env(matches.value_of("keypair"), matches.value_of("keypair-env"))
+                .context("Failed to load the node's keypair")?,
+        )
+    };
+    let committee = match matches.value_of("governance-key") {
+        Some(key) => {
+            let trust_anchor =
+                PublicKey::decode_base64(key).context("Invalid governance public key")?;
+            Committee::import_signed(committee_file, &trust_anchor)
+        }
+        None => Committee::import(committee_file),
+    }
+    .context("Failed to load the committee information")?;
+    committee.check_signature_scheme()?;
+    committee.check_hash_function()?;
+    if committee.threshold_public_key.is_none()
+        && matches.subcommand_name() != Some("primary")
+        && !observer
+    {
+        warn!("The committee does not specify a threshold public key: cannot check the threshold keypairs");
+    }
 
-    // Read the committee and node's keypair and threshold keypair from file.
-    let keypair = KeyPair::import(key_file).context("Failed to load the node's keypair")?;
-    let committee =
-        Committee::import(committee_file).context("Failed to load the committee information")?;
+    // Make sure we are part of the committee before spawning anything (observers are not).
+    if let Some(keypair) = &keypair {
+        ensure!(
+            committee.authorities.contains_key(&keypair.name),
+            "The node's public key {} is not in the committee file '{}'",
+            keypair.name,
+            committee_file
+        );
+        if let ("worker", Some(sub_matches)) = matches.subcommand() {
+            // An invalid worker id is reported when spawning the worker.
+            if let Ok(id) = sub_matches.value_of("id").unwrap().parse::<WorkerId>() {
+                ensure!(
+                    committee.worker(&keypair.name, &id).is_ok(),
+                    "Worker {} of authority {} is not in the committee file '{}'",
+                    id,
+                    keypair.name,
+                    committee_file
+                );
+            }
+        }
+    }
 
     // Load default parameters if none are specified.
-    let parameters = match parameters_file {
+    let mut parameters = match parameters_file {
         Some(filename) => {
             Parameters::import(filename).context("Failed to load the node's parameters")?
         }
         None => Parameters::default(),
     };
+    if let Some(seed) = matches.value_of("seed") {
+        let seed = seed
+            .parse::<u64>()
+            .context("The seed must be a positive integer")?;
+        parameters.seed = Some(seed);
+    }
+    if let Some(rounds) = matches.value_of("max-committed-rounds") {
+        let rounds = rounds
+            .parse::<u64>()
+            .context("The maximum number of committed rounds must be a positive integer")?;
+        parameters.max_committed_rounds = Some(rounds);
+    }
+    if let Err(e) = parameters.check_gc_depth() {
+        if !matches.is_present("force") {
+            return Err(e).context("Refusing to start (use --force to override)");
+        }
+        warn!("{}", e);
+    }
+
+    // The listeners are only bound once the tasks are spawned: make sure they can be.
+    parameters.check_bind_address()?;
+    committee.check_durable_acks(parameters.durable_acks)?;
+
+    // Optionally wrap the connections with the other nodes in TLS, pinning the public key of each authority
+    // to its addresses.
+    if let Some(tls) = &parameters.tls {
+        // Observers are not authorities: they have no certificate the primaries would accept.
+        let keypair = match &keypair {
+            Some(x) => x,
+            None => bail!("Observers cannot sync from a committee using TLS"),
+        };
+        let peers = committee
+            .addresses()
+            .into_iter()
+            .map(|(name, address)| (address, name.as_ref().to_vec()))
+            .collect();
+        let config = TlsConfig::new(&tls.certificate, &tls.private_key, peers)
+            .context("Failed to load the TLS certificate")?;
+        ensure!(
+            config.identity() == keypair.name.as_ref(),
+            "The TLS certificate does not hold the node's (ed25519) public key"
+        );
+        network::tls::install(config);
+    }
+
+    // The live-reloadable parameters are re-imported from file upon SIGHUP and broadcast to the tasks using them.
+    let (tx_parameters, rx_parameters) = watch::channel(parameters.clone());
 
     // Make the data store.
-    let store = Store::new(store_path).context("Failed to create a store")?;
+    let store = open_store(store_path, store_key.as_ref()).context("Failed to create a store")?;
+    let committed = consensus::read_committed(&mut store.clone())
+        .await
+        .context("Failed to read the committed watermark")?;
 
     // Channels the sequence of certificates.
-    let (tx_output, rx_output) = channel(CHANNEL_CAPACITY);
+    let channel_capacity = parameters.channel_capacity;
+    let (tx_output, rx_output) = channel(channel_capacity);
 
-    // Check whether to run a primary, a worker, or an entire authority.
-    match matches.subcommand() {
-        // Spawn the primary and consensus core.
-        ("primary", _) => {
-            let (tx_new_certificates, rx_new_certificates) = channel(CHANNEL_CAPACITY);
-            let (tx_feedback, rx_feedback) = channel(CHANNEL_CAPACITY);
-            Primary::spawn(
-                keypair,
-                committee.clone(),
-                parameters.clone(),
-                store,
-                /* tx_consensus */ tx_new_certificates,
-                /* rx_consensus */ rx_feedback,
-            );
-            Consensus::spawn(
-                committee,
-                parameters.gc_depth,
-                /* rx_primary */ rx_new_certificates,
-                /* tx_primary */ tx_feedback,
-                tx_output,
-            );
+    // The stores to flush upon shutdown.
+    let mut stores = vec![store.clone()];
+
+    // The address on which to stream the ordered certificates (only relevant if we run a primary).
+    let output_address = match matches.subcommand_name() {
+        Some("worker") | Some("workers") => None,
+        _ => parameters.output_address,
+    };
+
+    // Optionally stream the committed sub-dags, replaying them from the store (only relevant if we run
+    // a consensus).
+    let sub_dag_address = match matches.subcommand_name() {
+        Some("worker") | Some("workers") => None,
+        _ => parameters.sub_dag_address,
+    };
+    let (tx_sub_dags, sub_dag_stream) = match sub_dag_address {
+        Some(address) => {
+            let (tx_sub_dags, rx_sub_dags) = channel(channel_capacity);
+            let stream =
+                SubDagStream::spawn(address, store.clone(), parameters.gc_depth, rx_sub_dags);
+            (Some(tx_sub_dags), Some(stream))
         }
+        None => (None, None),
+    };
+
+    // Optionally report the liveness and readiness of the node over HTTP. The readiness is fed by the primary.
+    let peers_health = PeersHealth::default();
+    let admin_address = match matches.value_of("http-admin") {
+        Some(address) => Some(
+            address
+                .parse::<SocketAddr>()
+                .context("Invalid address for the HTTP admin server")?,
+        ),
+        None => None,
+    };
+    let admin_health = match matches.subcommand_name() {
+        Some("worker") | Some("workers") | Some("observer") => None,
+        _ => Some(peers_health.clone()),
+    };
+    let admin_committee = committee.clone();
+    let admin_store = store.clone();
+    let admin_dag_store = match matches.subcommand_name() {
+        Some("worker") | Some("workers") => None,
+        _ => Some(store.clone()),
+    };
+
+    // The stores of the workers we run, in which the admin server looks up batches.
+    let mut worker_stores = WorkerStores::new();
+
+    // Optionally export the metrics of the node in the Prometheus format.
+    let registry = Registry::new();
+    let metrics_server = match matches.value_of("metrics-addr") {
+        Some(address) => {
+            let address = address
+                .parse::<SocketAddr>()
+                .context("Invalid address for the metrics server")?;
+            Some(MetricsServer::spawn(address, registry.clone()))
+        }
+        None => None,
+    };
+
+    // The committed batches can only be decrypted when we run all the workers of the authority.
+    let mut decryptor = None;
+
+    // The threshold keypair files re-imported upon SIGHUP to rotate the keys of our workers.
+    let mut key_files = Vec::new();
+
+    // Tells the workers to seal their batch in flight upon shutdown. Their tasks hold a clone of the token
+    // until they drained, so that `rx_drained` returns `None` once all the workers are done.
+    let (tx_shutdown, rx_shutdown) = watch::channel(false);
+    let (tx_drained, mut rx_drained) = channel(1);
+    let shutdown = Shutdown::new(rx_shutdown, tx_drained);
+
+    // Check whether to run a primary, a worker, an entire authority, or an observer.
+    let keypair = || keypair.expect("Only observers run without keypair");
+    let mut handles = match matches.subcommand() {
+        // Spawn the primary and consensus core.
+        ("primary", _) => spawn_primary(
+            keypair(),
+            committee,
+            parameters.clone(),
+            store,
+            tx_output,
+            tx_sub_dags,
+            peers_health,
+            &registry,
+            rx_parameters,
+        )?,
 
         // Spawn a single worker.
         ("worker", Some(sub_matches)) => {
@@ -176,31 +1242,598 @@ async fn run(matches: &ArgMatches<'_>) -> Result<()> {
                 .unwrap()
                 .parse::<WorkerId>()
                 .context("The worker id must be a positive integer")?;
-            let threshold_key_file = sub_matches.value_of("threshold_keypair").unwrap();
-            let threshold_keypair = ThresholdKeyPair::import(threshold_key_file)
-                .context("Failed to load the node's threshold keypair")?;
+            let threshold_keypair: ThresholdKeyPair = import_from_file_or_env(
+                sub_matches.value_of("threshold_keypair"),
+                sub_matches.value_of("threshold_keypair-env"),
+            )
+            .context("Failed to load the node's threshold keypair")?;
+            committee
+                .check_worker_threshold_key(&id, &threshold_keypair.pk_set)
+                .context("Refusing to use the node's threshold keypair")?;
+            let key_ring = ThresholdKeyRing::new(threshold_keypair);
+            if let Some(file) = sub_matches.value_of("threshold_keypair") {
+                key_files.push((file.to_string(), key_ring.clone()));
+            }
+            worker_stores.insert(
+                id,
+                (PrefixedStore::new(store.clone(), &[]), key_ring.clone()),
+            );
+            // We do not decrypt the committed batches: the other workers may still ask for our shares.
+            let (_, rx_share_requests) = channel(1);
             Worker::spawn(
-                keypair.name,
+                keypair().name,
                 id,
-                threshold_keypair,
+                key_ring,
                 committee,
-                parameters,
+                parameters.clone(),
                 store,
-            );
+                &registry,
+                rx_parameters,
+                rx_share_requests,
+                shutdown.clone(),
+            )
+            .context("Failed to spawn the worker")?
+        }
+
+        // Spawn several workers of this authority, each keeping its data under its own prefix of the store.
+        ("workers", Some(sub_matches)) => {
+            let threshold_keys_dir = sub_matches.value_of("threshold-keypair-dir").unwrap();
+            let ids = sub_matches
+                .values_of("ids")
+                .unwrap()
+                .map(|x| {
+                    x.parse::<WorkerId>()
+                        .with_context(|| format!("Invalid worker id '{}'", x))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let name = keypair().name;
+            let ours = committee
+                .our_worker_ids(&name)
+                .context("Failed to infer the worker ids of this authority")?;
+            let mut seen = HashSet::new();
+            for id in &ids {
+                ensure!(seen.insert(*id), "Worker {} is listed more than once", id);
+                ensure!(
+                    ours.contains(id),
+                    "Worker {} is not one of the workers of this authority",
+                    id
+                );
+            }
+
+            // Load all the threshold keypairs before spawning anything.
+            let mut workers = Vec::new();
+            for id in ids {
+                let threshold_key_file = threshold_keypair_file(threshold_keys_dir, id);
+                let threshold_keypair = ThresholdKeyPair::import(&threshold_key_file)
+                    .with_context(|| {
+                        format!("Failed to load the threshold keypair of worker {}", id)
+                    })?;
+                committee
+                    .check_worker_threshold_key(&id, &threshold_keypair.pk_set)
+                    .with_context(|| {
+                        format!("Refusing to use the threshold keypair of worker {}", id)
+                    })?;
+                let key_ring = ThresholdKeyRing::new(threshold_keypair);
+                key_files.push((threshold_key_file, key_ring.clone()));
+                workers.push((id, key_ring));
+            }
+
+            let mut handles = Vec::new();
+            for (id, key_ring) in workers {
+                let worker_store = PrefixedStore::new(store.clone(), &worker_store_prefix(id));
+                worker_stores.insert(id, (worker_store.clone(), key_ring.clone()));
+                // We do not decrypt the committed batches: the other workers may still ask for our shares.
+                let (_, rx_share_requests) = channel(1);
+                handles.extend(
+                    Worker::spawn(
+                        name,
+                        id,
+                        key_ring,
+                        committee.clone(),
+                        parameters.clone(),
+                        worker_store,
+                        &registry,
+                        rx_parameters.clone(),
+                        rx_share_requests,
+                        shutdown.clone(),
+                    )
+                    .with_context(|| format!("Failed to spawn worker {}", id))?,
+                );
+            }
+            handles
         }
+
+        // Spawn the primary, the consensus core, and all the workers of this authority.
+        ("authority", Some(sub_matches)) => {
+            let threshold_keys_dir = sub_matches.value_of("threshold_keypairs").unwrap();
+            let name = keypair().name;
+            let ids = committee
+                .our_worker_ids(&name)
+                .context("Failed to infer the worker ids of this authority")?;
+
+            // Load the threshold keypairs and make the stores of all workers before spawning anything.
+            let mut workers = Vec::new();
+            for id in ids {
+                let threshold_key_file = threshold_keypair_file(threshold_keys_dir, id);
+                let threshold_keypair = ThresholdKeyPair::import(&threshold_key_file)
+                    .with_context(|| {
+                        format!("Failed to load the threshold keypair of worker {}", id)
+                    })?;
+                committee
+                    .check_worker_threshold_key(&id, &threshold_keypair.pk_set)
+                    .with_context(|| {
+                        format!("Refusing to use the threshold keypair of worker {}", id)
+                    })?;
+                let worker_store =
+                    open_store(&worker_store_path(store_path, id), store_key.as_ref())
+                        .with_context(|| format!("Failed to create a store for worker {}", id))?;
+                stores.push(worker_store.clone());
+                let key_ring = ThresholdKeyRing::new(threshold_keypair);
+                key_files.push((threshold_key_file, key_ring.clone()));
+                worker_stores.insert(
+                    id,
+                    (
+                        PrefixedStore::new(worker_store.clone(), &[]),
+                        key_ring.clone(),
+                    ),
+                );
+                workers.push((id, key_ring, worker_store));
+            }
+
+            let mut handles = spawn_primary(
+                keypair(),
+                committee.clone(),
+                parameters.clone(),
+                store,
+                tx_output,
+                tx_sub_dags,
+                peers_health,
+                &registry,
+                rx_parameters.clone(),
+            )?;
+            let mut decryption_keys = HashMap::new();
+            for (id, key_ring, worker_store) in workers {
+                let (tx_share_requests, rx_share_requests) = channel(channel_capacity);
+                decryption_keys.insert(
+                    id,
+                    (key_ring.clone(), worker_store.clone(), tx_share_requests),
+                );
+                handles.extend(
+                    Worker::spawn(
+                        name,
+                        id,
+                        key_ring,
+                        committee.clone(),
+                        parameters.clone(),
+                        worker_store,
+                        &registry,
+                        rx_parameters.clone(),
+                        rx_share_requests,
+                        shutdown.clone(),
+                    )
+                    .with_context(|| format!("Failed to spawn worker {}", id))?,
+                );
+            }
+            decryptor = Some(Decryptor::new(
+                decryption_keys,
+                Duration::from_millis(parameters.decryption_timeout_ms),
+            ));
+            handles
+        }
+
+        // Sync the certificates from the primaries of the committee and run the consensus core.
+        ("observer", _) => spawn_observer(
+            committee,
+            parameters.clone(),
+            store,
+            tx_output,
+            tx_sub_dags,
+            &registry,
+            rx_parameters,
+        ),
         _ => unreachable!(),
+    };
+    handles.extend(sub_dag_stream);
+
+    // Count the committed transactions as they are decrypted.
+    let throughput = decryptor
+        .as_ref()
+        .map(|_| Throughput::new(&registry, parameters.dedup_window));
+    handles.extend(throughput.as_ref().map(|x| x.spawn_refresher()));
+    if let Some(address) = admin_address {
+        handles.push(AdminServer::spawn(
+            address,
+            admin_committee,
+            admin_health,
+            admin_store,
+            admin_dag_store,
+            worker_stores,
+            throughput.clone(),
+        ));
+    }
+    handles.extend(metrics_server);
+    handles.extend(spawn_reloader(
+        parameters_file,
+        parameters,
+        tx_parameters,
+        key_files,
+    )?);
+
+    // Stream the ordered certificates to external subscribers before handing them to the application.
+    let rx_output = match output_address {
+        Some(address) => {
+            let (tx_application, rx_application) = channel(channel_capacity);
+            handles.push(CertificateStream::spawn(address, rx_output, tx_application));
+            rx_application
+        }
+        None => rx_output,
+    };
+
+    // Analyze the consensus' output until we are asked to shut down, or until the consensus stops (once it
+    // committed the rounds of `--max-committed-rounds`).
+    let summary = throughput.clone();
+    tokio::select! {
+        certificates = analyze(rx_output, application, decryptor, committed, throughput) => {
+            let last = consensus::read_committed(&mut stores[0].clone())
+                .await
+                .context("Failed to read the committed watermark")?;
+            info!(
+                "The consensus stopped: {} certificate(s) committed in this run",
+                certificates
+            );
+            if let Some(last) = last {
+                info!(
+                    "Committed up to sub-dag {} (leader of round {})",
+                    last.sub_dag_index, last.round
+                );
+            }
+            if let Some(report) = summary.map(|x| x.report()) {
+                info!("Committed {} transaction(s) in this run", report.transactions);
+            }
+        },
+        result = shutdown_signal() => result.context("Failed to listen for shutdown signals")?,
+    }
+    info!("Shutting down");
+
+    // Let the workers get their batches in flight acknowledged and stored, then stop all tasks so that no
+    // new work is accepted, and wait for them to terminate.
+    drop(shutdown);
+    let _ = tx_shutdown.send(true);
+    if timeout(DRAIN_TIMEOUT, rx_drained.recv()).await.is_err() {
+        warn!(
+            "The workers did not drain their batches in flight within {} ms",
+            DRAIN_TIMEOUT.as_millis()
+        );
+    }
+    for handle in &handles {
+        handle.abort();
+    }
+    for handle in handles {
+        let _ = handle.await;
+    }
+
+    // The store processes its commands in order: every write issued by the (now stopped) tasks is
+    // persisted once the flush completes.
+    for mut store in stores {
+        store.flush().await.context("Failed to flush the store")?;
+    }
+    #[cfg(feature = "otlp")]
+    opentelemetry::global::shutdown_tracer_provider();
+    info!("Shutdown complete");
+    Ok(())
+}
+
+/// Exports the tracing spans to the OpenTelemetry collector set by the `OTEL_EXPORTER_OTLP_ENDPOINT`
+/// environment variable (if any). Without collector no subscriber is installed, and the spans are
+/// disabled at their call sites.
+#[cfg(feature = "otlp")]
+fn install_otlp_exporter() -> Result<()> {
+    use tracing_subscriber::layer::SubscriberExt as _;
+
+    if env::var_os("OTEL_EXPORTER_OTLP_ENDPOINT").is_none() {
+        return Ok(());
+    }
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .with_env()
+        .install_batch(opentelemetry::runtime::Tokio)
+        .context("Failed to install the OTLP exporter")?;
+    let subscriber =
+        tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer));
+    tracing::subscriber::set_global_default(subscriber)
+        .context("Failed to install the tracing subscriber")?;
+    info!("Exporting traces to the OpenTelemetry collector");
+    Ok(())
+}
+
+/// Completes when the process receives SIGINT (Ctrl-C) or SIGTERM.
+#[cfg(unix)]
+async fn shutdown_signal() -> std::io::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut sigterm = signal(SignalKind::terminate())?;
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result,
+        _ = sigterm.recv() => Ok(()),
+    }
+}
+
+/// Completes when the process receives Ctrl-C.
+#[cfg(not(unix))]
+async fn shutdown_signal() -> std::io::Result<()> {
+    tokio::signal::ctrl_c().await
+}
+
+/// Upon SIGHUP, re-imports the parameters from `parameters_file` (if any) and broadcasts the live-reloadable
+/// ones through `tx_parameters`, and re-imports each threshold keypair file of `key_files` to rotate the keys
+/// of the matching key ring. `parameters` are the values currently in use.
+#[cfg(unix)]
+fn spawn_reloader(
+    parameters_file: Option<&str>,
+    mut parameters: Parameters,
+    tx_parameters: watch::Sender<Parameters>,
+    key_files: Vec<(String, ThresholdKeyRing)>,
+) -> Result<Option<JoinHandle<()>>> {
+    use tokio::signal::unix::{signal, SignalKind};
+    if parameters_file.is_none() && key_files.is_empty() {
+        return Ok(None);
     }
+    let mut sighup = signal(SignalKind::hangup()).context("Failed to listen for SIGHUP")?;
+    let parameters_file = parameters_file.map(|x| x.to_string());
+    Ok(Some(tokio::spawn(async move {
+        while sighup.recv().await.is_some() {
+            if let Some(filename) = &parameters_file {
+                match Parameters::import(filename) {
+                    Ok(new) => {
+                        for field in parameters.reload(&new) {
+                            warn!(
+                                "Ignoring the new value of '{}': it cannot change at runtime",
+                                field
+                            );
+                        }
+                        // The reloaded delays may leave too few rounds to the (boot-time) `gc_depth`.
+                        if let Err(e) = parameters.check_gc_depth() {
+                            warn!("{}", e);
+                        }
+                        info!("Reloaded the parameters from {}", filename);
+                        // This may fail if no task uses the live-reloadable parameters.
+                        let _ = tx_parameters.send(parameters.clone());
+                    }
+                    Err(e) => warn!("Failed to reload the parameters: {}", e),
+                }
+            }
+            for (filename, key_ring) in &key_files {
+                rotate_threshold_keys(filename, key_ring);
+            }
+        }
+    })))
+}
 
-    // Analyze the consensus' output.
-    analyze(rx_output).await;
+/// SIGHUP does not exist on this platform: neither the parameters nor the threshold keys can be reloaded.
+#[cfg(not(unix))]
+fn spawn_reloader(
+    _parameters_file: Option<&str>,
+    _parameters: Parameters,
+    _tx_parameters: watch::Sender<Parameters>,
+    _key_files: Vec<(String, ThresholdKeyRing)>,
+) -> Result<Option<JoinHandle<()>>> {
+    Ok(None)
+}
 
-    // If this expression is reached, the program ends and all other tasks terminate.
-    unreachable!();
+/// Moves `key_ring` to the epoch of the threshold keypair in `filename`, if it is newer than the current one.
+/// The keys of the epoch we leave are kept until the next rotation to decrypt its in-flight batches.
+#[cfg(unix)]
+fn rotate_threshold_keys(filename: &str, key_ring: &ThresholdKeyRing) {
+    let keypair = match ThresholdKeyPair::import(filename) {
+        Ok(keypair) => keypair,
+        Err(e) => {
+            warn!("Failed to reload the threshold keypair: {}", e);
+            return;
+        }
+    };
+    if keypair.epoch <= key_ring.current_epoch() {
+        return;
+    }
+    if let Err(e) = key_ring.install(keypair) {
+        warn!(
+            "Failed to install the threshold keypair of {}: {}",
+            filename, e
+        );
+        return;
+    }
+    if let Some(epoch) = key_ring.advance() {
+        info!(
+            "Rotated the threshold keys of {} to epoch {}",
+            filename, epoch
+        );
+    }
 }
 
-/// Receives an ordered list of certificates and apply any application-specific logic.
-async fn analyze(mut rx_output: Receiver<Certificate>) {
-    while let Some(_certificate) = rx_output.recv().await {
-        // NOTE: Here goes the application logic.
+/// Adds a new signing key to a key pair file, and prints the rotation to add to the node's entry in the
+/// committee. Both keys are accepted by the other nodes during the overlap.
+fn rotate_keypair(matches: &ArgMatches<'_>) -> Result<()> {
+    let filename = matches.value_of("filename").unwrap();
+    let epoch = matches
+        .value_of("epoch")
+        .unwrap()
+        .parse::<Epoch>()
+        .context("epoch must be an integer")?;
+    let overlap = matches
+        .value_of("overlap")
+        .unwrap_or("1")
+        .parse::<Epoch>()
+        .context("overlap must be an integer")?;
+    let mut keypair = KeyPair::import(filename).context("Failed to load the node's keypair")?;
+    if let Some(next) = &keypair.next {
+        bail!(
+            "The key pair already rotates to {} from epoch {}",
+            next.key,
+            next.from_epoch
+        );
     }
+    let rotation = keypair.rotate(epoch, epoch.saturating_add(overlap));
+    keypair
+        .export(filename)
+        .context("Failed to write the rotated key pair")?;
+    println!(
+        "Add to the committee entry of {}: \"key_rotation\": {}",
+        keypair.name,
+        serde_json::to_string(&rotation)?
+    );
+    Ok(())
+}
+
+/// Loads a configuration object from `file` if specified, or from the environment variable `var` otherwise.
+fn import_from_file_or_env<T: Import>(file: Option<&str>, var: Option<&str>) -> Result<T> {
+    match (file, var) {
+        (Some(file), _) => Ok(T::import(file)?),
+        (None, Some(var)) => {
+            let data = env::var(var)
+                .with_context(|| format!("Failed to read the environment variable '{}'", var))?;
+            Ok(T::import_from_str(&data)?)
+        }
+        (None, None) => unreachable!(),
+    }
+}
+
+/// Spawns an observer and its consensus core, fed with the certificates synced from the committee. The
+/// ordered certificates are sent to `tx_output`, and the sub-dags to `tx_sub_dags` (if any).
+fn spawn_observer(
+    committee: Committee,
+    parameters: Parameters,
+    store: Store,
+    tx_output: Sender<Certificate>,
+    tx_sub_dags: Option<Sender<CommittedSubDag>>,
+    registry: &Registry,
+    rx_parameters: watch::Receiver<Parameters>,
+) -> Vec<JoinHandle<()>> {
+    let (tx_new_certificates, rx_new_certificates) = channel(parameters.channel_capacity);
+    let (tx_feedback, rx_feedback) = channel(parameters.channel_capacity);
+    vec![
+        Observer::spawn(
+            committee.clone(),
+            store.clone(),
+            &parameters,
+            rx_parameters,
+            /* tx_consensus */ tx_new_certificates,
+            /* rx_consensus */ rx_feedback,
+        ),
+        Consensus::spawn(
+            committee.clone(),
+            store,
+            parameters.gc_depth,
+            consensus::commit_rule(parameters.consensus, &committee),
+            /* rx_primary */ rx_new_certificates,
+            /* tx_primary */ tx_feedback,
+            tx_output,
+            tx_sub_dags,
+            ConsensusMetrics::new(registry, &parameters.commit_latency_buckets),
+            parameters.max_committed_rounds,
+        ),
+    ]
+}
+
+/// Spawns a primary and its consensus core. The ordered certificates are sent to `tx_output`, and the
+/// sub-dags to `tx_sub_dags` (if any).
+#[allow(clippy::too_many_arguments)]
+fn spawn_primary(
+    keypair: KeyPair,
+    committee: Committee,
+    parameters: Parameters,
+    store: Store,
+    tx_output: Sender<Certificate>,
+    tx_sub_dags: Option<Sender<CommittedSubDag>>,
+    peers_health: PeersHealth,
+    registry: &Registry,
+    rx_parameters: watch::Receiver<Parameters>,
+) -> Result<Vec<JoinHandle<()>>> {
+    let (tx_new_certificates, rx_new_certificates) = channel(parameters.channel_capacity);
+    let (tx_feedback, rx_feedback) = channel(parameters.channel_capacity);
+    let mut handles = Primary::spawn(
+        keypair,
+        committee.clone(),
+        parameters.clone(),
+        store.clone(),
+        /* tx_consensus */ tx_new_certificates,
+        /* rx_consensus */ rx_feedback,
+        peers_health,
+        registry,
+        rx_parameters,
+        /* tx_equivocations */ None,
+    )
+    .context("Failed to spawn the primary")?;
+    handles.push(Consensus::spawn(
+        committee.clone(),
+        store,
+        parameters.gc_depth,
+        consensus::commit_rule(parameters.consensus, &committee),
+        /* rx_primary */ rx_new_certificates,
+        /* tx_primary */ tx_feedback,
+        tx_output,
+        tx_sub_dags,
+        ConsensusMetrics::new(registry, &parameters.commit_latency_buckets),
+        parameters.max_committed_rounds,
+    ));
+    Ok(handles)
+}
+
+/// Returns the threshold of the key shares generated for `worker` (or for the whole committee), after checking
+/// that the committee has `threshold + 1` authorities to hold them and one of index `node_index`.
+fn shard_threshold(
+    committee: &Committee,
+    worker: Option<WorkerId>,
+    threshold: Option<usize>,
+    node_index: usize,
+) -> Result<usize> {
+    let configured = worker.and_then(|id| committee.shard_threshold(&id));
+    let threshold = match (threshold, configured) {
+        (Some(threshold), Some(configured)) => {
+            ensure!(
+                threshold == configured,
+                "The committee sets the threshold of worker {} to {}, not {}",
+                worker.unwrap(),
+                configured,
+                threshold
+            );
+            threshold
+        }
+        (Some(threshold), None) | (None, Some(threshold)) => threshold,
+        (None, None) => {
+            bail!("Specify the threshold (the committee does not set it for this worker)")
+        }
+    };
+    committee
+        .check_threshold(threshold)
+        .context("The committee is incompatible with the threshold")?;
+    ensure!(
+        node_index < committee.size(),
+        "The share index {} is out of range for a committee of {} authorities",
+        node_index,
+        committee.size()
+    );
+    Ok(threshold)
+}
+
+/// Returns the file holding the threshold keypair of worker `id` inside the directory `dir`.
+fn threshold_keypair_file(dir: &str, id: WorkerId) -> String {
+    Path::new(dir)
+        .join(format!("{}.json", id))
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Opens the store at `path`, encrypting its values if there is a `key`.
+fn open_store(path: &str, key: Option<&StoreKey>) -> Result<Store, store::SchemaError> {
+    match key {
+        Some(key) => Store::encrypted(path, key),
+        None => Store::new(path),
+    }
+}
+
+/// Returns the path of the store of worker `id` when running alongside the primary using `store_path`.
+fn worker_store_path(store_path: &str, id: WorkerId) -> String {
+    format!("{}-{}", store_path, id)
+}
+
+/// The prefix of the keys of a worker sharing its store with the other workers of the process.
+fn worker_store_prefix(id: WorkerId) -> Vec<u8> {
+    format!("worker/{}/", id).into_bytes()
 }
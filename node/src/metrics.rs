@@ -0,0 +1,35 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::http::{reply, spawn_http_server, HttpHandler};
+use async_trait::async_trait;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use prometheus::{Encoder as _, Registry, TextEncoder};
+use std::net::SocketAddr;
+use tokio::task::JoinHandle;
+
+/// An HTTP server exposing the metrics of `registry` in the Prometheus text format (on `/metrics`).
+pub struct MetricsServer {
+    registry: Registry,
+}
+
+impl MetricsServer {
+    pub fn spawn(address: SocketAddr, registry: Registry) -> JoinHandle<()> {
+        spawn_http_server("metrics", address, Self { registry })
+    }
+}
+
+#[async_trait]
+impl HttpHandler for MetricsServer {
+    async fn handle(&self, request: Request<Body>) -> Response<Body> {
+        match (request.method(), request.uri().path()) {
+            (&Method::GET, "/metrics") => {
+                let mut buffer = Vec::new();
+                let encoder = TextEncoder::new();
+                match encoder.encode(&self.registry.gather(), &mut buffer) {
+                    Ok(()) => reply(StatusCode::OK, buffer),
+                    Err(e) => reply(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+                }
+            }
+            _ => reply(StatusCode::NOT_FOUND, "not found"),
+        }
+    }
+}
@@ -0,0 +1,315 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use bytes::Bytes;
+use consensus::{
+    read_committed, read_sub_dag, read_sub_dag_round, CommittedSubDag, CommittedWatermark,
+};
+use futures::sink::SinkExt as _;
+use futures::stream::StreamExt as _;
+use log::{info, warn};
+use primary::Round;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::net::SocketAddr;
+use store::{KeyValueStore, StoreResult};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tokio::time::{timeout, Duration};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+#[cfg(test)]
+#[path = "tests/sub_dag_stream_tests.rs"]
+pub mod sub_dag_stream_tests;
+
+/// The number of live sub-dags buffered for each subscriber (including while it replays the recorded ones).
+/// Subscribers falling further behind are dropped.
+pub const SUB_DAG_BUFFER: usize = 1_000;
+
+/// The delay within which a new subscriber sends its `SubscribeRequest`.
+pub const SUBSCRIBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The request a subscriber sends upon connection.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SubscribeRequest {
+    /// The index of the first sub-dag to stream.
+    pub from: u64,
+}
+
+/// The messages streamed to a subscriber, each in a length-delimited bincode frame.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum SubDagReply {
+    /// The next committed sub-dag.
+    SubDag(CommittedSubDag),
+    /// The requested sub-dags are no longer available: they are below the garbage collection bound, and
+    /// their certificates may be pruned. `oldest` is the first sub-dag the node can still replay. This is
+    /// the last message of the stream.
+    Pruned { oldest: u64 },
+}
+
+/// The channel feeding a subscriber with the serialized live sub-dags, along with their index.
+type SubscriberChannel = Sender<(u64, Bytes)>;
+
+/// A new subscriber, and the channel on which to confirm that it receives all the sub-dags broadcast from
+/// now on.
+type Subscription = (SocketAddr, SubscriberChannel, oneshot::Sender<()>);
+
+/// Streams the committed sub-dags to the subscribers connected over TCP, starting from the index each
+/// subscriber requests: the sub-dags that were already committed are read from the store (where the consensus
+/// records them), then the stream follows the sub-dags the consensus commits. This lets the consumers of the
+/// output resubscribe after a restart from the last sub-dag they processed. Only the sub-dags whose leader is
+/// within `gc_depth` rounds of the last committed one can be replayed.
+pub struct SubDagStream<S> {
+    /// The address on which to accept subscribers.
+    address: SocketAddr,
+    /// The store in which the consensus records the sub-dags.
+    store: S,
+    /// The depth of the garbage collector.
+    gc_depth: Round,
+    /// Receives the committed sub-dags from the consensus.
+    rx_sub_dags: Receiver<CommittedSubDag>,
+    /// Receives the subscribers once they sent their request.
+    rx_subscribers: Receiver<Subscription>,
+    /// Hands the subscribers to the stream.
+    tx_subscribers: Sender<Subscription>,
+    /// The channels feeding the connected subscribers.
+    subscribers: Vec<(SocketAddr, SubscriberChannel)>,
+}
+
+impl<S: KeyValueStore> SubDagStream<S> {
+    pub fn spawn(
+        address: SocketAddr,
+        store: S,
+        gc_depth: Round,
+        rx_sub_dags: Receiver<CommittedSubDag>,
+    ) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let (tx_subscribers, rx_subscribers) = channel(SUB_DAG_BUFFER);
+            Self {
+                address,
+                store,
+                gc_depth,
+                rx_sub_dags,
+                rx_subscribers,
+                tx_subscribers,
+                subscribers: Vec::new(),
+            }
+            .run()
+            .await;
+        })
+    }
+
+    /// Send a sub-dag to all subscribers (serializing it once), dropping the ones that cannot keep up.
+    fn broadcast(&mut self, sub_dag: CommittedSubDag) {
+        if self.subscribers.is_empty() {
+            return;
+        }
+        let index = sub_dag.sub_dag_index;
+        let bytes: Bytes = bincode::serialize(&SubDagReply::SubDag(sub_dag))
+            .expect("Failed to serialize sub-dag")
+            .into();
+        self.subscribers
+            .retain(|(peer, tx)| match tx.try_send((index, bytes.clone())) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    warn!("Dropping slow sub-dag subscriber {}", peer);
+                    false
+                }
+                Err(TrySendError::Closed(_)) => false,
+            });
+    }
+
+    /// Spawn a task reading the request of a newly connected subscriber, replaying the recorded sub-dags it
+    /// asks for, and then writing it the live ones.
+    fn subscribe(&self, socket: TcpStream, peer: SocketAddr) {
+        let tx_subscribers = self.tx_subscribers.clone();
+        let mut store = self.store.clone();
+        let gc_depth = self.gc_depth;
+        tokio::spawn(async move {
+            let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+            let from = match timeout(SUBSCRIBE_TIMEOUT, transport.next()).await {
+                Ok(Some(Ok(frame))) => match bincode::deserialize::<SubscribeRequest>(&frame) {
+                    Ok(request) => request.from,
+                    Err(e) => {
+                        warn!("Invalid request from sub-dag subscriber {}: {}", peer, e);
+                        return;
+                    }
+                },
+                _ => {
+                    warn!("Sub-dag subscriber {} sent no request", peer);
+                    return;
+                }
+            };
+            info!("Sub-dag subscriber {} streams from sub-dag {}", peer, from);
+
+            // Register for the live sub-dags before reading the store: the consensus records every sub-dag
+            // before outputting it, so those broadcast before the registration are all replayed.
+            let (tx, rx) = channel(SUB_DAG_BUFFER);
+            let (tx_registered, rx_registered) = oneshot::channel();
+            if tx_subscribers
+                .send((peer, tx, tx_registered))
+                .await
+                .is_err()
+                || rx_registered.await.is_err()
+            {
+                return;
+            }
+            if let Err(e) = stream(&mut transport, &mut store, gc_depth, from, rx).await {
+                warn!("Failed to stream sub-dags to {}: {}", peer, e);
+            }
+        });
+    }
+
+    async fn run(&mut self) {
+        let listener = TcpListener::bind(&self.address)
+            .await
+            .expect("Failed to bind the sub-dag stream TCP port");
+        info!("Streaming committed sub-dags on {}", self.address);
+
+        loop {
+            tokio::select! {
+                Some(sub_dag) = self.rx_sub_dags.recv() => self.broadcast(sub_dag),
+                Some((peer, tx, tx_registered)) = self.rx_subscribers.recv() => {
+                    self.subscribers.push((peer, tx));
+                    let _ = tx_registered.send(());
+                },
+                result = listener.accept() => match result {
+                    Ok((socket, peer)) => {
+                        info!("Sub-dag subscriber {} connected", peer);
+                        self.subscribe(socket, peer);
+                    },
+                    Err(e) => warn!("Failed to accept sub-dag subscriber: {}", e),
+                },
+            }
+        }
+    }
+}
+
+/// Writes to a subscriber the sub-dags from index `from`: first those recorded in the store, then the live
+/// ones received on `rx` (skipping those already replayed). The replay goes up to the last recorded sub-dag
+/// rather than the watermark: the consensus outputs the sub-dags before persisting their watermark.
+async fn stream<S: KeyValueStore>(
+    transport: &mut Framed<TcpStream, LengthDelimitedCodec>,
+    store: &mut S,
+    gc_depth: Round,
+    from: u64,
+    mut rx: Receiver<(u64, Bytes)>,
+) -> io::Result<()> {
+    if let Some(committed) = read_committed(store).await.map_err(to_io)? {
+        if from <= committed.sub_dag_index {
+            let oldest = oldest_replayable(store, committed, gc_depth)
+                .await
+                .map_err(to_io)?;
+            if from < oldest {
+                return send(transport, &SubDagReply::Pruned { oldest }).await;
+            }
+        }
+    }
+    let mut next = from;
+    loop {
+        match read_sub_dag(store, next).await.map_err(to_io)? {
+            Some(sub_dag) => send(transport, &SubDagReply::SubDag(sub_dag)).await?,
+            None if read_sub_dag_round(store, next)
+                .await
+                .map_err(to_io)?
+                .is_some() =>
+            {
+                // The certificates of the sub-dag were pruned meanwhile.
+                let reply = SubDagReply::Pruned { oldest: next + 1 };
+                return send(transport, &reply).await;
+            }
+            None => break,
+        }
+        next += 1;
+    }
+    while let Some((index, bytes)) = rx.recv().await {
+        if index < next {
+            continue;
+        }
+        transport.send(bytes).await?;
+        next = index + 1;
+    }
+    Ok(())
+}
+
+/// Returns the index of the first sub-dag whose leader is within `gc_depth` rounds of the last committed
+/// leader. The rounds of the leaders grow with the indices, so this is a binary search over the records.
+async fn oldest_replayable<S: KeyValueStore>(
+    store: &mut S,
+    committed: CommittedWatermark,
+    gc_depth: Round,
+) -> StoreResult<u64> {
+    let bound = committed.round.saturating_sub(gc_depth);
+    let (mut low, mut high) = (0, committed.sub_dag_index);
+    while low < high {
+        let middle = low + (high - low) / 2;
+        match read_sub_dag_round(store, middle).await? {
+            Some(round) if round >= bound => high = middle,
+            _ => low = middle + 1,
+        }
+    }
+    Ok(low)
+}
+
+async fn send(
+    transport: &mut Framed<TcpStream, LengthDelimitedCodec>,
+    reply: &SubDagReply,
+) -> io::Result<()> {
+    let bytes = bincode::serialize(reply).expect("Failed to serialize sub-dag reply");
+    transport.send(Bytes::from(bytes)).await
+}
+
+fn to_io<E: std::error::Error + Send + Sync + 'static>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e)
+}
+
+/// Subscribes to the sub-dag stream of a node from a given sub-dag index, and decodes the sub-dags. A client
+/// recovering after a restart reconnects with `next_index`, the index following the last sub-dag it received.
+pub struct SubDagSubscriber {
+    transport: Framed<TcpStream, LengthDelimitedCodec>,
+    /// The index of the next sub-dag the stream delivers.
+    next: u64,
+}
+
+impl SubDagSubscriber {
+    pub async fn connect(address: SocketAddr, from: u64) -> io::Result<Self> {
+        let socket = TcpStream::connect(address).await?;
+        let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+        let request = bincode::serialize(&SubscribeRequest { from })
+            .expect("Failed to serialize sub-dag subscription");
+        transport.send(Bytes::from(request)).await?;
+        Ok(Self {
+            transport,
+            next: from,
+        })
+    }
+
+    /// The index of the next sub-dag to receive.
+    pub fn next_index(&self) -> u64 {
+        self.next
+    }
+
+    /// Returns the next committed sub-dag, or `None` once the node closed the stream. Fails if the node can
+    /// no longer replay the requested sub-dags.
+    pub async fn recv(&mut self) -> Option<io::Result<CommittedSubDag>> {
+        let frame = match self.transport.next().await? {
+            Ok(x) => x,
+            Err(e) => return Some(Err(e)),
+        };
+        Some(match bincode::deserialize(&frame) {
+            Ok(SubDagReply::SubDag(sub_dag)) => {
+                self.next = sub_dag.sub_dag_index + 1;
+                Ok(sub_dag)
+            }
+            Ok(SubDagReply::Pruned { oldest }) => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "sub-dag {} is no longer available (the oldest one is {})",
+                    self.next, oldest
+                ),
+            )),
+            Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        })
+    }
+}
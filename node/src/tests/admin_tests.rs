@@ -0,0 +1,238 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use config::threshold::{decryption_share, encrypt};
+use config::{KeyPair, ThresholdKeyPair};
+use crypto::Hash as _;
+use primary::Header;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr};
+use store::{Key, MemoryStore, StoreResult, Value};
+
+fn committee() -> Committee {
+    let host = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    let authorities = (0..4).map(|_| (KeyPair::new().name, 1, host)).collect();
+    Committee::new(authorities, 13_100, 1).unwrap()
+}
+
+fn admin_server(path: &str, peers_health: Option<PeersHealth>) -> AdminServer {
+    let _ = fs::remove_dir_all(path);
+    AdminServer {
+        committee: committee(),
+        peers_health,
+        store: Store::new(path).unwrap(),
+        dag_store: None,
+        workers: WorkerStores::new(),
+        throughput: None,
+    }
+}
+
+/// A store silently losing its writes.
+#[derive(Clone)]
+struct LossyStore(MemoryStore);
+
+#[async_trait]
+impl KeyValueStore for LossyStore {
+    async fn write(&mut self, _key: Key, _value: Value) {}
+
+    async fn read(&mut self, key: Key) -> StoreResult<Option<Value>> {
+        self.0.read(key).await
+    }
+
+    async fn read_many(&mut self, keys: Vec<Key>) -> StoreResult<Vec<Option<Value>>> {
+        self.0.read_many(keys).await
+    }
+
+    async fn notify_read(&mut self, key: Key) -> StoreResult<Value> {
+        self.0.notify_read(key).await
+    }
+
+    async fn flush(&mut self) -> StoreResult<()> {
+        self.0.flush().await
+    }
+
+    async fn delete(&mut self, keys: Vec<Key>) {
+        self.0.delete(keys).await
+    }
+
+    async fn delete_range(&mut self, from: Key, to: Key) {
+        self.0.delete_range(from, to).await
+    }
+}
+
+#[tokio::test]
+async fn ready_once_store_is_writable() {
+    // A node without primary only waits for its store.
+    let admin = admin_server(".db_test_ready_once_store_is_writable", None);
+    assert_eq!(admin.readiness().await.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn not_ready_without_quorum() {
+    // The primary did not hear from any authority yet.
+    let admin = admin_server(
+        ".db_test_not_ready_without_quorum",
+        Some(PeersHealth::default()),
+    );
+    assert_eq!(
+        admin.readiness().await.status(),
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+}
+
+#[tokio::test]
+async fn probe_detects_lost_writes() {
+    assert!(probe_store(&mut MemoryStore::new()).await);
+    assert!(!probe_store(&mut LossyStore(MemoryStore::new())).await);
+}
+
+/// The body of a reply, as JSON.
+async fn json(response: Response<Body>) -> serde_json::Value {
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    serde_json::from_slice(&body).unwrap()
+}
+
+#[tokio::test]
+async fn batch_lookup() {
+    let mut admin = admin_server(".db_test_batch_lookup", None);
+    let keypairs: Vec<_> = (0..4).map(|i| ThresholdKeyPair::new(1, i, 0)).collect();
+    let mut store = PrefixedStore::new(admin.store.clone(), b"worker_0/");
+    let key_ring = ThresholdKeyRing::new(ThresholdKeyPair::new(1, 0, 0));
+    admin.workers.insert(0, (store.clone(), key_ring));
+
+    // A batch with enough decryption shares, stored compressed, and a plain one.
+    let plaintexts = vec![b"first".to_vec(), b"second".to_vec()];
+    let batch: Vec<_> = plaintexts
+        .iter()
+        .map(|x| encrypt(&keypairs[0].pk_set.public_key(), x))
+        .collect();
+    let shares = keypairs[..2]
+        .iter()
+        .map(|keypair| {
+            let shares = batch
+                .iter()
+                .map(|tx| decryption_share(keypair, tx).unwrap())
+                .collect();
+            (keypair.node_index, shares)
+        })
+        .collect();
+    let decryptable = Digest([1; 32]);
+    let message = WorkerMessage::DecryptableBatch(0, batch, shares);
+    let serialized = worker::compress(bincode::serialize(&message).unwrap(), Some(1));
+    store.write(decryptable.to_vec(), serialized).await;
+    let plain = Digest([2; 32]);
+    let message = WorkerMessage::Batch(0, plaintexts.clone());
+    store
+        .write(plain.to_vec(), bincode::serialize(&message).unwrap())
+        .await;
+    let encoded: Vec<_> = plaintexts.iter().map(base64::encode).collect();
+
+    // The decryptable batch is returned decrypted.
+    let reply = admin.batch(&base64::encode(&decryptable)).await;
+    assert_eq!(reply.status(), StatusCode::OK);
+    let reply = json(reply).await;
+    assert_eq!(reply["worker_id"], 0);
+    assert_eq!(reply["decrypted"], true);
+    assert_eq!(reply["transactions"], serde_json::json!(encoded));
+
+    // The plain one is returned raw, and the digest may use the URL-safe alphabet.
+    let reply = admin
+        .batch(&base64::encode_config(&plain, base64::URL_SAFE))
+        .await;
+    assert_eq!(reply.status(), StatusCode::OK);
+    let reply = json(reply).await;
+    assert_eq!(reply["decrypted"], false);
+    assert_eq!(reply["transactions"], serde_json::json!(encoded));
+
+    // Unknown and malformed digests.
+    let unknown = base64::encode(&Digest([3; 32]));
+    assert_eq!(admin.batch(&unknown).await.status(), StatusCode::NOT_FOUND);
+    assert_eq!(
+        admin.batch("not a digest").await.status(),
+        StatusCode::BAD_REQUEST
+    );
+}
+
+/// The body of a reply, as text.
+async fn text(response: Response<Body>) -> String {
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    String::from_utf8(body.to_vec()).unwrap()
+}
+
+#[tokio::test]
+async fn dag_dump() {
+    let mut admin = admin_server(".db_test_dag_dump", None);
+    assert_eq!(admin.dag(None).await.status(), StatusCode::NOT_FOUND);
+    let mut store = admin.store.clone();
+    admin.dag_store = Some(store.clone());
+
+    // A certificate of each authority at round 1, and one at round 2 (of the first authority).
+    let authorities: Vec<_> = admin.committee.authorities.keys().cloned().collect();
+    let make = |author: PublicKey, round, parents: Vec<Digest>| {
+        let header = Header {
+            author,
+            round,
+            parents: parents.into_iter().collect(),
+            ..Header::default()
+        };
+        Certificate {
+            header: Header {
+                id: header.digest(),
+                ..header
+            },
+            ..Certificate::default()
+        }
+    };
+    let first: Vec<_> = authorities
+        .iter()
+        .map(|x| make(*x, 1, Vec::new()))
+        .collect();
+    let second = make(
+        authorities[0],
+        2,
+        first.iter().map(|x| x.digest()).collect(),
+    );
+    let mut round_index = RoundIndex::new(store.clone(), &admin.committee);
+    for certificate in first.iter().chain(Some(&second)) {
+        let bytes = bincode::serialize(certificate).unwrap();
+        store.write(certificate.digest().to_vec(), bytes).await;
+        round_index.insert(certificate).await.unwrap();
+    }
+
+    // The consensus committed the first certificate of the first authority.
+    let watermark = Watermark {
+        last_committed_round: 1,
+        last_committed: vec![(authorities[0], 1)].into_iter().collect(),
+        sub_dag_index: 0,
+    };
+    let bytes = bincode::serialize(&watermark).unwrap();
+    store.write(WATERMARK_KEY.to_vec(), bytes).await;
+
+    // By default, all the certificates are rendered along with the edges to their parents.
+    let reply = admin.dag(None).await;
+    assert_eq!(reply.status(), StatusCode::OK);
+    let graph = text(reply).await;
+    assert!(graph.starts_with("digraph dag {"));
+    assert_eq!(graph.matches("[label=").count(), 5);
+    assert_eq!(graph.matches(" -> ").count(), 4);
+    assert_eq!(graph.matches("fillcolor").count(), 1);
+    let committed = format!(
+        "\"{:?}\" [label=\"{}\\nround 1\", style=filled",
+        first[0].digest(),
+        authorities[0]
+    );
+    assert!(graph.contains(&committed));
+
+    // The last round alone holds a single certificate, whose parents are not rendered.
+    let graph = text(admin.dag(Some("rounds=1")).await).await;
+    assert_eq!(graph.matches("[label=").count(), 1);
+    assert_eq!(graph.matches(" -> ").count(), 0);
+    assert!(graph.contains(&format!("{:?}", second.digest())));
+
+    // The number of rounds is capped, and must be positive.
+    let reply = admin.dag(Some("rounds=100000")).await;
+    assert_eq!(reply.status(), StatusCode::OK);
+    for query in &["rounds=0", "rounds=ten"] {
+        let reply = admin.dag(Some(*query)).await;
+        assert_eq!(reply.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,70 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use primary::{Header, Round};
+use tokio::io::{AsyncBufReadExt as _, BufReader};
+use tokio::time::sleep;
+
+fn certificate(round: Round) -> Certificate {
+    Certificate {
+        header: Header {
+            round,
+            ..Header::default()
+        },
+        votes: Vec::new(),
+    }
+}
+
+#[tokio::test]
+async fn stream_to_subscribers_and_application() {
+    let address = "127.0.0.1:18100".parse::<SocketAddr>().unwrap();
+    let (tx_output, rx_output) = channel(1);
+    let (tx_application, mut rx_application) = channel(1);
+    CertificateStream::spawn(address, rx_output, tx_application);
+    sleep(Duration::from_millis(100)).await;
+
+    // Connect a subscriber of each framing, and let them choose it.
+    let mut binary = CertificateSubscriber::connect(address).await.unwrap();
+    let mut socket = TcpStream::connect(address).await.unwrap();
+    socket.write_all(&[Framing::JSON]).await.unwrap();
+    let mut json = BufReader::new(socket).lines();
+    sleep(Duration::from_millis(100)).await;
+
+    // Every committed certificate reaches the application and both subscribers.
+    tx_output.send(certificate(1)).await.unwrap();
+    assert_eq!(rx_application.recv().await.unwrap().round(), 1);
+    assert_eq!(binary.recv().await.unwrap().unwrap().round(), 1);
+    let line = json.next_line().await.unwrap().unwrap();
+    let received: Certificate = serde_json::from_str(&line).unwrap();
+    assert_eq!(received.round(), 1);
+
+    // The stream stops once the consensus does.
+    drop(tx_output);
+    assert!(rx_application.recv().await.is_none());
+}
+
+#[tokio::test]
+async fn reject_unknown_framing() {
+    let address = "127.0.0.1:18110".parse::<SocketAddr>().unwrap();
+    let (tx_output, rx_output) = channel(1);
+    let (tx_application, mut rx_application) = channel(1);
+    CertificateStream::spawn(address, rx_output, tx_application);
+    sleep(Duration::from_millis(100)).await;
+
+    // The subscriber asking for an unknown framing is disconnected.
+    let mut socket = TcpStream::connect(address).await.unwrap();
+    socket.write_all(b"X").await.unwrap();
+    let mut buffer = Vec::new();
+    assert_eq!(socket.read_to_end(&mut buffer).await.unwrap(), 0);
+
+    // The application still gets the certificates.
+    tx_output.send(certificate(1)).await.unwrap();
+    assert_eq!(rx_application.recv().await.unwrap().round(), 1);
+}
+
+#[test]
+fn framing_bytes() {
+    for framing in vec![Framing::Binary, Framing::Json] {
+        assert_eq!(Framing::from_byte(framing.header()), Some(framing));
+    }
+    assert_eq!(Framing::from_byte(b'X'), None);
+}
@@ -0,0 +1,257 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use config::threshold::{decryption_share, encrypt};
+use config::ThresholdKeyPair;
+use primary::Header;
+use std::fs;
+use tokio::sync::mpsc::{channel, Receiver};
+
+/// The threshold keypairs of a committee of 4 nodes, any 2 of which can decrypt.
+fn keypairs() -> Vec<ThresholdKeyPair> {
+    (0..4).map(|i| ThresholdKeyPair::new(1, i, 0)).collect()
+}
+
+fn plaintexts() -> Vec<Vec<u8>> {
+    vec![b"first".to_vec(), b"second".to_vec()]
+}
+
+fn encrypted_batch(pk_set: &PublicKeySet) -> Vec<Vec<u8>> {
+    plaintexts()
+        .iter()
+        .map(|x| encrypt(&pk_set.public_key(), x))
+        .collect()
+}
+
+/// The decryption shares of the nodes of `keypairs` for every transaction of `batch`.
+fn shares(keypairs: &[ThresholdKeyPair], batch: &[Vec<u8>]) -> BatchDecryptionShares {
+    keypairs
+        .iter()
+        .map(|keypair| {
+            let shares = batch
+                .iter()
+                .map(|tx| decryption_share(keypair, tx).unwrap())
+                .collect();
+            (keypair.node_index, shares)
+        })
+        .collect()
+}
+
+/// A decryptor for worker 0, whose store holds each batch (with its shares) under its digest. Returns the
+/// share requests the decryptor sends to the worker.
+async fn decryptor(
+    path: &str,
+    batches: Vec<(Digest, Vec<Vec<u8>>, BatchDecryptionShares)>,
+    timeout: Duration,
+) -> (Decryptor, Receiver<ShareRequest>) {
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+    for (digest, batch, shares) in batches {
+        let message = WorkerMessage::DecryptableBatch(0, batch, shares);
+        let serialized = bincode::serialize(&message).unwrap();
+        store
+            .write(digest.to_vec(), worker::compress(serialized, Some(1)))
+            .await;
+    }
+
+    let (tx_share_request, rx_share_request) = channel(10);
+    let key_ring = ThresholdKeyRing::new(keypairs().remove(0));
+    let mut workers = HashMap::new();
+    workers.insert(0, (key_ring, store, tx_share_request));
+    (Decryptor::new(workers, timeout), rx_share_request)
+}
+
+fn certificate(payload: &[(Digest, WorkerId)]) -> Certificate {
+    Certificate {
+        header: Header {
+            payload: payload.iter().cloned().collect(),
+            ..Header::default()
+        },
+        votes: Vec::new(),
+    }
+}
+
+#[test]
+fn combine_shares() {
+    let keypairs = keypairs();
+    let pk_set = keypairs[0].pk_set.clone();
+    let batch = encrypted_batch(&pk_set);
+    let digest = Digest::default();
+
+    // Any two nodes suffice, and the transactions keep their order.
+    let shares = shares(&keypairs[2..], &batch);
+    let transactions = Decryptor::decrypt_transactions(&pk_set, &digest, &batch, &shares).unwrap();
+    assert_eq!(transactions, plaintexts());
+}
+
+#[test]
+fn undecryptable_transaction() {
+    let keypairs = keypairs();
+    let pk_set = keypairs[0].pk_set.clone();
+    let mut batch = encrypted_batch(&pk_set);
+    let shares = shares(&keypairs, &batch);
+
+    // The second transaction is not a ciphertext.
+    batch[1] = b"plaintext".to_vec();
+    let result = Decryptor::decrypt_transactions(&pk_set, &Digest::default(), &batch, &shares);
+    let error = result.unwrap_err();
+    assert!(matches!(error, DecryptionError::InvalidCiphertext(_, 1)));
+    assert_eq!(error.failure(), DecryptionFailure::BadCiphertext);
+}
+
+#[test]
+fn missing_shares() {
+    let keypairs = keypairs();
+    let pk_set = keypairs[0].pk_set.clone();
+    let batch = encrypted_batch(&pk_set);
+    let digest = Digest::default();
+
+    // A single share is not enough.
+    let shares = shares(&keypairs[..1], &batch);
+    let error = Decryptor::decrypt_transactions(&pk_set, &digest, &batch, &shares).unwrap_err();
+    assert!(matches!(
+        error,
+        DecryptionError::NotEnoughShares {
+            valid: 1,
+            invalid: 0,
+            required: 2,
+            ..
+        }
+    ));
+    assert_eq!(error.failure(), DecryptionFailure::MissingShares);
+}
+
+#[test]
+fn invalid_shares() {
+    let keypairs = keypairs();
+    let pk_set = keypairs[0].pk_set.clone();
+    let batch = encrypted_batch(&pk_set);
+
+    // Node 1 hands in the shares it computed for the transactions of another batch.
+    let mut shares = shares(&keypairs[..1], &batch);
+    let other = encrypted_batch(&pk_set);
+    shares.extend(self::shares(&keypairs[1..2], &other));
+    let error =
+        Decryptor::decrypt_transactions(&pk_set, &Digest::default(), &batch, &shares).unwrap_err();
+    assert_eq!(error.failure(), DecryptionFailure::BadShare);
+}
+
+#[tokio::test]
+async fn decrypt_stored_batch() {
+    let keypairs = keypairs();
+    let batch = encrypted_batch(&keypairs[0].pk_set);
+    let shares = shares(&keypairs[..2], &batch);
+    let digest = Digest([1; 32]);
+    let (decryptor, _rx_share_request) = decryptor(
+        ".db_test_decrypt_stored_batch",
+        vec![(digest.clone(), batch, shares)],
+        Duration::from_millis(1_000),
+    )
+    .await;
+
+    // The batches missing from the store or held by unknown workers are reported as unavailable.
+    let payload = [(digest, 0), (Digest([2; 32]), 0), (Digest([3; 32]), 1)];
+    let results = decryptor.decrypt_certificate(&certificate(&payload)).await;
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[0].as_ref().unwrap().transactions, plaintexts());
+    assert!(matches!(results[1], Err(DecryptionError::MissingBatch(_))));
+    assert!(matches!(results[2], Err(DecryptionError::UnknownWorker(1))));
+    assert!(results[1..]
+        .iter()
+        .all(|result| result.as_ref().unwrap_err().failure() == DecryptionFailure::Unavailable));
+}
+
+#[tokio::test]
+async fn gather_missing_shares() {
+    let keypairs = keypairs();
+    let batch = encrypted_batch(&keypairs[0].pk_set);
+    let digest = Digest([1; 32]);
+
+    // The worker only stored our own shares: the decryptor asks it to gather those of the others.
+    let stored = shares(&keypairs[..1], &batch);
+    let gathered = shares(&keypairs, &batch);
+    let (decryptor, mut rx_share_request) = decryptor(
+        ".db_test_gather_missing_shares",
+        vec![(digest.clone(), batch, stored)],
+        Duration::from_millis(1_000),
+    )
+    .await;
+    tokio::spawn(async move {
+        let (requested, sender) = rx_share_request.recv().await.unwrap();
+        assert_eq!(requested, Digest([1; 32]));
+        let _ = sender.send(gathered);
+    });
+    let batch = decryptor.decrypt_batch(&digest, 0).await.unwrap();
+    assert_eq!(batch.transactions, plaintexts());
+}
+
+#[tokio::test]
+async fn shares_unavailable() {
+    let keypairs = keypairs();
+    let batch = encrypted_batch(&keypairs[0].pk_set);
+    let digest = Digest([1; 32]);
+    let stored = shares(&keypairs[..1], &batch);
+    let (decryptor, rx_share_request) = decryptor(
+        ".db_test_shares_unavailable",
+        vec![(digest.clone(), batch, stored)],
+        Duration::from_millis(1_000),
+    )
+    .await;
+
+    // The worker stopped: the error is surfaced rather than waited for.
+    drop(rx_share_request);
+    let error = decryptor.decrypt_batch(&digest, 0).await.unwrap_err();
+    assert!(matches!(error, DecryptionError::SharesUnavailable(_)));
+    assert_eq!(error.failure(), DecryptionFailure::MissingShares);
+}
+
+#[tokio::test]
+async fn shares_timeout() {
+    let keypairs = keypairs();
+    let batch = encrypted_batch(&keypairs[0].pk_set);
+    let digest = Digest([1; 32]);
+    let stored = shares(&keypairs[..1], &batch);
+    let (decryptor, _rx_share_request) = decryptor(
+        ".db_test_shares_timeout",
+        vec![(digest.clone(), batch, stored)],
+        Duration::from_millis(100),
+    )
+    .await;
+
+    // The worker never gathers the missing shares: the batch is reported as failed after the timeout.
+    let results = decryptor
+        .decrypt_certificate(&certificate(&[(digest, 0)]))
+        .await;
+    assert_eq!(results.len(), 1);
+    let error = results[0].as_ref().unwrap_err();
+    assert!(matches!(error, DecryptionError::Timeout(_, 100)));
+    assert_eq!(error.failure(), DecryptionFailure::MissingShares);
+}
+
+#[tokio::test]
+async fn single_deadline_per_certificate() {
+    let keypairs = keypairs();
+    let batches: Vec<_> = (1..=3)
+        .map(|i| {
+            let batch = encrypted_batch(&keypairs[0].pk_set);
+            let stored = shares(&keypairs[..1], &batch);
+            (Digest([i; 32]), batch, stored)
+        })
+        .collect();
+    let payload: Vec<_> = batches
+        .iter()
+        .map(|(digest, _, _)| (digest.clone(), 0))
+        .collect();
+    let delay = Duration::from_millis(200);
+    let (decryptor, _rx_share_request) =
+        decryptor(".db_test_single_deadline_per_certificate", batches, delay).await;
+
+    // None of the batches gets its missing shares, yet the certificate is not held back for a timeout
+    // per batch.
+    let now = Instant::now();
+    let results = decryptor.decrypt_certificate(&certificate(&payload)).await;
+    assert!(now.elapsed() < 2 * delay);
+    assert_eq!(results.len(), 3);
+    assert!(results
+        .iter()
+        .all(|result| matches!(result, Err(DecryptionError::Timeout(..)))));
+}
@@ -0,0 +1,52 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use config::threshold::{decrypt_shares, decryption_share, encrypt, ThresholdError};
+use std::net::{IpAddr, Ipv4Addr};
+
+#[tokio::test]
+async fn generate_threshold_keys() {
+    let keypairs: Vec<_> = (0..4).map(|_| KeyPair::new()).collect();
+    let host = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    let authorities = keypairs.iter().map(|x| (x.name, 1, host)).collect();
+    let committee = Committee::new(authorities, 13_200, 1).unwrap();
+
+    // Run the key generation of all authorities concurrently.
+    let results = join_all(
+        keypairs
+            .into_iter()
+            .map(|keypair| Dkg::run(keypair, committee.clone(), 1)),
+    )
+    .await;
+    let keypairs: Vec<ThresholdKeyPair> = results.into_iter().map(|x| x.unwrap()).collect();
+
+    // All authorities derived the same key set, and hold distinct shares of it.
+    let pk_set = keypairs[0].pk_set.clone();
+    let mut indices: Vec<_> = keypairs.iter().map(|x| x.node_index).collect();
+    indices.sort_unstable();
+    assert_eq!(indices, vec![0, 1, 2, 3]);
+    for keypair in &keypairs {
+        assert!(keypair.pk_set == pk_set);
+        assert!(keypair.sk_share.public_key_share() == pk_set.public_key_share(keypair.node_index));
+    }
+
+    // Any `threshold + 1` shares decrypt the transactions encrypted with the threshold public key.
+    let ciphertext = encrypt(&pk_set.public_key(), b"transaction");
+    let mut shares = BTreeMap::new();
+    let keypair = &keypairs[1];
+    let share = decryption_share(keypair, &ciphertext).unwrap();
+    shares.insert(keypair.node_index, share);
+    assert_eq!(
+        decrypt_shares(&pk_set, &ciphertext, &shares),
+        Err(ThresholdError::NotEnoughShares {
+            valid: 1,
+            required: 2
+        })
+    );
+    let keypair = &keypairs[3];
+    let share = decryption_share(keypair, &ciphertext).unwrap();
+    shares.insert(keypair.node_index, share);
+    assert_eq!(
+        decrypt_shares(&pk_set, &ciphertext, &shares).unwrap(),
+        b"transaction".to_vec()
+    );
+}
@@ -0,0 +1,80 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use primary::Header;
+use std::fs;
+use std::io::Read as _;
+
+fn certificate(round: Round) -> Certificate {
+    Certificate {
+        header: Header {
+            round,
+            ..Header::default()
+        },
+        votes: Vec::new(),
+    }
+}
+
+#[test]
+fn compressed_output_is_valid_ndjson() {
+    let path = std::env::temp_dir().join(format!("json_output_{}.zst", std::process::id()));
+    let path = path.to_str().unwrap();
+    let _ = fs::remove_file(path);
+
+    // Two runs of the node append to the same file.
+    for rounds in &[1..=3, 4..=5] {
+        let mut output = JsonOutput::new(path).unwrap();
+        for round in rounds.clone() {
+            output
+                .write(&CommittedRecord::new(&certificate(round), &[]))
+                .unwrap();
+        }
+    }
+
+    let data = zstd::decode_all(fs::File::open(path).unwrap()).unwrap();
+    let rounds: Vec<_> = String::from_utf8(data)
+        .unwrap()
+        .lines()
+        .map(|line| {
+            let record: serde_json::Value = serde_json::from_str(line).unwrap();
+            assert_eq!(record["schema_version"], SCHEMA_VERSION);
+            record["round"].as_u64().unwrap()
+        })
+        .collect();
+    assert_eq!(rounds, vec![1, 2, 3, 4, 5]);
+    let _ = fs::remove_file(path);
+}
+
+#[test]
+fn gzip_output_is_finished_on_drop() {
+    let path = std::env::temp_dir().join(format!("json_output_{}.gz", std::process::id()));
+    let path = path.to_str().unwrap();
+    let _ = fs::remove_file(path);
+    {
+        let mut output = JsonOutput::new(path).unwrap();
+        output
+            .write(&CommittedRecord::new(&certificate(7), &[]))
+            .unwrap();
+    }
+
+    let mut data = String::new();
+    flate2::read::MultiGzDecoder::new(fs::File::open(path).unwrap())
+        .read_to_string(&mut data)
+        .unwrap();
+    assert_eq!(data.lines().count(), 1);
+    let _ = fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn flush_pending_records() {
+    let path = std::env::temp_dir().join(format!("json_output_{}.json", std::process::id()));
+    let path = path.to_str().unwrap();
+    let _ = fs::remove_file(path);
+    let mut output = JsonOutput::new(path).unwrap();
+
+    // The records stay buffered until the output is flushed.
+    output.process_certificate(certificate(1)).await;
+    assert_eq!(fs::read_to_string(path).unwrap(), "");
+    output.flush().await;
+    assert_eq!(fs::read_to_string(path).unwrap().lines().count(), 1);
+    let _ = fs::remove_file(path);
+}
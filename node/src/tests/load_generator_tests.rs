@@ -0,0 +1,194 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use config::threshold::{decrypt_shares, decryption_share};
+use config::ThresholdKeyPair;
+use std::collections::BTreeMap;
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+
+/// Accepts a single connection on `address` and returns the transactions received on it.
+async fn transactions_listener(address: SocketAddr) -> JoinHandle<Vec<Bytes>> {
+    let listener = TcpListener::bind(&address).await.unwrap();
+    tokio::spawn(async move {
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut reader = FramedRead::new(socket, LengthDelimitedCodec::new());
+        let mut received = Vec::new();
+        while let Some(Ok(transaction)) = reader.next().await {
+            received.push(transaction.freeze());
+        }
+        received
+    })
+}
+
+#[tokio::test]
+async fn submit_numbered_transactions() {
+    // A threshold of 0 lets a single share decrypt the transactions.
+    let keypair = ThresholdKeyPair::new(0, 0, 0);
+    let address = "127.0.0.1:18200".parse::<SocketAddr>().unwrap();
+    let handle = transactions_listener(address).await;
+
+    let generator = LoadGenerator {
+        target: address,
+        threshold_pk: keypair.pk_set.public_key(),
+        rate: 100,
+        size: 64,
+        duration: Duration::from_millis(500),
+    };
+    let report = generator.run().await.unwrap();
+    let received = handle.await.unwrap();
+    assert!(report.sent() > 0);
+    assert_eq!(received.len(), report.sent());
+
+    // Every plaintext starts with its counter and a timestamp, and is padded to the requested size.
+    let mut last_timestamp = 0;
+    for (i, transaction) in received.iter().enumerate() {
+        let share = decryption_share(&keypair, transaction).unwrap();
+        let shares: BTreeMap<_, _> = vec![(0, share)].into_iter().collect();
+        let plaintext = decrypt_shares(&keypair.pk_set, transaction, &shares).unwrap();
+        assert_eq!(plaintext.len(), 64);
+        let mut counter = [0u8; 8];
+        counter.copy_from_slice(&plaintext[..8]);
+        assert_eq!(u64::from_be_bytes(counter), i as u64);
+        let mut timestamp = [0u8; 8];
+        timestamp.copy_from_slice(&plaintext[8..TRANSACTION_HEADER_SIZE]);
+        let timestamp = u64::from_be_bytes(timestamp);
+        assert!(timestamp >= last_timestamp);
+        last_timestamp = timestamp;
+        assert!(plaintext[TRANSACTION_HEADER_SIZE..].iter().all(|x| *x == 0));
+    }
+}
+
+#[tokio::test]
+async fn reject_invalid_load() {
+    let keypair = ThresholdKeyPair::new(0, 0, 0);
+    let generator = LoadGenerator {
+        // Nothing listens on this address: the parameters are checked before connecting.
+        target: "127.0.0.1:18210".parse().unwrap(),
+        threshold_pk: keypair.pk_set.public_key(),
+        rate: 100,
+        size: TRANSACTION_HEADER_SIZE - 1,
+        duration: Duration::from_millis(100),
+    };
+    let error = generator.run().await.err().unwrap();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+
+    let generator = LoadGenerator {
+        rate: 0,
+        size: TRANSACTION_HEADER_SIZE,
+        ..generator
+    };
+    let error = generator.run().await.err().unwrap();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[tokio::test]
+async fn replay_file() {
+    let file = std::env::temp_dir().join(format!("replay_file_{}", std::process::id()));
+    let file = file.to_str().unwrap().to_string();
+    let transactions: Vec<Bytes> = (0..10u8).map(|i| Bytes::from(vec![i; 32])).collect();
+    let mut data = Vec::new();
+    for transaction in &transactions {
+        data.extend_from_slice(&(transaction.len() as u32).to_be_bytes());
+        data.extend_from_slice(transaction);
+    }
+    std::fs::write(&file, data).unwrap();
+
+    let address = "127.0.0.1:18220".parse::<SocketAddr>().unwrap();
+    let handle = transactions_listener(address).await;
+    let replayer = FileReplayer {
+        target: address,
+        file: file.clone(),
+        rate: 1_000,
+    };
+    let report = replayer.run().await.unwrap();
+
+    // The transactions are sent untouched and in order.
+    assert_eq!(report.sent(), transactions.len());
+    assert_eq!(handle.await.unwrap(), transactions);
+    let _ = std::fs::remove_file(&file);
+}
+
+/// Writes `data` to a fresh temporary file, and returns its path.
+fn temp_file(name: &str, data: &[u8]) -> String {
+    let file = std::env::temp_dir().join(format!("{}_{}", name, std::process::id()));
+    std::fs::write(&file, data).unwrap();
+    file.to_str().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn replay_file_at_rate() {
+    let mut data = Vec::new();
+    for i in 0..10u8 {
+        data.extend_from_slice(&4u32.to_be_bytes());
+        data.extend_from_slice(&[i; 4]);
+    }
+    let file = temp_file("replay_file_at_rate", &data);
+
+    // At 20 tx/s, the transactions are sent one by one every 50 ms (the first one right away).
+    let address = "127.0.0.1:18230".parse::<SocketAddr>().unwrap();
+    let handle = transactions_listener(address).await;
+    let replayer = FileReplayer {
+        target: address,
+        file: file.clone(),
+        rate: 20,
+    };
+    let report = replayer.run().await.unwrap();
+    assert_eq!(report.sent(), 10);
+    assert!(report.elapsed >= Duration::from_millis(450));
+    assert_eq!(handle.await.unwrap().len(), 10);
+    let _ = std::fs::remove_file(&file);
+}
+
+#[tokio::test]
+async fn reject_invalid_replay() {
+    let file = temp_file("reject_invalid_replay", &[]);
+    let replayer = FileReplayer {
+        // Nothing listens on this address: the rate is checked before connecting.
+        target: "127.0.0.1:18240".parse().unwrap(),
+        file: file.clone(),
+        rate: 0,
+    };
+    let error = replayer.run().await.err().unwrap();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+
+    // The file is opened before connecting.
+    let replayer = FileReplayer {
+        file: format!("{}_missing", file),
+        rate: 100,
+        ..replayer
+    };
+    let error = replayer.run().await.err().unwrap();
+    assert_eq!(error.kind(), io::ErrorKind::NotFound);
+
+    // A truncated transaction fails the replay before its burst is sent.
+    let mut data = 4u32.to_be_bytes().to_vec();
+    data.extend_from_slice(&[1; 4]);
+    data.extend_from_slice(&4u32.to_be_bytes());
+    data.extend_from_slice(&[2; 2]);
+    std::fs::write(&file, data).unwrap();
+    let address = "127.0.0.1:18250".parse::<SocketAddr>().unwrap();
+    let handle = transactions_listener(address).await;
+    let replayer = FileReplayer {
+        target: address,
+        file: file.clone(),
+        rate: 100,
+    };
+    assert!(replayer.run().await.is_err());
+    assert!(handle.await.unwrap().is_empty());
+    let _ = std::fs::remove_file(&file);
+}
+
+#[test]
+fn report_latency_percentiles() {
+    let latencies = (1..=100).rev().map(Duration::from_millis).collect();
+    let report = LoadReport::new(Duration::from_secs(2), latencies);
+    assert_eq!(report.sent(), 100);
+    assert!((report.throughput() - 50.0).abs() < f64::EPSILON);
+    assert_eq!(report.latency(0), Some(Duration::from_millis(1)));
+    assert_eq!(report.latency(50), Some(Duration::from_millis(51)));
+    assert_eq!(report.latency(100), Some(Duration::from_millis(100)));
+
+    let empty = LoadReport::new(Duration::from_secs(1), Vec::new());
+    assert_eq!(empty.latency(50), None);
+    assert!(empty.to_string().contains("p50 -"));
+}
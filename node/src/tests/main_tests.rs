@@ -0,0 +1,410 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use async_trait::async_trait;
+use primary::Round;
+use std::sync::{Arc, Mutex};
+
+/// Parses the command line of a subcommand of the node (`args[0]`), and returns its arguments.
+fn sub_matches(args: &[&str]) -> ArgMatches<'static> {
+    let matches = app()
+        .get_matches_from_safe(std::iter::once("node").chain(args.iter().cloned()))
+        .unwrap();
+    matches.subcommand_matches(args[0]).unwrap().clone()
+}
+
+/// Returns a fresh (empty) directory for a test.
+fn test_dir(name: &str) -> String {
+    let path = env::temp_dir().join(format!("{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&path);
+    path.to_string_lossy().into_owned()
+}
+
+/// Records the rounds of the certificates it receives.
+#[derive(Clone, Default)]
+struct Recorder(Arc<Mutex<Vec<Round>>>);
+
+#[async_trait]
+impl Application for Recorder {
+    async fn process_certificate(&mut self, certificate: Certificate) {
+        self.0.lock().unwrap().push(certificate.round());
+    }
+}
+
+#[tokio::test]
+async fn run_authority() {
+    // A committee of a single authority running two workers: it reaches a quorum on its own.
+    let dir = test_dir("run_authority");
+    let outdir = format!("--outdir={}", dir);
+    keygen_batch(&sub_matches(&[
+        "keygen_batch",
+        "--nodes=1",
+        "--threshold=0",
+        &outdir,
+        "--base_port=7300",
+        "--workers=2",
+    ]))
+    .unwrap();
+    let keys = Path::new(&dir).join("keys");
+    fs::create_dir_all(&keys).unwrap();
+    for id in 0..2 {
+        let target = keys.join(format!("{}.json", id));
+        fs::copy(Path::new(&dir).join("threshold-0.json"), target).unwrap();
+    }
+
+    // The worker ids are inferred from the committee, and the node stops once it committed round 4.
+    let keypair = format!("--keypair={}/node-0.json", dir);
+    let committee = format!("--committee={}/committee.json", dir);
+    let store = format!("--store={}/db", dir);
+    let threshold_keypairs = format!("--threshold_keypairs={}", keys.display());
+    let matches = sub_matches(&[
+        "run",
+        &keypair,
+        &committee,
+        &store,
+        "--max-committed-rounds=4",
+        "authority",
+        &threshold_keypairs,
+    ]);
+    let recorder = Recorder::default();
+    run(&matches, recorder.clone()).await.unwrap();
+
+    let rounds = recorder.0.lock().unwrap().clone();
+    assert_eq!(rounds.last(), Some(&4));
+    for id in 0..2 {
+        assert!(Path::new(&worker_store_path(&format!("{}/db", dir), id)).exists());
+    }
+    let _ = fs::remove_dir_all(&dir);
+}
+
+/// Writes a fresh key pair for each authority, and returns the key files along with the public keys.
+fn key_files(dir: &str, nodes: usize) -> (Vec<String>, Vec<PublicKey>) {
+    fs::create_dir_all(dir).unwrap();
+    (0..nodes)
+        .map(|i| {
+            let file = format!("{}/node-{}.json", dir, i);
+            let keypair = KeyPair::new();
+            keypair.export(&file).unwrap();
+            (file, keypair.name)
+        })
+        .unzip()
+}
+
+#[test]
+fn generate_committee_assigns_ports() {
+    let dir = test_dir("generate_committee_assigns_ports");
+    let (files, names) = key_files(&dir, 2);
+    let filename = format!("--filename={}/committee.json", dir);
+    let mut args = vec!["generate_committee", &filename, "--keys"];
+    args.extend(files.iter().map(|x| x.as_str()));
+    args.extend(&[
+        "--hosts",
+        "127.0.0.1",
+        "--stakes",
+        "1",
+        "2",
+        "--base_port=7400",
+        "--workers=2",
+    ]);
+    generate_committee(&sub_matches(&args)).unwrap();
+
+    // Both authorities share the host, each getting its own ports from the base port on.
+    let committee = Committee::import(&format!("{}/committee.json", dir)).unwrap();
+    assert_eq!(committee.size(), 2);
+    assert_eq!(committee.stake(&names[0]), 1);
+    assert_eq!(committee.stake(&names[1]), 2);
+    let addresses = committee.addresses();
+    let ports: HashSet<_> = addresses.iter().map(|(_, x)| x.port()).collect();
+    assert_eq!(ports.len(), addresses.len());
+    assert!(ports.iter().all(|x| *x >= 7400));
+    for name in &names {
+        for id in 0..2 {
+            assert!(committee.worker(name, &id).is_ok());
+        }
+    }
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn generate_committee_rejects_invalid_inputs() {
+    let dir = test_dir("generate_committee_rejects_invalid_inputs");
+    let (files, _) = key_files(&dir, 2);
+    let filename = format!("{}/committee.json", dir);
+    let generate = |hosts: &[&str], stakes: &[&str]| {
+        let filename = format!("--filename={}", filename);
+        let mut args = vec!["generate_committee", &filename, "--keys"];
+        args.extend(files.iter().map(|x| x.as_str()));
+        args.push("--hosts");
+        args.extend(hosts);
+        args.push("--stakes");
+        args.extend(stakes);
+        args.extend(&["--base_port=7500", "--workers=1"]);
+        generate_committee(&sub_matches(&args))
+    };
+
+    // A positive total stake, one stake per authority, and one host per authority (or a single one).
+    assert!(generate(&["127.0.0.1"], &["0", "0"]).is_err());
+    assert!(generate(&["127.0.0.1"], &["1"]).is_err());
+    assert!(generate(&["127.0.0.1", "127.0.0.2", "127.0.0.3"], &["1", "1"]).is_err());
+    assert!(!Path::new(&filename).exists());
+
+    // Distinct hosts are fine.
+    assert!(generate(&["127.0.0.1", "127.0.0.2"], &["1", "1"]).is_ok());
+    assert!(Path::new(&filename).exists());
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn import_keypair_from_env() {
+    let keypair = KeyPair::new();
+    let var = format!("NODE_TEST_KEYPAIR_{}", std::process::id());
+    env::set_var(&var, serde_json::to_string(&keypair).unwrap());
+    let imported: KeyPair = import_from_file_or_env(None, Some(&var)).unwrap();
+    assert_eq!(imported.name, keypair.name);
+
+    // A missing or invalid variable is an error.
+    env::remove_var(&var);
+    assert!(import_from_file_or_env::<KeyPair>(None, Some(&var)).is_err());
+    env::set_var(&var, "{");
+    assert!(import_from_file_or_env::<KeyPair>(None, Some(&var)).is_err());
+    env::remove_var(&var);
+}
+
+#[test]
+fn keypair_flags_are_exclusive() {
+    let args = [
+        "node",
+        "run",
+        "--keypair=node.json",
+        "--keypair-env=KEYPAIR",
+        "--committee=committee.json",
+        "--store=db",
+        "primary",
+    ];
+    assert!(app().get_matches_from_safe(args.iter().cloned()).is_err());
+}
+
+#[test]
+fn json_log_lines() {
+    let line = json_log_line(
+        &log::Record::builder()
+            .args(format_args!("Committed {} certificates", 3))
+            .level(log::Level::Info)
+            .target("consensus")
+            .build(),
+        1_600_000_000_000,
+    );
+    assert_eq!(
+        line,
+        serde_json::json!({
+            "timestamp": 1_600_000_000_000u64,
+            "level": "INFO",
+            "target": "consensus",
+            "message": "Committed 3 certificates",
+        })
+    );
+
+    // Each record is printed on a single line, even if its message spans several.
+    let line = json_log_line(
+        &log::Record::builder()
+            .args(format_args!("first\nsecond"))
+            .level(log::Level::Warn)
+            .build(),
+        0,
+    );
+    assert!(!line.to_string().contains('\n'));
+    assert_eq!(line["message"], "first\nsecond");
+}
+
+/// Generates the key material of a committee of four authorities (of threshold 1) in `dir`.
+fn keygen_committee(dir: &str, base_port: u16) {
+    let outdir = format!("--outdir={}", dir);
+    let base_port = format!("--base_port={}", base_port);
+    keygen_batch(&sub_matches(&[
+        "keygen_batch",
+        "--nodes=4",
+        "--threshold=1",
+        &outdir,
+        &base_port,
+        "--workers=1",
+    ]))
+    .unwrap();
+}
+
+#[test]
+fn keygen_batch_layout() {
+    let dir = test_dir("keygen_batch_layout");
+    let outdir = format!("--outdir={}", dir);
+    let keygen = |args: &[&str]| {
+        let mut all = vec!["keygen_batch", &outdir];
+        all.extend(args);
+        keygen_batch(&sub_matches(&all))
+    };
+    keygen(&["--nodes=4", "--threshold=1", "--seed=7", "--base_port=7800"]).unwrap();
+
+    // Every authority of the committee has its key pair, on localhost from the base port on.
+    let committee = Committee::import(&format!("{}/committee.json", dir)).unwrap();
+    assert_eq!(committee.size(), 4);
+    let ports: HashSet<_> = committee
+        .addresses()
+        .iter()
+        .map(|(_, x)| x.port())
+        .collect();
+    assert!(ports.iter().all(|x| *x >= 7800));
+    for i in 0..4 {
+        let keypair = KeyPair::import(&format!("{}/node-{}.json", dir, i)).unwrap();
+        let address = committee.primary(&keypair.name).unwrap().primary_to_primary;
+        assert!(address.ip().is_loopback());
+
+        // The threshold shares are those of indices 0..4 of a single key set, that of the committee.
+        let share = format!("{}/threshold-{}.json", dir, i);
+        let share = ThresholdKeyPair::import(&share).unwrap();
+        assert_eq!(share.node_index, i);
+        assert!(committee.check_threshold_key(&share.pk_set).is_ok());
+    }
+    let public_key = format!("{}/threshold_public_key.json", dir);
+    let public_key = ThresholdPublicKeyFile::import(&public_key).unwrap();
+    assert_eq!(committee.threshold_public_key, Some(public_key.public_key));
+
+    // The same seed derives the same threshold keys.
+    let first = ThresholdKeyPair::import(&format!("{}/threshold-0.json", dir)).unwrap();
+    keygen(&["--nodes=4", "--threshold=1", "--seed=7"]).unwrap();
+    let second = ThresholdKeyPair::import(&format!("{}/threshold-0.json", dir)).unwrap();
+    assert_eq!(first.pk_set.public_key(), second.pk_set.public_key());
+
+    // A committee needs an authority, and more authorities than the threshold.
+    assert!(keygen(&["--nodes=0", "--threshold=0"]).is_err());
+    assert!(keygen(&["--nodes=3", "--threshold=3"]).is_err());
+    assert!(keygen(&["--nodes=four", "--threshold=1"]).is_err());
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn validate_consistent_config() {
+    let dir = test_dir("validate_consistent_config");
+    keygen_committee(&dir, 7600);
+    let parameters = format!("{}/parameters.json", dir);
+    let data = r#"{
+        "header_size": 1000,
+        "max_header_delay": 100,
+        "gc_depth": 100,
+        "sync_retry_delay": 5000,
+        "sync_retry_nodes": 3,
+        "batch_size": 500000,
+        "max_batch_delay": 100
+    }"#;
+    fs::write(&parameters, data).unwrap();
+
+    let matches = sub_matches(&[
+        "validate_config",
+        &format!("--committee={}/committee.json", dir),
+        &format!("--parameters={}", parameters),
+        &format!("--keypair={}/node-0.json", dir),
+        &format!("--threshold_keypair={}/threshold-0.json", dir),
+    ]);
+    assert_eq!(config_problems(&matches), Vec::<String>::new());
+    assert!(validate_config(&matches).is_ok());
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn validate_inconsistent_config() {
+    let dir = test_dir("validate_inconsistent_config");
+    keygen_committee(&dir, 7700);
+
+    // A keypair and a threshold keypair foreign to the committee.
+    let keypair = format!("{}/stranger.json", dir);
+    KeyPair::new().export(&keypair).unwrap();
+    let threshold_keypair = format!("{}/stranger-threshold.json", dir);
+    ThresholdKeyPair::new(1, 0, 42)
+        .export(&threshold_keypair)
+        .unwrap();
+
+    let matches = sub_matches(&[
+        "validate_config",
+        &format!("--committee={}/committee.json", dir),
+        &format!("--keypair={}", keypair),
+        &format!("--threshold_keypair={}", threshold_keypair),
+    ]);
+    let problems = config_problems(&matches);
+    assert_eq!(problems.len(), 2, "{:?}", problems);
+    assert!(problems[0].contains("is not in the committee"));
+    assert!(validate_config(&matches).is_err());
+
+    // Missing files are reported as well.
+    let matches = sub_matches(&[
+        "validate_config",
+        &format!("--committee={}/missing.json", dir),
+        &format!("--keypair={}/node-0.json", dir),
+    ]);
+    assert_eq!(config_problems(&matches).len(), 1);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn reject_nodes_missing_from_the_committee() {
+    let dir = test_dir("reject_nodes_missing_from_the_committee");
+    keygen_committee(&dir, 7900);
+    let stranger = KeyPair::new();
+    stranger.export(&format!("{}/stranger.json", dir)).unwrap();
+    let committee_file = format!("{}/committee.json", dir);
+    let run_as = |keypair: &str, mode: &[&str]| {
+        let keypair = format!("--keypair={}/{}", dir, keypair);
+        let committee = format!("--committee={}", committee_file);
+        let store = format!("--store={}/db", dir);
+        let mut args = vec!["run", &keypair, &committee, &store];
+        args.extend(mode);
+        sub_matches(&args)
+    };
+    let threshold_keypair = format!("--threshold_keypair={}/threshold-0.json", dir);
+
+    // An authority foreign to the committee is told so before anything is spawned.
+    let matches = run_as("stranger.json", &["primary"]);
+    let error = run(&matches, Recorder::default()).await.unwrap_err();
+    let message = error.to_string();
+    assert!(message.contains(&stranger.name.to_string()), "{}", message);
+    assert!(message.contains(&committee_file), "{}", message);
+
+    // So is a worker the authority does not run.
+    let matches = run_as(
+        "node-0.json",
+        &["worker", "--id=5", threshold_keypair.as_str()],
+    );
+    let error = run(&matches, Recorder::default()).await.unwrap_err();
+    assert!(
+        error.to_string().starts_with("Worker 5 of authority"),
+        "{}",
+        error
+    );
+    assert!(!Path::new(&format!("{}/db", dir)).exists());
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn committee_table_lists_every_worker() {
+    let host = "127.0.0.1".parse().unwrap();
+    let authorities = vec![
+        (KeyPair::new().name, 1, host),
+        (KeyPair::new().name, 2, host),
+    ];
+    let committee = Committee::new(authorities, 7800, 2).unwrap();
+    let table = committee_table(&committee);
+    let lines: Vec<_> = table.lines().collect();
+
+    // A header, one row per worker, then the summary.
+    assert!(lines[0].starts_with("AUTHORITY"));
+    assert_eq!(lines[5], "");
+    assert_eq!(lines[6], "Authorities: 2");
+    assert_eq!(lines[7], "Quorum threshold (2f+1): 3 of 3 stake");
+    for (name, authority) in &committee.authorities {
+        let row = lines
+            .iter()
+            .position(|x| x.starts_with(&name.to_string()))
+            .unwrap();
+        assert!(lines[row].contains(&authority.workers[&0].transactions.to_string()));
+        assert!(lines[row + 1].contains(&authority.workers[&1].transactions.to_string()));
+    }
+
+    // The columns are aligned.
+    let column = lines[0].find("STAKE").unwrap();
+    assert!(lines[1..5].iter().all(|x| x[..column].ends_with("  ")));
+}
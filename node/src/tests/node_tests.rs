@@ -0,0 +1,112 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use primary::{Header, Round};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::channel;
+
+fn certificate(round: Round) -> Certificate {
+    Certificate {
+        header: Header {
+            round,
+            ..Header::default()
+        },
+        votes: Vec::new(),
+    }
+}
+
+/// An application recording what it is handed.
+#[derive(Clone, Default)]
+struct Recorder {
+    events: Arc<Mutex<Vec<String>>>,
+}
+
+#[async_trait]
+impl Application for Recorder {
+    async fn resume(&mut self, committed: CommittedWatermark) {
+        let event = format!("resume {}", committed.sub_dag_index);
+        self.events.lock().unwrap().push(event);
+    }
+
+    async fn process_certificate(&mut self, certificate: Certificate) {
+        let event = format!("certificate {}", certificate.round());
+        self.events.lock().unwrap().push(event);
+    }
+}
+
+#[tokio::test]
+async fn analyze_feeds_the_application() {
+    let (tx_output, rx_output) = channel(3);
+    for round in 1..=3 {
+        tx_output.send(certificate(round)).await.unwrap();
+    }
+    drop(tx_output);
+
+    // The application resumes after the committed watermark, and then gets the certificates in order.
+    let recorder = Recorder::default();
+    let committed = CommittedWatermark {
+        sub_dag_index: 7,
+        round: 14,
+    };
+    let certificates = analyze(
+        rx_output,
+        recorder.clone(),
+        /* decryptor */ None,
+        Some(committed),
+        /* throughput */ None,
+    )
+    .await;
+    assert_eq!(certificates, 3);
+    assert_eq!(
+        *recorder.events.lock().unwrap(),
+        vec![
+            "resume 7",
+            "certificate 1",
+            "certificate 2",
+            "certificate 3"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn analyze_without_watermark() {
+    let (tx_output, rx_output) = channel(1);
+    tx_output.send(certificate(1)).await.unwrap();
+    drop(tx_output);
+
+    // A node booting on an empty store does not resume anything.
+    let recorder = Recorder::default();
+    let certificates = analyze(rx_output, recorder.clone(), None, None, None).await;
+    assert_eq!(certificates, 1);
+    assert_eq!(*recorder.events.lock().unwrap(), vec!["certificate 1"]);
+}
+
+/// An application recording when it is flushed.
+#[derive(Clone, Default)]
+struct FlushRecorder {
+    flushes: Arc<Mutex<usize>>,
+}
+
+#[async_trait]
+impl Application for FlushRecorder {
+    async fn process_certificate(&mut self, _certificate: Certificate) {}
+
+    async fn flush(&mut self) {
+        *self.flushes.lock().unwrap() += 1;
+    }
+}
+
+#[tokio::test]
+async fn analyze_flushes_while_idle() {
+    let (tx_output, rx_output) = channel(1);
+    let recorder = FlushRecorder::default();
+    let flushes = recorder.flushes.clone();
+
+    // The application is flushed even though nothing is committed.
+    let idle = async move {
+        tokio::time::sleep(FLUSH_INTERVAL * 2).await;
+        assert!(*flushes.lock().unwrap() >= 2);
+        drop(tx_output);
+    };
+    let (certificates, ()) = tokio::join!(analyze(rx_output, recorder, None, None, None), idle);
+    assert_eq!(certificates, 0);
+}
@@ -0,0 +1,117 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use consensus::{write_sub_dag, COMMITTED_KEY};
+use crypto::Hash as _;
+use primary::{Certificate, Header};
+use store::MemoryStore;
+
+// Records a sub-dag of a single certificate (the leader of round `2 * (index + 1)`), as the primary and the
+// consensus do before outputting it.
+async fn record(store: &mut MemoryStore, index: u64) -> CommittedSubDag {
+    let leader = Certificate {
+        header: Header {
+            round: 2 * (index + 1),
+            ..Header::default()
+        },
+        votes: Vec::new(),
+    };
+    let sub_dag = CommittedSubDag {
+        leader: leader.clone(),
+        certificates: vec![leader.clone()],
+        sub_dag_index: index,
+    };
+    let bytes = bincode::serialize(&leader).unwrap();
+    store.write(leader.digest().to_vec(), bytes).await;
+    write_sub_dag(store, &sub_dag).await;
+    sub_dag
+}
+
+// Records a sub-dag and then persists its watermark, as the consensus does once it output it.
+async fn commit(store: &mut MemoryStore, index: u64) -> CommittedSubDag {
+    let sub_dag = record(store, index).await;
+    let committed = CommittedWatermark {
+        sub_dag_index: index,
+        round: sub_dag.round(),
+    };
+    store
+        .write(COMMITTED_KEY.to_vec(), committed.to_bytes())
+        .await;
+    sub_dag
+}
+
+#[tokio::test]
+async fn replay_then_follow_live_sub_dags() {
+    let address = "127.0.0.1:18000".parse::<SocketAddr>().unwrap();
+    let mut store = MemoryStore::new();
+    for index in 0..3 {
+        commit(&mut store, index).await;
+    }
+    let (tx_sub_dags, rx_sub_dags) = channel(1);
+    SubDagStream::spawn(address, store.clone(), /* gc_depth */ 50, rx_sub_dags);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // A subscriber resuming after sub-dag 0 first gets the recorded sub-dags.
+    let mut subscriber = SubDagSubscriber::connect(address, 1).await.unwrap();
+    for index in 1..3 {
+        let sub_dag = subscriber.recv().await.unwrap().unwrap();
+        assert_eq!(sub_dag.sub_dag_index, index);
+        assert_eq!(sub_dag.round(), 2 * (index + 1));
+    }
+
+    // It then follows the sub-dags committed afterwards.
+    let sub_dag = commit(&mut store, 3).await;
+    tx_sub_dags.send(sub_dag).await.unwrap();
+    let sub_dag = subscriber.recv().await.unwrap().unwrap();
+    assert_eq!(sub_dag.sub_dag_index, 3);
+    assert_eq!(subscriber.next_index(), 4);
+}
+
+#[tokio::test]
+async fn reject_sub_dags_below_gc_depth() {
+    let address = "127.0.0.1:18010".parse::<SocketAddr>().unwrap();
+    let mut store = MemoryStore::new();
+    for index in 0..5 {
+        commit(&mut store, index).await;
+    }
+
+    // The last leader is of round 10: with a garbage collection depth of 2, the sub-dags from index 3 (round
+    // 8) can be replayed.
+    SubDagStream::spawn(address, store, /* gc_depth */ 2, channel(1).1);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut subscriber = SubDagSubscriber::connect(address, 0).await.unwrap();
+    let error = subscriber.recv().await.unwrap().unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::NotFound);
+
+    let mut subscriber = SubDagSubscriber::connect(address, 3).await.unwrap();
+    let sub_dag = subscriber.recv().await.unwrap().unwrap();
+    assert_eq!(sub_dag.sub_dag_index, 3);
+}
+
+#[tokio::test]
+async fn replay_sub_dags_output_before_their_watermark() {
+    let address = "127.0.0.1:18020".parse::<SocketAddr>().unwrap();
+    let mut store = MemoryStore::new();
+    for index in 0..3 {
+        commit(&mut store, index).await;
+    }
+    let (tx_sub_dags, rx_sub_dags) = channel(1);
+    SubDagStream::spawn(address, store.clone(), /* gc_depth */ 50, rx_sub_dags);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // Sub-dag 3 is output before any subscriber registers, and its watermark is not yet persisted.
+    let sub_dag = record(&mut store, 3).await;
+    tx_sub_dags.send(sub_dag).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // A subscriber connecting meanwhile still gets it, and then follows the next sub-dags.
+    let mut subscriber = SubDagSubscriber::connect(address, 1).await.unwrap();
+    for index in 1..4 {
+        let sub_dag = subscriber.recv().await.unwrap().unwrap();
+        assert_eq!(sub_dag.sub_dag_index, index);
+    }
+    let sub_dag = commit(&mut store, 4).await;
+    tx_sub_dags.send(sub_dag).await.unwrap();
+    let sub_dag = subscriber.recv().await.unwrap().unwrap();
+    assert_eq!(sub_dag.sub_dag_index, 4);
+}
@@ -0,0 +1,35 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+
+#[test]
+fn count_unique_transactions() {
+    let throughput = Throughput::new(&Registry::new(), /* dedup_window */ 2);
+    throughput.record(&[b"a".to_vec(), b"b".to_vec(), b"a".to_vec()]);
+
+    // The window only remembers 'b' and 'c' after the second batch: 'a' then counts as unique again.
+    throughput.record(&[b"c".to_vec(), b"a".to_vec()]);
+    throughput.record(&[b"a".to_vec()]);
+
+    let report = throughput.report();
+    assert_eq!(report.transactions, 6);
+    assert_eq!(report.unique_transactions, Some(4));
+    assert!(report.transactions_per_second > 0.0 && report.transactions_per_second <= 6.0);
+    let unique_rate = report.unique_transactions_per_second.unwrap();
+    assert!(unique_rate > 0.0 && unique_rate <= report.transactions_per_second);
+}
+
+#[test]
+fn count_without_dedup() {
+    let throughput = Throughput::new(&Registry::new(), /* dedup_window */ 0);
+    throughput.record(&[b"a".to_vec(), b"a".to_vec()]);
+    throughput.record(&[]);
+
+    let report = throughput.report();
+    assert_eq!(report.transactions, 2);
+    assert_eq!(report.unique_transactions, None);
+    assert_eq!(report.unique_transactions_per_second, None);
+
+    // The unique counts are not reported at all.
+    let json = serde_json::to_value(&report).unwrap();
+    assert!(json.get("unique_transactions").is_none());
+}
@@ -0,0 +1,206 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crypto::{hash, Digest};
+use prometheus::{Gauge, IntCounter, Registry};
+use serde::Serialize;
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use tokio::task::JoinHandle;
+use tokio::time::{interval, Duration, Instant};
+
+#[cfg(test)]
+#[path = "tests/throughput_tests.rs"]
+pub mod throughput_tests;
+
+/// The window over which the committed throughput is averaged.
+pub const THROUGHPUT_WINDOW: Duration = Duration::from_secs(10);
+
+/// The delay between two refreshes of the throughput gauges (they decay even when nothing is committed).
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The committed throughput, as reported by the admin server.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ThroughputReport {
+    /// The number of transactions decrypted from the committed batches so far.
+    pub transactions: u64,
+    /// The number of committed transactions per second, averaged over `THROUGHPUT_WINDOW`.
+    pub transactions_per_second: f64,
+    /// The same counts without the transactions committed again within the deduplication window (only
+    /// reported if the node deduplicates the transactions).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unique_transactions: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unique_transactions_per_second: Option<f64>,
+}
+
+struct Metrics {
+    transactions: IntCounter,
+    transactions_per_second: Gauge,
+    unique_transactions: IntCounter,
+    unique_transactions_per_second: Gauge,
+}
+
+struct State {
+    /// The time from which we measure (for the averages of the first window).
+    start: Instant,
+    transactions: u64,
+    unique_transactions: u64,
+    /// The number of transactions (total and unique) of each committed batch of the current window, oldest
+    /// first.
+    samples: VecDeque<(Instant, u64, u64)>,
+    /// The number of recent transactions we remember to count the unique ones (0 if we do not).
+    dedup_window: usize,
+    /// The digests of the recent transactions, and the same digests oldest first.
+    recent: HashSet<Digest>,
+    recent_order: VecDeque<Digest>,
+}
+
+impl State {
+    /// Returns the rates of the total and unique transactions at time `now`, forgetting the samples that
+    /// left the window.
+    fn rates(&mut self, now: Instant) -> (f64, f64) {
+        while let Some((time, _, _)) = self.samples.front() {
+            if now.duration_since(*time) <= THROUGHPUT_WINDOW {
+                break;
+            }
+            self.samples.pop_front();
+        }
+        let elapsed = now
+            .duration_since(self.start)
+            .min(THROUGHPUT_WINDOW)
+            .as_secs_f64()
+            .max(1.0);
+        let (total, unique) = self
+            .samples
+            .iter()
+            .fold((0, 0), |(x, y), (_, total, unique)| (x + total, y + unique));
+        (total as f64 / elapsed, unique as f64 / elapsed)
+    }
+}
+
+/// Counts the transactions of the committed batches once they are decrypted, and derives the committed
+/// throughput: the headline number of the node. If `dedup_window` is not 0, it also counts the unique
+/// transactions: those not committed already among the last `dedup_window` transactions (the workers only
+/// drop the duplicates they receive themselves, a client may still submit a transaction to several
+/// workers). Handles are cheap to clone and share the same counts.
+#[derive(Clone)]
+pub struct Throughput {
+    state: Arc<Mutex<State>>,
+    metrics: Arc<Metrics>,
+}
+
+impl Throughput {
+    /// Creates the counters and registers their metrics with `registry`.
+    pub fn new(registry: &Registry, dedup_window: usize) -> Self {
+        let metrics = Metrics {
+            transactions: IntCounter::new(
+                "node_committed_transactions_total",
+                "Transactions decrypted from the committed batches",
+            )
+            .unwrap(),
+            transactions_per_second: Gauge::new(
+                "node_committed_transactions_per_second",
+                "Committed transactions per second (averaged over the last 10 seconds)",
+            )
+            .unwrap(),
+            unique_transactions: IntCounter::new(
+                "node_committed_unique_transactions_total",
+                "Committed transactions not committed already within the deduplication window",
+            )
+            .unwrap(),
+            unique_transactions_per_second: Gauge::new(
+                "node_committed_unique_transactions_per_second",
+                "Unique committed transactions per second (averaged over the last 10 seconds)",
+            )
+            .unwrap(),
+        };
+        registry
+            .register(Box::new(metrics.transactions.clone()))
+            .expect("Failed to register the throughput metrics");
+        registry
+            .register(Box::new(metrics.transactions_per_second.clone()))
+            .expect("Failed to register the throughput metrics");
+        if dedup_window > 0 {
+            registry
+                .register(Box::new(metrics.unique_transactions.clone()))
+                .expect("Failed to register the throughput metrics");
+            registry
+                .register(Box::new(metrics.unique_transactions_per_second.clone()))
+                .expect("Failed to register the throughput metrics");
+        }
+        let state = State {
+            start: Instant::now(),
+            transactions: 0,
+            unique_transactions: 0,
+            samples: VecDeque::new(),
+            dedup_window,
+            recent: HashSet::new(),
+            recent_order: VecDeque::new(),
+        };
+        Self {
+            state: Arc::new(Mutex::new(state)),
+            metrics: Arc::new(metrics),
+        }
+    }
+
+    /// Counts the (plaintext) transactions of a committed batch.
+    pub fn record(&self, transactions: &[Vec<u8>]) {
+        let mut state = self.state.lock().unwrap();
+        let total = transactions.len() as u64;
+        let unique = if state.dedup_window == 0 {
+            total
+        } else {
+            let mut unique = 0;
+            for transaction in transactions {
+                let digest = hash(transaction);
+                if state.recent.insert(digest.clone()) {
+                    state.recent_order.push_back(digest);
+                    unique += 1;
+                }
+            }
+            while state.recent_order.len() > state.dedup_window {
+                if let Some(digest) = state.recent_order.pop_front() {
+                    state.recent.remove(&digest);
+                }
+            }
+            unique
+        };
+        state.transactions += total;
+        state.unique_transactions += unique;
+        state.samples.push_back((Instant::now(), total, unique));
+        self.metrics.transactions.inc_by(total);
+        self.metrics.unique_transactions.inc_by(unique);
+        self.update_rates(&mut state);
+    }
+
+    /// Returns the committed throughput so far (and refreshes the gauges).
+    pub fn report(&self) -> ThroughputReport {
+        let mut state = self.state.lock().unwrap();
+        let (rate, unique_rate) = self.update_rates(&mut state);
+        let dedup = state.dedup_window > 0;
+        ThroughputReport {
+            transactions: state.transactions,
+            transactions_per_second: rate,
+            unique_transactions: Some(state.unique_transactions).filter(|_| dedup),
+            unique_transactions_per_second: Some(unique_rate).filter(|_| dedup),
+        }
+    }
+
+    /// Spawns a task refreshing the throughput gauges, so that they fall back to 0 when nothing commits.
+    pub fn spawn_refresher(&self) -> JoinHandle<()> {
+        let throughput = self.clone();
+        tokio::spawn(async move {
+            let mut timer = interval(REFRESH_INTERVAL);
+            loop {
+                timer.tick().await;
+                throughput.report();
+            }
+        })
+    }
+
+    fn update_rates(&self, state: &mut State) -> (f64, f64) {
+        let (rate, unique_rate) = state.rates(Instant::now());
+        self.metrics.transactions_per_second.set(rate);
+        self.metrics.unique_transactions_per_second.set(unique_rate);
+        (rate, unique_rate)
+    }
+}
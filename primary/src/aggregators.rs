@@ -5,12 +5,21 @@ use config::{Committee, Stake};
 use crypto::Hash as _;
 use crypto::{Digest, PublicKey, Signature};
 use std::collections::HashSet;
+use std::time::{Duration, Instant};
 
-/// Aggregates votes for a particular header into a certificate.
+#[cfg(test)]
+#[path = "tests/aggregators_tests.rs"]
+pub mod aggregators_tests;
+
+/// Aggregates votes for a particular header into a certificate. The certificate is made as soon as the
+/// votes reach a quorum of stake; the later votes are ignored.
 pub struct VotesAggregator {
     weight: Stake,
     votes: Vec<(PublicKey, Signature)>,
     used: HashSet<PublicKey>,
+    reached: bool,
+    /// When we started collecting the votes (ie. when we proposed the header).
+    started: Instant,
 }
 
 impl VotesAggregator {
@@ -19,9 +28,16 @@ impl VotesAggregator {
             weight: 0,
             votes: Vec::new(),
             used: HashSet::new(),
+            reached: false,
+            started: Instant::now(),
         }
     }
 
+    /// The time elapsed since we started collecting the votes.
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
     pub fn append(
         &mut self,
         vote: Vote,
@@ -33,13 +49,18 @@ impl VotesAggregator {
         // Ensure it is the first time this authority votes.
         ensure!(self.used.insert(author), DagError::AuthorityReuse(author));
 
+        // The certificate is already made: it does not need more votes.
+        if self.reached {
+            return Ok(None);
+        }
+
         self.votes.push((author, vote.signature));
         self.weight += committee.stake(&author);
         if self.weight >= committee.quorum_threshold() {
-            self.weight = 0; // Ensures quorum is only reached once.
+            self.reached = true; // Ensures quorum is only reached once.
             return Ok(Some(Certificate {
                 header: header.clone(),
-                votes: self.votes.clone(),
+                votes: self.votes.drain(..).collect(),
             }));
         }
         Ok(None)
@@ -51,6 +72,7 @@ pub struct CertificatesAggregator {
     weight: Stake,
     certificates: Vec<Digest>,
     used: HashSet<PublicKey>,
+    reached: bool,
 }
 
 impl CertificatesAggregator {
@@ -59,6 +81,7 @@ impl CertificatesAggregator {
             weight: 0,
             certificates: Vec::new(),
             used: HashSet::new(),
+            reached: false,
         }
     }
 
@@ -74,10 +97,15 @@ impl CertificatesAggregator {
             return Ok(None);
         }
 
+        // Once we reached a quorum, we output every new certificate on its own (late parents).
+        if self.reached {
+            return Ok(Some(vec![certificate.digest()]));
+        }
+
         self.certificates.push(certificate.digest());
         self.weight += committee.stake(&origin);
         if self.weight >= committee.quorum_threshold() {
-            self.weight = 0; // Ensures quorum is only reached once.
+            self.reached = true; // Ensures quorum is only reached once.
             return Ok(Some(self.certificates.drain(..).collect()));
         }
         Ok(None)
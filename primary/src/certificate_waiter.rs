@@ -1,56 +1,83 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use crate::error::{DagError, DagResult};
 use crate::messages::Certificate;
+use crate::primary::Round;
+use crypto::{Digest, Hash as _};
 use futures::future::try_join_all;
 use futures::stream::futures_unordered::FuturesUnordered;
 use futures::stream::StreamExt as _;
 use log::error;
-use store::Store;
-use tokio::sync::mpsc::{Receiver, Sender};
+use std::cmp::max;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use store::KeyValueStore;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::task::JoinHandle;
+
+#[cfg(test)]
+#[path = "tests/certificate_waiter_tests.rs"]
+pub mod certificate_waiter_tests;
 
 /// Waits to receive all the ancestors of a certificate before looping it back to the `Core`
 /// for further processing.
-pub struct CertificateWaiter {
+pub struct CertificateWaiter<S> {
     /// The persistent storage.
-    store: Store,
+    store: S,
+    /// The current consensus round (used for cleanup).
+    consensus_round: Arc<AtomicU64>,
+    /// The depth of the garbage collector.
+    gc_depth: Round,
     /// Receives sync commands from the `Synchronizer`.
     rx_synchronizer: Receiver<Certificate>,
     /// Loops back to the core certificates for which we got all parents.
     tx_core: Sender<Certificate>,
+    /// The certificates waiting for their parents, along with their round and the handler cancelling the wait.
+    pending: HashMap<Digest, (Round, Sender<()>)>,
+    /// The highest round of the certificates we waited for.
+    last_round: Round,
 }
 
-impl CertificateWaiter {
+impl<S: KeyValueStore> CertificateWaiter<S> {
     pub fn spawn(
-        store: Store,
+        store: S,
+        consensus_round: Arc<AtomicU64>,
+        gc_depth: Round,
         rx_synchronizer: Receiver<Certificate>,
         tx_core: Sender<Certificate>,
-    ) {
+    ) -> JoinHandle<()> {
         tokio::spawn(async move {
             Self {
                 store,
+                consensus_round,
+                gc_depth,
                 rx_synchronizer,
                 tx_core,
+                pending: HashMap::new(),
+                last_round: 0,
             }
             .run()
             .await
-        });
+        })
     }
 
     /// Helper function. It waits for particular data to become available in the storage
-    /// and then delivers the specified header.
+    /// and then delivers the specified certificate (unless the wait is cancelled).
     async fn waiter(
-        mut missing: Vec<(Vec<u8>, Store)>,
+        mut missing: Vec<(Vec<u8>, S)>,
         deliver: Certificate,
-    ) -> DagResult<Certificate> {
+        mut handler: Receiver<()>,
+    ) -> DagResult<Option<Certificate>> {
         let waiting: Vec<_> = missing
             .iter_mut()
             .map(|(x, y)| y.notify_read(x.to_vec()))
             .collect();
-
-        try_join_all(waiting)
-            .await
-            .map(|_| deliver)
-            .map_err(DagError::from)
+        tokio::select! {
+            result = try_join_all(waiting) => {
+                result.map(|_| Some(deliver)).map_err(DagError::from)
+            }
+            _ = handler.recv() => Ok(None),
+        }
     }
 
     async fn run(&mut self) {
@@ -59,6 +86,13 @@ impl CertificateWaiter {
         loop {
             tokio::select! {
                 Some(certificate) = self.rx_synchronizer.recv() => {
+                    // Ensure we wait only once per certificate.
+                    let digest = certificate.digest();
+                    if self.pending.contains_key(&digest) {
+                        continue;
+                    }
+                    self.last_round = self.last_round.max(certificate.round());
+
                     // Add the certificate to the waiter pool. The waiter will return it to us
                     // when all its parents are in the store.
                     let wait_for = certificate
@@ -68,19 +102,41 @@ impl CertificateWaiter {
                         .cloned()
                         .map(|x| (x.to_vec(), self.store.clone()))
                         .collect();
-                    let fut = Self::waiter(wait_for, certificate);
+                    let (tx_cancel, rx_cancel) = channel(1);
+                    self.pending.insert(digest, (certificate.round(), tx_cancel));
+                    let fut = Self::waiter(wait_for, certificate, rx_cancel);
                     waiting.push(fut);
                 }
                 Some(result) = waiting.next() => match result {
-                    Ok(certificate) => {
+                    Ok(Some(certificate)) => {
+                        let _ = self.pending.remove(&certificate.digest());
                         self.tx_core.send(certificate).await.expect("Failed to send certificate");
                     },
+                    Ok(None) => {
+                        // This wait has been cancelled.
+                    },
                     Err(e) => {
                         error!("{}", e);
                         panic!("Storage failure: killing node.");
                     }
                 },
             }
+
+            // Cleanup internal state. As for the `HeaderWaiter`, we also drop the certificates more than
+            // `2 * gc_depth` rounds behind the last one, since the consensus may lag far behind them.
+            let round = self.consensus_round.load(Ordering::Relaxed);
+            let gc_round = max(
+                round.saturating_sub(self.gc_depth),
+                self.last_round.saturating_sub(2 * self.gc_depth),
+            );
+            if gc_round > 0 {
+                for (r, handler) in self.pending.values() {
+                    if r <= &gc_round {
+                        let _ = handler.send(()).await;
+                    }
+                }
+                self.pending.retain(|_, (r, _)| r > &gc_round);
+            }
         }
     }
 }
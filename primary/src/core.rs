@@ -1,43 +1,58 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use crate::aggregators::{CertificatesAggregator, VotesAggregator};
+use crate::dag_width::{DagWidth, WIDTH_WINDOW};
 use crate::error::{DagError, DagResult};
-use crate::messages::{Certificate, Header, Vote};
+use crate::health::{PeersHealth, SuspectEvent};
+use crate::messages::{Certificate, Equivocation, Header, Vote};
+use crate::metrics::PrimaryMetrics;
 use crate::primary::{PrimaryMessage, Round};
+use crate::state_sync::RoundIndex;
 use crate::synchronizer::Synchronizer;
 use async_recursion::async_recursion;
 use bytes::Bytes;
-use config::Committee;
+use config::{Committee, Epoch, Parameters};
 use crypto::Hash as _;
 use crypto::{Digest, PublicKey, SignatureService};
-use log::{debug, error, warn};
-use network::{CancelHandler, ReliableSender};
+use log::{debug, error, info, warn};
+use network::{Backoff, CancelHandler, ReliableSender};
+use std::collections::hash_map::Entry;
 use std::collections::{HashMap, HashSet};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use store::Store;
+use store::KeyValueStore;
 use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tracing::{info_span, Instrument as _};
 
 #[cfg(test)]
 #[path = "tests/core_tests.rs"]
 pub mod core_tests;
 
-pub struct Core {
+pub struct Core<S> {
     /// The public key of this primary.
     name: PublicKey,
     /// The committee information.
     committee: Committee,
     /// The persistent storage.
-    store: Store,
+    store: S,
+    /// Indexes the stored certificates by round (to help the peers catching up).
+    round_index: RoundIndex<S>,
     /// Handles synchronization with other nodes and our workers.
-    synchronizer: Synchronizer,
+    synchronizer: Synchronizer<S>,
     /// Service to sign headers.
     signature_service: SignatureService,
     /// The current consensus round (used for cleanup).
     consensus_round: Arc<AtomicU64>,
     /// The depth of the garbage collector.
     gc_depth: Round,
+    /// Records the authorities from which we recently processed certificates.
+    peers_health: PeersHealth,
+    /// The metrics exported by the primary.
+    metrics: PrimaryMetrics,
 
-    /// Receiver for dag messages (headers, votes, certificates).
+    /// Receiver for dag messages (headers, votes, certificates), whose signatures the `Verifier` checked
+    /// (except for the votes).
     rx_primaries: Receiver<PrimaryMessage>,
     /// Receives loopback headers from the `HeaderWaiter`.
     rx_header_waiter: Receiver<Header>,
@@ -49,19 +64,30 @@ pub struct Core {
     tx_consensus: Sender<Certificate>,
     /// Send valid a quorum of certificates' ids to the `Proposer` (along with their round).
     tx_proposer: Sender<(Vec<Digest>, Round)>,
+    /// Optionally outputs the proofs of the equivocations we detect.
+    tx_equivocations: Option<Sender<Equivocation>>,
+    /// Tells the signing epoch (which selects the keys we accept from the other voters) and after how many
+    /// rounds we suspect the absent authorities.
+    rx_parameters: watch::Receiver<Parameters>,
 
     /// The last garbage collected round.
     gc_round: Round,
+    /// The highest round of the certificates we stored.
+    dag_round: Round,
     /// The authors of the last voted headers.
     last_voted: HashMap<Round, HashSet<PublicKey>>,
     /// The set of headers we are currently processing.
     processing: HashMap<Round, HashSet<Digest>>,
+    /// The first header we processed from each author at each round (to detect equivocations).
+    first_headers: HashMap<Round, HashMap<PublicKey, Header>>,
     /// The last header we proposed (for which we are waiting votes).
     current_header: Header,
     /// Aggregates votes into a certificate.
     votes_aggregator: VotesAggregator,
     /// Aggregates certificates to use as parents for new headers.
     certificates_aggregators: HashMap<Round, Box<CertificatesAggregator>>,
+    /// Measures the number of authors of the certificates of each round.
+    dag_width: DagWidth,
     /// A network sender to send the batches to the other workers.
     network: ReliableSender,
     // TOUNDERSTAND: understand why we need cancel_handlers. We don't seem to use them anywhere, other than
@@ -72,53 +98,69 @@ pub struct Core {
     cancel_handlers: HashMap<Round, Vec<CancelHandler>>,
 }
 
-impl Core {
+impl<S: KeyValueStore> Core<S> {
     #[allow(clippy::too_many_arguments)]
     pub fn spawn(
         name: PublicKey,
         committee: Committee,
-        store: Store,
-        synchronizer: Synchronizer,
+        store: S,
+        synchronizer: Synchronizer<S>,
         signature_service: SignatureService,
         consensus_round: Arc<AtomicU64>,
         gc_depth: Round,
+        backoff: Backoff,
+        peers_health: PeersHealth,
+        metrics: PrimaryMetrics,
         rx_primaries: Receiver<PrimaryMessage>,
         rx_header_waiter: Receiver<Header>,
         rx_certificate_waiter: Receiver<Certificate>,
         rx_proposer: Receiver<Header>,
         tx_consensus: Sender<Certificate>,
         tx_proposer: Sender<(Vec<Digest>, Round)>,
-    ) {
+        tx_equivocations: Option<Sender<Equivocation>>,
+        rx_parameters: watch::Receiver<Parameters>,
+    ) -> JoinHandle<()> {
         tokio::spawn(async move {
             Self {
                 name,
+                round_index: RoundIndex::new(store.clone(), &committee),
+                dag_width: DagWidth::new(committee.clone()),
                 committee,
                 store,
                 synchronizer,
                 signature_service,
                 consensus_round,
                 gc_depth,
+                peers_health,
+                metrics,
                 rx_primaries,
                 rx_header_waiter,
                 rx_certificate_waiter,
                 rx_proposer,
                 tx_consensus,
                 tx_proposer,
+                tx_equivocations,
+                rx_parameters,
                 gc_round: 0,
+                dag_round: 0,
                 last_voted: HashMap::with_capacity(2 * gc_depth as usize),
                 processing: HashMap::with_capacity(2 * gc_depth as usize),
+                first_headers: HashMap::with_capacity(2 * gc_depth as usize),
                 current_header: Header::default(),
                 votes_aggregator: VotesAggregator::new(),
                 certificates_aggregators: HashMap::with_capacity(2 * gc_depth as usize),
-                network: ReliableSender::new(),
+                network: ReliableSender::new().with_backoff(backoff),
                 cancel_handlers: HashMap::with_capacity(2 * gc_depth as usize),
             }
             .run()
             .await;
-        });
+        })
     }
 
     async fn process_own_header(&mut self, header: Header) -> DagResult<()> {
+        self.metrics.headers_proposed.inc();
+        self.metrics.current_round.set(header.round as i64);
+
         // Reset the votes aggregator.
         self.current_header = header.clone();
         self.votes_aggregator = VotesAggregator::new();
@@ -142,9 +184,61 @@ impl Core {
         self.process_header(&header).await
     }
 
+    /// Reports the author of `header` if we already processed a different header of the same author and round.
+    /// The header itself is still processed: we never vote for it (we already voted for this author and round)
+    /// but a quorum of nodes may have seen it first and certified it.
+    async fn check_equivocation(&mut self, header: &Header) -> DagResult<()> {
+        let first = match self
+            .first_headers
+            .entry(header.round)
+            .or_insert_with(HashMap::new)
+            .entry(header.author)
+        {
+            Entry::Occupied(x) if x.get().id != header.id => x.get().clone(),
+            Entry::Occupied(_) => return Ok(()),
+            Entry::Vacant(x) => {
+                x.insert(header.clone());
+                return Ok(());
+            }
+        };
+        let equivocation = Equivocation {
+            first,
+            second: header.clone(),
+        };
+
+        // Report every equivocation once (headers may be processed multiple times).
+        let key = equivocation.digest().to_vec();
+        if self.store.read(key.clone()).await?.is_some() {
+            return Ok(());
+        }
+        warn!(
+            "Authority {} equivocated at round {}: header {} (signature {}) conflicts with header {} (signature {})",
+            equivocation.author(),
+            equivocation.round(),
+            equivocation.first.id,
+            equivocation.first.signature.encode_base64(),
+            equivocation.second.id,
+            equivocation.second.signature.encode_base64()
+        );
+        self.metrics.equivocations.inc();
+
+        // Store the proof.
+        let bytes = bincode::serialize(&equivocation).expect("Failed to serialize equivocation");
+        self.store.write(key, bytes).await;
+
+        if let Some(tx_equivocations) = &self.tx_equivocations {
+            if let Err(e) = tx_equivocations.send(equivocation).await {
+                warn!("Failed to output equivocation: {}", e);
+            }
+        }
+        Ok(())
+    }
+
     #[async_recursion]
     async fn process_header(&mut self, header: &Header) -> DagResult<()> {
         debug!("Processing {:?}", header);
+        self.check_equivocation(header).await?;
+
         // Indicate that we are processing this header.
         self.processing
             .entry(header.round)
@@ -161,18 +255,7 @@ impl Core {
         }
 
         // Check the parent certificates. Ensure the parents form a quorum and are all from the previous round.
-        let mut stake = 0;
-        for x in parents {
-            ensure!(
-                x.round() + 1 == header.round,
-                DagError::MalformedHeader(header.id.clone())
-            );
-            stake += self.committee.stake(&x.origin());
-        }
-        ensure!(
-            stake >= self.committee.quorum_threshold(),
-            DagError::HeaderRequiresQuorum(header.id.clone())
-        );
+        header.verify_parents(&parents, &self.committee)?;
 
         // Ensure we have the payload. If we don't, the synchronizer will ask our workers to get it, and then
         // reschedule processing of this header once we have it.
@@ -220,6 +303,7 @@ impl Core {
     #[async_recursion]
     async fn process_vote(&mut self, vote: Vote) -> DagResult<()> {
         debug!("Processing {:?}", vote);
+        self.metrics.votes_received.inc();
 
         // Add it to the votes' aggregator and try to make a new certificate.
         if let Some(certificate) =
@@ -227,6 +311,10 @@ impl Core {
                 .append(vote, &self.committee, &self.current_header)?
         {
             debug!("Assembled {:?}", certificate);
+            self.metrics.certificates_assembled.inc();
+            self.metrics
+                .vote_collection_latency
+                .observe(self.votes_aggregator.elapsed().as_secs_f64());
 
             // Broadcast the certificate.
             let addresses = self
@@ -281,9 +369,16 @@ impl Core {
         // Store the certificate.
         let bytes = bincode::serialize(&certificate).expect("Failed to serialize certificate");
         self.store.write(certificate.digest().to_vec(), bytes).await;
+        self.synchronizer.cache_certificate(&certificate);
+        self.round_index.insert(&certificate).await?;
+        self.dag_round = self.dag_round.max(certificate.round());
+        self.peers_health
+            .certificate_received(certificate.origin(), certificate.round());
+        self.update_suspects();
+        self.update_dag_width(&certificate);
 
         // Check if we have enough certificates to enter a new dag round and propose a header.
-        // append returns 2f+1 certificates (parents) or None
+        // append returns 2f+1 certificates (parents), then every later certificate of the round on its own
         if let Some(parents) = self
             .certificates_aggregators
             .entry(certificate.round())
@@ -308,17 +403,74 @@ impl Core {
         Ok(())
     }
 
+    /// Updates the DAG width metrics, and warns if some authorities persistently fail to get certified.
+    fn update_dag_width(&mut self, certificate: &Certificate) {
+        for (round, width) in self
+            .dag_width
+            .add(certificate.origin(), certificate.round())
+        {
+            debug!(
+                "Round {} has certificates from {} authorities",
+                round, width
+            );
+            self.metrics.dag_width.set(width as i64);
+        }
+        self.metrics.dag_width_average.set(self.dag_width.average());
+
+        if let Some(absent) = self.dag_width.narrow() {
+            let absent: Vec<_> = absent
+                .iter()
+                .map(|(name, missed)| format!("{} ({} rounds)", name, missed))
+                .collect();
+            warn!(
+                "The DAG was narrow over the last {} rounds (average width {:.1} of {}), missing certificates from {}",
+                WIDTH_WINDOW,
+                self.dag_width.average(),
+                self.committee.size(),
+                absent.join(", ")
+            );
+        }
+    }
+
+    /// Reports the authorities that stopped (or resumed) contributing to the DAG.
+    fn update_suspects(&mut self) {
+        let max_absent_rounds = self.rx_parameters.borrow().suspect_after_rounds;
+        let events = self
+            .peers_health
+            .update_suspects(&self.committee, max_absent_rounds);
+        if events.is_empty() {
+            return;
+        }
+        for event in events {
+            match event {
+                SuspectEvent::Suspected(name, round) => warn!(
+                    "Suspecting {} to be faulty: no certificate since round {}",
+                    name, round
+                ),
+                SuspectEvent::Cleared(name, round) => {
+                    info!("{} is no longer suspected (round {})", name, round)
+                }
+            }
+        }
+        self.metrics
+            .suspected_authorities
+            .set(self.peers_health.suspects().len() as i64);
+    }
+
     fn sanitize_header(&mut self, header: &Header) -> DagResult<()> {
         ensure!(
             self.gc_round <= header.round,
             DagError::TooOld(header.id.clone(), header.round)
         );
 
-        // Verify the header's signature.
-        header.verify(&self.committee)?;
-
-        // TODO [issue #3]: Prevent bad nodes from sending junk headers with high round numbers.
+        // The `Verifier` already checked the header's signature.
 
+        // Bad nodes may send junk headers with high round numbers: we only process the headers close to the
+        // DAG (when lagging behind, we catch up through the certificates rather than the headers).
+        ensure!(
+            header.round <= self.dag_round + self.gc_depth,
+            DagError::TooNew(header.id.clone(), header.round)
+        );
         Ok(())
     }
 
@@ -337,42 +489,52 @@ impl Core {
         );
 
         // Verify the vote.
-        vote.verify(&self.committee).map_err(DagError::from)
+        vote.verify(&self.committee, self.signing_epoch())
+            .map_err(DagError::from)
     }
 
     fn sanitize_certificate(&mut self, certificate: &Certificate) -> DagResult<()> {
+        // The `Verifier` already checked the certificate (and the embedded header).
         ensure!(
             self.gc_round <= certificate.round(),
             DagError::TooOld(certificate.digest(), certificate.round())
         );
+        Ok(())
+    }
 
-        // Verify the certificate (and the embedded header).
-        certificate.verify(&self.committee).map_err(DagError::from)
+    /// The current signing epoch (it may be reloaded at runtime).
+    fn signing_epoch(&self) -> Epoch {
+        self.rx_parameters.borrow().signing_epoch
     }
 
     // Main loop listening to incoming messages.
     pub async fn run(&mut self) {
+        // Every message is processed in a tracing span carrying the digest of the header it relates to, so
+        // that the spans of a header (from its proposal to its commit) can be looked up together.
         loop {
             let result = tokio::select! {
                 // We receive here messages from other primaries.
                 Some(message) = self.rx_primaries.recv() => {
                     match message {
                         PrimaryMessage::Header(header) => {
+                            let span = info_span!("header", header = %header.id, author = %header.author, round = header.round);
                             match self.sanitize_header(&header) {
-                                Ok(()) => self.process_header(&header).await,
+                                Ok(()) => self.process_header(&header).instrument(span).await,
                                 error => error
                             }
 
                         },
                         PrimaryMessage::Vote(vote) => {
+                            let span = info_span!("vote", header = %vote.id, voter = %vote.author, round = vote.round);
                             match self.sanitize_vote(&vote) {
-                                Ok(()) => self.process_vote(vote).await,
+                                Ok(()) => self.process_vote(vote).instrument(span).await,
                                 error => error
                             }
                         },
                         PrimaryMessage::Certificate(certificate) => {
+                            let span = info_span!("certificate", header = %certificate.header.id, author = %certificate.origin(), round = certificate.round());
                             match self.sanitize_certificate(&certificate) {
-                                Ok(()) =>  self.process_certificate(certificate).await,
+                                Ok(()) =>  self.process_certificate(certificate).instrument(span).await,
                                 error => error
                             }
                         },
@@ -382,15 +544,24 @@ impl Core {
 
                 // We receive here loopback headers from the `HeaderWaiter`. Those are headers for which we interrupted
                 // execution (we were missing some of their dependencies) and we are now ready to resume processing.
-                Some(header) = self.rx_header_waiter.recv() => self.process_header(&header).await,
+                Some(header) = self.rx_header_waiter.recv() => {
+                    let span = info_span!("synchronized_header", header = %header.id, author = %header.author, round = header.round);
+                    self.process_header(&header).instrument(span).await
+                },
 
                 // We receive here loopback certificates from the `CertificateWaiter`. Those are certificates for which
                 // we interrupted execution (we were missing some of their ancestors) and we are now ready to resume
                 // processing.
-                Some(certificate) = self.rx_certificate_waiter.recv() => self.process_certificate(certificate).await,
+                Some(certificate) = self.rx_certificate_waiter.recv() => {
+                    let span = info_span!("synchronized_certificate", header = %certificate.header.id, author = %certificate.origin(), round = certificate.round());
+                    self.process_certificate(certificate).instrument(span).await
+                },
 
                 // We also receive here our new headers created by the `Proposer`.
-                Some(header) = self.rx_proposer.recv() => self.process_own_header(header).await,
+                Some(header) = self.rx_proposer.recv() => {
+                    let span = info_span!("own_header", header = %header.id, round = header.round);
+                    self.process_own_header(header).instrument(span).await
+                },
             };
             match result {
                 Ok(()) => (),
@@ -398,7 +569,7 @@ impl Core {
                     error!("{}", e);
                     panic!("Storage failure: killing node.");
                 }
-                Err(e @ DagError::TooOld(..)) => debug!("{}", e),
+                Err(e @ DagError::TooOld(..)) | Err(e @ DagError::TooNew(..)) => debug!("{}", e),
                 Err(e) => warn!("{}", e),
             }
 
@@ -408,10 +579,19 @@ impl Core {
                 let gc_round = round - self.gc_depth;
                 self.last_voted.retain(|k, _| k >= &gc_round);
                 self.processing.retain(|k, _| k >= &gc_round);
+                self.first_headers.retain(|k, _| k >= &gc_round);
                 self.certificates_aggregators.retain(|k, _| k >= &gc_round);
                 self.cancel_handlers.retain(|k, _| k >= &gc_round);
                 self.gc_round = gc_round;
+                self.metrics.gc_round.set(gc_round as i64);
             }
+
+            // The consensus may lag far behind the DAG (e.g. while we catch up): we also drop the headers and
+            // aggregators more than `gc_depth` rounds behind the DAG, which we no longer need to propose.
+            let dag_gc_round = self.dag_round.saturating_sub(self.gc_depth);
+            self.processing.retain(|k, _| k >= &dag_gc_round);
+            self.certificates_aggregators
+                .retain(|k, _| k >= &dag_gc_round);
         }
     }
 }
@@ -0,0 +1,109 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::primary::Round;
+use config::Committee;
+use crypto::PublicKey;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+
+#[cfg(test)]
+#[path = "tests/dag_width_tests.rs"]
+pub mod dag_width_tests;
+
+/// The number of complete rounds over which the width of the DAG is averaged.
+pub const WIDTH_WINDOW: usize = 10;
+
+/// Measures the width of the DAG, ie. the number of distinct authors of the certificates of each round.
+/// A round is complete once we see a certificate two rounds above it: a quorum already built on top of
+/// it, so the certificates that did not make it by then are unlikely to matter.
+pub struct DagWidth {
+    /// The committee information.
+    committee: Committee,
+    /// The authors of the certificates of the rounds that are not complete yet.
+    pending: BTreeMap<Round, HashSet<PublicKey>>,
+    /// The authors of the certificates of the last (at most `WIDTH_WINDOW`) complete rounds.
+    window: VecDeque<(Round, HashSet<PublicKey>)>,
+    /// The last complete round.
+    last_complete: Round,
+    /// The complete round at which we last reported a narrow DAG.
+    last_report: Option<Round>,
+}
+
+impl DagWidth {
+    pub fn new(committee: Committee) -> Self {
+        Self {
+            committee,
+            pending: BTreeMap::new(),
+            window: VecDeque::with_capacity(WIDTH_WINDOW + 1),
+            last_complete: 0,
+            last_report: None,
+        }
+    }
+
+    /// Records a certificate of `origin` at `round`, and returns the rounds it completes (along with
+    /// their width), oldest first.
+    pub fn add(&mut self, origin: PublicKey, round: Round) -> Vec<(Round, usize)> {
+        if round <= self.last_complete {
+            return Vec::new();
+        }
+        self.pending
+            .entry(round)
+            .or_insert_with(HashSet::new)
+            .insert(origin);
+
+        let mut completed = Vec::new();
+        while let Some(r) = self.pending.keys().next().cloned() {
+            if r + 2 > round {
+                break;
+            }
+            let authors = self.pending.remove(&r).unwrap();
+            completed.push((r, authors.len()));
+            self.window.push_back((r, authors));
+            if self.window.len() > WIDTH_WINDOW {
+                self.window.pop_front();
+            }
+            self.last_complete = r;
+        }
+        completed
+    }
+
+    /// The average width of the last complete rounds.
+    pub fn average(&self) -> f64 {
+        if self.window.is_empty() {
+            return 0.0;
+        }
+        let total: usize = self.window.iter().map(|(_, x)| x.len()).sum();
+        total as f64 / self.window.len() as f64
+    }
+
+    /// Checks whether some certificates were missing from every one of the last `WIDTH_WINDOW` complete
+    /// rounds. If so, returns the authorities that missed rounds (along with how many), most absent
+    /// first. A given window is only reported once.
+    pub fn narrow(&mut self) -> Option<Vec<(PublicKey, usize)>> {
+        let size = self.committee.size();
+        if self.window.len() < WIDTH_WINDOW || self.window.iter().any(|(_, x)| x.len() >= size) {
+            return None;
+        }
+        if let Some(r) = self.last_report {
+            if self.last_complete < r + WIDTH_WINDOW as Round {
+                return None;
+            }
+        }
+        self.last_report = Some(self.last_complete);
+
+        let mut absent: Vec<_> = self
+            .committee
+            .authorities
+            .keys()
+            .map(|name| {
+                let missed = self
+                    .window
+                    .iter()
+                    .filter(|(_, x)| !x.contains(name))
+                    .count();
+                (*name, missed)
+            })
+            .filter(|(_, missed)| *missed > 0)
+            .collect();
+        absent.sort_by(|a, b| b.1.cmp(&a.1));
+        Some(absent)
+    }
+}
@@ -54,6 +54,18 @@ pub enum DagError {
     #[error("Parents of header {0} are not a quorum")]
     HeaderRequiresQuorum(Digest),
 
+    #[error("Header {0} of round {1} references parent {2} of round {3}")]
+    InvalidParentRound(Digest, Round, Digest, Round),
+
+    #[error("Invalid equivocation proof")]
+    InvalidEquivocation,
+
     #[error("Message {0} (round {1}) too old")]
     TooOld(Digest, Round),
+
+    #[error("Message {0} (round {1}) too far ahead of the DAG")]
+    TooNew(Digest, Round),
+
+    #[error("Header {0} is timestamped {1} ms in the future")]
+    HeaderFromTheFuture(Digest, u64),
 }
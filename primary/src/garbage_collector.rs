@@ -2,13 +2,16 @@
 use crate::messages::Certificate;
 use crate::primary::PrimaryWorkerMessage;
 use bytes::Bytes;
-use config::Committee;
-use crypto::PublicKey;
+use config::{Committee, WorkerId};
+use crypto::{Digest, PublicKey};
+use log::warn;
 use network::SimpleSender;
+use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::sync::mpsc::Receiver;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::task::JoinHandle;
 
 /// Receives the highest round reached by consensus and update it for all tasks.
 pub struct GarbageCollector {
@@ -17,9 +20,11 @@ pub struct GarbageCollector {
     /// Receives the ordered certificates from consensus.
     rx_consensus: Receiver<Certificate>,
     /// The network addresses of our workers.
-    addresses: Vec<SocketAddr>,
-    /// A network sender to notify our workers of cleanup events.
+    workers: HashMap<WorkerId, SocketAddr>,
+    /// A network sender to notify our workers of cleanup and commit events.
     network: SimpleSender,
+    /// Forwards the ordered certificates to the `Pruner` (if the store is pruned).
+    tx_pruner: Option<Sender<Certificate>>,
 }
 
 impl GarbageCollector {
@@ -28,24 +33,28 @@ impl GarbageCollector {
         committee: &Committee,
         consensus_round: Arc<AtomicU64>,
         rx_consensus: Receiver<Certificate>,
-    ) {
-        let addresses = committee
-            .our_workers(name)
-            .expect("Our public key or worker id is not in the committee")
+        tx_pruner: Option<Sender<Certificate>>,
+    ) -> JoinHandle<()> {
+        let workers = committee
+            .authorities
+            .get(name)
+            .expect("Our public key is not in the committee")
+            .workers
             .iter()
-            .map(|x| x.primary_to_worker)
+            .map(|(id, x)| (*id, x.primary_to_worker))
             .collect();
 
         tokio::spawn(async move {
             Self {
                 consensus_round,
                 rx_consensus,
-                addresses,
+                workers,
                 network: SimpleSender::new(),
+                tx_pruner,
             }
             .run()
             .await;
-        });
+        })
     }
 
     async fn run(&mut self) {
@@ -54,6 +63,13 @@ impl GarbageCollector {
             // TODO [issue #9]: Re-include batch digests that have not been sequenced into our next block.
 
             let round = certificate.round();
+            self.notify_committed(&certificate).await;
+            if let Some(tx_pruner) = &self.tx_pruner {
+                tx_pruner
+                    .send(certificate)
+                    .await
+                    .expect("Failed to send certificate to the pruner");
+            }
             if round > last_committed_round {
                 last_committed_round = round;
 
@@ -63,9 +79,30 @@ impl GarbageCollector {
                 // Trigger cleanup on the workers..
                 let bytes = bincode::serialize(&PrimaryWorkerMessage::Cleanup(round))
                     .expect("Failed to serialize our own message");
-                self.network
-                    .broadcast(self.addresses.clone(), Bytes::from(bytes))
-                    .await;
+                let addresses = self.workers.values().cloned().collect();
+                self.network.broadcast(addresses, Bytes::from(bytes)).await;
+            }
+        }
+    }
+
+    /// Tells each of our workers which of its batches the certificate committed.
+    async fn notify_committed(&mut self, certificate: &Certificate) {
+        let mut batches: HashMap<WorkerId, Vec<Digest>> = HashMap::new();
+        for (digest, worker_id) in &certificate.header.payload {
+            batches
+                .entry(*worker_id)
+                .or_insert_with(Vec::new)
+                .push(digest.clone());
+        }
+        for (worker_id, digests) in batches {
+            match self.workers.get(&worker_id) {
+                Some(address) => {
+                    let message = PrimaryWorkerMessage::Committed(digests);
+                    let bytes =
+                        bincode::serialize(&message).expect("Failed to serialize our own message");
+                    self.network.send(*address, Bytes::from(bytes)).await;
+                }
+                None => warn!("Committed batches of unknown worker {}", worker_id),
             }
         }
     }
@@ -3,21 +3,28 @@ use crate::error::{DagError, DagResult};
 use crate::messages::Header;
 use crate::primary::{PrimaryMessage, PrimaryWorkerMessage, Round};
 use bytes::Bytes;
-use config::{Committee, WorkerId};
+use config::{Committee, Parameters, WorkerId};
 use crypto::{Digest, PublicKey};
 use futures::future::try_join_all;
 use futures::stream::futures_unordered::FuturesUnordered;
 use futures::stream::StreamExt as _;
 use log::{debug, error};
 use network::SimpleSender;
+use std::cmp::max;
 use std::collections::HashMap;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
-use store::Store;
+use store::KeyValueStore;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
 use tokio::time::{sleep, Duration, Instant};
 
+#[cfg(test)]
+#[path = "tests/header_waiter_tests.rs"]
+pub mod header_waiter_tests;
+
 /// The resolution of the timer that checks whether we received replies to our sync requests, and triggers
 /// new sync requests if we didn't.
 const TIMER_RESOLUTION: u64 = 1_000;
@@ -30,13 +37,13 @@ pub enum WaiterMessage {
 }
 
 /// Waits for missing parent certificates and batches' digests.
-pub struct HeaderWaiter {
+pub struct HeaderWaiter<S> {
     /// The name of this authority.
     name: PublicKey,
     /// The committee information.
     committee: Committee,
     /// The persistent storage.
-    store: Store,
+    store: S,
     /// The current consensus round (used for cleanup).
     consensus_round: Arc<AtomicU64>,
     /// The depth of the garbage collector.
@@ -45,11 +52,15 @@ pub struct HeaderWaiter {
     sync_retry_delay: u64,
     /// Determine with how many nodes to sync when re-trying to send sync-request.
     sync_retry_nodes: usize,
+    /// The maximum number of certificates we request at once, and wait for at any time.
+    max_sync_requests: usize,
 
     /// Receives sync commands from the `Synchronizer`.
     rx_synchronizer: Receiver<WaiterMessage>,
     /// Loops back to the core headers for which we got all parents and batches.
     tx_core: Sender<Header>,
+    /// Receives the parameters reloaded by the operator.
+    rx_parameters: watch::Receiver<Parameters>,
 
     /// Network driver allowing to send messages.
     network: SimpleSender,
@@ -62,21 +73,26 @@ pub struct HeaderWaiter {
     /// List of digests (either certificates, headers or tx batch) that are waiting
     /// to be processed. Their processing will resume when we get all their dependencies.
     pending: HashMap<Digest, (Round, Sender<()>)>,
+    /// The highest round of the headers we waited for.
+    last_round: Round,
 }
 
-impl HeaderWaiter {
+impl<S: KeyValueStore> HeaderWaiter<S> {
     #[allow(clippy::too_many_arguments)]
     pub fn spawn(
         name: PublicKey,
         committee: Committee,
-        store: Store,
+        store: S,
         consensus_round: Arc<AtomicU64>,
         gc_depth: Round,
         sync_retry_delay: u64,
         sync_retry_nodes: usize,
+        max_sync_requests: usize,
         rx_synchronizer: Receiver<WaiterMessage>,
         tx_core: Sender<Header>,
-    ) {
+        rx_parameters: watch::Receiver<Parameters>,
+    ) -> JoinHandle<()> {
+        let seed = rx_parameters.borrow().seed;
         tokio::spawn(async move {
             Self {
                 name,
@@ -86,22 +102,33 @@ impl HeaderWaiter {
                 gc_depth,
                 sync_retry_delay,
                 sync_retry_nodes,
+                max_sync_requests,
                 rx_synchronizer,
                 tx_core,
-                network: SimpleSender::new(),
+                rx_parameters,
+                network: SimpleSender::new().with_seed(seed),
                 parent_requests: HashMap::new(),
                 batch_requests: HashMap::new(),
                 pending: HashMap::new(),
+                last_round: 0,
             }
             .run()
             .await;
-        });
+        })
+    }
+
+    /// Helper function. It counts the certificates we requested less than `sync_retry_delay` ago.
+    fn outstanding(&self, now: u128) -> usize {
+        self.parent_requests
+            .values()
+            .filter(|(_, timestamp)| timestamp + (self.sync_retry_delay as u128) >= now)
+            .count()
     }
 
     /// Helper function. It waits for particular data to become available in the storage
     /// and then delivers the specified header.
     async fn waiter(
-        mut missing: Vec<(Vec<u8>, Store)>,
+        mut missing: Vec<(Vec<u8>, S)>,
         deliver: Header,
         mut handler: Receiver<()>,
     ) -> DagResult<Option<Header>> {
@@ -138,6 +165,7 @@ impl HeaderWaiter {
                             if self.pending.contains_key(&header_id) {
                                 continue;
                             }
+                            self.last_round = self.last_round.max(round);
 
                             // Add the header to the waiter pool. The waiter will return it to when all
                             // its parents are in the store.
@@ -183,6 +211,23 @@ impl HeaderWaiter {
                             if self.pending.contains_key(&header_id) {
                                 continue;
                             }
+                            self.last_round = self.last_round.max(round);
+
+                            // Some parents may have been stored since the synchronizer looked for them;
+                            // check them all at once and only wait for (and request) the others.
+                            let keys = missing.iter().map(|x| x.to_vec()).collect();
+                            let missing: Vec<_> = match self.store.read_many(keys).await {
+                                Ok(values) => missing
+                                    .into_iter()
+                                    .zip(values)
+                                    .filter(|(_, x)| x.is_none())
+                                    .map(|(x, _)| x)
+                                    .collect(),
+                                Err(e) => {
+                                    error!("{}", e);
+                                    panic!("Storage failure: killing node.");
+                                }
+                            };
 
                             // Add the header to the waiter pool. The waiter will return it to us
                             // when all its parents are in the store.
@@ -203,12 +248,22 @@ impl HeaderWaiter {
                                 .duration_since(UNIX_EPOCH)
                                 .expect("Failed to measure time")
                                 .as_millis();
+                            // Past `max_sync_requests` outstanding requests, the other parents wait for the
+                            // retry timer (a null timestamp makes them due right away).
+                            let mut outstanding = self.outstanding(now);
                             let mut requires_sync = Vec::new();
                             for missing in missing {
-                                self.parent_requests.entry(missing.clone()).or_insert_with(|| {
-                                    requires_sync.push(missing);
-                                    (round, now)
-                                });
+                                if self.parent_requests.contains_key(&missing) {
+                                    continue;
+                                }
+                                let timestamp = if outstanding < self.max_sync_requests {
+                                    outstanding += 1;
+                                    requires_sync.push(missing.clone());
+                                    now
+                                } else {
+                                    0
+                                };
+                                self.parent_requests.insert(missing, (round, timestamp));
                             }
                             if !requires_sync.is_empty() {
                                 let address = self.committee
@@ -252,33 +307,52 @@ impl HeaderWaiter {
                         .expect("Failed to measure time")
                         .as_millis();
 
+                    // Retry the oldest requests first, and wait `sync_retry_delay` again before the next retry.
+                    let mut due: Vec<_> = self.parent_requests
+                        .iter()
+                        .filter(|(_, (_, timestamp))| timestamp + (self.sync_retry_delay as u128) < now)
+                        .map(|(digest, (_, timestamp))| (*timestamp, digest.clone()))
+                        .collect();
+                    due.sort_by_key(|(timestamp, _)| *timestamp);
+                    due.truncate(self.max_sync_requests.saturating_sub(self.outstanding(now)));
                     let mut retry = Vec::new();
-                    for (digest, (_, timestamp)) in &self.parent_requests {
-                        if timestamp + (self.sync_retry_delay as u128) < now {
-                            debug!("Requesting sync for certificate {} (retry)", digest);
-                            retry.push(digest.clone());
+                    for (_, digest) in due {
+                        debug!("Requesting sync for certificate {} (retry)", digest);
+                        if let Some((_, timestamp)) = self.parent_requests.get_mut(&digest) {
+                            *timestamp = now;
                         }
+                        retry.push(digest);
                     }
 
-                    let addresses = self.committee
-                        .others_primaries(&self.name)
-                        .iter()
-                        .map(|(_, x)| x.primary_to_primary)
-                        .collect();
-                    let message = PrimaryMessage::CertificatesRequest(retry, self.name);
-                    let bytes = bincode::serialize(&message).expect("Failed to serialize cert request");
-                    self.network.lucky_broadcast(addresses, Bytes::from(bytes), self.sync_retry_nodes).await;
+                    if !retry.is_empty() {
+                        let addresses = self.committee
+                            .others_primaries(&self.name)
+                            .iter()
+                            .map(|(_, x)| x.primary_to_primary)
+                            .collect();
+                        let message = PrimaryMessage::CertificatesRequest(retry, self.name);
+                        let bytes = bincode::serialize(&message).expect("Failed to serialize cert request");
+                        self.network.lucky_broadcast(addresses, Bytes::from(bytes), self.sync_retry_nodes).await;
+                    }
 
                     // Reschedule the timer.
                     timer.as_mut().reset(Instant::now() + Duration::from_millis(TIMER_RESOLUTION));
+                },
+
+                Ok(()) = self.rx_parameters.changed() => {
+                    self.sync_retry_delay = self.rx_parameters.borrow().sync_retry_delay;
+                    debug!("Sync retry delay updated to {} ms", self.sync_retry_delay);
                 }
             }
 
-            // Cleanup internal state.
+            // Cleanup internal state. The consensus may lag far behind the headers we wait for (e.g. while we
+            // catch up), so we also drop the headers more than `2 * gc_depth` rounds behind the last one.
             let round = self.consensus_round.load(Ordering::Relaxed);
-            if round > self.gc_depth {
-                let mut gc_round = round - self.gc_depth;
-
+            let mut gc_round = max(
+                round.saturating_sub(self.gc_depth),
+                self.last_round.saturating_sub(2 * self.gc_depth),
+            );
+            if gc_round > 0 {
                 for (r, handler) in self.pending.values() {
                     if r <= &gc_round {
                         let _ = handler.send(()).await;
@@ -0,0 +1,110 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::primary::Round;
+use config::{Committee, Stake};
+use crypto::PublicKey;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[cfg(test)]
+#[path = "tests/health_tests.rs"]
+pub mod health_tests;
+
+/// A change in the suspicion of an authority, along with the last round at which we processed one of its
+/// certificates (0 if we never did).
+#[derive(Debug, PartialEq)]
+pub enum SuspectEvent {
+    /// The authority did not contribute to the DAG for too many rounds.
+    Suspected(PublicKey, Round),
+    /// A suspected authority contributes to the DAG again.
+    Cleared(PublicKey, Round),
+}
+
+#[derive(Default)]
+struct HealthState {
+    /// The last time and the highest round at which we processed a certificate of each authority.
+    last_seen: HashMap<PublicKey, (Instant, Round)>,
+    /// The highest round of the certificates we processed.
+    round: Round,
+    /// The suspected authorities, with the last round at which we saw them.
+    suspects: HashMap<PublicKey, Round>,
+}
+
+/// Keeps track of the last time (and round) the `Core` processed a certificate from each authority. It is
+/// shared with the admin endpoints to report whether the primary is ready (ie. connected to a quorum) and
+/// which authorities are suspected faulty.
+#[derive(Clone, Default)]
+pub struct PeersHealth {
+    state: Arc<Mutex<HealthState>>,
+}
+
+impl PeersHealth {
+    /// Record that we just processed a certificate created by `origin` at `round`.
+    pub fn certificate_received(&self, origin: PublicKey, round: Round) {
+        let mut state = self.state.lock().unwrap();
+        let last = state.last_seen.get(&origin).map_or(0, |(_, r)| *r);
+        state
+            .last_seen
+            .insert(origin, (Instant::now(), last.max(round)));
+        state.round = state.round.max(round);
+    }
+
+    /// Suspects the authorities from which we processed no certificate over the last `max_absent_rounds`
+    /// rounds (of the highest certificate we processed), and clears the suspicion of those that reappeared.
+    /// Returns the changes since the last call.
+    pub fn update_suspects(
+        &self,
+        committee: &Committee,
+        max_absent_rounds: Round,
+    ) -> Vec<SuspectEvent> {
+        let mut state = self.state.lock().unwrap();
+        let mut events = Vec::new();
+        for name in committee.authorities.keys() {
+            let last = state.last_seen.get(name).map_or(0, |(_, r)| *r);
+            let absent = state.round > last.saturating_add(max_absent_rounds);
+            match (absent, state.suspects.contains_key(name)) {
+                (true, false) => {
+                    state.suspects.insert(*name, last);
+                    events.push(SuspectEvent::Suspected(*name, last));
+                }
+                (false, true) => {
+                    state.suspects.remove(name);
+                    events.push(SuspectEvent::Cleared(*name, last));
+                }
+                _ => (),
+            }
+        }
+        events
+    }
+
+    /// Returns the suspected authorities, with the last round at which we processed their certificates.
+    pub fn suspects(&self) -> Vec<(PublicKey, Round)> {
+        let mut suspects: Vec<_> = self
+            .state
+            .lock()
+            .unwrap()
+            .suspects
+            .iter()
+            .map(|(name, round)| (*name, *round))
+            .collect();
+        suspects.sort();
+        suspects
+    }
+
+    /// Returns the stake of the authorities from which we processed a certificate within `window`.
+    pub fn active_stake(&self, committee: &Committee, window: Duration) -> Stake {
+        self.state
+            .lock()
+            .unwrap()
+            .last_seen
+            .iter()
+            .filter(|(_, (time, _))| time.elapsed() <= window)
+            .map(|(name, _)| committee.stake(name))
+            .sum()
+    }
+
+    /// Returns true if we processed certificates from a quorum of authorities (2f+1) within `window`.
+    pub fn is_ready(&self, committee: &Committee, window: Duration) -> bool {
+        self.active_stake(committee, window) >= committee.quorum_threshold()
+    }
+}
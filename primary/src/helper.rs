@@ -1,71 +1,160 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
-use crate::primary::PrimaryMessage;
+use crate::messages::SyncStatus;
+use crate::primary::{PrimaryMessage, Round};
+use crate::state_sync::{RoundIndex, MAX_SYNC_ROUNDS};
 use bytes::Bytes;
 use config::Committee;
-use crypto::{Digest, PublicKey};
+use crypto::{Digest, PublicKey, SignatureService};
 use log::{error, warn};
 use network::SimpleSender;
-use store::Store;
+use store::KeyValueStore;
 use tokio::sync::mpsc::Receiver;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
 
-/// A task dedicated to help other authorities by replying to their certificates requests.
-pub struct Helper {
+/// A task dedicated to help other authorities by replying to their certificates and state sync requests,
+/// and the observers by replying to their state sync requests.
+pub struct Helper<S> {
+    /// The public key of this primary.
+    name: PublicKey,
     /// The committee information.
     committee: Committee,
     /// The persistent storage.
-    store: Store,
+    store: S,
+    /// Service to sign our state sync replies.
+    signature_service: SignatureService,
+    /// The round index of our store.
+    round_index: RoundIndex<S>,
     /// Input channel to receive certificates requests.
     rx_primaries: Receiver<(Vec<Digest>, PublicKey)>,
+    /// Input channel to receive state sync requests.
+    rx_sync_requests: Receiver<(Round, PublicKey)>,
+    /// Input channel to receive the state sync requests of observers, along with where to reply.
+    rx_observer_requests: Receiver<(Round, oneshot::Sender<PrimaryMessage>)>,
     /// A network sender to reply to the sync requests.
     network: SimpleSender,
 }
 
-impl Helper {
+impl<S: KeyValueStore> Helper<S> {
     pub fn spawn(
+        name: PublicKey,
         committee: Committee,
-        store: Store,
+        store: S,
+        signature_service: SignatureService,
         rx_primaries: Receiver<(Vec<Digest>, PublicKey)>,
-    ) {
+        rx_sync_requests: Receiver<(Round, PublicKey)>,
+        rx_observer_requests: Receiver<(Round, oneshot::Sender<PrimaryMessage>)>,
+    ) -> JoinHandle<()> {
         tokio::spawn(async move {
             Self {
+                name,
+                round_index: RoundIndex::new(store.clone(), &committee),
                 committee,
                 store,
+                signature_service,
                 rx_primaries,
+                rx_sync_requests,
+                rx_observer_requests,
                 network: SimpleSender::new(),
             }
             .run()
             .await;
-        });
+        })
     }
 
     async fn run(&mut self) {
-        while let Some((digests, origin)) = self.rx_primaries.recv().await {
-            // TODO [issue #195]: Do some accounting to prevent bad nodes from monopolizing our resources.
+        loop {
+            tokio::select! {
+                Some((digests, origin)) = self.rx_primaries.recv() => self.reply_certificates(digests, origin).await,
+                Some((round, origin)) = self.rx_sync_requests.recv() => self.reply_state_sync(round, origin).await,
+                Some((round, reply)) = self.rx_observer_requests.recv() => {
+                    if let Some(message) = self.state_sync_reply(round, None).await {
+                        let _ = reply.send(message);
+                    }
+                },
+                else => break,
+            }
+        }
+    }
 
-            // get the requestors address.
-            let address = match self.committee.primary(&origin) {
-                Ok(x) => x.primary_to_primary,
-                Err(e) => {
-                    warn!("Unexpected certificate request: {}", e);
-                    continue;
+    async fn reply_certificates(&mut self, digests: Vec<Digest>, origin: PublicKey) {
+        // TODO [issue #195]: Do some accounting to prevent bad nodes from monopolizing our resources.
+
+        // get the requestors address.
+        let address = match self.committee.primary(&origin) {
+            Ok(x) => x.primary_to_primary,
+            Err(e) => {
+                warn!("Unexpected certificate request: {}", e);
+                return;
+            }
+        };
+
+        // Reply to the request (the best we can).
+        for digest in digests {
+            match self.store.read(digest.to_vec()).await {
+                Ok(Some(data)) => {
+                    // TODO: Remove this deserialization-serialization in the critical path.
+                    let certificate = bincode::deserialize(&data)
+                        .expect("Failed to deserialize our own certificate");
+                    let bytes = bincode::serialize(&PrimaryMessage::Certificate(certificate))
+                        .expect("Failed to serialize our own certificate");
+                    self.network.send(address, Bytes::from(bytes)).await;
                 }
-            };
+                Ok(None) => (),
+                Err(e) => error!("{}", e),
+            }
+        }
+    }
 
-            // Reply to the request (the best we can).
-            for digest in digests {
-                match self.store.read(digest.to_vec()).await {
-                    Ok(Some(data)) => {
-                        // TODO: Remove this deserialization-serialization in the critical path.
-                        let certificate = bincode::deserialize(&data)
-                            .expect("Failed to deserialize our own certificate");
-                        let bytes = bincode::serialize(&PrimaryMessage::Certificate(certificate))
-                            .expect("Failed to serialize our own certificate");
-                        self.network.send(address, Bytes::from(bytes)).await;
-                    }
-                    Ok(None) => (),
-                    Err(e) => error!("{}", e),
+    /// Builds the reply to a state sync request of `requestor` (`None` for observers): the certificates of (at
+    /// most `MAX_SYNC_ROUNDS`) rounds following `round`, along with our (signed) last round.
+    async fn state_sync_reply(
+        &mut self,
+        round: Round,
+        requestor: Option<PublicKey>,
+    ) -> Option<PrimaryMessage> {
+        let last_round = match self.round_index.last_round().await {
+            Ok(x) => x,
+            Err(e) => {
+                error!("{}", e);
+                return None;
+            }
+        };
+        let mut certificates = Vec::new();
+        for r in round + 1..=last_round.min(round + MAX_SYNC_ROUNDS) {
+            match self.round_index.certificates(r).await {
+                Ok(x) => certificates.extend(x),
+                Err(e) => {
+                    error!("{}", e);
+                    return None;
                 }
             }
         }
+        let status = SyncStatus::new(
+            round,
+            last_round,
+            requestor,
+            &self.name,
+            &mut self.signature_service,
+        )
+        .await;
+        Some(PrimaryMessage::StateSyncReply(certificates, status))
+    }
+
+    /// Sends the reply to the state sync request of another primary.
+    async fn reply_state_sync(&mut self, round: Round, origin: PublicKey) {
+        let address = match self.committee.primary(&origin) {
+            Ok(x) => x.primary_to_primary,
+            Err(e) => {
+                warn!("Unexpected state sync request: {}", e);
+                return;
+            }
+        };
+        let message = match self.state_sync_reply(round, Some(origin)).await {
+            Some(x) => x,
+            None => return,
+        };
+        let bytes = bincode::serialize(&message).expect("Failed to serialize our state sync reply");
+        self.network.send(address, Bytes::from(bytes)).await;
     }
 }
@@ -4,18 +4,32 @@ mod error;
 mod aggregators;
 mod certificate_waiter;
 mod core;
+mod dag_width;
 mod garbage_collector;
 mod header_waiter;
+mod health;
 mod helper;
+mod lru;
 mod messages;
+mod metrics;
+mod observer;
 mod payload_receiver;
 mod primary;
 mod proposer;
+mod pruner;
+mod state_sync;
 mod synchronizer;
+mod verifier;
 
 #[cfg(test)]
 #[path = "tests/common.rs"]
 mod common;
 
-pub use crate::messages::{Certificate, Header};
-pub use crate::primary::{Primary, PrimaryWorkerMessage, Round, WorkerPrimaryMessage};
+pub use crate::health::{PeersHealth, SuspectEvent};
+pub use crate::messages::{Certificate, Equivocation, Header};
+pub use crate::metrics::PrimaryMetrics;
+pub use crate::observer::Observer;
+pub use crate::primary::{
+    Primary, PrimaryMessage, PrimaryWorkerMessage, Round, WorkerPrimaryMessage,
+};
+pub use crate::state_sync::RoundIndex;
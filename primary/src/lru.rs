@@ -0,0 +1,71 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+#[cfg(test)]
+#[path = "tests/lru_tests.rs"]
+pub mod lru_tests;
+
+/// A map holding at most `capacity` entries: inserting in a full map evicts the least recently used entry.
+/// It only caches data that is also persisted, so evicted entries can be read again from the store.
+pub struct LruCache<K, V> {
+    /// The maximum number of entries (0 disables the cache).
+    capacity: usize,
+    /// The entries, along with the time at which they were last used.
+    entries: HashMap<K, (V, u64)>,
+    /// The keys of the entries, by time of last use.
+    order: BTreeMap<u64, K>,
+    /// Increments with every use of the cache.
+    clock: u64,
+}
+
+impl<K: Eq + Hash + Clone, V> LruCache<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            clock: 0,
+        }
+    }
+
+    /// Returns the value of `key` (if cached), and marks it as the most recently used.
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        self.clock += 1;
+        let (value, used) = self.entries.get_mut(key)?;
+        self.order.remove(&*used);
+        *used = self.clock;
+        self.order.insert(self.clock, key.clone());
+        Some(&*value)
+    }
+
+    /// Caches `value` under `key`, evicting the least recently used entry if the cache is full.
+    pub fn insert(&mut self, key: K, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.clock += 1;
+        if let Some((_, used)) = self.entries.insert(key.clone(), (value, self.clock)) {
+            self.order.remove(&used);
+        }
+        self.order.insert(self.clock, key);
+        while self.entries.len() > self.capacity {
+            let oldest = *self.order.keys().next().unwrap();
+            let key = self.order.remove(&oldest).unwrap();
+            self.entries.remove(&key);
+        }
+    }
+
+    /// Returns true if `key` is cached (without marking it as used).
+    pub fn contains(&self, key: &K) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
@@ -1,14 +1,16 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use crate::error::{DagError, DagResult};
 use crate::primary::Round;
-use config::{Committee, WorkerId};
-use crypto::{Digest, Hash, PublicKey, Signature, SignatureService};
-use ed25519_dalek::Digest as _;
-use ed25519_dalek::Sha512;
+use config::{Committee, Epoch, Stake, WorkerId};
+use crypto::{Digest, Hash, HashFunction as _, Hasher, PublicKey, Signature, SignatureService};
 use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, BTreeSet, HashSet};
-use std::convert::TryInto;
 use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(test)]
+#[path = "tests/messages_tests.rs"]
+pub mod messages_tests;
 
 #[derive(Clone, Serialize, Deserialize, Default)]
 pub struct Header {
@@ -16,6 +18,8 @@ pub struct Header {
     pub round: Round,
     pub payload: BTreeMap<Digest, WorkerId>,
     pub parents: BTreeSet<Digest>,
+    /// When the author created the header, according to its own clock (in ms since the Unix epoch).
+    pub timestamp: u64,
     pub id: Digest,
     pub signature: Signature,
 }
@@ -33,6 +37,10 @@ impl Header {
             round,
             payload,
             parents,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|x| x.as_millis() as u64)
+                .unwrap_or_default(),
             id: Digest::default(),
             signature: Signature::default(),
         };
@@ -45,7 +53,10 @@ impl Header {
         }
     }
 
-    pub fn verify(&self, committee: &Committee) -> DagResult<()> {
+    /// Checks the header against the committee only (it does no I/O, so it can be fuzzed in isolation).
+    /// Whether the parents actually form a quorum is checked by the core once it has them. The signature
+    /// is checked with the keys the author may sign with in the signing `epoch`.
+    pub fn verify(&self, committee: &Committee, epoch: Epoch) -> DagResult<()> {
         // Ensure the header id is well formed.
         ensure!(self.digest() == self.id, DagError::InvalidHeaderId);
 
@@ -53,6 +64,16 @@ impl Header {
         let voting_rights = committee.stake(&self.author);
         ensure!(voting_rights > 0, DagError::UnknownAuthority(self.author));
 
+        // Ensure there are enough parents to possibly form a quorum, and at most one per authority.
+        ensure!(
+            self.parents.len() >= min_quorum_size(committee),
+            DagError::HeaderRequiresQuorum(self.id.clone())
+        );
+        ensure!(
+            self.parents.len() <= committee.size(),
+            DagError::MalformedHeader(self.id.clone())
+        );
+
         // Ensure all worker ids are correct.
         for worker_id in self.payload.values() {
             committee
@@ -61,15 +82,82 @@ impl Header {
         }
 
         // Check the signature.
-        self.signature
-            .verify(&self.id, &self.author)
-            .map_err(DagError::from)
+        verify_signature(&self.signature, &self.id, &self.author, committee, epoch)
     }
+
+    /// Checks that the header is not timestamped more than `max_drift` ms after `now` (in ms since the Unix
+    /// epoch). Like `verify`, it does not read the clock itself.
+    pub fn verify_timestamp(&self, now: u64, max_drift: u64) -> DagResult<()> {
+        let drift = self.timestamp.saturating_sub(now);
+        ensure!(
+            drift <= max_drift,
+            DagError::HeaderFromTheFuture(self.id.clone(), drift)
+        );
+        Ok(())
+    }
+
+    /// Checks the parent certificates of the header (once we have them): they must all be from the
+    /// previous round, from distinct authorities, and together hold a quorum of stake.
+    pub fn verify_parents(&self, parents: &[Certificate], committee: &Committee) -> DagResult<()> {
+        let mut stake: Stake = 0;
+        let mut used = HashSet::new();
+        for x in parents {
+            ensure!(
+                self.round.checked_sub(1) == Some(x.round()),
+                DagError::InvalidParentRound(self.id.clone(), self.round, x.digest(), x.round())
+            );
+            ensure!(
+                used.insert(x.origin()),
+                DagError::AuthorityReuse(x.origin())
+            );
+            stake = stake.saturating_add(committee.stake(&x.origin()));
+        }
+        ensure!(
+            stake >= committee.quorum_threshold(),
+            DagError::HeaderRequiresQuorum(self.id.clone())
+        );
+        Ok(())
+    }
+}
+
+/// Checks a signature of the authority `name` over `digest`, with any of the keys it may sign with in
+/// `epoch` (there are two while it rotates its key).
+fn verify_signature(
+    signature: &Signature,
+    digest: &Digest,
+    name: &PublicKey,
+    committee: &Committee,
+    epoch: Epoch,
+) -> DagResult<()> {
+    let mut result = Err(DagError::UnknownAuthority(*name));
+    for key in committee.signing_keys(name, epoch) {
+        result = signature.verify(digest, &key).map_err(DagError::from);
+        if result.is_ok() {
+            break;
+        }
+    }
+    result
+}
+
+/// The smallest number of authorities whose stake reaches the quorum threshold.
+fn min_quorum_size(committee: &Committee) -> usize {
+    let mut stakes: Vec<_> = committee.authorities.values().map(|x| x.stake).collect();
+    stakes.sort_unstable_by(|a, b| b.cmp(a));
+    let threshold = committee.quorum_threshold();
+    stakes
+        .iter()
+        .scan(0, |weight, x| {
+            *weight = x.saturating_add(*weight);
+            Some(*weight)
+        })
+        .take_while(|weight| *weight < threshold)
+        .count()
+        + 1
 }
 
 impl Hash for Header {
     fn digest(&self) -> Digest {
-        let mut hasher = Sha512::new();
+        let mut hasher = Hasher::default();
         hasher.update(&self.author);
         hasher.update(self.round.to_le_bytes());
         for (x, y) in &self.payload {
@@ -79,7 +167,8 @@ impl Hash for Header {
         for x in &self.parents {
             hasher.update(x);
         }
-        Digest(hasher.finalize().as_slice()[..32].try_into().unwrap())
+        hasher.update(self.timestamp.to_le_bytes());
+        hasher.finalize()
     }
 }
 
@@ -128,7 +217,7 @@ impl Vote {
         Self { signature, ..vote }
     }
 
-    pub fn verify(&self, committee: &Committee) -> DagResult<()> {
+    pub fn verify(&self, committee: &Committee, epoch: Epoch) -> DagResult<()> {
         // Ensure the authority has voting rights.
         ensure!(
             committee.stake(&self.author) > 0,
@@ -136,19 +225,23 @@ impl Vote {
         );
 
         // Check the signature.
-        self.signature
-            .verify(&self.digest(), &self.author)
-            .map_err(DagError::from)
+        verify_signature(
+            &self.signature,
+            &self.digest(),
+            &self.author,
+            committee,
+            epoch,
+        )
     }
 }
 
 impl Hash for Vote {
     fn digest(&self) -> Digest {
-        let mut hasher = Sha512::new();
+        let mut hasher = Hasher::default();
         hasher.update(&self.id);
         hasher.update(self.round.to_le_bytes());
         hasher.update(&self.origin);
-        Digest(hasher.finalize().as_slice()[..32].try_into().unwrap())
+        hasher.finalize()
     }
 }
 
@@ -186,14 +279,16 @@ impl Certificate {
             .collect()
     }
 
-    pub fn verify(&self, committee: &Committee) -> DagResult<()> {
+    /// Checks the embedded header, the quorum of votes, and their signatures. Like `Header::verify`, it
+    /// only depends on the committee and can be called on crafted inputs.
+    pub fn verify(&self, committee: &Committee, epoch: Epoch) -> DagResult<()> {
         // Genesis certificates are always valid.
         if Self::genesis(committee).contains(self) {
             return Ok(());
         }
 
         // Check the embedded header.
-        self.header.verify(committee)?;
+        self.header.verify(committee, epoch)?;
 
         // Ensure the certificate has a quorum.
         let mut weight = 0;
@@ -210,8 +305,26 @@ impl Certificate {
             DagError::CertificateRequiresQuorum
         );
 
-        // Check the signatures.
-        Signature::verify_batch(&self.digest(), &self.votes).map_err(DagError::from)
+        // Check the signatures, all at once unless some voters are rotating their key (we then do not
+        // know which of their keys they signed with).
+        let digest = self.digest();
+        let keys: Vec<_> = self
+            .votes
+            .iter()
+            .map(|(name, _)| committee.signing_keys(name, epoch))
+            .collect();
+        if keys.iter().all(|x| x.len() == 1) {
+            let votes: Vec<_> = keys
+                .iter()
+                .zip(&self.votes)
+                .map(|(key, (_, signature))| (key[0], signature.clone()))
+                .collect();
+            return Signature::verify_batch(&digest, &votes).map_err(DagError::from);
+        }
+        for (name, signature) in &self.votes {
+            verify_signature(signature, &digest, name, committee, epoch)?;
+        }
+        Ok(())
     }
 
     pub fn round(&self) -> Round {
@@ -225,11 +338,11 @@ impl Certificate {
 
 impl Hash for Certificate {
     fn digest(&self) -> Digest {
-        let mut hasher = Sha512::new();
+        let mut hasher = Hasher::default();
         hasher.update(&self.header.id);
         hasher.update(self.round().to_le_bytes());
         hasher.update(&self.origin());
-        Digest(hasher.finalize().as_slice()[..32].try_into().unwrap())
+        hasher.finalize()
     }
 }
 
@@ -254,3 +367,143 @@ impl PartialEq for Certificate {
         ret
     }
 }
+
+/// The proof that an author signed two different headers for the same round.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Equivocation {
+    /// The first header we received.
+    pub first: Header,
+    /// The conflicting header.
+    pub second: Header,
+}
+
+impl Equivocation {
+    pub fn verify(&self, committee: &Committee, epoch: Epoch) -> DagResult<()> {
+        // Ensure the headers conflict.
+        ensure!(
+            self.first.author == self.second.author
+                && self.first.round == self.second.round
+                && self.first.id != self.second.id,
+            DagError::InvalidEquivocation
+        );
+
+        // Ensure the author signed both of them.
+        self.first.verify(committee, epoch)?;
+        self.second.verify(committee, epoch)
+    }
+
+    pub fn author(&self) -> PublicKey {
+        self.first.author
+    }
+
+    pub fn round(&self) -> Round {
+        self.first.round
+    }
+}
+
+impl Hash for Equivocation {
+    fn digest(&self) -> Digest {
+        let mut hasher = Hasher::default();
+        hasher.update(&self.first.id);
+        hasher.update(&self.second.id);
+        hasher.finalize()
+    }
+}
+
+impl fmt::Debug for Equivocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(
+            f,
+            "{}: E{}({}, {}, {})",
+            self.digest(),
+            self.round(),
+            self.author(),
+            self.first.id,
+            self.second.id
+        )
+    }
+}
+
+/// The last round of a primary, signed in reply to a state sync request. The signature covers the requestor
+/// so that the status can neither be forged nor replayed to other primaries.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SyncStatus {
+    /// The round following which the certificates were requested.
+    pub round: Round,
+    /// The last round of the author.
+    pub last_round: Round,
+    /// The primary that sent the request (`None` for observers).
+    pub requestor: Option<PublicKey>,
+    pub author: PublicKey,
+    pub signature: Signature,
+}
+
+impl SyncStatus {
+    pub async fn new(
+        round: Round,
+        last_round: Round,
+        requestor: Option<PublicKey>,
+        author: &PublicKey,
+        signature_service: &mut SignatureService,
+    ) -> Self {
+        let status = Self {
+            round,
+            last_round,
+            requestor,
+            author: *author,
+            signature: Signature::default(),
+        };
+        let signature = signature_service.request_signature(status.digest()).await;
+        Self {
+            signature,
+            ..status
+        }
+    }
+
+    pub fn verify(&self, committee: &Committee, epoch: Epoch) -> DagResult<()> {
+        // Ensure the authority has voting rights.
+        ensure!(
+            committee.stake(&self.author) > 0,
+            DagError::UnknownAuthority(self.author)
+        );
+
+        // Check the signature.
+        verify_signature(
+            &self.signature,
+            &self.digest(),
+            &self.author,
+            committee,
+            epoch,
+        )
+    }
+}
+
+impl Hash for SyncStatus {
+    fn digest(&self) -> Digest {
+        let mut hasher = Hasher::default();
+        hasher.update(self.round.to_le_bytes());
+        hasher.update(self.last_round.to_le_bytes());
+        match &self.requestor {
+            Some(requestor) => {
+                hasher.update([1u8]);
+                hasher.update(requestor);
+            }
+            None => hasher.update([0u8]),
+        }
+        hasher.update(&self.author);
+        hasher.finalize()
+    }
+}
+
+impl fmt::Debug for SyncStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(
+            f,
+            "{}: S{}({}, last round {})",
+            self.digest(),
+            self.round,
+            self.author,
+            self.last_round
+        )
+    }
+}
@@ -0,0 +1,139 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use prometheus::{Gauge, Histogram, HistogramOpts, IntCounter, IntGauge, Registry};
+
+/// The metrics exported by the primary.
+#[derive(Clone)]
+pub struct PrimaryMetrics {
+    /// The number of headers we proposed.
+    pub headers_proposed: IntCounter,
+    /// The number of votes we received for our headers (including our own votes).
+    pub votes_received: IntCounter,
+    /// The number of certificates we assembled from the votes on our headers.
+    pub certificates_assembled: IntCounter,
+    /// The time from the proposal of our headers to the quorum of their votes (in seconds).
+    pub vote_collection_latency: Histogram,
+    /// The round of the last header we proposed.
+    pub current_round: IntGauge,
+    /// The last garbage collected round.
+    pub gc_round: IntGauge,
+    /// The number of equivocations we detected.
+    pub equivocations: IntCounter,
+    /// The last round loaded by the state sync.
+    pub state_sync_round: IntGauge,
+    /// The round the state sync is catching up to (the round reached by a quorum).
+    pub state_sync_target_round: IntGauge,
+    /// The number of distinct authors of the certificates of the last complete round.
+    pub dag_width: IntGauge,
+    /// The average width of the DAG over the last complete rounds.
+    pub dag_width_average: Gauge,
+    /// The number of authorities suspected faulty (missing from the DAG for too many rounds).
+    pub suspected_authorities: IntGauge,
+}
+
+impl PrimaryMetrics {
+    /// Creates the metrics of the primary and registers them with `registry`.
+    pub fn new(registry: &Registry) -> Self {
+        let metrics = Self {
+            headers_proposed: IntCounter::new(
+                "primary_headers_proposed",
+                "Number of headers proposed by this primary",
+            )
+            .unwrap(),
+            votes_received: IntCounter::new(
+                "primary_votes_received",
+                "Number of votes received for the headers of this primary",
+            )
+            .unwrap(),
+            certificates_assembled: IntCounter::new(
+                "primary_certificates_assembled",
+                "Number of certificates assembled by this primary",
+            )
+            .unwrap(),
+            vote_collection_latency: Histogram::with_opts(HistogramOpts::new(
+                "primary_vote_collection_latency_seconds",
+                "Time from the proposal of a header of this primary to the quorum of its votes",
+            ))
+            .unwrap(),
+            current_round: IntGauge::new(
+                "primary_current_round",
+                "Round of the last header proposed by this primary",
+            )
+            .unwrap(),
+            gc_round: IntGauge::new("primary_gc_round", "Last garbage collected round").unwrap(),
+            equivocations: IntCounter::new(
+                "primary_equivocations",
+                "Number of headers conflicting with a header of the same author and round",
+            )
+            .unwrap(),
+            state_sync_round: IntGauge::new(
+                "primary_state_sync_round",
+                "Last round loaded by the state sync upon boot",
+            )
+            .unwrap(),
+            state_sync_target_round: IntGauge::new(
+                "primary_state_sync_target_round",
+                "Round reached by a quorum, that the state sync catches up to",
+            )
+            .unwrap(),
+            dag_width: IntGauge::new(
+                "primary_dag_width",
+                "Number of distinct authors of the certificates of the last complete round",
+            )
+            .unwrap(),
+            dag_width_average: Gauge::new(
+                "primary_dag_width_average",
+                "Average number of distinct authors of the certificates of the last complete rounds",
+            )
+            .unwrap(),
+            suspected_authorities: IntGauge::new(
+                "primary_suspected_authorities",
+                "Number of authorities without certificates over the last rounds",
+            )
+            .unwrap(),
+        };
+        registry
+            .register(Box::new(metrics.headers_proposed.clone()))
+            .expect("Failed to register the primary metrics");
+        registry
+            .register(Box::new(metrics.votes_received.clone()))
+            .expect("Failed to register the primary metrics");
+        registry
+            .register(Box::new(metrics.certificates_assembled.clone()))
+            .expect("Failed to register the primary metrics");
+        registry
+            .register(Box::new(metrics.vote_collection_latency.clone()))
+            .expect("Failed to register the primary metrics");
+        registry
+            .register(Box::new(metrics.current_round.clone()))
+            .expect("Failed to register the primary metrics");
+        registry
+            .register(Box::new(metrics.gc_round.clone()))
+            .expect("Failed to register the primary metrics");
+        registry
+            .register(Box::new(metrics.equivocations.clone()))
+            .expect("Failed to register the primary metrics");
+        registry
+            .register(Box::new(metrics.state_sync_round.clone()))
+            .expect("Failed to register the primary metrics");
+        registry
+            .register(Box::new(metrics.state_sync_target_round.clone()))
+            .expect("Failed to register the primary metrics");
+        registry
+            .register(Box::new(metrics.dag_width.clone()))
+            .expect("Failed to register the primary metrics");
+        registry
+            .register(Box::new(metrics.dag_width_average.clone()))
+            .expect("Failed to register the primary metrics");
+        registry
+            .register(Box::new(metrics.suspected_authorities.clone()))
+            .expect("Failed to register the primary metrics");
+        metrics
+    }
+}
+
+impl Default for PrimaryMetrics {
+    /// Creates metrics that are not exported (useful for tests).
+    fn default() -> Self {
+        Self::new(&Registry::new())
+    }
+}
@@ -0,0 +1,257 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::messages::Certificate;
+use crate::primary::{PrimaryMessage, Round};
+use crate::state_sync::RoundIndex;
+use bytes::Bytes;
+use config::{Committee, Parameters};
+use crypto::Hash as _;
+use futures::sink::SinkExt as _;
+use futures::stream::StreamExt as _;
+use log::{debug, info, warn};
+use std::collections::BTreeMap;
+use std::io;
+use std::net::SocketAddr;
+use store::{KeyValueStore, StoreResult};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, timeout, Duration};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+#[cfg(test)]
+#[path = "tests/observer_tests.rs"]
+pub mod observer_tests;
+
+/// Follows the DAG of the committee without being part of it: it repeatedly asks the primaries for the
+/// certificates following the last round it holds, and feeds them to the consensus (each one after its
+/// parents) so that the order of the committed certificates is derived locally. It needs no keypair:
+/// the certificates are only accepted with the votes of a quorum, and it never proposes nor votes.
+pub struct Observer<S> {
+    /// The committee information.
+    committee: Committee,
+    /// The persistent storage.
+    store: S,
+    /// The round index of our store.
+    round_index: RoundIndex<S>,
+    /// The primaries to sync from, tried in turn.
+    peers: Vec<SocketAddr>,
+    /// The delay to wait before asking for new certificates, once we caught up.
+    poll_delay: Duration,
+    /// The delay after which we give up on a primary and ask the next one.
+    timeout: Duration,
+    /// The depth of the garbage collector: we stop waiting for the parents of older certificates.
+    gc_depth: Round,
+    /// Tells the signing epoch, which selects the keys of the authorities.
+    rx_parameters: watch::Receiver<Parameters>,
+    /// Outputs the certificates to the consensus.
+    tx_consensus: Sender<Certificate>,
+    /// The valid certificates waiting for their parents, by round.
+    pending: BTreeMap<Round, Vec<Certificate>>,
+    /// The highest round of the certificates delivered to the consensus.
+    last_delivered: Round,
+    /// The round of the waiting certificates whose parents we last asked for.
+    last_missing: Option<Round>,
+    /// The connection to the primary we sync from (if any).
+    connection: Option<Framed<TcpStream, LengthDelimitedCodec>>,
+    /// The index in `peers` of the primary we sync from.
+    current: usize,
+}
+
+impl<S: KeyValueStore> Observer<S> {
+    pub fn spawn(
+        committee: Committee,
+        store: S,
+        parameters: &Parameters,
+        rx_parameters: watch::Receiver<Parameters>,
+        tx_consensus: Sender<Certificate>,
+        mut rx_consensus: Receiver<Certificate>,
+    ) -> JoinHandle<()> {
+        // There is no primary to clean up: drop the feedback of the consensus.
+        tokio::spawn(async move { while rx_consensus.recv().await.is_some() {} });
+
+        let peers = committee
+            .authorities
+            .values()
+            .map(|x| x.primary.primary_to_primary)
+            .collect();
+        let poll_delay = Duration::from_millis(parameters.max_header_delay);
+        let timeout = Duration::from_millis(parameters.sync_retry_delay);
+        let gc_depth = parameters.gc_depth;
+        tokio::spawn(async move {
+            Self {
+                round_index: RoundIndex::new(store.clone(), &committee),
+                committee,
+                store,
+                peers,
+                poll_delay,
+                timeout,
+                gc_depth,
+                rx_parameters,
+                tx_consensus,
+                pending: BTreeMap::new(),
+                last_delivered: 0,
+                last_missing: None,
+                connection: None,
+                current: 0,
+            }
+            .run()
+            .await;
+        })
+    }
+
+    /// Asks the current primary for the certificates following `round`. Returns them along with its last
+    /// round.
+    async fn request(&mut self, round: Round) -> io::Result<(Vec<Certificate>, Round)> {
+        if self.connection.is_none() {
+            let address = self.peers[self.current];
+            let stream = TcpStream::connect(address).await?;
+            debug!("Observing the DAG through {}", address);
+            self.connection = Some(Framed::new(stream, LengthDelimitedCodec::new()));
+        }
+        let connection = self.connection.as_mut().unwrap();
+        let message = PrimaryMessage::ObserverSyncRequest(round);
+        let bytes = bincode::serialize(&message).expect("Failed to serialize observer request");
+        connection.send(Bytes::from(bytes)).await?;
+
+        // The primary acknowledges every message before replying.
+        while let Some(frame) = connection.next().await {
+            let frame = frame?;
+            if frame.as_ref() == b"Ack" {
+                continue;
+            }
+            return match bincode::deserialize(&frame) {
+                Ok(PrimaryMessage::StateSyncReply(certificates, status)) => {
+                    Ok((certificates, status.last_round))
+                }
+                _ => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "unexpected reply to an observer request",
+                )),
+            };
+        }
+        Err(io::ErrorKind::UnexpectedEof.into())
+    }
+
+    /// Checks whether all the parents of a certificate were delivered to the consensus (the parents of the
+    /// first round are the genesis).
+    async fn has_parents(&mut self, certificate: &Certificate) -> StoreResult<bool> {
+        if certificate.round() <= 1 {
+            return Ok(true);
+        }
+        for parent in &certificate.header.parents {
+            if self.store.read(parent.to_vec()).await?.is_none() {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Stores the valid certificates we did not know whose parents we hold and delivers them to the
+    /// consensus, in round order. The others wait for their parents.
+    async fn process(&mut self, certificates: Vec<Certificate>) -> StoreResult<()> {
+        let epoch = self.rx_parameters.borrow().signing_epoch;
+        for certificate in certificates {
+            let digest = certificate.digest();
+            let waiting = self
+                .pending
+                .get(&certificate.round())
+                .map_or(false, |x| x.iter().any(|y| y.digest() == digest));
+            if waiting || self.store.read(digest.to_vec()).await?.is_some() {
+                continue;
+            }
+            if let Err(e) = certificate.verify(&self.committee, epoch) {
+                warn!("Observer received an invalid certificate: {}", e);
+                continue;
+            }
+            self.pending
+                .entry(certificate.round())
+                .or_insert_with(Vec::new)
+                .push(certificate);
+        }
+
+        let rounds: Vec<_> = self.pending.keys().cloned().collect();
+        for r in rounds {
+            let certificates = self.pending.remove(&r).unwrap_or_default();
+            let mut waiting = Vec::new();
+            for certificate in certificates {
+                if !self.has_parents(&certificate).await? {
+                    waiting.push(certificate);
+                    continue;
+                }
+                let bytes =
+                    bincode::serialize(&certificate).expect("Failed to serialize certificate");
+                self.store.write(certificate.digest().to_vec(), bytes).await;
+                self.round_index.insert(&certificate).await?;
+                self.tx_consensus
+                    .send(certificate)
+                    .await
+                    .expect("Failed to send certificate to consensus");
+                self.last_delivered = self.last_delivered.max(r);
+            }
+            if !waiting.is_empty() {
+                self.pending.insert(r, waiting);
+            }
+        }
+
+        // The consensus would not order certificates that old anyway.
+        self.pending = self
+            .pending
+            .split_off(&self.last_delivered.saturating_sub(self.gc_depth));
+        Ok(())
+    }
+
+    /// The round to ask the following certificates of. We ask once for the missing parents of the
+    /// oldest waiting certificates (they may have reached the primary after we asked for their round),
+    /// and otherwise follow the last certificates we delivered.
+    fn next_round(&mut self) -> Round {
+        match self.pending.keys().next() {
+            Some(r) if self.last_missing != Some(*r) => {
+                self.last_missing = Some(*r);
+                r.saturating_sub(2)
+            }
+            _ => self.last_delivered,
+        }
+    }
+
+    async fn run(&mut self) {
+        self.last_delivered = match self.round_index.last_round().await {
+            Ok(x) => x,
+            Err(e) => {
+                warn!(
+                    "Failed to read our last round, observing from genesis: {}",
+                    e
+                );
+                0
+            }
+        };
+        info!("Observer starting from round {}", self.last_delivered);
+
+        let mut round = self.last_delivered;
+        loop {
+            let (certificates, last_round) = match timeout(self.timeout, self.request(round)).await
+            {
+                Ok(Ok(reply)) => reply,
+                result => {
+                    if let Ok(Err(e)) = result {
+                        warn!("Failed to sync from {}: {}", self.peers[self.current], e);
+                    }
+                    self.connection = None;
+                    self.current = (self.current + 1) % self.peers.len();
+                    sleep(self.poll_delay).await;
+                    continue;
+                }
+            };
+            let previous = self.last_delivered;
+            if let Err(e) = self.process(certificates).await {
+                warn!("Observer failed to access the store: {}", e);
+            }
+            round = self.next_round();
+
+            // Keep asking while the primary has more rounds and we make progress.
+            if last_round <= self.last_delivered || self.last_delivered == previous {
+                sleep(self.poll_delay).await;
+            }
+        }
+    }
+}
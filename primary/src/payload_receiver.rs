@@ -1,23 +1,24 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use config::WorkerId;
 use crypto::Digest;
-use store::Store;
+use store::KeyValueStore;
 use tokio::sync::mpsc::Receiver;
+use tokio::task::JoinHandle;
 
 /// Receives batches' digests of other authorities. These are only needed to verify incoming
 /// headers (ie. make sure we have their payload).
-pub struct PayloadReceiver {
+pub struct PayloadReceiver<S> {
     /// The persistent storage.
-    store: Store,
+    store: S,
     /// Receives batches' digests from the network.
     rx_workers: Receiver<(Digest, WorkerId)>,
 }
 
-impl PayloadReceiver {
-    pub fn spawn(store: Store, rx_workers: Receiver<(Digest, WorkerId)>) {
+impl<S: KeyValueStore> PayloadReceiver<S> {
+    pub fn spawn(store: S, rx_workers: Receiver<(Digest, WorkerId)>) -> JoinHandle<()> {
         tokio::spawn(async move {
             Self { store, rx_workers }.run().await;
-        });
+        })
     }
 
     async fn run(&mut self) {
@@ -4,27 +4,32 @@ use crate::core::Core;
 use crate::error::DagError;
 use crate::garbage_collector::GarbageCollector;
 use crate::header_waiter::HeaderWaiter;
+use crate::health::PeersHealth;
 use crate::helper::Helper;
-use crate::messages::{Certificate, Header, Vote};
+use crate::messages::{Certificate, Equivocation, Header, SyncStatus, Vote};
+use crate::metrics::PrimaryMetrics;
 use crate::payload_receiver::PayloadReceiver;
 use crate::proposer::Proposer;
+use crate::pruner::Pruner;
+use crate::state_sync::StateSync;
 use crate::synchronizer::Synchronizer;
+use crate::verifier::Verifier;
 use async_trait::async_trait;
 use bytes::Bytes;
-use config::{Committee, KeyPair, Parameters, WorkerId};
+use config::{Committee, ConfigError, KeyPair, Parameters, WorkerId};
 use crypto::{Digest, PublicKey, SignatureService};
 use futures::sink::SinkExt as _;
 use log::info;
-use network::{MessageHandler, Receiver as NetworkReceiver, Writer};
+use network::{Backoff, MessageHandler, RateLimit, Receiver as NetworkReceiver, Writer};
+use prometheus::Registry;
 use serde::{Deserialize, Serialize};
 use std::error::Error;
 use std::sync::atomic::AtomicU64;
 use std::sync::Arc;
-use store::Store;
+use store::KeyValueStore;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
-
-/// The default channel capacity for each channel of the primary.
-pub const CHANNEL_CAPACITY: usize = 1_000;
+use tokio::sync::{oneshot, watch};
+use tokio::task::JoinHandle;
 
 /// The round number.
 pub type Round = u64;
@@ -35,6 +40,13 @@ pub enum PrimaryMessage {
     Vote(Vote),
     Certificate(Certificate),
     CertificatesRequest(Vec<Digest>, /* requestor */ PublicKey),
+    /// Asks for the certificates following a round (to catch up after booting).
+    StateSyncRequest(Round, /* requestor */ PublicKey),
+    /// The certificates following the requested round, along with the (signed) last round of the responder.
+    StateSyncReply(Vec<Certificate>, SyncStatus),
+    /// Asks for the certificates following a round on behalf of an observer (a node outside the committee
+    /// following the consensus). The `StateSyncReply` is sent back on the connection of the request.
+    ObserverSyncRequest(Round),
 }
 
 /// The messages sent by the primary to its workers.
@@ -44,6 +56,10 @@ pub enum PrimaryWorkerMessage {
     Synchronize(Vec<Digest>, /* target */ PublicKey),
     /// The primary indicates a round update.
     Cleanup(Round),
+    /// The primary indicates that the worker can delete these batches (committed long ago).
+    Prune(Vec<Digest>),
+    /// The primary indicates that these batches were committed: their decryption shares may be revealed.
+    Committed(Vec<Digest>),
 }
 
 /// The messages sent by the workers to their primary.
@@ -53,29 +69,50 @@ pub enum WorkerPrimaryMessage {
     OurBatch(Digest, WorkerId),
     /// The worker indicates it received a batch's digest from another authority.
     OthersBatch(Digest, WorkerId),
+    /// The worker coalesced the digests of several batches: those it sealed, then those it received from
+    /// other authorities.
+    Batches(
+        /* ours */ Vec<Digest>,
+        /* others */ Vec<Digest>,
+        WorkerId,
+    ),
 }
 
 pub struct Primary;
 
 impl Primary {
-    pub fn spawn(
+    /// Spawns all the tasks of the primary. Fails without spawning anything if our public key is not in
+    /// the committee.
+    #[allow(clippy::too_many_arguments)]
+    pub fn spawn<S: KeyValueStore>(
         keypair: KeyPair,
         committee: Committee,
         parameters: Parameters,
-        store: Store,
+        store: S,
         tx_consensus: Sender<Certificate>,
         rx_consensus: Receiver<Certificate>,
-    ) {
-        let (tx_others_digests, rx_others_digests) = channel(CHANNEL_CAPACITY);
-        let (tx_our_digests, rx_our_digests) = channel(CHANNEL_CAPACITY);
-        let (tx_parents, rx_parents) = channel(CHANNEL_CAPACITY);
-        let (tx_headers, rx_headers) = channel(CHANNEL_CAPACITY);
-        let (tx_sync_headers, rx_sync_headers) = channel(CHANNEL_CAPACITY);
-        let (tx_sync_certificates, rx_sync_certificates) = channel(CHANNEL_CAPACITY);
-        let (tx_headers_loopback, rx_headers_loopback) = channel(CHANNEL_CAPACITY);
-        let (tx_certificates_loopback, rx_certificates_loopback) = channel(CHANNEL_CAPACITY);
-        let (tx_primary_messages, rx_primary_messages) = channel(CHANNEL_CAPACITY);
-        let (tx_cert_requests, rx_cert_requests) = channel(CHANNEL_CAPACITY);
+        peers_health: PeersHealth,
+        registry: &Registry,
+        rx_parameters: watch::Receiver<Parameters>,
+        tx_equivocations: Option<Sender<Equivocation>>,
+    ) -> Result<Vec<JoinHandle<()>>, ConfigError> {
+        let addresses = committee.primary(&keypair.name)?;
+
+        let (tx_others_digests, rx_others_digests) = channel(parameters.channel_capacity);
+        let (tx_our_digests, rx_our_digests) = channel(parameters.channel_capacity);
+        let (tx_parents, rx_parents) = channel(parameters.channel_capacity);
+        let (tx_headers, rx_headers) = channel(parameters.channel_capacity);
+        let (tx_sync_headers, rx_sync_headers) = channel(parameters.channel_capacity);
+        let (tx_sync_certificates, rx_sync_certificates) = channel(parameters.channel_capacity);
+        let (tx_headers_loopback, rx_headers_loopback) = channel(parameters.channel_capacity);
+        let (tx_certificates_loopback, rx_certificates_loopback) =
+            channel(parameters.channel_capacity);
+        let (tx_primary_messages, rx_primary_messages) = channel(parameters.channel_capacity);
+        let (tx_verified_messages, rx_verified_messages) = channel(parameters.channel_capacity);
+        let (tx_cert_requests, rx_cert_requests) = channel(parameters.channel_capacity);
+        let (tx_sync_requests, rx_sync_requests) = channel(parameters.channel_capacity);
+        let (tx_observer_requests, rx_observer_requests) = channel(parameters.channel_capacity);
+        let (tx_sync_replies, rx_sync_replies) = channel(parameters.channel_capacity);
 
         // Write the parameters to the logs.
         parameters.log();
@@ -88,39 +125,42 @@ impl Primary {
         // used for cleanup. The only tasks that write into this variable is `GarbageCollector`.
         let consensus_round = Arc::new(AtomicU64::new(0));
 
-        // Spawn the network receiver listening to messages from the other primaries.
-        let mut address = committee
-            .primary(&name)
-            .expect("Our public key or worker id is not in the committee")
-            .primary_to_primary;
-        address.set_ip("0.0.0.0".parse().unwrap());
-        NetworkReceiver::spawn(
+        // The handles of all the tasks spawned by the primary, used to coordinate shutdown.
+        let mut handles = Vec::new();
+
+        // Spawn the network receiver listening to messages from the other primaries (throttling the primaries
+        // flooding us).
+        let address = parameters.listen_address(addresses.primary_to_primary);
+        handles.push(NetworkReceiver::spawn_with_limit(
             address,
             /* handler */
             PrimaryReceiverHandler {
                 tx_primary_messages,
                 tx_cert_requests,
+                tx_sync_requests,
+                tx_sync_replies,
+                tx_observer_requests,
             },
-        );
+            RateLimit {
+                messages_per_second: parameters.max_peer_messages_per_second,
+                bytes_per_second: parameters.max_peer_bytes_per_second,
+            },
+        ));
         info!(
             "Primary {} listening to primary messages on {}",
             name, address
         );
 
         // Spawn the network receiver listening to messages from our workers.
-        let mut address = committee
-            .primary(&name)
-            .expect("Our public key or worker id is not in the committee")
-            .worker_to_primary;
-        address.set_ip("0.0.0.0".parse().unwrap());
-        NetworkReceiver::spawn(
+        let address = parameters.listen_address(addresses.worker_to_primary);
+        handles.push(NetworkReceiver::spawn(
             address,
             /* handler */
             WorkerReceiverHandler {
                 tx_our_digests,
                 tx_others_digests,
             },
-        );
+        ));
         info!(
             "Primary {} listening to workers messages on {}",
             name, address
@@ -131,15 +171,48 @@ impl Primary {
             name,
             &committee,
             store.clone(),
+            parameters.max_cached_certificates,
             /* tx_header_waiter */ tx_sync_headers,
             /* tx_certificate_waiter */ tx_sync_certificates,
         );
 
-        // The `SignatureService` is used to require signatures on specific digests.
-        let signature_service = SignatureService::spawn(secret);
+        // The `SignatureService` is used to require signatures on specific digests. If we rotate our key, it
+        // switches to the new one once the signing epoch reaches the start of the rotation.
+        let signature_service = match keypair.next {
+            None => SignatureService::spawn(secret),
+            Some(next) => {
+                let (tx_secret, rx_secret) = channel(1);
+                let mut rx_parameters = rx_parameters.clone();
+                tokio::spawn(async move {
+                    while rx_parameters.borrow().signing_epoch < next.from_epoch {
+                        if rx_parameters.changed().await.is_err() {
+                            return;
+                        }
+                    }
+                    info!(
+                        "Signing with key {} from epoch {}",
+                        next.key, next.from_epoch
+                    );
+                    let _ = tx_secret.send(next.secret).await;
+                });
+                SignatureService::spawn_with_rotation(secret, rx_secret)
+            }
+        };
+
+        let metrics = PrimaryMetrics::new(registry);
+
+        // The `Verifier` checks the signatures of the headers and certificates of the other primaries in
+        // parallel, and hands them to the `Core` in order.
+        handles.push(Verifier::spawn(
+            committee.clone(),
+            parameters.verification_threads,
+            /* rx_primaries */ rx_primary_messages,
+            /* tx_core */ tx_verified_messages,
+            rx_parameters.clone(),
+        ));
 
         // The `Core` receives and handles headers, votes, and certificates from the other primaries.
-        Core::spawn(
+        handles.push(Core::spawn(
             name,
             committee.clone(),
             store.clone(),
@@ -147,69 +220,126 @@ impl Primary {
             signature_service.clone(),
             consensus_round.clone(),
             parameters.gc_depth,
-            /* rx_primaries */ rx_primary_messages,
+            Backoff {
+                min_delay: parameters.min_reconnect_delay_ms,
+                max_delay: parameters.max_reconnect_delay_ms,
+                multiplier: parameters.reconnect_backoff_multiplier,
+            },
+            peers_health,
+            metrics.clone(),
+            /* rx_primaries */ rx_verified_messages,
             /* rx_header_waiter */ rx_headers_loopback,
             /* rx_certificate_waiter */ rx_certificates_loopback,
             /* rx_proposer */ rx_headers,
             tx_consensus,
             /* tx_proposer */ tx_parents,
-        );
+            tx_equivocations,
+            rx_parameters.clone(),
+        ));
+
+        // The `Pruner` deletes from the store (ours and our workers') the data committed long enough ago.
+        let tx_pruner = parameters.pruning_margin.map(|margin| {
+            let (tx_pruner, rx_pruner) = channel(parameters.channel_capacity);
+            handles.push(Pruner::spawn(
+                name,
+                &committee,
+                store.clone(),
+                parameters.gc_depth,
+                margin,
+                rx_pruner,
+            ));
+            tx_pruner
+        });
 
         // Keeps track of the latest consensus round and allows other tasks to clean up their their internal state
-        GarbageCollector::spawn(&name, &committee, consensus_round.clone(), rx_consensus);
+        handles.push(GarbageCollector::spawn(
+            &name,
+            &committee,
+            consensus_round.clone(),
+            rx_consensus,
+            tx_pruner,
+        ));
 
         // Receives batch digests from other workers. They are only used to validate headers.
-        PayloadReceiver::spawn(store.clone(), /* rx_workers */ rx_others_digests);
+        handles.push(PayloadReceiver::spawn(
+            store.clone(),
+            /* rx_workers */ rx_others_digests,
+        ));
 
         // Whenever the `Synchronizer` does not manage to validate a header due to missing parent certificates of
         // batch digests, it commands the `HeaderWaiter` to synchronizer with other nodes, wait for their reply, and
         // re-schedule execution of the header once we have all missing data.
-        HeaderWaiter::spawn(
+        handles.push(HeaderWaiter::spawn(
             name,
             committee.clone(),
             store.clone(),
-            consensus_round,
+            consensus_round.clone(),
             parameters.gc_depth,
             parameters.sync_retry_delay,
             parameters.sync_retry_nodes,
+            parameters.max_sync_requests,
             /* rx_synchronizer */ rx_sync_headers,
             /* tx_core */ tx_headers_loopback,
-        );
+            rx_parameters.clone(),
+        ));
 
         // The `CertificateWaiter` waits to receive all the ancestors of a certificate before looping it back to the
         // `Core` for further processing.
-        CertificateWaiter::spawn(
+        handles.push(CertificateWaiter::spawn(
             store.clone(),
+            consensus_round,
+            parameters.gc_depth,
             /* rx_synchronizer */ rx_sync_certificates,
+            /* tx_core */ tx_certificates_loopback.clone(),
+        ));
+
+        // The `StateSync` fetches the certificates created while we were offline, and loops them back to the `Core`.
+        handles.push(StateSync::spawn(
+            name,
+            committee.clone(),
+            store.clone(),
+            parameters.sync_retry_delay,
+            metrics,
+            /* rx_replies */ rx_sync_replies,
             /* tx_core */ tx_certificates_loopback,
-        );
+            rx_parameters.clone(),
+        ));
 
         // When the `Core` collects enough parent certificates, the `Proposer` generates a new header with new batch
         // digests from our workers and it back to the `Core`.
-        Proposer::spawn(
+        handles.push(Proposer::spawn(
             name,
             &committee,
-            signature_service,
+            signature_service.clone(),
             parameters.header_size,
             parameters.max_header_delay,
+            parameters.max_header_parents,
+            parameters.min_header_batches,
             /* rx_core */ rx_parents,
             /* rx_workers */ rx_our_digests,
             /* tx_core */ tx_headers,
-        );
+            rx_parameters,
+        ));
 
         // The `Helper` is dedicated to reply to certificates requests from other primaries.
-        Helper::spawn(committee.clone(), store, rx_cert_requests);
+        handles.push(Helper::spawn(
+            name,
+            committee.clone(),
+            store,
+            signature_service,
+            rx_cert_requests,
+            rx_sync_requests,
+            rx_observer_requests,
+        ));
 
         // NOTE: This log entry is used to compute performance.
         info!(
             "Primary {} successfully booted on {}",
             name,
-            committee
-                .primary(&name)
-                .expect("Our public key or worker id is not in the committee")
-                .primary_to_primary
-                .ip()
+            addresses.primary_to_primary.ip()
         );
+
+        Ok(handles)
     }
 }
 
@@ -218,6 +348,9 @@ impl Primary {
 struct PrimaryReceiverHandler {
     tx_primary_messages: Sender<PrimaryMessage>,
     tx_cert_requests: Sender<(Vec<Digest>, PublicKey)>,
+    tx_sync_requests: Sender<(Round, PublicKey)>,
+    tx_sync_replies: Sender<(Vec<Certificate>, SyncStatus)>,
+    tx_observer_requests: Sender<(Round, oneshot::Sender<PrimaryMessage>)>,
 }
 
 #[async_trait]
@@ -233,6 +366,28 @@ impl MessageHandler for PrimaryReceiverHandler {
                 .send((missing, requestor))
                 .await
                 .expect("Failed to send primary message"),
+            PrimaryMessage::StateSyncRequest(round, requestor) => self
+                .tx_sync_requests
+                .send((round, requestor))
+                .await
+                .expect("Failed to send primary message"),
+            // Observers are not in the committee: we reply on their connection.
+            PrimaryMessage::ObserverSyncRequest(round) => {
+                let (sender, receiver) = oneshot::channel();
+                self.tx_observer_requests
+                    .send((round, sender))
+                    .await
+                    .expect("Failed to send primary message");
+                if let Ok(reply) = receiver.await {
+                    let bytes = bincode::serialize(&reply)
+                        .expect("Failed to serialize our state sync reply");
+                    writer.send(Bytes::from(bytes)).await?;
+                }
+            }
+            // The state sync stops listening once we caught up.
+            PrimaryMessage::StateSyncReply(certificates, status) => {
+                let _ = self.tx_sync_replies.send((certificates, status)).await;
+            }
             // All other PrimaryMessages (Header, Vote, Certificate) are sent to core for processing
             request => self
                 .tx_primary_messages
@@ -270,6 +425,20 @@ impl MessageHandler for WorkerReceiverHandler {
                 .send((digest, worker_id))
                 .await
                 .expect("Failed to send workers' digests"),
+            WorkerPrimaryMessage::Batches(ours, others, worker_id) => {
+                for digest in ours {
+                    self.tx_our_digests
+                        .send((digest, worker_id))
+                        .await
+                        .expect("Failed to send workers' digests");
+                }
+                for digest in others {
+                    self.tx_others_digests
+                        .send((digest, worker_id))
+                        .await
+                        .expect("Failed to send workers' digests");
+                }
+            }
         }
         Ok(())
     }
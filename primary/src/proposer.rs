@@ -1,14 +1,17 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use crate::messages::{Certificate, Header};
 use crate::primary::Round;
-use config::{Committee, WorkerId};
+use config::{Committee, Parameters, WorkerId};
 use crypto::Hash as _;
 use crypto::{Digest, PublicKey, SignatureService};
 use log::debug;
 #[cfg(feature = "benchmark")]
 use log::info;
 use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
 use tokio::time::{sleep, Duration, Instant};
+use tracing::{field, info_span, Instrument as _};
 
 #[cfg(test)]
 #[path = "tests/proposer_tests.rs"]
@@ -24,6 +27,10 @@ pub struct Proposer {
     header_size: usize,
     /// The maximum delay to wait for batches' digests.
     max_header_delay: u64,
+    /// The number of parents after which we create a header without waiting for batches' digests.
+    max_header_parents: usize,
+    /// The number of batches' digests we wait for before creating a header ahead of the delay.
+    min_header_batches: usize,
 
     /// Receives the parents to include in the next header (along with their round number).
     rx_core: Receiver<(Vec<Digest>, Round)>,
@@ -31,6 +38,8 @@ pub struct Proposer {
     rx_workers: Receiver<(Digest, WorkerId)>,
     /// Sends newly created headers to the `Core`.
     tx_core: Sender<Header>,
+    /// Receives the parameters reloaded by the operator.
+    rx_parameters: watch::Receiver<Parameters>,
 
     /// The current round of the dag.
     round: Round,
@@ -50,10 +59,13 @@ impl Proposer {
         signature_service: SignatureService,
         header_size: usize,
         max_header_delay: u64,
+        max_header_parents: usize,
+        min_header_batches: usize,
         rx_core: Receiver<(Vec<Digest>, Round)>,
         rx_workers: Receiver<(Digest, WorkerId)>,
         tx_core: Sender<Header>,
-    ) {
+        rx_parameters: watch::Receiver<Parameters>,
+    ) -> JoinHandle<()> {
         let genesis = Certificate::genesis(committee)
             .iter()
             .map(|x| x.digest())
@@ -65,9 +77,12 @@ impl Proposer {
                 signature_service,
                 header_size,
                 max_header_delay,
+                max_header_parents,
+                min_header_batches,
                 rx_core,
                 rx_workers,
                 tx_core,
+                rx_parameters,
                 round: 1,
                 last_parents: genesis,
                 digests: Vec::with_capacity(2 * header_size),
@@ -75,11 +90,12 @@ impl Proposer {
             }
             .run()
             .await;
-        });
+        })
     }
 
     async fn make_header(&mut self) {
-        // Make a new header.
+        // Make a new header (the span is the first of the trace of the header).
+        let span = info_span!("propose", round = self.round, header = field::Empty);
         let header = Header::new(
             self.name,
             self.round,
@@ -87,7 +103,9 @@ impl Proposer {
             self.last_parents.drain(..).collect(),
             &mut self.signature_service,
         )
+        .instrument(span.clone())
         .await;
+        span.record("header", &field::display(&header.id));
         debug!("Created {:?}", header);
 
         #[cfg(feature = "benchmark")]
@@ -115,11 +133,16 @@ impl Proposer {
             // conditions is met:
             // 1. We have a quorum of certificates from the previous round and enough batches' digests;
             // 2. We have a quorum of certificates from the previous round and the specified maximum
-            // inter-header delay has passed.
+            // inter-header delay has passed;
+            // 3. We have `max_header_parents` certificates from the previous round.
+            // Only the delay creates headers with less than `min_header_batches` digests.
             let enough_parents = !self.last_parents.is_empty();
+            let max_parents = self.last_parents.len() >= self.max_header_parents;
             let enough_digests = self.payload_size >= self.header_size;
+            let min_batches = self.digests.len() >= self.min_header_batches;
             let timer_expired = timer.is_elapsed();
-            if (timer_expired || enough_digests) && enough_parents {
+            if (timer_expired || ((enough_digests || max_parents) && min_batches)) && enough_parents
+            {
                 // Make a new header.
                 self.make_header().await;
                 self.payload_size = 0;
@@ -131,6 +154,12 @@ impl Proposer {
 
             tokio::select! {
                 Some((parents, round)) = self.rx_core.recv() => {
+                    // Late certificates of the previous round join the next header (if we did not create it yet).
+                    if round + 1 == self.round && !self.last_parents.is_empty() {
+                        let room = self.max_header_parents.saturating_sub(self.last_parents.len());
+                        self.last_parents.extend(parents.into_iter().take(room));
+                        continue;
+                    }
                     if round < self.round {
                         continue;
                     }
@@ -149,6 +178,13 @@ impl Proposer {
                 () = &mut timer => {
                     // Nothing to do.
                 }
+                Ok(()) = self.rx_parameters.changed() => {
+                    // The new delay applies from the next time the timer is rescheduled.
+                    let parameters = self.rx_parameters.borrow().clone();
+                    self.max_header_delay = parameters.max_header_delay;
+                    self.min_header_batches = parameters.min_header_batches;
+                    debug!("Max header delay updated to {} ms", self.max_header_delay);
+                }
             }
         }
     }
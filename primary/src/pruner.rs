@@ -0,0 +1,232 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::messages::Certificate;
+use crate::primary::{PrimaryWorkerMessage, Round};
+use crate::state_sync::RoundIndex;
+use bytes::Bytes;
+use config::{Committee, WorkerId};
+use crypto::{Digest, Hash as _, PublicKey};
+use log::{debug, warn};
+use network::SimpleSender;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use store::KeyValueStore;
+use tokio::sync::mpsc::Receiver;
+use tokio::task::JoinHandle;
+
+#[cfg(test)]
+#[path = "tests/pruner_tests.rs"]
+pub mod pruner_tests;
+
+/// The prefix of the keys of the pruning index.
+const INDEX_PREFIX: &[u8] = b"prune/";
+
+/// The key holding the round below which the store is pruned.
+const WATERMARK_KEY: &[u8] = b"pruned_round";
+
+/// The store entries and the batches of a committed certificate.
+#[derive(Serialize, Deserialize)]
+struct IndexEntry {
+    /// The keys of the header, the certificate, and the payload's digests in our store.
+    keys: Vec<Vec<u8>>,
+    /// The batches of the payload, stored by our workers.
+    batches: Vec<(Digest, WorkerId)>,
+}
+
+impl IndexEntry {
+    fn new(certificate: &Certificate) -> Self {
+        let mut keys = vec![
+            certificate.header.id.to_vec(),
+            certificate.digest().to_vec(),
+        ];
+        let mut batches = Vec::new();
+        for (digest, worker_id) in &certificate.header.payload {
+            keys.push([digest.as_ref(), &worker_id.to_le_bytes()].concat());
+            batches.push((digest.clone(), *worker_id));
+        }
+        Self { keys, batches }
+    }
+}
+
+/// Deletes the headers, certificates and batches committed `gc_depth + margin` rounds below the last
+/// committed round. Every committed certificate is recorded in an index (stored along with the data, and
+/// keyed by round) so that pruning resumes where it stopped after a crash. The certificates that were
+/// stored but never committed (found through the round index) are deleted along with those of their round.
+pub struct Pruner<S> {
+    /// The authorities of the committee.
+    authorities: Vec<PublicKey>,
+    /// The persistent storage.
+    store: S,
+    /// The index of the stored certificates by round, to find those that were never committed.
+    round_index: RoundIndex<S>,
+    /// The depth of the garbage collection.
+    gc_depth: Round,
+    /// How many rounds to keep below the garbage collection round.
+    margin: Round,
+    /// Receives the committed certificates.
+    rx_committed: Receiver<Certificate>,
+    /// The network addresses of our workers.
+    workers: HashMap<WorkerId, SocketAddr>,
+    /// A network sender to command our workers to delete their batches.
+    network: SimpleSender,
+}
+
+impl<S: KeyValueStore> Pruner<S> {
+    pub fn spawn(
+        name: PublicKey,
+        committee: &Committee,
+        store: S,
+        gc_depth: Round,
+        margin: Round,
+        rx_committed: Receiver<Certificate>,
+    ) -> JoinHandle<()> {
+        let workers = committee
+            .authorities
+            .get(&name)
+            .expect("Our public key is not in the committee")
+            .workers
+            .iter()
+            .map(|(id, x)| (*id, x.primary_to_worker))
+            .collect();
+        let authorities = committee.authorities.keys().cloned().collect();
+
+        tokio::spawn(async move {
+            Self {
+                authorities,
+                round_index: RoundIndex::new(store.clone(), committee),
+                store,
+                gc_depth,
+                margin,
+                rx_committed,
+                workers,
+                network: SimpleSender::new(),
+            }
+            .run()
+            .await;
+        })
+    }
+
+    fn index_key(round: Round, author: &PublicKey) -> Vec<u8> {
+        [INDEX_PREFIX, &round.to_be_bytes(), author.as_ref()].concat()
+    }
+
+    fn index_range_key(round: Round) -> Vec<u8> {
+        [INDEX_PREFIX, &round.to_be_bytes()].concat()
+    }
+
+    async fn run(&mut self) {
+        // Rounds below the watermark are already pruned. If we never pruned, nothing older than the first
+        // certificate we receive has been indexed.
+        let mut watermark = match self.store.read(WATERMARK_KEY.to_vec()).await {
+            Ok(Some(bytes)) => bincode::deserialize(&bytes).ok(),
+            Ok(None) => None,
+            Err(e) => {
+                warn!("Failed to read the pruning watermark: {}", e);
+                None
+            }
+        };
+        let mut last_committed_round = 0;
+
+        while let Some(certificate) = self.rx_committed.recv().await {
+            let round = certificate.round();
+            let boundary = round.saturating_sub(self.gc_depth + self.margin);
+            let pruned = *watermark.get_or_insert(boundary);
+
+            let entry = IndexEntry::new(&certificate);
+            if round < pruned {
+                self.delete(vec![entry]).await;
+            } else {
+                let bytes = bincode::serialize(&entry).expect("Failed to serialize index entry");
+                self.store
+                    .write(Self::index_key(round, &certificate.origin()), bytes)
+                    .await;
+            }
+
+            if round > last_committed_round {
+                last_committed_round = round;
+                if boundary > pruned {
+                    self.prune(pruned, boundary).await;
+                    watermark = Some(boundary);
+                }
+            }
+        }
+    }
+
+    /// Deletes the data of the certificates of rounds [`from`, `to`), committed or not.
+    async fn prune(&mut self, from: Round, to: Round) {
+        let mut entries: Vec<IndexEntry> = Vec::new();
+        for round in from..to {
+            for author in &self.authorities {
+                let key = Self::index_key(round, author);
+                match self.store.read(key).await {
+                    Ok(Some(bytes)) => match bincode::deserialize(&bytes) {
+                        Ok(entry) => entries.push(entry),
+                        Err(e) => warn!("Failed to deserialize index entry: {}", e),
+                    },
+                    Ok(None) => (),
+                    Err(e) => warn!("Failed to read index entry: {}", e),
+                }
+            }
+        }
+
+        // The key of the certificate comes second in its entry.
+        let committed: HashSet<Vec<u8>> = entries.iter().map(|x| x.keys[1].clone()).collect();
+        for round in from..to {
+            match self.round_index.certificates(round).await {
+                Ok(certificates) => entries.extend(
+                    certificates
+                        .iter()
+                        .filter(|x| !committed.contains(&x.digest().to_vec()))
+                        .map(IndexEntry::new),
+                ),
+                Err(e) => warn!("Failed to read the certificates of round {}: {}", round, e),
+            }
+        }
+        debug!(
+            "Pruning {} certificates of rounds {} to {}",
+            entries.len(),
+            from,
+            to
+        );
+        self.delete(entries).await;
+
+        self.store
+            .delete_range(Self::index_range_key(from), Self::index_range_key(to))
+            .await;
+        self.store
+            .delete_range(
+                RoundIndex::<S>::range_key(from),
+                RoundIndex::<S>::range_key(to),
+            )
+            .await;
+        let bytes = bincode::serialize(&to).expect("Failed to serialize watermark");
+        self.store.write(WATERMARK_KEY.to_vec(), bytes).await;
+    }
+
+    async fn delete(&mut self, entries: Vec<IndexEntry>) {
+        let mut keys = Vec::new();
+        let mut batches: HashMap<WorkerId, Vec<Digest>> = HashMap::new();
+        for entry in entries {
+            keys.extend(entry.keys);
+            for (digest, worker_id) in entry.batches {
+                batches
+                    .entry(worker_id)
+                    .or_insert_with(Vec::new)
+                    .push(digest);
+            }
+        }
+        self.store.delete(keys).await;
+
+        for (worker_id, digests) in batches {
+            match self.workers.get(&worker_id) {
+                Some(address) => {
+                    let message = PrimaryWorkerMessage::Prune(digests);
+                    let bytes =
+                        bincode::serialize(&message).expect("Failed to serialize our own message");
+                    self.network.send(*address, Bytes::from(bytes)).await;
+                }
+                None => warn!("Cannot prune the batches of unknown worker {}", worker_id),
+            }
+        }
+    }
+}
@@ -0,0 +1,295 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::messages::{Certificate, SyncStatus};
+use crate::metrics::PrimaryMetrics;
+use crate::primary::{PrimaryMessage, Round};
+use bytes::Bytes;
+use config::{Committee, Parameters, Stake};
+use crypto::Hash as _;
+use crypto::PublicKey;
+use log::{debug, info, warn};
+use network::SimpleSender;
+use std::collections::HashMap;
+use store::{KeyValueStore, StoreResult};
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Duration};
+
+#[cfg(test)]
+#[path = "tests/state_sync_tests.rs"]
+pub mod state_sync_tests;
+
+/// The prefix of the keys of the round index.
+const ROUND_PREFIX: &[u8] = b"round/";
+
+/// The key holding the highest round of the round index.
+const LAST_ROUND_KEY: &[u8] = b"last_round";
+
+/// The maximum number of rounds of certificates sent in a single state sync reply.
+pub const MAX_SYNC_ROUNDS: Round = 50;
+
+/// Records the digest of every stored certificate by round and author, so that we can serve the
+/// certificates of a range of rounds to the peers catching up.
+pub struct RoundIndex<S> {
+    /// The persistent storage.
+    store: S,
+    /// The authorities of the committee.
+    authorities: Vec<PublicKey>,
+    /// The highest round of the index (loaded upon the first insertion).
+    last_round: Option<Round>,
+}
+
+impl<S: KeyValueStore> RoundIndex<S> {
+    pub fn new(store: S, committee: &Committee) -> Self {
+        Self {
+            store,
+            authorities: committee.authorities.keys().cloned().collect(),
+            last_round: None,
+        }
+    }
+
+    fn key(round: Round, author: &PublicKey) -> Vec<u8> {
+        [ROUND_PREFIX, &round.to_be_bytes(), author.as_ref()].concat()
+    }
+
+    /// The first key of the index entries of `round` (used to delete ranges of rounds).
+    pub(crate) fn range_key(round: Round) -> Vec<u8> {
+        [ROUND_PREFIX, &round.to_be_bytes()].concat()
+    }
+
+    /// Records a certificate we stored.
+    pub async fn insert(&mut self, certificate: &Certificate) -> StoreResult<()> {
+        let key = Self::key(certificate.round(), &certificate.origin());
+        self.store.write(key, certificate.digest().to_vec()).await;
+
+        let last_round = match self.last_round {
+            Some(x) => x,
+            None => self.last_round().await?,
+        };
+        if certificate.round() > last_round {
+            let bytes =
+                bincode::serialize(&certificate.round()).expect("Failed to serialize round");
+            self.store.write(LAST_ROUND_KEY.to_vec(), bytes).await;
+        }
+        self.last_round = Some(last_round.max(certificate.round()));
+        Ok(())
+    }
+
+    /// The highest round of the stored certificates (0 if we have none).
+    pub async fn last_round(&mut self) -> StoreResult<Round> {
+        let round = self
+            .store
+            .read(LAST_ROUND_KEY.to_vec())
+            .await?
+            .and_then(|x| bincode::deserialize(&x).ok())
+            .unwrap_or_default();
+        Ok(round)
+    }
+
+    /// The stored certificates of a round.
+    pub async fn certificates(&mut self, round: Round) -> StoreResult<Vec<Certificate>> {
+        let keys = self
+            .authorities
+            .iter()
+            .map(|x| Self::key(round, x))
+            .collect();
+        let digests = self
+            .store
+            .read_many(keys)
+            .await?
+            .into_iter()
+            .flatten()
+            .collect();
+        let certificates = self
+            .store
+            .read_many(digests)
+            .await?
+            .into_iter()
+            .flatten()
+            .filter_map(|x| bincode::deserialize(&x).ok())
+            .collect();
+        Ok(certificates)
+    }
+}
+
+/// Returns the highest round reached by a quorum, given the last round of some authorities (or `None`
+/// if they do not have a quorum of stake).
+pub fn quorum_round(rounds: &HashMap<PublicKey, Round>, committee: &Committee) -> Option<Round> {
+    let mut rounds: Vec<_> = rounds
+        .iter()
+        .map(|(name, round)| (*round, committee.stake(name)))
+        .collect();
+    rounds.sort_by(|a, b| b.0.cmp(&a.0));
+
+    let mut stake: Stake = 0;
+    for (round, x) in rounds {
+        stake += x;
+        if stake >= committee.quorum_threshold() {
+            return Some(round);
+        }
+    }
+    None
+}
+
+/// Catches up with the rest of the committee when the primary boots: asks 2f+1 peers for the certificates
+/// following our last stored round and loops them back to the `Core` (which checks their ancestors and stores
+/// them as any other certificate). Stops once we reach a round reached by a quorum, as told by the signed
+/// status of the peers.
+pub struct StateSync<S> {
+    /// The public key of this primary.
+    name: PublicKey,
+    /// The committee information.
+    committee: Committee,
+    /// The round index of our store.
+    round_index: RoundIndex<S>,
+    /// The delay (ms) after which we ask other peers if we did not catch up.
+    sync_retry_delay: u64,
+    /// The metrics exported by the primary.
+    metrics: PrimaryMetrics,
+    /// Receives the replies of the peers: their certificates and their signed last round.
+    rx_replies: Receiver<(Vec<Certificate>, SyncStatus)>,
+    /// Loops back the certificates to the core.
+    tx_core: Sender<Certificate>,
+    /// A network sender to send the requests.
+    network: SimpleSender,
+    /// Tells the signing epoch, which selects the keys we accept from the other authorities.
+    rx_parameters: watch::Receiver<Parameters>,
+}
+
+impl<S: KeyValueStore> StateSync<S> {
+    pub fn spawn(
+        name: PublicKey,
+        committee: Committee,
+        store: S,
+        sync_retry_delay: u64,
+        metrics: PrimaryMetrics,
+        rx_replies: Receiver<(Vec<Certificate>, SyncStatus)>,
+        tx_core: Sender<Certificate>,
+        rx_parameters: watch::Receiver<Parameters>,
+    ) -> JoinHandle<()> {
+        let seed = rx_parameters.borrow().seed;
+        tokio::spawn(async move {
+            Self {
+                name,
+                round_index: RoundIndex::new(store, &committee),
+                committee,
+                sync_retry_delay,
+                metrics,
+                rx_replies,
+                tx_core,
+                network: SimpleSender::new().with_seed(seed),
+                rx_parameters,
+            }
+            .run()
+            .await;
+        })
+    }
+
+    /// Asks `nodes` random peers for the certificates following `round`.
+    async fn request(&mut self, round: Round, nodes: usize) {
+        let addresses = self
+            .committee
+            .others_primaries(&self.name)
+            .iter()
+            .map(|(_, x)| x.primary_to_primary)
+            .collect();
+        let message = PrimaryMessage::StateSyncRequest(round, self.name);
+        let bytes = bincode::serialize(&message).expect("Failed to serialize state sync request");
+        self.network
+            .lucky_broadcast(addresses, Bytes::from(bytes), nodes)
+            .await;
+    }
+
+    /// Loops the valid certificates following `round` back to the core, which stores them once it holds their
+    /// ancestors. Returns the highest round we looped back.
+    async fn load(&mut self, mut certificates: Vec<Certificate>, round: Round) -> Round {
+        certificates.retain(|x| x.round() > round);
+        certificates.sort_by_key(|x| x.round());
+
+        // The certificates are sent in round order: the core stores the parents of a certificate before
+        // processing it.
+        let epoch = self.rx_parameters.borrow().signing_epoch;
+        let mut last = round;
+        for certificate in certificates {
+            if let Err(e) = certificate.verify(&self.committee, epoch) {
+                warn!("Invalid certificate received during state sync: {}", e);
+                continue;
+            }
+            last = certificate.round();
+            self.tx_core
+                .send(certificate)
+                .await
+                .expect("Failed to send certificate to the core");
+        }
+        last
+    }
+
+    async fn run(&mut self) {
+        let mut round = match self.round_index.last_round().await {
+            Ok(x) => x,
+            Err(e) => {
+                warn!("Failed to read our last round, syncing from genesis: {}", e);
+                0
+            }
+        };
+        self.metrics.state_sync_round.set(round as i64);
+        info!("State sync starting from round {}", round);
+
+        // We ask 2f+1 peers, and then ask again the peers telling us they have more rounds.
+        let size = self.committee.size();
+        let nodes = 2 * (size - 1) / 3 + 1;
+        let mut rounds: HashMap<_, _> = vec![(self.name, round)].into_iter().collect();
+        if quorum_round(&rounds, &self.committee).is_some() {
+            info!("State sync done: we hold a quorum of stake");
+            return;
+        }
+        loop {
+            self.request(round, nodes).await;
+
+            let timer = sleep(Duration::from_millis(self.sync_retry_delay));
+            tokio::pin!(timer);
+            loop {
+                tokio::select! {
+                    Some((certificates, status)) = self.rx_replies.recv() => {
+                        // Only trust the last round of the peers that signed it for us.
+                        let epoch = self.rx_parameters.borrow().signing_epoch;
+                        if let Err(e) = status.verify(&self.committee, epoch) {
+                            warn!("Invalid state sync reply: {}", e);
+                            continue;
+                        }
+                        if status.requestor != Some(self.name) {
+                            warn!("Unexpected state sync reply from {}", status.author);
+                            continue;
+                        }
+                        let (origin, last_round) = (status.author, status.last_round);
+                        debug!("Received {} certificates from {} (last round {})", certificates.len(), origin, last_round);
+                        let entry = rounds.entry(origin).or_insert(last_round);
+                        *entry = last_round.max(*entry);
+                        round = self.load(certificates, round).await;
+                        rounds.insert(self.name, round);
+                        self.metrics.state_sync_round.set(round as i64);
+
+                        match quorum_round(&rounds, &self.committee) {
+                            Some(target) if round >= target => {
+                                info!("State sync done: caught up to round {}", round);
+                                return;
+                            }
+                            Some(target) => self.metrics.state_sync_target_round.set(target as i64),
+                            None => (),
+                        }
+                        if last_round > round {
+                            if let Ok(address) = self.committee.primary(&origin) {
+                                let message = PrimaryMessage::StateSyncRequest(round, self.name);
+                                let bytes = bincode::serialize(&message)
+                                    .expect("Failed to serialize state sync request");
+                                self.network.send(address.primary_to_primary, Bytes::from(bytes)).await;
+                            }
+                        }
+                    },
+                    () = &mut timer => break,
+                }
+            }
+            debug!("State sync still at round {}: asking other peers", round);
+        }
+    }
+}
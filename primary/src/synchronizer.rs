@@ -1,34 +1,42 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use crate::error::DagResult;
 use crate::header_waiter::WaiterMessage;
+use crate::lru::LruCache;
 use crate::messages::{Certificate, Header};
 use config::Committee;
 use crypto::Hash as _;
 use crypto::{Digest, PublicKey};
 use std::collections::HashMap;
-use store::Store;
+use store::KeyValueStore;
 use tokio::sync::mpsc::Sender;
 
+#[cfg(test)]
+#[path = "tests/synchronizer_tests.rs"]
+pub mod synchronizer_tests;
+
 /// The `Synchronizer` checks if we have all batches and parents referenced by a header. If we don't, it sends
 /// a command to the `Waiter` to request the missing data.
-pub struct Synchronizer {
+pub struct Synchronizer<S> {
     /// The public key of this primary.
     name: PublicKey,
     /// The persistent storage.
-    store: Store,
+    store: S,
     /// Send commands to the `HeaderWaiter`.
     tx_header_waiter: Sender<WaiterMessage>,
     /// Send commands to the `CertificateWaiter`.
     tx_certificate_waiter: Sender<Certificate>,
     /// The genesis and its digests.
     genesis: Vec<(Digest, Certificate)>,
+    /// The last certificates we processed or read from the store (the parents of the next headers).
+    certificates: LruCache<Digest, Certificate>,
 }
 
-impl Synchronizer {
+impl<S: KeyValueStore> Synchronizer<S> {
     pub fn new(
         name: PublicKey,
         committee: &Committee,
-        store: Store,
+        store: S,
+        max_cached_certificates: usize,
         tx_header_waiter: Sender<WaiterMessage>,
         tx_certificate_waiter: Sender<Certificate>,
     ) -> Self {
@@ -41,9 +49,16 @@ impl Synchronizer {
                 .into_iter()
                 .map(|x| (x.digest(), x))
                 .collect(),
+            certificates: LruCache::new(max_cached_certificates),
         }
     }
 
+    /// Caches a certificate that was just stored, as it is likely the parent of the next headers.
+    pub fn cache_certificate(&mut self, certificate: &Certificate) {
+        self.certificates
+            .insert(certificate.digest(), certificate.clone());
+    }
+
     /// Returns `true` if we have all transactions of the payload. If we don't, we return false,
     /// synchronize with other nodes (through our workers), and re-schedule processing of the
     /// header for when we will have its complete payload.
@@ -53,8 +68,15 @@ impl Synchronizer {
             return Ok(false);
         }
 
+        let payload: Vec<_> = header.payload.iter().collect();
+        let keys = payload
+            .iter()
+            .map(|(digest, worker_id)| [digest.as_ref(), &worker_id.to_le_bytes()].concat())
+            .collect();
+        let values = self.store.read_many(keys).await?;
+
         let mut missing = HashMap::new();
-        for (digest, worker_id) in header.payload.iter() {
+        for ((digest, worker_id), value) in payload.into_iter().zip(values) {
             // Check whether we have the batch. If one of our worker has the batch, the primary stores the pair
             // (digest, worker_id) in its own storage. It is important to verify that we received the batch
             // from the correct worker id to prevent the following attack:
@@ -66,8 +88,7 @@ impl Synchronizer {
             //      4. The last good node will never be able to sync as it will keep sending its sync requests
             //         to workers #1 (rather than workers #0). Also, clients will never be able to retrieve batch
             //         X as they will be querying worker #1.
-            let key = [digest.as_ref(), &worker_id.to_le_bytes()].concat();
-            if self.store.read(key).await?.is_none() {
+            if value.is_none() {
                 missing.insert(digest.clone(), *worker_id);
             }
         }
@@ -87,23 +108,37 @@ impl Synchronizer {
     /// we return an empty vector, synchronize with other nodes, and re-schedule processing
     /// of the header for when we will have all the parents.
     pub async fn get_parents(&mut self, header: &Header) -> DagResult<Vec<Certificate>> {
-        let mut missing = Vec::new();
         let mut parents = Vec::new();
+        let mut stored = Vec::new();
         for digest in &header.parents {
-            if let Some(genesis) = self
+            match self
                 .genesis
                 .iter()
                 .find(|(x, _)| x == digest)
                 .map(|(_, x)| x)
             {
-                parents.push(genesis.clone());
-                continue;
+                Some(genesis) => parents.push(genesis.clone()),
+                None => match self.certificates.get(digest) {
+                    Some(certificate) => parents.push(certificate.clone()),
+                    None => stored.push(digest.clone()),
+                },
             }
+        }
 
-            match self.store.read(digest.to_vec()).await? {
-                Some(certificate) => parents.push(bincode::deserialize(&certificate)?),
-                None => missing.push(digest.clone()),
-            };
+        // Read all the other parents at once: when catching up, headers reference a full round of parents
+        // (that may have been evicted from the cache).
+        let keys = stored.iter().map(|x| x.to_vec()).collect();
+        let values = self.store.read_many(keys).await?;
+        let mut missing = Vec::new();
+        for (digest, value) in stored.into_iter().zip(values) {
+            match value {
+                Some(bytes) => {
+                    let certificate: Certificate = bincode::deserialize(&bytes)?;
+                    self.certificates.insert(digest, certificate.clone());
+                    parents.push(certificate);
+                }
+                None => missing.push(digest),
+            }
         }
 
         if missing.is_empty() {
@@ -120,18 +155,27 @@ impl Synchronizer {
     /// Check whether we have all the ancestors of the certificate. If we don't, send the certificate to
     /// the `CertificateWaiter` which will trigger re-processing once we have all the missing data.
     pub async fn deliver_certificate(&mut self, certificate: &Certificate) -> DagResult<bool> {
-        for digest in &certificate.header.parents {
-            if self.genesis.iter().any(|(x, _)| x == digest) {
-                continue;
-            }
+        let keys = certificate
+            .header
+            .parents
+            .iter()
+            .filter(|digest| !self.genesis.iter().any(|(x, _)| x == *digest))
+            .filter(|digest| !self.certificates.contains(digest))
+            .map(|x| x.to_vec())
+            .collect();
 
-            if self.store.read(digest.to_vec()).await?.is_none() {
-                self.tx_certificate_waiter
-                    .send(certificate.clone())
-                    .await
-                    .expect("Failed to send sync certificate request");
-                return Ok(false);
-            };
+        if self
+            .store
+            .read_many(keys)
+            .await?
+            .iter()
+            .any(Option::is_none)
+        {
+            self.tx_certificate_waiter
+                .send(certificate.clone())
+                .await
+                .expect("Failed to send sync certificate request");
+            return Ok(false);
         }
         Ok(true)
     }
@@ -0,0 +1,40 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{committee, header, votes};
+
+#[test]
+fn certificate_at_quorum() {
+    let committee = committee();
+    let header = header();
+    let mut aggregator = VotesAggregator::new();
+    let mut votes = votes(&header).into_iter();
+
+    // The votes arrive one by one: the certificate is made by the vote reaching the quorum (3 of the
+    // 4 equal stakes), and holds the votes received so far.
+    for _ in 0..2 {
+        let vote = votes.next().unwrap();
+        assert!(aggregator
+            .append(vote, &committee, &header)
+            .unwrap()
+            .is_none());
+    }
+    let vote = votes.next().unwrap();
+    let certificate = aggregator
+        .append(vote, &committee, &header)
+        .unwrap()
+        .expect("The quorum of votes should make a certificate");
+    assert_eq!(certificate.votes.len(), 3);
+    assert_eq!(certificate.header.id, header.id);
+    assert!(certificate.verify(&committee, 0).is_ok());
+
+    // The late vote is ignored, but a vote cannot be counted twice.
+    let late = votes.next().unwrap();
+    assert!(aggregator
+        .append(late.clone(), &committee, &header)
+        .unwrap()
+        .is_none());
+    assert!(matches!(
+        aggregator.append(late, &committee, &header),
+        Err(DagError::AuthorityReuse(_))
+    ));
+}
@@ -0,0 +1,51 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{certificate, header};
+use crate::messages::Header;
+use store::MemoryStore;
+use tokio::time::{sleep, timeout, Duration};
+
+// A certificate of `round` whose only parent is `parent`.
+fn certificate_with_parent(round: Round, parent: &Digest) -> Certificate {
+    let header = Header {
+        round,
+        parents: [parent.clone()].iter().cloned().collect(),
+        ..header()
+    };
+    certificate(&Header {
+        id: header.digest(),
+        ..header
+    })
+}
+
+#[tokio::test]
+async fn drop_certificates_far_behind() {
+    let mut store = MemoryStore::new();
+    let (tx_synchronizer, rx_synchronizer) = channel(3);
+    let (tx_core, mut rx_core) = channel(2);
+    CertificateWaiter::spawn(
+        store.clone(),
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* gc_depth */ 2,
+        rx_synchronizer,
+        tx_core,
+    );
+
+    // The consensus does not move, but a certificate more than `2 * gc_depth` rounds ahead (sent twice)
+    // cancels the wait of the old one.
+    let (old_parent, new_parent) = (Digest([1; 32]), Digest([2; 32]));
+    let old = certificate_with_parent(1, &old_parent);
+    let new = certificate_with_parent(10, &new_parent);
+    tx_synchronizer.send(old).await.unwrap();
+    tx_synchronizer.send(new.clone()).await.unwrap();
+    tx_synchronizer.send(new.clone()).await.unwrap();
+    sleep(Duration::from_millis(100)).await;
+
+    // Only the new certificate is looped back (once) when the parents are stored.
+    store.write(old_parent.to_vec(), Vec::new()).await;
+    store.write(new_parent.to_vec(), Vec::new()).await;
+    assert_eq!(rx_core.recv().await.unwrap(), new);
+    assert!(timeout(Duration::from_millis(200), rx_core.recv())
+        .await
+        .is_err());
+}
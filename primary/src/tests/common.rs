@@ -3,15 +3,19 @@ use crate::messages::{Certificate, Header, Vote};
 use bytes::Bytes;
 use config::{Authority, Committee, PrimaryAddresses, WorkerAddresses};
 use crypto::Hash as _;
-use crypto::{generate_keypair, PublicKey, SecretKey, Signature};
+use crypto::{generate_keypair, HashKind, PublicKey, SchemeKind, SecretKey, Signature};
 use futures::sink::SinkExt as _;
 use futures::stream::StreamExt as _;
 use rand::rngs::StdRng;
 use rand::SeedableRng as _;
+use std::collections::HashMap;
+use std::fmt;
 use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
 use tokio::net::TcpListener;
 use tokio::task::JoinHandle;
 use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use tracing::{field, span, Event, Metadata, Subscriber};
 
 impl PartialEq for Header {
     fn eq(&self, other: &Self) -> bool {
@@ -34,6 +38,11 @@ pub fn keys() -> Vec<(PublicKey, SecretKey)> {
 // Fixture
 pub fn committee() -> Committee {
     Committee {
+        threshold_public_key: None,
+        signature_scheme: SchemeKind::selected(),
+        hash_function: HashKind::selected(),
+        durable_acks: None,
+        shard_thresholds: HashMap::new(),
         authorities: keys()
             .iter()
             .enumerate()
@@ -59,6 +68,7 @@ pub fn committee() -> Committee {
                         stake: 1,
                         primary,
                         workers,
+                        key_rotation: None,
                     },
                 )
             })
@@ -181,3 +191,63 @@ pub fn listener(address: SocketAddr) -> JoinHandle<Bytes> {
         }
     })
 }
+
+/// A tracing subscriber recording the name of every span, along with its `header` field (if any).
+#[derive(Clone, Default)]
+pub struct SpanRecorder {
+    pub spans: Arc<Mutex<Vec<(&'static str, Option<String>)>>>,
+}
+
+impl SpanRecorder {
+    /// The headers of the spans named `name`.
+    pub fn headers(&self, name: &str) -> Vec<String> {
+        self.spans
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(x, _)| *x == name)
+            .filter_map(|(_, header)| header.clone())
+            .collect()
+    }
+}
+
+/// Reads the `header` field of a span.
+struct HeaderVisitor(Option<String>);
+
+impl field::Visit for HeaderVisitor {
+    fn record_debug(&mut self, field: &field::Field, value: &dyn fmt::Debug) {
+        if field.name() == "header" {
+            self.0 = Some(format!("{:?}", value));
+        }
+    }
+}
+
+impl Subscriber for SpanRecorder {
+    fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, span: &span::Attributes<'_>) -> span::Id {
+        let mut visitor = HeaderVisitor(None);
+        span.record(&mut visitor);
+        let mut spans = self.spans.lock().unwrap();
+        spans.push((span.metadata().name(), visitor.0));
+        span::Id::from_u64(spans.len() as u64)
+    }
+
+    fn record(&self, span: &span::Id, values: &span::Record<'_>) {
+        let mut visitor = HeaderVisitor(None);
+        values.record(&mut visitor);
+        if let Some(header) = visitor.0 {
+            self.spans.lock().unwrap()[span.into_u64() as usize - 1].1 = Some(header);
+        }
+    }
+
+    fn record_follows_from(&self, _span: &span::Id, _follows: &span::Id) {}
+
+    fn event(&self, _event: &Event<'_>) {}
+
+    fn enter(&self, _span: &span::Id) {}
+
+    fn exit(&self, _span: &span::Id) {}
+}
@@ -2,9 +2,13 @@
 use super::*;
 use crate::common::{
     certificate, committee, committee_with_base_port, header, headers, keys, listener, votes,
+    SpanRecorder,
 };
+use crate::header_waiter::WaiterMessage;
+use crypto::Signature;
 use futures::future::try_join_all;
 use std::fs;
+use store::Store;
 use tokio::sync::mpsc::channel;
 
 #[tokio::test]
@@ -45,6 +49,7 @@ async fn process_header() {
         name,
         &committee,
         store.clone(),
+        /* max_cached_certificates */ 1_000,
         /* tx_header_waiter */ tx_sync_headers,
         /* tx_certificate_waiter */ tx_sync_certificates,
     );
@@ -58,12 +63,17 @@ async fn process_header() {
         signature_service,
         /* consensus_round */ Arc::new(AtomicU64::new(0)),
         /* gc_depth */ 50,
+        Backoff::default(),
+        PeersHealth::default(),
+        PrimaryMetrics::default(),
         /* rx_primaries */ rx_primary_messages,
         /* rx_header_waiter */ rx_headers_loopback,
         /* rx_certificate_waiter */ rx_certificates_loopback,
         /* rx_proposer */ rx_headers,
         tx_consensus,
         /* tx_proposer */ tx_parents,
+        /* tx_equivocations */ None,
+        watch::channel(Parameters::default()).1,
     );
 
     // Send a header to the core.
@@ -112,6 +122,7 @@ async fn process_header_missing_parent() {
         name,
         &committee(),
         store.clone(),
+        /* max_cached_certificates */ 1_000,
         /* tx_header_waiter */ tx_sync_headers,
         /* tx_certificate_waiter */ tx_sync_certificates,
     );
@@ -125,12 +136,17 @@ async fn process_header_missing_parent() {
         signature_service,
         /* consensus_round */ Arc::new(AtomicU64::new(0)),
         /* gc_depth */ 50,
+        Backoff::default(),
+        PeersHealth::default(),
+        PrimaryMetrics::default(),
         /* rx_primaries */ rx_primary_messages,
         /* rx_header_waiter */ rx_headers_loopback,
         /* rx_certificate_waiter */ rx_certificates_loopback,
         /* rx_proposer */ rx_headers,
         tx_consensus,
         /* tx_proposer */ tx_parents,
+        /* tx_equivocations */ None,
+        watch::channel(Parameters::default()).1,
     );
 
     // Send a header to the core.
@@ -148,6 +164,81 @@ async fn process_header_missing_parent() {
     assert!(store.read(id.to_vec()).await.unwrap().is_none());
 }
 
+#[tokio::test]
+async fn reject_headers_far_ahead() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::spawn(secret);
+
+    let (tx_sync_headers, mut rx_sync_headers) = channel(1);
+    let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
+    let (tx_primary_messages, rx_primary_messages) = channel(2);
+    let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
+    let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
+    let (_tx_headers, rx_headers) = channel(1);
+    let (tx_consensus, _rx_consensus) = channel(1);
+    let (tx_parents, _rx_parents) = channel(1);
+
+    // Create a new test store.
+    let path = ".db_test_reject_headers_far_ahead";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Make a synchronizer for the core.
+    let synchronizer = Synchronizer::new(
+        name,
+        &committee(),
+        store.clone(),
+        /* max_cached_certificates */ 1_000,
+        /* tx_header_waiter */ tx_sync_headers,
+        /* tx_certificate_waiter */ tx_sync_certificates,
+    );
+
+    // Spawn the core.
+    Core::spawn(
+        name,
+        committee(),
+        store,
+        synchronizer,
+        signature_service,
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* gc_depth */ 2,
+        Backoff::default(),
+        PeersHealth::default(),
+        PrimaryMetrics::default(),
+        /* rx_primaries */ rx_primary_messages,
+        /* rx_header_waiter */ rx_headers_loopback,
+        /* rx_certificate_waiter */ rx_certificates_loopback,
+        /* rx_proposer */ rx_headers,
+        tx_consensus,
+        /* tx_proposer */ tx_parents,
+        /* tx_equivocations */ None,
+        watch::channel(Parameters::default()).1,
+    );
+
+    // The DAG is empty: only the headers of the next `gc_depth` rounds are processed (here, synced).
+    let make = |round| {
+        let header = Header {
+            round,
+            parents: [Digest::default()].iter().cloned().collect(),
+            ..header()
+        };
+        Header {
+            id: header.digest(),
+            ..header
+        }
+    };
+    for round in &[3, 2] {
+        tx_primary_messages
+            .send(PrimaryMessage::Header(make(*round)))
+            .await
+            .unwrap();
+    }
+    match rx_sync_headers.recv().await.unwrap() {
+        WaiterMessage::SyncParents(_, x) => assert_eq!(x.round, 2),
+        x => panic!("Unexpected message: {:?}", x),
+    }
+}
+
 #[tokio::test]
 async fn process_header_missing_payload() {
     let (name, secret) = keys().pop().unwrap();
@@ -172,6 +263,7 @@ async fn process_header_missing_payload() {
         name,
         &committee(),
         store.clone(),
+        /* max_cached_certificates */ 1_000,
         /* tx_header_waiter */ tx_sync_headers,
         /* tx_certificate_waiter */ tx_sync_certificates,
     );
@@ -185,12 +277,17 @@ async fn process_header_missing_payload() {
         signature_service,
         /* consensus_round */ Arc::new(AtomicU64::new(0)),
         /* gc_depth */ 50,
+        Backoff::default(),
+        PeersHealth::default(),
+        PrimaryMetrics::default(),
         /* rx_primaries */ rx_primary_messages,
         /* rx_header_waiter */ rx_headers_loopback,
         /* rx_certificate_waiter */ rx_certificates_loopback,
         /* rx_proposer */ rx_headers,
         tx_consensus,
         /* tx_proposer */ tx_parents,
+        /* tx_equivocations */ None,
+        watch::channel(Parameters::default()).1,
     );
 
     // Send a header to the core.
@@ -234,6 +331,7 @@ async fn process_votes() {
         name,
         &committee,
         store.clone(),
+        /* max_cached_certificates */ 1_000,
         /* tx_header_waiter */ tx_sync_headers,
         /* tx_certificate_waiter */ tx_sync_certificates,
     );
@@ -247,12 +345,17 @@ async fn process_votes() {
         signature_service,
         /* consensus_round */ Arc::new(AtomicU64::new(0)),
         /* gc_depth */ 50,
+        Backoff::default(),
+        PeersHealth::default(),
+        PrimaryMetrics::default(),
         /* rx_primaries */ rx_primary_messages,
         /* rx_header_waiter */ rx_headers_loopback,
         /* rx_certificate_waiter */ rx_certificates_loopback,
         /* rx_proposer */ rx_headers,
         tx_consensus,
         /* tx_proposer */ tx_parents,
+        /* tx_equivocations */ None,
+        watch::channel(Parameters::default()).1,
     );
 
     // Make the certificate we expect to receive.
@@ -282,6 +385,80 @@ async fn process_votes() {
     }
 }
 
+#[tokio::test]
+async fn trace_headers_and_certificates() {
+    // Record the spans of the core (which runs on the thread of the test).
+    let recorder = SpanRecorder::default();
+    let _guard = tracing::subscriber::set_default(recorder.clone());
+
+    let mut keys = keys();
+    let _ = keys.pop().unwrap(); // Skip the header' author.
+    let (name, secret) = keys.pop().unwrap();
+    let signature_service = SignatureService::spawn(secret);
+
+    let (tx_sync_headers, _rx_sync_headers) = channel(1);
+    let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
+    let (tx_primary_messages, rx_primary_messages) = channel(2);
+    let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
+    let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
+    let (_tx_headers, rx_headers) = channel(1);
+    let (tx_consensus, mut rx_consensus) = channel(1);
+    let (tx_parents, _rx_parents) = channel(1);
+
+    // Create a new test store.
+    let path = ".db_test_trace_headers_and_certificates";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Make a synchronizer for the core.
+    let synchronizer = Synchronizer::new(
+        name,
+        &committee(),
+        store.clone(),
+        /* max_cached_certificates */ 1_000,
+        /* tx_header_waiter */ tx_sync_headers,
+        /* tx_certificate_waiter */ tx_sync_certificates,
+    );
+
+    // Spawn the core.
+    Core::spawn(
+        name,
+        committee(),
+        store,
+        synchronizer,
+        signature_service,
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* gc_depth */ 50,
+        Backoff::default(),
+        PeersHealth::default(),
+        PrimaryMetrics::default(),
+        /* rx_primaries */ rx_primary_messages,
+        /* rx_header_waiter */ rx_headers_loopback,
+        /* rx_certificate_waiter */ rx_certificates_loopback,
+        /* rx_proposer */ rx_headers,
+        tx_consensus,
+        /* tx_proposer */ tx_parents,
+        /* tx_equivocations */ None,
+        watch::channel(Parameters::default()).1,
+    );
+
+    // Send a header and its certificate to the core, and wait until it processed both.
+    tx_primary_messages
+        .send(PrimaryMessage::Header(header()))
+        .await
+        .unwrap();
+    tx_primary_messages
+        .send(PrimaryMessage::Certificate(certificate(&header())))
+        .await
+        .unwrap();
+    assert_eq!(rx_consensus.recv().await.unwrap(), certificate(&header()));
+
+    // Both were processed in spans keyed by the digest of the header.
+    let id = header().id.to_string();
+    assert_eq!(recorder.headers("header"), vec![id.clone()]);
+    assert_eq!(recorder.headers("certificate"), vec![id]);
+}
+
 #[tokio::test]
 async fn process_certificates() {
     let (name, secret) = keys().pop().unwrap();
@@ -306,6 +483,7 @@ async fn process_certificates() {
         name,
         &committee(),
         store.clone(),
+        /* max_cached_certificates */ 1_000,
         /* tx_header_waiter */ tx_sync_headers,
         /* tx_certificate_waiter */ tx_sync_certificates,
     );
@@ -319,12 +497,17 @@ async fn process_certificates() {
         signature_service,
         /* consensus_round */ Arc::new(AtomicU64::new(0)),
         /* gc_depth */ 50,
+        Backoff::default(),
+        PeersHealth::default(),
+        PrimaryMetrics::default(),
         /* rx_primaries */ rx_primary_messages,
         /* rx_header_waiter */ rx_headers_loopback,
         /* rx_certificate_waiter */ rx_certificates_loopback,
         /* rx_proposer */ rx_headers,
         tx_consensus,
         /* tx_proposer */ tx_parents,
+        /* tx_equivocations */ None,
+        watch::channel(Parameters::default()).1,
     );
 
     // Send enough certificates to the core.
@@ -359,3 +542,91 @@ async fn process_certificates() {
         assert_eq!(stored, Some(serialized));
     }
 }
+
+#[tokio::test]
+async fn detect_equivocation() {
+    let (name, secret) = keys().remove(0);
+    let signature_service = SignatureService::spawn(secret);
+
+    let (tx_sync_headers, _rx_sync_headers) = channel(1);
+    let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
+    let (tx_primary_messages, rx_primary_messages) = channel(1);
+    let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
+    let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
+    let (_tx_headers, rx_headers) = channel(1);
+    let (tx_consensus, _rx_consensus) = channel(1);
+    let (tx_parents, _rx_parents) = channel(1);
+    let (tx_equivocations, mut rx_equivocations) = channel(1);
+
+    // Create a new test store.
+    let path = ".db_test_detect_equivocation";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+
+    // Make a synchronizer for the core.
+    let synchronizer = Synchronizer::new(
+        name,
+        &committee(),
+        store.clone(),
+        /* max_cached_certificates */ 1_000,
+        /* tx_header_waiter */ tx_sync_headers,
+        /* tx_certificate_waiter */ tx_sync_certificates,
+    );
+
+    // Spawn the core.
+    Core::spawn(
+        name,
+        committee(),
+        store.clone(),
+        synchronizer,
+        signature_service,
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* gc_depth */ 50,
+        Backoff::default(),
+        PeersHealth::default(),
+        PrimaryMetrics::default(),
+        /* rx_primaries */ rx_primary_messages,
+        /* rx_header_waiter */ rx_headers_loopback,
+        /* rx_certificate_waiter */ rx_certificates_loopback,
+        /* rx_proposer */ rx_headers,
+        tx_consensus,
+        /* tx_proposer */ tx_parents,
+        Some(tx_equivocations),
+        watch::channel(Parameters::default()).1,
+    );
+
+    // Make a second header of the same author and round, referencing only 3 of the 4 genesis certificates.
+    let (_, author_secret) = keys().pop().unwrap();
+    let conflicting = Header {
+        parents: header().parents.into_iter().skip(1).collect(),
+        ..header()
+    };
+    let conflicting = Header {
+        id: conflicting.digest(),
+        signature: Signature::new(&conflicting.digest(), &author_secret),
+        ..conflicting
+    };
+
+    // Send both headers to the core.
+    for x in vec![header(), conflicting.clone()] {
+        tx_primary_messages
+            .send(PrimaryMessage::Header(x))
+            .await
+            .unwrap();
+    }
+
+    // Ensure the core outputs the proof of the equivocation.
+    let equivocation = rx_equivocations.recv().await.unwrap();
+    assert_eq!(equivocation.first, header());
+    assert_eq!(equivocation.second, conflicting);
+    assert!(equivocation.verify(&committee(), 0).is_ok());
+
+    // Ensure the proof is stored, and that the first header was processed.
+    let stored = store
+        .read(equivocation.digest().to_vec())
+        .await
+        .unwrap()
+        .map(|x| bincode::deserialize::<Equivocation>(&x).unwrap().digest());
+    assert_eq!(stored, Some(equivocation.digest()));
+    assert!(store.read(header().id.to_vec()).await.unwrap().is_some());
+}
@@ -0,0 +1,50 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{committee, keys};
+
+#[test]
+fn rounds_complete_two_rounds_later() {
+    let names: Vec<_> = keys().into_iter().map(|(name, _)| name).collect();
+    let mut width = DagWidth::new(committee());
+
+    for name in &names {
+        assert!(width.add(*name, 1).is_empty());
+    }
+    assert!(width.add(names[0], 2).is_empty());
+    assert_eq!(width.add(names[0], 3), vec![(1, 4)]);
+
+    // A late certificate does not change the width of a complete round.
+    assert!(width.add(names[1], 1).is_empty());
+    assert_eq!(width.average(), 4.0);
+
+    // A jump completes all the rounds below.
+    assert_eq!(width.add(names[0], 6), vec![(2, 1), (3, 1)]);
+    assert_eq!(width.average(), 2.0);
+}
+
+#[test]
+fn persistently_narrow_dag() {
+    let names: Vec<_> = keys().into_iter().map(|(name, _)| name).collect();
+    let mut width = DagWidth::new(committee());
+
+    // The last authority only shows up once.
+    for round in 1..=WIDTH_WINDOW as Round + 2 {
+        let authors = if round == 5 { &names[..] } else { &names[..3] };
+        for name in authors {
+            width.add(*name, round);
+        }
+        // The full round 5 is still in the window.
+        assert!(width.narrow().is_none());
+    }
+
+    for round in WIDTH_WINDOW as Round + 3..=WIDTH_WINDOW as Round + 7 {
+        for name in &names[..3] {
+            width.add(*name, round);
+        }
+    }
+    let absent = width.narrow().unwrap();
+    assert_eq!(absent, vec![(names[3], WIDTH_WINDOW)]);
+
+    // The same window is not reported twice.
+    assert!(width.narrow().is_none());
+}
@@ -0,0 +1,155 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{certificate, committee_with_base_port, header, headers, keys};
+use crate::messages::Certificate;
+use crypto::Hash as _;
+use store::MemoryStore;
+use tokio::net::TcpListener;
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+// Spawns a peer receiving `expected` certificate requests. It answers them by storing the certificates it
+// has (as our core would upon receiving them), and returns the digests requested by each of them.
+fn peer(
+    address: std::net::SocketAddr,
+    certificates: Vec<Certificate>,
+    mut store: MemoryStore,
+    expected: usize,
+) -> JoinHandle<Vec<Vec<Digest>>> {
+    tokio::spawn(async move {
+        let listener = TcpListener::bind(&address).await.unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+        let mut requests = Vec::new();
+        while requests.len() < expected {
+            let bytes = match transport.next().await {
+                Some(Ok(bytes)) => bytes,
+                _ => panic!("Failed to receive network message"),
+            };
+            match bincode::deserialize(&bytes) {
+                Ok(PrimaryMessage::CertificatesRequest(digests, _)) => {
+                    for certificate in &certificates {
+                        if digests.contains(&certificate.digest()) {
+                            let value = bincode::serialize(certificate).unwrap();
+                            store.write(certificate.digest().to_vec(), value).await;
+                        }
+                    }
+                    requests.push(digests);
+                }
+                _ => panic!("Unexpected message"),
+            }
+        }
+        requests
+    })
+}
+
+#[tokio::test]
+async fn sync_parents_from_a_single_peer() {
+    let (name, _) = keys().remove(0);
+    let committee = committee_with_base_port(15_000);
+    let store = MemoryStore::new();
+    let (tx_synchronizer, rx_synchronizer) = channel(1);
+    let (tx_core, mut rx_core) = channel(1);
+
+    // The header misses two parents, that only one peer (neither us nor the author) has.
+    let header = header();
+    let parents: Vec<_> = headers()
+        .iter()
+        .skip(1)
+        .take(2)
+        .map(|x| certificate(x))
+        .collect();
+    let (holder, _) = keys().remove(1);
+    let address = committee.primary(&holder).unwrap().primary_to_primary;
+    let peer = peer(
+        address,
+        parents.clone(),
+        store.clone(),
+        /* expected */ 2,
+    );
+
+    // Re-request at most one certificate at a time from all the other primaries.
+    HeaderWaiter::spawn(
+        name,
+        committee.clone(),
+        store,
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* gc_depth */ 50,
+        /* sync_retry_delay */ 500,
+        /* sync_retry_nodes */ 3,
+        /* max_sync_requests */ 1,
+        rx_synchronizer,
+        tx_core,
+        watch::channel(Parameters::default()).1,
+    );
+    let missing = parents.iter().map(|x| x.digest()).collect();
+    tx_synchronizer
+        .send(WaiterMessage::SyncParents(missing, header.clone()))
+        .await
+        .unwrap();
+
+    // The header is looped back once both parents are synced, after one retry for each of them.
+    let synced = tokio::time::timeout(Duration::from_millis(5_000), rx_core.recv())
+        .await
+        .expect("The parents were not synced in time")
+        .unwrap();
+    assert_eq!(synced.id, header.id);
+    let mut requests = peer.await.unwrap();
+    assert!(requests.iter().all(|x| x.len() == 1));
+    let mut requested: Vec<_> = requests.drain(..).flatten().collect();
+    let mut expected: Vec<_> = parents.iter().map(|x| x.digest()).collect();
+    requested.sort();
+    expected.sort();
+    assert_eq!(requested, expected);
+}
+
+#[tokio::test]
+async fn cap_outstanding_certificate_requests() {
+    let (name, _) = keys().remove(0);
+    let committee = committee_with_base_port(17_000);
+    let store = MemoryStore::new();
+    let (tx_synchronizer, rx_synchronizer) = channel(1);
+    let (tx_core, _rx_core) = channel(1);
+
+    // The header misses two parents, that its author has.
+    let header = header();
+    let parents: Vec<_> = headers()
+        .iter()
+        .skip(1)
+        .take(2)
+        .map(|x| certificate(x))
+        .collect();
+    let address = committee
+        .primary(&header.author)
+        .unwrap()
+        .primary_to_primary;
+    let peer = peer(
+        address,
+        parents.clone(),
+        store.clone(),
+        /* expected */ 1,
+    );
+
+    // Wait for at most one certificate at a time (and never retry).
+    HeaderWaiter::spawn(
+        name,
+        committee.clone(),
+        store,
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* gc_depth */ 50,
+        /* sync_retry_delay */ 1_000_000,
+        /* sync_retry_nodes */ 3,
+        /* max_sync_requests */ 1,
+        rx_synchronizer,
+        tx_core,
+        watch::channel(Parameters::default()).1,
+    );
+    let missing = parents.iter().map(|x| x.digest()).collect();
+    tx_synchronizer
+        .send(WaiterMessage::SyncParents(missing, header))
+        .await
+        .unwrap();
+
+    // The author is only asked for the first parent.
+    let requests = peer.await.unwrap();
+    assert_eq!(requests, vec![vec![parents[0].digest()]]);
+}
@@ -0,0 +1,38 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{committee, keys};
+
+#[test]
+fn suspect_absent_authorities() {
+    let committee = committee();
+    let names: Vec<_> = keys().into_iter().map(|(name, _)| name).collect();
+    let health = PeersHealth::default();
+    let advance = |round: Round, authors: &[PublicKey]| {
+        for name in authors {
+            health.certificate_received(*name, round);
+        }
+        health.update_suspects(&committee, /* max_absent_rounds */ 3)
+    };
+
+    // The last authority stops contributing after round 2.
+    for round in 1..=2 {
+        assert!(advance(round, &names).is_empty());
+    }
+    for round in 3..=5 {
+        assert!(advance(round, &names[..3]).is_empty());
+    }
+    assert_eq!(
+        advance(6, &names[..3]),
+        vec![SuspectEvent::Suspected(names[3], 2)]
+    );
+    assert!(advance(7, &names[..3]).is_empty());
+    assert_eq!(health.suspects(), vec![(names[3], 2)]);
+
+    // A late certificate of an old round does not clear the suspicion, a recent one does.
+    assert!(advance(3, &names[3..]).is_empty());
+    assert_eq!(
+        advance(7, &names[3..]),
+        vec![SuspectEvent::Cleared(names[3], 7)]
+    );
+    assert!(health.suspects().is_empty());
+}
@@ -0,0 +1,44 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+
+#[test]
+fn evict_least_recently_used() {
+    let mut cache = LruCache::new(2);
+    cache.insert(1, "a");
+    cache.insert(2, "b");
+    assert_eq!(cache.get(&1), Some(&"a"));
+
+    // Key 2 is now the least recently used.
+    cache.insert(3, "c");
+    assert_eq!(cache.len(), 2);
+    assert!(!cache.contains(&2));
+    assert_eq!(cache.get(&1), Some(&"a"));
+    assert_eq!(cache.get(&3), Some(&"c"));
+
+    // Re-inserting a key refreshes it without growing the cache.
+    cache.insert(1, "d");
+    cache.insert(4, "e");
+    assert_eq!(cache.get(&1), Some(&"d"));
+    assert!(!cache.contains(&3));
+}
+
+#[test]
+fn stay_bounded_under_sustained_load() {
+    let mut cache = LruCache::new(100);
+    for i in 0..100_000u64 {
+        cache.insert(i, vec![0u8; 64]);
+        // Keep reading an old entry: it is never evicted.
+        assert!(cache.get(&0).is_some());
+        assert!(cache.len() <= 100);
+    }
+    assert_eq!(cache.order.len(), cache.len());
+    assert!(cache.contains(&0) && cache.contains(&99_999));
+}
+
+#[test]
+fn disabled_cache() {
+    let mut cache = LruCache::new(0);
+    cache.insert(1, 1);
+    assert!(cache.is_empty());
+    assert_eq!(cache.get(&1), None);
+}
@@ -0,0 +1,277 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{certificate, committee, header, headers, keys};
+use config::KeyRotation;
+use crypto::generate_keypair;
+use rand::rngs::StdRng;
+use rand::SeedableRng as _;
+
+// Signs a header with the specified key.
+fn signed(header: Header, secret: &crypto::SecretKey) -> Header {
+    let id = header.digest();
+    Header {
+        signature: Signature::new(&id, secret),
+        id,
+        ..header
+    }
+}
+
+#[test]
+fn verify_certificate() {
+    assert!(certificate(&header()).verify(&committee(), 0).is_ok());
+}
+
+#[test]
+fn verify_genesis() {
+    for x in Certificate::genesis(&committee()) {
+        assert!(x.verify(&committee(), 0).is_ok());
+    }
+}
+
+#[test]
+fn reject_bad_signature() {
+    // Swapping two signatures keeps the set of voters but breaks both votes.
+    let mut certificate = certificate(&header());
+    let signature = certificate.votes[0].1.clone();
+    certificate.votes[0].1 = certificate.votes[1].1.clone();
+    certificate.votes[1].1 = signature;
+    assert!(matches!(
+        certificate.verify(&committee(), 0),
+        Err(DagError::InvalidSignature(_))
+    ));
+}
+
+#[test]
+fn reject_bad_header_signature() {
+    let (_, secret) = keys().remove(0);
+    let header = signed(header(), &secret);
+    assert!(matches!(
+        certificate(&header).verify(&committee(), 0),
+        Err(DagError::InvalidSignature(_))
+    ));
+}
+
+#[test]
+fn reject_insufficient_stake() {
+    let mut certificate = certificate(&header());
+    certificate.votes.truncate(2);
+    assert!(matches!(
+        certificate.verify(&committee(), 0),
+        Err(DagError::CertificateRequiresQuorum)
+    ));
+}
+
+#[test]
+fn reject_reused_voter() {
+    let mut certificate = certificate(&header());
+    let vote = certificate.votes[0].clone();
+    certificate.votes[1] = vote;
+    assert!(matches!(
+        certificate.verify(&committee(), 0),
+        Err(DagError::AuthorityReuse(_))
+    ));
+}
+
+#[test]
+fn reject_unknown_voter() {
+    let (name, secret) = generate_keypair(&mut StdRng::from_seed([1; 32]));
+    let mut certificate = certificate(&header());
+    let signature = Signature::new(&certificate.digest(), &secret);
+    certificate.votes[0] = (name, signature);
+    assert!(matches!(
+        certificate.verify(&committee(), 0),
+        Err(DagError::UnknownAuthority(x)) if x == name
+    ));
+}
+
+#[test]
+fn reject_unknown_author() {
+    let (author, secret) = generate_keypair(&mut StdRng::from_seed([1; 32]));
+    let header = signed(Header { author, ..header() }, &secret);
+    assert!(matches!(
+        certificate(&header).verify(&committee(), 0),
+        Err(DagError::UnknownAuthority(x)) if x == author
+    ));
+}
+
+#[test]
+fn verify_timestamp_drift() {
+    let (_, secret) = keys().pop().unwrap();
+    let stamped = |timestamp| {
+        signed(
+            Header {
+                timestamp,
+                ..header()
+            },
+            &secret,
+        )
+    };
+    let now = 1_000_000;
+
+    // Past timestamps (of lagging authors) and a drift within the bound are accepted.
+    assert!(stamped(0).verify_timestamp(now, 500).is_ok());
+    assert!(stamped(now + 500).verify_timestamp(now, 500).is_ok());
+
+    // Beyond the bound, the header is rejected.
+    assert!(matches!(
+        stamped(now + 501).verify_timestamp(now, 500),
+        Err(DagError::HeaderFromTheFuture(_, 501))
+    ));
+}
+
+#[test]
+fn reject_tampered_header() {
+    let mut header = header();
+    header.round += 1;
+    assert!(matches!(
+        header.verify(&committee(), 0),
+        Err(DagError::InvalidHeaderId)
+    ));
+}
+
+#[test]
+fn reject_bad_parents() {
+    let (_, secret) = keys().pop().unwrap();
+
+    // Two parents cannot be a quorum of 4 authorities of equal stake.
+    let few = signed(
+        Header {
+            parents: header().parents.into_iter().take(2).collect(),
+            ..header()
+        },
+        &secret,
+    );
+    assert!(matches!(
+        few.verify(&committee(), 0),
+        Err(DagError::HeaderRequiresQuorum(_))
+    ));
+
+    // There cannot be more parents than authorities.
+    let mut parents = header().parents;
+    parents.insert(Digest([1; 32]));
+    let many = signed(
+        Header {
+            parents,
+            ..header()
+        },
+        &secret,
+    );
+    assert!(matches!(
+        many.verify(&committee(), 0),
+        Err(DagError::MalformedHeader(_))
+    ));
+}
+
+#[test]
+fn verify_parents() {
+    let genesis = Certificate::genesis(&committee());
+    assert!(header().verify_parents(&genesis, &committee()).is_ok());
+}
+
+#[test]
+fn reject_future_round_parents() {
+    // A round-1 header cannot build on round-1 certificates.
+    let mut parents = Certificate::genesis(&committee());
+    parents[0] = certificate(&headers().remove(0));
+    assert!(matches!(
+        header().verify_parents(&parents, &committee()),
+        Err(DagError::InvalidParentRound(_, 1, _, 1))
+    ));
+}
+
+#[test]
+fn reject_duplicate_parents() {
+    // Two distinct certificates of the same authority only count once.
+    let mut parents = Certificate::genesis(&committee());
+    let mut duplicate = parents[0].clone();
+    duplicate.header.payload.insert(Digest([1; 32]), 0);
+    parents[1] = duplicate;
+    assert!(matches!(
+        header().verify_parents(&parents, &committee()),
+        Err(DagError::AuthorityReuse(_))
+    ));
+}
+
+#[test]
+fn reject_insufficient_parents_stake() {
+    let parents: Vec<_> = Certificate::genesis(&committee())
+        .into_iter()
+        .take(2)
+        .collect();
+    assert!(matches!(
+        header().verify_parents(&parents, &committee()),
+        Err(DagError::HeaderRequiresQuorum(_))
+    ));
+}
+
+#[test]
+fn accept_rotated_keys_during_overlap() {
+    // The author of the header rotates to a new key, accepted from epoch 1; its old key is accepted
+    // until epoch 2.
+    let (author, old_secret) = keys().pop().unwrap();
+    let (key, new_secret) = generate_keypair(&mut StdRng::from_seed([1; 32]));
+    let mut committee = committee();
+    committee.authorities.get_mut(&author).unwrap().key_rotation = Some(KeyRotation {
+        key,
+        from_epoch: 1,
+        overlap_until: 2,
+    });
+
+    let old = signed(header(), &old_secret);
+    let new = signed(header(), &new_secret);
+    assert!(old.verify(&committee, 0).is_ok());
+    assert!(new.verify(&committee, 0).is_err());
+    assert!(old.verify(&committee, 1).is_ok());
+    assert!(new.verify(&committee, 1).is_ok());
+    assert!(old.verify(&committee, 2).is_err());
+    assert!(new.verify(&committee, 2).is_ok());
+
+    // The votes of a certificate may be signed with either key during the overlap.
+    let mut certificate = certificate(&new);
+    let signature = Signature::new(&certificate.digest(), &new_secret);
+    let vote = certificate
+        .votes
+        .iter_mut()
+        .find(|(name, _)| *name == author)
+        .unwrap();
+    vote.1 = signature;
+    assert!(certificate.verify(&committee, 1).is_ok());
+    assert!(certificate.verify(&committee, 0).is_err());
+}
+
+#[tokio::test]
+async fn verify_sync_status() {
+    let mut keys = keys();
+    let (requestor, _) = keys.pop().unwrap();
+    let (author, secret) = keys.pop().unwrap();
+    let mut signature_service = SignatureService::spawn(secret);
+    let status = SyncStatus::new(3, 10, Some(requestor), &author, &mut signature_service).await;
+    assert!(status.verify(&committee(), 0).is_ok());
+
+    // The last round cannot be inflated, and the status cannot be replayed to another primary.
+    let inflated = SyncStatus {
+        last_round: 100,
+        ..status.clone()
+    };
+    assert!(matches!(
+        inflated.verify(&committee(), 0),
+        Err(DagError::InvalidSignature(_))
+    ));
+    let replayed = SyncStatus {
+        requestor: Some(keys[0].0),
+        ..status.clone()
+    };
+    assert!(matches!(
+        replayed.verify(&committee(), 0),
+        Err(DagError::InvalidSignature(_))
+    ));
+
+    // Only the authorities of the committee are trusted.
+    let (outsider, secret) = generate_keypair(&mut StdRng::from_seed([1; 32]));
+    let mut signature_service = SignatureService::spawn(secret);
+    let status = SyncStatus::new(3, 10, Some(requestor), &outsider, &mut signature_service).await;
+    assert!(matches!(
+        status.verify(&committee(), 0),
+        Err(DagError::UnknownAuthority(_))
+    ));
+}
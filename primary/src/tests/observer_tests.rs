@@ -0,0 +1,95 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{certificate, committee_with_base_port, headers, keys};
+use crate::messages::{Header, SyncStatus};
+use crypto::Signature;
+use store::MemoryStore;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::channel;
+
+#[tokio::test]
+async fn deliver_certificates_after_their_parents() {
+    let committee = committee_with_base_port(16_000);
+    let parameters = Parameters::default();
+
+    // Make the certificates of round 1, and a certificate of round 2 on top of them.
+    let round_1: Vec<_> = headers().iter().map(certificate).collect();
+    let (author, secret) = keys().pop().unwrap();
+    let header = Header {
+        author,
+        round: 2,
+        parents: round_1.iter().map(|x| x.digest()).collect(),
+        ..Header::default()
+    };
+    let header = Header {
+        id: header.digest(),
+        signature: Signature::new(&header.digest(), &secret),
+        ..header
+    };
+    let round_2 = certificate(&header);
+
+    // Spawn a primary that first omits one of the parents of the certificate of round 2.
+    let address = committee
+        .authorities
+        .values()
+        .next()
+        .unwrap()
+        .primary
+        .primary_to_primary;
+    let mut first = round_1[..3].to_vec();
+    first.push(round_2.clone());
+    let mut all = round_1.clone();
+    all.push(round_2.clone());
+    tokio::spawn(async move {
+        let listener = TcpListener::bind(&address).await.unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+        let mut replies = vec![first].into_iter();
+        while let Some(Ok(bytes)) = transport.next().await {
+            match bincode::deserialize(&bytes).unwrap() {
+                PrimaryMessage::ObserverSyncRequest(_) => (),
+                _ => panic!("Unexpected request"),
+            }
+            let certificates = replies.next().unwrap_or_else(|| all.clone());
+            let status = SyncStatus {
+                round: 0,
+                last_round: 2,
+                requestor: None,
+                author,
+                signature: Signature::default(),
+            };
+            let reply = PrimaryMessage::StateSyncReply(certificates, status);
+            transport.send(Bytes::from("Ack")).await.unwrap();
+            transport
+                .send(Bytes::from(bincode::serialize(&reply).unwrap()))
+                .await
+                .unwrap();
+        }
+    });
+
+    // Spawn an `Observer` instance.
+    let (tx_consensus, mut rx_consensus) = channel(10);
+    let (_tx_feedback, rx_feedback) = channel(1);
+    Observer::spawn(
+        committee,
+        MemoryStore::new(),
+        &parameters,
+        watch::channel(parameters.clone()).1,
+        tx_consensus,
+        rx_feedback,
+    );
+
+    // Ensure the consensus gets all the certificates of round 1 before the one of round 2.
+    let mut received = Vec::new();
+    for _ in 0..round_1.len() {
+        received.push(rx_consensus.recv().await.unwrap().digest());
+    }
+    received.sort();
+    let mut expected: Vec<_> = round_1.iter().map(|x| x.digest()).collect();
+    expected.sort();
+    assert_eq!(received, expected);
+    assert_eq!(
+        rx_consensus.recv().await.unwrap().digest(),
+        round_2.digest()
+    );
+}
@@ -1,7 +1,8 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
-use crate::common::{committee, keys};
+use crate::common::{committee, keys, SpanRecorder};
 use tokio::sync::mpsc::channel;
+use tokio::time::timeout;
 
 #[tokio::test]
 async fn propose_empty() {
@@ -19,16 +20,19 @@ async fn propose_empty() {
         signature_service,
         /* header_size */ 1_000,
         /* max_header_delay */ 20,
+        /* max_header_parents */ usize::MAX,
+        /* min_header_batches */ 0,
         /* rx_core */ rx_parents,
         /* rx_workers */ rx_our_digests,
         /* tx_core */ tx_headers,
+        watch::channel(Parameters::default()).1,
     );
 
     // Ensure the proposer makes a correct empty header.
     let header = rx_headers.recv().await.unwrap();
     assert_eq!(header.round, 1);
     assert!(header.payload.is_empty());
-    assert!(header.verify(&committee()).is_ok());
+    assert!(header.verify(&committee(), 0).is_ok());
 }
 
 #[tokio::test]
@@ -47,9 +51,12 @@ async fn propose_payload() {
         signature_service,
         /* header_size */ 32,
         /* max_header_delay */ 1_000_000, // Ensure it is not triggered.
+        /* max_header_parents */ usize::MAX,
+        /* min_header_batches */ 0,
         /* rx_core */ rx_parents,
         /* rx_workers */ rx_our_digests,
         /* tx_core */ tx_headers,
+        watch::channel(Parameters::default()).1,
     );
 
     // Send enough digests for the header payload.
@@ -64,5 +71,165 @@ async fn propose_payload() {
     let header = rx_headers.recv().await.unwrap();
     assert_eq!(header.round, 1);
     assert_eq!(header.payload.get(&digest), Some(&worker_id));
-    assert!(header.verify(&committee()).is_ok());
+    assert!(header.verify(&committee(), 0).is_ok());
+}
+
+#[tokio::test]
+async fn propose_after_delay_with_few_parents() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::spawn(secret);
+
+    let (tx_parents, rx_parents) = channel(1);
+    let (_tx_our_digests, rx_our_digests) = channel(1);
+    let (tx_headers, mut rx_headers) = channel(1);
+
+    // Spawn the proposer.
+    Proposer::spawn(
+        name,
+        &committee(),
+        signature_service,
+        /* header_size */ 1_000,
+        /* max_header_delay */ 200,
+        /* max_header_parents */ 4,
+        /* min_header_batches */ 0,
+        /* rx_core */ rx_parents,
+        /* rx_workers */ rx_our_digests,
+        /* tx_core */ tx_headers,
+        watch::channel(Parameters::default()).1,
+    );
+
+    // The first header references the 4 genesis certificates: it is created right away.
+    let header = rx_headers.recv().await.unwrap();
+    let start = Instant::now();
+    assert_eq!(header.round, 1);
+    assert_eq!(header.parents.len(), 4);
+
+    // Send a quorum of parents (but less than `max_header_parents`) and no payload.
+    let parents: Vec<_> = (0..3).map(|i| Digest([i; 32])).collect();
+    tx_parents.send((parents, 1)).await.unwrap();
+
+    // Ensure the proposer creates the next header once the delay elapsed.
+    let header = rx_headers.recv().await.unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(150));
+    assert_eq!(header.round, 2);
+    assert_eq!(header.parents.len(), 3);
+    assert!(header.payload.is_empty());
+}
+
+#[tokio::test]
+async fn propose_with_late_parents() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::spawn(secret);
+
+    let (tx_parents, rx_parents) = channel(1);
+    let (_tx_our_digests, rx_our_digests) = channel(1);
+    let (tx_headers, mut rx_headers) = channel(1);
+
+    // Spawn the proposer.
+    Proposer::spawn(
+        name,
+        &committee(),
+        signature_service,
+        /* header_size */ 1_000,
+        /* max_header_delay */ 1_000_000, // Ensure it is not triggered.
+        /* max_header_parents */ 4,
+        /* min_header_batches */ 0,
+        /* rx_core */ rx_parents,
+        /* rx_workers */ rx_our_digests,
+        /* tx_core */ tx_headers,
+        watch::channel(Parameters::default()).1,
+    );
+    let header = rx_headers.recv().await.unwrap();
+    assert_eq!(header.round, 1);
+
+    // Send a quorum of parents, then a late parent: the header is created once it has 4 parents.
+    let parents: Vec<_> = (0..3).map(|i| Digest([i; 32])).collect();
+    tx_parents.send((parents, 1)).await.unwrap();
+    tx_parents.send((vec![Digest([3; 32])], 1)).await.unwrap();
+
+    let header = rx_headers.recv().await.unwrap();
+    assert_eq!(header.round, 2);
+    assert_eq!(header.parents.len(), 4);
+}
+
+#[tokio::test]
+async fn propose_min_header_batches() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::spawn(secret);
+
+    let (tx_parents, rx_parents) = channel(1);
+    let (tx_our_digests, rx_our_digests) = channel(10);
+    let (tx_headers, mut rx_headers) = channel(1);
+
+    // Spawn the proposer.
+    Proposer::spawn(
+        name,
+        &committee(),
+        signature_service,
+        /* header_size */ 1_000,
+        /* max_header_delay */ 1_000_000, // Ensure it is not triggered.
+        /* max_header_parents */ 3,
+        /* min_header_batches */ 3,
+        /* rx_core */ rx_parents,
+        /* rx_workers */ rx_our_digests,
+        /* tx_core */ tx_headers,
+        watch::channel(Parameters::default()).1,
+    );
+
+    // The genesis parents are enough for the first header, but it waits for three batches.
+    let digests: Vec<_> = (0..3).map(|i| Digest([i; 32])).collect();
+    for digest in &digests[..2] {
+        tx_our_digests.send((digest.clone(), 0)).await.unwrap();
+    }
+    let result = timeout(Duration::from_millis(200), rx_headers.recv()).await;
+    assert!(result.is_err());
+
+    tx_our_digests.send((digests[2].clone(), 0)).await.unwrap();
+    let header = rx_headers.recv().await.unwrap();
+    assert_eq!(header.round, 1);
+    assert!(header.payload.len() >= 3);
+    assert!(digests.iter().all(|x| header.payload.contains_key(x)));
+
+    // With enough batches available, the next header also carries at least three of them.
+    for i in 3..6 {
+        tx_our_digests.send((Digest([i; 32]), 0)).await.unwrap();
+    }
+    let parents: Vec<_> = (0..3).map(|i| Digest([i; 32])).collect();
+    tx_parents.send((parents, 1)).await.unwrap();
+    let header = rx_headers.recv().await.unwrap();
+    assert_eq!(header.round, 2);
+    assert!(header.payload.len() >= 3);
+}
+
+#[tokio::test]
+async fn trace_proposed_headers() {
+    // Record the spans of the proposer (which runs on the thread of the test).
+    let recorder = SpanRecorder::default();
+    let _guard = tracing::subscriber::set_default(recorder.clone());
+
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::spawn(secret);
+
+    let (_tx_parents, rx_parents) = channel(1);
+    let (_tx_our_digests, rx_our_digests) = channel(1);
+    let (tx_headers, mut rx_headers) = channel(1);
+
+    // Spawn the proposer.
+    Proposer::spawn(
+        name,
+        &committee(),
+        signature_service,
+        /* header_size */ 1_000,
+        /* max_header_delay */ 20,
+        /* max_header_parents */ usize::MAX,
+        /* min_header_batches */ 0,
+        /* rx_core */ rx_parents,
+        /* rx_workers */ rx_our_digests,
+        /* tx_core */ tx_headers,
+        watch::channel(Parameters::default()).1,
+    );
+
+    // The trace of the header starts with its proposal, keyed by its digest once it is known.
+    let header = rx_headers.recv().await.unwrap();
+    assert_eq!(recorder.headers("propose"), vec![header.id.to_string()]);
 }
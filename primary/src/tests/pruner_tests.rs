@@ -0,0 +1,124 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{committee_with_base_port, keys, listener};
+use crate::messages::Header;
+use crypto::Hash as _;
+use store::MemoryStore;
+use tokio::sync::mpsc::channel;
+use tokio::time::{sleep, timeout, Duration};
+
+/// A certificate of `author` for `round`, whose payload is a batch of worker 0.
+fn certificate(author: PublicKey, round: Round, batch: Digest) -> Certificate {
+    let header = Header {
+        author,
+        round,
+        payload: vec![(batch, 0)].into_iter().collect(),
+        ..Header::default()
+    };
+    let header = Header {
+        id: header.digest(),
+        ..header
+    };
+    Certificate {
+        header,
+        ..Certificate::default()
+    }
+}
+
+/// The keys of the header, the certificate, and the batch of `certificate` in our store.
+fn stored_keys(certificate: &Certificate) -> Vec<Vec<u8>> {
+    IndexEntry::new(certificate).keys
+}
+
+#[tokio::test]
+async fn prune_below_gc_round() {
+    let (name, _) = keys().pop().unwrap();
+    let committee = committee_with_base_port(14_000);
+    let gc_depth = 10;
+    let margin = 5;
+
+    // Listen for the batches our worker is commanded to delete.
+    let address = committee.worker(&name, &0).unwrap().primary_to_worker;
+    let handle = listener(address);
+
+    // Spawn the pruner.
+    let store = MemoryStore::new();
+    let (tx_committed, rx_committed) = channel(1);
+    Pruner::spawn(
+        name,
+        &committee,
+        store.clone(),
+        gc_depth,
+        margin,
+        rx_committed,
+    );
+
+    // Store the certificates (and batches) of 100 rounds as the core would, and commit those of all
+    // authorities but the last one: its certificates are orphans.
+    let mut writer = store.clone();
+    let mut round_index = RoundIndex::new(store.clone(), &committee);
+    let mut certificates = Vec::new();
+    for round in 1..=100 {
+        for (i, (author, _)) in keys().into_iter().enumerate() {
+            let mut batch = [0; 32];
+            batch[0] = round as u8;
+            batch[1] = i as u8;
+            let certificate = certificate(author, round, Digest(batch));
+            let keys = stored_keys(&certificate);
+            writer.write(keys[0].clone(), vec![0; 100]).await;
+            let bytes = bincode::serialize(&certificate).unwrap();
+            writer.write(keys[1].clone(), bytes).await;
+            writer.write(keys[2].clone(), Vec::default()).await;
+            round_index.insert(&certificate).await.unwrap();
+
+            if i < 3 {
+                tx_committed.send(certificate.clone()).await.unwrap();
+            }
+            certificates.push(certificate);
+        }
+    }
+
+    // The last committed round is 100: the rounds below 85 get pruned.
+    let boundary = 100 - gc_depth - margin;
+    let mut reader = store.clone();
+    let pruned = async {
+        loop {
+            let watermark = reader.read(WATERMARK_KEY.to_vec()).await.unwrap();
+            if watermark.map(|x| bincode::deserialize::<Round>(&x).unwrap()) == Some(boundary) {
+                break;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+    };
+    assert!(timeout(Duration::from_secs(5), pruned).await.is_ok());
+
+    // The data of the certificates below the boundary is deleted whether they were committed or not,
+    // and the rest is kept.
+    for certificate in &certificates {
+        let expected = certificate.round() >= boundary;
+        for key in stored_keys(certificate) {
+            let found = reader.read(key).await.unwrap().is_some();
+            assert_eq!(found, expected, "round {}", certificate.round());
+        }
+    }
+    for round in 1..=100 {
+        let expected = if round >= boundary { 4 } else { 0 };
+        let indexed = round_index.certificates(round).await.unwrap();
+        assert_eq!(indexed.len(), expected, "round {}", round);
+    }
+
+    // Our worker is first commanded to delete the batches of round 1, including the orphan one.
+    let message: PrimaryWorkerMessage = bincode::deserialize(&handle.await.unwrap()).unwrap();
+    match message {
+        PrimaryWorkerMessage::Prune(mut digests) => {
+            let mut expected: Vec<_> = certificates[..4]
+                .iter()
+                .flat_map(|x| x.header.payload.keys().cloned())
+                .collect();
+            digests.sort();
+            expected.sort();
+            assert_eq!(digests, expected);
+        }
+        _ => panic!("Unexpected message"),
+    }
+}
@@ -0,0 +1,61 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{committee, keys};
+use crate::messages::Header;
+use store::MemoryStore;
+
+// Fixture
+fn certificates(round: Round) -> Vec<Certificate> {
+    keys()
+        .into_iter()
+        .map(|(author, _)| Certificate {
+            header: Header {
+                author,
+                round,
+                ..Header::default()
+            },
+            ..Certificate::default()
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn round_index() {
+    let mut store = MemoryStore::new();
+    let mut index = RoundIndex::new(store.clone(), &committee());
+    assert_eq!(index.last_round().await.unwrap(), 0);
+
+    // Store and index the certificates of rounds 1 to 3, the most recent first.
+    for round in (1..=3).rev() {
+        for certificate in certificates(round) {
+            let bytes = bincode::serialize(&certificate).unwrap();
+            store.write(certificate.digest().to_vec(), bytes).await;
+            index.insert(&certificate).await.unwrap();
+        }
+    }
+
+    // The last round never decreases, and we get all the certificates of each round.
+    assert_eq!(index.last_round().await.unwrap(), 3);
+    let mut received = index.certificates(2).await.unwrap();
+    let mut expected = certificates(2);
+    received.sort_by_key(|x| x.digest());
+    expected.sort_by_key(|x| x.digest());
+    assert_eq!(received, expected);
+    assert!(index.certificates(4).await.unwrap().is_empty());
+}
+
+#[test]
+fn quorum_round_needs_a_quorum() {
+    let committee = committee();
+    let names: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+
+    // Two authorities (out of four) are not a quorum.
+    let mut rounds: HashMap<_, _> = vec![(names[0], 10), (names[1], 8)].into_iter().collect();
+    assert_eq!(quorum_round(&rounds, &committee), None);
+
+    // Three authorities reached round 5.
+    rounds.insert(names[2], 5);
+    assert_eq!(quorum_round(&rounds, &committee), Some(5));
+    rounds.insert(names[3], 7);
+    assert_eq!(quorum_round(&rounds, &committee), Some(7));
+}
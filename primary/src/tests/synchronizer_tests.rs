@@ -0,0 +1,123 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{certificate, committee, headers, keys};
+use crate::primary::Round;
+use crypto::Hash as _;
+use store::{MemoryStore, Store};
+use tokio::sync::mpsc::channel;
+
+#[tokio::test]
+async fn read_evicted_parents_from_store() {
+    let (name, _) = keys().pop().unwrap();
+    let mut store = MemoryStore::new();
+    let (tx_header_waiter, _rx_header_waiter) = channel(1);
+    let (tx_certificate_waiter, _rx_certificate_waiter) = channel(1);
+    let mut synchronizer = Synchronizer::new(
+        name,
+        &committee(),
+        store.clone(),
+        /* max_cached_certificates */ 1,
+        tx_header_waiter,
+        tx_certificate_waiter,
+    );
+
+    // Store (and cache) the certificates of round 1: only the last one stays in the cache.
+    let parents: Vec<_> = headers().iter().map(certificate).collect();
+    for x in &parents {
+        let bytes = bincode::serialize(x).unwrap();
+        store.write(x.digest().to_vec(), bytes).await;
+        synchronizer.cache_certificate(x);
+    }
+    assert_eq!(synchronizer.certificates.len(), 1);
+
+    // The evicted parents are read again from the store, without growing the cache.
+    let header = Header {
+        round: 2,
+        parents: parents.iter().map(|x| x.digest()).collect(),
+        ..Header::default()
+    };
+    let mut found = synchronizer.get_parents(&header).await.unwrap();
+    let mut expected = parents;
+    found.sort_by_key(|x| x.digest());
+    expected.sort_by_key(|x| x.digest());
+    assert_eq!(found, expected);
+    assert_eq!(synchronizer.certificates.len(), 1);
+    assert!(synchronizer
+        .deliver_certificate(&expected[0])
+        .await
+        .unwrap());
+}
+
+// Run with `cargo test --release catch_up_rate -- --ignored --nocapture`.
+#[tokio::test]
+#[ignore]
+async fn catch_up_rate() {
+    const ROUNDS: Round = 2_000;
+    let path = ".db_test_catch_up_rate";
+    let _ = std::fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+    let (name, _) = keys().pop().unwrap();
+    let (tx_header_waiter, _rx_header_waiter) = channel(1);
+    let (tx_certificate_waiter, _rx_certificate_waiter) = channel(1);
+    let mut synchronizer = Synchronizer::new(
+        name,
+        &committee(),
+        store.clone(),
+        /* max_cached_certificates */ 1,
+        tx_header_waiter,
+        tx_certificate_waiter,
+    );
+
+    // Store the certificates of every round, as if received while catching up.
+    let mut headers = Vec::new();
+    let mut parents: Vec<Digest> = Vec::new();
+    for round in 1..=ROUNDS {
+        let mut digests = Vec::new();
+        for (author, _) in keys() {
+            let header = Header {
+                author,
+                round,
+                parents: parents.iter().cloned().collect(),
+                ..Header::default()
+            };
+            let header = Header {
+                id: header.digest(),
+                ..header
+            };
+            let certificate = Certificate {
+                header: header.clone(),
+                ..Certificate::default()
+            };
+            let bytes = bincode::serialize(&certificate).unwrap();
+            store.write(certificate.digest().to_vec(), bytes).await;
+            digests.push(certificate.digest());
+            headers.push(header);
+        }
+        parents = digests;
+    }
+    let headers: Vec<_> = headers.into_iter().filter(|x| x.round > 1).collect();
+
+    // Before: the parents are read one key at a time.
+    let start = std::time::Instant::now();
+    for header in &headers {
+        for digest in &header.parents {
+            let bytes = store.read(digest.to_vec()).await.unwrap().unwrap();
+            let _: Certificate = bincode::deserialize(&bytes).unwrap();
+        }
+    }
+    println!(
+        "read: {:.0} headers/s",
+        headers.len() as f64 / start.elapsed().as_secs_f64()
+    );
+
+    // After: the synchronizer reads all the parents of a header at once.
+    let start = std::time::Instant::now();
+    for header in &headers {
+        let found = synchronizer.get_parents(header).await.unwrap();
+        assert_eq!(found.len(), header.parents.len());
+    }
+    println!(
+        "read_many: {:.0} headers/s",
+        headers.len() as f64 / start.elapsed().as_secs_f64()
+    );
+}
@@ -0,0 +1,129 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{certificate, committee, header, headers, keys};
+use crate::messages::Header;
+use crypto::Hash as _;
+use crypto::Signature;
+use tokio::sync::mpsc::channel;
+
+#[tokio::test]
+async fn forward_verified_messages_in_order() {
+    let (tx_primaries, rx_primaries) = channel(10);
+    let (tx_core, mut rx_core) = channel(10);
+    Verifier::spawn(
+        committee(),
+        /* threads */ 2,
+        rx_primaries,
+        tx_core,
+        watch::channel(Parameters::default()).1,
+    );
+
+    // Interleave headers, a certificate, and a tampered header.
+    let tampered = Header {
+        round: 2,
+        ..header()
+    };
+    let certificate = certificate(&header());
+    let mut messages: Vec<_> = headers().into_iter().map(PrimaryMessage::Header).collect();
+    messages.insert(1, PrimaryMessage::Header(tampered));
+    messages.insert(3, PrimaryMessage::Certificate(certificate.clone()));
+    for message in messages {
+        tx_primaries.send(message).await.unwrap();
+    }
+
+    // The tampered header is dropped, the other messages come out in the order they came in.
+    let mut expected: Vec<_> = headers().into_iter().map(PrimaryMessage::Header).collect();
+    expected.insert(2, PrimaryMessage::Certificate(certificate));
+    for expected in expected {
+        match (rx_core.recv().await.unwrap(), expected) {
+            (PrimaryMessage::Header(x), PrimaryMessage::Header(y)) => assert_eq!(x, y),
+            (PrimaryMessage::Certificate(x), PrimaryMessage::Certificate(y)) => assert_eq!(x, y),
+            (x, _) => panic!("Unexpected message: {:?}", x),
+        }
+    }
+    drop(tx_primaries);
+    assert!(rx_core.recv().await.is_none());
+}
+
+#[tokio::test]
+async fn drop_headers_from_the_future() {
+    let (tx_primaries, rx_primaries) = channel(10);
+    let (tx_core, mut rx_core) = channel(10);
+    let parameters = Parameters {
+        max_header_drift: 1_000,
+        ..Parameters::default()
+    };
+    Verifier::spawn(
+        committee(),
+        /* threads */ 1,
+        rx_primaries,
+        tx_core,
+        watch::channel(parameters).1,
+    );
+
+    // A header timestamped an hour from now, properly signed by its author.
+    let (_, secret) = keys().pop().unwrap();
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+    let future = Header {
+        timestamp: now + 3_600_000,
+        ..header()
+    };
+    let id = future.digest();
+    let future = Header {
+        signature: Signature::new(&id, &secret),
+        id,
+        ..future
+    };
+    tx_primaries
+        .send(PrimaryMessage::Header(future))
+        .await
+        .unwrap();
+    tx_primaries
+        .send(PrimaryMessage::Header(header()))
+        .await
+        .unwrap();
+
+    // Only the header of the past reaches the core.
+    match rx_core.recv().await.unwrap() {
+        PrimaryMessage::Header(x) => assert_eq!(x, header()),
+        x => panic!("Unexpected message: {:?}", x),
+    }
+    drop(tx_primaries);
+    assert!(rx_core.recv().await.is_none());
+}
+
+// Run with `cargo test --release ingestion_rate -- --ignored --nocapture` (on a multi-core machine).
+#[tokio::test]
+#[ignore]
+async fn ingestion_rate() {
+    const CERTIFICATES: usize = 2_000;
+    for threads in &[1, 2, 4, 8] {
+        let (tx_primaries, rx_primaries) = channel(CERTIFICATES);
+        let (tx_core, mut rx_core) = channel(CERTIFICATES);
+        Verifier::spawn(
+            committee(),
+            *threads,
+            rx_primaries,
+            tx_core,
+            watch::channel(Parameters::default()).1,
+        );
+
+        let certificate = certificate(&header());
+        let start = std::time::Instant::now();
+        for _ in 0..CERTIFICATES {
+            let message = PrimaryMessage::Certificate(certificate.clone());
+            tx_primaries.send(message).await.unwrap();
+        }
+        for _ in 0..CERTIFICATES {
+            rx_core.recv().await.unwrap();
+        }
+        println!(
+            "{} threads: {:.0} certificates/s",
+            threads,
+            CERTIFICATES as f64 / start.elapsed().as_secs_f64()
+        );
+    }
+}
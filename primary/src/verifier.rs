@@ -0,0 +1,113 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::error::DagResult;
+use crate::primary::PrimaryMessage;
+use config::{Committee, Epoch, Parameters};
+use futures::stream::FuturesOrdered;
+use futures::stream::StreamExt as _;
+use log::warn;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::watch;
+use tokio::task::{spawn_blocking, JoinHandle};
+
+#[cfg(test)]
+#[path = "tests/verifier_tests.rs"]
+pub mod verifier_tests;
+
+/// Verifies the signatures of the headers and certificates received from the other primaries on the blocking
+/// threads of the runtime, so that a single task does not bound the rate at which we ingest them. The messages
+/// are handed to the `Core` in the order in which they were received, whatever the order in which their
+/// verification completes; the invalid ones are dropped.
+pub struct Verifier {
+    /// The committee information.
+    committee: Arc<Committee>,
+    /// The maximum number of messages being verified (or waiting for the messages received before them).
+    threads: usize,
+    /// Receives the messages of the other primaries.
+    rx_primaries: Receiver<PrimaryMessage>,
+    /// Outputs the verified messages to the `Core`.
+    tx_core: Sender<PrimaryMessage>,
+    /// Tells the signing epoch, which selects the keys we accept from the other authorities.
+    rx_parameters: watch::Receiver<Parameters>,
+    /// How far in the future the headers may be timestamped (in ms).
+    max_header_drift: u64,
+}
+
+impl Verifier {
+    pub fn spawn(
+        committee: Committee,
+        threads: usize,
+        rx_primaries: Receiver<PrimaryMessage>,
+        tx_core: Sender<PrimaryMessage>,
+        rx_parameters: watch::Receiver<Parameters>,
+    ) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let max_header_drift = rx_parameters.borrow().max_header_drift;
+            Self {
+                committee: Arc::new(committee),
+                threads: threads.max(1),
+                rx_primaries,
+                tx_core,
+                rx_parameters,
+                max_header_drift,
+            }
+            .run()
+            .await;
+        })
+    }
+
+    /// Verifies the signatures of a message, and that headers are not timestamped more than `max_drift` ms
+    /// after `now`. Votes are verified by the `Core`, which first checks that they are for our current
+    /// header (and drops the others without verifying them). The timestamps of the headers embedded in
+    /// certificates are not checked: a quorum already accepted them.
+    fn verify(
+        message: &PrimaryMessage,
+        committee: &Committee,
+        epoch: Epoch,
+        now: u64,
+        max_drift: u64,
+    ) -> DagResult<()> {
+        match message {
+            PrimaryMessage::Header(header) => {
+                header.verify_timestamp(now, max_drift)?;
+                header.verify(committee, epoch)
+            }
+            PrimaryMessage::Certificate(certificate) => certificate.verify(committee, epoch),
+            _ => Ok(()),
+        }
+    }
+
+    async fn run(&mut self) {
+        let mut pending = FuturesOrdered::new();
+        loop {
+            tokio::select! {
+                Some(message) = self.rx_primaries.recv(), if pending.len() < self.threads => {
+                    let committee = self.committee.clone();
+                    let epoch = self.rx_parameters.borrow().signing_epoch;
+                    let now = SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .map(|x| x.as_millis() as u64)
+                        .unwrap_or_default();
+                    let max_drift = self.max_header_drift;
+                    pending.push(spawn_blocking(move || {
+                        let result = Self::verify(&message, &committee, epoch, now, max_drift);
+                        (message, result)
+                    }));
+                },
+                Some(result) = pending.next() => {
+                    let (message, result) = result.expect("Failed to verify a message");
+                    match result {
+                        Ok(()) => self
+                            .tx_core
+                            .send(message)
+                            .await
+                            .expect("Failed to send message to the core"),
+                        Err(e) => warn!("{}", e),
+                    }
+                },
+                else => break,
+            }
+        }
+    }
+}
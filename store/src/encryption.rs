@@ -0,0 +1,164 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::{StoreError, StoreResult, Value};
+use aes::{Aes256, Block, BlockEncrypt, NewBlockCipher};
+use aes_gcm::aead::generic_array::GenericArray;
+use aes_gcm::aead::{Aead, NewAead, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// The size of the salt prefixed to every encrypted value, from which the store key derives the subkey the
+/// value is encrypted with.
+const SALT_SIZE: usize = 15;
+
+/// The size of the AES-GCM nonce following the salt.
+const NONCE_SIZE: usize = 12;
+
+/// The key holding a value encrypted by the store key, to check that a store is opened with the key it
+/// was written with (and that an encrypted store is not opened without key). Like the schema version, it
+/// cannot collide with the keys of the primary and the workers.
+pub const ENCRYPTION_CHECK_KEY: &[u8] = b"\0store-encryption-check";
+
+/// The plaintext of the value under `ENCRYPTION_CHECK_KEY`.
+const ENCRYPTION_CHECK_VALUE: &[u8] = b"narwhal-store";
+
+/// The 256-bit key with which a store encrypts its values. Files and environment variables hold it in
+/// base64.
+#[derive(Clone)]
+pub struct StoreKey([u8; 32]);
+
+impl StoreKey {
+    /// Generates a fresh random key.
+    pub fn generate() -> Self {
+        Self(rand::random())
+    }
+
+    pub fn encode_base64(&self) -> String {
+        base64::encode(&self.0)
+    }
+
+    pub fn decode_base64(data: &str) -> io::Result<Self> {
+        let bytes = base64::decode(data.trim())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if bytes.len() != 32 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("a store key has 32 bytes, not {}", bytes.len()),
+            ));
+        }
+        let mut key = [0; 32];
+        key.copy_from_slice(&bytes);
+        Ok(Self(key))
+    }
+
+    /// Reads a key from a file holding it in base64.
+    pub fn from_file(path: &str) -> io::Result<Self> {
+        Self::decode_base64(&fs::read_to_string(path)?)
+    }
+
+    /// Writes the key to a file, in base64.
+    pub fn export(&self, path: &str) -> io::Result<()> {
+        fs::write(path, self.encode_base64())
+    }
+}
+
+impl fmt::Debug for StoreKey {
+    // Never print the key itself.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "StoreKey(..)")
+    }
+}
+
+/// Encrypts and authenticates the values of a store with AES-256-GCM. A key should encrypt at most 2^32
+/// values under random nonces, so every opening of the store derives a fresh subkey from the store key and a
+/// random salt, under which the values take counter nonces. The salt and the nonce are prefixed to the
+/// ciphertext, and the key of the value is its associated data: a value moved under another key fails to
+/// decrypt, as does a value written with another store key.
+pub(crate) struct Cipher {
+    /// AES-256 under the store key, which derives the subkeys.
+    kdf: Aes256,
+    /// The salt of the subkey we encrypt with.
+    salt: [u8; SALT_SIZE],
+    /// AES-256-GCM under that subkey.
+    current: Aes256Gcm,
+    /// The number of values encrypted under that subkey (the counter of the next nonce).
+    counter: AtomicU64,
+}
+
+impl Cipher {
+    pub fn new(key: &StoreKey) -> Self {
+        let kdf = Aes256::new(GenericArray::from_slice(&key.0));
+        let salt: [u8; SALT_SIZE] = rand::random();
+        let current = derive(&kdf, &salt);
+        Self {
+            kdf,
+            salt,
+            current,
+            counter: AtomicU64::new(0),
+        }
+    }
+
+    pub fn encrypt(&self, key: &[u8], value: &[u8]) -> Value {
+        let mut nonce = [0; NONCE_SIZE];
+        nonce[4..].copy_from_slice(&self.counter.fetch_add(1, Ordering::Relaxed).to_be_bytes());
+        let payload = Payload {
+            msg: value,
+            aad: key,
+        };
+        let ciphertext = self
+            .current
+            .encrypt(Nonce::from_slice(&nonce), payload)
+            .expect("Failed to encrypt a store value");
+        [&self.salt[..], &nonce[..], &ciphertext].concat()
+    }
+
+    pub fn decrypt(&self, key: &[u8], value: &[u8]) -> StoreResult<Value> {
+        if value.len() < SALT_SIZE + NONCE_SIZE {
+            return Err(StoreError::Decryption);
+        }
+        let (salt, rest) = value.split_at(SALT_SIZE);
+        let (nonce, ciphertext) = rest.split_at(NONCE_SIZE);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: key,
+        };
+        // The values written since the store was opened use the current subkey.
+        let derived;
+        let cipher = if salt == &self.salt[..] {
+            &self.current
+        } else {
+            derived = derive(&self.kdf, salt);
+            &derived
+        };
+        cipher
+            .decrypt(Nonce::from_slice(nonce), payload)
+            .map_err(|_| StoreError::Decryption)
+    }
+
+    /// The value to write under `ENCRYPTION_CHECK_KEY`.
+    pub fn check_value(&self) -> Value {
+        self.encrypt(ENCRYPTION_CHECK_KEY, ENCRYPTION_CHECK_VALUE)
+    }
+
+    /// Whether `value` (read under `ENCRYPTION_CHECK_KEY`) was written with our key.
+    pub fn verify_check_value(&self, value: &[u8]) -> bool {
+        self.decrypt(ENCRYPTION_CHECK_KEY, value)
+            .map_or(false, |x| x == ENCRYPTION_CHECK_VALUE)
+    }
+}
+
+/// Derives the AES-256-GCM subkey of `salt`, with AES-256 under the store key as pseudorandom function: each
+/// half of the subkey is the encryption of the salt followed by the index of the half.
+fn derive(kdf: &Aes256, salt: &[u8]) -> Aes256Gcm {
+    let mut subkey = [0; 32];
+    for (index, half) in subkey.chunks_mut(16).enumerate() {
+        let mut block = Block::default();
+        block[..SALT_SIZE].copy_from_slice(salt);
+        block[SALT_SIZE] = index as u8;
+        kdf.encrypt_block(&mut block);
+        half.copy_from_slice(&block);
+    }
+    Aes256Gcm::new(Key::from_slice(&subkey))
+}
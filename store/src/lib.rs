@@ -1,34 +1,127 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+use crate::encryption::{Cipher, ENCRYPTION_CHECK_KEY};
+use async_trait::async_trait;
 use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use thiserror::Error;
 use tokio::sync::mpsc::{channel, Sender};
-use tokio::sync::oneshot;
+use tokio::sync::{oneshot, watch};
 
 #[cfg(test)]
 #[path = "tests/store_tests.rs"]
 pub mod store_tests;
 
-pub type StoreError = rocksdb::Error;
-type StoreResult<T> = Result<T, StoreError>;
+mod encryption;
+mod memory;
+mod prefixed;
+mod schema;
+mod snapshot;
 
-type Key = Vec<u8>;
-type Value = Vec<u8>;
+pub use crate::encryption::StoreKey;
+pub use crate::memory::MemoryStore;
+pub use crate::prefixed::PrefixedStore;
+pub use crate::schema::{Migration, Migrations, SchemaError, SCHEMA_VERSION};
+pub use crate::snapshot::{restore, snapshot, SnapshotError, SNAPSHOT_VERSION};
+
+/// The failures of the store, for the callers reading from it.
+#[derive(Error, Debug)]
+pub enum StoreError {
+    /// The underlying database failed (eg. an I/O failure of the disk).
+    #[error(transparent)]
+    Database(#[from] rocksdb::Error),
+
+    /// A value of an encrypted store failed to decrypt (it was altered, or written with another key).
+    #[error("Failed to decrypt a value of the store")]
+    Decryption,
+}
+
+pub type StoreResult<T> = Result<T, StoreError>;
+
+pub type Key = Vec<u8>;
+pub type Value = Vec<u8>;
+
+/// A handle to a key-value store. Handles are cheap to clone and all the clones access the same data.
+#[async_trait]
+pub trait KeyValueStore: Clone + Send + Sync + 'static {
+    async fn write(&mut self, key: Key, value: Value);
+
+    async fn read(&mut self, key: Key) -> StoreResult<Option<Value>>;
+
+    /// Reads the values of many keys at once, in the order of `keys` (`None` for the missing keys).
+    async fn read_many(&mut self, keys: Vec<Key>) -> StoreResult<Vec<Option<Value>>>;
+
+    /// Reads the value of `key`, waiting for it to be written if it is not in the store yet.
+    async fn notify_read(&mut self, key: Key) -> StoreResult<Value>;
+
+    /// Persists all the writes issued before this call.
+    async fn flush(&mut self) -> StoreResult<()>;
+
+    /// Writes all the entries at once, and returns once they are durable (they survive a crash of the
+    /// process or of the machine). By default, the entries are written one by one and then flushed.
+    async fn write_synced(&mut self, entries: Vec<(Key, Value)>) -> StoreResult<()> {
+        for (key, value) in entries {
+            self.write(key, value).await;
+        }
+        self.flush().await
+    }
+
+    async fn delete(&mut self, keys: Vec<Key>);
+
+    /// Deletes all the keys in the range [`from`, `to`) (in lexicographic order).
+    async fn delete_range(&mut self, from: Key, to: Key);
+}
 
 pub enum StoreCommand {
     Write(Key, Value),
     Read(Key, oneshot::Sender<StoreResult<Option<Value>>>),
+    ReadMany(Vec<Key>, oneshot::Sender<StoreResult<Vec<Option<Value>>>>),
     NotifyRead(Key, oneshot::Sender<StoreResult<Value>>),
+    Flush(oneshot::Sender<StoreResult<()>>),
+    WriteSynced(Vec<(Key, Value)>, oneshot::Sender<StoreResult<()>>),
+    Delete(Vec<Key>),
+    DeleteRange(Key, Key),
 }
 
+/// A store persisted on disk with RocksDB. If it is opened with a `StoreKey`, its values are encrypted at
+/// rest (its keys, which are mostly digests, stay in plaintext); this is transparent to the callers.
 #[derive(Clone)]
 pub struct Store {
     channel: Sender<StoreCommand>,
+    /// Encrypts the values on their way to the database, and decrypts them on their way back.
+    cipher: Option<Arc<Cipher>>,
+    /// Tells once the database is closed (its sender is then dropped).
+    closed: watch::Receiver<()>,
 }
 
 impl Store {
-    pub fn new(path: &str) -> StoreResult<Self> {
+    /// Opens (or creates) the store at `path`, running the registered migrations if it was written by an
+    /// older version. Refuses to open a store written by a newer version.
+    pub fn new(path: &str) -> Result<Self, SchemaError> {
+        Self::with_migrations(path, &Migrations::registered())
+    }
+
+    pub fn with_migrations(path: &str, migrations: &Migrations) -> Result<Self, SchemaError> {
+        Self::open(path, migrations, None)
+    }
+
+    /// Opens (or creates) the store at `path`, encrypting its values with `key`. A store is either always
+    /// or never encrypted: this refuses plaintext stores, as well as stores encrypted with another key.
+    pub fn encrypted(path: &str, key: &StoreKey) -> Result<Self, SchemaError> {
+        Self::open(path, &Migrations::registered(), Some(key))
+    }
+
+    fn open(
+        path: &str,
+        migrations: &Migrations,
+        key: Option<&StoreKey>,
+    ) -> Result<Self, SchemaError> {
         let db = rocksdb::DB::open_default(path)?;
+        schema::check(&db, migrations, SCHEMA_VERSION)?;
+        let cipher = key.map(Cipher::new);
+        check_encryption(&db, cipher.as_ref())?;
         let mut obligations = HashMap::<_, VecDeque<oneshot::Sender<_>>>::new();
         let (tx, mut rx) = channel(100);
+        let (tx_closed, closed) = watch::channel(());
         tokio::spawn(async move {
             while let Some(command) = rx.recv().await {
                 match command {
@@ -41,11 +134,19 @@ impl Store {
                         }
                     }
                     StoreCommand::Read(key, sender) => {
-                        let response = db.get(&key);
+                        let response = db.get(&key).map_err(StoreError::from);
+                        let _ = sender.send(response);
+                    }
+                    StoreCommand::ReadMany(keys, sender) => {
+                        let response = db
+                            .multi_get(&keys)
+                            .into_iter()
+                            .collect::<Result<_, _>>()
+                            .map_err(StoreError::from);
                         let _ = sender.send(response);
                     }
                     StoreCommand::NotifyRead(key, sender) => {
-                        let response = db.get(&key);
+                        let response = db.get(&key).map_err(StoreError::from);
                         match response {
                             Ok(None) => obligations
                                 .entry(key)
@@ -56,13 +157,82 @@ impl Store {
                             }
                         }
                     }
+                    StoreCommand::Flush(sender) => {
+                        let _ = sender.send(db.flush().map_err(StoreError::from));
+                    }
+                    StoreCommand::WriteSynced(entries, sender) => {
+                        // A single batch synced to the write-ahead log: the entries are durable once it
+                        // returns, without flushing the memtables.
+                        let mut batch = rocksdb::WriteBatch::default();
+                        for (key, value) in &entries {
+                            batch.put(key, value);
+                        }
+                        let mut options = rocksdb::WriteOptions::default();
+                        options.set_sync(true);
+                        let result = db.write_opt(batch, &options).map_err(StoreError::from);
+                        if result.is_ok() {
+                            for (key, value) in entries {
+                                if let Some(mut senders) = obligations.remove(&key) {
+                                    while let Some(s) = senders.pop_front() {
+                                        let _ = s.send(Ok(value.clone()));
+                                    }
+                                }
+                            }
+                        }
+                        let _ = sender.send(result);
+                    }
+                    StoreCommand::Delete(keys) => {
+                        for key in keys {
+                            let _ = db.delete(&key);
+                        }
+                    }
+                    StoreCommand::DeleteRange(from, to) => {
+                        let mut batch = rocksdb::WriteBatch::default();
+                        batch.delete_range(&from, &to);
+                        let _ = db.write(batch);
+                    }
                 }
             }
+
+            // All the handles are dropped: release the database before telling those waiting to reopen it.
+            drop(db);
+            drop(tx_closed);
         });
-        Ok(Self { channel: tx })
+        Ok(Self {
+            channel: tx,
+            cipher: cipher.map(Arc::new),
+            closed,
+        })
+    }
+
+    /// Drops this handle and waits until the database is closed, which happens once all the handles of the
+    /// store are dropped. The store can then be opened again.
+    pub async fn close(self) {
+        let Self {
+            channel,
+            mut closed,
+            ..
+        } = self;
+        drop(channel);
+        while closed.changed().await.is_ok() {}
+    }
+
+    fn encrypt(&self, key: &[u8], value: Value) -> Value {
+        match &self.cipher {
+            Some(cipher) => cipher.encrypt(key, &value),
+            None => value,
+        }
+    }
+
+    fn decrypt(&self, key: &[u8], value: Value) -> StoreResult<Value> {
+        match &self.cipher {
+            Some(cipher) => cipher.decrypt(key, &value),
+            None => Ok(value),
+        }
     }
 
     pub async fn write(&mut self, key: Key, value: Value) {
+        let value = self.encrypt(&key, value);
         if let Err(e) = self.channel.send(StoreCommand::Write(key, value)).await {
             panic!("Failed to send Write command to store: {}", e);
         }
@@ -70,25 +240,161 @@ impl Store {
 
     pub async fn read(&mut self, key: Key) -> StoreResult<Option<Value>> {
         let (sender, receiver) = oneshot::channel();
-        if let Err(e) = self.channel.send(StoreCommand::Read(key, sender)).await {
+        if let Err(e) = self
+            .channel
+            .send(StoreCommand::Read(key.clone(), sender))
+            .await
+        {
             panic!("Failed to send Read command to store: {}", e);
         }
         receiver
             .await
-            .expect("Failed to receive reply to Read command from store")
+            .expect("Failed to receive reply to Read command from store")?
+            .map(|x| self.decrypt(&key, x))
+            .transpose()
+    }
+
+    /// Reads the values of many keys with a single multi-get, in the order of `keys` (`None` for the
+    /// missing keys).
+    pub async fn read_many(&mut self, keys: Vec<Key>) -> StoreResult<Vec<Option<Value>>> {
+        let (sender, receiver) = oneshot::channel();
+        if let Err(e) = self
+            .channel
+            .send(StoreCommand::ReadMany(keys.clone(), sender))
+            .await
+        {
+            panic!("Failed to send ReadMany command to store: {}", e);
+        }
+        receiver
+            .await
+            .expect("Failed to receive reply to ReadMany command from store")?
+            .into_iter()
+            .zip(keys)
+            .map(|(value, key)| value.map(|x| self.decrypt(&key, x)).transpose())
+            .collect()
     }
 
     pub async fn notify_read(&mut self, key: Key) -> StoreResult<Value> {
         let (sender, receiver) = oneshot::channel();
         if let Err(e) = self
             .channel
-            .send(StoreCommand::NotifyRead(key, sender))
+            .send(StoreCommand::NotifyRead(key.clone(), sender))
             .await
         {
             panic!("Failed to send NotifyRead command to store: {}", e);
         }
+        let value = receiver
+            .await
+            .expect("Failed to receive reply to NotifyRead command from store")?;
+        self.decrypt(&key, value)
+    }
+
+    /// Persists to disk all the writes issued before this call. Commands are processed in order, so every
+    /// `write` that returned before `flush` is called is covered.
+    pub async fn flush(&mut self) -> StoreResult<()> {
+        let (sender, receiver) = oneshot::channel();
+        if let Err(e) = self.channel.send(StoreCommand::Flush(sender)).await {
+            panic!("Failed to send Flush command to store: {}", e);
+        }
         receiver
             .await
-            .expect("Failed to receive reply to NotifyRead command from store")
+            .expect("Failed to receive reply to Flush command from store")
+    }
+
+    /// Writes all the entries in a single batch synced to disk: they are durable once this returns, and
+    /// either all or none of them survive a crash.
+    pub async fn write_synced(&mut self, entries: Vec<(Key, Value)>) -> StoreResult<()> {
+        let entries = entries
+            .into_iter()
+            .map(|(key, value)| {
+                let value = self.encrypt(&key, value);
+                (key, value)
+            })
+            .collect();
+        let (sender, receiver) = oneshot::channel();
+        if let Err(e) = self
+            .channel
+            .send(StoreCommand::WriteSynced(entries, sender))
+            .await
+        {
+            panic!("Failed to send WriteSynced command to store: {}", e);
+        }
+        receiver
+            .await
+            .expect("Failed to receive reply to WriteSynced command from store")
+    }
+
+    pub async fn delete(&mut self, keys: Vec<Key>) {
+        if let Err(e) = self.channel.send(StoreCommand::Delete(keys)).await {
+            panic!("Failed to send Delete command to store: {}", e);
+        }
+    }
+
+    /// Deletes all the keys in the range [`from`, `to`) (in lexicographic order).
+    pub async fn delete_range(&mut self, from: Key, to: Key) {
+        if let Err(e) = self.channel.send(StoreCommand::DeleteRange(from, to)).await {
+            panic!("Failed to send DeleteRange command to store: {}", e);
+        }
+    }
+}
+
+/// Whether `key` holds metadata of the store itself (rather than data of the primary or the workers).
+pub(crate) fn is_metadata(key: &[u8]) -> bool {
+    key == schema::SCHEMA_KEY || key == ENCRYPTION_CHECK_KEY
+}
+
+/// Checks that a freshly opened store is encrypted with the key of `cipher`, or not encrypted if there is no
+/// cipher. Stores holding nothing but their schema version are new: they are marked as encrypted if needed.
+fn check_encryption(db: &rocksdb::DB, cipher: Option<&Cipher>) -> Result<(), SchemaError> {
+    match (db.get(ENCRYPTION_CHECK_KEY)?, cipher) {
+        (Some(value), Some(cipher)) if cipher.verify_check_value(&value) => Ok(()),
+        (Some(_), Some(_)) => Err(SchemaError::WrongKey),
+        (Some(_), None) => Err(SchemaError::Encrypted),
+        (None, None) => Ok(()),
+        (None, Some(cipher)) => {
+            let empty = db
+                .iterator(rocksdb::IteratorMode::Start)
+                .all(|(key, _)| is_metadata(&key));
+            if !empty {
+                return Err(SchemaError::Plaintext);
+            }
+            db.put(ENCRYPTION_CHECK_KEY, cipher.check_value())?;
+            Ok(())
+        }
+    }
+}
+
+#[async_trait]
+impl KeyValueStore for Store {
+    async fn write(&mut self, key: Key, value: Value) {
+        Store::write(self, key, value).await
+    }
+
+    async fn read(&mut self, key: Key) -> StoreResult<Option<Value>> {
+        Store::read(self, key).await
+    }
+
+    async fn read_many(&mut self, keys: Vec<Key>) -> StoreResult<Vec<Option<Value>>> {
+        Store::read_many(self, keys).await
+    }
+
+    async fn notify_read(&mut self, key: Key) -> StoreResult<Value> {
+        Store::notify_read(self, key).await
+    }
+
+    async fn flush(&mut self) -> StoreResult<()> {
+        Store::flush(self).await
+    }
+
+    async fn write_synced(&mut self, entries: Vec<(Key, Value)>) -> StoreResult<()> {
+        Store::write_synced(self, entries).await
+    }
+
+    async fn delete(&mut self, keys: Vec<Key>) {
+        Store::delete(self, keys).await
+    }
+
+    async fn delete_range(&mut self, from: Key, to: Key) {
+        Store::delete_range(self, from, to).await
     }
 }
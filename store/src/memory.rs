@@ -0,0 +1,108 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::{Key, KeyValueStore, StoreResult, Value};
+use async_trait::async_trait;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+
+#[derive(Default)]
+struct MemoryState {
+    values: HashMap<Key, Value>,
+    /// The readers waiting for a key to be written.
+    obligations: HashMap<Key, VecDeque<oneshot::Sender<Value>>>,
+}
+
+/// A store keeping all its data in memory (and losing it when the last handle is dropped). It never
+/// fails and does not spawn any task, which makes it suited to tests.
+#[derive(Clone, Default)]
+pub struct MemoryStore {
+    state: Arc<Mutex<MemoryState>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of keys in the store.
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[async_trait]
+impl KeyValueStore for MemoryStore {
+    async fn write(&mut self, key: Key, value: Value) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(senders) = state.obligations.remove(&key) {
+            for sender in senders {
+                let _ = sender.send(value.clone());
+            }
+        }
+        state.values.insert(key, value);
+    }
+
+    async fn read(&mut self, key: Key) -> StoreResult<Option<Value>> {
+        Ok(self.state.lock().unwrap().values.get(&key).cloned())
+    }
+
+    async fn read_many(&mut self, keys: Vec<Key>) -> StoreResult<Vec<Option<Value>>> {
+        let state = self.state.lock().unwrap();
+        Ok(keys.iter().map(|x| state.values.get(x).cloned()).collect())
+    }
+
+    async fn notify_read(&mut self, key: Key) -> StoreResult<Value> {
+        let receiver = {
+            let mut state = self.state.lock().unwrap();
+            if let Some(value) = state.values.get(&key) {
+                return Ok(value.clone());
+            }
+            let (sender, receiver) = oneshot::channel();
+            state
+                .obligations
+                .entry(key)
+                .or_insert_with(VecDeque::new)
+                .push_back(sender);
+            receiver
+        };
+        Ok(receiver
+            .await
+            .expect("The memory store dropped a pending read"))
+    }
+
+    async fn flush(&mut self) -> StoreResult<()> {
+        Ok(())
+    }
+
+    async fn write_synced(&mut self, entries: Vec<(Key, Value)>) -> StoreResult<()> {
+        let mut state = self.state.lock().unwrap();
+        for (key, value) in entries {
+            if let Some(senders) = state.obligations.remove(&key) {
+                for sender in senders {
+                    let _ = sender.send(value.clone());
+                }
+            }
+            state.values.insert(key, value);
+        }
+        Ok(())
+    }
+
+    async fn delete(&mut self, keys: Vec<Key>) {
+        let mut state = self.state.lock().unwrap();
+        for key in keys {
+            state.values.remove(&key);
+        }
+    }
+
+    async fn delete_range(&mut self, from: Key, to: Key) {
+        self.state
+            .lock()
+            .unwrap()
+            .values
+            .retain(|key, _| key < &from || key >= &to);
+    }
+}
@@ -0,0 +1,70 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::{Key, KeyValueStore, StoreResult, Value};
+use async_trait::async_trait;
+use std::sync::Arc;
+
+/// A namespace of another store: all its keys are transparently prefixed, so that several components
+/// (e.g. the workers of an authority running in a single process) can share a store without colliding.
+#[derive(Clone)]
+pub struct PrefixedStore<S> {
+    inner: S,
+    prefix: Arc<Vec<u8>>,
+}
+
+impl<S: KeyValueStore> PrefixedStore<S> {
+    pub fn new(inner: S, prefix: &[u8]) -> Self {
+        Self {
+            inner,
+            prefix: Arc::new(prefix.to_vec()),
+        }
+    }
+
+    fn key(&self, key: Key) -> Key {
+        [self.prefix.as_slice(), &key].concat()
+    }
+}
+
+#[async_trait]
+impl<S: KeyValueStore> KeyValueStore for PrefixedStore<S> {
+    async fn write(&mut self, key: Key, value: Value) {
+        let key = self.key(key);
+        self.inner.write(key, value).await
+    }
+
+    async fn read(&mut self, key: Key) -> StoreResult<Option<Value>> {
+        let key = self.key(key);
+        self.inner.read(key).await
+    }
+
+    async fn read_many(&mut self, keys: Vec<Key>) -> StoreResult<Vec<Option<Value>>> {
+        let keys = keys.into_iter().map(|x| self.key(x)).collect();
+        self.inner.read_many(keys).await
+    }
+
+    async fn notify_read(&mut self, key: Key) -> StoreResult<Value> {
+        let key = self.key(key);
+        self.inner.notify_read(key).await
+    }
+
+    async fn flush(&mut self) -> StoreResult<()> {
+        self.inner.flush().await
+    }
+
+    async fn write_synced(&mut self, entries: Vec<(Key, Value)>) -> StoreResult<()> {
+        let entries = entries
+            .into_iter()
+            .map(|(key, value)| (self.key(key), value))
+            .collect();
+        self.inner.write_synced(entries).await
+    }
+
+    async fn delete(&mut self, keys: Vec<Key>) {
+        let keys = keys.into_iter().map(|x| self.key(x)).collect();
+        self.inner.delete(keys).await
+    }
+
+    async fn delete_range(&mut self, from: Key, to: Key) {
+        let (from, to) = (self.key(from), self.key(to));
+        self.inner.delete_range(from, to).await
+    }
+}
@@ -0,0 +1,114 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::StoreError;
+use std::collections::BTreeMap;
+use std::convert::TryInto as _;
+use thiserror::Error;
+
+/// The version of the key layout of the store. Bump it (and register a migration from the previous
+/// version in `Migrations::registered`) whenever the layout changes.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// The key holding the schema version of the store. It cannot collide with the keys of the primary and
+/// the workers (digests, or prefixed digests and rounds).
+pub const SCHEMA_KEY: &[u8] = b"\0store-schema-version";
+
+/// Rewrites the entries of a store from the layout of one schema version to the layout of another.
+pub type Migration = fn(&rocksdb::DB) -> Result<(), StoreError>;
+
+#[derive(Debug, Error)]
+pub enum SchemaError {
+    #[error(transparent)]
+    Store(#[from] StoreError),
+
+    #[error("Malformed schema version")]
+    Malformed,
+
+    #[error("The store has schema version {found}, newer than the version {expected} of this node (downgrades are not supported)")]
+    Newer { found: u32, expected: u32 },
+
+    #[error("No migration of the store from schema version {found} to {expected}")]
+    NoMigration { found: u32, expected: u32 },
+
+    #[error("The store is encrypted with another key")]
+    WrongKey,
+
+    #[error("The store is encrypted: it needs its key to be opened")]
+    Encrypted,
+
+    #[error("The store holds plaintext values: it cannot be opened with an encryption key")]
+    Plaintext,
+}
+
+impl From<rocksdb::Error> for SchemaError {
+    fn from(e: rocksdb::Error) -> Self {
+        Self::Store(e.into())
+    }
+}
+
+/// The migrations between schema versions, by (from, to) versions.
+#[derive(Clone, Default)]
+pub struct Migrations {
+    steps: BTreeMap<(u32, u32), Migration>,
+}
+
+impl Migrations {
+    /// The migrations shipped with this crate, run when opening a store written by an older version.
+    pub fn registered() -> Self {
+        Self::default()
+    }
+
+    /// Registers a migration from the schema version `from` to the (greater) version `to`.
+    pub fn register(mut self, from: u32, to: u32, migration: Migration) -> Self {
+        assert!(from < to, "Migrations go from a version to a greater one");
+        self.steps.insert((from, to), migration);
+        self
+    }
+
+    /// The migration from `from` closest to `target` (without overshooting it).
+    fn next(&self, from: u32, target: u32) -> Option<(u32, Migration)> {
+        self.steps
+            .range((from, from)..=(from, target))
+            .next_back()
+            .map(|((_, to), migration)| (*to, *migration))
+    }
+}
+
+/// Checks the schema version of a freshly opened store and brings it to `target`, running the migrations
+/// step by step (the version is written after each step, so an interrupted migration resumes where it
+/// stopped). Empty stores are stamped with `target`; non-empty stores without a version predate the
+/// versioning and have the layout of version 1.
+pub(crate) fn check(
+    db: &rocksdb::DB,
+    migrations: &Migrations,
+    target: u32,
+) -> Result<(), SchemaError> {
+    let mut version = match db.get(SCHEMA_KEY)? {
+        Some(bytes) => u32::from_le_bytes(
+            bytes
+                .as_slice()
+                .try_into()
+                .map_err(|_| SchemaError::Malformed)?,
+        ),
+        None if db.iterator(rocksdb::IteratorMode::Start).next().is_none() => target,
+        None => 1,
+    };
+    if version > target {
+        return Err(SchemaError::Newer {
+            found: version,
+            expected: target,
+        });
+    }
+    while version < target {
+        let (to, migration) = migrations
+            .next(version, target)
+            .ok_or(SchemaError::NoMigration {
+                found: version,
+                expected: target,
+            })?;
+        migration(db)?;
+        db.put(SCHEMA_KEY, &to.to_le_bytes())?;
+        version = to;
+    }
+    db.put(SCHEMA_KEY, &version.to_le_bytes())?;
+    Ok(())
+}
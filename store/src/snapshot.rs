@@ -0,0 +1,159 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::{is_metadata, StoreError};
+use std::convert::TryInto as _;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use thiserror::Error;
+
+/// The first bytes of every snapshot file.
+const MAGIC: &[u8; 8] = b"NWSTORE\0";
+
+/// The version of the layout of the snapshot file.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// Marks the end of the entries (in place of the length of a key).
+const END_MARKER: u32 = u32::MAX;
+
+#[derive(Debug, Error)]
+pub enum SnapshotError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    #[error(transparent)]
+    Store(#[from] StoreError),
+
+    #[error("Not a store snapshot")]
+    InvalidSnapshot,
+
+    #[error("The snapshot has version {found} but this node only reads version {expected}")]
+    UnsupportedVersion { found: u32, expected: u32 },
+
+    #[error("The snapshot is truncated")]
+    Truncated,
+
+    #[error("The store at '{0}' is not empty")]
+    NotEmpty(String),
+}
+
+impl From<rocksdb::Error> for SnapshotError {
+    fn from(e: rocksdb::Error) -> Self {
+        Self::Store(e.into())
+    }
+}
+
+/// Copies all the entries of the (closed) store at `store_path` into the file `out`, after flushing the
+/// store. The file holds a header recording the snapshot version, followed by the length-prefixed keys
+/// and values (including the schema version of the store). Returns the number of entries.
+pub fn snapshot(store_path: &str, out: &str) -> Result<u64, SnapshotError> {
+    let mut options = rocksdb::Options::default();
+    options.create_if_missing(false);
+    let db = rocksdb::DB::open(&options, store_path)?;
+    db.flush()?;
+
+    // Write to a temporary file first, so that `out` is never a partial snapshot.
+    let tmp = format!("{}.tmp", out);
+    let mut writer = BufWriter::new(File::create(&tmp)?);
+    writer.write_all(MAGIC)?;
+    writer.write_all(&SNAPSHOT_VERSION.to_le_bytes())?;
+
+    let mut count = 0;
+    let snapshot = db.snapshot();
+    for (key, value) in snapshot.iterator(rocksdb::IteratorMode::Start) {
+        write_bytes(&mut writer, &key)?;
+        write_bytes(&mut writer, &value)?;
+        if !is_metadata(&key) {
+            count += 1;
+        }
+    }
+    writer.write_all(&END_MARKER.to_le_bytes())?;
+    writer.flush()?;
+    drop(writer);
+    fs::rename(&tmp, out)?;
+    Ok(count)
+}
+
+/// Writes the entries of the snapshot file `input` into the store at `store_path`. Refuses to restore into
+/// a non-empty store unless `force` is set, in which case the current entries of the store are deleted.
+/// Returns the number of entries.
+pub fn restore(input: &str, store_path: &str, force: bool) -> Result<u64, SnapshotError> {
+    let mut reader = BufReader::new(File::open(input)?);
+    let mut magic = [0u8; 8];
+    reader
+        .read_exact(&mut magic)
+        .map_err(|_| SnapshotError::InvalidSnapshot)?;
+    if &magic != MAGIC {
+        return Err(SnapshotError::InvalidSnapshot);
+    }
+    let version = read_u32(&mut reader)?;
+    if version != SNAPSHOT_VERSION {
+        return Err(SnapshotError::UnsupportedVersion {
+            found: version,
+            expected: SNAPSHOT_VERSION,
+        });
+    }
+
+    // The schema version stamped by `Store::new` does not make a store non-empty.
+    let db = rocksdb::DB::open_default(store_path)?;
+    if db
+        .iterator(rocksdb::IteratorMode::Start)
+        .any(|(key, _)| !is_metadata(&key))
+    {
+        if !force {
+            return Err(SnapshotError::NotEmpty(store_path.to_string()));
+        }
+        let mut batch = rocksdb::WriteBatch::default();
+        for (key, _) in db.iterator(rocksdb::IteratorMode::Start) {
+            batch.delete(key);
+        }
+        db.write(batch)?;
+    }
+
+    let mut count = 0;
+    let mut batch = rocksdb::WriteBatch::default();
+    loop {
+        let length = read_u32(&mut reader)?;
+        if length == END_MARKER {
+            break;
+        }
+        let key = read_exact(&mut reader, length)?;
+        let length = read_u32(&mut reader)?;
+        let value = read_exact(&mut reader, length)?;
+        if !is_metadata(&key) {
+            count += 1;
+        }
+        batch.put(key, value);
+        if count % 1_000 == 0 {
+            db.write(std::mem::take(&mut batch))?;
+        }
+    }
+    db.write(batch)?;
+    db.flush()?;
+    Ok(count)
+}
+
+fn write_bytes<W: Write>(writer: &mut W, bytes: &[u8]) -> io::Result<()> {
+    let length: u32 = bytes
+        .len()
+        .try_into()
+        .ok()
+        .filter(|x| *x != END_MARKER)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Entry too large"))?;
+    writer.write_all(&length.to_le_bytes())?;
+    writer.write_all(bytes)
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> Result<u32, SnapshotError> {
+    let mut bytes = [0u8; 4];
+    reader
+        .read_exact(&mut bytes)
+        .map_err(|_| SnapshotError::Truncated)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_exact<R: Read>(reader: &mut R, length: u32) -> Result<Vec<u8>, SnapshotError> {
+    let mut bytes = vec![0u8; length as usize];
+    reader
+        .read_exact(&mut bytes)
+        .map_err(|_| SnapshotError::Truncated)?;
+    Ok(bytes)
+}
@@ -72,3 +72,327 @@ async fn read_notify() {
     store.write(key, value).await;
     assert!(handle.await.is_ok());
 }
+
+#[tokio::test]
+async fn flush_persists_writes() {
+    // Create new store.
+    let path = ".db_test_flush_persists_writes";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+
+    // Write value to the store and flush it.
+    let key = vec![0u8, 1u8, 2u8, 3u8];
+    let value = vec![4u8, 5u8, 6u8, 7u8];
+    store.write(key.clone(), value.clone()).await;
+    assert!(store.flush().await.is_ok());
+
+    // Ensure the value is read back from disk once the store is opened again.
+    store.close().await;
+    let mut store = Store::new(path).unwrap();
+    let result = store.read(key).await;
+    assert_eq!(result.unwrap(), Some(value));
+}
+
+#[tokio::test]
+async fn write_synced_persists_writes() {
+    // Create new store.
+    let path = ".db_test_write_synced_persists_writes";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+
+    // A pending read is notified by the synced write.
+    let key = vec![0u8, 1u8, 2u8, 3u8];
+    let value = vec![4u8, 5u8, 6u8, 7u8];
+    let mut reader = store.clone();
+    let pending = tokio::spawn({
+        let key = key.clone();
+        async move { reader.notify_read(key).await }
+    });
+    let entries = vec![(key.clone(), value.clone()), (vec![8u8], vec![9u8])];
+    assert!(store.write_synced(entries).await.is_ok());
+    assert_eq!(pending.await.unwrap().unwrap(), value);
+
+    // Ensure the values are read back from disk once the store is opened again.
+    store.close().await;
+    let mut store = Store::new(path).unwrap();
+    let result = store.read_many(vec![key, vec![8u8]]).await;
+    assert_eq!(result.unwrap(), vec![Some(value), Some(vec![9u8])]);
+}
+
+#[tokio::test]
+async fn memory_read_write_value() {
+    let mut store = MemoryStore::new();
+
+    // Write value to the store.
+    let key = vec![0u8, 1u8, 2u8, 3u8];
+    let value = vec![4u8, 5u8, 6u8, 7u8];
+    store.write(key.clone(), value.clone()).await;
+
+    // Read value (and an unknown key).
+    assert_eq!(store.read(key).await.unwrap(), Some(value));
+    assert_eq!(store.read(vec![8u8]).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn memory_read_notify() {
+    let mut store = MemoryStore::new();
+    let key = vec![0u8, 1u8, 2u8, 3u8];
+    let value = vec![4u8, 5u8, 6u8, 7u8];
+
+    // Wait for a missing value from another handle.
+    let mut store_copy = store.clone();
+    let key_copy = key.clone();
+    let handle = tokio::spawn(async move { store_copy.notify_read(key_copy).await.unwrap() });
+
+    // Write the missing value and ensure the handle gets it.
+    store.write(key, value.clone()).await;
+    assert_eq!(handle.await.unwrap(), value);
+}
+
+#[tokio::test]
+async fn delete_range() {
+    // Create new store.
+    let path = ".db_test_delete_range";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+
+    // Write keys 0 to 4, then delete the keys 1 to 3 (excluded), and key 4.
+    for i in 0u8..5 {
+        store.write(vec![i], vec![i]).await;
+    }
+    store.delete_range(vec![1], vec![3]).await;
+    store.delete(vec![vec![4]]).await;
+
+    // Only keys 0 and 3 are left.
+    for i in 0u8..5 {
+        let expected = if i == 0 || i == 3 {
+            Some(vec![i])
+        } else {
+            None
+        };
+        assert_eq!(store.read(vec![i]).await.unwrap(), expected);
+    }
+}
+
+#[tokio::test]
+async fn snapshot_and_restore() {
+    // Fill a store, and close it.
+    let path = ".db_test_snapshot_and_restore";
+    let _ = fs::remove_dir_all(path);
+    {
+        let mut store = Store::new(path).unwrap();
+        for i in 0u8..10 {
+            store.write(vec![i], vec![i; 100]).await;
+        }
+        store.flush().await.unwrap();
+        store.close().await;
+    }
+
+    // Snapshot it.
+    let file = ".db_test_snapshot_and_restore.snapshot";
+    assert_eq!(snapshot(path, file).unwrap(), 10);
+
+    // Restore it into a fresh store.
+    let restored = ".db_test_snapshot_and_restore_restored";
+    let _ = fs::remove_dir_all(restored);
+    assert_eq!(restore(file, restored, false).unwrap(), 10);
+
+    // Restoring again requires `force`.
+    assert!(matches!(
+        restore(file, restored, false),
+        Err(SnapshotError::NotEmpty(_))
+    ));
+    assert_eq!(restore(file, restored, true).unwrap(), 10);
+
+    let mut store = Store::new(restored).unwrap();
+    for i in 0u8..10 {
+        assert_eq!(store.read(vec![i]).await.unwrap(), Some(vec![i; 100]));
+    }
+    let _ = fs::remove_file(file);
+}
+
+#[tokio::test]
+async fn read_many() {
+    // Create new store.
+    let path = ".db_test_read_many";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+    store.write(vec![0u8], vec![0u8]).await;
+    store.write(vec![2u8], vec![2u8]).await;
+
+    // Values come back in the order of the keys, with `None` for the missing ones.
+    let result = store.read_many(vec![vec![2u8], vec![1u8], vec![0u8]]).await;
+    assert_eq!(
+        result.unwrap(),
+        vec![Some(vec![2u8]), None, Some(vec![0u8])]
+    );
+}
+
+#[tokio::test]
+async fn prefixed_stores_do_not_collide() {
+    let store = MemoryStore::new();
+    let mut first = PrefixedStore::new(store.clone(), b"worker/1/");
+    let mut second = PrefixedStore::new(store.clone(), b"worker/2/");
+
+    // The same key holds a different value in each namespace.
+    first.write(vec![0], vec![1]).await;
+    second.write(vec![0], vec![2]).await;
+    assert_eq!(first.read(vec![0]).await.unwrap(), Some(vec![1]));
+    assert_eq!(second.read(vec![0]).await.unwrap(), Some(vec![2]));
+    assert_eq!(store.len(), 2);
+
+    // Deleting from a namespace leaves the others untouched.
+    first.delete_range(vec![0], vec![1]).await;
+    assert_eq!(first.read(vec![0]).await.unwrap(), None);
+    assert_eq!(
+        second.read_many(vec![vec![0]]).await.unwrap(),
+        vec![Some(vec![2])]
+    );
+}
+
+#[tokio::test]
+async fn refuse_newer_schema() {
+    let path = ".db_test_refuse_newer_schema";
+    let _ = fs::remove_dir_all(path);
+    {
+        let db = rocksdb::DB::open_default(path).unwrap();
+        db.put(schema::SCHEMA_KEY, &(SCHEMA_VERSION + 1).to_le_bytes())
+            .unwrap();
+    }
+    assert!(matches!(
+        Store::new(path),
+        Err(SchemaError::Newer { found, expected })
+            if found == SCHEMA_VERSION + 1 && expected == SCHEMA_VERSION
+    ));
+}
+
+#[test]
+fn migrate_schema() {
+    fn double(db: &rocksdb::DB) -> StoreResult<()> {
+        let value = db.get(&[0u8]).unwrap().unwrap();
+        Ok(db.put(&[0u8], [value.clone(), value].concat())?)
+    }
+    fn tag(db: &rocksdb::DB) -> StoreResult<()> {
+        Ok(db.put(&[1u8], &[1u8])?)
+    }
+
+    // A store written with the layout of version 1 (before the versioning).
+    let path = ".db_test_migrate_schema";
+    let _ = fs::remove_dir_all(path);
+    let db = rocksdb::DB::open_default(path).unwrap();
+    db.put(&[0u8], &[7u8]).unwrap();
+
+    // There is no way to version 4 without a migration from version 3.
+    let migrations = Migrations::default()
+        .register(1, 2, double)
+        .register(2, 3, tag);
+    assert!(matches!(
+        schema::check(&db, &migrations, 4),
+        Err(SchemaError::NoMigration {
+            found: 3,
+            expected: 4
+        })
+    ));
+
+    // The store stays at version 3 and only the missing migration runs on the next attempt.
+    let migrations = migrations.register(3, 4, |_| Ok(()));
+    schema::check(&db, &migrations, 4).unwrap();
+    assert_eq!(db.get(&[0u8]).unwrap(), Some(vec![7u8, 7u8]));
+    assert_eq!(db.get(&[1u8]).unwrap(), Some(vec![1u8]));
+    assert_eq!(
+        db.get(schema::SCHEMA_KEY).unwrap(),
+        Some(4u32.to_le_bytes().to_vec())
+    );
+}
+
+#[tokio::test]
+async fn encrypted_values() {
+    let path = ".db_test_encrypted_values";
+    let _ = fs::remove_dir_all(path);
+    let key = StoreKey::generate();
+    let plaintext = b"a transaction of the batch".to_vec();
+    {
+        let mut store = Store::encrypted(path, &key).unwrap();
+        store.write(vec![0], plaintext.clone()).await;
+        store.write(vec![1], vec![1; 10]).await;
+        assert_eq!(store.read(vec![0]).await.unwrap(), Some(plaintext.clone()));
+        assert_eq!(
+            store.read_many(vec![vec![1], vec![2]]).await.unwrap(),
+            vec![Some(vec![1; 10]), None]
+        );
+        assert_eq!(store.notify_read(vec![1]).await.unwrap(), vec![1; 10]);
+        store.flush().await.unwrap();
+        store.close().await;
+    }
+
+    // The keys are in plaintext but not the values.
+    {
+        let db = rocksdb::DB::open_default(path).unwrap();
+        let value = db.get(&[0u8]).unwrap().unwrap();
+        assert_ne!(value, plaintext);
+        assert!(!value
+            .windows(plaintext.len())
+            .any(|x| x == plaintext.as_slice()));
+    }
+
+    // The store only opens with its key.
+    assert!(matches!(Store::new(path), Err(SchemaError::Encrypted)));
+    assert!(matches!(
+        Store::encrypted(path, &StoreKey::generate()),
+        Err(SchemaError::WrongKey)
+    ));
+    let mut store = Store::encrypted(path, &key).unwrap();
+    assert_eq!(store.read(vec![0]).await.unwrap(), Some(plaintext));
+}
+
+#[tokio::test]
+async fn encrypt_under_a_subkey_per_opening() {
+    let path = ".db_test_encrypt_under_a_subkey_per_opening";
+    let _ = fs::remove_dir_all(path);
+    let key = StoreKey::generate();
+    for keys in &[vec![vec![0], vec![1]], vec![vec![2]]] {
+        let mut store = Store::encrypted(path, &key).unwrap();
+        for k in keys {
+            store.write(k.clone(), vec![7; 10]).await;
+        }
+        store.flush().await.unwrap();
+        store.close().await;
+    }
+
+    // The values of an opening share their salt (15 bytes) but not their nonce (the next 12 bytes).
+    let db = rocksdb::DB::open_default(path).unwrap();
+    let values: Vec<_> = (0u8..3).map(|x| db.get(&[x]).unwrap().unwrap()).collect();
+    assert_eq!(values[0][..15], values[1][..15]);
+    assert_ne!(values[0][15..27], values[1][15..27]);
+    assert_ne!(values[0][..15], values[2][..15]);
+    drop(db);
+
+    let mut store = Store::encrypted(path, &key).unwrap();
+    for k in 0..3 {
+        assert_eq!(store.read(vec![k]).await.unwrap(), Some(vec![7; 10]));
+    }
+}
+
+#[tokio::test]
+async fn refuse_to_encrypt_plaintext_store() {
+    let path = ".db_test_refuse_to_encrypt_plaintext_store";
+    let _ = fs::remove_dir_all(path);
+    {
+        let mut store = Store::new(path).unwrap();
+        store.write(vec![0], vec![0]).await;
+        store.flush().await.unwrap();
+        store.close().await;
+    }
+    assert!(matches!(
+        Store::encrypted(path, &StoreKey::generate()),
+        Err(SchemaError::Plaintext)
+    ));
+}
+
+#[test]
+fn store_key_encoding() {
+    let key = StoreKey::generate();
+    let decoded = StoreKey::decode_base64(&format!("{}\n", key.encode_base64())).unwrap();
+    assert_eq!(decoded.encode_base64(), key.encode_base64());
+    assert!(StoreKey::decode_base64(&base64::encode(&[0u8; 16])).is_err());
+}
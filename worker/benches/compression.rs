@@ -0,0 +1,79 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+//! Measures how much the zstd compression of the batches saves on storage and bandwidth, and how long it
+//! takes. Run with `cargo bench --bench compression`.
+use config::{Epoch, ThresholdKeyPair};
+use std::time::Instant;
+use worker::{compress, decompress, SerializedCiphertext, WorkerMessage};
+
+/// The size of the batches (in bytes), as in the default parameters.
+const BATCH_SIZE: usize = 500_000;
+
+/// The size of the transactions (in bytes), as sent by the benchmark client.
+const TRANSACTION_SIZE: usize = 512;
+
+/// The number of times each batch is compressed and decompressed.
+const ITERATIONS: u32 = 20;
+
+fn bench(name: &str, batch: Vec<SerializedCiphertext>) {
+    let serialized = bincode::serialize(&WorkerMessage::Batch(Epoch::default(), batch)).unwrap();
+    for level in &[1, 3, 9] {
+        let start = Instant::now();
+        let mut compressed = Vec::new();
+        for _ in 0..ITERATIONS {
+            compressed = compress(serialized.clone(), Some(*level));
+        }
+        let compression = start.elapsed() / ITERATIONS;
+
+        let start = Instant::now();
+        for _ in 0..ITERATIONS {
+            assert_eq!(
+                decompress(compressed.clone()).unwrap().len(),
+                serialized.len()
+            );
+        }
+        let decompression = start.elapsed() / ITERATIONS;
+
+        println!(
+            "{:<12} level {}: {} B -> {} B ({:.1}% saved), compress {:?}, decompress {:?}",
+            name,
+            level,
+            serialized.len(),
+            compressed.len(),
+            100.0 * (1.0 - compressed.len() as f64 / serialized.len() as f64),
+            compression,
+            decompression
+        );
+    }
+}
+
+fn main() {
+    let count = BATCH_SIZE / TRANSACTION_SIZE;
+
+    // Text-heavy payloads, as stored by applications that do not encrypt their transactions.
+    let text: Vec<_> = (0..count)
+        .map(|i| {
+            format!(
+                "{{\"from\":\"alice\",\"to\":\"bob\",\"amount\":{},\"memo\":\"",
+                i
+            )
+            .into_bytes()
+            .into_iter()
+            .chain(b"payment for services rendered ".iter().cycle().cloned())
+            .take(TRANSACTION_SIZE)
+            .collect()
+        })
+        .collect();
+    bench("text", text);
+
+    // Threshold-encrypted payloads, as produced by our clients: ciphertexts are close to random, so only
+    // their framing compresses.
+    let keypair = ThresholdKeyPair::new(1, 0, 0);
+    let public_key = keypair.pk_set.public_key();
+    let ciphertexts: Vec<_> = (0..count)
+        .map(|i| {
+            let message = vec![(i % 256) as u8; TRANSACTION_SIZE];
+            bincode::serialize(&public_key.encrypt(&message)).unwrap()
+        })
+        .collect();
+    bench("ciphertexts", ciphertexts);
+}
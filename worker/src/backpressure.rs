@@ -0,0 +1,87 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+#[cfg(test)]
+#[path = "tests/backpressure_tests.rs"]
+pub mod backpressure_tests;
+
+/// The response of the worker to a client transaction it rejects because its mempool is full.
+pub const MEMPOOL_FULL: &[u8] = b"MempoolFull";
+
+/// Tracks the size of the transactions received from the clients that are not yet sealed into a batch.
+/// Once it exceeds the high-water mark, the worker rejects new transactions until it drains below the
+/// low-water mark.
+#[derive(Clone)]
+pub struct Backpressure {
+    /// The size of the pending transactions (in bytes).
+    pending: Arc<AtomicUsize>,
+    /// Whether we are rejecting transactions.
+    full: Arc<AtomicBool>,
+    /// The size (in bytes) above which we reject transactions.
+    high_water_mark: usize,
+    /// The size (in bytes) below which we accept transactions again.
+    low_water_mark: usize,
+}
+
+impl Backpressure {
+    pub fn new(high_water_mark: usize, low_water_mark: usize) -> Self {
+        Self {
+            pending: Arc::new(AtomicUsize::new(0)),
+            full: Arc::new(AtomicBool::new(false)),
+            high_water_mark,
+            low_water_mark: low_water_mark.min(high_water_mark),
+        }
+    }
+
+    /// Accounts for a new transaction of `size` bytes. Returns `false` if the transaction must be rejected.
+    pub fn try_accept(&self, size: usize) -> bool {
+        let pending = self.pending.load(Ordering::Relaxed);
+        if self.full.load(Ordering::Relaxed) {
+            if pending >= self.low_water_mark {
+                return false;
+            }
+            self.full.store(false, Ordering::Relaxed);
+        } else if pending.saturating_add(size) > self.high_water_mark {
+            self.full.store(true, Ordering::Relaxed);
+            return false;
+        }
+        self.pending.fetch_add(size, Ordering::Relaxed);
+        true
+    }
+
+    /// Accounts for transactions of `size` bytes that left the mempool (sealed into a batch, or dropped).
+    pub fn release(&self, size: usize) {
+        // Never underflow, even if we release more than we accepted.
+        let mut pending = self.pending.load(Ordering::Relaxed);
+        loop {
+            let new = pending.saturating_sub(size);
+            match self.pending.compare_exchange_weak(
+                pending,
+                new,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(x) => pending = x,
+            }
+        }
+    }
+
+    /// The size of the pending transactions (in bytes).
+    pub fn pending(&self) -> usize {
+        self.pending.load(Ordering::Relaxed)
+    }
+
+    /// Whether we currently reject transactions.
+    pub fn is_full(&self) -> bool {
+        self.full.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for Backpressure {
+    /// Never rejects transactions.
+    fn default() -> Self {
+        Self::new(usize::MAX, usize::MAX)
+    }
+}
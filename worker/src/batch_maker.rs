@@ -1,26 +1,33 @@
-use crate::processor::SerializedDecryptableBatchMessage;
 // Copyright(C) Facebook, Inc. and its affiliates.
+use crate::backpressure::Backpressure;
+use crate::compression::compress;
+use crate::key_ring::ThresholdKeyRing;
+use crate::metrics::WorkerMetrics;
+use crate::priority::{sort_by_priority, split_epoch};
+use crate::processor::SerializedDecryptableBatchMessage;
 use crate::quorum_waiter::QuorumWaiterMessage;
+use crate::sampler::BatchSampler;
+use crate::shutdown::Shutdown;
 use crate::worker::WorkerMessage;
 use bytes::Bytes;
-use crypto::Digest;
+use config::{Epoch, Parameters};
 use crypto::PublicKey;
-#[cfg(feature = "benchmark")]
-use ed25519_dalek::{Digest as _, Sha512};
+use crypto::{hash, Digest};
 #[cfg(feature = "benchmark")]
 #[cfg(not(test))]
-use log::{debug, error, info, warn}; // Use log crate when building application
-use network::{CancelHandler, ReliableSender};
-use std::collections::HashMap;
+use log::{debug, error, info, trace, warn}; // Use log crate when building application
+use network::{Backoff, CancelHandler, ReliableSender};
 use std::collections::VecDeque;
+use std::collections::{HashMap, HashSet};
 use std::convert::TryInto;
-#[cfg(feature = "benchmark")]
-use std::convert::TryInto as _;
 use std::net::SocketAddr;
+use std::ops::Range;
 #[cfg(test)]
-use std::{println as info, println as warn, println as error, println as debug};
-use tokio::sync::mpsc::{Receiver, Sender};
-use tokio::sync::oneshot;
+use std::{println as info, println as warn, println as error, println as debug, println as trace};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{Receiver, Sender, UnboundedReceiver};
+use tokio::sync::{oneshot, watch};
+use tokio::task::JoinHandle;
 use tokio::time::{sleep, Duration, Instant};
 
 #[cfg(test)]
@@ -30,61 +37,150 @@ pub mod batch_maker_tests;
 pub type SerializedCiphertext = Vec<u8>;
 pub type Batch = Vec<SerializedCiphertext>;
 
+/// Whether trace lines get logged (the tests print them all).
+#[cfg(not(test))]
+fn trace_enabled() -> bool {
+    log::log_enabled!(log::Level::Trace)
+}
+
+#[cfg(test)]
+fn trace_enabled() -> bool {
+    true
+}
+
 /// Assemble clients transactions into batches.
 pub struct BatchMaker {
     /// The preferred batch size (in bytes).
     batch_size: usize,
+    /// The size (in bytes) that batches never exceed, unless made of a single transaction.
+    max_batch_size_bytes: usize,
+    /// The number of transactions at which we seal a batch.
+    max_batch_size_txs: usize,
     /// hack for benchmarking... shouldn't be done this way
     batch_sizes: VecDeque<usize>,
     /// The maximum delay after which to seal the batch (in ms).
     max_batch_delay: u64,
     /// Channel to receive transactions from the network.
     rx_transaction: Receiver<SerializedCiphertext>,
+    /// Channel to receive the bundles of transactions that must all land in the same batch.
+    rx_bundle: Receiver<Vec<SerializedCiphertext>>,
     /// Channel to receive DecryptableBatches from quorum_waiter.
     rx_decryptable_batches: Receiver<(
         SerializedDecryptableBatchMessage,
         oneshot::Sender<Vec<(PublicKey, CancelHandler)>>,
     )>,
-    /// Output channel to deliver sealed batches to the `QuorumWaiter`.
-    tx_message: Sender<QuorumWaiterMessage>,
+    /// Channel to receive from the `QuorumWaiter` the batches that peers rejected as too large.
+    rx_reseal: UnboundedReceiver<(Epoch, Batch, Vec<Range<usize>>)>,
+    /// Output channel to deliver sealed batches to the `QuorumWaiter` (dropped once we shut down).
+    tx_message: Option<Sender<QuorumWaiterMessage>>,
+    /// The sealed batches for which the `QuorumWaiter` had no room yet, oldest first. We never block on the
+    /// `QuorumWaiter`, which may itself wait for us to broadcast its decryptable batches: we stop taking
+    /// transactions until it has room for them instead.
+    pending: VecDeque<QuorumWaiterMessage>,
     /// The network addresses of the other workers that share our worker id.
     workers_addresses: Vec<(PublicKey, SocketAddr)>,
     /// Holds the current batch.
     current_batch: Batch,
+    /// The epoch of the threshold key the transactions of the current batch are encrypted with.
+    current_batch_epoch: Epoch,
     /// Holds the size of the current batch (in bytes).
     current_batch_size: usize,
+    /// The time at which we received each transaction of the current batch.
+    current_batch_arrivals: VecDeque<Instant>,
+    /// The positions of the bundles in the current batch, which stay together when we order it by priority.
+    current_batch_bundles: Vec<Range<usize>>,
+    /// The age (in ms) after which we drop the transactions not yet sealed (0 if they never expire).
+    transaction_ttl: u64,
+    /// The zstd level at which we compress the batches we broadcast (if any).
+    compression_level: Option<i32>,
+    /// The number of recent transactions we remember to drop duplicates (0 if we do not deduplicate).
+    dedup_window: usize,
+    /// Whether we order the transactions of each batch by priority (rather than by arrival).
+    priority_ordering: bool,
+    /// Picks the batches whose content we log.
+    sampler: BatchSampler,
+    /// The digests of the recent transactions (pending or sealed).
+    recent: HashSet<Digest>,
+    /// The digests of the recent transactions, oldest first.
+    recent_order: VecDeque<Digest>,
     /// A network sender to broadcast the batches to the other workers.
     network: ReliableSender,
+    /// The metrics exported by the worker.
+    metrics: WorkerMetrics,
+    /// Receives the parameters reloaded by the operator.
+    rx_parameters: watch::Receiver<Parameters>,
+    /// Tells the epoch of the threshold key of the transactions that do not specify it.
+    key_ring: ThresholdKeyRing,
+    /// Accounts for the transactions we sealed, so that the worker accepts new ones.
+    backpressure: Backpressure,
+    /// Tells when to seal the batch in flight and stop.
+    shutdown: Shutdown,
 }
 
 impl BatchMaker {
+    #[allow(clippy::too_many_arguments)]
     pub fn spawn(
         batch_size: usize,
         max_batch_delay: u64,
         rx_transaction: Receiver<SerializedCiphertext>,
+        rx_bundle: Receiver<Vec<SerializedCiphertext>>,
         rx_decryptable_batches: Receiver<(
             SerializedDecryptableBatchMessage,
             oneshot::Sender<Vec<(PublicKey, CancelHandler)>>,
         )>,
+        rx_reseal: UnboundedReceiver<(Epoch, Batch, Vec<Range<usize>>)>,
         tx_message: Sender<QuorumWaiterMessage>,
         workers_addresses: Vec<(PublicKey, SocketAddr)>,
-    ) {
+        backoff: Backoff,
+        metrics: WorkerMetrics,
+        rx_parameters: watch::Receiver<Parameters>,
+        key_ring: ThresholdKeyRing,
+        backpressure: Backpressure,
+        shutdown: Shutdown,
+    ) -> JoinHandle<()> {
         tokio::spawn(async move {
+            let transaction_ttl = rx_parameters.borrow().transaction_ttl;
+            let compression_level = rx_parameters.borrow().batch_compression_level;
+            let dedup_window = rx_parameters.borrow().dedup_window;
+            let max_batch_size_bytes = rx_parameters.borrow().max_batch_size_bytes;
+            let max_batch_size_txs = rx_parameters.borrow().max_batch_size_txs;
+            let priority_ordering = rx_parameters.borrow().priority_ordering;
+            let sampler = BatchSampler::new(rx_parameters.borrow().batch_sample_rate);
             Self {
                 batch_size,
+                max_batch_size_bytes,
+                max_batch_size_txs,
                 batch_sizes: VecDeque::new(),
                 max_batch_delay,
                 rx_transaction,
+                rx_bundle,
                 rx_decryptable_batches,
-                tx_message,
+                rx_reseal,
+                tx_message: Some(tx_message),
+                pending: VecDeque::new(),
                 workers_addresses,
                 current_batch: Batch::with_capacity(batch_size * 2),
+                current_batch_epoch: key_ring.current_epoch(),
                 current_batch_size: 0,
-                network: ReliableSender::new(),
+                current_batch_arrivals: VecDeque::with_capacity(batch_size * 2),
+                current_batch_bundles: Vec::new(),
+                transaction_ttl,
+                compression_level,
+                dedup_window,
+                priority_ordering,
+                sampler,
+                recent: HashSet::new(),
+                recent_order: VecDeque::new(),
+                network: ReliableSender::new().with_backoff(backoff),
+                metrics,
+                rx_parameters,
+                key_ring,
+                backpressure,
+                shutdown,
             }
             .run()
             .await;
-        });
+        })
     }
 
     /// Main loop receiving incoming transactions and creating batches.
@@ -95,61 +191,290 @@ impl BatchMaker {
         loop {
             tokio::select! {
                 // Assemble client transactions into batches of preset size.
-                Some(transaction) = self.rx_transaction.recv() => {
+                Some(transaction) = self.rx_transaction.recv(), if self.pending.is_empty() => {
                     debug!("batch_maker: received tx");
+                    self.metrics.transactions_received.inc();
+                    if self.is_duplicate(&transaction) {
+                        self.backpressure.release(transaction.len());
+                        self.metrics.transactions_duplicated.inc();
+                        continue;
+                    }
+
+                    // A batch only holds transactions of a single epoch.
+                    let epoch = self.epoch(&transaction);
+                    if self.seal_other_epoch(epoch).await {
+                        timer.as_mut().reset(Instant::now() + Duration::from_millis(self.max_batch_delay));
+                    }
+
+                    // Seal the current batch first if the transaction would make it exceed its maximum size.
+                    if self.current_batch_size + transaction.len() > self.max_batch_size_bytes {
+                        self.expire();
+                        if !self.current_batch.is_empty()
+                            && self.current_batch_size + transaction.len() > self.max_batch_size_bytes
+                        {
+                            self.seal().await;
+                            timer.as_mut().reset(Instant::now() + Duration::from_millis(self.max_batch_delay));
+                        }
+                    }
+
                     self.current_batch_size += transaction.len();
                     self.current_batch.push(transaction);
-                    if self.current_batch_size >= self.batch_size {
-                        self.seal().await;
+                    self.current_batch_arrivals.push_back(Instant::now());
+                    if self.is_full() {
+                        // The batch may no longer be full once we drop its expired transactions.
+                        self.expire();
+                        if self.is_full() {
+                            self.seal().await;
+                            timer.as_mut().reset(Instant::now() + Duration::from_millis(self.max_batch_delay));
+                        }
+                    }
+                },
+
+                // Place each bundle entirely in a single batch (the intake checked that it fits in one).
+                Some(bundle) = self.rx_bundle.recv(), if self.pending.is_empty() => {
+                    let size: usize = bundle.iter().map(|x| x.len()).sum();
+                    self.metrics.transactions_received.inc_by(bundle.len() as u64);
+                    if self.is_duplicate_bundle(&bundle) {
+                        self.backpressure.release(size);
+                        self.metrics.transactions_duplicated.inc_by(bundle.len() as u64);
+                        continue;
+                    }
+
+                    // The intake checked that all the transactions of the bundle have the same epoch.
+                    let epoch = bundle.first().map_or(self.current_batch_epoch, |x| self.epoch(x));
+                    if self.seal_other_epoch(epoch).await {
                         timer.as_mut().reset(Instant::now() + Duration::from_millis(self.max_batch_delay));
                     }
+
+                    // Seal the current batch first if the bundle does not fit in it.
+                    if !self.fits(size, bundle.len()) {
+                        self.expire();
+                        if !self.current_batch.is_empty() && !self.fits(size, bundle.len()) {
+                            self.seal().await;
+                            timer.as_mut().reset(Instant::now() + Duration::from_millis(self.max_batch_delay));
+                        }
+                    }
+
+                    // The transactions of the bundle share their arrival time, so they also expire together.
+                    let now = Instant::now();
+                    self.current_batch_size += size;
+                    self.current_batch_arrivals.extend(bundle.iter().map(|_| now));
+                    if !bundle.is_empty() {
+                        let start = self.current_batch.len();
+                        self.current_batch_bundles.push(start..start + bundle.len());
+                    }
+                    self.current_batch.extend(bundle);
+                    if self.is_full() {
+                        self.expire();
+                        if self.is_full() {
+                            self.seal().await;
+                            timer.as_mut().reset(Instant::now() + Duration::from_millis(self.max_batch_delay));
+                        }
+                    }
                 },
 
                 // If we received decryptable batch from quorum_waiter
                 Some((serialized_decryptable_batch_msg, return_channel)) = self.rx_decryptable_batches.recv() => {
-                    let (names, addresses): (Vec<_>, _) =
-                    self.workers_addresses.iter().cloned().unzip();
-                    let bytes = Bytes::from(serialized_decryptable_batch_msg);
-                    #[cfg(feature = "benchmark")]
-                    {
-                        // NOTE: This is one extra hash that is only needed to print the following log entries.
-                        let digest = Digest(
-                        Sha512::digest(&bytes).as_slice()[..32]
-                            .try_into()
-                            .unwrap(),
-                        );
-                        let size = self.batch_sizes.pop_front().unwrap();
-                        info!("Batch {:?} contains {} B", digest, size);
-                    }
-                    // Broadcast the decryptable shares batch through the network.
-                    debug!("batch_maker: broadcasting serialized_decryptable_batch_msg to other validators");
-                    let handlers = self.network.broadcast(addresses, bytes).await;
-                    // and return the named handlers to quorum_waiter
-                    let named_handlers = names.into_iter().zip(handlers.into_iter()).collect();
-                    return_channel.send(named_handlers).unwrap();
+                    self.broadcast_decryptable(serialized_decryptable_batch_msg, return_channel).await;
                 },
 
+                // Split the batches that peers rejected as too large.
+                Some((epoch, batch, bundles)) = self.rx_reseal.recv() => self.reseal(epoch, batch, bundles).await,
+
+                // Hand the pending batches to the `QuorumWaiter` once it has room for them.
+                true = ready(&self.tx_message), if !self.pending.is_empty() => self.deliver_pending(),
+
                 // If the timer triggers, seal the batch even if it contains few transactions.
-                () = &mut timer => {
+                () = &mut timer, if self.pending.is_empty() => {
+                    self.expire();
                     if !self.current_batch.is_empty() {
                         self.seal().await;
                     }
                     timer.as_mut().reset(Instant::now() + Duration::from_millis(self.max_batch_delay));
-                }
+                },
+
+                // The new batch size applies to the batch currently being assembled.
+                Ok(()) = self.rx_parameters.changed() => {
+                    self.batch_size = self.rx_parameters.borrow().batch_size;
+                    self.transaction_ttl = self.rx_parameters.borrow().transaction_ttl;
+                    self.dedup_window = self.rx_parameters.borrow().dedup_window;
+                    self.sampler.set_rate(self.rx_parameters.borrow().batch_sample_rate);
+                    debug!("Batch size updated to {} B", self.batch_size);
+                },
+
+                () = self.shutdown.wait() => break,
             }
 
             // Give the change to schedule other tasks.
             tokio::task::yield_now().await;
         }
+
+        // Seal the batch in flight, and let the `QuorumWaiter` stop once it delivered the sealed batches. It
+        // exits after dropping its end of the channel of the decryptable batches, which we keep broadcasting.
+        self.expire();
+        if !self.current_batch.is_empty() {
+            self.seal().await;
+        }
+        loop {
+            if self.pending.is_empty() {
+                self.tx_message = None;
+            }
+            tokio::select! {
+                true = ready(&self.tx_message), if !self.pending.is_empty() => self.deliver_pending(),
+                message = self.rx_decryptable_batches.recv() => match message {
+                    Some((serialized_decryptable_batch_msg, return_channel)) => {
+                        self.broadcast_decryptable(serialized_decryptable_batch_msg, return_channel).await;
+                    },
+                    None => break,
+                },
+                Some(_) = self.rx_reseal.recv() => warn!("Dropping a batch to reseal while shutting down"),
+            }
+        }
+        debug!("batch_maker: drained the in-flight batches");
+    }
+
+    /// Broadcasts a decryptable batch from the `QuorumWaiter`, and returns it the named handlers.
+    async fn broadcast_decryptable(
+        &mut self,
+        serialized_decryptable_batch_msg: SerializedDecryptableBatchMessage,
+        return_channel: oneshot::Sender<Vec<(PublicKey, CancelHandler)>>,
+    ) {
+        let (names, addresses): (Vec<_>, _) = self.workers_addresses.iter().cloned().unzip();
+        let bytes = Bytes::from(serialized_decryptable_batch_msg);
+        #[cfg(feature = "benchmark")]
+        {
+            // NOTE: This is one extra hash that is only needed to print the following log entries.
+            let digest = hash(&bytes);
+            let size = self.batch_sizes.pop_front().unwrap();
+            info!("Batch {:?} contains {} B", digest, size);
+        }
+        // Broadcast the decryptable shares batch through the network.
+        debug!("batch_maker: broadcasting serialized_decryptable_batch_msg to other validators");
+        let bytes = Bytes::from(compress(bytes.to_vec(), self.compression_level));
+        let handlers = self.network.broadcast(addresses, bytes).await;
+        // and return the named handlers to quorum_waiter
+        let named_handlers = names.into_iter().zip(handlers.into_iter()).collect();
+        return_channel.send(named_handlers).unwrap();
+    }
+
+    /// The epoch of the threshold key a transaction is encrypted with: the one set by the client, or else the
+    /// current one.
+    fn epoch(&self, transaction: &[u8]) -> Epoch {
+        split_epoch(transaction)
+            .0
+            .unwrap_or_else(|| self.key_ring.current_epoch())
+    }
+
+    /// Prepares the current batch to receive transactions of `epoch`: if it holds transactions of another
+    /// epoch, we seal it first. Returns whether we sealed a batch.
+    async fn seal_other_epoch(&mut self, epoch: Epoch) -> bool {
+        let mut sealed = false;
+        if epoch != self.current_batch_epoch {
+            self.expire();
+            if !self.current_batch.is_empty() {
+                self.seal().await;
+                sealed = true;
+            }
+            self.current_batch_epoch = epoch;
+        }
+        sealed
+    }
+
+    /// Whether the current batch reached its preferred size or its maximum number of transactions.
+    fn is_full(&self) -> bool {
+        self.current_batch_size >= self.batch_size.min(self.max_batch_size_bytes)
+            || self.current_batch.len() >= self.max_batch_size_txs
+    }
+
+    /// Whether `count` more transactions of `size` bytes fit in the current batch.
+    fn fits(&self, size: usize, count: usize) -> bool {
+        self.current_batch_size + size <= self.max_batch_size_bytes
+            && self.current_batch.len() + count <= self.max_batch_size_txs
+    }
+
+    /// Remembers the digests of the transactions of a new bundle. Returns `true` (and remembers none of
+    /// them) if we already saw one of them recently: the bundle is then dropped as a whole.
+    fn is_duplicate_bundle(&mut self, bundle: &[SerializedCiphertext]) -> bool {
+        if self.dedup_window == 0 {
+            return false;
+        }
+        let digests: Vec<_> = bundle.iter().map(hash).collect();
+        let distinct: HashSet<_> = digests.iter().collect();
+        if distinct.len() < digests.len() || digests.iter().any(|x| self.recent.contains(x)) {
+            return true;
+        }
+        self.recent.extend(digests.iter().cloned());
+        self.recent_order.extend(digests);
+        false
+    }
+
+    /// Remembers the digest of a new transaction. Returns `true` if we already saw it recently.
+    fn is_duplicate(&mut self, transaction: &[u8]) -> bool {
+        if self.dedup_window == 0 {
+            return false;
+        }
+        let digest = hash(transaction);
+        if !self.recent.insert(digest.clone()) {
+            return true;
+        }
+        self.recent_order.push_back(digest);
+        false
+    }
+
+    /// Forgets the oldest transactions beyond the deduplication window. The pending transactions are the
+    /// most recent ones, so we only forget sealed ones unless the window is smaller than the batch.
+    fn prune_recent(&mut self) {
+        while self.recent_order.len() > self.dedup_window {
+            if let Some(digest) = self.recent_order.pop_front() {
+                self.recent.remove(&digest);
+            }
+        }
+    }
+
+    /// Drops the transactions of the current batch older than the TTL. Transactions are appended in the
+    /// order we receive them, so the expired ones are at the front of the batch.
+    fn expire(&mut self) {
+        if self.transaction_ttl == 0 {
+            return;
+        }
+        let ttl = Duration::from_millis(self.transaction_ttl);
+        let expired = self
+            .current_batch_arrivals
+            .iter()
+            .take_while(|x| x.elapsed() > ttl)
+            .count();
+        if expired == 0 {
+            return;
+        }
+
+        let size: usize = self.current_batch.drain(..expired).map(|x| x.len()).sum();
+        self.current_batch_arrivals.drain(..expired);
+        self.current_batch_bundles.retain(|x| x.start >= expired);
+        for bundle in &mut self.current_batch_bundles {
+            *bundle = bundle.start - expired..bundle.end - expired;
+        }
+        self.current_batch_size -= size;
+        self.backpressure.release(size);
+        self.metrics.transactions_expired.inc_by(expired as u64);
+        debug!("Dropped {} expired transactions ({} B)", expired, size);
     }
 
     /// Seal and broadcast the current batch.
     async fn seal(&mut self) {
         #[cfg(feature = "benchmark")]
         let size = self.current_batch_size;
+        self.metrics.batches_created.inc();
+        self.metrics
+            .batch_bytes
+            .inc_by(self.current_batch_size as u64);
+
         // Serialize the batch.
+        self.backpressure.release(self.current_batch_size);
         self.current_batch_size = 0;
+        self.current_batch_arrivals.clear();
+        self.prune_recent();
         let batch: Batch = self.current_batch.drain(..).collect();
+        let mut bundles = std::mem::take(&mut self.current_batch_bundles);
 
         // Look for sample txs (they all start with 0) and gather their txs id (the next 8 bytes).
         #[cfg(feature = "benchmark")]
@@ -165,18 +490,21 @@ impl BatchMaker {
             .filter_map(|suffix| suffix[1..9].try_into().ok())
             .collect();
 
-        let message = WorkerMessage::Batch(batch.clone());
+        // The order of the transactions is part of the batch (hence of its digest): all nodes see it.
+        let mut batch = batch;
+        if self.priority_ordering {
+            sort_by_priority(&mut batch, &mut bundles);
+        }
+
+        let epoch = self.current_batch_epoch;
+        let message = WorkerMessage::Batch(epoch, batch.clone());
         let serialized_batch_msg =
             bincode::serialize(&message).expect("Failed to serialize our own batch");
 
         #[cfg(feature = "benchmark")]
         {
             // NOTE: This is one extra hash that is only needed to print the following log entries.
-            let digest = Digest(
-                Sha512::digest(&serialized_batch_msg).as_slice()[..32]
-                    .try_into()
-                    .unwrap(),
-            );
+            let digest = hash(&serialized_batch_msg);
 
             for id in tx_ids {
                 // NOTE: This log entry is used to compute performance.
@@ -192,22 +520,107 @@ impl BatchMaker {
             self.batch_sizes.push_back(size);
         }
 
+        self.broadcast(epoch, batch, bundles, serialized_batch_msg)
+            .await;
+    }
+
+    /// Splits in two halves a batch that peers rejected as too large, and broadcasts them anew. The
+    /// halves are split again if needed. We only split the batch between its bundles (as close to the
+    /// middle as possible): a single transaction or bundle cannot be split, so we drop it.
+    async fn reseal(&mut self, epoch: Epoch, mut batch: Batch, bundles: Vec<Range<usize>>) {
+        let middle = batch.len() / 2;
+        let split = match bundles.iter().find(|x| x.start < middle && middle < x.end) {
+            Some(x)
+                if x.start > 0 && (middle - x.start <= x.end - middle || x.end == batch.len()) =>
+            {
+                x.start
+            }
+            Some(x) => x.end,
+            None => middle,
+        };
+        if split == 0 || split == batch.len() {
+            warn!(
+                "Dropping {} transaction(s) that peers reject as too large",
+                batch.len()
+            );
+            return;
+        }
+
+        let second = batch.split_off(split);
+        let (first_bundles, second_bundles): (Vec<_>, Vec<_>) =
+            bundles.into_iter().partition(|x| x.end <= split);
+        let second_bundles = second_bundles
+            .into_iter()
+            .map(|x| x.start - split..x.end - split)
+            .collect();
+        for (half, bundles) in vec![(batch, first_bundles), (second, second_bundles)] {
+            #[cfg(feature = "benchmark")]
+            self.batch_sizes
+                .push_back(half.iter().map(|x| x.len()).sum());
+            let message = WorkerMessage::Batch(epoch, half.clone());
+            let serialized_batch_msg =
+                bincode::serialize(&message).expect("Failed to serialize our own batch");
+            self.broadcast(epoch, half, bundles, serialized_batch_msg)
+                .await;
+        }
+    }
+
+    /// Broadcasts a (serialized) batch to the other workers and hands it to the `QuorumWaiter`.
+    async fn broadcast(
+        &mut self,
+        epoch: Epoch,
+        batch: Batch,
+        bundles: Vec<Range<usize>>,
+        serialized_batch_msg: Vec<u8>,
+    ) {
+        // Describing a batch formats all its transactions: only sample it if the description gets logged.
+        if trace_enabled() {
+            if let Some(description) = self.sampler.sample(&batch, &serialized_batch_msg) {
+                trace!("{}", description);
+                self.metrics.batches_sampled.inc();
+            }
+        }
+
         // Broadcast the batch through the network.
         let (names, addresses): (Vec<_>, _) = self.workers_addresses.iter().cloned().unzip();
-        let bytes = Bytes::from(serialized_batch_msg.clone());
+        let bytes = Bytes::from(compress(serialized_batch_msg, self.compression_level));
         debug!("batch_maker: broadcasting batch to other validators");
         let handlers = self.network.broadcast(addresses, bytes).await;
 
         // Send the batch through the deliver channel for further processing.
-        self.tx_message
-            .send(QuorumWaiterMessage {
-                batch,
-                named_decrypt_shares_handlers: names
-                    .into_iter()
-                    .zip(handlers.into_iter())
-                    .collect(),
-            })
-            .await
-            .expect("Failed to deliver batch");
+        self.pending.push_back(QuorumWaiterMessage {
+            epoch,
+            batch,
+            bundles,
+            named_decrypt_shares_handlers: names.into_iter().zip(handlers.into_iter()).collect(),
+        });
+        self.deliver_pending();
+    }
+
+    /// Hands the pending batches to the `QuorumWaiter`, as long as it has room for them.
+    fn deliver_pending(&mut self) {
+        let tx_message = self
+            .tx_message
+            .as_ref()
+            .expect("Sealed a batch after shutting down");
+        while let Some(message) = self.pending.pop_front() {
+            match tx_message.try_send(message) {
+                Ok(()) => (),
+                Err(TrySendError::Full(message)) => {
+                    self.pending.push_front(message);
+                    return;
+                }
+                Err(TrySendError::Closed(_)) => panic!("Failed to deliver batch"),
+            }
+        }
+    }
+}
+
+/// Completes once the `QuorumWaiter` has room for a batch. Returns false if it stopped, or if we did.
+async fn ready(tx_message: &Option<Sender<QuorumWaiterMessage>>) -> bool {
+    match tx_message {
+        // Nobody else sends to the `QuorumWaiter`: the room stays free once the permit is released.
+        Some(tx_message) => tx_message.reserve().await.is_ok(),
+        None => false,
     }
 }
@@ -0,0 +1,34 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use std::io;
+
+#[cfg(test)]
+#[path = "tests/compression_tests.rs"]
+pub mod compression_tests;
+
+/// The magic number starting every zstd frame. Serialized worker messages start with the (little-endian)
+/// index of their variant, so they never start with it.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Compresses a serialized batch with zstd at the given level, or leaves it untouched if the level is
+/// `None`. Digests are always computed over the uncompressed form.
+pub fn compress(serialized: Vec<u8>, level: Option<i32>) -> Vec<u8> {
+    match level {
+        Some(level) => zstd::stream::encode_all(&serialized[..], level)
+            .expect("Failed to compress batch in memory"),
+        None => serialized,
+    }
+}
+
+/// Returns whether a serialized batch is compressed.
+pub fn is_compressed(bytes: &[u8]) -> bool {
+    bytes.starts_with(&ZSTD_MAGIC)
+}
+
+/// Restores the uncompressed form of a serialized batch, whether or not it was compressed.
+pub fn decompress(bytes: Vec<u8>) -> io::Result<Vec<u8>> {
+    if is_compressed(&bytes) {
+        zstd::stream::decode_all(&bytes[..])
+    } else {
+        Ok(bytes)
+    }
+}
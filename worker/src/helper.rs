@@ -4,34 +4,35 @@ use config::{Committee, WorkerId};
 use crypto::{Digest, PublicKey};
 use log::{error, warn};
 use network::SimpleSender;
-use store::Store;
+use store::KeyValueStore;
 use tokio::sync::mpsc::Receiver;
+use tokio::task::JoinHandle;
 
 #[cfg(test)]
 #[path = "tests/helper_tests.rs"]
 pub mod helper_tests;
 
 /// A task dedicated to help other authorities by replying to their batch requests.
-pub struct Helper {
+pub struct Helper<S> {
     /// The id of this worker.
     id: WorkerId,
     /// The committee information.
     committee: Committee,
     /// The persistent storage.
-    store: Store,
+    store: S,
     /// Input channel to receive batch requests.
     rx_request: Receiver<(Vec<Digest>, PublicKey)>,
     /// A network sender to send the batches to the other workers.
     network: SimpleSender,
 }
 
-impl Helper {
+impl<S: KeyValueStore> Helper<S> {
     pub fn spawn(
         id: WorkerId,
         committee: Committee,
-        store: Store,
+        store: S,
         rx_request: Receiver<(Vec<Digest>, PublicKey)>,
-    ) {
+    ) -> JoinHandle<()> {
         tokio::spawn(async move {
             Self {
                 id,
@@ -42,7 +43,7 @@ impl Helper {
             }
             .run()
             .await;
-        });
+        })
     }
 
     async fn run(&mut self) {
@@ -0,0 +1,251 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::receipts::Receipts;
+use crate::worker::{IntakeError, TxReceiverHandler};
+use futures::future::join_all;
+use hyper::header::{CONTENT_LENGTH, CONTENT_TYPE, RETRY_AFTER};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+#[cfg(not(test))]
+use log::{info, warn};
+use std::convert::{Infallible, TryInto as _};
+use std::net::SocketAddr;
+#[cfg(test)]
+use std::{println as info, println as warn};
+use tokio::task::JoinHandle;
+use tokio::time::{timeout, Duration};
+
+/// The path on which clients submit their transactions.
+const TRANSACTIONS_PATH: &str = "/transactions";
+
+/// The content type of a body carrying several transactions, each prefixed by its length (as a 4-byte
+/// big-endian integer), ie. the framing of the TCP protocol.
+pub const LENGTH_DELIMITED: &str = "application/x-length-delimited";
+
+/// The query parameter with which clients wait for the receipts of their transactions.
+const RECEIPTS_QUERY: &str = "receipts=true";
+
+/// The query parameter with which clients submit their transactions as a bundle, that lands entirely in
+/// a single batch or is rejected.
+const BUNDLE_QUERY: &str = "bundle=true";
+
+/// Lets the clients that cannot speak the TCP protocol submit their transactions with `POST /transactions`.
+/// The body is a single raw transaction, or several of them with the content type `LENGTH_DELIMITED`.
+/// The transactions go through the same intake as those received over TCP.
+///
+/// With `POST /transactions?receipts=true`, the response is only sent once the batches carrying the
+/// transactions are acknowledged by a quorum, and lists the digest and epoch of these batches. With
+/// `POST /transactions?bundle=true`, the transactions of the body are all placed in the same batch, or
+/// all rejected.
+#[derive(Clone)]
+pub struct HttpReceiver {
+    intake: TxReceiverHandler,
+    receipts: Receipts,
+    /// The size above which bodies are rejected without being read (they could not fit in the mempool).
+    max_body_size: usize,
+    /// The delay after which we stop waiting for receipts.
+    receipt_timeout: Duration,
+}
+
+impl HttpReceiver {
+    pub fn spawn(
+        address: SocketAddr,
+        intake: TxReceiverHandler,
+        receipts: Receipts,
+        max_body_size: usize,
+        receipt_timeout_ms: u64,
+    ) -> JoinHandle<()> {
+        let receiver = Self {
+            intake,
+            receipts,
+            max_body_size,
+            receipt_timeout: Duration::from_millis(receipt_timeout_ms),
+        };
+        tokio::spawn(async move {
+            let make_service = make_service_fn(move |_| {
+                let receiver = receiver.clone();
+                async move {
+                    Ok::<_, Infallible>(service_fn(move |request| {
+                        let receiver = receiver.clone();
+                        async move { Ok::<_, Infallible>(receiver.handle(request).await) }
+                    }))
+                }
+            });
+            let server = match Server::try_bind(&address) {
+                Ok(builder) => builder.serve(make_service),
+                Err(e) => {
+                    warn!(
+                        "Failed to bind the transactions HTTP server to {}: {}",
+                        address, e
+                    );
+                    return;
+                }
+            };
+            info!("Listening to client transactions over HTTP on {}", address);
+            if let Err(e) = server.await {
+                warn!("The transactions HTTP server failed: {}", e);
+            }
+        })
+    }
+
+    async fn handle(&self, request: Request<Body>) -> Response<Body> {
+        if request.uri().path() != TRANSACTIONS_PATH {
+            return reply(StatusCode::NOT_FOUND, "not found");
+        }
+        if request.method() != Method::POST {
+            return reply(StatusCode::METHOD_NOT_ALLOWED, "only POST is supported");
+        }
+        let query = |parameter: &str| {
+            request
+                .uri()
+                .query()
+                .map_or(false, |x| x.split('&').any(|x| x == parameter))
+        };
+        let with_receipts = query(RECEIPTS_QUERY);
+        let bundle = query(BUNDLE_QUERY);
+
+        // Only read bodies of a known (and acceptable) size.
+        let length = match request
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|x| x.to_str().ok())
+            .and_then(|x| x.parse::<usize>().ok())
+        {
+            Some(x) => x,
+            None => return reply(StatusCode::LENGTH_REQUIRED, "missing content length"),
+        };
+        if length > self.max_body_size {
+            return reply(StatusCode::PAYLOAD_TOO_LARGE, "body too large");
+        }
+        let delimited = request
+            .headers()
+            .get(CONTENT_TYPE)
+            .map_or(false, |x| x.as_bytes() == LENGTH_DELIMITED.as_bytes());
+        let body = match hyper::body::to_bytes(request.into_body()).await {
+            Ok(x) => x,
+            Err(e) => {
+                return reply(
+                    StatusCode::BAD_REQUEST,
+                    format!("failed to read body: {}", e),
+                )
+            }
+        };
+
+        let transactions = if delimited {
+            match split(&body) {
+                Some(x) => x,
+                None => return reply(StatusCode::BAD_REQUEST, "malformed length-delimited body"),
+            }
+        } else {
+            vec![body.to_vec()]
+        };
+
+        let total = transactions.len();
+        let mut accepted = 0;
+        let mut pending_receipts = Vec::new();
+        if bundle {
+            if with_receipts {
+                pending_receipts.extend(transactions.iter().map(|x| self.receipts.subscribe(x)));
+            }
+            match self.intake.submit_bundle(transactions) {
+                Ok(()) => accepted = total,
+                Err(IntakeError::MempoolFull) => return mempool_full(0, total),
+                Err(e @ IntakeError::MixedEpochs) | Err(e @ IntakeError::MixedPriorities) => {
+                    return reply(StatusCode::BAD_REQUEST, e.to_string())
+                }
+                Err(e) => return reply(StatusCode::PAYLOAD_TOO_LARGE, e.to_string()),
+            }
+        } else {
+            // Stop at the first rejected transaction: the client resends the remaining ones once it backed off.
+            for transaction in transactions {
+                if with_receipts {
+                    pending_receipts.push(self.receipts.subscribe(&transaction));
+                }
+                match self.intake.submit(transaction) {
+                    Ok(()) => (),
+                    Err(IntakeError::MempoolFull) => return mempool_full(accepted, total),
+                    Err(e) => {
+                        return reply(
+                            StatusCode::PAYLOAD_TOO_LARGE,
+                            format!("{} (accepted {} of {} transactions)", e, accepted, total),
+                        )
+                    }
+                }
+                accepted += 1;
+            }
+        }
+
+        if with_receipts {
+            return match timeout(self.receipt_timeout, join_all(pending_receipts)).await {
+                Ok(receipts) if receipts.iter().all(|x| x.is_ok()) => {
+                    let receipts: Vec<_> = receipts
+                        .into_iter()
+                        .filter_map(|x| x.ok())
+                        .map(|x| {
+                            serde_json::json!({
+                                "transaction": format!("{:?}", x.transaction),
+                                "batch": format!("{:?}", x.batch),
+                                "epoch": x.epoch,
+                            })
+                        })
+                        .collect();
+                    reply(
+                        StatusCode::OK,
+                        serde_json::json!({ "receipts": receipts }).to_string(),
+                    )
+                }
+                _ => reply(
+                    StatusCode::GATEWAY_TIMEOUT,
+                    format!(
+                        "accepted {} transactions, but not all of them were acknowledged by a quorum",
+                        accepted
+                    ),
+                ),
+            };
+        }
+        reply(
+            StatusCode::ACCEPTED,
+            format!("accepted {} transactions", accepted),
+        )
+    }
+}
+
+/// Splits a length-delimited body into its transactions.
+fn split(mut body: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let mut transactions = Vec::new();
+    while !body.is_empty() {
+        if body.len() < 4 {
+            return None;
+        }
+        let length = u32::from_be_bytes(body[..4].try_into().unwrap()) as usize;
+        body = &body[4..];
+        if body.len() < length {
+            return None;
+        }
+        transactions.push(body[..length].to_vec());
+        body = &body[length..];
+    }
+    Some(transactions)
+}
+
+/// Tells the client to back off: the mempool only accepted `accepted` of its `total` transactions.
+fn mempool_full(accepted: usize, total: usize) -> Response<Body> {
+    let mut response = reply(
+        StatusCode::SERVICE_UNAVAILABLE,
+        format!(
+            "mempool full: accepted {} of {} transactions",
+            accepted, total
+        ),
+    );
+    response
+        .headers_mut()
+        .insert(RETRY_AFTER, "1".parse().unwrap());
+    response
+}
+
+/// Builds a response with the specified status code and body.
+fn reply<B: Into<Body>>(status: StatusCode, body: B) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(body.into())
+        .expect("Failed to build HTTP response")
+}
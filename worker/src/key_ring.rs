@@ -0,0 +1,124 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use config::{Epoch, ThresholdKeyPair};
+use crypto::threshold::PublicKeySet;
+use crypto::ThresholdDecryptionService;
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+use thiserror::Error;
+
+#[cfg(test)]
+#[path = "tests/key_ring_tests.rs"]
+pub mod key_ring_tests;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum KeyRingError {
+    #[error("The threshold keys of epoch {0} have been garbage collected")]
+    GarbageCollected(Epoch),
+
+    #[error("No threshold keys for epoch {0}")]
+    UnknownEpoch(Epoch),
+
+    #[error(
+        "Cannot install the threshold keys of epoch {epoch}: we are already at epoch {current}"
+    )]
+    StaleEpoch { epoch: Epoch, current: Epoch },
+}
+
+/// The threshold keys of a single epoch.
+struct EpochKeys {
+    /// The public key set, to verify and combine decryption shares.
+    pk_set: PublicKeySet,
+    /// The service holding our secret key share, to compute decryption shares.
+    service: ThresholdDecryptionService,
+}
+
+struct KeyRingState {
+    /// The epoch with which we tag the batches we seal.
+    current: Epoch,
+    /// The keys of the previous epoch (still used to drain its batches), of the current epoch, and of the
+    /// future epochs installed ahead of time.
+    keys: BTreeMap<Epoch, EpochKeys>,
+}
+
+/// The threshold keys of a worker, indexed by epoch. A key set for a future epoch can be installed while
+/// the current one is in use; once the worker advances to it, the keys of the previous epoch are kept so
+/// that its in-flight batches can still be decrypted, and are garbage collected on the following advance.
+#[derive(Clone)]
+pub struct ThresholdKeyRing {
+    state: Arc<RwLock<KeyRingState>>,
+}
+
+impl ThresholdKeyRing {
+    /// Creates a key ring whose current epoch is the epoch of `keypair`.
+    pub fn new(keypair: ThresholdKeyPair) -> Self {
+        let current = keypair.epoch;
+        let mut keys = BTreeMap::new();
+        keys.insert(current, Self::make_keys(keypair));
+        Self {
+            state: Arc::new(RwLock::new(KeyRingState { current, keys })),
+        }
+    }
+
+    fn make_keys(keypair: ThresholdKeyPair) -> EpochKeys {
+        EpochKeys {
+            service: ThresholdDecryptionService::spawn(keypair.sk_share, keypair.node_index),
+            pk_set: keypair.pk_set,
+        }
+    }
+
+    /// The epoch to use for the batches we seal.
+    pub fn current_epoch(&self) -> Epoch {
+        self.state.read().unwrap().current
+    }
+
+    /// Installs the keys of a future epoch, replacing any keys previously installed for that epoch.
+    pub fn install(&self, keypair: ThresholdKeyPair) -> Result<(), KeyRingError> {
+        let mut state = self.state.write().unwrap();
+        if keypair.epoch <= state.current {
+            return Err(KeyRingError::StaleEpoch {
+                epoch: keypair.epoch,
+                current: state.current,
+            });
+        }
+        state.keys.insert(keypair.epoch, Self::make_keys(keypair));
+        Ok(())
+    }
+
+    /// Moves to the next installed epoch (if any), and garbage collects the keys of all epochs older than
+    /// the one we are leaving. Returns the new current epoch.
+    pub fn advance(&self) -> Option<Epoch> {
+        let mut state = self.state.write().unwrap();
+        let previous = state.current;
+        let next = *state.keys.range(previous + 1..).next()?.0;
+        state.current = next;
+        state.keys = state.keys.split_off(&previous);
+        Some(next)
+    }
+
+    /// Returns the service computing our decryption shares for the batches of `epoch`.
+    pub fn decryption_service(
+        &self,
+        epoch: Epoch,
+    ) -> Result<ThresholdDecryptionService, KeyRingError> {
+        self.with_keys(epoch, |keys| keys.service.clone())
+    }
+
+    /// Returns the public key set to decrypt the batches of `epoch`.
+    pub fn public_key_set(&self, epoch: Epoch) -> Result<PublicKeySet, KeyRingError> {
+        self.with_keys(epoch, |keys| keys.pk_set.clone())
+    }
+
+    fn with_keys<T, F>(&self, epoch: Epoch, f: F) -> Result<T, KeyRingError>
+    where
+        F: FnOnce(&EpochKeys) -> T,
+    {
+        let state = self.state.read().unwrap();
+        match state.keys.get(&epoch) {
+            Some(keys) => Ok(f(keys)),
+            None => match state.keys.keys().next() {
+                Some(oldest) if epoch < *oldest => Err(KeyRingError::GarbageCollected(epoch)),
+                _ => Err(KeyRingError::UnknownEpoch(epoch)),
+            },
+        }
+    }
+}
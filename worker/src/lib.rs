@@ -1,9 +1,19 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+mod backpressure;
 mod batch_maker;
+mod compression;
 mod helper;
+mod http_receiver;
+mod key_ring;
+mod metrics;
 mod primary_connector;
+mod priority;
 mod processor;
 mod quorum_waiter;
+mod receipts;
+mod sampler;
+mod share_gossip;
+mod shutdown;
 mod synchronizer;
 mod worker;
 
@@ -11,5 +21,19 @@ mod worker;
 #[path = "tests/common.rs"]
 mod common;
 
+pub use crate::backpressure::MEMPOOL_FULL;
+pub use crate::compression::{compress, decompress};
+pub use crate::http_receiver::LENGTH_DELIMITED;
+pub use crate::key_ring::{KeyRingError, ThresholdKeyRing};
+pub use crate::metrics::WorkerMetrics;
+pub use crate::priority::{
+    is_sorted_by_priority, sort_by_priority, split_epoch, split_priority, strip_priority,
+    with_epoch, with_priority, EPOCH_TAG, PRIORITY_TAG,
+};
+pub use crate::receipts::Receipt;
+pub use crate::share_gossip::ShareRequest;
+pub use crate::shutdown::Shutdown;
 pub use crate::worker::SerializedCiphertext;
 pub use crate::worker::Worker;
+pub use crate::worker::WorkerMessage;
+pub use crate::worker::{IntakeError, TRANSACTION_TOO_LARGE, UNKNOWN_EPOCH};
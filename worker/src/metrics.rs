@@ -0,0 +1,96 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use config::WorkerId;
+use prometheus::{IntCounter, Opts, Registry};
+
+/// The metrics exported by a worker. They are labeled with the worker id so that all the workers of an
+/// authority can share the same registry.
+#[derive(Clone)]
+pub struct WorkerMetrics {
+    /// The number of transactions received from clients.
+    pub transactions_received: IntCounter,
+    /// The number of client transactions rejected because the mempool was full.
+    pub transactions_rejected: IntCounter,
+    /// The number of client transactions dropped because they outlived their TTL before being batched.
+    pub transactions_expired: IntCounter,
+    /// The number of duplicate client transactions dropped before being batched.
+    pub transactions_duplicated: IntCounter,
+    /// The number of batches sealed by this worker.
+    pub batches_created: IntCounter,
+    /// The total size of the batches sealed by this worker (in bytes).
+    pub batch_bytes: IntCounter,
+    /// The number of our batches acknowledged by a quorum of workers.
+    pub batches_acknowledged: IntCounter,
+    /// The number of our batches re-sealed into smaller ones because peers rejected them as too large.
+    pub batches_resealed: IntCounter,
+    /// The number of our batches that a quorum of workers did not acknowledge (or not durably, with
+    /// `durable_acks`) once all of them replied.
+    pub batches_unacknowledged: IntCounter,
+    /// The number of our batches described in the logs (see `batch_sample_rate`).
+    pub batches_sampled: IntCounter,
+    /// The number of threshold decryption shares produced by this worker.
+    pub decryption_shares_produced: IntCounter,
+}
+
+impl WorkerMetrics {
+    /// Creates the metrics of worker `id` and registers them with `registry`.
+    pub fn new(registry: &Registry, id: WorkerId) -> Self {
+        let counter = |name: &str, help: &str| {
+            let opts = Opts::new(name, help).const_label("worker", id.to_string());
+            let counter = IntCounter::with_opts(opts).unwrap();
+            registry
+                .register(Box::new(counter.clone()))
+                .expect("Failed to register the worker metrics");
+            counter
+        };
+        Self {
+            transactions_received: counter(
+                "worker_transactions_received",
+                "Number of transactions received from clients",
+            ),
+            transactions_rejected: counter(
+                "worker_transactions_rejected",
+                "Number of client transactions rejected because the mempool was full",
+            ),
+            transactions_expired: counter(
+                "worker_transactions_expired",
+                "Number of client transactions dropped because they expired before being batched",
+            ),
+            transactions_duplicated: counter(
+                "worker_transactions_duplicated",
+                "Number of duplicate client transactions dropped before being batched",
+            ),
+            batches_created: counter("worker_batches_created", "Number of batches sealed"),
+            batch_bytes: counter(
+                "worker_batch_bytes",
+                "Total size of the sealed batches in bytes",
+            ),
+            batches_acknowledged: counter(
+                "worker_batches_acknowledged",
+                "Number of batches acknowledged by a quorum of workers",
+            ),
+            batches_resealed: counter(
+                "worker_batches_resealed",
+                "Number of batches re-sealed because peers rejected them as too large",
+            ),
+            batches_unacknowledged: counter(
+                "worker_batches_unacknowledged",
+                "Number of batches that a quorum of workers did not acknowledge",
+            ),
+            batches_sampled: counter(
+                "worker_batches_sampled",
+                "Number of batches described in the logs",
+            ),
+            decryption_shares_produced: counter(
+                "worker_decryption_shares_produced",
+                "Number of threshold decryption shares produced",
+            ),
+        }
+    }
+}
+
+impl Default for WorkerMetrics {
+    /// Creates metrics that are not exported (useful for tests).
+    fn default() -> Self {
+        Self::new(&Registry::new(), 0)
+    }
+}
@@ -1,39 +1,107 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use crate::worker::SerializedBatchDigestMessage;
 use bytes::Bytes;
+use config::WorkerId;
+use crypto::Digest;
+use log::warn;
 use network::SimpleSender;
+use primary::WorkerPrimaryMessage;
 use std::net::SocketAddr;
 use tokio::sync::mpsc::Receiver;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Duration, Instant};
+
+#[cfg(test)]
+#[path = "tests/primary_connector_tests.rs"]
+pub mod primary_connector_tests;
 
 // Send batches' digests to the primary.
 pub struct PrimaryConnector {
+    /// The id of this worker.
+    id: WorkerId,
     /// The primary network address.
     primary_address: SocketAddr,
+    /// The delay during which to coalesce the digests (sent immediately if zero).
+    flush_interval: Duration,
     /// Input channel to receive the digests to send to the primary.
     rx_digest: Receiver<SerializedBatchDigestMessage>,
     /// A network sender to send the baches' digests to the primary.
     network: SimpleSender,
+    /// The digests of our batches waiting for the next flush.
+    ours: Vec<Digest>,
+    /// The digests of the batches of the other authorities waiting for the next flush.
+    others: Vec<Digest>,
 }
 
 impl PrimaryConnector {
-    pub fn spawn(primary_address: SocketAddr, rx_digest: Receiver<SerializedBatchDigestMessage>) {
+    pub fn spawn(
+        id: WorkerId,
+        primary_address: SocketAddr,
+        flush_interval: u64,
+        rx_digest: Receiver<SerializedBatchDigestMessage>,
+    ) -> JoinHandle<()> {
         tokio::spawn(async move {
             Self {
+                id,
                 primary_address,
+                flush_interval: Duration::from_millis(flush_interval),
                 rx_digest,
                 network: SimpleSender::new(),
+                ours: Vec::new(),
+                others: Vec::new(),
             }
             .run()
             .await;
-        });
+        })
+    }
+
+    /// Sends all the coalesced digests to the primary in a single message.
+    async fn flush(&mut self) {
+        if self.ours.is_empty() && self.others.is_empty() {
+            return;
+        }
+        let message = WorkerPrimaryMessage::Batches(
+            self.ours.drain(..).collect(),
+            self.others.drain(..).collect(),
+            self.id,
+        );
+        let serialized = bincode::serialize(&message).expect("Failed to serialize our own message");
+        self.network
+            .send(self.primary_address, Bytes::from(serialized))
+            .await;
     }
 
     async fn run(&mut self) {
-        while let Some(digest) = self.rx_digest.recv().await {
-            // Send the digest through the network.
-            self.network
-                .send(self.primary_address, Bytes::from(digest))
-                .await;
+        if self.flush_interval.as_millis() == 0 {
+            while let Some(digest) = self.rx_digest.recv().await {
+                // Send the digest through the network.
+                self.network
+                    .send(self.primary_address, Bytes::from(digest))
+                    .await;
+            }
+            return;
+        }
+
+        // The timer runs from the first digest received after a flush.
+        let timer = sleep(self.flush_interval);
+        tokio::pin!(timer);
+        loop {
+            let pending = !self.ours.is_empty() || !self.others.is_empty();
+            tokio::select! {
+                Some(serialized) = self.rx_digest.recv() => {
+                    if !pending {
+                        timer.as_mut().reset(Instant::now() + self.flush_interval);
+                    }
+                    match bincode::deserialize(&serialized) {
+                        Ok(WorkerPrimaryMessage::OurBatch(digest, _)) => self.ours.push(digest),
+                        Ok(WorkerPrimaryMessage::OthersBatch(digest, _)) => self.others.push(digest),
+                        _ => warn!("Cannot coalesce unexpected message to the primary"),
+                    }
+                },
+                () = &mut timer, if pending => self.flush().await,
+                else => break,
+            }
         }
+        self.flush().await;
     }
 }
@@ -0,0 +1,105 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::batch_maker::SerializedCiphertext;
+use config::Epoch;
+use crypto::{hash, Digest};
+use std::cmp::Reverse;
+use std::convert::TryInto as _;
+use std::ops::Range;
+
+#[cfg(test)]
+#[path = "tests/priority_tests.rs"]
+pub mod priority_tests;
+
+/// The tag of the (optional) priority prefixed to a transaction, followed by the priority itself (8 bytes,
+/// big-endian) and then the serialized ciphertext. The tag is chosen not to be the start of a serialized
+/// ciphertext, so transactions without priority are left as they are.
+pub const PRIORITY_TAG: &[u8] = b"\xffPRIO";
+
+/// The size of the priority prefix.
+const PRIORITY_PREFIX_SIZE: usize = PRIORITY_TAG.len() + 8;
+
+/// The tag of the (optional) epoch prefixed to a transaction, followed by the epoch itself (8 bytes,
+/// big-endian) and then the transaction with its priority, if any. It tells the epoch of the threshold key
+/// the client encrypted the ciphertext with; transactions without it are assumed to use the current key.
+pub const EPOCH_TAG: &[u8] = b"\xffEPOC";
+
+/// The size of the epoch prefix.
+const EPOCH_PREFIX_SIZE: usize = EPOCH_TAG.len() + 8;
+
+/// Prefixes a serialized ciphertext with a priority.
+pub fn with_priority(priority: u64, ciphertext: &[u8]) -> SerializedCiphertext {
+    [PRIORITY_TAG, &priority.to_be_bytes()[..], ciphertext].concat()
+}
+
+/// Prefixes a transaction (possibly with a priority) with the epoch of the key it is encrypted with.
+pub fn with_epoch(epoch: Epoch, transaction: &[u8]) -> SerializedCiphertext {
+    [EPOCH_TAG, &epoch.to_be_bytes()[..], transaction].concat()
+}
+
+/// Splits a transaction into its epoch (if it has one) and the rest of the transaction.
+pub fn split_epoch(transaction: &[u8]) -> (Option<Epoch>, &[u8]) {
+    if transaction.len() >= EPOCH_PREFIX_SIZE && transaction.starts_with(EPOCH_TAG) {
+        let (prefix, rest) = transaction.split_at(EPOCH_PREFIX_SIZE);
+        let epoch = Epoch::from_be_bytes(prefix[EPOCH_TAG.len()..].try_into().unwrap());
+        return (Some(epoch), rest);
+    }
+    (None, transaction)
+}
+
+/// Splits a transaction into its priority (0 if it has none) and its serialized ciphertext, skipping
+/// its epoch.
+pub fn split_priority(transaction: &[u8]) -> (u64, &[u8]) {
+    let transaction = split_epoch(transaction).1;
+    if transaction.len() >= PRIORITY_PREFIX_SIZE && transaction.starts_with(PRIORITY_TAG) {
+        let (prefix, ciphertext) = transaction.split_at(PRIORITY_PREFIX_SIZE);
+        let priority = u64::from_be_bytes(prefix[PRIORITY_TAG.len()..].try_into().unwrap());
+        return (priority, ciphertext);
+    }
+    (0, transaction)
+}
+
+/// The serialized ciphertext of a transaction, without its epoch and priority.
+pub fn strip_priority(transaction: &[u8]) -> &[u8] {
+    split_priority(transaction).1
+}
+
+/// The key ordering the transactions of a batch: the highest priority first, ties broken by digest so
+/// that the order only depends on the transactions.
+fn priority_key(transaction: &[u8]) -> (Reverse<u64>, Digest) {
+    (Reverse(split_priority(transaction).0), hash(transaction))
+}
+
+/// Orders the transactions of a batch by priority. The transactions of each bundle (given by their
+/// positions in the batch, in order) stay together and in their order: the bundle is placed as a whole
+/// by its first transaction. The positions of the bundles are updated accordingly.
+pub fn sort_by_priority(batch: &mut Vec<SerializedCiphertext>, bundles: &mut Vec<Range<usize>>) {
+    let mut units = Vec::with_capacity(batch.len());
+    let mut remaining = bundles.drain(..).filter(|x| !x.is_empty()).peekable();
+    let mut next = 0;
+    while next < batch.len() {
+        let unit = match remaining.peek() {
+            Some(bundle) if bundle.start == next => (remaining.next().unwrap(), true),
+            _ => (next..next + 1, false),
+        };
+        next = unit.0.end;
+        units.push(unit);
+    }
+    drop(remaining);
+    units.sort_by_cached_key(|(x, _)| priority_key(&batch[x.start]));
+
+    let mut transactions: Vec<_> = batch.drain(..).map(Some).collect();
+    for (unit, bundle) in units {
+        if bundle {
+            bundles.push(batch.len()..batch.len() + unit.len());
+        }
+        batch.extend(unit.map(|i| transactions[i].take().unwrap()));
+    }
+}
+
+/// Whether the transactions of a batch are ordered by priority. Ties may come in any order: we cannot
+/// tell the transactions of a bundle (which all have the same priority) from the others.
+pub fn is_sorted_by_priority(batch: &[SerializedCiphertext]) -> bool {
+    batch
+        .windows(2)
+        .all(|x| split_priority(&x[0]).0 >= split_priority(&x[1]).0)
+}
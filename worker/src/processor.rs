@@ -1,13 +1,18 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+use crate::compression::compress;
+use crate::shutdown::Shutdown;
 use crate::worker::SerializedBatchDigestMessage;
 use config::WorkerId;
-use crypto::Digest;
-use ed25519_dalek::Digest as _;
-use ed25519_dalek::Sha512;
+use crypto::hash;
+#[cfg(not(test))]
+use log::warn;
 use primary::WorkerPrimaryMessage;
-use std::convert::TryInto;
-use store::Store;
+#[cfg(test)]
+use std::println as warn;
+use store::KeyValueStore;
 use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
 
 #[cfg(test)]
 #[path = "tests/processor_tests.rs"]
@@ -16,29 +21,54 @@ pub mod processor_tests;
 /// Indicates a serialized `WorkerMessage::DecryptableBatch` message.
 pub type SerializedDecryptableBatchMessage = Vec<u8>;
 
-/// Hashes and stores batches, it then outputs the batch's digest.
+/// A batch to store, along with the channel on which to signal whether it is durably stored (when its
+/// author waits for a durable acknowledgement).
+pub type StoreRequest = (
+    SerializedDecryptableBatchMessage,
+    Option<oneshot::Sender<bool>>,
+);
+
+/// Hashes and stores batches, it then outputs the batch's digest. The batches whose author waits for a
+/// durable acknowledgement are written synced to disk before signaling them.
 pub struct Processor;
 
 impl Processor {
-    pub fn spawn(
+    pub fn spawn<S: KeyValueStore>(
         // Our worker's id.
         id: WorkerId,
         // The persistent storage.
-        mut store: Store,
+        mut store: S,
         // Input channel to receive batches.
-        mut rx_batch: Receiver<SerializedDecryptableBatchMessage>,
+        mut rx_batch: Receiver<StoreRequest>,
         // Output channel to send out batches' digests.
         tx_digest: Sender<SerializedBatchDigestMessage>,
         // Whether we are processing our own batches or the batches of other nodes.
         own_digest: bool,
-    ) {
+        // The zstd level at which we compress the batches we store (if any).
+        compression_level: Option<i32>,
+        // Delays the end of the shutdown until we stored the batches in flight.
+        shutdown: Shutdown,
+    ) -> JoinHandle<()> {
         tokio::spawn(async move {
-            while let Some(batch) = rx_batch.recv().await {
-                // Hash the batch.
-                let digest = Digest(Sha512::digest(&batch).as_slice()[..32].try_into().unwrap());
+            let _shutdown = shutdown;
+            while let Some((batch, stored)) = rx_batch.recv().await {
+                // Hash the (uncompressed) batch, so that its digest does not depend on the compression.
+                let digest = hash(&batch);
 
-                // Store the batch.
-                store.write(digest.to_vec(), batch).await;
+                // Store the batch. A synced write only waits for the write-ahead log to reach the disk,
+                // rather than flushing the whole store for every batch.
+                let value = compress(batch, compression_level);
+                match stored {
+                    Some(stored) => {
+                        let result = store.write_synced(vec![(digest.to_vec(), value)]).await;
+                        if let Err(e) = &result {
+                            warn!("Failed to store batch {}: {}", digest, e);
+                        }
+                        // Always signal, so that the author counts on the other workers upon failures.
+                        let _ = stored.send(result.is_ok());
+                    }
+                    None => store.write(digest.to_vec(), value).await,
+                }
 
                 // Deliver the batch's digest.
                 let message = match own_digest {
@@ -52,6 +82,6 @@ impl Processor {
                     .await
                     .expect("Failed to send digest");
             }
-        });
+        })
     }
 }
@@ -1,11 +1,18 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use crate::{
-    batch_maker::Batch, processor::SerializedDecryptableBatchMessage, worker::WorkerMessage,
+    batch_maker::Batch,
+    key_ring::ThresholdKeyRing,
+    metrics::WorkerMetrics,
+    priority::strip_priority,
+    processor::{SerializedDecryptableBatchMessage, StoreRequest},
+    receipts::Receipts,
+    shutdown::Shutdown,
+    worker::{WorkerMessage, BATCH_MISORDERED, BATCH_STORED, BATCH_TOO_LARGE, UNKNOWN_EPOCH},
 };
-use config::{Committee, Stake};
+use config::{Committee, Epoch, Stake};
 use crypto::{
     threshold::{Ciphertext, DecryptionShare},
-    BatchDecryptionShares, NodeDecryptionShares, NodeIndex, PublicKey, ThresholdDecryptionService,
+    BatchDecryptionShares, NodeDecryptionShares, NodeIndex, PublicKey,
 };
 use futures::stream::futures_unordered::FuturesUnordered;
 use futures::stream::StreamExt as _;
@@ -13,12 +20,14 @@ use futures::stream::StreamExt as _;
 use log::{debug, error, info, warn}; // Use log crate when building application
 use network::CancelHandler;
 use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
+use std::ops::Range;
 #[cfg(test)]
 use std::{println as info, println as warn, println as error, println as debug};
 use tokio::sync::{
-    mpsc::{Receiver, Sender},
+    mpsc::{Receiver, Sender, UnboundedSender},
     oneshot,
 };
+use tokio::task::JoinHandle;
 
 #[cfg(test)]
 #[path = "tests/quorum_waiter_tests.rs"]
@@ -26,101 +35,165 @@ pub mod quorum_waiter_tests;
 
 #[derive(Debug)]
 pub struct QuorumWaiterMessage {
+    /// The epoch of the threshold key the transactions of the batch are encrypted with.
+    pub epoch: Epoch,
     /// A serialized `WorkerMessage::Batch` message.
     pub batch: Batch,
+    /// The positions of the bundles in the batch, which must not be split if we reseal it.
+    pub bundles: Vec<Range<usize>>,
     /// The cancel handlers to receive the acknowledgements of our broadcast.
     pub named_decrypt_shares_handlers: Vec<(PublicKey, CancelHandler)>,
 }
 
+/// The reply of a worker to one of our batches.
+enum SharesReply {
+    /// The decryption shares of the worker (weighted by its stake).
+    Shares(Stake, (NodeIndex, Vec<DecryptionShare>)),
+    /// The worker (of the specified stake) rejected the batch as too large.
+    TooLarge(Stake),
+    /// The worker (of the specified stake) cannot compute its decryption shares for the epoch of the batch.
+    UnknownEpoch(Stake),
+    /// The worker (of the specified stake) rejected the batch as not ordered by priority.
+    Misordered(Stake),
+    /// The worker did not reply, or with an invalid message.
+    Invalid,
+}
+
 /// The QuorumWaiter waits for 2f authorities to acknowledge reception of a batch.
 pub struct QuorumWaiter {
     /// The committee information.
     committee: Committee,
     /// The stake of this authority.
     stake: Stake,
-    /// Service for decrypting ciphertexts
-    threshold_decryption_service: ThresholdDecryptionService,
+    /// Whether we only count the acknowledgements of the workers that stored the batch on disk.
+    durable_acks: bool,
+    /// The threshold keys of each epoch, to compute our decryption shares.
+    key_ring: ThresholdKeyRing,
     /// Input Channel to receive commands.
     rx_message: Receiver<QuorumWaiterMessage>,
     /// Channel to deliver decryptable batches for which we have enough acknowledgements.
-    tx_batch: Sender<SerializedDecryptableBatchMessage>,
+    tx_batch: Sender<StoreRequest>,
     /// Channel to send DecryptableBatches to batch_maker.
     tx_decryptable_batch: Sender<(
         SerializedDecryptableBatchMessage,
         oneshot::Sender<Vec<(PublicKey, CancelHandler)>>,
     )>,
+    /// Channel to send back to the batch_maker the batches that too many workers rejected as too large. It is
+    /// unbounded so that we never wait for the batch_maker, which may be waiting for us to take its batches.
+    tx_reseal: UnboundedSender<(Epoch, Batch, Vec<Range<usize>>)>,
+    /// The metrics exported by the worker.
+    metrics: WorkerMetrics,
+    /// The clients waiting for their transactions to be acknowledged by a quorum.
+    receipts: Receipts,
 }
 
 impl QuorumWaiter {
     /// Spawn a new QuorumWaiter.
+    #[allow(clippy::too_many_arguments)]
     pub fn spawn(
         committee: Committee,
         stake: Stake,
-        threshold_decryption_service: ThresholdDecryptionService,
+        durable_acks: bool,
+        key_ring: ThresholdKeyRing,
         rx_message: Receiver<QuorumWaiterMessage>,
-        tx_batch: Sender<Vec<u8>>,
+        tx_batch: Sender<StoreRequest>,
         tx_decryptable_batch: Sender<(
             SerializedDecryptableBatchMessage,
             oneshot::Sender<Vec<(PublicKey, CancelHandler)>>,
         )>,
-    ) {
+        tx_reseal: UnboundedSender<(Epoch, Batch, Vec<Range<usize>>)>,
+        metrics: WorkerMetrics,
+        receipts: Receipts,
+        shutdown: Shutdown,
+    ) -> JoinHandle<()> {
         tokio::spawn(async move {
+            // Holding the token delays the end of the shutdown until we delivered the sealed batches.
+            let _shutdown = shutdown;
             Self {
                 committee,
                 stake,
-                threshold_decryption_service,
+                durable_acks,
+                key_ring,
                 rx_message,
                 tx_batch,
                 tx_decryptable_batch: tx_decryptable_batch,
+                tx_reseal,
+                metrics,
+                receipts,
             }
             .run()
             .await;
-        });
+        })
     }
 
     /// Helper function. It waits for a future to complete and then delivers a value.
     async fn decrypt_shares_waiter(
         wait_for_decrypt_shares: CancelHandler,
         stake: Stake,
-    ) -> Option<(Stake, (NodeIndex, Vec<DecryptionShare>))> {
+    ) -> SharesReply {
         match wait_for_decrypt_shares.await {
+            Ok(bytes) if bytes.as_ref() == BATCH_TOO_LARGE => SharesReply::TooLarge(stake),
+            Ok(bytes) if bytes.as_ref() == UNKNOWN_EPOCH => SharesReply::UnknownEpoch(stake),
+            Ok(bytes) if bytes.as_ref() == BATCH_MISORDERED => SharesReply::Misordered(stake),
             Ok(bytes) => {
                 // we try to decrypt the bytes.
-                bincode::deserialize(&bytes)
-                    .ok()
-                    .map(|dec_shares| (stake, dec_shares))
+                bincode::deserialize(&bytes).map_or(SharesReply::Invalid, |dec_shares| {
+                    SharesReply::Shares(stake, dec_shares)
+                })
             }
             // We only receive an error if the sender closes the channel before sending a bytes message.
             // If too many byzantine (>f) don't send us decrypt shares, this will stall our worker.
-            Err(_) => None,
+            Err(_) => SharesReply::Invalid,
         }
     }
-    /// Helper function. It waits for a future to complete and then delivers a value.
-    async fn ack_waiter(wait_for: CancelHandler, deliver: Stake) -> Stake {
-        let _ = wait_for.await;
-        deliver
+    /// Helper function. It waits for the acknowledgement of a worker and then delivers its stake, or
+    /// nothing if we require a durable acknowledgement and the worker did not store the batch.
+    async fn ack_waiter(wait_for: CancelHandler, deliver: Stake, durable: bool) -> Stake {
+        match wait_for.await {
+            Ok(bytes) if durable && bytes.as_ref() != BATCH_STORED => 0,
+            Err(_) if durable => 0,
+            _ => deliver,
+        }
+    }
+
+    /// Whether the workers that did not reject a batch (weighted by stake) can no longer form a quorum.
+    fn unreachable_quorum(&self, rejected_stake: Stake) -> bool {
+        self.committee.total_stake() - rejected_stake < self.committee.quorum_threshold()
     }
 
     /// Main loop.
     async fn run(&mut self) {
         // receive batch from batch_maker
         while let Some(QuorumWaiterMessage {
+            epoch,
             batch,
+            bundles,
             named_decrypt_shares_handlers,
         }) = self.rx_message.recv().await
         {
             debug!("quorum_waiter: received QuorumWaiterMessage(batch)");
             // Step 1. first we decrypt our batch's ciphertexts
+            let threshold_decryption_service = match self.key_ring.decryption_service(epoch) {
+                Ok(service) => service,
+                Err(e) => {
+                    warn!("Dropping batch: {}", e);
+                    continue;
+                }
+            };
             let ciphertexts: Vec<Ciphertext> = batch
                 .par_iter()
-                .map(|tx| bincode::deserialize(tx).unwrap())
+                .map(|tx| bincode::deserialize(strip_priority(tx)).unwrap())
                 .collect();
-            let dec_shares: NodeDecryptionShares = self
-                .threshold_decryption_service
+            let dec_shares: NodeDecryptionShares = threshold_decryption_service
                 .request_decryption(ciphertexts)
                 .await;
+            self.metrics
+                .decryption_shares_produced
+                .inc_by(dec_shares.1.len() as u64);
             let mut batch_decryption_shares: BatchDecryptionShares = vec![dec_shares];
             debug!("quorum_waiter: successfully decrypted our shares");
+            let watched = self.receipts.watched(&batch);
+            let transactions = batch.len();
 
             // Then we wrap the handlers in futures
             let mut decrypt_shares_futures: FuturesUnordered<_> = named_decrypt_shares_handlers
@@ -133,17 +206,69 @@ impl QuorumWaiter {
 
             // Step 2. and then wait for the first 2f nodes' decryption shares to arrive
             let mut total_stake = self.stake;
+            let mut rejected_stake = 0;
+            let mut abandoned = false;
             let mut maybe_serialized_decryptable_batch: Option<SerializedDecryptableBatchMessage> =
                 None;
             let (sender, receiver): (oneshot::Sender<_>, oneshot::Receiver<_>) = oneshot::channel();
-            while let Some(Some((stake, dec_shares))) = decrypt_shares_futures.next().await {
+            while let Some(reply) = decrypt_shares_futures.next().await {
+                let (stake, dec_shares) = match reply {
+                    SharesReply::Shares(stake, dec_shares) => (stake, dec_shares),
+                    SharesReply::TooLarge(stake) => {
+                        // Once the workers that accept the batch can no longer form a quorum, we
+                        // hand its transactions back to the batch_maker to split them.
+                        rejected_stake += stake;
+                        if self.unreachable_quorum(rejected_stake) {
+                            warn!(
+                                "Workers rejected our batch of {} transactions as too large: re-sealing it",
+                                batch.len()
+                            );
+                            self.metrics.batches_resealed.inc();
+                            self.tx_reseal
+                                .send((epoch, batch, bundles))
+                                .expect("Failed to send batch back to batch_maker");
+                            abandoned = true;
+                            break;
+                        }
+                        continue;
+                    }
+                    SharesReply::UnknownEpoch(stake) => {
+                        // The batch can never be decrypted without a quorum of decryption shares.
+                        rejected_stake += stake;
+                        if self.unreachable_quorum(rejected_stake) {
+                            warn!(
+                                "Workers have no keys for epoch {}: dropping our batch of {} transactions",
+                                epoch,
+                                batch.len()
+                            );
+                            abandoned = true;
+                            break;
+                        }
+                        continue;
+                    }
+                    SharesReply::Misordered(stake) => {
+                        // Peers do not order transactions as we do (e.g. they use another setting).
+                        rejected_stake += stake;
+                        if self.unreachable_quorum(rejected_stake) {
+                            warn!(
+                                "Workers rejected the order of our batch of {} transactions: dropping it",
+                                batch.len()
+                            );
+                            abandoned = true;
+                            break;
+                        }
+                        continue;
+                    }
+                    SharesReply::Invalid => break,
+                };
                 debug!("quorum_waiter: received dec_shares");
                 total_stake += stake;
                 batch_decryption_shares.push(dec_shares);
                 if total_stake >= self.committee.quorum_threshold() {
                     debug!("quorum_waiter: gathered quorum threshold of dec shares!");
                     // Then we broadcast the decryptable_batch back to all nodes
-                    let message = WorkerMessage::DecryptableBatch(batch, batch_decryption_shares);
+                    let message =
+                        WorkerMessage::DecryptableBatch(epoch, batch, batch_decryption_shares);
                     let serialized_decryptable_batch =
                         bincode::serialize(&message).expect("Failed to serialize our own batch");
                     maybe_serialized_decryptable_batch = Some(serialized_decryptable_batch.clone());
@@ -154,6 +279,9 @@ impl QuorumWaiter {
                     break;
                 }
             }
+            if abandoned {
+                continue;
+            }
             let serialized_decryptable_batch = maybe_serialized_decryptable_batch
                 .expect("Couldn't get enough decryptable shares for batch");
             let named_ack_handlers = receiver
@@ -165,27 +293,42 @@ impl QuorumWaiter {
                 .into_iter()
                 .map(|(name, handler)| {
                     let stake = self.committee.stake(&name);
-                    Self::ack_waiter(handler, stake)
+                    Self::ack_waiter(handler, stake, self.durable_acks)
                 })
                 .collect();
 
-            
-            // Wait for the first 2f nodes to send back an Ack. Then we consider the decryptable batch
-            // delivered and we send its digest to the primary (that will include it into
-            // the dag). This should reduce the amount of syncing.
+            // Wait for the first 2f nodes to send back an Ack (once they stored the batch, with durable
+            // acks). Then we consider the decryptable batch delivered and we send its digest to the primary
+            // (that will include it into the dag). This should reduce the amount of syncing.
             let mut total_stake = self.stake;
+            let mut acknowledged = false;
             debug!("quorum_waiter: waiting for 2f acks");
             while let Some(stake) = wait_for_quorum.next().await {
                 total_stake += stake;
                 if total_stake >= self.committee.quorum_threshold() {
+                    acknowledged = true;
                     debug!("quorum_waiter: gathered quorum of acks!");
+                    self.metrics.batches_acknowledged.inc();
+                    self.receipts
+                        .deliver(watched, &serialized_decryptable_batch, epoch);
                     self.tx_batch
-                        .send(serialized_decryptable_batch)
+                        .send((serialized_decryptable_batch, None))
                         .await
                         .expect("Failed to deliver batch");
                     break;
                 }
             }
+            if !acknowledged {
+                // The committee should check that all its workers share our `durable_acks` setting.
+                warn!(
+                    "Only {} of {} stake acknowledged our batch of {} transactions{}: giving up on it",
+                    total_stake,
+                    self.committee.total_stake(),
+                    transactions,
+                    if self.durable_acks { " durably" } else { "" }
+                );
+                self.metrics.batches_unacknowledged.inc();
+            }
         }
     }
 }
@@ -0,0 +1,88 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::batch_maker::Batch;
+use config::Epoch;
+use crypto::{hash, Digest};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+
+#[cfg(test)]
+#[path = "tests/receipts_tests.rs"]
+pub mod receipts_tests;
+
+/// Confirms that a client transaction made it into a batch acknowledged by a quorum of workers. The
+/// round of the header including the batch is not known yet at that point (the primary picks it later).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Receipt {
+    /// The digest of the transaction.
+    pub transaction: Digest,
+    /// The digest of the batch carrying the transaction (as reported to the primary).
+    pub batch: Digest,
+    /// The epoch of the threshold key the batch is encrypted with.
+    pub epoch: Epoch,
+}
+
+/// The clients waiting for receipts, by transaction digest. It is shared between the intake of the
+/// transactions (that subscribes) and the `QuorumWaiter` (that delivers the receipts).
+#[derive(Clone, Default)]
+pub struct Receipts {
+    pending: Arc<Mutex<HashMap<Digest, Vec<oneshot::Sender<Receipt>>>>>,
+}
+
+impl Receipts {
+    /// Hashes a transaction (or a batch).
+    pub fn digest(data: &[u8]) -> Digest {
+        hash(data)
+    }
+
+    /// Registers for the receipt of a transaction, before submitting it. Dropping the receiver cancels
+    /// the subscription; the receipt never comes if the transaction is dropped (eg. it expired or it is
+    /// a duplicate).
+    pub fn subscribe(&self, transaction: &[u8]) -> oneshot::Receiver<Receipt> {
+        let (sender, receiver) = oneshot::channel();
+        self.pending
+            .lock()
+            .unwrap()
+            .entry(Self::digest(transaction))
+            .or_insert_with(Vec::new)
+            .push(sender);
+        receiver
+    }
+
+    /// Returns the digests of the transactions of `batch` that clients wait a receipt for. This does not
+    /// hash anything when nobody waits for a receipt.
+    pub fn watched(&self, batch: &Batch) -> Vec<Digest> {
+        let pending = self.pending.lock().unwrap();
+        if pending.is_empty() {
+            return Vec::new();
+        }
+        batch
+            .iter()
+            .map(|x| Self::digest(x))
+            .filter(|x| pending.contains_key(x))
+            .collect()
+    }
+
+    /// Delivers the receipts of `transactions`, once their (serialized) batch is acknowledged by a
+    /// quorum. It also forgets the subscriptions the clients gave up on.
+    pub fn deliver(&self, transactions: Vec<Digest>, batch: &[u8], epoch: Epoch) {
+        let mut pending = self.pending.lock().unwrap();
+        if pending.is_empty() {
+            return;
+        }
+        let batch = Self::digest(batch);
+        for transaction in transactions {
+            for subscriber in pending.remove(&transaction).unwrap_or_default() {
+                let _ = subscriber.send(Receipt {
+                    transaction: transaction.clone(),
+                    batch: batch.clone(),
+                    epoch,
+                });
+            }
+        }
+        pending.retain(|_, subscribers| {
+            subscribers.retain(|x| !x.is_closed());
+            !subscribers.is_empty()
+        });
+    }
+}
@@ -0,0 +1,72 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::batch_maker::SerializedCiphertext;
+use crypto::hash;
+use std::fmt::Write as _;
+
+#[cfg(test)]
+#[path = "tests/sampler_tests.rs"]
+pub mod sampler_tests;
+
+/// The number of leading bytes of each transaction shown in the description of a sampled batch.
+pub const SAMPLED_BYTES: usize = 8;
+
+/// Picks the batches whose content the worker logs (at trace level), a fraction `rate` of them. The
+/// choice is deterministic (every `1 / rate`-th batch), and costs nothing but an addition per batch
+/// that is not sampled. Callers only ask for a sample when trace logging is enabled.
+pub struct BatchSampler {
+    /// The fraction of the batches to sample, between 0 (none) and 1 (all).
+    rate: f64,
+    /// The fraction of a batch accumulated since the last sample.
+    credit: f64,
+}
+
+impl BatchSampler {
+    pub fn new(rate: f64) -> Self {
+        Self {
+            rate: Self::clamp(rate),
+            credit: 0.0,
+        }
+    }
+
+    /// Sets the fraction of the batches to sample from now on.
+    pub fn set_rate(&mut self, rate: f64) {
+        self.rate = Self::clamp(rate);
+    }
+
+    fn clamp(rate: f64) -> f64 {
+        if rate.is_nan() {
+            0.0
+        } else {
+            rate.clamp(0.0, 1.0)
+        }
+    }
+
+    /// Describes the batch (given along with its serialized message) if it is sampled: its digest, its
+    /// number of transactions, and the first bytes of each of them (in hex).
+    pub fn sample(&mut self, batch: &[SerializedCiphertext], serialized: &[u8]) -> Option<String> {
+        if self.rate <= 0.0 {
+            return None;
+        }
+        self.credit += self.rate;
+        if self.credit < 1.0 {
+            return None;
+        }
+        self.credit -= 1.0;
+
+        let mut description = format!(
+            "Sampled batch {:?} contains {} tx(s):",
+            hash(serialized),
+            batch.len()
+        );
+        for transaction in batch {
+            description.push(' ');
+            for byte in transaction.iter().take(SAMPLED_BYTES) {
+                let _ = write!(description, "{:02x}", byte);
+            }
+            if transaction.len() > SAMPLED_BYTES {
+                description.push_str("..");
+            }
+        }
+        Some(description)
+    }
+}
@@ -0,0 +1,458 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::compression::decompress;
+use crate::key_ring::ThresholdKeyRing;
+use crate::priority::strip_priority;
+use crate::worker::WorkerMessage;
+use bytes::Bytes;
+use config::{Committee, Parameters, WorkerId};
+use crypto::threshold::{Ciphertext, DecryptionShare, PublicKeySet};
+use crypto::{BatchDecryptionShares, Digest, NodeDecryptionShares, NodeIndex, PublicKey};
+use log::{debug, error, warn};
+use network::SimpleSender;
+use rayon::prelude::*;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::time::{SystemTime, UNIX_EPOCH};
+use store::KeyValueStore;
+use tokio::sync::mpsc::Receiver;
+use tokio::sync::{oneshot, watch};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Duration, Instant};
+
+#[cfg(test)]
+#[path = "tests/share_gossip_tests.rs"]
+pub mod share_gossip_tests;
+
+/// Resolution of the timer managing retrials of share requests (in ms).
+const TIMER_RESOLUTION: u64 = 1_000;
+
+/// The number of times we ask the other workers for their shares before giving up on a batch.
+const MAX_ATTEMPTS: u32 = 5;
+
+/// The number of recently committed batches whose shares we reveal to the other workers (the shares of
+/// older batches were gathered long ago).
+const MAX_COMMITTED: usize = 100_000;
+
+/// Asks the worker to gather `threshold + 1` valid decryption shares for a committed batch. The shares are
+/// delivered through the oneshot channel, which is dropped if they cannot be gathered.
+pub type ShareRequest = (Digest, oneshot::Sender<BatchDecryptionShares>);
+
+/// The share messages received from the other workers.
+#[derive(Debug)]
+pub enum ShareMessage {
+    /// A worker asks for our decryption shares of a batch.
+    Request(Digest, /* origin */ PublicKey),
+    /// A worker sends us its decryption shares of a batch.
+    Shares(Digest, /* author */ PublicKey, NodeDecryptionShares),
+    /// Our primary tells us that these batches were committed.
+    Committed(Vec<Digest>),
+}
+
+/// The shares gathered so far for a batch.
+struct PendingShares {
+    /// The transactions of the batch.
+    ciphertexts: Vec<Ciphertext>,
+    /// The key set the batch is encrypted with, to verify the shares.
+    pk_set: PublicKeySet,
+    /// The valid shares, indexed by node index. A node index only counts once towards the threshold, no
+    /// matter how many times its shares are received.
+    shares: BTreeMap<NodeIndex, Vec<DecryptionShare>>,
+    /// The authorities whose valid shares we already processed. The retries only go to the others, and a
+    /// worker resending its shares (eg. after a restart) is ignored. The author of a share message is not
+    /// authenticated, so invalid shares do not count: they must not silence the actual author.
+    authors: HashSet<PublicKey>,
+    /// The channels through which to deliver the shares once we have enough of them.
+    replies: Vec<oneshot::Sender<BatchDecryptionShares>>,
+    /// The number of times we requested the missing shares.
+    attempts: u32,
+    /// The time (in ms) at which we last requested the missing shares.
+    timestamp: u128,
+}
+
+impl PendingShares {
+    fn is_complete(&self) -> bool {
+        self.shares.len() > self.pk_set.threshold()
+    }
+
+    /// Adds the shares of node `index` if they are valid for every transaction of the batch.
+    fn add(&mut self, index: NodeIndex, shares: Vec<DecryptionShare>) -> bool {
+        if self.shares.contains_key(&index) || shares.len() != self.ciphertexts.len() {
+            return false;
+        }
+        let public_key_share = self.pk_set.public_key_share(index);
+        let valid = shares
+            .par_iter()
+            .zip(self.ciphertexts.par_iter())
+            .all(|(share, ciphertext)| public_key_share.verify_decryption_share(share, ciphertext));
+        if valid {
+            self.shares.insert(index, shares);
+        }
+        valid
+    }
+}
+
+/// Gossips the decryption shares of committed batches with the workers of the other authorities that share
+/// our worker id. It answers their requests for our shares, and collects theirs when the shares stored
+/// alongside one of our batches are not enough to decrypt it.
+pub struct ShareGossip<S> {
+    /// The public key of this authority.
+    name: PublicKey,
+    /// The id of this worker.
+    id: WorkerId,
+    /// The committee information.
+    committee: Committee,
+    /// The persistent storage.
+    store: S,
+    /// The threshold keys of each epoch.
+    key_ring: ThresholdKeyRing,
+    /// The delay to wait before re-trying to request the missing shares (doubled after every attempt).
+    sync_retry_delay: u64,
+    /// Input channel to receive the share requests of the application.
+    rx_request: Receiver<ShareRequest>,
+    /// Input channel to receive the share messages of the other workers.
+    rx_message: Receiver<ShareMessage>,
+    /// Receives the parameters reloaded by the operator.
+    rx_parameters: watch::Receiver<Parameters>,
+    /// A network sender to send our shares and requests to the other workers.
+    network: SimpleSender,
+    /// The batches for which we are gathering shares.
+    pending: HashMap<Digest, PendingShares>,
+    /// The recently committed batches: we only reveal our shares of committed batches, as they would
+    /// otherwise let the other workers decrypt transactions before their order is fixed.
+    committed: HashSet<Digest>,
+    /// The recently committed batches, oldest first.
+    committed_order: VecDeque<Digest>,
+}
+
+impl<S: KeyValueStore> ShareGossip<S> {
+    #[allow(clippy::too_many_arguments)]
+    pub fn spawn(
+        name: PublicKey,
+        id: WorkerId,
+        committee: Committee,
+        store: S,
+        key_ring: ThresholdKeyRing,
+        sync_retry_delay: u64,
+        rx_request: Receiver<ShareRequest>,
+        rx_message: Receiver<ShareMessage>,
+        rx_parameters: watch::Receiver<Parameters>,
+    ) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            Self {
+                name,
+                id,
+                committee,
+                store,
+                key_ring,
+                sync_retry_delay,
+                rx_request,
+                rx_message,
+                rx_parameters,
+                network: SimpleSender::new(),
+                pending: HashMap::new(),
+                committed: HashSet::new(),
+                committed_order: VecDeque::new(),
+            }
+            .run()
+            .await;
+        })
+    }
+
+    fn now() -> u128 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Failed to measure time")
+            .as_millis()
+    }
+
+    /// Reads one of the batches of our store, along with the decryption shares stored with it.
+    async fn read_batch(
+        &mut self,
+        digest: &Digest,
+    ) -> Option<(
+        PublicKeySet,
+        Vec<Ciphertext>,
+        BatchDecryptionShares,
+        NodeDecryptionShares,
+    )> {
+        let serialized = match self
+            .store
+            .read(digest.to_vec())
+            .await
+            .map(|x| x.map(decompress))
+        {
+            Ok(Some(Ok(serialized))) => serialized,
+            Ok(Some(Err(e))) => {
+                warn!("Failed to decompress batch {}: {}", digest, e);
+                return None;
+            }
+            Ok(None) => {
+                warn!("Cannot gossip the shares of unknown batch {}", digest);
+                return None;
+            }
+            Err(e) => {
+                error!("{}", e);
+                return None;
+            }
+        };
+        let (epoch, batch, shares) = match bincode::deserialize(&serialized) {
+            Ok(WorkerMessage::DecryptableBatch(epoch, batch, shares)) => (epoch, batch, shares),
+            _ => {
+                warn!("Batch {} does not carry decryption shares", digest);
+                return None;
+            }
+        };
+        let keys = self.key_ring.public_key_set(epoch).and_then(|pk_set| {
+            self.key_ring
+                .decryption_service(epoch)
+                .map(|service| (pk_set, service))
+        });
+        let (pk_set, service) = match keys {
+            Ok(keys) => keys,
+            Err(e) => {
+                warn!("Cannot gossip the shares of batch {}: {}", digest, e);
+                return None;
+            }
+        };
+        let ciphertexts: Option<Vec<Ciphertext>> = batch
+            .par_iter()
+            .map(|tx| {
+                bincode::deserialize(strip_priority(tx))
+                    .ok()
+                    .filter(|ciphertext: &Ciphertext| ciphertext.verify())
+            })
+            .collect();
+        let ciphertexts = match ciphertexts {
+            Some(ciphertexts) => ciphertexts,
+            None => {
+                warn!("Batch {} contains invalid ciphertexts", digest);
+                return None;
+            }
+        };
+        let ours = service.request_decryption(ciphertexts.clone()).await;
+        Some((pk_set, ciphertexts, shares, ours))
+    }
+
+    /// Sends a share request for `digest` to the given authorities.
+    async fn request(&mut self, digest: &Digest, targets: Vec<PublicKey>) {
+        let addresses = targets
+            .iter()
+            .filter_map(|name| self.committee.worker(name, &self.id).ok())
+            .map(|address| address.worker_to_worker)
+            .collect();
+        let message = WorkerMessage::DecryptionSharesRequest(digest.clone(), self.name);
+        let serialized = bincode::serialize(&message).expect("Failed to serialize our own message");
+        self.network
+            .broadcast(addresses, Bytes::from(serialized))
+            .await;
+    }
+
+    /// Remembers that a batch was committed, forgetting the oldest ones beyond `MAX_COMMITTED`.
+    fn commit(&mut self, digest: Digest) {
+        if !self.committed.insert(digest.clone()) {
+            return;
+        }
+        self.committed_order.push_back(digest);
+        while self.committed_order.len() > MAX_COMMITTED {
+            if let Some(digest) = self.committed_order.pop_front() {
+                self.committed.remove(&digest);
+            }
+        }
+    }
+
+    /// Starts gathering the shares of one of our batches.
+    async fn handle_request(
+        &mut self,
+        digest: Digest,
+        reply: oneshot::Sender<BatchDecryptionShares>,
+    ) {
+        if let Some(pending) = self.pending.get_mut(&digest) {
+            pending.replies.push(reply);
+            return;
+        }
+        let (pk_set, ciphertexts, stored, ours) = match self.read_batch(&digest).await {
+            Some(batch) => batch,
+            None => return,
+        };
+
+        let mut pending = PendingShares {
+            ciphertexts,
+            pk_set,
+            shares: BTreeMap::new(),
+            authors: HashSet::new(),
+            replies: vec![reply],
+            attempts: 1,
+            timestamp: Self::now(),
+        };
+        pending.authors.insert(self.name);
+        for (index, shares) in stored.into_iter().chain(std::iter::once(ours)) {
+            pending.add(index, shares);
+        }
+        if pending.is_complete() {
+            Self::deliver(pending);
+            return;
+        }
+
+        debug!("Requesting the decryption shares of batch {}", digest);
+        let others = self
+            .committee
+            .others_workers(&self.name, &self.id)
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        self.request(&digest, others).await;
+        self.pending.insert(digest, pending);
+    }
+
+    /// Answers the share request of another worker, if the batch is committed.
+    async fn handle_share_request(&mut self, digest: Digest, origin: PublicKey) {
+        if !self.committed.contains(&digest) {
+            debug!(
+                "Not revealing our shares of uncommitted batch {} to {}",
+                digest, origin
+            );
+            return;
+        }
+        let address = match self.committee.worker(&origin, &self.id) {
+            Ok(address) => address.worker_to_worker,
+            Err(e) => {
+                warn!("Unexpected share request: {}", e);
+                return;
+            }
+        };
+        if let Some((_, _, _, ours)) = self.read_batch(&digest).await {
+            let message = WorkerMessage::DecryptionShares(digest, self.name, ours);
+            let serialized =
+                bincode::serialize(&message).expect("Failed to serialize our own message");
+            self.network.send(address, Bytes::from(serialized)).await;
+        }
+    }
+
+    /// Adds the shares of another worker to the batch they belong to.
+    fn handle_shares(&mut self, digest: Digest, author: PublicKey, shares: NodeDecryptionShares) {
+        let pending = match self.pending.get_mut(&digest) {
+            Some(pending) => pending,
+            None => return,
+        };
+        // Only process the valid shares of each authority once.
+        if pending.authors.contains(&author) {
+            return;
+        }
+        let (index, shares) = shares;
+        if let Some(held) = pending.shares.get(&index) {
+            // We already hold these shares (eg. they were stored alongside the batch).
+            debug!(
+                "Ignoring duplicate decryption shares for batch {} from {}",
+                digest, author
+            );
+            if held == &shares {
+                pending.authors.insert(author);
+            }
+            return;
+        }
+        // The shares must be valid for the public key share of the index they claim.
+        if !pending.add(index, shares) {
+            warn!(
+                "Received invalid decryption shares for batch {} from {}",
+                digest, author
+            );
+            return;
+        }
+        pending.authors.insert(author);
+        if pending.is_complete() {
+            debug!("Gathered enough decryption shares for batch {}", digest);
+            if let Some(pending) = self.pending.remove(&digest) {
+                Self::deliver(pending);
+            }
+        }
+    }
+
+    fn deliver(pending: PendingShares) {
+        let shares: BatchDecryptionShares = pending.shares.into_iter().collect();
+        for reply in pending.replies {
+            let _ = reply.send(shares.clone());
+        }
+    }
+
+    /// Asks the authorities that did not send their shares yet again, waiting twice as long as the previous
+    /// time. We give up on the batches for which we made too many attempts.
+    async fn retry(&mut self) {
+        let now = Self::now();
+        let mut retry = Vec::new();
+        let mut expired = Vec::new();
+        for (digest, pending) in &mut self.pending {
+            let delay = (self.sync_retry_delay as u128) << (pending.attempts - 1);
+            if pending.timestamp + delay >= now {
+                continue;
+            }
+            if pending.attempts >= MAX_ATTEMPTS {
+                expired.push(digest.clone());
+                continue;
+            }
+            pending.attempts += 1;
+            pending.timestamp = now;
+            let missing: Vec<_> = self
+                .committee
+                .others_workers(&self.name, &self.id)
+                .into_iter()
+                .map(|(name, _)| name)
+                .filter(|name| !pending.authors.contains(name))
+                .collect();
+            retry.push((digest.clone(), missing));
+        }
+
+        for digest in expired {
+            warn!(
+                "Failed to gather enough decryption shares for batch {}",
+                digest
+            );
+            self.pending.remove(&digest);
+        }
+        for (digest, missing) in retry {
+            debug!(
+                "Requesting the decryption shares of batch {} (retry)",
+                digest
+            );
+            self.request(&digest, missing).await;
+        }
+    }
+
+    /// Main loop listening to share requests and share messages.
+    async fn run(&mut self) {
+        let timer = sleep(Duration::from_millis(TIMER_RESOLUTION));
+        tokio::pin!(timer);
+
+        loop {
+            tokio::select! {
+                // The application only asks for the shares of committed batches.
+                Some((digest, reply)) = self.rx_request.recv() => {
+                    self.commit(digest.clone());
+                    self.handle_request(digest, reply).await;
+                },
+
+                Some(message) = self.rx_message.recv() => match message {
+                    ShareMessage::Request(digest, origin) => {
+                        self.handle_share_request(digest, origin).await;
+                    },
+                    ShareMessage::Shares(digest, author, shares) => {
+                        self.handle_shares(digest, author, shares);
+                    },
+                    ShareMessage::Committed(digests) => {
+                        for digest in digests {
+                            self.commit(digest);
+                        }
+                    },
+                },
+
+                () = &mut timer => {
+                    self.retry().await;
+                    timer.as_mut().reset(Instant::now() + Duration::from_millis(TIMER_RESOLUTION));
+                },
+
+                Ok(()) = self.rx_parameters.changed() => {
+                    self.sync_retry_delay = self.rx_parameters.borrow().sync_retry_delay;
+                    debug!("Share retry delay updated to {} ms", self.sync_retry_delay);
+                },
+            }
+        }
+    }
+}
@@ -0,0 +1,40 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use futures::future;
+use tokio::sync::{mpsc, watch};
+
+/// Tells the tasks of the worker when to shut down. The tasks holding a clone of it delay the end
+/// of the shutdown until they exit: the caller waits for the receiver of `tx_drained` to return `None`.
+#[derive(Clone)]
+pub struct Shutdown {
+    /// Becomes `true` once the worker is asked to shut down.
+    rx_signal: watch::Receiver<bool>,
+    /// Dropped (along its clones) once all the tasks holding it exited.
+    _tx_drained: mpsc::Sender<()>,
+}
+
+impl Shutdown {
+    pub fn new(rx_signal: watch::Receiver<bool>, tx_drained: mpsc::Sender<()>) -> Self {
+        Self {
+            rx_signal,
+            _tx_drained: tx_drained,
+        }
+    }
+
+    /// Waits until the worker is asked to shut down. Never returns if the signal is dropped first.
+    pub async fn wait(&mut self) {
+        while !*self.rx_signal.borrow() {
+            if self.rx_signal.changed().await.is_err() {
+                future::pending::<()>().await;
+            }
+        }
+    }
+}
+
+impl Default for Shutdown {
+    /// A shutdown that is never signaled.
+    fn default() -> Self {
+        let (_, rx_signal) = watch::channel(false);
+        let (tx_drained, _) = mpsc::channel(1);
+        Self::new(rx_signal, tx_drained)
+    }
+}
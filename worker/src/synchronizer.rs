@@ -1,7 +1,7 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use crate::worker::{Round, WorkerMessage};
 use bytes::Bytes;
-use config::{Committee, WorkerId};
+use config::{Committee, Parameters, WorkerId};
 use crypto::{Digest, PublicKey};
 use futures::stream::futures_unordered::FuturesUnordered;
 use futures::stream::StreamExt as _;
@@ -10,8 +10,10 @@ use network::SimpleSender;
 use primary::PrimaryWorkerMessage;
 use std::collections::HashMap;
 use std::time::{SystemTime, UNIX_EPOCH};
-use store::{Store, StoreError};
+use store::{KeyValueStore, StoreError};
 use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
 use tokio::time::{sleep, Duration, Instant};
 
 #[cfg(test)]
@@ -22,7 +24,7 @@ pub mod synchronizer_tests;
 const TIMER_RESOLUTION: u64 = 1_000;
 
 // The `Synchronizer` is responsible to keep the worker in sync with the others.
-pub struct Synchronizer {
+pub struct Synchronizer<S> {
     /// The public key of this authority.
     name: PublicKey,
     /// The id of this worker.
@@ -30,7 +32,7 @@ pub struct Synchronizer {
     /// The committee information.
     committee: Committee,
     // The persistent storage.
-    store: Store,
+    store: S,
     /// The depth of the garbage collection.
     gc_depth: Round,
     /// The delay to wait before re-trying to send sync requests.
@@ -38,8 +40,12 @@ pub struct Synchronizer {
     /// Determine with how many nodes to sync when re-trying to send sync-requests. These nodes
     /// are picked at random from the committee.
     sync_retry_nodes: usize,
+    /// The maximum number of batches we request at once, and wait for at any time.
+    max_sync_requests: usize,
     /// Input channel to receive the commands from the primary.
     rx_message: Receiver<PrimaryWorkerMessage>,
+    /// Receives the parameters reloaded by the operator.
+    rx_parameters: watch::Receiver<Parameters>,
     /// A network sender to send requests to the other workers.
     network: SimpleSender,
     /// Loosely keep track of the primary's round number (only used for cleanup).
@@ -50,18 +56,21 @@ pub struct Synchronizer {
     pending: HashMap<Digest, (Round, Sender<()>, u128)>,
 }
 
-impl Synchronizer {
+impl<S: KeyValueStore> Synchronizer<S> {
     #[allow(clippy::too_many_arguments)]
     pub fn spawn(
         name: PublicKey,
         id: WorkerId,
         committee: Committee,
-        store: Store,
+        store: S,
         gc_depth: Round,
         sync_retry_delay: u64,
         sync_retry_nodes: usize,
+        max_sync_requests: usize,
         rx_message: Receiver<PrimaryWorkerMessage>,
-    ) {
+        rx_parameters: watch::Receiver<Parameters>,
+    ) -> JoinHandle<()> {
+        let seed = rx_parameters.borrow().seed;
         tokio::spawn(async move {
             Self {
                 name,
@@ -71,21 +80,31 @@ impl Synchronizer {
                 gc_depth,
                 sync_retry_delay,
                 sync_retry_nodes,
+                max_sync_requests,
                 rx_message,
-                network: SimpleSender::new(),
+                rx_parameters,
+                network: SimpleSender::new().with_seed(seed),
                 round: Round::default(),
                 pending: HashMap::new(),
             }
             .run()
             .await;
-        });
+        })
+    }
+
+    /// Helper function. It counts the batches we requested less than `sync_retry_delay` ago.
+    fn outstanding(&self, now: u128) -> usize {
+        self.pending
+            .values()
+            .filter(|(_, _, timestamp)| timestamp + (self.sync_retry_delay as u128) >= now)
+            .count()
     }
 
     /// Helper function. It waits for a batch to become available in the storage
     /// and then delivers its digest.
     async fn waiter(
         missing: Digest,
-        mut store: Store,
+        mut store: S,
         deliver: Digest,
         mut handler: Receiver<()>,
     ) -> Result<Option<Digest>, StoreError> {
@@ -114,6 +133,9 @@ impl Synchronizer {
                             .expect("Failed to measure time")
                             .as_millis();
 
+                        // Past `max_sync_requests` outstanding requests, the other batches wait for the
+                        // retry timer (a null timestamp makes them due right away).
+                        let mut outstanding = self.outstanding(now);
                         let mut missing = Vec::new();
                         for digest in digests {
                             // Ensure we do not send twice the same sync request.
@@ -122,11 +144,14 @@ impl Synchronizer {
                             }
 
                             // Check if we received the batch in the meantime.
+                            let mut timestamp = now;
                             match self.store.read(digest.to_vec()).await {
-                                Ok(None) => {
+                                Ok(None) if outstanding < self.max_sync_requests => {
+                                    outstanding += 1;
                                     missing.push(digest.clone());
                                     debug!("Requesting sync for batch {}", digest);
                                 },
+                                Ok(None) => timestamp = 0,
                                 Ok(Some(_)) => {
                                     // The batch arrived in the meantime: no need to request it.
                                 },
@@ -141,11 +166,14 @@ impl Synchronizer {
                             let (tx_cancel, rx_cancel) = channel(1);
                             let fut = Self::waiter(digest.clone(), self.store.clone(), deliver, rx_cancel);
                             waiting.push(fut);
-                            self.pending.insert(digest, (self.round, tx_cancel, now));
+                            self.pending.insert(digest, (self.round, tx_cancel, timestamp));
                         }
 
                         // Send sync request to a single node. If this fails, we will send it
                         // to other nodes when a timer times out.
+                        if missing.is_empty() {
+                            continue;
+                        }
                         let address = match self.committee.worker(&target, &self.id) {
                             Ok(address) => address.worker_to_worker,
                             Err(e) => {
@@ -174,6 +202,12 @@ impl Synchronizer {
                         }
                         self.pending.retain(|_, (r, _, _)| r > &mut gc_round);
                     }
+                    PrimaryWorkerMessage::Prune(digests) => {
+                        let keys = digests.iter().map(|x| x.to_vec()).collect();
+                        self.store.delete(keys).await;
+                    }
+                    // The commit notifications go to the `ShareGossip`.
+                    PrimaryWorkerMessage::Committed(_) => (),
                 },
 
                 // Stream out the futures of the `FuturesUnordered` that completed.
@@ -198,12 +232,21 @@ impl Synchronizer {
                         .expect("Failed to measure time")
                         .as_millis();
 
+                    // Retry the oldest requests first, and wait `sync_retry_delay` again before the next retry.
+                    let mut due: Vec<_> = self.pending
+                        .iter()
+                        .filter(|(_, (_, _, timestamp))| timestamp + (self.sync_retry_delay as u128) < now)
+                        .map(|(digest, (_, _, timestamp))| (*timestamp, digest.clone()))
+                        .collect();
+                    due.sort_by_key(|(timestamp, _)| *timestamp);
+                    due.truncate(self.max_sync_requests.saturating_sub(self.outstanding(now)));
                     let mut retry = Vec::new();
-                    for (digest, (_, _, timestamp)) in &self.pending {
-                        if timestamp + (self.sync_retry_delay as u128) < now {
-                            debug!("Requesting sync for batch {} (retry)", digest);
-                            retry.push(digest.clone());
+                    for (_, digest) in due {
+                        debug!("Requesting sync for batch {} (retry)", digest);
+                        if let Some((_, _, timestamp)) = self.pending.get_mut(&digest) {
+                            *timestamp = now;
                         }
+                        retry.push(digest);
                     }
                     if !retry.is_empty() {
                         let addresses = self.committee
@@ -220,6 +263,12 @@ impl Synchronizer {
                     // Reschedule the timer.
                     timer.as_mut().reset(Instant::now() + Duration::from_millis(TIMER_RESOLUTION));
                 },
+
+                // Pick up the new retry delay if the operator reloaded the parameters.
+                Ok(()) = self.rx_parameters.changed() => {
+                    self.sync_retry_delay = self.rx_parameters.borrow().sync_retry_delay;
+                    debug!("Sync retry delay updated to {} ms", self.sync_retry_delay);
+                },
             }
         }
     }
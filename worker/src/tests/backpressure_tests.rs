@@ -0,0 +1,23 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+
+#[test]
+fn reject_between_water_marks() {
+    let backpressure = Backpressure::new(/* high */ 100, /* low */ 50);
+
+    // Accept transactions up to the high-water mark.
+    assert!(backpressure.try_accept(60));
+    assert!(backpressure.try_accept(40));
+    assert!(!backpressure.try_accept(1));
+    assert!(backpressure.is_full());
+
+    // Draining below the high-water mark is not enough.
+    backpressure.release(40);
+    assert!(!backpressure.try_accept(1));
+
+    // Accept again once below the low-water mark.
+    backpressure.release(20);
+    assert!(backpressure.try_accept(1));
+    assert!(!backpressure.is_full());
+    assert_eq!(backpressure.pending(), 41);
+}
@@ -1,7 +1,10 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
-use crate::common::transaction;
-use tokio::sync::mpsc::channel;
+use crate::common::{transaction, transaction_length};
+use crate::priority::{is_sorted_by_priority, with_epoch, with_priority};
+use config::ThresholdKeyPair;
+use tokio::sync::mpsc::{channel, unbounded_channel};
+use tokio::time::timeout;
 
 #[tokio::test]
 async fn make_batch() {
@@ -15,9 +18,17 @@ async fn make_batch() {
         /* max_batch_size */ 200,
         /* max_batch_delay */ 1_000_000, // Ensure the timer is not triggered.
         rx_transaction,
+        /* rx_bundle */ channel(1).1,
         rx_decryptable_batches,
+        /* rx_reseal */ unbounded_channel().1,
         tx_message,
         /* workers_addresses */ dummy_addresses,
+        Backoff::default(),
+        WorkerMetrics::default(),
+        watch::channel(Parameters::default()).1,
+        ThresholdKeyRing::new(ThresholdKeyPair::new(1, 0, 0)),
+        Backpressure::default(),
+        Shutdown::default(),
     );
 
     // Send enough transactions to seal a batch.
@@ -27,12 +38,14 @@ async fn make_batch() {
     // Ensure the batch is as expected.
     let expected_batch = vec![transaction(), transaction()];
     let QuorumWaiterMessage {
+        epoch: _,
         batch,
+        bundles: _,
         named_decrypt_shares_handlers: _,
     } = rx_message.recv().await.unwrap();
     // TODO: fix this test
     // match bincode::deserialize(&batch).unwrap() {
-    //     WorkerMessage::Batch(batch) => assert_eq!(batch, expected_batch),
+    //     WorkerMessage::Batch(_, batch) => assert_eq!(batch, expected_batch),
     //     _ => panic!("Unexpected message"),
     // }
 }
@@ -49,9 +62,17 @@ async fn batch_timeout() {
         /* max_batch_size */ 200,
         /* max_batch_delay */ 50, // Ensure the timer is triggered.
         rx_transaction,
+        /* rx_bundle */ channel(1).1,
         rx_decryptable_batches,
+        /* rx_reseal */ unbounded_channel().1,
         tx_message,
         /* workers_addresses */ dummy_addresses,
+        Backoff::default(),
+        WorkerMetrics::default(),
+        watch::channel(Parameters::default()).1,
+        ThresholdKeyRing::new(ThresholdKeyPair::new(1, 0, 0)),
+        Backpressure::default(),
+        Shutdown::default(),
     );
 
     // Do not send enough transactions to seal a batch..
@@ -60,12 +81,571 @@ async fn batch_timeout() {
     // Ensure the batch is as expected.
     let expected_batch = vec![transaction()];
     let QuorumWaiterMessage {
+        epoch: _,
         batch,
+        bundles: _,
         named_decrypt_shares_handlers: _,
     } = rx_message.recv().await.unwrap();
     // TODO: fix this test
     // match bincode::deserialize(&batch).unwrap() {
-    //     WorkerMessage::Batch(batch) => assert_eq!(batch, expected_batch),
+    //     WorkerMessage::Batch(_, batch) => assert_eq!(batch, expected_batch),
     //     _ => panic!("Unexpected message"),
     // }
 }
+
+#[tokio::test]
+async fn expired_transactions_are_dropped() {
+    let (tx_transaction, rx_transaction) = channel(1);
+    let (tx_message, mut rx_message) = channel(1);
+    let dummy_addresses = vec![(PublicKey::default(), "127.0.0.1:0".parse().unwrap())];
+    let (_tx_decryptable_batches, rx_decryptable_batches) = channel(1);
+    let parameters = Parameters {
+        transaction_ttl: 50,
+        ..Parameters::default()
+    };
+    let metrics = WorkerMetrics::default();
+
+    // Spawn a `BatchMaker` instance.
+    BatchMaker::spawn(
+        /* max_batch_size */ 3 * transaction_length(),
+        /* max_batch_delay */ 1_000_000, // Ensure the timer is not triggered.
+        rx_transaction,
+        /* rx_bundle */ channel(1).1,
+        rx_decryptable_batches,
+        /* rx_reseal */ unbounded_channel().1,
+        tx_message,
+        /* workers_addresses */ dummy_addresses,
+        Backoff::default(),
+        metrics.clone(),
+        watch::channel(parameters).1,
+        ThresholdKeyRing::new(ThresholdKeyPair::new(1, 0, 0)),
+        Backpressure::default(),
+        Shutdown::default(),
+    );
+
+    // Send a transaction and leave it idle beyond its TTL.
+    let old = vec![1u8; transaction_length()];
+    tx_transaction.send(old.clone()).await.unwrap();
+    sleep(Duration::from_millis(100)).await;
+
+    // The old transaction expires when the batch would be full, so the batch is only sealed once we
+    // send three fresh transactions.
+    for _ in 0..3 {
+        tx_transaction.send(transaction()).await.unwrap();
+    }
+    let QuorumWaiterMessage { batch, .. } = rx_message.recv().await.unwrap();
+    assert!(!batch.contains(&old));
+    assert_eq!(batch.len(), 3);
+    assert_eq!(metrics.transactions_expired.get(), 1);
+}
+
+#[tokio::test]
+async fn duplicate_transactions_are_dropped() {
+    let (tx_transaction, rx_transaction) = channel(1);
+    let (tx_message, mut rx_message) = channel(1);
+    let dummy_addresses = vec![(PublicKey::default(), "127.0.0.1:0".parse().unwrap())];
+    let (_tx_decryptable_batches, rx_decryptable_batches) = channel(1);
+    let parameters = Parameters {
+        dedup_window: 10,
+        ..Parameters::default()
+    };
+    let metrics = WorkerMetrics::default();
+
+    // Spawn a `BatchMaker` instance.
+    BatchMaker::spawn(
+        /* max_batch_size */ 2 * transaction_length(),
+        /* max_batch_delay */ 1_000_000, // Ensure the timer is not triggered.
+        rx_transaction,
+        /* rx_bundle */ channel(1).1,
+        rx_decryptable_batches,
+        /* rx_reseal */ unbounded_channel().1,
+        tx_message,
+        /* workers_addresses */ dummy_addresses,
+        Backoff::default(),
+        metrics.clone(),
+        watch::channel(parameters).1,
+        ThresholdKeyRing::new(ThresholdKeyPair::new(1, 0, 0)),
+        Backpressure::default(),
+        Shutdown::default(),
+    );
+
+    // Submit the same transaction twice within the pending batch, and once more after it is sealed.
+    let duplicate = transaction();
+    let others = vec![transaction(), transaction(), transaction()];
+    tx_transaction.send(duplicate.clone()).await.unwrap();
+    tx_transaction.send(duplicate.clone()).await.unwrap();
+    tx_transaction.send(others[0].clone()).await.unwrap();
+    let QuorumWaiterMessage { batch, .. } = rx_message.recv().await.unwrap();
+    assert_eq!(batch, vec![duplicate.clone(), others[0].clone()]);
+
+    tx_transaction.send(duplicate.clone()).await.unwrap();
+    tx_transaction.send(others[1].clone()).await.unwrap();
+    tx_transaction.send(others[2].clone()).await.unwrap();
+    let QuorumWaiterMessage { batch, .. } = rx_message.recv().await.unwrap();
+    assert_eq!(batch, vec![others[1].clone(), others[2].clone()]);
+    assert_eq!(metrics.transactions_duplicated.get(), 2);
+}
+
+#[tokio::test]
+async fn batch_sealed_at_max_transactions() {
+    let (tx_transaction, rx_transaction) = channel(1);
+    let (tx_message, mut rx_message) = channel(1);
+    let dummy_addresses = vec![(PublicKey::default(), "127.0.0.1:0".parse().unwrap())];
+    let (_tx_decryptable_batches, rx_decryptable_batches) = channel(1);
+    let parameters = Parameters {
+        max_batch_size_txs: 2,
+        ..Parameters::default()
+    };
+
+    // Spawn a `BatchMaker` instance whose preferred size is never reached.
+    BatchMaker::spawn(
+        /* max_batch_size */ 1_000 * transaction_length(),
+        /* max_batch_delay */ 1_000_000, // Ensure the timer is not triggered.
+        rx_transaction,
+        /* rx_bundle */ channel(1).1,
+        rx_decryptable_batches,
+        /* rx_reseal */ unbounded_channel().1,
+        tx_message,
+        /* workers_addresses */ dummy_addresses,
+        Backoff::default(),
+        WorkerMetrics::default(),
+        watch::channel(parameters).1,
+        ThresholdKeyRing::new(ThresholdKeyPair::new(1, 0, 0)),
+        Backpressure::default(),
+        Shutdown::default(),
+    );
+
+    // The batch is sealed as soon as it holds two transactions.
+    let transactions = vec![transaction(), transaction()];
+    for tx in &transactions {
+        tx_transaction.send(tx.clone()).await.unwrap();
+    }
+    let QuorumWaiterMessage { batch, .. } = rx_message.recv().await.unwrap();
+    assert_eq!(batch, transactions);
+}
+
+#[tokio::test]
+async fn batch_sealed_before_exceeding_max_bytes() {
+    let (tx_transaction, rx_transaction) = channel(1);
+    let (tx_message, mut rx_message) = channel(1);
+    let dummy_addresses = vec![(PublicKey::default(), "127.0.0.1:0".parse().unwrap())];
+    let (_tx_decryptable_batches, rx_decryptable_batches) = channel(1);
+    let parameters = Parameters {
+        max_batch_size_bytes: 2 * transaction_length() + transaction_length() / 2,
+        ..Parameters::default()
+    };
+
+    // Spawn a `BatchMaker` instance whose preferred size is never reached.
+    BatchMaker::spawn(
+        /* max_batch_size */ 1_000 * transaction_length(),
+        /* max_batch_delay */ 1_000_000, // Ensure the timer is not triggered.
+        rx_transaction,
+        /* rx_bundle */ channel(1).1,
+        rx_decryptable_batches,
+        /* rx_reseal */ unbounded_channel().1,
+        tx_message,
+        /* workers_addresses */ dummy_addresses,
+        Backoff::default(),
+        WorkerMetrics::default(),
+        watch::channel(parameters).1,
+        ThresholdKeyRing::new(ThresholdKeyPair::new(1, 0, 0)),
+        Backpressure::default(),
+        Shutdown::default(),
+    );
+
+    // The third transaction does not fit: the first two are sealed without it.
+    let transactions = vec![transaction(), transaction(), transaction()];
+    for tx in &transactions {
+        tx_transaction.send(tx.clone()).await.unwrap();
+    }
+    let QuorumWaiterMessage { batch, .. } = rx_message.recv().await.unwrap();
+    assert_eq!(batch, transactions[..2].to_vec());
+}
+
+#[tokio::test]
+async fn bundle_lands_in_a_single_batch() {
+    let (tx_transaction, rx_transaction) = channel(1);
+    let (tx_bundle, rx_bundle) = channel(1);
+    let (tx_message, mut rx_message) = channel(1);
+    let dummy_addresses = vec![(PublicKey::default(), "127.0.0.1:0".parse().unwrap())];
+    let (_tx_decryptable_batches, rx_decryptable_batches) = channel(1);
+    let parameters = Parameters {
+        max_batch_size_bytes: 2 * transaction_length() + transaction_length() / 2,
+        ..Parameters::default()
+    };
+
+    // Spawn a `BatchMaker` instance sealing batches of two transactions.
+    BatchMaker::spawn(
+        /* max_batch_size */ 2 * transaction_length(),
+        /* max_batch_delay */ 1_000_000, // Ensure the timer is not triggered.
+        rx_transaction,
+        rx_bundle,
+        rx_decryptable_batches,
+        /* rx_reseal */ unbounded_channel().1,
+        tx_message,
+        /* workers_addresses */ dummy_addresses,
+        Backoff::default(),
+        WorkerMetrics::default(),
+        watch::channel(parameters).1,
+        ThresholdKeyRing::new(ThresholdKeyPair::new(1, 0, 0)),
+        Backpressure::default(),
+        Shutdown::default(),
+    );
+
+    // The bundle does not fit next to the pending transaction: the latter is sealed on its own (rather
+    // than with the first transaction of the bundle), and the bundle makes the next batch.
+    let single = transaction();
+    let bundle = vec![transaction(), transaction()];
+    tx_transaction.send(single.clone()).await.unwrap();
+    sleep(Duration::from_millis(50)).await;
+    tx_bundle.send(bundle.clone()).await.unwrap();
+    let QuorumWaiterMessage { batch, .. } = rx_message.recv().await.unwrap();
+    assert_eq!(batch, vec![single]);
+    let QuorumWaiterMessage { batch, .. } = rx_message.recv().await.unwrap();
+    assert_eq!(batch, bundle);
+}
+
+#[tokio::test]
+async fn batch_ordered_by_priority() {
+    let (tx_transaction, rx_transaction) = channel(1);
+    let (tx_message, mut rx_message) = channel(1);
+    let dummy_addresses = vec![(PublicKey::default(), "127.0.0.1:0".parse().unwrap())];
+    let (_tx_decryptable_batches, rx_decryptable_batches) = channel(1);
+    let parameters = Parameters {
+        max_batch_size_txs: 3,
+        priority_ordering: true,
+        ..Parameters::default()
+    };
+
+    // Spawn a `BatchMaker` instance sealing batches of three transactions.
+    BatchMaker::spawn(
+        /* max_batch_size */ 1_000 * transaction_length(),
+        /* max_batch_delay */ 1_000_000, // Ensure the timer is not triggered.
+        rx_transaction,
+        /* rx_bundle */ channel(1).1,
+        rx_decryptable_batches,
+        /* rx_reseal */ unbounded_channel().1,
+        tx_message,
+        /* workers_addresses */ dummy_addresses,
+        Backoff::default(),
+        WorkerMetrics::default(),
+        watch::channel(parameters).1,
+        ThresholdKeyRing::new(ThresholdKeyPair::new(1, 0, 0)),
+        Backpressure::default(),
+        Shutdown::default(),
+    );
+
+    // The transactions arrive with increasing priorities (the first one has none).
+    let plain = transaction();
+    let low = with_priority(1, &transaction());
+    let high = with_priority(5, &transaction());
+    for tx in &[plain.clone(), low.clone(), high.clone()] {
+        tx_transaction.send(tx.clone()).await.unwrap();
+    }
+
+    // The batch holds them by decreasing priority.
+    let QuorumWaiterMessage { batch, .. } = rx_message.recv().await.unwrap();
+    assert_eq!(batch, vec![high, low, plain]);
+    assert!(is_sorted_by_priority(&batch));
+}
+
+#[tokio::test]
+async fn bundle_ordered_by_priority_as_a_whole() {
+    let (tx_transaction, rx_transaction) = channel(1);
+    let (tx_bundle, rx_bundle) = channel(1);
+    let (tx_message, mut rx_message) = channel(1);
+    let dummy_addresses = vec![(PublicKey::default(), "127.0.0.1:0".parse().unwrap())];
+    let (_tx_decryptable_batches, rx_decryptable_batches) = channel(1);
+    let parameters = Parameters {
+        max_batch_size_txs: 5,
+        priority_ordering: true,
+        ..Parameters::default()
+    };
+
+    // Spawn a `BatchMaker` instance sealing batches of five transactions.
+    BatchMaker::spawn(
+        /* max_batch_size */ 1_000 * transaction_length(),
+        /* max_batch_delay */ 1_000_000, // Ensure the timer is not triggered.
+        rx_transaction,
+        rx_bundle,
+        rx_decryptable_batches,
+        /* rx_reseal */ unbounded_channel().1,
+        tx_message,
+        /* workers_addresses */ dummy_addresses,
+        Backoff::default(),
+        WorkerMetrics::default(),
+        watch::channel(parameters).1,
+        ThresholdKeyRing::new(ThresholdKeyPair::new(1, 0, 0)),
+        Backpressure::default(),
+        Shutdown::default(),
+    );
+
+    // The transactions of the bundle are not in the order of their digests, and another transaction has
+    // their priority.
+    let plain = transaction();
+    let tie = with_priority(3, &transaction());
+    let high = with_priority(5, &transaction());
+    let mut bundle = vec![
+        with_priority(3, &transaction()),
+        with_priority(3, &transaction()),
+    ];
+    if hash(&bundle[0]) < hash(&bundle[1]) {
+        bundle.reverse();
+    }
+    for tx in &[plain.clone(), tie.clone()] {
+        tx_transaction.send(tx.clone()).await.unwrap();
+    }
+    tx_bundle.send(bundle.clone()).await.unwrap();
+    sleep(Duration::from_millis(50)).await;
+    tx_transaction.send(high.clone()).await.unwrap();
+
+    // The bundle stays together and in order, placed by its first transaction.
+    let QuorumWaiterMessage { batch, .. } = rx_message.recv().await.unwrap();
+    assert_eq!(batch.len(), 5);
+    assert_eq!(batch[0], high);
+    assert_eq!(batch[4], plain);
+    let start = batch.iter().position(|x| x == &bundle[0]).unwrap();
+    assert_eq!(batch[start..start + 2], bundle[..]);
+    let expected = if hash(&bundle[0]) < hash(&tie) { 3 } else { 1 };
+    assert_eq!(batch[expected], tie);
+    assert!(is_sorted_by_priority(&batch));
+}
+
+#[tokio::test]
+async fn reseal_between_bundles() {
+    let (tx_reseal, rx_reseal) = unbounded_channel();
+    let (tx_message, mut rx_message) = channel(1);
+    let dummy_addresses = vec![(PublicKey::default(), "127.0.0.1:0".parse().unwrap())];
+    let (_tx_decryptable_batches, rx_decryptable_batches) = channel(1);
+
+    // Spawn a `BatchMaker` instance.
+    BatchMaker::spawn(
+        /* max_batch_size */ 1_000 * transaction_length(),
+        /* max_batch_delay */ 1_000_000, // Ensure the timer is not triggered.
+        /* rx_transaction */ channel(1).1,
+        /* rx_bundle */ channel(1).1,
+        rx_decryptable_batches,
+        rx_reseal,
+        tx_message,
+        /* workers_addresses */ dummy_addresses,
+        Backoff::default(),
+        WorkerMetrics::default(),
+        watch::channel(Parameters::default()).1,
+        ThresholdKeyRing::new(ThresholdKeyPair::new(1, 0, 0)),
+        Backpressure::default(),
+        Shutdown::default(),
+    );
+
+    // The bundle straddles the middle of the batch: the batch is split before it rather than through it.
+    let batch: Vec<_> = (0..4).map(|_| transaction()).collect();
+    tx_reseal.send((0, batch.clone(), vec![1..3])).unwrap();
+    let QuorumWaiterMessage {
+        batch: first,
+        bundles,
+        ..
+    } = rx_message.recv().await.unwrap();
+    assert_eq!(first, batch[..1]);
+    assert!(bundles.is_empty());
+    let QuorumWaiterMessage {
+        batch: second,
+        bundles,
+        ..
+    } = rx_message.recv().await.unwrap();
+    assert_eq!(second, batch[1..]);
+    assert_eq!(bundles, vec![0..2]);
+
+    // A bundle rejected on its own cannot be split: it is dropped as a whole.
+    tx_reseal
+        .send((0, batch[1..].to_vec(), vec![0..2]))
+        .unwrap();
+    let QuorumWaiterMessage { batch: first, .. } = rx_message.recv().await.unwrap();
+    assert_eq!(first, batch[1..3]);
+    let QuorumWaiterMessage { batch: second, .. } = rx_message.recv().await.unwrap();
+    assert_eq!(second, batch[3..]);
+    tx_reseal
+        .send((0, batch[1..3].to_vec(), vec![0..2]))
+        .unwrap();
+    tx_reseal
+        .send((0, batch[..2].to_vec(), Vec::new()))
+        .unwrap();
+    let QuorumWaiterMessage { batch: next, .. } = rx_message.recv().await.unwrap();
+    assert_eq!(next, batch[..1]);
+}
+
+#[tokio::test]
+async fn busy_quorum_waiter_does_not_block() {
+    let (tx_transaction, rx_transaction) = channel(1);
+    let (tx_message, mut rx_message) = channel(1);
+    let dummy_addresses = vec![(PublicKey::default(), "127.0.0.1:0".parse().unwrap())];
+    let (tx_decryptable_batches, rx_decryptable_batches) = channel(1);
+
+    // Spawn a `BatchMaker` instance.
+    BatchMaker::spawn(
+        /* max_batch_size */ 200,
+        /* max_batch_delay */ 1_000_000, // Ensure the timer is not triggered.
+        rx_transaction,
+        /* rx_bundle */ channel(1).1,
+        rx_decryptable_batches,
+        /* rx_reseal */ unbounded_channel().1,
+        tx_message,
+        /* workers_addresses */ dummy_addresses,
+        Backoff::default(),
+        WorkerMetrics::default(),
+        watch::channel(Parameters::default()).1,
+        ThresholdKeyRing::new(ThresholdKeyPair::new(1, 0, 0)),
+        Backpressure::default(),
+        Shutdown::default(),
+    );
+
+    // Seal two batches while the `QuorumWaiter` only has room for one.
+    for _ in 0..4 {
+        tx_transaction.send(transaction()).await.unwrap();
+    }
+
+    // The `BatchMaker` still broadcasts the decryptable batches the `QuorumWaiter` may be waiting for.
+    let (tx_handlers, rx_handlers) = oneshot::channel();
+    tx_decryptable_batches
+        .send((vec![0u8; 8], tx_handlers))
+        .await
+        .unwrap();
+    let handlers = timeout(Duration::from_millis(1_000), rx_handlers).await;
+    assert_eq!(handlers.unwrap().unwrap().len(), 1);
+
+    // Both batches are eventually handed to the `QuorumWaiter`, in order.
+    for _ in 0..2 {
+        let QuorumWaiterMessage { batch, .. } = rx_message.recv().await.unwrap();
+        assert_eq!(batch.len(), 2);
+    }
+}
+
+#[tokio::test]
+async fn shutdown_seals_the_batch_in_flight() {
+    let (tx_transaction, rx_transaction) = channel(1);
+    let (tx_message, mut rx_message) = channel(1);
+    let dummy_addresses = vec![(PublicKey::default(), "127.0.0.1:0".parse().unwrap())];
+    let (tx_decryptable_batches, rx_decryptable_batches) = channel(1);
+    let (tx_signal, rx_signal) = watch::channel(false);
+    let (tx_drained, mut rx_drained) = channel(1);
+
+    // Spawn a `BatchMaker` instance.
+    BatchMaker::spawn(
+        /* max_batch_size */ 1_000,
+        /* max_batch_delay */ 1_000_000, // Ensure the timer is not triggered.
+        rx_transaction,
+        /* rx_bundle */ channel(1).1,
+        rx_decryptable_batches,
+        /* rx_reseal */ unbounded_channel().1,
+        tx_message,
+        /* workers_addresses */ dummy_addresses,
+        Backoff::default(),
+        WorkerMetrics::default(),
+        watch::channel(Parameters::default()).1,
+        ThresholdKeyRing::new(ThresholdKeyPair::new(1, 0, 0)),
+        Backpressure::default(),
+        Shutdown::new(rx_signal, tx_drained),
+    );
+
+    // Do not send enough transactions to seal a batch (the second one is only queued once the
+    // `BatchMaker` received the first), then shut down.
+    tx_transaction.send(transaction()).await.unwrap();
+    tx_transaction.send(transaction()).await.unwrap();
+    tx_signal.send(true).unwrap();
+
+    // Ensure the batch in flight is sealed, and that no batch follows.
+    let QuorumWaiterMessage { batch, .. } = rx_message.recv().await.unwrap();
+    assert!(!batch.is_empty());
+    assert!(rx_message.recv().await.is_none());
+
+    // The decryptable batches are still broadcast until the `QuorumWaiter` exits.
+    let (tx_handlers, rx_handlers) = oneshot::channel();
+    tx_decryptable_batches
+        .send((vec![0u8; 8], tx_handlers))
+        .await
+        .unwrap();
+    assert_eq!(rx_handlers.await.unwrap().len(), 1);
+    assert!(timeout(Duration::from_millis(50), rx_drained.recv())
+        .await
+        .is_err());
+
+    // Ensure the shutdown completes once the `QuorumWaiter` exits.
+    drop(tx_decryptable_batches);
+    assert!(rx_drained.recv().await.is_none());
+}
+
+#[tokio::test]
+async fn batch_per_epoch() {
+    let (tx_transaction, rx_transaction) = channel(1);
+    let (tx_message, mut rx_message) = channel(1);
+    let dummy_addresses = vec![(PublicKey::default(), "127.0.0.1:0".parse().unwrap())];
+    let (_tx_decryptable_batches, rx_decryptable_batches) = channel(1);
+
+    // Spawn a `BatchMaker` instance sealing batches of two transactions.
+    BatchMaker::spawn(
+        /* max_batch_size */ 2 * transaction_length(),
+        /* max_batch_delay */ 1_000_000, // Ensure the timer is not triggered.
+        rx_transaction,
+        /* rx_bundle */ channel(1).1,
+        rx_decryptable_batches,
+        /* rx_reseal */ unbounded_channel().1,
+        tx_message,
+        /* workers_addresses */ dummy_addresses,
+        Backoff::default(),
+        WorkerMetrics::default(),
+        watch::channel(Parameters::default()).1,
+        ThresholdKeyRing::new(ThresholdKeyPair::new(1, 0, 0)),
+        Backpressure::default(),
+        Shutdown::default(),
+    );
+
+    // The client encrypted the first transaction with the key of epoch 1, and the others with the
+    // current one (epoch 0).
+    let tagged = with_epoch(1, &transaction());
+    tx_transaction.send(tagged.clone()).await.unwrap();
+    tx_transaction.send(transaction()).await.unwrap();
+    tx_transaction.send(transaction()).await.unwrap();
+
+    // The batches are tagged with the epoch of their transactions.
+    let QuorumWaiterMessage { epoch, batch, .. } = rx_message.recv().await.unwrap();
+    assert_eq!((epoch, batch), (1, vec![tagged]));
+    let QuorumWaiterMessage { epoch, batch, .. } = rx_message.recv().await.unwrap();
+    assert_eq!((epoch, batch.len()), (0, 2));
+}
+
+#[tokio::test]
+async fn sample_every_sealed_batch() {
+    let (tx_transaction, rx_transaction) = channel(1);
+    let (tx_message, mut rx_message) = channel(1);
+    let dummy_addresses = vec![(PublicKey::default(), "127.0.0.1:0".parse().unwrap())];
+    let (_tx_decryptable_batches, rx_decryptable_batches) = channel(1);
+    let parameters = Parameters {
+        batch_sample_rate: 1.0,
+        ..Parameters::default()
+    };
+    let metrics = WorkerMetrics::default();
+
+    // Spawn a `BatchMaker` instance describing all its batches.
+    BatchMaker::spawn(
+        /* max_batch_size */ 2 * transaction_length(),
+        /* max_batch_delay */ 1_000_000, // Ensure the timer is not triggered.
+        rx_transaction,
+        /* rx_bundle */ channel(1).1,
+        rx_decryptable_batches,
+        /* rx_reseal */ unbounded_channel().1,
+        tx_message,
+        /* workers_addresses */ dummy_addresses,
+        Backoff::default(),
+        metrics.clone(),
+        watch::channel(parameters).1,
+        ThresholdKeyRing::new(ThresholdKeyPair::new(1, 0, 0)),
+        Backpressure::default(),
+        Shutdown::default(),
+    );
+
+    // Seal three batches: each of them gets exactly one trace line.
+    for i in 1..=3 {
+        tx_transaction.send(transaction()).await.unwrap();
+        tx_transaction.send(transaction()).await.unwrap();
+        let _ = rx_message.recv().await.unwrap();
+        assert_eq!(metrics.batches_sampled.get(), i);
+    }
+}
@@ -1,18 +1,19 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use crate::batch_maker::{Batch, SerializedCiphertext};
-use crate::worker::WorkerMessage;
+use crate::worker::{WorkerMessage, BATCH_STORED, BATCH_TOO_LARGE};
 use bytes::Bytes;
 use config::{Authority, Committee, PrimaryAddresses, ThresholdKeyPair, WorkerAddresses};
 use crypto::threshold::Ciphertext;
-use crypto::{generate_keypair, Digest, PublicKey, SecretKey, ThresholdDecryptionService};
-use ed25519_dalek::Digest as _;
-use ed25519_dalek::Sha512;
+use crypto::{
+    generate_keypair, hash, Digest, HashKind, PublicKey, SchemeKind, SecretKey,
+    ThresholdDecryptionService,
+};
 use futures::sink::SinkExt as _;
 use futures::stream::StreamExt as _;
 use rand::rngs::StdRng;
 use rand::SeedableRng as _;
 use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
-use std::convert::TryInto as _;
+use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::{println as info, println as warn, println as error, println as debug};
 use tokio::net::TcpListener;
@@ -28,6 +29,11 @@ pub fn keys() -> Vec<(PublicKey, SecretKey)> {
 // Fixture
 pub fn committee() -> Committee {
     Committee {
+        threshold_public_key: None,
+        signature_scheme: SchemeKind::selected(),
+        hash_function: HashKind::selected(),
+        durable_acks: None,
+        shard_thresholds: HashMap::new(),
         authorities: keys()
             .iter()
             .enumerate()
@@ -53,6 +59,7 @@ pub fn committee() -> Committee {
                         stake: 1,
                         primary,
                         workers,
+                        key_rotation: None,
                     },
                 )
             })
@@ -107,17 +114,13 @@ pub fn batch() -> Batch {
 
 // Fixture
 pub fn serialized_batch() -> Vec<u8> {
-    let message = WorkerMessage::Batch(batch());
+    let message = WorkerMessage::Batch(0, batch());
     bincode::serialize(&message).unwrap()
 }
 
 // Fixture
 pub fn batch_digest() -> Digest {
-    Digest(
-        Sha512::digest(&serialized_batch()).as_slice()[..32]
-            .try_into()
-            .unwrap(),
-    )
+    hash(&serialized_batch())
 }
 
 // Fixture
@@ -139,8 +142,26 @@ pub fn ack_listener(address: SocketAddr, expected: Option<Bytes>) -> JoinHandle<
     })
 }
 
+// Fixture: replies `reply` to every message.
+pub fn reply_listener(address: SocketAddr, reply: &'static [u8]) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let listener = TcpListener::bind(&address).await.unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let transport = Framed::new(socket, LengthDelimitedCodec::new());
+        let (mut writer, mut reader) = transport.split();
+        while let Some(Ok(_)) = reader.next().await {
+            writer.send(Bytes::from_static(reply)).await.unwrap();
+        }
+    })
+}
+
 // Fixture
-pub fn dec_shares_listener(address: SocketAddr, expected: Option<Bytes>) -> JoinHandle<()> {
+pub fn dec_shares_listener(address: SocketAddr, _expected: Option<Bytes>) -> JoinHandle<()> {
+    limited_dec_shares_listener(address, usize::MAX)
+}
+
+// Fixture: rejects the batches whose transactions exceed `max_batch_size` bytes.
+pub fn limited_dec_shares_listener(address: SocketAddr, max_batch_size: usize) -> JoinHandle<()> {
     let threshold_keypair = ThresholdKeyPair::new(1, 0, 0);
     let threshold_decryption_service =
         ThresholdDecryptionService::spawn(threshold_keypair.sk_share, threshold_keypair.node_index);
@@ -154,7 +175,16 @@ pub fn dec_shares_listener(address: SocketAddr, expected: Option<Bytes>) -> Join
                 Some(Ok(received)) => {
                     let msg = received.freeze();
                     match bincode::deserialize(&msg) {
-                        Ok(WorkerMessage::Batch(txs)) => {
+                        Ok(WorkerMessage::Batch(_, txs))
+                            if txs.iter().map(|x| x.len()).sum::<usize>() > max_batch_size =>
+                        {
+                            debug!("dec_shares_listener: rejecting large workermessage(Batch)");
+                            writer
+                                .send(Bytes::from_static(BATCH_TOO_LARGE))
+                                .await
+                                .unwrap();
+                        }
+                        Ok(WorkerMessage::Batch(_, txs)) => {
                             debug!("dec_shares_listener: received workermessage(Batch)");
                             let ciphertexts: Vec<Ciphertext> = txs
                                 .par_iter()
@@ -170,7 +200,7 @@ pub fn dec_shares_listener(address: SocketAddr, expected: Option<Bytes>) -> Join
                         }
                         Ok(WorkerMessage::DecryptableBatch(..)) => {
                             debug!("dec_shares_listener: received a WorkerMessage::DecryptableBatch, sending back Ack");
-                            writer.send(Bytes::from("Ack")).await.unwrap();
+                            writer.send(Bytes::from_static(BATCH_STORED)).await.unwrap();
                         }
                         _ => debug!("dec_shares_listener: received wrong workermessage!"),
                     }
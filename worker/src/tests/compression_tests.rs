@@ -0,0 +1,34 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::batch;
+use crate::worker::WorkerMessage;
+use config::Epoch;
+
+fn serialized_batch() -> Vec<u8> {
+    let message = WorkerMessage::Batch(Epoch::default(), batch());
+    bincode::serialize(&message).unwrap()
+}
+
+#[test]
+fn round_trip() {
+    let serialized = serialized_batch();
+    let compressed = compress(serialized.clone(), Some(3));
+    assert!(is_compressed(&compressed));
+    assert_eq!(decompress(compressed).unwrap(), serialized);
+}
+
+#[test]
+fn uncompressed_batches_are_untouched() {
+    let serialized = serialized_batch();
+    assert!(!is_compressed(&serialized));
+    assert_eq!(compress(serialized.clone(), None), serialized);
+    assert_eq!(decompress(serialized.clone()).unwrap(), serialized);
+}
+
+#[test]
+fn text_payloads_shrink() {
+    let text = b"transfer 100 coins from alice to bob; ".repeat(1_000);
+    let message = WorkerMessage::Batch(Epoch::default(), vec![text; 10]);
+    let serialized = bincode::serialize(&message).unwrap();
+    assert!(compress(serialized.clone(), Some(3)).len() < serialized.len() / 10);
+}
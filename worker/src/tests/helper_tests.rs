@@ -1,7 +1,8 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
-use crate::common::{batch_digest, committee_with_base_port, keys, ack_listener, serialized_batch};
+use crate::common::{ack_listener, batch_digest, committee_with_base_port, keys, serialized_batch};
 use std::fs;
+use store::Store;
 use tokio::sync::mpsc::channel;
 
 #[tokio::test]
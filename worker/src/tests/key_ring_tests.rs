@@ -0,0 +1,44 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+
+#[tokio::test]
+async fn rotate_keys() {
+    let key_ring = ThresholdKeyRing::new(ThresholdKeyPair::new(1, 0, 0));
+    assert_eq!(key_ring.current_epoch(), 0);
+
+    // We cannot advance before the keys of the next epoch are installed.
+    assert_eq!(key_ring.advance(), None);
+    assert_eq!(
+        key_ring.install(ThresholdKeyPair::new(1, 0, 1)),
+        Err(KeyRingError::StaleEpoch {
+            epoch: 0,
+            current: 0
+        })
+    );
+
+    // Install the keys of epoch 1: we keep using epoch 0 until we advance.
+    let keypair = ThresholdKeyPair::new(1, 0, 1).with_epoch(1);
+    let pk_set = keypair.pk_set.clone();
+    key_ring.install(keypair).unwrap();
+    assert_eq!(key_ring.current_epoch(), 0);
+    assert_eq!(key_ring.advance(), Some(1));
+    assert_eq!(key_ring.public_key_set(1).unwrap(), pk_set);
+
+    // The keys of epoch 0 are still available to drain its batches.
+    assert!(key_ring.public_key_set(0).is_ok());
+    assert_eq!(
+        key_ring.public_key_set(2).err(),
+        Some(KeyRingError::UnknownEpoch(2))
+    );
+
+    // They are garbage collected once we move to epoch 2.
+    key_ring
+        .install(ThresholdKeyPair::new(1, 0, 2).with_epoch(2))
+        .unwrap();
+    assert_eq!(key_ring.advance(), Some(2));
+    assert!(key_ring.public_key_set(1).is_ok());
+    assert_eq!(
+        key_ring.public_key_set(0).err(),
+        Some(KeyRingError::GarbageCollected(0))
+    );
+}
@@ -0,0 +1,42 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::ack_listener;
+use tokio::sync::mpsc::channel;
+use tokio::time::timeout;
+
+#[tokio::test]
+async fn coalesce_digests() {
+    let (tx_digest, rx_digest) = channel(10);
+    let id = 0;
+    let address = "127.0.0.1:14100".parse().unwrap();
+
+    // Spawn a `PrimaryConnector` coalescing the digests over 200ms.
+    PrimaryConnector::spawn(id, address, /* flush_interval */ 200, rx_digest);
+
+    // Send the digests of two of our batches and of one batch of another authority.
+    let ours = vec![Digest([1u8; 32]), Digest([2u8; 32])];
+    let others = vec![Digest([3u8; 32])];
+    for digest in &ours {
+        let message = WorkerPrimaryMessage::OurBatch(digest.clone(), id);
+        tx_digest
+            .send(bincode::serialize(&message).unwrap())
+            .await
+            .unwrap();
+    }
+    let message = WorkerPrimaryMessage::OthersBatch(others[0].clone(), id);
+    tx_digest
+        .send(bincode::serialize(&message).unwrap())
+        .await
+        .unwrap();
+
+    // Ensure the primary receives all of them in a single message once the interval elapsed.
+    let expected = WorkerPrimaryMessage::Batches(ours, others, id);
+    let handle = ack_listener(
+        address,
+        Some(Bytes::from(bincode::serialize(&expected).unwrap())),
+    );
+    assert!(timeout(Duration::from_millis(1_000), handle)
+        .await
+        .unwrap()
+        .is_ok());
+}
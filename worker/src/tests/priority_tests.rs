@@ -0,0 +1,44 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::transaction;
+
+#[test]
+fn split_prefixed_priority() {
+    let ciphertext = transaction();
+    let prioritized = with_priority(7, &ciphertext);
+    assert_eq!(split_priority(&prioritized), (7, &ciphertext[..]));
+    assert_eq!(strip_priority(&prioritized), &ciphertext[..]);
+
+    // Transactions without prefix have priority 0, and are their own ciphertext.
+    assert_eq!(split_priority(&ciphertext), (0, &ciphertext[..]));
+    assert_eq!(split_priority(PRIORITY_TAG), (0, PRIORITY_TAG));
+}
+
+#[test]
+fn sort_ties_by_digest() {
+    let mut batch = vec![
+        with_priority(1, b"a"),
+        with_priority(1, b"b"),
+        with_priority(1, b"c"),
+        with_priority(1, b"d"),
+    ];
+    let mut shuffled = batch.clone();
+    shuffled.reverse();
+    sort_by_priority(&mut batch, &mut Vec::new());
+    sort_by_priority(&mut shuffled, &mut Vec::new());
+    assert_eq!(batch, shuffled);
+    assert!(is_sorted_by_priority(&batch));
+}
+
+#[test]
+fn split_prefixed_epoch() {
+    let ciphertext = transaction();
+    let tagged = with_epoch(3, &with_priority(7, &ciphertext));
+    assert_eq!(split_epoch(&tagged).0, Some(3));
+    assert_eq!(split_priority(&tagged), (7, &ciphertext[..]));
+    assert_eq!(strip_priority(&tagged), &ciphertext[..]);
+
+    // Transactions without epoch use the current key.
+    assert_eq!(split_epoch(&ciphertext), (None, &ciphertext[..]));
+    assert_eq!(strip_priority(&with_epoch(3, &ciphertext)), &ciphertext[..]);
+}
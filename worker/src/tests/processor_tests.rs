@@ -2,8 +2,60 @@
 use super::*;
 use crate::common::batch;
 use crate::worker::WorkerMessage;
+use async_trait::async_trait;
 use std::fs;
+use std::sync::Arc;
+use store::{Key, MemoryStore, Store, StoreError, StoreResult, Value};
 use tokio::sync::mpsc::channel;
+use tokio::sync::Semaphore;
+use tokio::time::{timeout, Duration};
+
+/// A store whose synced writes only complete once the test allows them (and fail if it is broken).
+#[derive(Clone)]
+struct GatedStore {
+    inner: MemoryStore,
+    syncs: Arc<Semaphore>,
+    broken: bool,
+}
+
+#[async_trait]
+impl KeyValueStore for GatedStore {
+    async fn write(&mut self, key: Key, value: Value) {
+        self.inner.write(key, value).await
+    }
+
+    async fn read(&mut self, key: Key) -> StoreResult<Option<Value>> {
+        self.inner.read(key).await
+    }
+
+    async fn read_many(&mut self, keys: Vec<Key>) -> StoreResult<Vec<Option<Value>>> {
+        self.inner.read_many(keys).await
+    }
+
+    async fn notify_read(&mut self, key: Key) -> StoreResult<Value> {
+        self.inner.notify_read(key).await
+    }
+
+    async fn flush(&mut self) -> StoreResult<()> {
+        self.inner.flush().await
+    }
+
+    async fn write_synced(&mut self, entries: Vec<(Key, Value)>) -> StoreResult<()> {
+        self.syncs.acquire().await.unwrap().forget();
+        if self.broken {
+            return Err(StoreError::Decryption);
+        }
+        self.inner.write_synced(entries).await
+    }
+
+    async fn delete(&mut self, keys: Vec<Key>) {
+        self.inner.delete(keys).await
+    }
+
+    async fn delete_range(&mut self, from: Key, to: Key) {
+        self.inner.delete_range(from, to).await
+    }
+}
 
 #[tokio::test]
 async fn hash_and_store() {
@@ -23,20 +75,18 @@ async fn hash_and_store() {
         rx_batch,
         tx_digest,
         /* own_batch */ true,
+        /* compression_level */ None,
+        Shutdown::default(),
     );
 
     // Send a batch to the `Processor`.
-    let message = WorkerMessage::Batch(batch());
+    let message = WorkerMessage::Batch(0, batch());
     let serialized = bincode::serialize(&message).unwrap();
-    tx_batch.send(serialized.clone()).await.unwrap();
+    tx_batch.send((serialized.clone(), None)).await.unwrap();
 
     // Ensure the `Processor` outputs the batch's digest.
     let output = rx_digest.recv().await.unwrap();
-    let digest = Digest(
-        Sha512::digest(&serialized).as_slice()[..32]
-            .try_into()
-            .unwrap(),
-    );
+    let digest = hash(&serialized);
     let expected = bincode::serialize(&WorkerPrimaryMessage::OurBatch(digest.clone(), id)).unwrap();
     assert_eq!(output, expected);
 
@@ -45,3 +95,76 @@ async fn hash_and_store() {
     assert!(stored_batch.is_some(), "The batch is not in the store");
     assert_eq!(stored_batch.unwrap(), serialized);
 }
+
+#[tokio::test]
+async fn signal_stored_batches_after_sync() {
+    let (tx_batch, rx_batch) = channel(1);
+    let (tx_digest, mut rx_digest) = channel(1);
+    let syncs = Arc::new(Semaphore::new(0));
+    let mut store = GatedStore {
+        inner: MemoryStore::new(),
+        syncs: syncs.clone(),
+        broken: false,
+    };
+
+    // Spawn a `Processor` storing the batches of the other workers.
+    Processor::spawn(
+        /* id */ 0,
+        store.clone(),
+        rx_batch,
+        tx_digest,
+        /* own_batch */ false,
+        /* compression_level */ None,
+        Shutdown::default(),
+    );
+
+    // Send a batch whose author waits for a durable acknowledgement.
+    let message = WorkerMessage::Batch(0, batch());
+    let serialized = bincode::serialize(&message).unwrap();
+    let (tx_stored, mut rx_stored) = oneshot::channel();
+    tx_batch
+        .send((serialized.clone(), Some(tx_stored)))
+        .await
+        .unwrap();
+
+    // The acknowledgement is withheld while the store did not complete the synced write.
+    let withheld = timeout(Duration::from_millis(100), &mut rx_stored).await;
+    assert!(withheld.is_err(), "Acknowledged a batch before syncing it");
+
+    // Once the synced write completes, the batch is in the store and acknowledged.
+    syncs.add_permits(1);
+    assert!(rx_stored.await.unwrap());
+    let digest = hash(&serialized);
+    assert_eq!(store.read(digest.to_vec()).await.unwrap(), Some(serialized));
+    assert!(rx_digest.recv().await.is_some());
+}
+
+#[tokio::test]
+async fn signal_failed_synced_writes() {
+    let (tx_batch, rx_batch) = channel(1);
+    let (tx_digest, mut rx_digest) = channel(1);
+    let store = GatedStore {
+        inner: MemoryStore::new(),
+        syncs: Arc::new(Semaphore::new(1)),
+        broken: true,
+    };
+    Processor::spawn(
+        /* id */ 0,
+        store,
+        rx_batch,
+        tx_digest,
+        /* own_batch */ false,
+        /* compression_level */ None,
+        Shutdown::default(),
+    );
+
+    // The author is told that the batch is not durable, rather than left waiting.
+    let message = WorkerMessage::Batch(0, batch());
+    let (tx_stored, rx_stored) = oneshot::channel();
+    tx_batch
+        .send((bincode::serialize(&message).unwrap(), Some(tx_stored)))
+        .await
+        .unwrap();
+    assert!(!rx_stored.await.unwrap());
+    assert!(rx_digest.recv().await.is_some());
+}
@@ -1,12 +1,19 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
-use crate::common::{batch, committee_with_base_port, keys, ack_listener};
+use crate::backpressure::Backpressure;
+use crate::batch_maker::BatchMaker;
+use crate::common::{
+    ack_listener, batch, committee_with_base_port, dec_shares_listener, keys,
+    limited_dec_shares_listener, reply_listener, transaction, transaction_length,
+};
 use crate::worker::WorkerMessage;
 use bytes::Bytes;
-use config::ThresholdKeyPair;
+use config::{Parameters, ThresholdKeyPair};
 use futures::future::try_join_all;
-use network::ReliableSender;
-use tokio::sync::mpsc::channel;
+use network::{Backoff, ReliableSender};
+use tokio::sync::mpsc::{channel, unbounded_channel};
+use tokio::sync::watch;
+use tokio::time::{sleep, Duration};
 
 #[tokio::test]
 async fn wait_for_quorum() {
@@ -15,23 +22,26 @@ async fn wait_for_quorum() {
     let (myself, _) = keys().pop().unwrap();
     let committee = committee_with_base_port(7_000);
 
-    let threshold_keypair = ThresholdKeyPair::new(1, 0, 0);
-    let threshold_decryption_service =
-        ThresholdDecryptionService::spawn(threshold_keypair.sk_share, threshold_keypair.node_index);
+    let key_ring = ThresholdKeyRing::new(ThresholdKeyPair::new(1, 0, 0));
     let (tx_decryptable_batches, rx_decryptable_batches) = channel(1);
 
     // Spawn a `QuorumWaiter` instance.
     QuorumWaiter::spawn(
         committee.clone(),
         /* stake */ 1,
-        threshold_decryption_service,
+        /* durable_acks */ true,
+        key_ring,
         rx_message,
         tx_batch,
         tx_decryptable_batches,
+        /* tx_reseal */ unbounded_channel().0,
+        WorkerMetrics::default(),
+        Receipts::default(),
+        Shutdown::default(),
     );
 
     // Make a batch.
-    let message = WorkerMessage::Batch(batch());
+    let message = WorkerMessage::Batch(0, batch());
     let serialized = bincode::serialize(&message).unwrap();
     let expected = Bytes::from(serialized.clone());
 
@@ -53,15 +63,209 @@ async fn wait_for_quorum() {
 
     // Forward the batch along with the handlers to the `QuorumWaiter`.
     let message = QuorumWaiterMessage {
+        epoch: 0,
         batch: batch(),
+        bundles: Vec::new(),
         named_decrypt_shares_handlers: names.into_iter().zip(handlers.into_iter()).collect(),
     };
     tx_message.send(message).await.unwrap();
 
     // Wait for the `QuorumWaiter` to gather enough acknowledgements and output the batch.
-    let output = rx_batch.recv().await.unwrap();
+    let (output, _) = rx_batch.recv().await.unwrap();
     assert_eq!(output, serialized);
 
     // Ensure the other listeners correctly received the batch.
     assert!(try_join_all(listener_handles).await.is_ok());
 }
+
+#[tokio::test]
+async fn reseal_batches_rejected_as_too_large() {
+    let (tx_transaction, rx_transaction) = channel(10);
+    let (tx_message, rx_message) = channel(1);
+    let (tx_batch, mut rx_batch) = channel(10);
+    let (tx_decryptable_batches, rx_decryptable_batches) = channel(1);
+    let (tx_reseal, rx_reseal) = unbounded_channel();
+    let (myself, _) = keys().pop().unwrap();
+    let committee = committee_with_base_port(15_000);
+    let key_ring = ThresholdKeyRing::new(ThresholdKeyPair::new(1, 0, 0));
+
+    // The other workers only accept batches of a single transaction.
+    let workers_addresses: Vec<_> = committee
+        .others_workers(&myself, /* id */ &0)
+        .into_iter()
+        .map(|(name, addresses)| (name, addresses.worker_to_worker))
+        .collect();
+    for (_, address) in &workers_addresses {
+        limited_dec_shares_listener(*address, transaction_length());
+    }
+
+    // Spawn a `BatchMaker` sealing batches of two transactions, and the `QuorumWaiter`.
+    BatchMaker::spawn(
+        /* max_batch_size */ 2 * transaction_length(),
+        /* max_batch_delay */ 1_000_000, // Ensure the timer is not triggered.
+        rx_transaction,
+        /* rx_bundle */ channel(1).1,
+        rx_decryptable_batches,
+        rx_reseal,
+        tx_message,
+        workers_addresses,
+        Backoff::default(),
+        WorkerMetrics::default(),
+        watch::channel(Parameters::default()).1,
+        key_ring.clone(),
+        Backpressure::default(),
+        Shutdown::default(),
+    );
+    QuorumWaiter::spawn(
+        committee.clone(),
+        /* stake */ 1,
+        /* durable_acks */ true,
+        key_ring,
+        rx_message,
+        tx_batch,
+        tx_decryptable_batches,
+        tx_reseal,
+        WorkerMetrics::default(),
+        Receipts::default(),
+        Shutdown::default(),
+    );
+
+    // The rejected batch is split, and both halves are acknowledged.
+    tx_transaction.send(transaction()).await.unwrap();
+    tx_transaction.send(transaction()).await.unwrap();
+    for _ in 0..2 {
+        let (output, _) = rx_batch.recv().await.unwrap();
+        match bincode::deserialize(&output).unwrap() {
+            WorkerMessage::DecryptableBatch(_, batch, _) => assert_eq!(batch.len(), 1),
+            _ => panic!("Unexpected message"),
+        }
+    }
+}
+
+#[tokio::test]
+async fn drop_batches_rejected_for_their_epoch_or_order() {
+    let (tx_message, rx_message) = channel(1);
+    let (tx_reseal, mut rx_reseal) = unbounded_channel();
+    let (myself, _) = keys().pop().unwrap();
+    let committee = committee_with_base_port(16_000);
+    let names: Vec<_> = committee
+        .others_workers(&myself, /* id */ &0)
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect();
+    let key_ring = ThresholdKeyRing::new(ThresholdKeyPair::new(1, 0, 0).with_epoch(1));
+
+    // Spawn a `QuorumWaiter` instance.
+    QuorumWaiter::spawn(
+        committee.clone(),
+        /* stake */ 1,
+        /* durable_acks */ true,
+        key_ring,
+        rx_message,
+        /* tx_batch */ channel(1).0,
+        /* tx_decryptable_batches */ channel(1).0,
+        tx_reseal,
+        WorkerMetrics::default(),
+        Receipts::default(),
+        Shutdown::default(),
+    );
+
+    // The other workers have no keys for epoch 1, then reject the order of our next batch, and then our
+    // last batch as too large.
+    let mut network = ReliableSender::new();
+    for (base_port, reply) in &[
+        (16_000, UNKNOWN_EPOCH),
+        (16_200, BATCH_MISORDERED),
+        (16_100, BATCH_TOO_LARGE),
+    ] {
+        let addresses: Vec<_> = committee_with_base_port(*base_port)
+            .others_workers(&myself, /* id */ &0)
+            .into_iter()
+            .map(|(_, addresses)| addresses.worker_to_worker)
+            .collect();
+        for address in &addresses {
+            reply_listener(*address, *reply);
+        }
+        let message = WorkerMessage::Batch(1, batch());
+        let bytes = Bytes::from(bincode::serialize(&message).unwrap());
+        let handlers = network.broadcast(addresses, bytes).await;
+        let message = QuorumWaiterMessage {
+            epoch: 1,
+            batch: batch(),
+            bundles: Vec::new(),
+            named_decrypt_shares_handlers: names.iter().cloned().zip(handlers).collect(),
+        };
+        tx_message.send(message).await.unwrap();
+    }
+
+    // The batches rejected for their epoch or order are dropped, and the `QuorumWaiter` moves on to the
+    // next one.
+    let (epoch, _, _) = rx_reseal.recv().await.unwrap();
+    assert_eq!(epoch, 1);
+}
+
+#[tokio::test]
+async fn report_batches_not_acknowledged_durably() {
+    let (tx_message, rx_message) = channel(1);
+    let (tx_decryptable_batches, mut rx_decryptable_batches) = channel(1);
+    let (myself, _) = keys().pop().unwrap();
+    let committee = committee_with_base_port(16_300);
+    let metrics = WorkerMetrics::default();
+
+    // Spawn a `QuorumWaiter` instance requiring durable acknowledgements.
+    QuorumWaiter::spawn(
+        committee.clone(),
+        /* stake */ 1,
+        /* durable_acks */ true,
+        ThresholdKeyRing::new(ThresholdKeyPair::new(1, 0, 0)),
+        rx_message,
+        /* tx_batch */ channel(1).0,
+        tx_decryptable_batches,
+        /* tx_reseal */ unbounded_channel().0,
+        metrics.clone(),
+        Receipts::default(),
+        Shutdown::default(),
+    );
+
+    // The other workers send us their decryption shares.
+    let (names, addresses): (Vec<_>, Vec<_>) = committee
+        .others_workers(&myself, /* id */ &0)
+        .into_iter()
+        .map(|(name, addresses)| (name, addresses.worker_to_worker))
+        .unzip();
+    for address in &addresses {
+        dec_shares_listener(*address, None);
+    }
+    let mut network = ReliableSender::new();
+    let message = WorkerMessage::Batch(0, batch());
+    let bytes = Bytes::from(bincode::serialize(&message).unwrap());
+    let handlers = network.broadcast(addresses, bytes).await;
+    let message = QuorumWaiterMessage {
+        epoch: 0,
+        batch: batch(),
+        bundles: Vec::new(),
+        named_decrypt_shares_handlers: names.iter().cloned().zip(handlers).collect(),
+    };
+    tx_message.send(message).await.unwrap();
+
+    // They then acknowledge the decryptable batch without storing it first (as with `durable_acks` off).
+    let (decryptable_batch, tx_handlers) = rx_decryptable_batches.recv().await.unwrap();
+    let addresses: Vec<_> = committee_with_base_port(16_400)
+        .others_workers(&myself, /* id */ &0)
+        .into_iter()
+        .map(|(_, addresses)| addresses.worker_to_worker)
+        .collect();
+    for address in &addresses {
+        reply_listener(*address, b"Ack");
+    }
+    let handlers = network
+        .broadcast(addresses, Bytes::from(decryptable_batch))
+        .await;
+    tx_handlers
+        .send(names.into_iter().zip(handlers).collect())
+        .unwrap();
+
+    // The batch misses its quorum of durable acknowledgements, which is reported.
+    sleep(Duration::from_millis(500)).await;
+    assert_eq!(metrics.batches_unacknowledged.get(), 1);
+}
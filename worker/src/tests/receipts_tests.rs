@@ -0,0 +1,40 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{batch, transaction};
+
+#[tokio::test]
+async fn deliver_receipts() {
+    let receipts = Receipts::default();
+    let tx = transaction();
+    let batch = vec![tx.clone(), transaction()];
+    assert!(receipts.watched(&batch).is_empty());
+
+    // Two clients wait for the same transaction.
+    let first = receipts.subscribe(&tx);
+    let second = receipts.subscribe(&tx);
+    let watched = receipts.watched(&batch);
+    assert_eq!(watched, vec![Receipts::digest(&tx)]);
+
+    let serialized = bincode::serialize(&batch).unwrap();
+    receipts.deliver(watched, &serialized, /* epoch */ 3);
+    let expected = Receipt {
+        transaction: Receipts::digest(&tx),
+        batch: Receipts::digest(&serialized),
+        epoch: 3,
+    };
+    assert_eq!(first.await.unwrap(), expected);
+    assert_eq!(second.await.unwrap(), expected);
+    assert!(receipts.watched(&batch).is_empty());
+}
+
+#[tokio::test]
+async fn forget_abandoned_subscriptions() {
+    let receipts = Receipts::default();
+    let batch = batch();
+
+    // A client gives up on its receipt before the batch is acknowledged.
+    drop(receipts.subscribe(b"never batched"));
+    receipts.deliver(Vec::new(), &[], /* epoch */ 0);
+    assert!(receipts.pending.lock().unwrap().is_empty());
+    assert!(receipts.watched(&batch).is_empty());
+}
@@ -0,0 +1,51 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crypto::Digest;
+
+fn batch(i: u8) -> (Vec<SerializedCiphertext>, Vec<u8>) {
+    let batch = vec![vec![i; 3], (0..10).collect()];
+    let serialized = vec![i; 16];
+    (batch, serialized)
+}
+
+#[test]
+fn sample_every_batch() {
+    let mut sampler = BatchSampler::new(1.0);
+    for i in 0..5 {
+        let (batch, serialized) = batch(i);
+        let description = sampler.sample(&batch, &serialized).unwrap();
+        let digest: Digest = hash(&serialized);
+        let expected = format!(
+            "Sampled batch {:?} contains 2 tx(s): {:02x}{:02x}{:02x} 0001020304050607..",
+            digest, i, i, i
+        );
+        assert_eq!(description, expected);
+    }
+}
+
+#[test]
+fn sample_nothing_by_default() {
+    let mut sampler = BatchSampler::new(0.0);
+    let (batch, serialized) = batch(0);
+    assert!((0..100).all(|_| sampler.sample(&batch, &serialized).is_none()));
+
+    // Invalid rates sample nothing either.
+    sampler.set_rate(-1.0);
+    assert!(sampler.sample(&batch, &serialized).is_none());
+    sampler.set_rate(f64::NAN);
+    assert!(sampler.sample(&batch, &serialized).is_none());
+}
+
+#[test]
+fn sample_a_fraction_of_the_batches() {
+    let mut sampler = BatchSampler::new(0.5);
+    let (batch, serialized) = batch(0);
+    let sampled: Vec<_> = (0..6)
+        .map(|_| sampler.sample(&batch, &serialized).is_some())
+        .collect();
+    assert_eq!(sampled, vec![false, true, false, true, false, true]);
+
+    // Rates above 1 sample every batch.
+    sampler.set_rate(3.0);
+    assert!((0..3).all(|_| sampler.sample(&batch, &serialized).is_some()));
+}
@@ -0,0 +1,215 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{ack_listener, batch, batch_digest, committee_with_base_port, keys};
+use config::ThresholdKeyPair;
+use futures::stream::StreamExt as _;
+use std::fs;
+use store::Store;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::channel;
+use tokio::time::timeout;
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+// Fixture
+fn shares(keypair: &ThresholdKeyPair, batch: &[Vec<u8>]) -> NodeDecryptionShares {
+    let shares = batch
+        .iter()
+        .map(|tx| bincode::deserialize::<Ciphertext>(tx).unwrap())
+        .map(|ciphertext| keypair.sk_share.decrypt_share(&ciphertext).unwrap())
+        .collect();
+    (keypair.node_index, shares)
+}
+
+#[tokio::test]
+async fn share_reply() {
+    let (_tx_request, rx_request) = channel(1);
+    let (tx_message, rx_message) = channel(1);
+    let mut keys = keys();
+    let (requestor, _) = keys.pop().unwrap();
+    let (name, _) = keys.pop().unwrap();
+    let id = 0;
+    let committee = committee_with_base_port(12_000);
+    let keypair = ThresholdKeyPair::new(1, 0, 0);
+
+    // Create a new test store holding one of our batches.
+    let path = ".db_test_share_reply";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+    let batch = batch();
+    let message = WorkerMessage::DecryptableBatch(0, batch.clone(), Vec::new());
+    store
+        .write(
+            batch_digest().to_vec(),
+            bincode::serialize(&message).unwrap(),
+        )
+        .await;
+
+    // Spawn a `ShareGossip` instance.
+    ShareGossip::spawn(
+        name,
+        id,
+        committee.clone(),
+        store,
+        ThresholdKeyRing::new(ThresholdKeyPair::new(1, 0, 0)),
+        /* sync_retry_delay */ 10_000,
+        rx_request,
+        rx_message,
+        watch::channel(Parameters::default()).1,
+    );
+
+    // Spawn a listener to receive our shares.
+    let address = committee.worker(&requestor, &id).unwrap().worker_to_worker;
+    let expected = WorkerMessage::DecryptionShares(batch_digest(), name, shares(&keypair, &batch));
+    let mut handle = ack_listener(
+        address,
+        Some(Bytes::from(bincode::serialize(&expected).unwrap())),
+    );
+
+    // Ask for our shares: they stay hidden until the batch is committed.
+    tx_message
+        .send(ShareMessage::Request(batch_digest(), requestor))
+        .await
+        .unwrap();
+    assert!(timeout(Duration::from_millis(100), &mut handle)
+        .await
+        .is_err());
+
+    // Once our primary tells us the batch is committed, the requestor gets the shares it asks for.
+    for message in vec![
+        ShareMessage::Committed(vec![batch_digest()]),
+        ShareMessage::Request(batch_digest(), requestor),
+    ] {
+        tx_message.send(message).await.unwrap();
+    }
+    assert!(handle.await.is_ok());
+}
+
+#[tokio::test]
+async fn gather_shares() {
+    let (tx_request, rx_request) = channel(1);
+    let (tx_message, rx_message) = channel(10);
+    let keys = keys();
+    let name = keys[0].0;
+    let id = 0;
+    let committee = committee_with_base_port(13_000);
+
+    // Create a new test store holding a batch without decryption shares.
+    let path = ".db_test_gather_shares";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+    let batch = batch();
+    let message = WorkerMessage::DecryptableBatch(0, batch.clone(), Vec::new());
+    store
+        .write(
+            batch_digest().to_vec(),
+            bincode::serialize(&message).unwrap(),
+        )
+        .await;
+
+    // Spawn a `ShareGossip` instance: one more share than ours is needed to decrypt.
+    ShareGossip::spawn(
+        name,
+        id,
+        committee,
+        store,
+        ThresholdKeyRing::new(ThresholdKeyPair::new(1, 0, 0)),
+        /* sync_retry_delay */ 10_000,
+        rx_request,
+        rx_message,
+        watch::channel(Parameters::default()).1,
+    );
+
+    // Ask for the shares of the batch.
+    let (tx_reply, rx_reply) = oneshot::channel();
+    tx_request.send((batch_digest(), tx_reply)).await.unwrap();
+    sleep(Duration::from_millis(100)).await;
+
+    // Someone sends shares computed for another batch on behalf of a node: they are ignored, and do not
+    // prevent the node from sending its valid shares.
+    let author = keys[1].0;
+    let invalid = shares(&ThresholdKeyPair::new(1, 1, 0), &crate::common::batch());
+    let valid = shares(&ThresholdKeyPair::new(1, 1, 0), &batch);
+    for shares in vec![invalid, valid] {
+        let message = ShareMessage::Shares(batch_digest(), author, shares);
+        tx_message.send(message).await.unwrap();
+    }
+
+    // Ensure we gathered our shares and the valid ones.
+    let gathered = rx_reply.await.unwrap();
+    let indices: Vec<_> = gathered.iter().map(|(index, _)| *index).collect();
+    assert_eq!(indices, vec![0, 1]);
+}
+
+#[tokio::test]
+async fn retry_dropped_shares() {
+    let (tx_request, rx_request) = channel(1);
+    let (tx_message, rx_message) = channel(10);
+    let keys = keys();
+    let name = keys[0].0;
+    let id = 0;
+    let committee = committee_with_base_port(13_100);
+
+    // Create a new test store holding a batch without decryption shares.
+    let path = ".db_test_retry_dropped_shares";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+    let batch = batch();
+    let message = WorkerMessage::DecryptableBatch(0, batch.clone(), Vec::new());
+    store
+        .write(
+            batch_digest().to_vec(),
+            bincode::serialize(&message).unwrap(),
+        )
+        .await;
+
+    // Spawn a listener for the worker of another authority, counting the share requests it receives.
+    let author = keys[1].0;
+    let address = committee.worker(&author, &id).unwrap().worker_to_worker;
+    let (tx_received, mut rx_received) = channel(10);
+    tokio::spawn(async move {
+        let listener = TcpListener::bind(&address).await.unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+        while let Some(Ok(bytes)) = transport.next().await {
+            match bincode::deserialize(&bytes).unwrap() {
+                WorkerMessage::DecryptionSharesRequest(digest, origin) => {
+                    tx_received.send((digest, origin)).await.unwrap()
+                }
+                _ => panic!("Unexpected message"),
+            }
+        }
+    });
+
+    // Spawn a `ShareGossip` instance: one more share than ours is needed to decrypt.
+    ShareGossip::spawn(
+        name,
+        id,
+        committee,
+        store,
+        ThresholdKeyRing::new(ThresholdKeyPair::new(1, 0, 0)),
+        /* sync_retry_delay */ 100,
+        rx_request,
+        rx_message,
+        watch::channel(Parameters::default()).1,
+    );
+
+    // Ask for the shares of the batch. The reply to the first request is lost.
+    let (tx_reply, rx_reply) = oneshot::channel();
+    tx_request.send((batch_digest(), tx_reply)).await.unwrap();
+    assert_eq!(rx_received.recv().await, Some((batch_digest(), name)));
+
+    // The missing shares are requested again.
+    assert_eq!(rx_received.recv().await, Some((batch_digest(), name)));
+
+    // The worker replies twice (eg. it restarted in between): its shares only count once.
+    let valid = shares(&ThresholdKeyPair::new(1, 1, 0), &batch);
+    for _ in 0..2 {
+        let message = ShareMessage::Shares(batch_digest(), author, valid.clone());
+        tx_message.send(message).await.unwrap();
+    }
+
+    // Ensure we gathered our shares and those of the other worker.
+    let gathered = rx_reply.await.unwrap();
+    let indices: Vec<_> = gathered.iter().map(|(index, _)| *index).collect();
+    assert_eq!(indices, vec![0, 1]);
+}
@@ -1,7 +1,8 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
-use crate::common::{batch_digest, committee_with_base_port, keys, ack_listener};
+use crate::common::{ack_listener, batch_digest, committee_with_base_port, keys};
 use std::fs;
+use store::Store;
 use tokio::sync::mpsc::channel;
 
 #[tokio::test]
@@ -27,7 +28,9 @@ async fn synchronize() {
         /* gc_depth */ 50, // Not used in this test.
         /* sync_retry_delay */ 1_000_000, // Ensure it is not triggered.
         /* sync_retry_nodes */ 3, // Not used in this test.
+        /* max_sync_requests */ 1_000, // Not used in this test.
         rx_message,
+        watch::channel(Parameters::default()).1,
     );
 
     // Spawn a listener to receive our batch requests.
@@ -45,3 +48,47 @@ async fn synchronize() {
     // Ensure the target receives the sync request.
     assert!(handle.await.is_ok());
 }
+
+#[tokio::test]
+async fn cap_outstanding_batch_requests() {
+    let (tx_message, rx_message) = channel(1);
+
+    let mut keys = keys();
+    let (name, _) = keys.pop().unwrap();
+    let id = 0;
+    let committee = committee_with_base_port(19_000);
+
+    // Create a new test store.
+    let path = ".db_test_cap_outstanding_batch_requests";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn a `Synchronizer` waiting for at most one batch at a time.
+    Synchronizer::spawn(
+        name,
+        id,
+        committee.clone(),
+        store.clone(),
+        /* gc_depth */ 50, // Not used in this test.
+        /* sync_retry_delay */ 1_000_000, // Ensure it is not triggered.
+        /* sync_retry_nodes */ 3, // Not used in this test.
+        /* max_sync_requests */ 1,
+        rx_message,
+        watch::channel(Parameters::default()).1,
+    );
+
+    // Spawn a listener expecting a request for the first batch only.
+    let (target, _) = keys.pop().unwrap();
+    let address = committee.worker(&target, &id).unwrap().worker_to_worker;
+    let message = WorkerMessage::BatchRequest(vec![batch_digest()], name);
+    let serialized = bincode::serialize(&message).unwrap();
+    let handle = ack_listener(address, Some(Bytes::from(serialized)));
+
+    // Ask to sync two batches.
+    let missing = vec![batch_digest(), Digest([1u8; 32])];
+    let message = PrimaryWorkerMessage::Synchronize(missing, target);
+    tx_message.send(message).await.unwrap();
+
+    // Ensure the target receives the capped sync request.
+    assert!(handle.await.is_ok());
+}
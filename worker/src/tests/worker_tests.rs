@@ -4,15 +4,23 @@ use crate::common::{
     ack_listener, batch_digest, committee_with_base_port, dec_shares_listener, keys, transaction,
     transaction_length,
 };
-use network::SimpleSender;
+use crate::http_receiver::LENGTH_DELIMITED;
+use crate::priority::{with_epoch, with_priority};
+use config::ThresholdKeyPair;
+use network::{ReliableSender, SimpleSender};
 use primary::WorkerPrimaryMessage;
 use std::fs;
+use std::net::SocketAddr;
+use store::Store;
+use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+use tokio::net::TcpStream;
+use tokio::time::{sleep, Duration};
 
 #[tokio::test]
 async fn handle_clients_transactions() {
     let (name, _) = keys().pop().unwrap();
     let id = 0;
-    let threshold_keypair = ThresholdKeyPair::new(1, 0, 0);
+    let key_ring = ThresholdKeyRing::new(ThresholdKeyPair::new(1, 0, 0));
     let committee = committee_with_base_port(11_000);
     let parameters = Parameters {
         batch_size: 2 * transaction_length(), // Two transactions.
@@ -28,11 +36,16 @@ async fn handle_clients_transactions() {
     Worker::spawn(
         name,
         id,
-        threshold_keypair,
+        key_ring,
         committee.clone(),
-        parameters,
+        parameters.clone(),
         store,
-    );
+        &Registry::new(),
+        watch::channel(parameters).1,
+        /* rx_share_requests */ channel(1).1,
+        Shutdown::default(),
+    )
+    .unwrap();
 
     // Spawn a network listener to receive our batch's digest.
     let primary_address = committee.primary(&name).unwrap().worker_to_primary;
@@ -55,3 +68,303 @@ async fn handle_clients_transactions() {
     // Ensure the primary received the batch's digest (ie. it did not panic).
     assert!(handle.await.is_ok());
 }
+
+#[tokio::test]
+async fn handle_clients_transactions_with_small_channels() {
+    let (name, _) = keys().pop().unwrap();
+    let id = 0;
+    let key_ring = ThresholdKeyRing::new(ThresholdKeyPair::new(1, 0, 0));
+    let committee = committee_with_base_port(11_100);
+    let parameters = Parameters {
+        batch_size: 2 * transaction_length(), // Two transactions.
+        channel_capacity: 1,
+        ..Parameters::default()
+    };
+
+    // Create a new test store.
+    let path = ".db_test_handle_clients_transactions_with_small_channels";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn a `Worker` instance whose channels only hold a single message.
+    Worker::spawn(
+        name,
+        id,
+        key_ring,
+        committee.clone(),
+        parameters.clone(),
+        store,
+        &Registry::new(),
+        watch::channel(parameters).1,
+        /* rx_share_requests */ channel(1).1,
+        Shutdown::default(),
+    )
+    .unwrap();
+
+    let primary_address = committee.primary(&name).unwrap().worker_to_primary;
+    let handle = ack_listener(primary_address, None);
+    for (_, addresses) in committee.others_workers(&name, &id) {
+        let _ = dec_shares_listener(addresses.worker_to_worker, /* expected */ None);
+    }
+
+    // The transactions still make it into a batch whose digest reaches the primary.
+    let mut network = SimpleSender::new();
+    let address = committee.worker(&name, &id).unwrap().transactions;
+    network.send(address, Bytes::from(transaction())).await;
+    network.send(address, Bytes::from(transaction())).await;
+    assert!(handle.await.is_ok());
+}
+
+#[tokio::test]
+async fn handle_clients_transactions_over_http() {
+    let (name, _) = keys().pop().unwrap();
+    let id = 0;
+    let key_ring = ThresholdKeyRing::new(ThresholdKeyPair::new(1, 0, 0));
+    let committee = committee_with_base_port(11_200);
+    let http_address: SocketAddr = "127.0.0.1:11290".parse().unwrap();
+    let parameters = Parameters {
+        batch_size: 2 * transaction_length(), // Two transactions.
+        http_transactions_address: Some(http_address),
+        ..Parameters::default()
+    };
+
+    // Create a new test store.
+    let path = ".db_test_handle_clients_transactions_over_http";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn a `Worker` instance.
+    Worker::spawn(
+        name,
+        id,
+        key_ring,
+        committee.clone(),
+        parameters.clone(),
+        store,
+        &Registry::new(),
+        watch::channel(parameters).1,
+        /* rx_share_requests */ channel(1).1,
+        Shutdown::default(),
+    )
+    .unwrap();
+
+    let primary_address = committee.primary(&name).unwrap().worker_to_primary;
+    let handle = ack_listener(primary_address, None);
+    for (_, addresses) in committee.others_workers(&name, &id) {
+        let _ = dec_shares_listener(addresses.worker_to_worker, /* expected */ None);
+    }
+
+    // Post two length-delimited transactions in a single request.
+    let mut body = Vec::new();
+    for _ in 0..2 {
+        let tx = transaction();
+        body.extend_from_slice(&(tx.len() as u32).to_be_bytes());
+        body.extend_from_slice(&tx);
+    }
+    let mut request = format!(
+        "POST /transactions HTTP/1.1\r\nHost: {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        http_address,
+        LENGTH_DELIMITED,
+        body.len()
+    )
+    .into_bytes();
+    request.extend_from_slice(&body);
+
+    sleep(Duration::from_millis(100)).await;
+    let mut stream = TcpStream::connect(http_address).await.unwrap();
+    stream.write_all(&request).await.unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.unwrap();
+    assert!(response.starts_with(b"HTTP/1.1 202"));
+
+    // The transactions make it into a batch whose digest reaches the primary.
+    assert!(handle.await.is_ok());
+}
+
+#[tokio::test]
+async fn reject_transactions_larger_than_a_batch() {
+    let (tx_batch_maker, mut rx_batch_maker) = channel(10);
+    let metrics = WorkerMetrics::default();
+    let handler = TxReceiverHandler {
+        tx_batch_maker,
+        tx_bundles: channel(1).0,
+        backpressure: Backpressure::default(),
+        metrics: metrics.clone(),
+        max_transaction_size: transaction_length(),
+        max_bundle_transactions: 10,
+        priority_ordering: false,
+    };
+
+    assert!(handler.submit(transaction()).is_ok());
+    match handler.submit(vec![0u8; transaction_length() + 1]) {
+        Err(IntakeError::TooLarge { size, max }) => {
+            assert_eq!(size, transaction_length() + 1);
+            assert_eq!(max, transaction_length());
+        }
+        x => panic!("Unexpected result: {:?}", x),
+    }
+    assert_eq!(metrics.transactions_rejected.get(), 1);
+
+    // Only the first transaction reached the batch maker.
+    assert_eq!(
+        rx_batch_maker.recv().await.unwrap().len(),
+        transaction_length()
+    );
+    drop(handler);
+    assert!(rx_batch_maker.recv().await.is_none());
+}
+
+#[tokio::test]
+async fn reject_bundles_larger_than_a_batch() {
+    let (tx_bundles, mut rx_bundles) = channel(10);
+    let metrics = WorkerMetrics::default();
+    let handler = TxReceiverHandler {
+        tx_batch_maker: channel(1).0,
+        tx_bundles,
+        backpressure: Backpressure::default(),
+        metrics: metrics.clone(),
+        max_transaction_size: 2 * transaction_length(),
+        max_bundle_transactions: 2,
+        priority_ordering: false,
+    };
+
+    // A bundle of three transactions exceeds both limits: none of its transactions is accepted.
+    let too_large = vec![transaction(), transaction(), transaction()];
+    match handler.submit_bundle(too_large) {
+        Err(IntakeError::BundleTooLarge { count, size, .. }) => {
+            assert_eq!(count, 3);
+            assert_eq!(size, 3 * transaction_length());
+        }
+        x => panic!("Unexpected result: {:?}", x),
+    }
+    assert_eq!(metrics.transactions_rejected.get(), 3);
+
+    // A fitting bundle reaches the batch maker whole.
+    let bundle = vec![transaction(), transaction()];
+    assert!(handler.submit_bundle(bundle.clone()).is_ok());
+    assert_eq!(rx_bundles.recv().await.unwrap(), bundle);
+    drop(handler);
+    assert!(rx_bundles.recv().await.is_none());
+}
+
+#[tokio::test]
+async fn reject_bundles_of_mixed_epochs() {
+    let (tx_bundles, mut rx_bundles) = channel(10);
+    let metrics = WorkerMetrics::default();
+    let handler = TxReceiverHandler {
+        tx_batch_maker: channel(1).0,
+        tx_bundles,
+        backpressure: Backpressure::default(),
+        metrics: metrics.clone(),
+        max_transaction_size: 10 * transaction_length(),
+        max_bundle_transactions: 10,
+        priority_ordering: false,
+    };
+
+    // A bundle cannot span two batches, hence two epochs.
+    let mixed = vec![transaction(), with_epoch(1, &transaction())];
+    assert!(matches!(
+        handler.submit_bundle(mixed),
+        Err(IntakeError::MixedEpochs)
+    ));
+    assert_eq!(metrics.transactions_rejected.get(), 2);
+
+    let bundle = vec![with_epoch(1, &transaction()), with_epoch(1, &transaction())];
+    assert!(handler.submit_bundle(bundle.clone()).is_ok());
+    assert_eq!(rx_bundles.recv().await.unwrap(), bundle);
+}
+
+#[tokio::test]
+async fn reject_bundles_of_mixed_priorities() {
+    let (tx_bundles, mut rx_bundles) = channel(10);
+    let metrics = WorkerMetrics::default();
+    let handler = TxReceiverHandler {
+        tx_batch_maker: channel(1).0,
+        tx_bundles,
+        backpressure: Backpressure::default(),
+        metrics: metrics.clone(),
+        max_transaction_size: 10 * transaction_length(),
+        max_bundle_transactions: 10,
+        priority_ordering: true,
+    };
+
+    // A bundle ordered as a whole cannot hold transactions of different priorities.
+    let mixed = vec![with_priority(1, &transaction()), transaction()];
+    assert!(matches!(
+        handler.submit_bundle(mixed),
+        Err(IntakeError::MixedPriorities)
+    ));
+    assert_eq!(metrics.transactions_rejected.get(), 2);
+
+    let bundle = vec![
+        with_priority(1, &transaction()),
+        with_priority(1, &transaction()),
+    ];
+    assert!(handler.submit_bundle(bundle.clone()).is_ok());
+    assert_eq!(rx_bundles.recv().await.unwrap(), bundle);
+}
+
+#[tokio::test]
+async fn reply_to_batches_of_unknown_epochs() {
+    let address: SocketAddr = "127.0.0.1:15500".parse().unwrap();
+    let handler = WorkerReceiverHandler {
+        tx_helper: channel(1).0,
+        tx_processor: channel(1).0,
+        tx_share_gossip: channel(1).0,
+        key_ring: ThresholdKeyRing::new(ThresholdKeyPair::new(1, 0, 0)),
+        metrics: WorkerMetrics::default(),
+        max_batch_size: usize::MAX,
+        durable_acks: false,
+        priority_ordering: false,
+    };
+    Receiver::spawn(address, handler);
+    sleep(Duration::from_millis(50)).await;
+
+    // We have no keys for epoch 1: the sender is told so rather than left waiting for our shares, and the
+    // replies to its next batches still match them.
+    let mut sender = ReliableSender::new();
+    for epoch in &[1, 0] {
+        let message = WorkerMessage::Batch(*epoch, vec![transaction()]);
+        let bytes = Bytes::from(bincode::serialize(&message).unwrap());
+        let reply = sender.send(address, bytes).await.await.unwrap();
+        match epoch {
+            1 => assert_eq!(reply.as_ref(), UNKNOWN_EPOCH),
+            _ => assert!(bincode::deserialize::<NodeDecryptionShares>(&reply).is_ok()),
+        }
+    }
+}
+
+#[tokio::test]
+async fn reply_to_misordered_batches() {
+    let address: SocketAddr = "127.0.0.1:15600".parse().unwrap();
+    let handler = WorkerReceiverHandler {
+        tx_helper: channel(1).0,
+        tx_processor: channel(1).0,
+        tx_share_gossip: channel(1).0,
+        key_ring: ThresholdKeyRing::new(ThresholdKeyPair::new(1, 0, 0)),
+        metrics: WorkerMetrics::default(),
+        max_batch_size: usize::MAX,
+        durable_acks: false,
+        priority_ordering: true,
+    };
+    Receiver::spawn(address, handler);
+    sleep(Duration::from_millis(50)).await;
+
+    // The sender is told that we reject the order of its batch, rather than left waiting for our shares.
+    let mut sender = ReliableSender::new();
+    let low = with_priority(1, &transaction());
+    let high = with_priority(5, &transaction());
+    for (batch, misordered) in vec![
+        (vec![low.clone(), high.clone()], true),
+        (vec![high, low], false),
+    ] {
+        let message = WorkerMessage::Batch(0, batch);
+        let bytes = Bytes::from(bincode::serialize(&message).unwrap());
+        let reply = sender.send(address, bytes).await.await.unwrap();
+        if misordered {
+            assert_eq!(reply.as_ref(), BATCH_MISORDERED);
+        } else {
+            assert!(bincode::deserialize::<NodeDecryptionShares>(&reply).is_ok());
+        }
+    }
+}
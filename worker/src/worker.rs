@@ -1,36 +1,46 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+use crate::backpressure::{Backpressure, MEMPOOL_FULL};
 pub use crate::batch_maker::SerializedCiphertext;
 use crate::batch_maker::{Batch, BatchMaker};
+use crate::compression::decompress;
 use crate::helper::Helper;
+use crate::http_receiver::HttpReceiver;
+use crate::key_ring::ThresholdKeyRing;
+use crate::metrics::WorkerMetrics;
 use crate::primary_connector::PrimaryConnector;
-use crate::processor::{Processor, SerializedDecryptableBatchMessage};
+use crate::priority::{is_sorted_by_priority, split_epoch, split_priority, strip_priority};
+use crate::processor::{Processor, StoreRequest};
 use crate::quorum_waiter::QuorumWaiter;
+use crate::receipts::Receipts;
+use crate::share_gossip::{ShareGossip, ShareMessage, ShareRequest};
+use crate::shutdown::Shutdown;
 use crate::synchronizer::Synchronizer;
 use async_trait::async_trait;
 use bytes::Bytes;
-use config::{Committee, Parameters, ThresholdKeyPair, WorkerId};
+use config::{Committee, ConfigError, Epoch, Parameters, WorkerId};
 use crypto::threshold::Ciphertext;
-use crypto::{BatchDecryptionShares, Digest, PublicKey, ThresholdDecryptionService};
+use crypto::{BatchDecryptionShares, Digest, NodeDecryptionShares, PublicKey};
 use futures::sink::SinkExt as _;
 #[cfg(not(test))]
 use log::{debug, error, info, warn}; // Use log crate when building application
-use network::{MessageHandler, Receiver, Writer};
+use network::{Backoff, MessageHandler, RateLimit, Receiver, Writer};
 use primary::PrimaryWorkerMessage;
+use prometheus::Registry;
 use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
 use serde::{Deserialize, Serialize};
 use std::error::Error;
 #[cfg(test)]
 use std::{println as info, println as warn, println as error, println as debug};
-use store::Store;
-use tokio::sync::mpsc::{channel, Sender};
+use store::KeyValueStore;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{self, channel, Sender};
+use tokio::sync::{oneshot, watch};
+use tokio::task::JoinHandle;
 
 #[cfg(test)]
 #[path = "tests/worker_tests.rs"]
 pub mod worker_tests;
 
-/// The default channel capacity for each channel of the worker.
-pub const CHANNEL_CAPACITY: usize = 1_000;
-
 /// The primary round number.
 // TODO: Move to the primary.
 pub type Round = u64;
@@ -38,15 +48,18 @@ pub type Round = u64;
 /// Indicates a serialized `WorkerPrimaryMessage` message.
 pub type SerializedBatchDigestMessage = Vec<u8>;
 
-/// The message exchanged between workers.
+/// The message exchanged between workers. Batches carry the epoch of the threshold key used to encrypt
+/// their transactions.
 #[derive(Debug, Serialize, Deserialize)]
 pub enum WorkerMessage {
-    Batch(Batch),
-    DecryptableBatch(Batch, BatchDecryptionShares),
+    Batch(Epoch, Batch),
+    DecryptableBatch(Epoch, Batch, BatchDecryptionShares),
     BatchRequest(Vec<Digest>, /* origin */ PublicKey),
+    DecryptionSharesRequest(Digest, /* origin */ PublicKey),
+    DecryptionShares(Digest, /* author */ PublicKey, NodeDecryptionShares),
 }
 
-pub struct Worker {
+pub struct Worker<S> {
     /// The public key of this authority.
     name: PublicKey,
     /// The id of this worker.
@@ -56,18 +69,35 @@ pub struct Worker {
     /// The configuration parameters.
     parameters: Parameters,
     /// The persistent storage.
-    store: Store,
+    store: S,
+    /// The metrics exported by the worker.
+    metrics: WorkerMetrics,
+    /// Receives the parameters reloaded by the operator.
+    rx_parameters: watch::Receiver<Parameters>,
+    /// The threshold keys of the current (and neighbouring) epochs.
+    key_ring: ThresholdKeyRing,
 }
 
-impl Worker {
+impl<S: KeyValueStore> Worker<S> {
+    /// Spawns all the tasks of the worker. Fails without spawning anything if our public key or worker id
+    /// is not in the committee.
+    #[allow(clippy::too_many_arguments)]
     pub fn spawn(
         name: PublicKey,
         id: WorkerId,
-        threshold_keypair: ThresholdKeyPair,
+        key_ring: ThresholdKeyRing,
         committee: Committee,
         parameters: Parameters,
-        store: Store,
-    ) {
+        store: S,
+        registry: &Registry,
+        rx_parameters: watch::Receiver<Parameters>,
+        rx_share_requests: mpsc::Receiver<ShareRequest>,
+        shutdown: Shutdown,
+    ) -> Result<Vec<JoinHandle<()>>, ConfigError> {
+        // The tasks below look up our addresses in the committee.
+        let addresses = committee.worker(&name, &id)?;
+        let primary = committee.primary(&name)?;
+
         // Define a worker instance.
         let worker = Self {
             name,
@@ -75,65 +105,68 @@ impl Worker {
             committee,
             parameters,
             store,
+            metrics: WorkerMetrics::new(registry, id),
+            rx_parameters,
+            key_ring,
         };
 
-        // Spawn threshold decryption service
-        let threshold_decryption_service = ThresholdDecryptionService::spawn(
-            threshold_keypair.sk_share,
-            threshold_keypair.node_index,
-        );
-
         // Spawn all worker tasks.
-        let (tx_primary, rx_primary) = channel(CHANNEL_CAPACITY);
-        worker.handle_primary_messages();
-        // TODO: find a better way to pass the threshold_decryption_service twice than to clone it...
-        worker
-            .handle_clients_transactions(tx_primary.clone(), threshold_decryption_service.clone());
-        worker.handle_workers_messages(tx_primary, threshold_decryption_service);
+        let (tx_primary, rx_primary) = channel(worker.parameters.channel_capacity);
+        let (tx_share_gossip, rx_share_gossip) = channel(worker.parameters.channel_capacity);
+        let mut handles = worker.handle_primary_messages(tx_share_gossip.clone());
+        handles.extend(worker.handle_clients_transactions(tx_primary.clone(), shutdown));
+        handles.extend(worker.handle_workers_messages(
+            tx_primary,
+            tx_share_gossip,
+            rx_share_gossip,
+            rx_share_requests,
+        ));
 
         // The `PrimaryConnector` allows the worker to send messages to its primary.
-        PrimaryConnector::spawn(
-            worker
-                .committee
-                .primary(&worker.name)
-                .expect("Our public key is not in the committee")
-                .worker_to_primary,
+        handles.push(PrimaryConnector::spawn(
+            id,
+            primary.worker_to_primary,
+            worker.parameters.digest_flush_interval,
             rx_primary,
-        );
+        ));
 
         // NOTE: This log entry is used to compute performance.
         info!(
             "Worker {} successfully booted on {}",
             id,
-            worker
-                .committee
-                .worker(&worker.name, &worker.id)
-                .expect("Our public key or worker id is not in the committee")
-                .transactions
-                .ip()
+            addresses.transactions.ip()
         );
+
+        Ok(handles)
     }
 
     /// Spawn all tasks responsible to handle messages from our primary.
-    fn handle_primary_messages(&self) {
-        let (tx_synchronizer, rx_synchronizer) = channel(CHANNEL_CAPACITY);
+    fn handle_primary_messages(
+        &self,
+        tx_share_gossip: Sender<ShareMessage>,
+    ) -> Vec<JoinHandle<()>> {
+        let (tx_synchronizer, rx_synchronizer) = channel(self.parameters.channel_capacity);
+        let mut handles = Vec::new();
 
         // Receive incoming messages from our primary.
-        let mut address = self
-            .committee
-            .worker(&self.name, &self.id)
-            .expect("Our public key or worker id is not in the committee")
-            .primary_to_worker;
-        address.set_ip("0.0.0.0".parse().unwrap());
-        Receiver::spawn(
+        let address = self.parameters.listen_address(
+            self.committee
+                .worker(&self.name, &self.id)
+                .expect("Our public key or worker id is not in the committee")
+                .primary_to_worker,
+        );
+        handles.push(Receiver::spawn(
             address,
             /* handler */
-            PrimaryReceiverHandler { tx_synchronizer },
-        );
+            PrimaryReceiverHandler {
+                tx_synchronizer,
+                tx_share_gossip,
+            },
+        ));
 
         // The `Synchronizer` is responsible to keep the worker in sync with the others. It handles the commands
         // it receives from the primary (which are mainly notifications that we are out of sync).
-        Synchronizer::spawn(
+        handles.push(Synchronizer::spawn(
             self.name,
             self.id,
             self.committee.clone(),
@@ -141,46 +174,83 @@ impl Worker {
             self.parameters.gc_depth,
             self.parameters.sync_retry_delay,
             self.parameters.sync_retry_nodes,
+            self.parameters.max_sync_requests,
             /* rx_message */ rx_synchronizer,
-        );
+            self.rx_parameters.clone(),
+        ));
 
         info!(
             "Worker {} listening to primary messages on {}",
             self.id, address
         );
+
+        handles
     }
 
-    /// Spawn all tasks responsible to handle clients transactions.
+    /// Spawn all tasks responsible to handle clients transactions. Once shut down, they seal the batch in
+    /// flight and wait for a quorum to acknowledge the sealed batches before exiting.
     fn handle_clients_transactions(
         &self,
         tx_primary: Sender<SerializedBatchDigestMessage>,
-        threshold_decryption_service: ThresholdDecryptionService,
-    ) {
-        let (tx_batch_maker, rx_batch_maker) = channel(CHANNEL_CAPACITY);
-        let (tx_quorum_waiter, rx_quorum_waiter) = channel(CHANNEL_CAPACITY);
-        let (tx_processor, rx_processor) = channel(CHANNEL_CAPACITY);
-        let (tx_decryptable_batches, rx_decryptable_batches) = channel(CHANNEL_CAPACITY);
+        shutdown: Shutdown,
+    ) -> Vec<JoinHandle<()>> {
+        let (tx_batch_maker, rx_batch_maker) = channel(self.parameters.channel_capacity);
+        let (tx_bundles, rx_bundles) = channel(self.parameters.channel_capacity);
+        let (tx_quorum_waiter, rx_quorum_waiter) = channel(self.parameters.channel_capacity);
+        let (tx_processor, rx_processor) = channel(self.parameters.channel_capacity);
+        let (tx_decryptable_batches, rx_decryptable_batches) =
+            channel(self.parameters.channel_capacity);
+        let (tx_reseal, rx_reseal) = mpsc::unbounded_channel();
+        let mut handles = Vec::new();
 
         // We first receive clients' transactions from the network.
-        let mut address = self
-            .committee
-            .worker(&self.name, &self.id)
-            .expect("Our public key or worker id is not in the committee")
-            .transactions;
-        address.set_ip("0.0.0.0".parse().unwrap());
-        Receiver::spawn(
-            address,
-            /* handler */ TxReceiverHandler { tx_batch_maker },
+        let address = self.parameters.listen_address(
+            self.committee
+                .worker(&self.name, &self.id)
+                .expect("Our public key or worker id is not in the committee")
+                .transactions,
+        );
+        let backpressure = Backpressure::new(
+            self.parameters.mempool_high_water_mark,
+            self.parameters.mempool_low_water_mark,
         );
+        let receipts = Receipts::default();
+        let intake = TxReceiverHandler {
+            tx_batch_maker,
+            tx_bundles,
+            backpressure: backpressure.clone(),
+            metrics: self.metrics.clone(),
+            max_transaction_size: self.parameters.max_batch_size_bytes,
+            max_bundle_transactions: self.parameters.max_batch_size_txs,
+            priority_ordering: self.parameters.priority_ordering,
+        };
+        if let Some(mut http_address) = self.parameters.http_transactions_address {
+            // The workers of an authority may run on the same host: each gets its own port.
+            http_address.set_port(http_address.port() + self.id as u16);
+            handles.push(HttpReceiver::spawn(
+                http_address,
+                intake.clone(),
+                receipts.clone(),
+                self.parameters.mempool_high_water_mark,
+                self.parameters.receipt_timeout_ms,
+            ));
+        }
+        handles.push(Receiver::spawn_public_with_max_connections(
+            address,
+            /* handler */ intake,
+            self.parameters.max_client_connections,
+        ));
 
         // The transactions are sent to the `BatchMaker` that assembles them into batches. It then broadcasts
         // (in a reliable manner) the batches to all other workers that share the same `id` as us. Finally, it
         // gathers the 'cancel handlers' of the messages and send them to the `QuorumWaiter`.
-        BatchMaker::spawn(
+        handles.push(BatchMaker::spawn(
             self.parameters.batch_size,
             self.parameters.max_batch_delay,
             /* rx_transaction */ rx_batch_maker,
+            /* rx_bundle */ rx_bundles,
             rx_decryptable_batches,
+            rx_reseal,
             /* tx_message */ tx_quorum_waiter,
             /* workers_addresses */
             self.committee
@@ -188,100 +258,284 @@ impl Worker {
                 .iter()
                 .map(|(name, addresses)| (*name, addresses.worker_to_worker))
                 .collect(),
-        );
-
-        // The `QuorumWaiter` waits for 2f authorities to acknowledge reception of the batch. It then forwards
+            Backoff {
+                min_delay: self.parameters.min_reconnect_delay_ms,
+                max_delay: self.parameters.max_reconnect_delay_ms,
+                multiplier: self.parameters.reconnect_backoff_multiplier,
+            },
+            self.metrics.clone(),
+            self.rx_parameters.clone(),
+            self.key_ring.clone(),
+            backpressure,
+            shutdown.clone(),
+        ));
+
+        // The `QuorumWaiter` waits for 2f authorities to acknowledge reception (or storage) of the batch. It then forwards
         // the batch to the `Processor`.
-        QuorumWaiter::spawn(
+        handles.push(QuorumWaiter::spawn(
             self.committee.clone(),
             /* stake */ self.committee.stake(&self.name),
-            threshold_decryption_service,
+            self.parameters.durable_acks,
+            self.key_ring.clone(),
             /* rx_message */ rx_quorum_waiter,
             /* tx_batch */ tx_processor,
             tx_decryptable_batches,
-        );
+            tx_reseal,
+            self.metrics.clone(),
+            receipts,
+            shutdown.clone(),
+        ));
 
         // The `Processor` hashes and stores the batch. It then forwards the batch's digest to the `PrimaryConnector`
         // that will send it to our primary machine.
-        Processor::spawn(
+        handles.push(Processor::spawn(
             self.id,
             self.store.clone(),
             /* rx_batch */ rx_processor,
             /* tx_digest */ tx_primary,
             /* own_batch */ true,
-        );
+            self.parameters.batch_compression_level,
+            shutdown,
+        ));
 
         info!(
             "Worker {} listening to client transactions on {}",
             self.id, address
         );
+
+        handles
     }
 
     /// Spawn all tasks responsible to handle messages from other workers.
     fn handle_workers_messages(
         &self,
         tx_primary: Sender<SerializedBatchDigestMessage>,
-        threshold_decryption_service: ThresholdDecryptionService,
-    ) {
-        let (tx_helper, rx_helper) = channel(CHANNEL_CAPACITY);
-        let (tx_processor, rx_processor) = channel(CHANNEL_CAPACITY);
-
-        // Receive incoming messages from other workers.
-        let mut address = self
-            .committee
-            .worker(&self.name, &self.id)
-            .expect("Our public key or worker id is not in the committee")
-            .worker_to_worker;
-        address.set_ip("0.0.0.0".parse().unwrap());
-        Receiver::spawn(
+        tx_share_gossip: Sender<ShareMessage>,
+        rx_share_gossip: mpsc::Receiver<ShareMessage>,
+        rx_share_requests: mpsc::Receiver<ShareRequest>,
+    ) -> Vec<JoinHandle<()>> {
+        let (tx_helper, rx_helper) = channel(self.parameters.channel_capacity);
+        let (tx_processor, rx_processor) = channel(self.parameters.channel_capacity);
+        let mut handles = Vec::new();
+
+        // Receive incoming messages from other workers (throttling the workers flooding us).
+        let address = self.parameters.listen_address(
+            self.committee
+                .worker(&self.name, &self.id)
+                .expect("Our public key or worker id is not in the committee")
+                .worker_to_worker,
+        );
+        handles.push(Receiver::spawn_with_limit(
             address,
             /* handler */
             WorkerReceiverHandler {
                 tx_helper,
                 tx_processor,
-                threshold_decryption_service,
+                tx_share_gossip,
+                key_ring: self.key_ring.clone(),
+                metrics: self.metrics.clone(),
+                max_batch_size: self.parameters.max_batch_size_bytes,
+                durable_acks: self.parameters.durable_acks,
+                priority_ordering: self.parameters.priority_ordering,
             },
-        );
+            RateLimit {
+                messages_per_second: self.parameters.max_peer_messages_per_second,
+                bytes_per_second: self.parameters.max_peer_bytes_per_second,
+            },
+        ));
 
         // The `Helper` is dedicated to reply to batch requests from other workers.
-        Helper::spawn(
+        handles.push(Helper::spawn(
             self.id,
             self.committee.clone(),
             self.store.clone(),
             /* rx_request */ rx_helper,
-        );
+        ));
+
+        // The `ShareGossip` exchanges the decryption shares of committed batches with the other workers.
+        handles.push(ShareGossip::spawn(
+            self.name,
+            self.id,
+            self.committee.clone(),
+            self.store.clone(),
+            self.key_ring.clone(),
+            self.parameters.sync_retry_delay,
+            /* rx_request */ rx_share_requests,
+            /* rx_message */ rx_share_gossip,
+            self.rx_parameters.clone(),
+        ));
 
         // This `Processor` hashes and stores the batches we receive from the other workers. It then forwards the
         // batch's digest to the `PrimaryConnector` that will send it to our primary.
-        Processor::spawn(
+        handles.push(Processor::spawn(
             self.id,
             self.store.clone(),
             /* rx_batch */ rx_processor,
             /* tx_digest */ tx_primary,
             /* own_batch */ false,
-        );
+            self.parameters.batch_compression_level,
+            // We do not drain the batches of the other workers: they may send them to us again.
+            Shutdown::default(),
+        ));
 
         info!(
             "Worker {} listening to worker messages on {}",
             self.id, address
         );
+
+        handles
     }
 }
 
+/// The response of the worker to a client transaction it rejects because it does not fit in a batch.
+pub const TRANSACTION_TOO_LARGE: &[u8] = b"TransactionTooLarge";
+
+/// The response of the worker to a batch of another worker that exceeds its maximum batch size (instead
+/// of its decryption shares), so that the author re-seals the transactions into smaller batches.
+pub const BATCH_TOO_LARGE: &[u8] = b"BatchTooLarge";
+
+/// The response of the worker to a batch of another worker encrypted with the keys of an epoch it has no
+/// (or no longer) keys for. The sender worker gives up on the batch if too many workers reject it.
+pub const UNKNOWN_EPOCH: &[u8] = b"UnknownEpoch";
+
+/// The response of the worker to a batch of another worker whose transactions are not ordered by priority
+/// (with `priority_ordering`). The sender worker gives up on the batch if too many workers reject it.
+pub const BATCH_MISORDERED: &[u8] = b"BatchMisordered";
+
+/// The acknowledgement of a decryptable batch once it is synced to our store (with `durable_acks`).
+pub const BATCH_STORED: &[u8] = b"Stored";
+
+/// The reasons why the worker rejects a client transaction.
+#[derive(Debug, thiserror::Error)]
+pub enum IntakeError {
+    #[error("The mempool is full")]
+    MempoolFull,
+
+    #[error("Transaction of {size} B exceeds the maximum batch size of {max} B")]
+    TooLarge { size: usize, max: usize },
+
+    #[error("Bundle of {count} transactions ({size} B) exceeds the maximum batch size of {max_count} transactions and {max_size} B")]
+    BundleTooLarge {
+        count: usize,
+        size: usize,
+        max_count: usize,
+        max_size: usize,
+    },
+
+    #[error("The transactions of a bundle must all be encrypted with the key of the same epoch")]
+    MixedEpochs,
+
+    #[error("The transactions of a bundle must all have the same priority")]
+    MixedPriorities,
+}
+
 /// Defines how the network receiver handles incoming transactions.
 #[derive(Clone)]
-struct TxReceiverHandler {
+pub(crate) struct TxReceiverHandler {
     tx_batch_maker: Sender<SerializedCiphertext>,
+    tx_bundles: Sender<Vec<SerializedCiphertext>>,
+    backpressure: Backpressure,
+    metrics: WorkerMetrics,
+    /// The size (in bytes) above which transactions (and bundles) cannot fit in a batch.
+    max_transaction_size: usize,
+    /// The number of transactions above which bundles cannot fit in a batch.
+    max_bundle_transactions: usize,
+    /// Whether the batches are ordered by priority: a bundle then only stays together if all its
+    /// transactions have the same priority.
+    priority_ordering: bool,
+}
+
+impl TxReceiverHandler {
+    /// Sends a client transaction to the batch maker, unless it cannot fit in a batch or the mempool is
+    /// full (the client should then back off rather than block).
+    pub(crate) fn submit(&self, transaction: SerializedCiphertext) -> Result<(), IntakeError> {
+        let size = transaction.len();
+        if size > self.max_transaction_size {
+            self.metrics.transactions_rejected.inc();
+            return Err(IntakeError::TooLarge {
+                size,
+                max: self.max_transaction_size,
+            });
+        }
+        let accepted = self.backpressure.try_accept(size)
+            && match self.tx_batch_maker.try_send(transaction) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    self.backpressure.release(size);
+                    false
+                }
+                Err(TrySendError::Closed(_)) => panic!("Failed to send transaction"),
+            };
+        if !accepted {
+            self.metrics.transactions_rejected.inc();
+            return Err(IntakeError::MempoolFull);
+        }
+        Ok(())
+    }
+
+    /// Sends a bundle of client transactions to the batch maker, that places them all in the same batch
+    /// (and thus at the same commit point). The bundle is rejected as a whole if it cannot fit in a batch
+    /// or the mempool is full.
+    pub(crate) fn submit_bundle(
+        &self,
+        bundle: Vec<SerializedCiphertext>,
+    ) -> Result<(), IntakeError> {
+        let count = bundle.len();
+        let size: usize = bundle.iter().map(|x| x.len()).sum();
+        if size > self.max_transaction_size || count > self.max_bundle_transactions {
+            self.metrics.transactions_rejected.inc_by(count as u64);
+            return Err(IntakeError::BundleTooLarge {
+                count,
+                size,
+                max_count: self.max_bundle_transactions,
+                max_size: self.max_transaction_size,
+            });
+        }
+        // A batch only holds the transactions of a single epoch.
+        let epoch = |tx: &SerializedCiphertext| split_epoch(tx).0;
+        if bundle.windows(2).any(|x| epoch(&x[0]) != epoch(&x[1])) {
+            self.metrics.transactions_rejected.inc_by(count as u64);
+            return Err(IntakeError::MixedEpochs);
+        }
+        let priority = |tx: &SerializedCiphertext| split_priority(tx).0;
+        if self.priority_ordering
+            && bundle
+                .windows(2)
+                .any(|x| priority(&x[0]) != priority(&x[1]))
+        {
+            self.metrics.transactions_rejected.inc_by(count as u64);
+            return Err(IntakeError::MixedPriorities);
+        }
+        let accepted = self.backpressure.try_accept(size)
+            && match self.tx_bundles.try_send(bundle) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    self.backpressure.release(size);
+                    false
+                }
+                Err(TrySendError::Closed(_)) => panic!("Failed to send bundle"),
+            };
+        if !accepted {
+            self.metrics.transactions_rejected.inc_by(count as u64);
+            return Err(IntakeError::MempoolFull);
+        }
+        Ok(())
+    }
 }
 
 #[async_trait]
 impl MessageHandler for TxReceiverHandler {
-    async fn dispatch(&self, _writer: &mut Writer, message: Bytes) -> Result<(), Box<dyn Error>> {
-        // Send the transaction to the batch maker.
-        self.tx_batch_maker
-            .send(message.to_vec())
-            .await
-            .expect("Failed to send transaction");
+    async fn dispatch(&self, writer: &mut Writer, message: Bytes) -> Result<(), Box<dyn Error>> {
+        match self.submit(message.to_vec()) {
+            Ok(()) => (),
+            Err(IntakeError::MempoolFull) => {
+                let _ = writer.send(Bytes::from_static(MEMPOOL_FULL)).await;
+            }
+            Err(e) => {
+                debug!("{}", e);
+                let _ = writer.send(Bytes::from_static(TRANSACTION_TOO_LARGE)).await;
+            }
+        }
 
         // Give the change to schedule other tasks.
         tokio::task::yield_now().await;
@@ -293,32 +547,87 @@ impl MessageHandler for TxReceiverHandler {
 #[derive(Clone)]
 struct WorkerReceiverHandler {
     tx_helper: Sender<(Vec<Digest>, PublicKey)>,
-    tx_processor: Sender<SerializedDecryptableBatchMessage>,
-    threshold_decryption_service: ThresholdDecryptionService,
+    tx_processor: Sender<StoreRequest>,
+    tx_share_gossip: Sender<ShareMessage>,
+    key_ring: ThresholdKeyRing,
+    metrics: WorkerMetrics,
+    /// The size (in bytes) above which we reject the batches of the other workers.
+    max_batch_size: usize,
+    /// Whether we acknowledge the decryptable batches only once they are flushed to disk.
+    durable_acks: bool,
+    /// Whether the transactions of the batches must be ordered by priority.
+    priority_ordering: bool,
 }
 
 #[async_trait]
 impl MessageHandler for WorkerReceiverHandler {
     async fn dispatch(&self, writer: &mut Writer, serialized: Bytes) -> Result<(), Box<dyn Error>> {
-        // Deserialize and parse the message.
+        // Deserialize and parse the message (batches may be compressed).
+        let serialized = match decompress(serialized.to_vec()) {
+            Ok(x) => x,
+            Err(e) => {
+                warn!("Failed to decompress worker message: {}", e);
+                return Ok(());
+            }
+        };
         match bincode::deserialize(&serialized) {
-            Ok(WorkerMessage::Batch(txs)) => {
+            Ok(WorkerMessage::Batch(epoch, txs)) => {
+                let size: usize = txs.iter().map(|x| x.len()).sum();
+                if size > self.max_batch_size {
+                    debug!(
+                        "Rejecting batch of {} B (maximum batch size is {} B)",
+                        size, self.max_batch_size
+                    );
+                    let _ = writer.send(Bytes::from_static(BATCH_TOO_LARGE)).await;
+                    return Ok(());
+                }
+                // We do not help a worker ordering its transactions otherwise than by priority.
+                if self.priority_ordering && !is_sorted_by_priority(&txs) {
+                    warn!("Rejecting batch whose transactions are not ordered by priority");
+                    let _ = writer.send(Bytes::from_static(BATCH_MISORDERED)).await;
+                    return Ok(());
+                }
+                // We cannot help decrypting batches of epochs we have no (or no longer) keys for.
+                let threshold_decryption_service = match self.key_ring.decryption_service(epoch) {
+                    Ok(service) => service,
+                    Err(e) => {
+                        warn!("Cannot compute decryption shares: {}", e);
+                        let _ = writer.send(Bytes::from_static(UNKNOWN_EPOCH)).await;
+                        return Ok(());
+                    }
+                };
                 let ciphertexts: Vec<Ciphertext> = txs
                     .par_iter()
-                    .map(|tx| bincode::deserialize(tx).unwrap())
+                    .map(|tx| bincode::deserialize(strip_priority(tx)).unwrap())
                     .collect();
-                let dec_shares = self
-                    .threshold_decryption_service
+                let dec_shares = threshold_decryption_service
                     .request_decryption(ciphertexts)
                     .await;
+                self.metrics
+                    .decryption_shares_produced
+                    .inc_by(dec_shares.1.len() as u64);
                 let serialized_dec_shares = Bytes::from(bincode::serialize(&dec_shares)?);
                 writer.send(serialized_dec_shares).await?;
             }
+            Ok(WorkerMessage::DecryptableBatch(..)) if self.durable_acks => {
+                // Only acknowledge once the batch is on disk: the sender worker counts 2f of these.
+                let (sender, receiver) = oneshot::channel();
+                self.tx_processor
+                    .send((serialized, Some(sender)))
+                    .await
+                    .expect("Failed to send decryptable batch");
+                // A plain ACK does not count towards the quorum of the author.
+                let reply = match receiver.await {
+                    Ok(true) => Bytes::from_static(BATCH_STORED),
+                    _ => Bytes::from("Ack"),
+                };
+                let _ = writer.send(reply).await;
+            }
             Ok(WorkerMessage::DecryptableBatch(..)) => {
                 // Reply with an ACK. Sender worker is waiting for 2f of these.
                 let _ = writer.send(Bytes::from("Ack")).await;
                 self.tx_processor
-                    .send(serialized.to_vec())
+                    .send((serialized, None))
                     .await
                     .expect("Failed to send decryptable batch")
             }
@@ -330,6 +639,16 @@ impl MessageHandler for WorkerReceiverHandler {
                     .await
                     .expect("Failed to send batch request")
             }
+            Ok(WorkerMessage::DecryptionSharesRequest(digest, origin)) => self
+                .tx_share_gossip
+                .send(ShareMessage::Request(digest, origin))
+                .await
+                .expect("Failed to send share request"),
+            Ok(WorkerMessage::DecryptionShares(digest, author, shares)) => self
+                .tx_share_gossip
+                .send(ShareMessage::Shares(digest, author, shares))
+                .await
+                .expect("Failed to send decryption shares"),
             Err(e) => warn!("Serialization error: {}", e),
         }
         Ok(())
@@ -340,6 +659,7 @@ impl MessageHandler for WorkerReceiverHandler {
 #[derive(Clone)]
 struct PrimaryReceiverHandler {
     tx_synchronizer: Sender<PrimaryWorkerMessage>,
+    tx_share_gossip: Sender<ShareMessage>,
 }
 
 #[async_trait]
@@ -349,9 +669,15 @@ impl MessageHandler for PrimaryReceiverHandler {
         _writer: &mut Writer,
         serialized: Bytes,
     ) -> Result<(), Box<dyn Error>> {
-        // Deserialize the message and send it to the synchronizer.
+        // Deserialize the message and send it to the synchronizer (or the commit notifications to the
+        // share gossip).
         match bincode::deserialize(&serialized) {
             Err(e) => error!("Failed to deserialize primary message: {}", e),
+            Ok(PrimaryWorkerMessage::Committed(digests)) => self
+                .tx_share_gossip
+                .send(ShareMessage::Committed(digests))
+                .await
+                .expect("Failed to send commit notification"),
             Ok(message) => self
                 .tx_synchronizer
                 .send(message)